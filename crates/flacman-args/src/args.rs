@@ -1,5 +1,72 @@
 use clap::{Arg, ArgAction, ArgMatches, Command};
-use std::process;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use chrono::{DateTime, Local, NaiveDate, TimeDelta, TimeZone};
+use flacman_core::{
+    Checkpoint, Collation, Confirm, WithoutTerminal, ContentPolicy, ContentType, Diagnosis, DiscLayout, DownloadUser, ExportOptions, ExportPolicy, GainMode, Health, FuzzyMatcher, LogScoreCheck, ManifestCheck, Metric, MetricsStore,
+    Encoding, NotifyConfig, NotifySettings, QualityLadder, QualityPolicy, QuotaLedger, QuotaLevel, QuotaPolicy, QuotaWindow, Resolution, SourceTrust, SpectrogramCheck, Summary,
+    TrackFilter, Trust, TxFilter, TxLog, TxOutcome, TxRecord, Verdict, VerifyStage, check_free_space, check_json_file,
+    check_program, check_symlinks, find_program, check_writable_dir, pager_command,
+    ArtStorage, BlobOrigin, DbLock, DownloadCache, Hook, HookOperation, HookWhen, LibraryGraph, RemovalScope, SearchIndex, SearchQuery, NoProgress, Progress, ProgressTotals, EvictionPolicy, LibraryDb, TrackRecord, read_beets_library, write_beets_library, DaemonRequest, DaemonResponse, serve_requests, NOTES_FILE, NoteStore, NoteSubject, edit_file, edit_text, editor_command, mirror_note, ProvenanceStore, SOURCE_SIDECAR, SearchCache, SourceInfo, parse_size, sha256_file, start_pager, Template, TemplateFields, Checklist, ChecklistStep, Failover, SourceHealth, TrackSelection, TimeWindow, FileChange,
+};
+use flacman_config::{Config, ConfigError, DefaultTransfer, config_path};
+use flacman_remote::{
+    CachedSource, CatalogCache, CatalogOrigin, DownloadEvent, DownloadJob, Downloader, RemoteAlbum, RemoteError, RemoteKind, RemoteSource, RemoteTrack, RankKey, Release, SourceConfig, SourceRegistry, is_url, rank_releases,
+};
+use flacman_mb::MbClient;
+use flacman_convert::{AudioFormat, ConvertJob, ConvertTarget, Converter, CueSheet, find_cue_images, plan_conversion};
+use flacman_fs::{ArchiveKind, ChangeKind, FsCapabilities, InboxWatcher, LibraryWatcher, TransferMode, Trash};
+use flacman_tag::{
+    Album, AlbumTrack, ArtFetchOptions, analyze_album, has_replay_gain, write_replay_gain, embed_folder_art, extract_cover, resize_album_art, CanonicalTrack, apply_canonical, canonical_tracks, write_canonical_tags, AudioQuality, AutoImport, Conflict, ConflictDecision, ConflictStrategy, album_quality, ImportOutcome, resolve_conflict, NumberingIssue, PlayStats, Popularity, CollectionRelease, CollectionSync, DuplicateKind, DuplicateOptions, MediaFile, ValidationFailure, ViewFacet,
+    Chapter, MbCollection, ViewRegistry, ViewSpec, Relocation, Volume, VolumeSet, build_view, fetch_album_art, find_duplicates, group_albums,
+    listenbrainz_play_stats, local_release_ids, mpd_play_stats, MpdClient, NowPlaying, TagError, update_uris, plan_numbering, read_chapters, check_files,
+    flac_md5_tag, unchanged_since_indexed, validate_file, verify_stored_checksums,
+    SearchKind, Subscription, Watchlist, PUBLISH_INDEX, scan_album_art, share_album_art, ReleaseFacts, fetch_release_facts, read_release_facts, write_release_facts, release_ids, thumbnail, PublishedAlbum, Publisher, WritePreview, lookup_release, preview_write, track_provenance, search_musicbrainz, write_m3u, write_popularity,
+    PathStyle, write_playlist, SourceTags, write_missing_tags, Scanner,
+};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, Once, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::logging::init_logging;
+
+mod move_root;
+mod reconcile;
+mod remove;
+mod rollback;
+mod trash;
+mod update;
+mod upgrade;
+mod watch;
+
+use move_root::{move_to_root, rebalance};
+use reconcile::{list_missing, list_orphans};
+use remove::handle_remove;
+use rollback::rollback;
+use trash::{manage_trash, purge_expired_trash, restore};
+use update::{handle_update, import_albums, import_changes, naming_template};
+use upgrade::upgrade_albums;
+use watch::watch_inboxes;
+
+/// Downloads `-S` runs at once when `--jobs` isn't given
+const DOWNLOAD_JOBS: usize = 4;
+
+/// `--watch` rescans the inboxes at least this often
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often `--watch` rescans idle inboxes when the operating system
+/// reports changes to them, in case it missed some (network filesystems)
+const WATCH_RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// `--doctor` warns when a library root has less free space than this
+const DOCTOR_MIN_FREE_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// `--metrics` compares this many recent days against everything before
+const METRICS_WINDOW_DAYS: i64 = 7;
 
 
 pub fn build_cli() -> Command {
@@ -7,414 +74,6811 @@ pub fn build_cli() -> Command {
         .version(env!("CARGO_PKG_VERSION"))
         .author("naromori")
         .about("Pacman-style music package manager")
+        .subcommand(sync_command())
+        .subcommand(query_command())
+        .subcommand(remove_command())
+        .subcommand(update_command())
+        .subcommand(trash_command())
+        .subcommand(move_root_command())
         .arg(
-            Arg::new("sync")
-                .short('S')
-                .long("sync")
-                .help("Download music from remote sources")
-                .action(ArgAction::SetTrue)
-                .conflicts_with_all(["query", "remove", "update"]),
+            Arg::new("validate-local")
+                .long("validate-local")
+                .help("Validate local music repository; files unchanged since they were indexed are checked against their stored checksums, others decoded (FLAC with `flac -t`)")
+                .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("query")
-                .short('Q')
-                .long("query")
-                .help("Query local music library")
+            Arg::new("full")
+                .long("full")
+                .help("With --validate-local, decode every file, not only those changed since they were indexed")
                 .action(ArgAction::SetTrue)
-                .conflicts_with_all(["sync", "remove", "update"]),
+                .requires("validate-local"),
         )
         .arg(
-            Arg::new("remove")
-                .short('R')
-                .long("remove")
-                .help("Remove music from library")
-                .action(ArgAction::SetTrue)
-                .conflicts_with_all(["sync", "query", "update"]),
+            Arg::new("report")
+                .long("report")
+                .help("With --validate-local, also write the failures to FILE")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .requires("validate-local"),
         )
         .arg(
-            Arg::new("update")
-                .short('U')
-                .long("update")
-                .help("Update/move music files into repository")
-                .action(ArgAction::SetTrue)
-                .conflicts_with_all(["sync", "query", "remove"]),
+            Arg::new("normalize-numbers")
+                .long("normalize-numbers")
+                .help("Normalize track/disc numbers in tags and file names under the given directories")
+                .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("artist")
-                .short('A')
-                .long("artist")
-                .help("Target: Artist (download full discography)")
-                .action(ArgAction::SetTrue)
-                .conflicts_with_all(["album", "track"])
-                .requires("sync"),
+            Arg::new("reindex")
+                .long("reindex")
+                .help("Update the library database from the files under the given directories")
+                .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("album")
-                .short('a')
-                .long("album")
-                .help("Target: Album")
-                .action(ArgAction::SetTrue)
-                .conflicts_with_all(["artist", "track"])
-                .requires("sync"),
+            Arg::new("validate-remote")
+                .long("validate-remote")
+                .help("Validate remote music sources")
+                .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("track")
-                .short('t')
-                .long("track")
-                .help("Target: Track")
-                .action(ArgAction::SetTrue)
-                .conflicts_with_all(["artist", "album"])
-                .requires("sync"),
+            Arg::new("config")
+                .long("config")
+                .help("Open configuration file in default editor")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("force-unlock")
+                .long("force-unlock")
+                .help("Remove the database lock left by a flacman that is no longer running, then go on with any operation given")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("doctor")
+                .long("doctor")
+                .help("Check the environment (config, state files, library roots in targets, tools) and suggest fixes")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("restore")
+                .long("restore")
+                .help("Restore removed files whose original path contains ALBUM")
+                .value_name("ALBUM")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("rollback")
+                .long("rollback")
+                .help("Undo the file changes of transaction TXN (see --history)")
+                .value_name("TXN")
+                .value_parser(clap::value_parser!(u64))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("mb-sync")
+                .long("mb-sync")
+                .help("Sync the releases in targets with a MusicBrainz collection (token in FLACMAN_MB_TOKEN)")
+                .value_name("COLLECTION")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("import-ratings")
+                .long("import-ratings")
+                .help("Write ratings and play counts from SOURCE (mpd, listenbrainz:USER) into the tracks in targets")
+                .value_name("SOURCE")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("dedup")
+                .long("dedup")
+                .help("Find byte-identical audio files in the library directories in targets")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dedup-art")
+                .long("dedup-art")
+                .help("Find albums whose tracks all embed the same cover; with [art] mode = \"shared\" for the \
+                       profile, keep it once as folder.jpg and embed a thumbnail")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("extract-art")
+                .long("extract-art")
+                .help("Write the cover embedded in each album's tracks to cover.jpg next to them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("embed-art")
+                .long("embed-art")
+                .help("Embed each album's cover.jpg or folder.jpg into its tracks that have no front cover")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("resize-art")
+                .long("resize-art")
+                .help("Scale embedded art down to --art-max-size and convert art that isn't JPEG or PNG")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("art-max-size")
+                .long("art-max-size")
+                .help("Longest side in pixels of art --embed-art and --resize-art embed (default: [art] max_size)")
+                .value_name("PX")
+                .value_parser(clap::value_parser!(u32))
+                .action(ArgAction::Set)
+                .global(true),
+        )
+        .arg(
+            Arg::new("backfill")
+                .long("backfill")
+                .help("Fill in missing genre, year and label tags from MusicBrainz for albums tagged with a release ID")
+                .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("move")
-                .short('m')
-                .long("move")
-                .help("Move files into repository")
+            Arg::new("batch-size")
+                .long("batch-size")
+                .help("Albums --backfill tags before updating the library database and the history")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .default_value("25")
+                .requires("backfill"),
+        )
+        .arg(
+            Arg::new("hardlink")
+                .long("hardlink")
+                .help("With --dedup, replace identical copies with hardlinks to one file")
                 .action(ArgAction::SetTrue)
-                .conflicts_with_all(["copy", "symlink"])
-                .requires("update"),
+                .requires("dedup"),
         )
         .arg(
-            Arg::new("copy")
-                .short('c')
-                .long("copy")
-                .help("Copy files into repository")
+            Arg::new("build-view")
+                .long("build-view")
+                .help("Build a symlink view of the library in targets by genre, year or decade")
+                .value_names(["FACET", "DIR"])
+                .num_args(2)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .help("Watch the inbox directories in targets (or the configured inboxes) and import complete albums into LIBRARY")
+                .value_name("LIBRARY")
+                .action(ArgAction::Set),
+        )
+        // --watch brings inbox files in the way -U does
+        .args(import_args().map(|arg| arg.requires("watch")))
+        .arg(
+            Arg::new("publish")
+                .long("publish")
+                .help("Serve the library over HTTP as a source other flacman instances can sync from")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("listen")
+                .long("listen")
+                .help("Address --publish listens on (default: 127.0.0.1:6601; 0.0.0.0:6601 for the whole network)")
+                .value_name("ADDR")
+                .requires("publish"),
+        )
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .help("Keep the library database open, follow changes to the library and answer JSON requests on a Unix socket")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("socket")
+                .long("socket")
+                .help("Socket --daemon listens on (default: $XDG_RUNTIME_DIR/flacman.sock)")
+                .value_name("PATH")
+                .value_parser(clap::value_parser!(PathBuf))
+                .requires("daemon"),
+        )
+        .arg(
+            Arg::new("sign-with")
+                .long("sign-with")
+                .help("Sign published manifests with this GPG key")
+                .value_name("FINGERPRINT")
+                .requires("publish"),
+        )
+        .arg(
+            Arg::new("debounce")
+                .long("debounce")
+                .help("Treat an inbox file as complete once unchanged for SECS seconds")
+                .value_name("SECS")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("30")
+                .requires("watch"),
+        )
+        .arg(
+            Arg::new("min-confidence")
+                .long("min-confidence")
+                .help("Hold albums identified with less confidence (0-100) for review instead of importing")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u8).range(0..=100))
+                .default_value("80")
+                .requires("watch"),
+        )
+        .arg(
+            Arg::new("on-conflict")
+                .long("on-conflict")
+                .help("When an album is already in the library: keep-higher-quality, keep-both, replace or interactive")
+                .value_name("STRATEGY")
+                .value_parser(|s: &str| s.parse::<ConflictStrategy>())
+                .default_value("keep-higher-quality")
+                .global(true),
+        )
+        .arg(
+            Arg::new("checksum")
+                .long("checksum")
+                .help("Check every track -U or --watch copies or moves against its source by SHA-256")
                 .action(ArgAction::SetTrue)
-                .conflicts_with_all(["move", "symlink"])
-                .requires("update"),
+                .global(true),
+        )
+        .arg(
+            Arg::new("template")
+                .long("template")
+                .help("Where -U and --watch place tracks, e.g. \"%albumartist%/%album% (%year%)/%track:02% %title%\"")
+                .value_name("TEMPLATE")
+                .value_parser(clap::value_parser!(Template))
+                .global(true),
         )
         .arg(
-            Arg::new("symlink")
-                .long("symlink")
-                .help("Create symlinks in repository")
+            Arg::new("once")
+                .long("once")
+                .help("With --watch, import what is in the inboxes and exit instead of watching")
                 .action(ArgAction::SetTrue)
-                .conflicts_with_all(["move", "copy"])
-                .requires("update"),
+                .requires("watch"),
         )
         .arg(
-            Arg::new("search")
-                .short('s')
-                .long("search")
-                .help("Search for music")
+            Arg::new("quota")
+                .long("quota")
+                .help("Show how much of each source's download quota is used")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("set-quota")
+                .long("set-quota")
+                .help("Limit downloads from SOURCE per day or month (SIZE like 50GiB, 0 removes the quota)")
+                .value_names(["SOURCE", "WINDOW", "SIZE"])
+                .num_args(3)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("export-playlist")
+                .long("export-playlist")
+                .help("Write an M3U playlist of the tracks in targets, with ReplayGain as the --profile exports it")
+                .value_name("FILE")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("gain")
+                .long("gain")
+                .help("How exports and playlists carry ReplayGain: none, tags or bake (default: per --profile)")
+                .value_name("MODE")
+                .value_parser(|s: &str| s.parse::<GainMode>())
+                .global(true),
+        )
+        .arg(
+            Arg::new("subscribe")
+                .long("subscribe")
+                .help("Watch for new releases by ARTIST")
+                .value_name("ARTIST")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("export-watchlist")
+                .long("export-watchlist")
+                .help("Write the subscriptions and wantlist to FILE (.toml or .json)")
+                .value_name("FILE")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("import-watchlist")
+                .long("import-watchlist")
+                .help("Merge the subscriptions and wantlist exported to FILE into this install")
+                .value_name("FILE")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("import-beets")
+                .long("import-beets")
+                .help("Add the tracks of the beets library FILE (e.g. ~/.config/beets/library.db) to the database")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("export-beets")
+                .long("export-beets")
+                .help("Write the library database to a new beets library FILE")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("add-volume")
+                .long("add-volume")
+                .help("Store albums matching RULE (e.g. \"age<=90\", \"\" for the rest) under DIR as volume NAME")
+                .value_names(["NAME", "DIR", "RULE"])
+                .num_args(3)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("volumes")
+                .long("volumes")
+                .help("List the volumes the library is split across")
                 .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("info")
-                .short('i')
-                .long("info")
-                .help("Display detailed information")
+            Arg::new("cache-info")
+                .long("cache-info")
+                .help("Show what the download cache holds, including unfinished downloads")
                 .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("list")
-                .short('l')
-                .long("list")
-                .help("List items")
-                .action(ArgAction::SetTrue)
-                .requires("query"),
+            Arg::new("set-cache-policy")
+                .long("set-cache-policy")
+                .help("Limit the download cache, applied after every sync: max-size=SIZE, max-age=DAYS or \
+                       keep-per-album=N (0 removes the limit)")
+                .value_name("SETTING")
+                .action(ArgAction::Append),
         )
         .arg(
-            Arg::new("validate-local")
-                .long("validate-local")
-                .help("Validate local music repository")
+            Arg::new("rebalance")
+                .long("rebalance")
+                .help("Move albums to the volume their placement rules pick")
                 .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("validate-remote")
-                .long("validate-remote")
-                .help("Validate remote music sources")
+            Arg::new("history")
+                .long("history")
+                .help("List past operations from the transaction log")
                 .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("config")
-                .long("config")
-                .help("Open configuration file in default editor")
+            Arg::new("since")
+                .long("since")
+                .help("Only show operations since DATE (YYYY-MM-DD or RFC 3339)")
+                .value_name("DATE")
+                .value_parser(parse_since)
+                .action(ArgAction::Set)
+                .requires("history"),
+        )
+        .arg(
+            Arg::new("target")
+                .long("target")
+                .help("Only show operations whose targets contain TEXT")
+                .value_name("TEXT")
+                .action(ArgAction::Set)
+                .requires("history"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print JSON instead of text for -Q -l/-s/-i, -Q --playlists/--now-playing, -S -s and --history")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("download-user")
+                .long("download-user")
+                .help("When running as root, download as USER instead")
+                .value_name("USER")
+                .action(ArgAction::Set)
+                .global(true),
+        )
+        .arg(
+            Arg::new("metrics")
+                .long("metrics")
+                .help("Show trends from the local usage metrics (recorded when FLACMAN_METRICS=1)")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("fetch-art")
+                .long("fetch-art")
+                .help("Fetch missing front cover art for album directories")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("replaygain")
+                .long("replaygain")
+                .help("Measure EBU R128 loudness and write ReplayGain tags to the albums in targets")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("skip-tagged")
+                .long("skip-tagged")
+                .help("With --replaygain, skip albums whose tracks all have ReplayGain tags")
+                .action(ArgAction::SetTrue)
+                .requires("replaygain")
+                .global(true),
+        )
         .arg(
             Arg::new("format")
                 .short('f')
                 .long("format")
                 .help("Specify audio format (flac, mp3, opus, etc.)")
                 .value_name("FORMAT")
-                .action(ArgAction::Set),
+                .action(ArgAction::Set)
+                .global(true),
         )
         .arg(
             Arg::new("quality")
                 .short('q')
                 .long("quality")
-                .help("Specify quality/bitrate")
+                .help("Acceptable encodings, best first, e.g. \"lossless, opus>=128\"")
                 .value_name("QUALITY")
-                .action(ArgAction::Set),
+                .value_parser(|s: &str| s.parse::<QualityLadder>().map_err(|e| e.to_string()))
+                .action(ArgAction::Set)
+                .global(true),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .help("Library or device profile to apply (archive, portable, audiobooks, podcasts, ...)")
+                .value_name("PROFILE")
+                .action(ArgAction::Set)
+                .global(true),
         )
         .arg(
             Arg::new("refresh")
                 .short('y')
                 .long("refresh")
-                .help("Refresh remote source cache")
-                .action(ArgAction::SetTrue),
+                .help("Refresh the cached catalogs of remote sources (twice to download them in full)")
+                .action(ArgAction::Count)
+                .global(true),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .short('n')
+                .long("dry-run")
+                .help("Print which files -S, -U, -R or --rollback would download, move, rename or delete, and stop (spelled out after -R, where -n is --nosave)")
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         .arg(
             Arg::new("noconfirm")
                 .long("noconfirm")
                 .help("Do not ask for confirmation")
-                .action(ArgAction::SetTrue),
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("confirm-without-tty")
+                .long("confirm-without-tty")
+                .help("Without a terminal to ask on, go ahead as if confirmed (default: refuse)")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("read-only")
+                .long("read-only")
+                .help("Refuse anything that would change the library, e.g. on a mounted backup or a shared drive")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("preview-writes")
+                .long("preview-writes")
+                .help("Show what retagging would change, worked out on temporary copies, without touching any file")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("no-pager")
+                .long("no-pager")
+                .help("Print long output directly instead of through $FLACMAN_PAGER or $PAGER")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("notify")
+                .long("notify")
+                .help("Show a desktop notification when a download or import finishes")
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         .arg(
             Arg::new("verbose")
                 .short('v')
                 .long("verbose")
-                .help("Be verbose")
-                .action(ArgAction::SetTrue),
+                .help("Be verbose; repeat to log debug (-vv) and trace (-vvv) events")
+                .action(ArgAction::Count)
+                .global(true),
         )
         .arg(
-            Arg::new("recursive")
-                .long("recursive")
-                .help("Process directories recursively")
+            Arg::new("log-file")
+                .long("log-file")
+                .help("Append log events to FILE as JSON lines")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .action(ArgAction::Set)
+                .global(true),
+        )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .help("Don't draw progress bars")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .help("Workers for validation and conversion (default: number of CPUs) or downloads (default: 4)")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .action(ArgAction::Set)
+                .global(true),
+        )
+        .arg(
+            Arg::new("restart")
+                .long("restart")
+                .help("Discard the saved progress of an interrupted recursive import or validation")
                 .action(ArgAction::SetTrue)
-                .requires("update"),
+                .global(true),
         )
         .arg(
-            Arg::new("targets")
-                .help("Target items (artists, albums, tracks, or paths)")
-                .action(ArgAction::Append)
-                .num_args(0..),
+            Arg::new("now")
+                .long("now")
+                .help("Run a download or scrub outside the hours the schedule in flacman.conf allows")
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
+        .arg(targets_arg())
 }
 
-pub fn handle_matches(matches: &ArgMatches) {
-    // Handle standalone operations first
-    if matches.get_flag("config") {
-        open_config();
-        return;
-    }
-
-    if matches.get_flag("validate-local") {
-        validate_local_repo(matches.get_flag("verbose"));
-        return;
-    }
-
-    if matches.get_flag("validate-remote") {
-        validate_remote_repo(matches.get_flag("verbose"));
-        return;
-    }
-
-    // Determine primary operation
-    let operation = if matches.get_flag("sync") {
-        "sync"
-    } else if matches.get_flag("query") {
-        "query"
-    } else if matches.get_flag("remove") {
-        "remove"
-    } else if matches.get_flag("update") {
-        "update"
-    } else {
-        eprintln!("Error: No operation specified");
-        eprintln!("Use -S (download), -Q (query), -R (remove), -U (update), or --config/--validate-*");
-        process::exit(1);
-    };
-
-    let verbose = matches.get_flag("verbose");
-    let noconfirm = matches.get_flag("noconfirm");
+/// `-S`: download from remote sources, or look things up there
+fn sync_command() -> Command {
+    Command::new("sync")
+        .short_flag('S')
+        .long_flag("sync")
+        .visible_alias("download")
+        .about("Download music from remote sources")
+        .arg(search_arg("Search remote sources instead of downloading"))
+        .arg(info_arg())
+        .arg(
+            Arg::new("artist")
+                .short('A')
+                .long("artist")
+                .help("Target: Artist (download full discography)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["album", "track"]),
+        )
+        .arg(
+            Arg::new("album")
+                .short('a')
+                .long("album")
+                .help("Target: Album")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["artist", "track"]),
+        )
+        .arg(
+            Arg::new("track")
+                .short('t')
+                .long("track")
+                .help("Target: Track; with -S, URLs are downloaded with yt-dlp")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["artist", "album"]),
+        )
+        .arg(
+            Arg::new("tracks")
+                .long("tracks")
+                .help("With -S -a, download only these tracks of the album, e.g. 1,3,7-9")
+                .value_name("LIST")
+                .value_parser(clap::value_parser!(TrackSelection))
+                .requires("album"),
+        )
+        .arg(
+            Arg::new("sysupgrade")
+                .short('u')
+                .long("sysupgrade")
+                .help("Replace library albums (or those in targets) that a source has in better quality, e.g. MP3 rips with FLAC")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["search", "info", "artist", "album", "track", "needed"]),
+        )
+        .arg(
+            Arg::new("needed")
+                .long("needed")
+                .help("Download the releases on the wantlist pulled by --mb-sync")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("source")
+                .long("source")
+                .help("Source in flacman.conf to search or download from; repeat to fail over, best first")
+                .value_name("SOURCE")
+                .action(ArgAction::Append),
+        )
+        .arg(targets_arg())
+}
 
-    // Get targets if provided
-    let targets: Vec<&String> = matches
+/// `-Q`: look at the local library
+fn query_command() -> Command {
+    Command::new("query")
+        .short_flag('Q')
+        .long_flag("query")
+        .visible_alias("ls")
+        .about("Query local music library")
+        .arg(search_arg("Search the library"))
+        .arg(info_arg())
+        .arg(
+            Arg::new("list")
+                .short('l')
+                .long("list")
+                .help("List items")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("duplicates")
+                .long("duplicates")
+                .help("Report duplicate tracks in the given directories")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("format-string")
+                .long("format-string")
+                .help("Print each listed album or track with a naming template, e.g. \"%artist% | %album% | %path%\"")
+                .value_name("TEMPLATE")
+                .value_parser(clap::value_parser!(Template)),
+        )
+        .arg(
+            Arg::new("filter")
+                .long("filter")
+                .help("List tracks matching all conditions, e.g. \"rating>=4, genre~jazz\"")
+                .value_name("EXPR")
+                .value_parser(clap::value_parser!(TrackFilter)),
+        )
+        .arg(
+            Arg::new("playlist")
+                .long("playlist")
+                .help("Write the tracks -Q finds (-Qs hits, albums matching targets, --load-playlist) to an .m3u8 or .xspf FILE")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("playlist-paths")
+                .long("playlist-paths")
+                .help("How --playlist refers to tracks: relative (to the playlist's directory) or absolute")
+                .value_name("STYLE")
+                .value_parser(|s: &str| s.parse::<PathStyle>())
+                .default_value("relative")
+                .requires("playlist"),
+        )
+        .arg(
+            Arg::new("save-playlist")
+                .long("save-playlist")
+                .help("Save the tracks -Q finds in the library database as playlist NAME")
+                .value_name("NAME"),
+        )
+        .arg(
+            Arg::new("load-playlist")
+                .long("load-playlist")
+                .help("List the tracks of the saved playlist NAME, or write them out with --playlist")
+                .value_name("NAME")
+                .conflicts_with("search"),
+        )
+        .arg(
+            Arg::new("playlists")
+                .long("playlists")
+                .help("List the playlists saved in the library database")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("orphans")
+                .long("orphans")
+                .help("List audio files in the library (or targets) that aren't in the library database")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("missing"),
+        )
+        .arg(
+            Arg::new("adopt")
+                .long("adopt")
+                .help("With --orphans, add the files it lists to the library database")
+                .action(ArgAction::SetTrue)
+                .requires("orphans"),
+        )
+        .arg(
+            Arg::new("missing")
+                .long("missing")
+                .help("List tracks in the library database (under targets) whose files are gone")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("prune")
+                .long("prune")
+                .help("With --missing, remove the tracks it lists from the library database")
+                .action(ArgAction::SetTrue)
+                .requires("missing"),
+        )
+        .arg(
+            Arg::new("now-playing")
+                .long("now-playing")
+                .help("Show the library entry of the track MPD is playing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("delete-playlist")
+                .long("delete-playlist")
+                .help("Delete the saved playlist NAME")
+                .value_name("NAME"),
+        )
+        .arg(
+            Arg::new("fingerprint")
+                .long("fingerprint")
+                .help("Also match different encodes of a recording by acoustic fingerprint (needs fpcalc)")
+                .action(ArgAction::SetTrue)
+                .requires("duplicates"),
+        )
+        .arg(
+            Arg::new("by-tags")
+                .long("by-tags")
+                .help("Also match tracks tagged with the same artist and title at about the same length")
+                .action(ArgAction::SetTrue)
+                .requires("duplicates"),
+        )
+        .arg(
+            Arg::new("provenance")
+                .long("provenance")
+                .help("With -Qi, show where each file came from: source, original name, checksum, transcodes")
+                .action(ArgAction::SetTrue)
+                .requires("info"),
+        )
+        .arg(
+            Arg::new("edit-notes")
+                .long("edit-notes")
+                .help("With -Qi, edit the notes on each target album directory or artist name (read from stdin if piped)")
+                .action(ArgAction::SetTrue)
+                .requires("info"),
+        )
+        .arg(
+            Arg::new("mirror-notes")
+                .long("mirror-notes")
+                .help("Also write album notes to NOTES.md in the album directory")
+                .action(ArgAction::SetTrue)
+                .requires("edit-notes"),
+        )
+        .arg(targets_arg())
+}
+
+/// `-R`: take music out of the library
+fn remove_command() -> Command {
+    Command::new("remove")
+        .short_flag('R')
+        .long_flag("remove")
+        .visible_alias("rm")
+        .about("Remove music from library")
+        .arg(
+            Arg::new("recursive")
+                .short('s')
+                .long("recursive")
+                .help("Remove the rest of each track's album too")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("cascade")
+                .short('c')
+                .long("cascade")
+                .help("Remove artists with all of their albums")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("nosave")
+                .short('n')
+                .long("nosave")
+                .help("Remove the cover art, cue sheets and logs of what -R empties instead of keeping them")
+                .action(ArgAction::SetTrue),
+        )
+        // -n is --nosave here, as in pacman, so this shadows the global
+        // -n/--dry-run with a long-only flag
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Print which files -R would delete, and stop")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-trash")
+                .long("purge")
+                .alias("no-trash")
+                .help("Delete what -R removes for good instead of moving it to the trash")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(targets_arg())
+}
+
+/// `-U`: bring files into the library
+fn update_command() -> Command {
+    Command::new("update")
+        .short_flag('U')
+        .long_flag("update")
+        .visible_alias("import")
+        .about("Update/move music files into repository")
+        .args(import_args())
+        .arg(
+            Arg::new("recursive")
+                .long("recursive")
+                .help("Process directories recursively")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("convert")
+                .long("convert")
+                .help("Convert tracks to the -f format, at --quality, as -U imports them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("delete-archive")
+                .long("delete-archive")
+                .help("Delete zip, 7z or rar archives once -U has imported everything in them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("mb-lookup")
+                .long("mb-lookup")
+                .help("Look each album up on MusicBrainz and file and tag it under the canonical names")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(targets_arg())
+}
+
+/// `flacman trash`: look into and manage what `-R` removed
+fn trash_command() -> Command {
+    Command::new("trash")
+        .about("List, restore or empty what -R moved to the trash")
+        .subcommand_required(true)
+        .subcommand(Command::new("list").about("List the trash, oldest removal first"))
+        .subcommand(
+            Command::new("restore")
+                .about("Put back removed files whose original path contains PATTERN")
+                .arg(Arg::new("pattern").value_name("PATTERN").required(true)),
+        )
+        .subcommand(Command::new("empty").about("Delete everything in the trash for good"))
+}
+
+/// `flacman move-root`: migrate albums between the roots of a split library
+fn move_root_command() -> Command {
+    Command::new("move-root")
+        .about("Move the albums under TARGETS to the library root ROOT, keeping their paths below it")
+        .arg(Arg::new("root").value_name("ROOT").help("Name of a [[roots]] entry or --add-volume volume").required(true))
+        .arg(targets_arg().num_args(1..).required(true))
+}
+
+/// What `-U` and `--watch` share: how files are brought in and how they
+/// are verified first
+fn import_args() -> [Arg; 9] {
+    [
+        Arg::new("move")
+            .short('m')
+            .long("move")
+            .help("Move files into repository")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["copy", "symlink"]),
+        Arg::new("copy")
+            .short('c')
+            .long("copy")
+            .help("Copy files into repository")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["move", "symlink"]),
+        Arg::new("symlink")
+            .long("symlink")
+            .help("Create symlinks in repository")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["move", "copy"]),
+        Arg::new("reflink")
+            .long("reflink")
+            .help("Create copy-on-write clones in repository (Btrfs, XFS); -c clones where it can anyway")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["move", "copy", "symlink"]),
+        Arg::new("min-log-score")
+            .long("min-log-score")
+            .help("Veto imports whose EAC/XLD rip log scores below SCORE")
+            .value_name("SCORE")
+            .value_parser(clap::value_parser!(i32))
+            .action(ArgAction::Set),
+        Arg::new("spectrograms")
+            .long("spectrograms")
+            .help("Render spectrograms of imported FLACs into DIR (needs sox)")
+            .value_name("DIR")
+            .action(ArgAction::Set),
+        Arg::new("minisign-key")
+            .long("minisign-key")
+            .help("Only import items whose flacman.manifest is signed with this minisign key (file or base64)")
+            .value_name("KEY")
+            .action(ArgAction::Set)
+            .conflicts_with("gpg-key"),
+        Arg::new("gpg-key")
+            .long("gpg-key")
+            .help("Only import items whose flacman.manifest is signed by this GPG key fingerprint")
+            .value_name("FINGERPRINT")
+            .action(ArgAction::Set),
+        Arg::new("require-manifest")
+            .long("require-manifest")
+            .help("Veto items that come without a flacman.manifest")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+fn search_arg(help: &'static str) -> Arg {
+    Arg::new("search").short('s').long("search").help(help).action(ArgAction::SetTrue)
+}
+
+fn info_arg() -> Arg {
+    Arg::new("info").short('i').long("info").help("Display detailed information").action(ArgAction::SetTrue)
+}
+
+fn targets_arg() -> Arg {
+    Arg::new("targets")
+        .help("Target items (artists, albums, tracks, or paths)")
+        .action(ArgAction::Append)
+        .num_args(0..)
+}
+
+pub fn handle_matches(matches: &ArgMatches) -> ExitCode {
+    QUIET.store(matches.get_flag("quiet"), Ordering::Relaxed);
+    init_download_user(matches);
+    let log_file = matches.get_one::<PathBuf>("log-file");
+    if let Err(e) = init_logging(matches.get_count("verbose"), log_file.map(PathBuf::as_path)) {
+        let path = log_file.map(|path| path.display().to_string()).unwrap_or_default();
+        eprintln!("Warning: could not open log file {}: {}", path, e);
+    }
+    debug!(args = ?std::env::args().skip(1).collect::<Vec<_>>(), version = env!("CARGO_PKG_VERSION"), "started");
+
+    // Handle standalone operations first
+    if matches.get_flag("config") {
+        open_config();
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("doctor") {
+        let targets = library_targets(matches);
+        run_doctor(&targets);
+        return ExitCode::SUCCESS;
+    }
+
+    // A dry run writes nothing, but only the main operations know how to
+    // report what they would have done
+    let dry_run = matches.get_flag("dry-run");
+    let writes = library_write(matches);
+    if dry_run
+        && let Some(operation) = writes.filter(|op| !DRY_RUN_OPERATIONS.contains(op))
+    {
+        eprintln!("Error: --dry-run is not supported with {}", operation);
+        process::exit(1);
+    }
+
+    if (matches.get_flag("read-only") || config().read_only)
+        && let Some(operation) = writes.filter(|_| !dry_run)
+    {
+        eprintln!("Error: The library is read-only; {} would change it", operation);
+        eprintln!("Queries, exports and playlists still work; drop --read-only or read_only in flacman.conf to write");
+        process::exit(1);
+    }
+
+    let reconciles = matches.subcommand_matches("query").is_some_and(|q| q.get_flag("adopt") || q.get_flag("prune"));
+    let indexes = matches.get_flag("validate-local") || matches.get_flag("reindex") || reconciles;
+    if matches.get_flag("force-unlock") {
+        force_unlock();
+        if writes.is_none() && !indexes {
+            return ExitCode::SUCCESS;
+        }
+    }
+    // Held until the operation is done; --watch takes it for each batch,
+    // and -S only downloads outside the library
+    let locks = !dry_run && (writes.is_some_and(|op| op != "--watch" && op != "-S") || indexes);
+    let _lock = locks.then(|| lock_database(false));
+
+    if matches.get_flag("validate-local") {
+        if !within_schedule(matches, "validate-local", "scrubs", config().schedule.scrubs) {
+            return ExitCode::SUCCESS;
+        }
+        let targets = library_targets(matches);
+        let jobs = matches
+            .get_one::<usize>("jobs")
+            .copied()
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+        validate_local_repo(
+            &targets,
+            jobs,
+            matches.get_one::<PathBuf>("report").map(PathBuf::as_path),
+            matches.get_flag("restart"),
+            matches.get_flag("full"),
+            !matches.get_flag("no-pager"),
+            matches.get_count("verbose") > 0,
+        );
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("normalize-numbers") {
+        let targets = library_targets(matches);
+        if matches.get_flag("preview-writes") {
+            preview_numbering(&targets);
+        } else {
+            normalize_numbers(&targets, matches.get_count("verbose") > 0, confirm_policy(matches));
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("publish") {
+        publish_library(matches, &library_targets(matches));
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("daemon") {
+        run_daemon(matches, &library_targets(matches), matches.get_count("verbose") > 0);
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("reindex") {
+        reindex_library(&library_targets(matches), matches.get_count("verbose") > 0);
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("validate-remote") {
+        validate_remote_repo(matches.get_count("verbose") > 0);
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(source) = matches.get_one::<String>("import-ratings") {
+        let targets = library_targets(matches);
+        import_ratings(source, &targets, matches.get_count("verbose") > 0, matches.get_flag("preview-writes"));
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(collection) = matches.get_one::<String>("mb-sync") {
+        let targets: Vec<&String> = matches
+            .get_many::<String>("targets")
+            .unwrap_or_default()
+            .collect();
+        sync_mb_collection(collection, &targets, matches.get_count("verbose") > 0);
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("dedup-art") {
+        let targets = library_targets(matches);
+        dedup_album_art(matches, &targets, confirm_policy(matches));
+        return ExitCode::SUCCESS;
+    }
+
+    if ["extract-art", "embed-art", "resize-art"].iter().any(|flag| matches.get_flag(flag)) {
+        let targets = library_targets(matches);
+        manage_album_art(matches, &targets, confirm_policy(matches));
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("backfill") {
+        let targets = library_targets(matches);
+        let batch = matches.get_one::<u32>("batch-size").copied().unwrap_or(25) as usize;
+        let (preview, verbose) = (matches.get_flag("preview-writes"), matches.get_count("verbose") > 0);
+        backfill_tags(&targets, batch, preview, verbose, confirm_policy(matches));
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("dedup") {
+        let targets = library_targets(matches);
+        dedup_library(
+            &targets,
+            matches.get_flag("hardlink"),
+            matches.get_count("verbose") > 0,
+            confirm_policy(matches),
+        );
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(mut view) = matches.get_many::<String>("build-view") {
+        let (facet, dir) = (view.next().expect("two values"), view.next().expect("two values"));
+        let targets: Vec<&String> = matches
+            .get_many::<String>("targets")
+            .unwrap_or_default()
+            .collect();
+        build_library_view(facet, dir, &targets);
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(library) = matches.get_one::<String>("watch") {
+        let inboxes: Vec<String> = config().inboxes.iter().map(|p| p.display().to_string()).collect();
+        let mut targets: Vec<&String> = matches
+            .get_many::<String>("targets")
+            .unwrap_or_default()
+            .collect();
+        if targets.is_empty() {
+            targets = inboxes.iter().collect();
+        }
+        watch_inboxes(matches, library, &targets, matches.get_count("verbose") > 0);
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(mut quota) = matches.get_many::<String>("set-quota") {
+        let (source, window, size) = (quota.next().expect("three values"), quota.next(), quota.next());
+        set_quota(source, window.expect("three values"), size.expect("three values"));
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("quota") {
+        show_quotas();
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(artist) = matches.get_one::<String>("subscribe") {
+        subscribe(artist);
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(file) = matches.get_one::<String>("export-watchlist") {
+        export_watchlist(Path::new(file));
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(file) = matches.get_one::<String>("import-watchlist") {
+        import_watchlist(Path::new(file));
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(file) = matches.get_one::<PathBuf>("import-beets") {
+        import_beets(file, matches.get_count("verbose") > 0);
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(file) = matches.get_one::<PathBuf>("export-beets") {
+        export_beets(file);
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(playlist) = matches.get_one::<String>("export-playlist") {
+        let targets: Vec<&String> = matches
+            .get_many::<String>("targets")
+            .unwrap_or_default()
+            .collect();
+        export_playlist(matches, playlist, &targets);
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(mut volume) = matches.get_many::<String>("add-volume") {
+        let (name, dir, rule) = (volume.next().expect("three values"), volume.next(), volume.next());
+        add_volume(name, dir.expect("three values"), rule.expect("three values"));
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("volumes") {
+        show_volumes();
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(settings) = matches.get_many::<String>("set-cache-policy") {
+        set_cache_policy(&settings.collect::<Vec<_>>());
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("cache-info") {
+        show_download_cache(matches.get_count("verbose") > 0);
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("rebalance") {
+        rebalance(matches.get_count("verbose") > 0, confirm_policy(matches));
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("history") {
+        let filter = TxFilter {
+            since: matches.get_one::<DateTime<Local>>("since").copied(),
+            target: matches.get_one::<String>("target").cloned(),
+        };
+        let json = matches.get_flag("json");
+        page_output(!json && !matches.get_flag("no-pager"));
+        show_history(&filter, json);
+        return ExitCode::SUCCESS;
+    }
+
+    if matches.get_flag("metrics") {
+        show_metrics();
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(trash_matches) = matches.subcommand_matches("trash") {
+        manage_trash(trash_matches, &trash(), confirm_policy(matches));
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(move_root) = matches.subcommand_matches("move-root") {
+        let root = move_root.get_one::<String>("root").expect("required");
+        let targets: Vec<&String> = move_root.get_many::<String>("targets").unwrap_or_default().collect();
+        move_to_root(root, &targets, matches.get_count("verbose") > 0, confirm_policy(matches));
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(pattern) = matches.get_one::<String>("restore") {
+        restore(&trash(), pattern, matches.get_count("verbose") > 0);
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(&id) = matches.get_one::<u64>("rollback") {
+        rollback(id, matches.get_flag("dry-run"), confirm_policy(matches));
+        return ExitCode::SUCCESS;
+    }
+
+    // With -U, art fetching runs as part of the import instead
+    if matches.get_flag("fetch-art") && matches.subcommand_name() != Some("update") {
+        let targets: Vec<&String> = matches
+            .get_many::<String>("targets")
+            .unwrap_or_default()
+            .collect();
+        fetch_art(matches, &targets, matches.get_count("verbose") > 0);
+        return ExitCode::SUCCESS;
+    }
+
+    // With -U, the albums are measured before they are imported instead
+    if matches.get_flag("replaygain") && matches.subcommand_name() != Some("update") {
+        if !replay_gain(matches, &library_targets(matches), Some(confirm_policy(matches))) {
+            process::exit(1);
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    // Determine primary operation
+    let Some((operation, matches)) = matches.subcommand() else {
+        eprintln!("Error: No operation specified");
+        eprintln!("Use -S (download), -Q (query), -R (remove), -U (update), or --config/--validate-*/--history");
+        return ExitCode::FAILURE;
+    };
+
+    let verbose = matches.get_count("verbose") > 0;
+    let confirm = confirm_policy(matches);
+
+    // Get targets if provided
+    let targets: Vec<&String> = matches
         .get_many::<String>("targets")
         .unwrap_or_default()
         .collect();
 
-    match operation {
-        "sync" => handle_sync(matches, &targets, verbose, noconfirm),
-        "query" => handle_query(matches, &targets, verbose),
-        "remove" => handle_remove(matches, &targets, verbose, noconfirm),
-        "update" => handle_update(matches, &targets, verbose, noconfirm),
-        _ => unreachable!(),
-    }
+    let started = Instant::now();
+
+    match operation {
+        "sync" => handle_sync(matches, &targets, verbose, confirm),
+        "query" => handle_query(matches, &targets, verbose),
+        "remove" => handle_remove(matches, &targets, verbose, confirm),
+        "update" => handle_update(matches, &targets, verbose, confirm),
+        _ => unreachable!(),
+    }
+
+    record_metric(Metric::Operation { operation: operation.to_owned(), secs: started.elapsed().as_secs_f64() });
+    ExitCode::SUCCESS
+}
+
+/// Operations of [`library_write`] that can report a dry run
+const DRY_RUN_OPERATIONS: &[&str] = &["-Su", "-S", "-R", "-U", "--rollback"];
+
+/// The operation in `matches` that would write to the library, if any
+///
+/// Previews, searches and `-S` lookups don't count, and neither does
+/// flacman's own data (the database, notes, quotas): only the files and
+/// directories of the library itself.
+fn library_write(matches: &ArgMatches) -> Option<&'static str> {
+    let preview = matches.get_flag("preview-writes");
+    let sync = matches.subcommand_matches("sync");
+    let lookup = sync.is_some_and(|sync| sync.get_flag("search") || sync.get_flag("info"));
+    let upgrade = sync.is_some_and(|sync| sync.get_flag("sysupgrade"));
+    let mirror_notes = matches.subcommand_matches("query").is_some_and(|query| query.get_flag("mirror-notes"));
+
+    [
+        ("--normalize-numbers", matches.get_flag("normalize-numbers") && !preview),
+        ("--import-ratings", matches.contains_id("import-ratings") && !preview),
+        ("--dedup-art", matches.get_flag("dedup-art") && !preview),
+        ("--extract-art", matches.get_flag("extract-art") && !preview),
+        ("--embed-art", matches.get_flag("embed-art") && !preview),
+        ("--resize-art", matches.get_flag("resize-art") && !preview),
+        ("--backfill", matches.get_flag("backfill") && !preview),
+        ("--dedup", matches.get_flag("dedup")),
+        ("--watch", matches.contains_id("watch")),
+        ("--rebalance", matches.get_flag("rebalance")),
+        ("move-root", matches.subcommand_name() == Some("move-root")),
+        ("--restore", matches.contains_id("restore")),
+        (
+            "trash restore",
+            matches.subcommand_matches("trash").is_some_and(|trash| trash.subcommand_name() == Some("restore")),
+        ),
+        ("--rollback", matches.contains_id("rollback")),
+        ("--fetch-art", matches.get_flag("fetch-art") && !preview),
+        ("--replaygain", matches.get_flag("replaygain") && !preview),
+        ("--mirror-notes", mirror_notes),
+        ("-Su", upgrade),
+        ("-S", sync.is_some() && !lookup),
+        ("-R", matches.subcommand_name() == Some("remove")),
+        ("-U", matches.subcommand_name() == Some("update")),
+    ]
+    .into_iter()
+    .find_map(|(operation, writes)| writes.then_some(operation))
+}
+
+/// How to confirm changes: `--noconfirm` skips the question, and
+/// `--confirm-without-tty` goes ahead when there is no one to ask
+fn confirm_policy(matches: &ArgMatches) -> Confirm {
+    Confirm {
+        noconfirm: matches.get_flag("noconfirm"),
+        without_terminal: if matches.get_flag("confirm-without-tty") {
+            WithoutTerminal::Proceed
+        } else {
+            WithoutTerminal::Refuse
+        },
+    }
+}
+
+/// Ask `question` before changing anything; exits unless the answer is yes
+fn confirm_or_exit(confirm: Confirm, question: &str) {
+    match confirm.ask(question) {
+        Ok(true) => {}
+        Ok(false) => {
+            println!("Aborted");
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Set once Ctrl-C asks the running operation to stop
+static CANCEL: AtomicBool = AtomicBool::new(false);
+
+/// The flag long operations check between items
+///
+/// The first call installs the Ctrl-C handler. The first Ctrl-C only sets
+/// the flag, so the item in flight is finished or rolled back before the
+/// operation stops; a second one quits at once.
+fn cancel_flag() -> &'static AtomicBool {
+    static HANDLER: Once = Once::new();
+    HANDLER.call_once(|| {
+        let installed = ctrlc::set_handler(|| {
+            if CANCEL.swap(true, Ordering::Relaxed) {
+                process::exit(130);
+            }
+            eprintln!("\nCancelling at the next safe point (Ctrl-C again to quit now)");
+        });
+        if let Err(e) = installed {
+            eprintln!("Warning: cannot handle Ctrl-C, interrupting will not stop cleanly: {}", e);
+        }
+    });
+    &CANCEL
+}
+
+/// Report an operation stopped by Ctrl-C and exit with status 130
+///
+/// # Arguments
+/// * `committed` - Items finished before the operation stopped
+/// * `total` - Items it set out to do
+/// * `resume` - How to carry on, shown when some but not all were committed
+fn exit_cancelled(committed: usize, total: usize, resume: &str) -> ! {
+    if committed == 0 {
+        eprintln!("Operation cancelled, nothing committed");
+    } else {
+        eprintln!("Operation cancelled: {} of {} committed; {}", committed, total, resume);
+    }
+    process::exit(130);
+}
+
+pub fn handle_sync(matches: &ArgMatches, targets: &[&String], verbose: bool, confirm: Confirm) {
+    let started = Instant::now();
+    let needed = matches.get_flag("needed");
+
+    // --needed without targets downloads the wantlist
+    let wanted: Vec<String> = if needed && targets.is_empty() {
+        read_wantlist().iter().map(|r| format!("{} - {}", r.artist, r.title)).collect()
+    } else {
+        Vec::new()
+    };
+    if needed && targets.is_empty() && wanted.is_empty() {
+        println!("Nothing on the wantlist; pull one with --mb-sync");
+        return;
+    }
+    let targets: Vec<&String> = if wanted.is_empty() { targets.to_vec() } else { wanted.iter().collect() };
+    let targets = targets.as_slice();
+
+    let artist = matches.get_flag("artist");
+    let album = matches.get_flag("album") || needed;
+    let track = matches.get_flag("track");
+    let search = matches.get_flag("search");
+    let info = matches.get_flag("info");
+    let refresh = matches.get_count("refresh");
+    let quality = quality_ladder(matches);
+    let format = matches.get_one::<String>("format").or(config().format.as_ref());
+    let target = format.map(|format| convert_target(format, &quality));
+
+    if verbose {
+        println!("Operation: Sync (Download)");
+    }
+
+    if refresh > 0 && !search && !info {
+        refresh_catalogs(&source_names(matches), refresh);
+        if targets.is_empty() && !matches.get_flag("sysupgrade") {
+            return;
+        }
+    }
+    if matches.get_flag("sysupgrade") {
+        upgrade_albums(matches, targets, verbose, confirm);
+        return;
+    }
+
+    if search {
+        if targets.is_empty() {
+            eprintln!("Error: No search term specified");
+            process::exit(1);
+        }
+        let kinds = if artist {
+            vec![SearchKind::Artist]
+        } else if album {
+            vec![SearchKind::Album]
+        } else if track {
+            vec![SearchKind::Track]
+        } else {
+            vec![SearchKind::Artist, SearchKind::Album]
+        };
+        let query: Vec<&str> = targets.iter().map(|t| t.as_str()).collect();
+        let names: Vec<&String> = matches.get_many::<String>("source").unwrap_or_default().collect();
+        let mut results = matches.get_flag("json").then(Vec::new);
+        if names.is_empty() {
+            remote_search(&kinds, &query.join(" "), refresh, verbose, results.as_mut());
+        }
+        for name in &names {
+            let Some(source) = remote_source(name, refresh) else {
+                eprintln!("Error: {}", RemoteError::UnknownSource(name.to_string()));
+                process::exit(1);
+            };
+            if names.len() > 1 && results.is_none() {
+                println!("{}:", name);
+            }
+            source_search(&source, &kinds, &query.join(" "), refresh, verbose, results.as_mut());
+        }
+        if let Some(results) = results {
+            print_json(&results);
+        }
+        return;
+    }
+
+    if info {
+        if targets.is_empty() {
+            eprintln!("Error: No target specified");
+            process::exit(1);
+        }
+        let target_type = if artist {
+            "artist"
+        } else if album {
+            "album"
+        } else if track {
+            "track"
+        } else {
+            "item"
+        };
+        if album && targets.iter().all(|t| is_mbid(t)) {
+            for id in targets {
+                release_info(id, refresh);
+            }
+            return;
+        }
+        println!("Getting info for {}: {:?}", target_type, targets);
+        return;
+    }
+
+    if targets.is_empty() {
+        eprintln!("Error: No targets specified");
+        process::exit(1);
+    }
+    let dry_run = matches.get_flag("dry-run");
+    if !dry_run && !within_schedule(matches, "sync", "downloads", config().schedule.downloads) {
+        return;
+    }
+
+    // Determine download type
+    let download_type = if artist {
+        "artist discography"
+    } else if album {
+        "album"
+    } else if track {
+        "track"
+    } else {
+        eprintln!("Error: No target type specified (use -A for artist, -a for album, -t for track)");
+        process::exit(1);
+    };
+
+    // URLs are downloaded with yt-dlp, whatever else is configured
+    let sources = if track && targets.iter().all(|t| is_url(t)) { vec![ytdlp_source()] } else { source_names(matches) };
+    let source = if sources.is_empty() { None } else { pick_source(&sources, targets, needed) };
+    if !sources.is_empty() && source.is_none() {
+        return;
+    }
+    // Albums are looked for on every usable source, to choose between the
+    // releases they have; anything else comes from the one picked
+    let searched: Vec<String> = match &source {
+        Some(source) if album => std::iter::once(source.clone()).chain(further_sources(&sources, source, needed)).collect(),
+        Some(source) => vec![source.clone()],
+        None => Vec::new(),
+    };
+
+    let partial = partial_selection(matches, targets, track);
+    println!("Downloading {} for: {:?}", download_type, targets);
+    for (artist, title, tracks) in &partial {
+        if tracks.0.is_empty() {
+            println!("Partial: {} - {}", artist, title);
+        } else {
+            println!("Partial: {} - {}, tracks {}", artist, title, tracks);
+        }
+    }
+    if !searched.is_empty() {
+        let described: Vec<String> = searched
+            .iter()
+            .map(|source| match remote_source(source, refresh) {
+                Some(remote) => format!("{} ({})", source, remote.kind()),
+                None => source.clone(),
+            })
+            .collect();
+        println!("Source: {}", described.join(", "));
+    }
+
+    if let Some(target) = target {
+        println!("Format: {}", target);
+    }
+
+    println!("Quality: {}", quality);
+
+    let remotes: Vec<CachedSource> = searched.iter().filter_map(|s| remote_source(s, refresh)).collect();
+    let mut summary = Summary::new("sync");
+    let mut downloads = Vec::new();
+    if !remotes.is_empty() {
+        // Nothing is downloaded on a dry run, so the best release can be taken unasked
+        let confirm = if dry_run { Confirm { without_terminal: WithoutTerminal::Proceed, ..confirm } } else { confirm };
+        downloads = resolve_downloads(&remotes, matches, targets, artist, track, confirm, &mut summary);
+    }
+
+    if dry_run {
+        preview_downloads(&remotes, &downloads);
+        if summary.failed > 0 {
+            process::exit(1);
+        }
+        return;
+    }
+    if !remotes.is_empty() && downloads.is_empty() && summary.failed == 0 {
+        println!("Nothing to download");
+        return;
+    }
+
+    confirm_or_exit(confirm, "Proceed with download?");
+
+    let planned: Vec<PathBuf> = downloads.iter().map(|d| downloads_dir().join(d.name().replace('/', "_"))).collect();
+    if !run_hooks(HookWhen::PreTransaction, HookOperation::Sync, &planned) {
+        process::exit(1);
+    }
+
+    evict_download_cache(verbose);
+
+    // The sources used, and whether anything failed on them
+    let mut outcomes: Vec<(String, bool)> = source.iter().map(|s| (s.clone(), false)).collect();
+    if remotes.is_empty() {
+        summary.succeeded = targets.len();
+    } else {
+        let jobs = matches.get_one::<usize>("jobs").copied().unwrap_or(DOWNLOAD_JOBS);
+        // Albums that couldn't be found count against the source picked
+        outcomes[0].1 = summary.failed > 0;
+        // Started inside the download window, no new track starts after it closes
+        let window = config().schedule.downloads.filter(|_| !matches.get_flag("now"));
+        let until = window.and_then(|w| w.closes(Local::now()));
+        let mut dirs = Vec::new();
+        for (i, remote) in remotes.iter().enumerate() {
+            let albums: Vec<AlbumDownload>;
+            (albums, downloads) = downloads.into_iter().partition(|d| d.source == i);
+            if albums.is_empty() {
+                continue;
+            }
+            let failed = summary.failed;
+            dirs.extend(download_albums(remote, &albums, jobs, until, verbose, &mut summary));
+            match outcomes.iter_mut().find(|(name, _)| name == remote.name()) {
+                Some((_, failures)) => *failures |= summary.failed > failed,
+                None => outcomes.push((remote.name().to_owned(), summary.failed > failed)),
+            }
+        }
+        if let Some(target) = target {
+            convert_downloads(target, &dirs, cpu_jobs(matches), verbose, &mut summary);
+        }
+        run_hooks(HookWhen::PostTransaction, HookOperation::Sync, &dirs);
+    }
+
+    let health = source_health();
+    for (source, failed) in &outcomes {
+        let recorded = if *failed {
+            health.record_failure(source, Local::now())
+        } else {
+            health.record_success(source, Local::now())
+        };
+        if let Err(e) = recorded {
+            eprintln!("Warning: could not update source health: {}", e);
+        }
+    }
+
+    if !partial.is_empty() {
+        let mut db = library_db();
+        for (artist, title, tracks) in &partial {
+            if let Err(e) = db.mark_partial(artist, title, tracks) {
+                eprintln!("Warning: could not mark {} - {} as partial: {}", artist, title, e);
+            }
+        }
+    }
+
+    summary.elapsed = started.elapsed();
+    notify_finished(matches, &summary);
+    if summary.failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// Where `-S` places downloaded albums
+fn downloads_dir() -> PathBuf {
+    config().downloads.clone().unwrap_or_else(|| data_dir().join("downloads"))
+}
+
+/// An album of a remote source, and which of its tracks to download
+struct AlbumDownload {
+    /// Which of the sources searched has it
+    source: usize,
+    album: RemoteAlbum,
+    tracks: Vec<RemoteTrack>,
+}
+
+impl AlbumDownload {
+    fn name(&self) -> String {
+        format!("{} - {}", self.album.artist, self.album.title)
+    }
+}
+
+/// Print which tracks `-S` would download from `sources`, and where to
+fn preview_downloads(sources: &[CachedSource], albums: &[AlbumDownload]) {
+    let cache = download_cache();
+    for album in albums {
+        let source = &sources[album.source];
+        let name = album.name();
+        let dir = downloads_dir().join(name.replace('/', "_"));
+        println!("Would download {} from {} to {}:", name, source.name(), dir.display());
+        for track in &album.tracks {
+            let cached = cache.lookup(source.name(), &track.id).ok().flatten().is_some();
+            let note = match track.size {
+                _ if cached => " (in the download cache)".to_owned(),
+                Some(size) => format!(" ({})", format_size(size)),
+                None => String::new(),
+            };
+            println!("    {}{}", track.file_name, note);
+        }
+    }
+}
+
+/// Look up the albums `-S` targets name on `sources`
+///
+/// Albums are looked for on all of them, by `Artist - Album` or the album
+/// title alone; when they have several releases of one, the user picks
+/// from them ranked by flacman.conf's `rank` (see [`choose_release`]).
+/// Artists (`-A`) and URLs come from the first source, which has all the
+/// albums of an artist. `--tracks` and `-t "Artist - Album - Track"` (by
+/// number or title) select tracks. Targets none of the sources have are
+/// reported and counted as failed in `summary`.
+fn resolve_downloads(
+    sources: &[CachedSource],
+    matches: &ArgMatches,
+    targets: &[&String],
+    artist: bool,
+    track: bool,
+    confirm: Confirm,
+    summary: &mut Summary,
+) -> Vec<AlbumDownload> {
+    let mut fail = |target: &str, reason: String| {
+        eprintln!("Error: {}: {}", target, reason);
+        summary.failed += 1;
+        summary.details.push(format!("{}: {}", target, reason));
+    };
+    let source = &sources[0];
+    let not_found = || {
+        let names: Vec<&str> = sources.iter().map(|s| s.name()).collect();
+        format!("not found on {}", names.join(", "))
+    };
+    let same = |a: &str, b: &str| a.to_lowercase() == b.to_lowercase();
+
+    let selection = matches.get_one::<TrackSelection>("tracks");
+    // "Artist - Album" with the track names asked for, none meaning all
+    let mut wanted: Vec<(String, Vec<String>)> = Vec::new();
+    let mut downloads = Vec::new();
+    for target in targets {
+        if is_url(target) {
+            match source.get_album(target) {
+                Ok(album) => {
+                    let picked: Vec<RemoteTrack> = album
+                        .tracks
+                        .iter()
+                        .filter(|t| selection.is_none_or(|s| t.number.is_some_and(|n| s.contains(n))))
+                        .cloned()
+                        .collect();
+                    if picked.is_empty() {
+                        fail(target, "none of the tracks asked for are on it".to_owned());
+                    } else {
+                        downloads.push(AlbumDownload { source: 0, album, tracks: picked });
+                    }
+                }
+                Err(e) => fail(target, e.to_string()),
+            }
+            continue;
+        }
+        if artist {
+            let found = source.search(RemoteKind::Artist, target).and_then(|hits| {
+                match hits.iter().find(|hit| same(&hit.title, target)) {
+                    Some(hit) => source.get_artist(&hit.id).map(Some),
+                    None => Ok(None),
+                }
+            });
+            match found {
+                Ok(Some(found)) => {
+                    wanted.extend(found.albums.iter().map(|a| (format!("{} - {}", found.name, a.title), Vec::new())))
+                }
+                Ok(None) => fail(target, not_found()),
+                Err(e) => fail(target, e.to_string()),
+            }
+            continue;
+        }
+
+        let (album, name) = match target.splitn(3, " - ").map(str::trim).collect::<Vec<_>>()[..] {
+            [artist, title, name] if track => (format!("{} - {}", artist, title), Some(name.to_owned())),
+            _ => (target.trim().to_owned(), None),
+        };
+        match wanted.iter_mut().find(|(a, _)| same(a, &album)) {
+            Some((_, names)) => names.extend(name),
+            None => wanted.push((album, name.into_iter().collect())),
+        }
+    }
+
+    let ranking = if config().rank.is_empty() { &RankKey::DEFAULT[..] } else { &config().rank[..] };
+    let ladder = quality_ladder(matches);
+    for (name, tracks) in wanted {
+        let (mut releases, errors) = find_releases(sources, &name);
+        if releases.is_empty() {
+            match errors.into_iter().next() {
+                Some(e) => fail(&name, e.to_string()),
+                None => fail(&name, not_found()),
+            }
+            continue;
+        }
+        for e in errors {
+            eprintln!("Warning: {}: {}", name, e);
+        }
+        rank_releases(&mut releases, ranking, &ladder);
+        let Some(release) = choose_release(&name, releases, confirm) else {
+            println!("Skipping {}", name);
+            continue;
+        };
+
+        let is_wanted = |t: &RemoteTrack| match selection {
+            Some(selection) => t.number.is_some_and(|n| selection.contains(n)),
+            None => {
+                let named = |name: &String| same(name, &t.title) || name.parse().is_ok_and(|n| t.number == Some(n));
+                tracks.is_empty() || tracks.iter().any(named)
+            }
+        };
+        let picked: Vec<RemoteTrack> = release.album.tracks.iter().filter(|t| is_wanted(t)).cloned().collect();
+        if picked.is_empty() {
+            fail(&name, "none of the tracks asked for are on it".to_owned());
+            continue;
+        }
+        downloads.push(AlbumDownload { source: release.source_rank, album: release.album, tracks: picked });
+    }
+
+    downloads
+}
+
+/// The releases `sources` have of `name`, an `Artist - Album` or album
+/// title, one per format for sources that offer several
+///
+/// # Returns
+/// The releases, and the errors of the sources that couldn't be searched
+fn find_releases(sources: &[CachedSource], name: &str) -> (Vec<Release>, Vec<RemoteError>) {
+    let same = |a: &str, b: &str| a.to_lowercase() == b.to_lowercase();
+    let mut releases = Vec::new();
+    let mut errors = Vec::new();
+
+    for (rank, source) in sources.iter().enumerate() {
+        let hits = match source.search(RemoteKind::Album, name) {
+            Ok(hits) => hits,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        for hit in hits {
+            let exact = hit.artist.as_ref().is_some_and(|a| same(&format!("{} - {}", a, hit.title), name));
+            if !exact && !same(&hit.title, name) {
+                continue;
+            }
+            let offers: Vec<(String, Option<String>)> = match &hit.formats[..] {
+                [_, _, ..] => hit.formats.iter().map(|f| (format!("{}/{}", hit.id, f), Some(f.clone()))).collect(),
+                formats => vec![(hit.id.clone(), formats.first().cloned())],
+            };
+            for (id, format) in offers {
+                match source.get_album(&id) {
+                    Ok(album) => releases.push(Release::new(source.name(), rank, exact, album, format)),
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+    }
+
+    (releases, errors)
+}
+
+/// Ask which of `releases` of `name`, ranked best first, to download
+///
+/// With one release, `--noconfirm` or nobody to ask, the first is taken.
+///
+/// # Returns
+/// The release, or `None` if the user skipped the album
+fn choose_release(name: &str, mut releases: Vec<Release>, confirm: Confirm) -> Option<Release> {
+    let labels: Vec<String> = releases
+        .iter()
+        .map(|release| {
+            let year = release.album.year.map(|y| format!(" ({})", y)).unwrap_or_default();
+            let size = release.size().map(|s| format!(", {}", format_size(s))).unwrap_or_default();
+            format!("{} - {}{} [{}] {}{}", release.album.artist, release.album.title, year, release.source, release.format, size)
+        })
+        .collect();
+
+    if labels.len() > 1 && !confirm.noconfirm && std::io::stdin().is_terminal() {
+        println!(":: There are {} releases of {}:", labels.len(), name);
+    }
+    match confirm.choose("Enter a number", &labels) {
+        Ok(Some(i)) => {
+            if labels.len() > 1 {
+                println!("{}: taking {}", name, labels[i]);
+            }
+            Some(releases.swap_remove(i))
+        }
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Download `albums` from `source` into [`downloads_dir`], one directory
+/// per album with a source sidecar for `--watch` to import
+///
+/// Tracks go through the download cache: ones it already has aren't
+/// fetched again, and an interrupted download resumes from its partial
+/// file on the next run. Per-track [`progress`] is drawn on stderr. No track
+/// starts after `until`; the rest wait for the next run.
+///
+/// # Returns
+/// The directories of the albums downloaded completely
+fn download_albums(
+    source: &dyn RemoteSource,
+    albums: &[AlbumDownload],
+    jobs: usize,
+    until: Option<DateTime<Local>>,
+    verbose: bool,
+    summary: &mut Summary,
+) -> Vec<PathBuf> {
+    let cache = download_cache();
+    let cached = |track: &RemoteTrack| {
+        cache.lookup(source.name(), &track.id).ok().flatten().map(|entry| cache.blob_path(&entry.sha256))
+    };
+
+    let mut blobs: BTreeMap<&str, PathBuf> = BTreeMap::new();
+    let mut downloads = Vec::new();
+    let mut album_of = Vec::new();
+    for (a, album) in albums.iter().enumerate() {
+        for track in &album.tracks {
+            if let Some(blob) = cached(track).filter(|blob| blob.is_file()) {
+                blobs.insert(&track.id, blob);
+                continue;
+            }
+            match cache.begin(source.name(), &track.id) {
+                Ok(dest) => {
+                    downloads.push(DownloadJob { track: track.clone(), dest });
+                    album_of.push(a);
+                }
+                Err(e) => {
+                    eprintln!("Error: Could not use the download cache: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+    if !blobs.is_empty() {
+        println!("{} track(s) already in the download cache", blobs.len());
+    }
+
+    let cancel = cancel_flag();
+    let stop = AtomicBool::new(false);
+    let progress = progress(downloads.len(), "Downloading");
+    let totals = ProgressTotals::new();
+    let finished = AtomicBool::new(false);
+    let label = |i: usize| format!("{} - {}", albums[album_of[i]].album.artist, downloads[i].track.file_name);
+
+    let report = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            while !finished.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(200));
+                if cancel.load(Ordering::Relaxed) || until.is_some_and(|until| Local::now() >= until) {
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+        });
+
+        let report = Downloader::new(jobs).run(source, &downloads, &stop, |i, event| {
+            let message = match event {
+                DownloadEvent::Progress(done, total) => {
+                    totals.update(progress.as_ref(), i, &label(i), done, total);
+                    return;
+                }
+                DownloadEvent::Started => return,
+                DownloadEvent::Retrying { attempt, error, delay } => Some(format!(
+                    "Warning: {}: {}; retrying in {}s (attempt {})",
+                    label(i),
+                    error,
+                    delay.as_secs(),
+                    attempt + 1
+                )),
+                DownloadEvent::Finished(size) => {
+                    verbose.then(|| format!("Downloaded {} ({})", label(i), format_size(size)))
+                }
+                DownloadEvent::Failed(error) => Some(format!("Error: {}: {}", label(i), error)),
+            };
+
+            if matches!(event, DownloadEvent::Finished(_) | DownloadEvent::Failed(_)) {
+                totals.finish(progress.as_ref(), i);
+            }
+            if let Some(message) = message {
+                progress.message(&message);
+            }
+        });
+
+        finished.store(true, Ordering::Relaxed);
+        report
+    });
+    drop(progress);
+
+    let mut failures: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for (i, error) in &report.failed {
+        failures.entry(album_of[*i]).or_default().push(format!("{}: {}", downloads[*i].track.file_name, error));
+    }
+    for (i, _) in &report.finished {
+        let job = &downloads[*i];
+        let origin = BlobOrigin {
+            source: source.name().to_owned(),
+            remote_id: job.track.id.clone(),
+            name: job.track.file_name.clone(),
+            album: Some(albums[album_of[*i]].name()),
+        };
+        match cache.store(&job.dest, origin) {
+            Ok(stored) => {
+                blobs.insert(&job.track.id, stored.path);
+            }
+            Err(e) => failures.entry(album_of[*i]).or_default().push(format!("{}: {}", job.track.file_name, e)),
+        }
+    }
+    if let Err(e) = quota_ledger().record(source.name(), report.bytes()) {
+        eprintln!("Warning: could not record download usage: {}", e);
+    }
+
+    let mut complete_dirs = Vec::new();
+    for (a, download) in albums.iter().enumerate() {
+        let name = download.name();
+        let dir = downloads_dir().join(name.replace('/', "_"));
+        let mut messages = failures.remove(&a).unwrap_or_default();
+        let mut placed = 0;
+        let mut bytes = 0;
+        let mut changes = Vec::new();
+        for track in &download.tracks {
+            let Some(blob) = blobs.get(track.id.as_str()) else {
+                continue;
+            };
+            let path = dir.join(&track.file_name);
+            match std::fs::create_dir_all(&dir).and_then(|_| std::fs::copy(blob, &path)) {
+                Ok(size) => {
+                    placed += 1;
+                    bytes += size;
+                    let tags = SourceTags {
+                        artist: download.album.artist.clone(),
+                        album: download.album.title.clone(),
+                        title: track.title.clone(),
+                        year: download.album.year,
+                        track: track.number,
+                    };
+                    if let Err(e) = write_missing_tags(&path, &tags) {
+                        eprintln!("Warning: could not tag {}: {}", path.display(), e);
+                    }
+                    changes.push(FileChange::Created { path });
+                }
+                Err(e) => messages.push(format!("{}: {}", track.file_name, e)),
+            }
+        }
+
+        let complete = placed == download.tracks.len();
+        if complete {
+            let info = SourceInfo {
+                source: source.name().to_owned(),
+                remote_id: Some(download.album.id.clone()),
+                downloaded: Some(Local::now()),
+                ..Default::default()
+            };
+            match info.save(&dir) {
+                Ok(()) => changes.push(FileChange::Created { path: dir.join(SOURCE_SIDECAR) }),
+                Err(e) => eprintln!("Warning: could not write {} in {}: {}", SOURCE_SIDECAR, dir.display(), e),
+            }
+            println!("Downloaded {} to {}", name, dir.display());
+            summary.succeeded += 1;
+            complete_dirs.push(dir.clone());
+        } else {
+            for message in &messages {
+                summary.details.push(format!("{}: {}", name, message));
+            }
+            summary.failed += 1;
+        }
+
+        let outcome = if complete {
+            TxOutcome::Success
+        } else if report.interrupted && messages.is_empty() {
+            TxOutcome::Cancelled
+        } else if placed > 0 {
+            TxOutcome::Partial
+        } else {
+            TxOutcome::Failed
+        };
+        let mut record = TxRecord::new("sync", vec![name], outcome);
+        record.source = Some(source.name().to_owned());
+        record.files = placed as u64;
+        record.bytes = bytes;
+        record.messages = messages;
+        record.changes = changes;
+        log_transaction(record);
+    }
+
+    if report.interrupted && cancel.load(Ordering::Relaxed) {
+        exit_cancelled(summary.succeeded, albums.len(), "run the same -S again to resume the rest");
+    }
+    if report.interrupted {
+        println!("Paused: the download window closed; run the same -S again in the next one to resume the rest");
+    }
+    complete_dirs
+}
+
+/// Workers for work bound by the CPU: `-j`, else one per CPU
+fn cpu_jobs(matches: &ArgMatches) -> usize {
+    let cpus = || std::thread::available_parallelism().map_or(1, |n| n.get());
+    matches.get_one::<usize>("jobs").copied().unwrap_or_else(cpus)
+}
+
+/// `format` at the bitrate `quality` asks of it; exits if flacman can't
+/// convert to it
+fn convert_target(format: &str, quality: &QualityLadder) -> ConvertTarget {
+    match format.parse::<AudioFormat>() {
+        Ok(format) => ConvertTarget::new(format, quality),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Convert the tracks of the albums downloaded to `dirs` that aren't in
+/// the `-f` format, in place
+///
+/// Each converted track replaces its original, which stays in the download
+/// cache. An album any track of which fails to convert counts as failed.
+fn convert_downloads(target: ConvertTarget, dirs: &[PathBuf], jobs: usize, verbose: bool, summary: &mut Summary) {
+    let mut work: Vec<ConvertJob> = Vec::new();
+    for dir in dirs {
+        match flacman_fs::find_audio_files(dir) {
+            Ok(files) => work.extend(plan_conversion(&files, dir, dir, target.format)),
+            Err(e) => eprintln!("Warning: could not list {}: {}", dir.display(), e),
+        }
+    }
+    if work.is_empty() {
+        return;
+    }
+    let converter = match Converter::new(target) {
+        Ok(converter) => converter,
+        Err(e) => {
+            eprintln!("Error: {}; the downloads were left as they are", e);
+            summary.failed += 1;
+            summary.details.push(format!("convert to {}: {}", target, e));
+            return;
+        }
+    };
+
+    let cancel = cancel_flag();
+    let progress = progress(work.len(), "Converting");
+    let report = converter.convert_files(&work, jobs, cancel, |i, job, failure| {
+        match failure {
+            Some(e) => progress.message(&format!("Error: {}: {}", job.source.display(), e)),
+            None if verbose => progress.println(&format!("Converted {}", job.dest.display())),
+            None => {}
+        }
+        progress.finish(i);
+    });
+    drop(progress);
+
+    let converted: HashSet<&PathBuf> = report.converted.iter().collect();
+    for dir in dirs {
+        let jobs: Vec<&ConvertJob> = work.iter().filter(|job| job.source.starts_with(dir)).collect();
+        if jobs.is_empty() {
+            continue;
+        }
+        let mut record = TxRecord::new("convert", vec![dir.display().to_string()], TxOutcome::Success);
+        for job in jobs.iter().filter(|job| converted.contains(&job.dest)) {
+            match std::fs::remove_file(&job.source) {
+                Ok(()) => record.changes.push(FileChange::Deleted { path: job.source.clone() }),
+                Err(e) => record.messages.push(format!("could not remove {}: {}", job.source.display(), e)),
+            }
+            record.changes.push(FileChange::Created { path: job.dest.clone() });
+            record.files += 1;
+            record.bytes += job.dest.metadata().map_or(0, |m| m.len());
+        }
+
+        let failures: Vec<String> = report
+            .failures
+            .iter()
+            .filter(|f| f.path.starts_with(dir))
+            .map(|f| format!("{}: {}", f.path.display(), f.message))
+            .collect();
+        if !failures.is_empty() {
+            record.outcome = if record.files > 0 { TxOutcome::Partial } else { TxOutcome::Failed };
+            summary.succeeded = summary.succeeded.saturating_sub(1);
+            summary.failed += 1;
+            summary.details.extend(failures.iter().cloned());
+            record.messages.extend(failures);
+        } else if record.files < jobs.len() as u64 {
+            record.outcome = TxOutcome::Cancelled;
+        } else {
+            println!("Converted {} to {}", dir.display(), target);
+        }
+        log_transaction(record);
+    }
+
+    if report.interrupted && cancel.load(Ordering::Relaxed) {
+        exit_cancelled(report.converted.len(), work.len(), "run the same -S again to convert the rest");
+    }
+}
+
+/// Set by `--quiet`
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Progress of `total` items of an operation, drawn on stderr unless that
+/// isn't a terminal or `--quiet` is given
+fn progress(total: usize, what: &str) -> Box<dyn Progress> {
+    if QUIET.load(Ordering::Relaxed) || !std::io::stderr().is_terminal() {
+        return Box::new(NoProgress);
+    }
+    Box::new(CliProgress::new(total as u64, what))
+}
+
+/// Progress drawn with indicatif: a line per item under way, with its
+/// throughput and time left when its size is known, above a bar counting
+/// the items done, their rate and the time left for the rest
+struct CliProgress {
+    bars: MultiProgress,
+    overall: ProgressBar,
+    items: Mutex<BTreeMap<usize, ProgressBar>>,
+}
+
+impl CliProgress {
+    fn new(total: u64, what: &str) -> Self {
+        let bars = MultiProgress::with_draw_target(ProgressDrawTarget::stderr());
+        let style = Self::style("{prefix} [{bar:30}] {pos}/{len} ({rate}, {eta} left)")
+            .with_key("rate", |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                let _ = write!(w, "{:.1}/s", state.per_sec());
+            });
+        let overall = bars.add(ProgressBar::new(total).with_style(style).with_prefix(what.to_owned()));
+        CliProgress { bars, overall, items: Mutex::new(BTreeMap::new()) }
+    }
+
+    fn style(template: &str) -> ProgressStyle {
+        ProgressStyle::with_template(template).expect("progress templates parse").progress_chars("#>.")
+    }
+}
+
+impl Progress for CliProgress {
+    fn start(&self, id: usize, label: &str, len: Option<u64>) {
+        let (bar, template) = match len {
+            Some(len) => (ProgressBar::new(len), "{msg:48} [{bar:20}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"),
+            None => (ProgressBar::no_length(), "{msg:48} {bytes} so far ({bytes_per_sec})"),
+        };
+        let label: String = label.chars().take(48).collect();
+        let bar = self.bars.insert_before(&self.overall, bar.with_style(Self::style(template)).with_message(label));
+        self.items.lock().expect("progress lock poisoned").insert(id, bar);
+    }
+
+    fn advance(&self, id: usize, delta: u64) {
+        if let Some(bar) = self.items.lock().expect("progress lock poisoned").get(&id) {
+            bar.inc(delta);
+        }
+    }
+
+    fn finish(&self, id: usize) {
+        if let Some(bar) = self.items.lock().expect("progress lock poisoned").remove(&id) {
+            bar.finish_and_clear();
+            self.bars.remove(&bar);
+        }
+        self.overall.inc(1);
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.bars.suspend(f);
+    }
+}
+
+impl Drop for CliProgress {
+    fn drop(&mut self) {
+        let _ = self.bars.clear();
+    }
+}
+
+/// Albums `-S` fetches only some tracks of: every album target with
+/// `--tracks`, or the album of each "Artist - Album - Track" target of `-t`
+///
+/// # Returns
+/// `(artist, album, tracks)`; tracks named by title leave the selection empty
+fn partial_selection(matches: &ArgMatches, targets: &[&String], track: bool) -> Vec<(String, String, TrackSelection)> {
+    let mut partial = Vec::new();
+
+    if let Some(tracks) = matches.get_one::<TrackSelection>("tracks") {
+        for target in targets {
+            let Some((artist, title)) = target.split_once(" - ") else {
+                eprintln!("Error: --tracks needs albums as \"Artist - Album\", not {:?}", target);
+                process::exit(1);
+            };
+            partial.push((artist.trim().to_owned(), title.trim().to_owned(), tracks.clone()));
+        }
+    } else if track {
+        for target in targets {
+            let parts: Vec<&str> = target.splitn(3, " - ").map(str::trim).collect();
+            let [artist, title, name] = parts[..] else {
+                continue;
+            };
+            // A track named by number is selected; one named by title (even
+            // "1979") can't be until it has been looked up
+            let number = name.parse::<u32>().ok().filter(|n| (1..1000).contains(n));
+            let tracks = TrackSelection(number.into_iter().collect());
+            match partial.iter_mut().find(|(a, t, _)| a == artist && t == title) {
+                Some((_, _, selection)) => selection.0.extend(tracks.0),
+                None => partial.push((artist.to_owned(), title.to_owned(), tracks)),
+            }
+        }
+    }
+
+    partial
+}
+
+fn search_cache() -> SearchCache {
+    SearchCache::new(data_dir().join("search-cache.json"))
+}
+
+/// Where remote results came from
+enum Origin {
+    Remote,
+    Cache { fetched: DateTime<Local>, stale: bool },
+}
+
+impl Origin {
+    /// Marker printed after cached results
+    fn note(&self) -> Option<String> {
+        match self {
+            Origin::Remote => None,
+            Origin::Cache { fetched, stale } => Some(format!(
+                "(cached {}{})",
+                fetched.format("%Y-%m-%d %H:%M"),
+                if *stale { ", may be out of date" } else { "" }
+            )),
+        }
+    }
+}
+
+/// Fetch from a remote source with the search cache in front of it
+///
+/// Fresh cached results answer without going online. With `-y` the
+/// source is always asked, and the cache only answers if it can't be
+/// reached; `-yy` bypasses the cache entirely.
+fn cached_fetch<T, E, F>(key: &str, refresh: u8, fetch: F) -> (T, Origin)
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+    E: std::fmt::Display,
+    F: FnOnce() -> Result<T, E>,
+{
+    let cache = search_cache();
+    let now = Local::now();
+    let cached = if refresh >= 2 {
+        None
+    } else {
+        cache.get::<T>(key, now).unwrap_or_else(|e| {
+            eprintln!("Warning: ignoring unreadable search cache: {}", e);
+            None
+        })
+    };
+
+    let cached = match cached {
+        Some(hit) if refresh == 0 && !hit.stale => {
+            return (hit.results, Origin::Cache { fetched: hit.fetched, stale: false });
+        }
+        cached => cached,
+    };
+
+    match fetch() {
+        Ok(results) => {
+            if let Err(e) = cache.put(key, &results, now) {
+                eprintln!("Warning: could not update search cache: {}", e);
+            }
+            (results, Origin::Remote)
+        }
+        Err(e) => match cached {
+            Some(cached) => {
+                eprintln!("Warning: {}; answering from the cache", e);
+                (cached.results, Origin::Cache { fetched: cached.fetched, stale: cached.stale })
+            }
+            None => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+/// A remote search result as `--json` prints it
+#[derive(serde::Serialize)]
+struct SearchResultJson<'a, T> {
+    source: &'a str,
+    #[serde(flatten)]
+    hit: &'a T,
+    /// Answered from the search cache
+    cached: bool,
+}
+
+/// Add `hits` from `source` to the `--json` output
+fn push_search_json<T: serde::Serialize>(json: &mut Vec<serde_json::Value>, source: &str, hits: &[T], origin: &Origin) {
+    let cached = matches!(origin, Origin::Cache { .. });
+    for hit in hits {
+        match serde_json::to_value(SearchResultJson { source, hit, cached }) {
+            Ok(value) => json.push(value),
+            Err(e) => eprintln!("Warning: {}", e),
+        }
+    }
+}
+
+/// Search MusicBrainz, through the search cache
+///
+/// With `json`, the results are added to it instead of printed.
+pub fn remote_search(
+    kinds: &[SearchKind],
+    query: &str,
+    refresh: u8,
+    verbose: bool,
+    mut json: Option<&mut Vec<serde_json::Value>>,
+) {
+    for kind in kinds {
+        let key = SearchCache::key("musicbrainz", &kind.to_string(), query);
+        let (hits, origin) = cached_fetch(&key, refresh, || search_musicbrainz(musicbrainz(), *kind, query));
+        if let Some(json) = json.as_deref_mut() {
+            push_search_json(json, "musicbrainz", &hits, &origin);
+            continue;
+        }
+
+        if kinds.len() > 1 {
+            println!("{}s:", kind);
+        }
+        if hits.is_empty() {
+            println!("No {}s found for {:?}", kind, query);
+        }
+        for hit in &hits {
+            let mut line = hit.title.clone();
+            if let Some(artist) = &hit.artist {
+                line = match kind {
+                    SearchKind::Artist => format!("{} ({})", line, artist),
+                    _ => format!("{} - {}", artist, line),
+                };
+            }
+            if let Some(date) = &hit.date {
+                line.push_str(&format!(" [{}]", date));
+            }
+            if verbose {
+                line.push_str(&format!(" {} ({}%)", hit.id, hit.score));
+            }
+            println!("{}", line);
+        }
+        if let Some(note) = origin.note() {
+            println!("{}", note);
+        }
+    }
+}
+
+/// Whether an operation limited to `window` by the schedule in flacman.conf
+/// may start now
+///
+/// Outside its window the operation is deferred and logged as vetoed, so a
+/// job started too early leaves a trace; `--now` runs it anyway.
+///
+/// # Arguments
+/// * `operation` - Name in the transaction log
+/// * `kind` - What the window is for, as named in `[schedule]`
+fn within_schedule(matches: &ArgMatches, operation: &str, kind: &str, window: Option<TimeWindow>) -> bool {
+    let now = Local::now();
+    let Some(window) = window.filter(|w| !w.contains(now.time())) else {
+        return true;
+    };
+    if matches.get_flag("now") {
+        println!("Running outside the {} window ({}) as asked by --now", kind, window);
+        return true;
+    }
+
+    let next = window.next_open(now).format("%Y-%m-%d %H:%M");
+    println!("Deferred: {} are scheduled for {}, next at {}; use --now to run anyway", kind, window, next);
+    let targets = matches.get_many::<String>("targets").unwrap_or_default().cloned().collect();
+    let mut record = TxRecord::new(operation, targets, TxOutcome::Vetoed);
+    record.messages.push(format!("deferred: outside the {} window {}", kind, window));
+    log_transaction(record);
+    false
+}
+
+/// Sources `-S` may download from: those given with `--source`, else
+/// every source in flacman.conf, best first
+fn source_names(matches: &ArgMatches) -> Vec<String> {
+    match matches.get_many::<String>("source") {
+        Some(names) => names.cloned().collect(),
+        None => config().sources.iter().map(|s| s.name.clone()).collect(),
+    }
+}
+
+/// Name `-S` downloads URLs under when no `ytdlp` source is configured
+const YTDLP_SOURCE: &str = "yt-dlp";
+
+/// The source `-S -t` downloads URLs from: the first configured `ytdlp`
+/// source, else yt-dlp as it is
+fn ytdlp_source() -> String {
+    let configured = config().sources.iter().find(|s| s.kind == "ytdlp");
+    configured.map_or_else(|| YTDLP_SOURCE.to_owned(), |s| s.name.clone())
+}
+
+/// The source configured as `name` in flacman.conf, with its catalog
+/// cached as `-y` given `refresh` times asks
+///
+/// # Returns
+/// None if no source is configured by that name; the name still counts
+/// for source health and quotas
+fn remote_source(name: &str, refresh: u8) -> Option<CachedSource> {
+    let unconfigured;
+    let source = match config().sources.iter().find(|s| s.name == name) {
+        Some(source) => source,
+        None if name == YTDLP_SOURCE => {
+            unconfigured = SourceConfig { name: name.to_owned(), kind: "ytdlp".to_owned(), ..Default::default() };
+            &unconfigured
+        }
+        None => return None,
+    };
+    match SourceRegistry::with_builtins().download_as(download_user().cloned()).create(source) {
+        Ok(source) => Some(CachedSource::new(source, catalog_cache(), refresh)),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Where the remote catalogs of `-S` are cached
+fn catalog_cache() -> CatalogCache {
+    CatalogCache::new(cache_dir().join("catalogs"))
+}
+
+/// `-Sy`: bring the cached catalogs of `sources` up to date
+///
+/// `-y` asks each source whether its catalog changed, `-yy` downloads
+/// it again regardless. Sources that can't be reached keep what is cached.
+fn refresh_catalogs(sources: &[String], refresh: u8) {
+    println!("Refreshing remote source catalogs...");
+    for name in sources {
+        let Some(source) = remote_source(name, refresh) else {
+            eprintln!("Warning: {}", RemoteError::UnknownSource(name.clone()));
+            continue;
+        };
+        match source.update() {
+            Ok(None) => println!(" {} has no catalog to cache", name),
+            Ok(Some(status)) => match status.origin {
+                CatalogOrigin::Unchanged | CatalogOrigin::Cached => println!(" {} is up to date", name),
+                CatalogOrigin::Downloaded => println!(" {}: {} albums", name, status.albums),
+                CatalogOrigin::Offline(reason) => {
+                    eprintln!("Warning: could not refresh {}: {}", name, reason);
+                    println!(" {}: keeping the catalog of {}", name, status.fetched.format("%Y-%m-%d %H:%M"));
+                }
+            },
+            Err(e) => eprintln!("Warning: could not refresh {}: {}", name, e),
+        }
+    }
+}
+
+/// Search a configured source, through its cached catalog, or the search
+/// cache for what a catalog doesn't list
+///
+/// With `json`, the results are added to it instead of printed.
+fn source_search(
+    source: &CachedSource,
+    kinds: &[SearchKind],
+    query: &str,
+    refresh: u8,
+    verbose: bool,
+    mut json: Option<&mut Vec<serde_json::Value>>,
+) {
+    let catalog = source.update().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    if let Some(CatalogOrigin::Offline(reason)) = catalog.as_ref().map(|c| &c.origin) {
+        eprintln!("Warning: {}; answering from the cached catalog", reason);
+    }
+
+    for kind in kinds {
+        let remote_kind = match kind {
+            SearchKind::Artist => RemoteKind::Artist,
+            SearchKind::Album => RemoteKind::Album,
+            SearchKind::Track => RemoteKind::Track,
+        };
+        let (hits, origin) = match &catalog {
+            Some(status) if remote_kind != RemoteKind::Track => {
+                let hits = source.search(remote_kind, query).unwrap_or_else(|e| {
+                    eprintln!("Error: {}", e);
+                    process::exit(1);
+                });
+                let origin = match status.origin {
+                    CatalogOrigin::Cached | CatalogOrigin::Offline(_) => {
+                        Origin::Cache { fetched: status.fetched, stale: status.stale }
+                    }
+                    CatalogOrigin::Unchanged | CatalogOrigin::Downloaded => Origin::Remote,
+                };
+                (hits, origin)
+            }
+            _ => {
+                let key = SearchCache::key(&format!("source:{}", source.name()), &kind.to_string(), query);
+                cached_fetch(&key, refresh, || source.search(remote_kind, query))
+            }
+        };
+        if let Some(json) = json.as_deref_mut() {
+            push_search_json(json, source.name(), &hits, &origin);
+            continue;
+        }
+
+        if kinds.len() > 1 {
+            println!("{}s:", kind);
+        }
+        if hits.is_empty() {
+            println!("No {}s found for {:?}", kind, query);
+        }
+        for hit in &hits {
+            let mut line = match &hit.artist {
+                Some(artist) => format!("{} - {}", artist, hit.title),
+                None => hit.title.clone(),
+            };
+            if let Some(year) = hit.year {
+                line.push_str(&format!(" [{}]", year));
+            }
+            if !hit.formats.is_empty() {
+                line.push_str(&format!(" ({})", hit.formats.join(", ")));
+            }
+            if verbose {
+                line.push_str(&format!(" {}", hit.id));
+            }
+            println!("{}", line);
+        }
+        if let Some(note) = origin.note() {
+            println!("{}", note);
+        }
+    }
+}
+
+/// Whether `s` looks like a MusicBrainz ID
+fn is_mbid(s: &str) -> bool {
+    s.len() == 36 && s.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+}
+
+/// Show a release and its track list, through the search cache
+pub fn release_info(id: &str, refresh: u8) {
+    let key = SearchCache::key("musicbrainz", "release", id);
+    let (release, origin) = cached_fetch(&key, refresh, || lookup_release(musicbrainz(), id));
+
+    println!("{} - {}", release.artist, release.title);
+    for (label, value) in [("Date", &release.date), ("Country", &release.country)] {
+        if let Some(value) = value {
+            println!("{:<8}: {}", label, value);
+        }
+    }
+    for (disc, tracks) in release.media.iter().enumerate() {
+        for (number, title) in tracks.iter().enumerate() {
+            if release.media.len() > 1 {
+                println!("  {}-{:02} {}", disc + 1, number + 1, title);
+            } else {
+                println!("  {:02} {}", number + 1, title);
+            }
+        }
+    }
+    if let Some(note) = origin.note() {
+        println!("{}", note);
+    }
+}
+
+fn quota_policy_path() -> PathBuf {
+    data_dir().join("quotas.json")
+}
+
+fn quota_ledger() -> QuotaLedger {
+    QuotaLedger::new(data_dir().join("downloads.log"))
+}
+
+/// Check `source`'s download quotas before a sync
+///
+/// Warns when a quota is nearly used up. Once one is exhausted,
+/// non-urgent downloads (the `--needed` wantlist) are deferred until it
+/// resets; downloads the user asked for by name go ahead with a warning.
+///
+/// # Returns
+/// Whether the download should proceed
+fn quota_allows(source: &str, deferrable: bool) -> bool {
+    let policy = match QuotaPolicy::load(&quota_policy_path()) {
+        Ok(policy) => policy,
+        Err(e) => {
+            eprintln!("Warning: ignoring unreadable quotas: {}", e);
+            return true;
+        }
+    };
+    let ledger = quota_ledger().read_all().unwrap_or_else(|e| {
+        eprintln!("Warning: could not read download ledger: {}", e);
+        Vec::new()
+    });
+
+    let usage = policy.usage(source, &ledger, Local::now());
+    let Some(worst) = usage.iter().max_by_key(|u| u.level()) else {
+        return true;
+    };
+
+    let describe = |u: &flacman_core::QuotaUsage| {
+        format!(
+            "{} of {} per {} used, resets {}",
+            format_size(u.used),
+            format_size(u.quota.limit),
+            u.quota.window,
+            u.resets.format("%Y-%m-%d %H:%M")
+        )
+    };
+
+    match worst.level() {
+        QuotaLevel::Within => true,
+        QuotaLevel::Approaching => {
+            eprintln!("Warning: {} is approaching its download quota ({})", source, describe(worst));
+            true
+        }
+        QuotaLevel::Exhausted if deferrable => {
+            println!("Deferred: {} has used up its download quota ({})", source, describe(worst));
+            let mut record = TxRecord::new("sync", vec![source.to_owned()], TxOutcome::Vetoed);
+            record.messages.push(format!("deferred: quota exhausted until {}", worst.resets.to_rfc3339()));
+            log_transaction(record);
+            false
+        }
+        QuotaLevel::Exhausted => {
+            eprintln!("Warning: {} is over its download quota ({})", source, describe(worst));
+            true
+        }
+    }
+}
+
+fn source_health() -> SourceHealth {
+    SourceHealth::new(data_dir().join("source-health.json"))
+}
+
+/// The first of `sources` that is healthy and within its download quota
+///
+/// Passing over a higher-priority source is logged as a failover, so the
+/// transaction log shows why a release came from where it did.
+fn pick_source(sources: &[String], targets: &[&String], deferrable: bool) -> Option<String> {
+    let failover = source_health().failover(sources, Local::now(), |source| {
+        if quota_allows(source, deferrable) { Ok(()) } else { Err("download quota used up".to_owned()) }
+    });
+    let failover = failover.unwrap_or_else(|e| {
+        eprintln!("Warning: ignoring unreadable source health: {}", e);
+        Failover { source: sources.first().cloned(), skipped: Vec::new() }
+    });
+
+    if failover.skipped.is_empty() {
+        return failover.source;
+    }
+    for (skipped, reason) in &failover.skipped {
+        println!("Passing over {}: {}", skipped, reason);
+    }
+
+    let outcome = if failover.source.is_some() { TxOutcome::Success } else { TxOutcome::Vetoed };
+    let mut record = TxRecord::new("failover", targets.iter().map(|t| t.to_string()).collect(), outcome);
+    record.source = failover.source.clone();
+    record.messages = failover.skipped.iter().map(|(source, reason)| format!("{}: {}", source, reason)).collect();
+    log_transaction(record);
+
+    if failover.source.is_none() {
+        println!("Deferred: no source is usable right now");
+    }
+    failover.source
+}
+
+/// The sources after `picked` in `sources` that are healthy and within
+/// their download quota too, in order
+fn further_sources(sources: &[String], picked: &str, deferrable: bool) -> Vec<String> {
+    let health = source_health();
+    let mut rest = &sources[sources.iter().position(|s| s == picked).map_or(sources.len(), |i| i + 1)..];
+    let within_quota =
+        |source: &str| if quota_allows(source, deferrable) { Ok(()) } else { Err("download quota used up".to_owned()) };
+    let mut further = Vec::new();
+    while let Ok(Failover { source: Some(source), .. }) = health.failover(rest, Local::now(), within_quota) {
+        rest = &rest[rest.iter().position(|s| *s == source).map_or(rest.len(), |i| i + 1)..];
+        further.push(source);
+    }
+    further
+}
+
+pub fn set_quota(source: &str, window: &str, size: &str) {
+    let (window, limit) = match (window.parse::<QuotaWindow>(), parse_size(size)) {
+        (Ok(window), Ok(limit)) => (window, limit),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let path = quota_policy_path();
+    let mut policy = QuotaPolicy::load(&path).unwrap_or_else(|e| {
+        eprintln!("Error: {}: {}", path.display(), e);
+        process::exit(1);
+    });
+    policy.set(source, window, limit);
+
+    if let Err(e) = policy.save(&path) {
+        eprintln!("Error: {}: {}", path.display(), e);
+        process::exit(1);
+    }
+
+    if limit == 0 {
+        println!("Removed the per-{} quota of {}", window, source);
+    } else {
+        println!("{} may download {} per {}", source, format_size(limit), window);
+    }
+}
+
+pub fn show_quotas() {
+    let policy = match QuotaPolicy::load(&quota_policy_path()) {
+        Ok(policy) => policy,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    if policy.quotas.is_empty() {
+        println!("No download quotas set; add one with --set-quota");
+        return;
+    }
+
+    let ledger = quota_ledger().read_all().unwrap_or_default();
+    let now = Local::now();
+
+    for source in policy.quotas.keys() {
+        for usage in policy.usage(source, &ledger, now) {
+            let marker = match usage.level() {
+                QuotaLevel::Within => "",
+                QuotaLevel::Approaching => " (approaching)",
+                QuotaLevel::Exhausted => " (exhausted)",
+            };
+            println!(
+                "{}: {} of {} per {} ({:.0}%){}, resets {}",
+                source,
+                format_size(usage.used),
+                format_size(usage.quota.limit),
+                usage.quota.window,
+                usage.fraction() * 100.0,
+                marker,
+                usage.resets.format("%Y-%m-%d %H:%M")
+            );
+        }
+    }
+}
+
+/// The export options for this run: the active `--profile`'s, with
+/// `--gain` on top; spoken word never carries ReplayGain
+fn export_options(matches: &ArgMatches) -> ExportOptions {
+    let mut options = ExportPolicy::default().options(active_profile(matches));
+
+    if let Some(gain) = matches.get_one::<GainMode>("gain") {
+        options.gain = *gain;
+    }
+    if !content_type(matches).replaygain() {
+        options.gain = GainMode::None;
+    }
+
+    options
+}
+
+/// Write the tracks under `targets`, album by album, to an M3U playlist
+pub fn export_playlist(matches: &ArgMatches, playlist: &str, targets: &[&String]) {
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+
+    let albums = read_albums(targets);
+    let tracks: Vec<&AlbumTrack> = albums.iter().flat_map(|album| album.tracks()).collect();
+    let options = export_options(matches);
+
+    match write_m3u(Path::new(playlist), &tracks, &options) {
+        Ok(with_gain) => {
+            println!("Wrote {} tracks to {}", tracks.len(), playlist);
+            if options.gain == GainMode::Tags && with_gain < tracks.len() {
+                println!("Note: {} tracks have no ReplayGain tags", tracks.len() - with_gain);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error: {}: {}", playlist, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn volumes_path() -> PathBuf {
+    data_dir().join("volumes.json")
+}
+
+/// The volumes added with --add-volume
+fn stored_volumes() -> VolumeSet {
+    VolumeSet::load(&volumes_path()).unwrap_or_else(|e| {
+        eprintln!("Error: {}: {}", volumes_path().display(), e);
+        process::exit(1);
+    })
+}
+
+/// Every root the library is split across: the `[[roots]]` of
+/// flacman.conf, then the volumes added with --add-volume that don't share
+/// a name with one of them
+fn volume_set() -> VolumeSet {
+    let mut volumes = VolumeSet::default();
+    for root in &config().roots {
+        volumes.add(Volume { name: root.name.clone(), root: root.path.clone(), rule: root.rule.clone() });
+    }
+    for volume in stored_volumes().volumes {
+        if !volumes.volumes.iter().any(|v| v.name == volume.name) {
+            volumes.volumes.push(volume);
+        }
+    }
+    volumes
+}
+
+/// Add a storage volume, or change the root or rule of an existing one
+///
+/// # Arguments
+/// * `rule` - A `--filter` style expression over album fields plus `age`
+///   in days; empty or `*` makes this the volume for everything else
+pub fn add_volume(name: &str, dir: &str, rule: &str) {
+    let rule = match rule.trim() {
+        "" | "*" => None,
+        rule => match rule.parse::<TrackFilter>() {
+            Ok(filter) => Some(filter),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        },
+    };
+
+    let root = std::path::absolute(dir).unwrap_or_else(|_| PathBuf::from(dir));
+    if !root.is_dir() {
+        eprintln!("Error: {} is not a directory", root.display());
+        process::exit(1);
+    }
+
+    if config().roots.iter().any(|r| r.name == name) {
+        eprintln!("Error: {} is a root in {}; change it there", name, config_path().display());
+        process::exit(1);
+    }
+    let mut stored = stored_volumes();
+    stored.add(Volume { name: name.to_owned(), root, rule });
+    if let Err(e) = stored.save(&volumes_path()) {
+        eprintln!("Error: {}: {}", volumes_path().display(), e);
+        process::exit(1);
+    }
+
+    let volumes = volume_set();
+
+    if !volumes.volumes.iter().any(|v| v.rule.is_none()) {
+        eprintln!("Warning: no volume takes albums that match no rule; add one with an empty RULE");
+    }
+    println!("{} volume(s) configured", volumes.volumes.len());
+}
+
+pub fn show_volumes() {
+    let volumes = volume_set();
+    if volumes.volumes.is_empty() {
+        println!("The library isn't split across volumes; add [[roots]] to flacman.conf or use --add-volume");
+        return;
+    }
+
+    for volume in &volumes.volumes {
+        let rule = volume.rule.as_ref().map_or_else(|| "everything else".to_owned(), ToString::to_string);
+        let albums = if volume.root.is_dir() {
+            let root = volume.root.display().to_string();
+            format!("{} albums", read_albums(&[&root]).len())
+        } else {
+            "missing".to_owned()
+        };
+        let free = flacman_core::free_space(&volume.root)
+            .map(|bytes| format!("{} free", format_size(bytes)))
+            .unwrap_or_else(|_| "free space unknown".to_owned());
+
+        println!("{}: {} ({}) [{}, {}]", volume.name, volume.root.display(), rule, albums, free);
+    }
+}
+
+fn cache_policy_path() -> PathBuf {
+    data_dir().join("cache-policy.json")
+}
+
+fn describe_cache_policy(policy: &EvictionPolicy) -> String {
+    let mut limits = Vec::new();
+    if let Some(bytes) = policy.max_bytes {
+        limits.push(format!("max-size={}", format_size(bytes)));
+    }
+    if let Some(days) = policy.max_age_days {
+        limits.push(format!("max-age={}d", days));
+    }
+    if let Some(keep) = policy.keep_per_album {
+        limits.push(format!("keep-per-album={}", keep));
+    }
+
+    if limits.is_empty() { "no limits".to_owned() } else { limits.join(", ") }
+}
+
+pub fn set_cache_policy(settings: &[&String]) {
+    let path = cache_policy_path();
+    let mut policy = EvictionPolicy::load(&path).unwrap_or_else(|e| {
+        eprintln!("Warning: replacing unreadable cache policy: {}", e);
+        EvictionPolicy::default()
+    });
+
+    for setting in settings {
+        if let Err(e) = policy.set(setting) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+    if let Err(e) = policy.save(&path) {
+        eprintln!("Error: Could not save cache policy: {}", e);
+        process::exit(1);
+    }
+
+    println!("Download cache: {}", describe_cache_policy(&policy));
+}
+
+/// Hold the download cache to its policy, reporting what was evicted
+fn evict_download_cache(verbose: bool) {
+    let policy = match EvictionPolicy::load(&cache_policy_path()) {
+        Ok(policy) if policy.is_empty() => return,
+        Ok(policy) => policy,
+        Err(e) => {
+            eprintln!("Warning: ignoring unreadable cache policy: {}", e);
+            return;
+        }
+    };
+
+    let report = match download_cache().evict(&policy, Local::now()) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Warning: could not evict from the download cache: {}", e);
+            return;
+        }
+    };
+    if report.evicted.is_empty() && report.partial == 0 {
+        return;
+    }
+
+    for (entry, reason) in &report.evicted {
+        if verbose {
+            let name = entry.origins.first().map_or(entry.sha256.as_str(), |o| o.name.as_str());
+            println!("Evicted {} ({}): {}", name, format_size(entry.size), reason);
+        }
+    }
+    println!(
+        "Evicted {} cached file(s), {} freed, and {} unfinished download(s)",
+        report.evicted.len(),
+        format_size(report.bytes),
+        report.partial
+    );
+
+    let mut record = TxRecord::new("evict-cache", Vec::new(), TxOutcome::Success);
+    record.files = report.evicted.len() as u64;
+    record.bytes = report.bytes;
+    log_transaction(record);
+}
+
+/// Summarize the download cache; `verbose` also lists unfinished downloads
+pub fn show_download_cache(verbose: bool) {
+    let cache = download_cache();
+    let (stats, partials) = match cache.stats().and_then(|stats| Ok((stats, cache.partials()?))) {
+        Ok(found) => found,
+        Err(e) => {
+            eprintln!("Error: Could not read the download cache: {}", e);
+            process::exit(1);
+        }
+    };
+
+    println!("Download cache: {}", cache_dir().join("downloads").display());
+    println!("  {} file(s), {}", stats.blobs, format_size(stats.bytes));
+    println!("  {} file(s) returned by more than one source", stats.shared);
+    println!("  {} unfinished download(s)", stats.partial);
+    match EvictionPolicy::load(&cache_policy_path()) {
+        Ok(policy) => println!("  Eviction: {}", describe_cache_policy(&policy)),
+        Err(e) => eprintln!("Warning: unreadable cache policy: {}", e),
+    }
+
+    if verbose {
+        for partial in partials {
+            println!("  {}:{} ({} so far)", partial.source, partial.remote_id, format_size(partial.bytes));
+        }
+    }
+}
+
+/// The quality ladder for this run: `--quality`, else the one of the
+/// active `--profile`, else the policy's default
+fn quality_ladder(matches: &ArgMatches) -> QualityLadder {
+    if let Some(ladder) = matches.get_one::<QualityLadder>("quality") {
+        return ladder.clone();
+    }
+
+    QualityPolicy::default().ladder(active_profile(matches)).clone()
+}
+
+/// The `--profile` in effect, if it's known to the quality or content policy
+fn active_profile(matches: &ArgMatches) -> Option<&str> {
+    let profile = matches.get_one::<String>("profile")?;
+    let (quality, content) = (QualityPolicy::default(), ContentPolicy::default());
+
+    if !quality.profiles.contains_key(profile) && !content.profiles.contains_key(profile) {
+        let mut known: Vec<&String> = quality.profiles.keys().chain(content.profiles.keys()).collect();
+        known.sort();
+        known.dedup();
+        eprintln!("Error: Unknown profile: {} (known: {:?})", profile, known);
+        process::exit(1);
+    }
+
+    Some(profile)
+}
+
+/// What the library of the active `--profile` holds
+fn content_type(matches: &ArgMatches) -> ContentType {
+    ContentPolicy::default().content_type(active_profile(matches))
+}
+
+/// Page the rest of stdout, like git does, if `enabled` and stdout is a terminal
+fn page_output(enabled: bool) {
+    if !enabled || !std::io::stdout().is_terminal() {
+        return;
+    }
+
+    if let Some(command) = pager_command()
+        && let Err(e) = start_pager(&command)
+    {
+        eprintln!("Warning: {}", e);
+    }
+}
+
+/// Send the completion notifications enabled for this run
+fn notify_finished(matches: &ArgMatches, summary: &Summary) {
+    let config = NotifyConfig {
+        default: NotifySettings { desktop: matches.get_flag("notify"), ..Default::default() },
+        ..Default::default()
+    };
+
+    for e in config.notify(summary) {
+        eprintln!("Warning: {}", e);
+    }
+}
+
+pub fn handle_query(matches: &ArgMatches, targets: &[&String], verbose: bool) {
+    let list = matches.get_flag("list");
+    let search = matches.get_flag("search");
+    let info = matches.get_flag("info");
+    let duplicates = matches.get_flag("duplicates");
+    let json = matches.get_flag("json");
+
+    page_output(!json && !matches.get_flag("no-pager"));
+
+    if verbose {
+        println!("Operation: Query (Local Library)");
+    }
+
+    let content = content_type(matches);
+
+    // Without targets, a library split across volumes is queried as a whole
+    let library: Vec<&String> = if targets.is_empty() { library_roots().iter().collect() } else { targets.to_vec() };
+
+    let format = matches.get_one::<Template>("format-string");
+    if matches.get_flag("now-playing") {
+        show_now_playing(json);
+    } else if matches.get_flag("orphans") {
+        list_orphans(&library, matches.get_flag("adopt"), matches.get_flag("dry-run"), json);
+    } else if matches.get_flag("missing") {
+        list_missing(targets, matches.get_flag("prune"), matches.get_flag("dry-run"), json);
+    } else if let Some(name) = matches.get_one::<String>("delete-playlist") {
+        delete_playlist(name);
+    } else if matches.get_flag("playlists") {
+        list_playlists(json);
+    } else if ["playlist", "save-playlist", "load-playlist"].iter().any(|id| matches.contains_id(id)) {
+        query_playlist(matches, targets, format, verbose, json);
+    } else if let Some(filter) = matches.get_one::<TrackFilter>("filter") {
+        let resolved = resolve_targets(&library);
+        list_matching(&resolved.iter().collect::<Vec<_>>(), filter, format, verbose);
+    } else if duplicates {
+        let resolved = resolve_targets(&library);
+        let fingerprint = matches.get_flag("fingerprint");
+        if fingerprint && !content.in_music_stats() {
+            println!("Note: recordings aren't compared by fingerprint in {:?} libraries", content);
+        }
+        let options = DuplicateOptions {
+            fingerprint: fingerprint && content.in_music_stats(),
+            by_tags: matches.get_flag("by-tags"),
+            ..Default::default()
+        };
+        report_duplicates(&resolved.iter().collect::<Vec<_>>(), &options, verbose);
+    } else if list && !library.is_empty() {
+        let resolved = resolve_targets(&library);
+        list_albums(&resolved.iter().collect::<Vec<_>>(), content, format, verbose, json);
+    } else if list {
+        list_indexed_albums(None, format, json);
+    } else if search {
+        if targets.is_empty() {
+            eprintln!("Error: No search term specified");
+            process::exit(1);
+        }
+        let term: Vec<&str> = targets.iter().map(|t| t.as_str()).collect();
+        search_library(&term.join(" "), format, verbose, json);
+    } else if info && matches.get_flag("provenance") {
+        let resolved = resolve_targets(targets);
+        show_provenance(&resolved.iter().collect::<Vec<_>>());
+    } else if info && matches.get_flag("edit-notes") {
+        edit_notes(targets, matches.get_flag("mirror-notes"));
+    } else if info {
+        if targets.is_empty() {
+            eprintln!("Error: No target specified");
+            process::exit(1);
+        }
+        show_track_info(targets, json);
+        if !json {
+            show_notes(targets);
+        }
+    } else if !targets.is_empty() {
+        let term: Vec<&str> = targets.iter().map(|t| t.as_str()).collect();
+        list_indexed_albums(Some(&term.join(" ")), format, json);
+    } else {
+        list_indexed_albums(None, format, json);
+    }
+}
+
+/// List the tracks under `targets` that match a smart-collection filter,
+/// or render each with `format`
+fn list_matching(targets: &[&String], filter: &TrackFilter, format: Option<&Template>, verbose: bool) {
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+
+    let mut count = 0;
+    for album in &read_albums(targets) {
+        for track in album.tracks().filter(|t| filter.matches(&t.metadata)) {
+            count += 1;
+            if let Some(format) = format {
+                println!("{}", format.render(track));
+                continue;
+            }
+            let metadata = &track.metadata;
+            let mut line = format!("{} - {} ({})", album.artist, metadata.track_name.as_str(), album.title);
+            if let Some(stars) = metadata.rating {
+                line.push_str(&format!(" {}{}", "*".repeat(stars.into()), "-".repeat(5 - usize::from(stars))));
+            }
+            if let Some(plays) = metadata.play_count {
+                line.push_str(&format!(" [{} plays]", plays));
+            }
+            println!("{}", line);
+            if verbose {
+                println!("    {}", track.path.display());
+            }
+        }
+    }
+
+    if format.is_none() {
+        println!("{} tracks match {}", count, filter);
+    }
+}
+
+/// Read the tags of every audio file under `targets` and group them into
+/// albums, sorted by artist and title
+fn read_albums(targets: &[&String]) -> Vec<Album> {
+    let mut tracks = Vec::new();
+
+    for target in targets {
+        let files = match flacman_fs::find_audio_files_par(target.as_str()) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("Error: {}: {}", target, e);
+                process::exit(1);
+            }
+        };
+
+        for path in files {
+            let mut file = MediaFile::new(&path);
+            match file.read() {
+                Ok(metadata) => {
+                    let metadata = metadata.clone();
+                    tracks.push(AlbumTrack { path, metadata });
+                }
+                Err(e) => eprintln!("Warning: skipped {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    let mut albums = group_albums(tracks);
+    let collation = collation();
+    albums.sort_by(|a, b| {
+        collation.compare(&a.artist, &b.artist).then_with(|| collation.compare(&a.title, &b.title))
+    });
+
+    albums
+}
+
+/// List the albums under `targets`, with multi-disc releases as one album,
+/// and warn about gaps in disc/track numbering
+///
+/// With `format`, each album is only rendered with it, whatever the content type.
+/// An album as `-Q -l --json` prints it
+#[derive(serde::Serialize)]
+struct AlbumJson<'a> {
+    artist: &'a str,
+    title: &'a str,
+    year: Option<u32>,
+    discs: u32,
+    tracks: Vec<TrackJson<'a>>,
+    /// Numbering problems, as the text listing warns about them
+    warnings: Vec<String>,
+}
+
+/// A track as `-Q -l --json` prints it
+#[derive(serde::Serialize)]
+struct TrackJson<'a> {
+    path: &'a Path,
+    disc: Option<u32>,
+    track: Option<u32>,
+    title: &'a str,
+    artist: &'a str,
+    genre: Option<&'a str>,
+    year: Option<u32>,
+    /// In seconds
+    duration: f64,
+}
+
+impl<'a> AlbumJson<'a> {
+    fn new(album: &'a Album, warnings: Vec<String>) -> Self {
+        let tracks: Vec<TrackJson> = album
+            .tracks()
+            .map(|t| TrackJson {
+                path: &t.path,
+                disc: t.metadata.disc_number,
+                track: t.metadata.track_number,
+                title: t.metadata.track_name.as_str(),
+                artist: t.metadata.author.as_str(),
+                genre: t.metadata.genre.as_ref().map(|g| g.as_str()),
+                year: t.metadata.year,
+                duration: t.metadata.properties.duration.as_secs_f64(),
+            })
+            .collect();
+        AlbumJson {
+            artist: &album.artist,
+            title: &album.title,
+            year: tracks.iter().find_map(|t| t.year),
+            discs: album.disc_total(),
+            tracks,
+            warnings,
+        }
+    }
+}
+
+/// Numbering problems of `album`, leaving out tracks its partial sync
+/// skipped on purpose
+fn numbering_warnings(album: &Album, db: Option<&LibraryDb>) -> Vec<String> {
+    let partial = db.and_then(|db| db.partial_tracks(&album.artist, &album.title).ok().flatten());
+    album
+        .check_numbering()
+        .into_iter()
+        .filter(|issue| match (&partial, issue) {
+            (Some(selection), NumberingIssue::MissingTrack { track, .. }) => {
+                !selection.0.is_empty() && selection.contains(*track)
+            }
+            _ => true,
+        })
+        .map(|issue| issue.to_string())
+        .collect()
+}
+
+pub fn list_albums(targets: &[&String], content: ContentType, format: Option<&Template>, verbose: bool, json: bool) {
+    if json {
+        let db = LibraryDb::open(data_dir().join("library.db")).ok();
+        let albums = read_albums(targets);
+        let albums: Vec<AlbumJson> =
+            albums.iter().map(|album| AlbumJson::new(album, numbering_warnings(album, db.as_ref()))).collect();
+        print_json(&albums);
+        return;
+    }
+    if let Some(format) = format {
+        for album in &read_albums(targets) {
+            println!("{}", format.render(album));
+        }
+        return;
+    }
+    if content != ContentType::Music {
+        list_spoken_word(targets, content, verbose);
+        return;
+    }
+
+    // Tracks left out of albums synced partially on purpose aren't missing
+    let db = LibraryDb::open(data_dir().join("library.db")).ok();
+    for album in &read_albums(targets) {
+        if album.is_multidisc() {
+            println!(
+                "{} - {} [{} discs, {} tracks]",
+                album.artist,
+                album.title,
+                album.disc_total(),
+                album.track_count()
+            );
+        } else {
+            println!("{} - {} [{} tracks]", album.artist, album.title, album.track_count());
+        }
+
+        if verbose && album.is_multidisc() {
+            for (disc, tracks) in &album.discs {
+                match album.disc_subtitle(*disc) {
+                    Some(subtitle) => println!("    Disc {}: {} ({} tracks)", disc, subtitle, tracks.len()),
+                    None => println!("    Disc {} ({} tracks)", disc, tracks.len()),
+                }
+            }
+        }
+
+        for warning in numbering_warnings(album, db.as_ref()) {
+            println!("    warning: {}", warning);
+        }
+    }
+}
+
+/// List audiobooks (with narrator and chapter counts) or podcast shows
+fn list_spoken_word(targets: &[&String], content: ContentType, verbose: bool) {
+    for album in &read_albums(targets) {
+        let chapters: Vec<(&Path, Vec<Chapter>)> = album
+            .tracks()
+            .map(|t| (t.path.as_path(), read_chapters(&t.path).unwrap_or_default()))
+            .collect();
+        let chapter_count: usize = chapters.iter().map(|(_, c)| c.len()).sum();
+
+        if content == ContentType::Podcast {
+            println!("{} [{} episodes]", album.title, album.track_count());
+            continue;
+        }
+
+        let narrator = album.tracks().find_map(|t| t.metadata.narrator.as_ref());
+        match narrator {
+            Some(narrator) => print!("{} - {} (read by {})", album.artist, album.title, narrator.as_str()),
+            None => print!("{} - {}", album.artist, album.title),
+        }
+        if chapter_count > 0 {
+            println!(" [{} files, {} chapters]", album.track_count(), chapter_count);
+        } else {
+            println!(" [{} files]", album.track_count());
+        }
+
+        if verbose {
+            for (path, chapters) in &chapters {
+                for chapter in chapters {
+                    let secs = chapter.start.as_secs();
+                    println!(
+                        "    {:>2}:{:02}:{:02}  {}  ({})",
+                        secs / 3600,
+                        secs / 60 % 60,
+                        secs % 60,
+                        chapter.title,
+                        path.file_name().unwrap_or_default().to_string_lossy()
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Print a write preview: the tag diff and the change in file size
+fn print_preview(preview: &WritePreview) {
+    if preview.is_unchanged() {
+        println!("{}: no change", preview.path.display());
+        return;
+    }
+
+    print!("{}", preview.diff);
+    let delta = preview.size_after as i64 - preview.size_before as i64;
+    println!(
+        "size: {} -> {} ({:+} bytes)\n",
+        format_size(preview.size_before),
+        format_size(preview.size_after),
+        delta
+    );
+}
+
+/// Show what `--normalize-numbers` would write, without touching any file
+fn preview_numbering(targets: &[&String]) {
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+
+    let mut previewed = 0;
+    for album in read_albums(targets) {
+        let fixes = match plan_numbering(&album) {
+            Ok(fixes) => fixes,
+            Err(e) => {
+                eprintln!("Warning: skipped {} - {}: {}", album.artist, album.title, e);
+                continue;
+            }
+        };
+
+        for fix in fixes {
+            match fix.preview() {
+                Ok(Some(preview)) => {
+                    print_preview(&preview);
+                    previewed += 1;
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("Error: {}: {}", fix.path.display(), e),
+            }
+            if let Some(dest) = &fix.rename {
+                println!("{}: would rename to {}", fix.path.display(), dest.display());
+            }
+        }
+    }
+
+    println!("Previewed {} tag write(s); nothing was changed", previewed);
+}
+
+/// Normalize track and disc numbering under `targets`: canonical tag
+/// forms, missing totals filled in from the album, and zero-padded
+/// track numbers in file names
+pub fn normalize_numbers(targets: &[&String], verbose: bool, confirm: Confirm) {
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+
+    let mut fixes = Vec::new();
+    for album in read_albums(targets) {
+        match plan_numbering(&album) {
+            Ok(album_fixes) => fixes.extend(album_fixes),
+            Err(e) => eprintln!("Warning: skipped {} - {}: {}", album.artist, album.title, e),
+        }
+    }
+
+    if fixes.is_empty() {
+        println!("Numbering is already normalized");
+        return;
+    }
+
+    for fix in &fixes {
+        let mut changes = Vec::new();
+        if let Some(numbers) = &fix.retag {
+            match (numbers.disc, numbers.disc_total) {
+                (Some(disc), Some(disc_total)) => changes.push(format!(
+                    "track {}/{}, disc {}/{}",
+                    numbers.track, numbers.track_total, disc, disc_total
+                )),
+                _ => changes.push(format!("track {}/{}", numbers.track, numbers.track_total)),
+            }
+        }
+        if let Some(dest) = &fix.rename {
+            changes.push(format!("rename to {}", dest.file_name().unwrap_or_default().to_string_lossy()));
+        }
+        println!("{}: {}", fix.path.display(), changes.join(", "));
+    }
+
+    confirm_or_exit(confirm, &format!("Normalize {} file(s)?", fixes.len()));
+
+    let targets = targets.iter().map(|t| t.to_string()).collect();
+    let mut record = TxRecord::new("normalize-numbers", targets, TxOutcome::Success);
+    let cancel = cancel_flag();
+    let mut failed = 0;
+    for fix in &fixes {
+        if cancel.load(Ordering::Relaxed) {
+            let committed = record.files as usize;
+            record.outcome = TxOutcome::Cancelled;
+            log_transaction(record);
+            exit_cancelled(committed, fixes.len(), "run --normalize-numbers again to finish");
+        }
+        match fix.apply() {
+            Ok(path) => {
+                record.files += 1;
+                if verbose {
+                    println!("Normalized {}", path.display());
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("Error: {}: {}", fix.path.display(), e);
+            }
+        }
+    }
+
+    if failed == fixes.len() {
+        record.outcome = TxOutcome::Failed;
+    } else if failed > 0 {
+        record.outcome = TxOutcome::Partial;
+    }
+    log_transaction(record);
+
+    println!("Normalized {} of {} file(s)", fixes.len() - failed, fixes.len());
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// Every audio file under `targets`, recording how long each scan took
+fn scan_audio_files(targets: &[&String]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for target in targets {
+        let started = Instant::now();
+        match flacman_fs::find_audio_files_par(target.as_str()) {
+            Ok(found) => {
+                record_metric(Metric::Scan {
+                    root: target.to_string(),
+                    files: found.len() as u64,
+                    secs: started.elapsed().as_secs_f64(),
+                });
+                files.extend(found);
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {}", target, e);
+                process::exit(1);
+            }
+        }
+    }
+    files
+}
+
+fn wantlist_path() -> PathBuf {
+    data_dir().join("wantlist.json")
+}
+
+fn read_wantlist() -> Vec<CollectionRelease> {
+    match std::fs::read_to_string(wantlist_path()) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            eprintln!("Warning: ignoring unreadable wantlist: {}", e);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn write_wantlist(wantlist: &[CollectionRelease]) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(wantlist).map_err(std::io::Error::other)?;
+    std::fs::create_dir_all(data_dir())?;
+    std::fs::write(wantlist_path(), json)
+}
+
+fn subscriptions_path() -> PathBuf {
+    data_dir().join("subscriptions.json")
+}
+
+fn read_subscriptions() -> Vec<Subscription> {
+    match std::fs::read_to_string(subscriptions_path()) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            eprintln!("Warning: ignoring unreadable subscriptions: {}", e);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn write_subscriptions(subscriptions: &[Subscription]) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(subscriptions).map_err(std::io::Error::other)?;
+    std::fs::create_dir_all(data_dir())?;
+    std::fs::write(subscriptions_path(), json)
+}
+
+pub fn subscribe(artist: &str) {
+    let mut watchlist = Watchlist::new(read_subscriptions(), Vec::new());
+    let added = watchlist.merge(Watchlist::new(vec![Subscription { artist: artist.to_owned(), mbid: None }], Vec::new()));
+    if added.subscriptions == 0 {
+        println!("Already subscribed to {}", artist);
+        return;
+    }
+
+    if let Err(e) = write_subscriptions(&watchlist.subscriptions) {
+        eprintln!("Error: Could not save subscriptions: {}", e);
+        process::exit(1);
+    }
+    println!("Subscribed to {} ({} subscription(s))", artist, watchlist.subscriptions.len());
+}
+
+/// Write the subscriptions and wantlist to a portable file
+pub fn export_watchlist(file: &Path) {
+    let watchlist = Watchlist::new(read_subscriptions(), read_wantlist());
+    match watchlist.save(file) {
+        Ok(path) => println!(
+            "Exported {} subscription(s) and {} wanted release(s) to {}",
+            watchlist.subscriptions.len(),
+            watchlist.wantlist.len(),
+            path.display()
+        ),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Merge an exported watchlist into the local one; nothing already
+/// watched is removed or duplicated
+pub fn import_watchlist(file: &Path) {
+    let imported = match Watchlist::load(file) {
+        Ok(imported) => imported,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut watchlist = Watchlist::new(read_subscriptions(), read_wantlist());
+    let added = watchlist.merge(imported);
+
+    let saved = write_subscriptions(&watchlist.subscriptions).and_then(|()| write_wantlist(&watchlist.wantlist));
+    if let Err(e) = saved {
+        eprintln!("Error: Could not save the watchlist: {}", e);
+        process::exit(1);
+    }
+
+    let mut record = TxRecord::new("import-watchlist", vec![file.display().to_string()], TxOutcome::Success);
+    record.files = (added.subscriptions + added.wanted) as u64;
+    log_transaction(record);
+
+    println!(
+        "Imported {} new subscription(s) and {} wanted release(s) ({} and {} in total)",
+        added.subscriptions,
+        added.wanted,
+        watchlist.subscriptions.len(),
+        watchlist.wantlist.len()
+    );
+}
+
+/// Two-way sync with a MusicBrainz collection: add the releases owned
+/// under `targets` to it, and save the ones not owned as the wantlist
+/// for `-S --needed`
+pub fn sync_mb_collection(collection: &str, targets: &[&String], verbose: bool) {
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+
+    let local = local_release_ids(&scan_audio_files(targets));
+    if verbose {
+        println!("{} releases tagged with a MusicBrainz ID", local.len());
+    }
+
+    let collection = MbCollection {
+        id: collection.to_owned(),
+        token: std::env::var("FLACMAN_MB_TOKEN").ok().filter(|t| !t.is_empty()),
+    };
+
+    let remote = match collection.releases(musicbrainz()) {
+        Ok(remote) => remote,
+        Err(e) => {
+            eprintln!("Error: cannot read collection {}: {}", collection.id, e);
+            process::exit(1);
+        }
+    };
+
+    let sync = CollectionSync::new(&local, &remote);
+    let mut record = TxRecord::new("mb-sync", vec![collection.id.clone()], TxOutcome::Success);
+
+    if sync.to_add.is_empty() {
+        println!("Collection already has every local release");
+    } else if collection.token.is_none() {
+        println!(
+            "{} local release(s) missing from the collection; set FLACMAN_MB_TOKEN to add them",
+            sync.to_add.len()
+        );
+        record.outcome = TxOutcome::Partial;
+    } else {
+        match collection.add(musicbrainz(), &sync.to_add) {
+            Ok(()) => {
+                println!("Added {} release(s) to the collection", sync.to_add.len());
+                record.files = sync.to_add.len() as u64;
+            }
+            Err(e) => {
+                eprintln!("Error: cannot add releases to collection {}: {}", collection.id, e);
+                record.outcome = TxOutcome::Failed;
+            }
+        }
+    }
+
+    if verbose {
+        for release in &sync.wanted {
+            println!("Wanted: {} - {}", release.artist, release.title);
+        }
+    }
+
+    match write_wantlist(&sync.wanted) {
+        Ok(()) => println!(
+            "{} release(s) on the wantlist; download them with: flacman -S --needed",
+            sync.wanted.len()
+        ),
+        Err(e) => eprintln!("Warning: could not save wantlist: {}", e),
+    }
+
+    let failed = record.outcome == TxOutcome::Failed;
+    log_transaction(record);
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Replace byte-identical audio files under `targets` with hardlinks to
+/// one copy; without `hardlink`, only show what would be linked
+pub fn dedup_library(targets: &[&String], hardlink: bool, verbose: bool, confirm: Confirm) {
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+
+    let files = scan_audio_files(targets);
+    if verbose {
+        println!("Scanning {} audio files for identical copies...", files.len());
+    }
+
+    let report = match find_duplicates(&files, &DuplicateOptions::default()) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    for (path, reason) in &report.skipped {
+        eprintln!("Warning: skipped {}: {}", path.display(), reason);
+    }
+
+    // Pairs of (kept file, duplicate) that aren't linked yet
+    let mut links: Vec<(&Path, &Path, u64)> = Vec::new();
+    for group in report.groups.iter().filter(|g| g.kind == DuplicateKind::Identical) {
+        // Identical files rank the same, so keep the first path for stable results
+        let mut paths: Vec<(&Path, u64)> = group.files.iter().map(|(p, q)| (p.as_path(), q.size)).collect();
+        paths.sort();
+
+        let keep = paths[0].0;
+        for (duplicate, size) in &paths[1..] {
+            if !flacman_fs::same_file(keep, duplicate).unwrap_or(false) {
+                links.push((keep, duplicate, *size));
+            }
+        }
+    }
+
+    if links.is_empty() {
+        println!("No unlinked identical files found");
+        return;
+    }
+
+    links.sort_by(|a, b| a.1.cmp(b.1));
+    for (keep, duplicate, _) in &links {
+        println!("{} -> {}", duplicate.display(), keep.display());
+    }
+    let reclaimable: u64 = links.iter().map(|(_, _, size)| size).sum();
+
+    if !hardlink {
+        println!(
+            "{} file(s) could be hardlinked, {} reclaimable; run with --hardlink to link them",
+            links.len(),
+            format_size(reclaimable)
+        );
+        return;
+    }
+
+    confirm_or_exit(confirm, &format!("Replace {} file(s) with hardlinks?", links.len()));
+
+    let mut record = TxRecord::new("dedup", Vec::new(), TxOutcome::Success);
+    let cancel = cancel_flag();
+    let mut failed = 0;
+    for (keep, duplicate, _) in &links {
+        if cancel.load(Ordering::Relaxed) {
+            let committed = record.files as usize;
+            record.outcome = TxOutcome::Cancelled;
+            log_transaction(record);
+            exit_cancelled(committed, links.len(), "run --dedup --hardlink again to link the rest");
+        }
+        match flacman_fs::replace_with_hardlink(keep, duplicate) {
+            Ok(reclaimed) => {
+                record.files += 1;
+                record.bytes += reclaimed;
+                record.targets.push(format!("{} -> {}", duplicate.display(), keep.display()));
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("Error: {}: {}", duplicate.display(), e);
+            }
+        }
+    }
+
+    if failed == links.len() {
+        record.outcome = TxOutcome::Failed;
+    } else if failed > 0 {
+        record.outcome = TxOutcome::Partial;
+    }
+    let (linked, reclaimed) = (record.files, record.bytes);
+    log_transaction(record);
+
+    println!("Hardlinked {} file(s), reclaimed {}", linked, format_size(reclaimed));
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// Report albums whose tracks all embed the same cover and, when the
+/// active profile stores art shared, keep each such cover once
+pub fn dedup_album_art(matches: &ArgMatches, targets: &[&String], confirm: Confirm) {
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+    let verbose = matches.get_count("verbose") > 0;
+    let storage = config().art.storage(active_profile(matches));
+
+    let mut shared = Vec::new();
+    for album in read_albums(targets) {
+        let Some(dir) = flacman_tag::album_dir(&album) else { continue };
+        let art = match scan_album_art(&dir) {
+            Ok(art) => art,
+            Err(e) => {
+                eprintln!("Warning: skipped {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+
+        // Covers that already fit the thumbnail were shared by an earlier run
+        if let (ArtStorage::Shared { thumbnail: max @ 1.. }, Some(cover)) = (storage, &art.shared)
+            && matches!(thumbnail(cover, max), Ok(None))
+        {
+            if verbose {
+                println!("{} - {}: already shared", album.artist, album.title);
+            }
+            continue;
+        }
+
+        if art.reclaimable() > 0 {
+            let size = art.shared.as_ref().map_or(0, |p| p.data().len() as u64);
+            println!(
+                "{} - {}: {} tracks embed the same {} cover, {} reclaimable",
+                album.artist,
+                album.title,
+                art.tracks.len(),
+                format_size(size),
+                format_size(art.reclaimable())
+            );
+            shared.push(art);
+        } else if verbose && art.distinct > 1 {
+            println!("{} - {}: {} different covers, left alone", album.artist, album.title, art.distinct);
+        }
+    }
+
+    if shared.is_empty() {
+        println!("No album repeats one cover in every track");
+        return;
+    }
+    let reclaimable: u64 = shared.iter().map(|a| a.reclaimable()).sum();
+
+    let ArtStorage::Shared { thumbnail: max } = storage else {
+        println!(
+            "{} album(s), {} reclaimable; set [art] mode = \"shared\" in flacman.conf to keep each cover once",
+            shared.len(),
+            format_size(reclaimable)
+        );
+        return;
+    };
+
+    let preview = matches.get_flag("preview-writes");
+    if !preview {
+        let embedded = if max == 0 { "no art".to_owned() } else { format!("a {}px thumbnail", max) };
+        confirm_or_exit(
+            confirm,
+            &format!("Move the cover of {} album(s) to folder images, embedding {}?", shared.len(), embedded),
+        );
+    }
+
+    let mut record = TxRecord::new("dedup-art", Vec::new(), TxOutcome::Success);
+    let cancel = cancel_flag();
+    let (mut done, mut failed) = (0, 0);
+    for art in &shared {
+        if cancel.load(Ordering::Relaxed) {
+            record.outcome = TxOutcome::Cancelled;
+            log_transaction(record);
+            exit_cancelled(done, shared.len(), "run --dedup-art again to finish");
+        }
+
+        match share_album_art(art, max, preview) {
+            Ok(report) if preview => report.previews.iter().for_each(print_preview),
+            Ok(report) => {
+                if verbose && let Some(folder_image) = &report.folder_image {
+                    let tracks = report.rewritten.len();
+                    println!("{}: {} tracks now share {}", art.album_dir.display(), tracks, folder_image.display());
+                }
+                record.files += report.rewritten.len() as u64;
+                record.bytes += report.bytes_before.saturating_sub(report.bytes_after);
+                record.targets.push(art.album_dir.display().to_string());
+                done += 1;
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {}", art.album_dir.display(), e);
+                record.messages.push(format!("{}: {}", art.album_dir.display(), e));
+                failed += 1;
+            }
+        }
+    }
+
+    if preview {
+        return;
+    }
+    let reclaimed = record.bytes;
+    if failed == shared.len() {
+        record.outcome = TxOutcome::Failed;
+    } else if failed > 0 {
+        record.outcome = TxOutcome::Partial;
+    }
+    log_transaction(record);
+
+    println!("Shared the cover of {} album(s), {} reclaimed", done, format_size(reclaimed));
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// Extract, embed and resize the cover art of the albums under `targets`,
+/// as asked for by `--extract-art`, `--embed-art` and `--resize-art`
+///
+/// Each album has its embedded cover written out first, then the folder
+/// image embedded where tracks lack one, then oversized art scaled down,
+/// so the three can run together on a library in any state.
+pub fn manage_album_art(matches: &ArgMatches, targets: &[&String], confirm: Confirm) {
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+    let verbose = matches.get_count("verbose") > 0;
+    let preview = matches.get_flag("preview-writes");
+    let (extract, embed, resize) =
+        (matches.get_flag("extract-art"), matches.get_flag("embed-art"), matches.get_flag("resize-art"));
+    let max = matches.get_one::<u32>("art-max-size").copied().unwrap_or(config().art.max_size);
+    if resize && max == 0 {
+        eprintln!("Error: --resize-art needs a size: pass --art-max-size or set max_size under [art]");
+        process::exit(1);
+    }
+
+    let dirs: Vec<PathBuf> = read_albums(targets).iter().filter_map(flacman_tag::album_dir).collect();
+    if dirs.is_empty() {
+        println!("No albums found");
+        return;
+    }
+    if !preview {
+        let steps: Vec<&str> = [(extract, "extract"), (embed, "embed"), (resize, "resize")]
+            .into_iter()
+            .filter_map(|(asked, step)| asked.then_some(step))
+            .collect();
+        confirm_or_exit(confirm, &format!("{} the cover art of {} album(s)?", steps.join(", "), dirs.len()));
+    }
+
+    let mut record = TxRecord::new("art", Vec::new(), TxOutcome::Success);
+    let cancel = cancel_flag();
+    let mut failed = 0;
+    for (done, dir) in dirs.iter().enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            record.outcome = TxOutcome::Cancelled;
+            log_transaction(record);
+            exit_cancelled(done, dirs.len(), "run the same command again to finish");
+        }
+
+        let result = album_art_steps(dir, (extract, embed, resize), max, preview, verbose);
+        match result {
+            Ok((written, rewritten)) => {
+                record.files += (written.len() + rewritten.len()) as u64;
+                if !written.is_empty() || !rewritten.is_empty() {
+                    record.targets.push(dir.display().to_string());
+                }
+                record.changes.extend(written.into_iter().map(|path| FileChange::Created { path }));
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {}", dir.display(), e);
+                record.messages.push(format!("{}: {}", dir.display(), e));
+                failed += 1;
+            }
+        }
+    }
+
+    if preview {
+        return;
+    }
+    println!("Updated the art of {} album(s)", record.targets.len());
+    if failed == dirs.len() {
+        record.outcome = TxOutcome::Failed;
+    } else if failed > 0 {
+        record.outcome = TxOutcome::Partial;
+    }
+    log_transaction(record);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// The art steps asked for, `(extract, embed, resize)`, on one album
+///
+/// # Returns
+/// The folder images written and the tracks rewritten
+fn album_art_steps(
+    dir: &Path,
+    (extract, embed, resize): (bool, bool, bool),
+    max: u32,
+    preview: bool,
+    verbose: bool,
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>), flacman_tag::TagError> {
+    let (mut written, mut rewritten) = (Vec::new(), Vec::new());
+
+    if extract && flacman_tag::folder_image(dir).is_none() {
+        match extract_cover(dir, preview)? {
+            Some(cover) if preview => println!("Would write {}", cover.display()),
+            Some(cover) => {
+                println!("Wrote {}", cover.display());
+                written.push(cover);
+            }
+            None if verbose => println!("{}: no embedded cover to extract", dir.display()),
+            None => {}
+        }
+    }
+
+    // An image extracted in a preview isn't there to embed
+    if embed && flacman_tag::folder_image(dir).is_some() {
+        let report = embed_folder_art(dir, max, preview)?;
+        report.previews.iter().for_each(print_preview);
+        if !report.rewritten.is_empty() {
+            println!("{}: embedded the folder image into {} track(s)", dir.display(), report.rewritten.len());
+        }
+        rewritten.extend(report.rewritten);
+    } else if embed && verbose && !preview {
+        println!("{}: no folder image to embed", dir.display());
+    }
+
+    if resize {
+        let report = resize_album_art(dir, max, preview)?;
+        report.previews.iter().for_each(print_preview);
+        if !report.rewritten.is_empty() {
+            println!("{}: resized the art of {} track(s)", dir.display(), report.rewritten.len());
+        }
+        rewritten.extend(report.rewritten);
+    }
+
+    rewritten.sort();
+    rewritten.dedup();
+    Ok((written, rewritten))
+}
+
+/// An album `--backfill` can fill in: its release ID and the tracks
+/// missing genre, year or label
+struct BackfillAlbum {
+    name: String,
+    release_id: String,
+    tracks: Vec<(PathBuf, ReleaseFacts)>,
+}
+
+/// Fill in the genre, year and label of albums under `targets` that are
+/// tagged with a MusicBrainz release ID but not with those
+///
+/// Releases are fetched at MusicBrainz's rate limit. Tags already there
+/// are never replaced. Every `batch` albums the library database is
+/// updated and the history gets an entry, so an interrupted run keeps
+/// what it wrote. With `preview`, the tag changes are shown as diffs
+/// instead.
+pub fn backfill_tags(targets: &[&String], batch: usize, preview: bool, verbose: bool, confirm: Confirm) {
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+
+    let mut albums = Vec::new();
+    for album in read_albums(targets) {
+        let release_id = album.tracks().find_map(|t| release_ids(&t.path).ok().and_then(|(release, _)| release));
+        let Some(release_id) = release_id else { continue };
+
+        let tracks: Vec<(PathBuf, ReleaseFacts)> = album
+            .tracks()
+            .filter_map(|t| match read_release_facts(&t.path) {
+                Ok(facts) => Some((t.path.clone(), facts)),
+                Err(e) => {
+                    eprintln!("Warning: skipped {}: {}", t.path.display(), e);
+                    None
+                }
+            })
+            .filter(|(_, facts)| facts.genre.is_none() || facts.year.is_none() || facts.label.is_none())
+            .collect();
+        if !tracks.is_empty() {
+            albums.push(BackfillAlbum { name: format!("{} - {}", album.artist, album.title), release_id, tracks });
+        }
+    }
+
+    if albums.is_empty() {
+        println!("No album with a MusicBrainz release ID is missing genre, year or label");
+        return;
+    }
+    println!("{} album(s) with a MusicBrainz release ID are missing genre, year or label", albums.len());
+    if !preview {
+        confirm_or_exit(confirm, "Fetch them from MusicBrainz and fill in the tags?");
+    }
+
+    let cancel = cancel_flag();
+    let show_progress = std::io::stderr().is_terminal();
+    let new_record = || TxRecord::new("backfill", Vec::new(), TxOutcome::Success);
+    let (mut record, mut written) = (new_record(), Vec::new());
+    let (mut done, mut tracks_done, mut failed) = (0, 0, 0);
+
+    let commit = |record: TxRecord, written: &mut Vec<PathBuf>| {
+        if !written.is_empty() {
+            index_paths(written);
+        }
+        if !written.is_empty() || !record.messages.is_empty() {
+            log_transaction(record);
+        }
+        written.clear();
+    };
+
+    for (i, album) in albums.iter().enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            if show_progress {
+                eprint!("\r\x1b[K");
+            }
+            record.outcome = TxOutcome::Cancelled;
+            commit(record, &mut written);
+            exit_cancelled(done, albums.len(), "run --backfill again to finish");
+        }
+        if show_progress {
+            eprint!("\r\x1b[K[{}/{}] {}", i + 1, albums.len(), album.name);
+        }
+
+        let canonical = match fetch_release_facts(musicbrainz(), &album.release_id) {
+            Ok(canonical) => canonical,
+            Err(e) => {
+                if show_progress {
+                    eprint!("\r\x1b[K");
+                }
+                eprintln!("Error: {}: {}", album.name, e);
+                record.messages.push(format!("{}: {}", album.name, e));
+                failed += 1;
+                continue;
+            }
+        };
+
+        let mut album_failed = false;
+        for (path, tagged) in &album.tracks {
+            let missing = tagged.missing_from(&canonical);
+            if missing.is_empty() {
+                continue;
+            }
+
+            if preview {
+                if show_progress {
+                    eprint!("\r\x1b[K");
+                }
+                match preview_write(path, |copy| write_release_facts(copy, &missing)) {
+                    Ok(preview) => print_preview(&preview),
+                    Err(e) => eprintln!("Error: {}: {}", path.display(), e),
+                }
+                continue;
+            }
+
+            match write_release_facts(path, &missing) {
+                Ok(()) => {
+                    record.files += 1;
+                    written.push(path.clone());
+                    tracks_done += 1;
+                }
+                Err(e) => {
+                    eprintln!("Error: {}: {}", path.display(), e);
+                    record.messages.push(format!("{}: {}", path.display(), e));
+                    album_failed = true;
+                }
+            }
+        }
+
+        if album_failed {
+            failed += 1;
+        } else {
+            done += 1;
+        }
+        if verbose && !preview {
+            let facts = [
+                canonical.genre.clone(),
+                canonical.year.map(|y| y.to_string()),
+                canonical.label.clone(),
+            ];
+            let facts: Vec<String> = facts.into_iter().flatten().collect();
+            if facts.is_empty() {
+                println!("{}: nothing on MusicBrainz", album.name);
+            } else {
+                println!("{}: {}", album.name, facts.join(", "));
+            }
+        }
+        record.targets.push(album.name.clone());
+
+        if (i + 1) % batch == 0 {
+            if failed > 0 {
+                record.outcome = TxOutcome::Partial;
+            }
+            commit(std::mem::replace(&mut record, new_record()), &mut written);
+        }
+    }
+    if show_progress {
+        eprint!("\r\x1b[K");
+    }
+
+    if preview {
+        return;
+    }
+    if failed > 0 {
+        record.outcome = if done == 0 { TxOutcome::Failed } else { TxOutcome::Partial };
+    }
+    commit(record, &mut written);
+
+    println!("Backfilled {} track(s) in {} album(s)", tracks_done, done);
+    if failed > 0 {
+        println!("{} album(s) failed", failed);
+        process::exit(1);
+    }
+}
+
+pub fn report_duplicates(targets: &[&String], options: &DuplicateOptions, verbose: bool) {
+    if targets.is_empty() {
+        eprintln!("Error: No directories specified");
+        process::exit(1);
+    }
+
+    let files = scan_audio_files(targets);
+
+    if verbose {
+        println!("Scanning {} audio files for duplicates...", files.len());
+    }
+
+    let mut report = match find_duplicates(&files, options) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    for (path, reason) in &report.skipped {
+        eprintln!("Warning: skipped {}: {}", path.display(), reason);
+    }
+
+    if report.groups.is_empty() {
+        println!("No duplicates found");
+        return;
+    }
+
+    collation().sort_by(&mut report.groups, |g| g.files[0].0.to_str().unwrap_or_default());
+
+    let mut total = 0;
+    for group in &report.groups {
+        let kind = match group.kind {
+            DuplicateKind::Identical => "Identical files",
+            DuplicateKind::SameRecording => "Same recording",
+            DuplicateKind::SameTags => "Same artist and title",
+        };
+        println!("{} ({} reclaimable):", kind, format_size(group.reclaimable()));
+
+        for (i, (path, quality)) in group.files.iter().enumerate() {
+            let marker = if i == 0 { "keep" } else { "    " };
+            let detail = match (quality.lossless, quality.bit_depth, quality.sample_rate, quality.bitrate) {
+                (true, Some(depth), Some(rate), _) => format!("{}bit/{}Hz", depth, rate),
+                (_, _, _, Some(bitrate)) => format!("{}kbps", bitrate),
+                _ => String::new(),
+            };
+            println!(
+                "  {} {:<5} {:<14} {:>10}  {}",
+                marker,
+                quality.format,
+                detail,
+                format_size(quality.size),
+                path.display()
+            );
+        }
+
+        total += group.reclaimable();
+    }
+
+    println!("{} duplicate sets, {} reclaimable", report.groups.len(), format_size(total));
+}
+
+/// Resolve path targets that don't exist against their siblings
+///
+/// A near miss ("Lwo" for "Low", "things we lost" for "Things We Lost in
+/// the Fire") is picked automatically when one entry is clearly closest;
+/// otherwise the closest entries are suggested and flacman exits.
+fn resolve_targets(targets: &[&String]) -> Vec<String> {
+    let matcher = FuzzyMatcher { collation: collation().clone(), ..Default::default() };
+    let mut resolved = Vec::with_capacity(targets.len());
+
+    for target in targets {
+        let path = Path::new(target.as_str());
+        if path.exists() {
+            resolved.push(target.to_string());
+            continue;
+        }
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let query = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut names: Vec<String> = std::fs::read_dir(parent)
+            .map(|entries| entries.flatten().map(|e| e.file_name().to_string_lossy().into_owned()).collect())
+            .unwrap_or_default();
+        names.sort();
+
+        match matcher.resolve(&query, &names) {
+            Resolution::Exact(i) | Resolution::Close(i) => {
+                let path = path.with_file_name(&names[i]);
+                eprintln!("Resolved '{}' to '{}'", target, path.display());
+                resolved.push(path.display().to_string());
+            }
+            Resolution::Suggestions(suggestions) => {
+                eprintln!("Error: Target not found: {}", target);
+                eprintln!("Did you mean:");
+                for i in suggestions {
+                    eprintln!("    {}", path.with_file_name(&names[i]).display());
+                }
+                process::exit(1);
+            }
+            Resolution::NotFound => {
+                eprintln!("Error: Target not found: {}", target);
+                process::exit(1);
+            }
+        }
+    }
+
+    resolved
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Number of files and total bytes under `path` (or of `path` itself)
+fn path_stats(path: &Path) -> (u64, u64) {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return (0, 0);
+    };
+
+    if !metadata.is_dir() {
+        return (1, metadata.len());
+    }
+
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return (0, 0);
+    };
+
+    entries.filter_map(|e| e.ok()).fold((0, 0), |(files, bytes), entry| {
+        let (f, b) = path_stats(&entry.path());
+        (files + f, bytes + b)
+    })
+}
+
+fn tx_log() -> TxLog {
+    TxLog::new(data_dir().join("flacman.log"))
+}
+
+/// Append `record` to the transaction log
+///
+/// # Returns
+/// The id it was logged under, unless the log couldn't be written
+fn log_transaction(record: TxRecord) -> Option<u64> {
+    let changed_library = record.outcome != TxOutcome::Vetoed;
+    debug!(
+        operation = %record.operation,
+        outcome = ?record.outcome,
+        files = record.files,
+        bytes = record.bytes,
+        targets = ?record.targets,
+        "transaction"
+    );
+
+    let id = tx_log().append(record).map_err(|e| eprintln!("Warning: could not write transaction log: {}", e)).ok();
+
+    if changed_library {
+        refresh_views();
+    }
+    id
+}
+
+fn view_registry() -> ViewRegistry {
+    ViewRegistry::new(data_dir().join("views.json"))
+}
+
+/// Create a symlink view of the library and register it, so it's
+/// regenerated after every transaction
+pub fn build_library_view(facet: &str, dir: &str, targets: &[&String]) {
+    let facet = match facet.parse::<ViewFacet>() {
+        Ok(facet) => facet,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+
+    let absolute = |p: &str| std::path::absolute(p).unwrap_or_else(|_| PathBuf::from(p));
+    let spec = ViewSpec {
+        facet,
+        dir: absolute(dir),
+        library: targets.iter().map(|t| absolute(t)).collect(),
+    };
+
+    match build_view(&read_albums(targets), facet, &spec.dir) {
+        Ok(report) => println!(
+            "Built {} view in {}: {} added, {} removed, {} unchanged",
+            facet,
+            spec.dir.display(),
+            report.added,
+            report.removed,
+            report.unchanged
+        ),
+        Err(e) => {
+            eprintln!("Error: {}: {}", spec.dir.display(), e);
+            process::exit(1);
+        }
+    }
+
+    if let Err(e) = view_registry().register(spec) {
+        eprintln!("Warning: could not register view, it won't be kept up to date: {}", e);
+    }
+}
+
+/// Bring every registered view up to date with its library
+fn refresh_views() {
+    let views = match view_registry().load() {
+        Ok(views) => views,
+        Err(e) => {
+            eprintln!("Warning: could not read registered views: {}", e);
+            return;
+        }
+    };
+
+    for view in views {
+        let library: Vec<String> = view.library.iter().map(|p| p.display().to_string()).collect();
+        let library: Vec<&String> = library.iter().filter(|p| Path::new(p.as_str()).is_dir()).collect();
+
+        if let Err(e) = build_view(&read_albums(&library), view.facet, &view.dir) {
+            eprintln!("Warning: could not update view {}: {}", view.dir.display(), e);
+        }
+    }
+}
+
+/// Local usage metrics are opt-in
+fn metrics_store() -> Option<MetricsStore> {
+    let enabled = std::env::var("FLACMAN_METRICS").is_ok_and(|v| v == "1");
+    enabled.then(|| MetricsStore::new(data_dir().join("metrics.log")))
+}
+
+fn record_metric(metric: Metric) {
+    if let Some(store) = metrics_store()
+        && let Err(e) = store.record(metric)
+    {
+        eprintln!("Warning: could not record metrics: {}", e);
+    }
+}
+
+pub fn show_metrics() {
+    let store = MetricsStore::new(data_dir().join("metrics.log"));
+
+    let trends = match store.trends(TimeDelta::days(METRICS_WINDOW_DAYS)) {
+        Ok(trends) => trends,
+        Err(e) => {
+            eprintln!("Error: cannot read metrics: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if trends.is_empty() {
+        println!("No metrics recorded yet (set FLACMAN_METRICS=1 to start collecting)");
+        return;
+    }
+
+    let show = |group: &str, value: Option<f64>| match (group, value) {
+        (_, None) => "-".to_owned(),
+        ("throughput", Some(v)) => format!("{}/s", format_size(v as u64)),
+        ("hit rate", Some(v)) => format!("{:.0}%", v * 100.0),
+        (_, Some(v)) => format!("{:.1}s", v),
+    };
+
+    println!("Last {} days vs. before:", METRICS_WINDOW_DAYS);
+
+    let mut current_group = "";
+    for trend in &trends {
+        if trend.group != current_group {
+            current_group = trend.group;
+            println!("{}:", current_group);
+        }
+
+        let change = trend.change().map(|c| format!(" ({:+.0}%)", c * 100.0)).unwrap_or_default();
+        println!(
+            "    {:<30} {:>12} <- {:<12}{} [{} samples]",
+            trend.label,
+            show(trend.group, trend.recent),
+            show(trend.group, trend.previous),
+            change,
+            trend.samples
+        );
+    }
+}
+
+/// Parse `--since`: a plain date means midnight local time
+fn parse_since(value: &str) -> Result<DateTime<Local>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Local));
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("invalid date '{}', expected YYYY-MM-DD", value))?;
+
+    Local
+        .from_local_datetime(&date.and_time(chrono::NaiveTime::MIN))
+        .earliest()
+        .ok_or_else(|| format!("'{}' does not exist in the local time zone", value))
+}
+
+/// Print `value` as pretty JSON for `--json`
+fn print_json<T: serde::Serialize + ?Sized>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(out) => println!("{}", out),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+pub fn show_history(filter: &TxFilter, json: bool) {
+    let records = match tx_log().history(filter) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Error: cannot read transaction log: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if json {
+        print_json(&records);
+        return;
+    }
+
+    if records.is_empty() {
+        println!("No matching transactions");
+        return;
+    }
+
+    for record in &records {
+        println!(
+            "[{}] #{} {} {:?}: {} file(s), {}",
+            record.time.format("%Y-%m-%d %H:%M"),
+            record.id,
+            record.operation,
+            record.outcome,
+            record.files,
+            format_size(record.bytes)
+        );
+
+        if let Some(source) = &record.source {
+            println!("    from {}", source);
+        }
+        for target in &record.targets {
+            println!("    {}", target);
+        }
+        for message in &record.messages {
+            println!("    note: {}", message);
+        }
+    }
+}
+
+/// `$XDG_<kind>_HOME/flacman`, falling back to `~/<fallback>/flacman`
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    let base = std::env::var_os(var)
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    base.join("flacman")
+}
+
+fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// The config file's settings, loaded once; a broken file is reported and
+/// the defaults used instead
+fn config() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        Config::load().unwrap_or_else(|e| {
+            eprintln!("Warning: ignoring config: {}", e);
+            Config::default()
+        })
+    })
+}
+
+/// How listings order names, from the `[collation]` config section
+fn collation() -> &'static Collation {
+    &config().collation
+}
+
+/// The configured library, else the first of the `[[roots]]`
+fn default_library() -> Option<&'static String> {
+    static LIBRARY: OnceLock<Option<String>> = OnceLock::new();
+    LIBRARY
+        .get_or_init(|| {
+            let config = config();
+            let library = config.library.as_ref().or_else(|| config.roots.first().map(|r| &r.path));
+            library.map(|p| p.display().to_string())
+        })
+        .as_ref()
+}
+
+/// Every root of the library that is there: those of a library split
+/// across volumes, else the configured library
+fn library_roots() -> &'static [String] {
+    static ROOTS: OnceLock<Vec<String>> = OnceLock::new();
+    ROOTS.get_or_init(|| {
+        let volumes = volume_set();
+        let roots: Vec<String> =
+            volumes.roots().iter().filter(|r| r.is_dir()).map(|r| r.display().to_string()).collect();
+        if roots.is_empty() { default_library().into_iter().cloned().collect() } else { roots }
+    })
+}
+
+/// Targets given on the command line, or every library root when there
+/// are none
+fn library_targets(matches: &ArgMatches) -> Vec<&String> {
+    let targets: Vec<&String> = matches.get_many::<String>("targets").unwrap_or_default().collect();
+    if targets.is_empty() { library_roots().iter().collect() } else { targets }
+}
+
+fn data_dir() -> PathBuf {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+fn cache_dir() -> PathBuf {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// Content-addressed store shared by every download source
+fn download_cache() -> DownloadCache {
+    DownloadCache::new(cache_dir().join("downloads"))
+}
+
+/// Holding area for soft-deleted files: `[trash] path`, else
+/// `.flacman/trash` in the library, where removing is a rename
+fn trash_dir() -> PathBuf {
+    let in_library = || default_library().map(|library| Path::new(library).join(".flacman").join("trash"));
+    config().trash.path.clone().or_else(in_library).unwrap_or_else(|| data_dir().join("trash"))
+}
+
+/// The trash, each batch named after the transaction that removes it
+fn trash() -> Trash {
+    Trash::new(trash_dir()).with_transaction_ids(|| tx_log().next_id().ok())
+}
+
+/// Client for MusicBrainz lookups, caching responses in the cache directory
+fn musicbrainz() -> &'static MbClient {
+    static CLIENT: OnceLock<MbClient> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        MbClient::default().with_cache(cache_dir().join("musicbrainz")).with_download_user(download_user().cloned())
+    })
+}
+
+fn provenance_store() -> ProvenanceStore {
+    ProvenanceStore::new(data_dir().join("provenance.log"))
+}
+
+/// Record where each track of `album`, now imported to `paths`, came from
+///
+/// # Arguments
+/// * `arrived` - When each track landed in the inbox, standing in for the
+///   download date when the source doesn't say
+/// * `source` - What the downloader left in the album's source sidecar
+fn record_provenance(
+    album: &Album,
+    arrived: &[Option<DateTime<Local>>],
+    paths: &[PathBuf],
+    source: Option<&SourceInfo>,
+) {
+    let mut records = Vec::new();
+
+    for ((track, arrived), path) in album.tracks().zip(arrived).zip(paths) {
+        let mut source =
+            source.cloned().unwrap_or_else(|| SourceInfo { source: "inbox".to_owned(), ..Default::default() });
+        source.downloaded = source.downloaded.or(*arrived);
+
+        match track_provenance(path, &track.path, source) {
+            Ok(record) => records.push(record),
+            Err(e) => eprintln!("Warning: no provenance for {}: {}", path.display(), e),
+        }
+    }
+
+    if let Err(e) = provenance_store().record(&records) {
+        eprintln!("Warning: could not write provenance: {}", e);
+    }
+}
+
+/// Files read and written to the library database per transaction while
+/// reindexing, so an interrupted run keeps what it finished
+const INDEX_BATCH: usize = 200;
+
+/// The lock flacman holds while it changes the library or its database
+fn db_lock_path() -> PathBuf {
+    data_dir().join("db.lck")
+}
+
+/// Take the database lock, or wait for it when `wait` is set; exits if
+/// it can't be had
+fn lock_database(wait: bool) -> DbLock {
+    let path = db_lock_path();
+    let locked = if wait {
+        if let Ok(Some(holder)) = DbLock::holder(&path) {
+            let holder = holder.map_or_else(|| "another process".to_owned(), |pid| format!("PID {}", pid));
+            println!("Waiting for the database lock held by {}", holder);
+        }
+        DbLock::wait(&path)
+    } else {
+        DbLock::acquire(&path)
+    };
+    locked.unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    })
+}
+
+/// `--force-unlock`: remove the database lock, whoever holds it
+fn force_unlock() {
+    let path = db_lock_path();
+    if let Ok(Some(holder)) = DbLock::holder(&path) {
+        let holder = holder.map_or_else(|| "a process".to_owned(), |pid| format!("PID {}", pid));
+        eprintln!("Warning: {} still holds the lock; both may now change the library", holder);
+    }
+    match DbLock::force_unlock(&path) {
+        Ok(true) => println!("Removed the database lock {}", path.display()),
+        Ok(false) => println!("The database isn't locked"),
+        Err(e) => {
+            eprintln!("Error: could not remove {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+fn library_db() -> LibraryDb {
+    match LibraryDb::open(data_dir().join("library.db")) {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// The database record of the file at `path`, read from its tags
+fn track_record(path: &Path) -> std::result::Result<TrackRecord, String> {
+    let stat = path.metadata().map_err(|e| e.to_string())?;
+    let mut file = MediaFile::new(path);
+    let metadata = file.read().map_err(|e| e.to_string())?;
+
+    let mut tags = BTreeMap::new();
+    let mut tag = |key: &str, value: Option<String>| {
+        if let Some(value) = value {
+            tags.insert(key.to_owned(), value);
+        }
+    };
+    tag("genre", metadata.genre.as_ref().map(|g| g.as_str().to_owned()));
+    tag("tracktotal", metadata.track_total.map(|n| n.to_string()));
+    tag("disctotal", metadata.disc_total.map(|n| n.to_string()));
+    tag("discsubtitle", metadata.disc_subtitle.as_ref().map(|s| s.as_str().to_owned()));
+    tag("narrator", metadata.narrator.as_ref().map(|n| n.as_str().to_owned()));
+    tag("label", metadata.label.as_ref().map(|l| l.as_str().to_owned()));
+    tag("rating", metadata.rating.map(|r| r.to_string()));
+    tag("playcount", metadata.play_count.map(|n| n.to_string()));
+    for key in ["length", "samplerate", "bitdepth", "channels"] {
+        tag(key, metadata.field(key).map(|v| v.into_owned()));
+    }
+    // Named as beets names them, so they survive --export-beets
+    let (release, release_group) = release_ids(path).unwrap_or_default();
+    tag("mb_albumid", release);
+    tag("mb_releasegroupid", release_group);
+    // Lets --validate-local tell bit rot from edits without decoding
+    tag("flac_md5", flac_md5_tag(path));
+
+    let artist = metadata.author.as_str().to_owned();
+    Ok(TrackRecord {
+        path: std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+        album_artist: metadata.album_artist.as_ref().map_or_else(|| artist.clone(), |a| a.as_str().to_owned()),
+        album: metadata.album.as_str().to_owned(),
+        artist,
+        title: metadata.track_name.as_str().to_owned(),
+        disc: metadata.disc_number,
+        track: metadata.track_number,
+        year: metadata.year,
+        tags,
+        sha256: sha256_file(path).ok(),
+        size: stat.len(),
+        modified: stat.modified().ok().map_or(0, |m| DateTime::<Local>::from(m).timestamp()),
+        added: Local::now(),
+    })
+}
+
+/// Library paths the imports of this run brought in, for the
+/// post-transaction hooks
+static IMPORTED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+fn note_imported(paths: &[PathBuf]) {
+    IMPORTED.lock().expect("imported paths lock poisoned").extend_from_slice(paths);
+}
+
+fn take_imported() -> Vec<PathBuf> {
+    std::mem::take(&mut *IMPORTED.lock().expect("imported paths lock poisoned"))
+}
+
+/// Run the hooks in `hooks/` of the config directory that `paths`, the
+/// ones an `operation` transaction affects, trigger at `when`
+///
+/// # Returns
+/// False if a pre-transaction hook with `AbortOnFail` failed, or the
+/// hooks couldn't be read before a transaction; any other failure only
+/// warns
+fn run_hooks(when: HookWhen, operation: HookOperation, paths: &[PathBuf]) -> bool {
+    let hooks = match Hook::load_dir(&config_dir().join("hooks")) {
+        Ok(hooks) => hooks,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return when == HookWhen::PostTransaction;
+        }
+    };
+    let triggered: Vec<(&Hook, Vec<&PathBuf>)> = hooks
+        .iter()
+        .filter(|hook| hook.when == when)
+        .filter_map(|hook| hook.triggered(operation, paths).map(|matched| (hook, matched)))
+        .collect();
+    if triggered.is_empty() {
+        return true;
+    }
+
+    let pre = when == HookWhen::PreTransaction;
+    println!(":: Running {}-transaction hooks...", if pre { "pre" } else { "post" });
+    for (i, (hook, matched)) in triggered.iter().enumerate() {
+        println!("({}/{}) {}", i + 1, triggered.len(), hook.description.as_deref().unwrap_or(&hook.name));
+        if let Err(e) = hook.run(matched) {
+            if pre && hook.abort_on_fail {
+                eprintln!("Error: {}", e);
+                return false;
+            }
+            eprintln!("Warning: {}", e);
+        }
+    }
+    true
+}
+
+/// Read the audio files under `paths` into the library database
+///
+/// Used after flacman adds files itself; failures only warn, since
+/// `--reindex` can always catch up.
+fn index_paths(paths: &[PathBuf]) {
+    let mut records = Vec::new();
+    for path in paths {
+        let files =
+            if path.is_dir() { flacman_fs::find_audio_files(path).unwrap_or_default() } else { vec![path.clone()] };
+        for file in files {
+            match track_record(&file) {
+                Ok(record) => records.push(record),
+                Err(e) => eprintln!("Warning: not indexed: {}: {}", file.display(), e),
+            }
+        }
+    }
+
+    if let Err(e) = library_db().update_tracks(&records) {
+        eprintln!("Warning: could not update the library database: {}", e);
+    }
+}
+
+/// The audio files a [`Scanner`] finds under `root`, recording how long
+/// the scan took; exits if `target`, as given, can't be scanned
+fn scan_library_root(target: &str, root: &Path) -> Vec<PathBuf> {
+    let started = Instant::now();
+    match Scanner::new(root).scan() {
+        Ok(scan) => {
+            let files: Vec<PathBuf> = scan.map(|file| file.path).collect();
+            record_metric(Metric::Scan {
+                root: target.to_owned(),
+                files: files.len() as u64,
+                secs: started.elapsed().as_secs_f64(),
+            });
+            files
+        }
+        Err(e) => {
+            eprintln!("Error: {}: {}", target, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Bring the library database up to date with the files under `targets`
+///
+/// Only files whose size or modification time changed are read again;
+/// indexed files that are gone are dropped.
+pub fn reindex_library(targets: &[&String], verbose: bool) {
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+
+    let mut db = library_db();
+    let indexed = db.file_states().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+
+    let mut changed = Vec::new();
+    let mut missing = Vec::new();
+    for target in targets {
+        let root = std::path::absolute(target.as_str()).unwrap_or_else(|_| PathBuf::from(target.as_str()));
+        let files = scan_library_root(target, &root);
+
+        let on_disk: HashSet<&PathBuf> = files.iter().collect();
+        missing.extend(indexed.keys().filter(|p| p.starts_with(&root) && !on_disk.contains(p)).cloned());
+
+        for file in files {
+            let state = file.metadata().ok().map(|m| {
+                let modified = m.modified().ok().map_or(0, |t| DateTime::<Local>::from(t).timestamp());
+                (m.len(), modified)
+            });
+            if state.is_none() || indexed.get(&file) != state.as_ref() {
+                changed.push(file);
+            }
+        }
+    }
+
+    let unchanged = indexed.len() - missing.len();
+    if verbose {
+        println!("{} file(s) to index, {} unchanged, {} gone", changed.len(), unchanged, missing.len());
+    }
+
+    for path in &missing {
+        if let Err(e) = db.remove_under(path) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+
+    let cancel = cancel_flag();
+    let mut done = 0;
+    for batch in changed.chunks(INDEX_BATCH) {
+        if cancel.load(Ordering::Relaxed) {
+            exit_cancelled(done, changed.len(), "run --reindex again to index the rest");
+        }
+
+        let mut records = Vec::with_capacity(batch.len());
+        for path in batch {
+            match track_record(path) {
+                Ok(record) => {
+                    if verbose {
+                        println!("Indexed {}", path.display());
+                    }
+                    records.push(record);
+                }
+                Err(e) => eprintln!("Warning: skipped {}: {}", path.display(), e),
+            }
+        }
+
+        if let Err(e) = db.update_tracks(&records) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        done += batch.len();
+    }
+
+    println!("Indexed {} changed file(s), dropped {} missing, {} unchanged", changed.len(), missing.len(), unchanged);
+}
+
+/// Add the items of a beets library to the library database without
+/// reading the files
+///
+/// Files already indexed keep their checksum and the earlier of the two
+/// dates they were added.
+pub fn import_beets(file: &Path, verbose: bool) {
+    let records = read_beets_library(file).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+
+    let mut db = library_db();
+    let mut missing = 0;
+    let mut imported = Vec::with_capacity(records.len());
+    for mut record in records {
+        if !record.path.exists() {
+            missing += 1;
+            if verbose {
+                println!("Missing: {}", record.path.display());
+            }
+        }
+        if let Ok(Some(known)) = db.track(&record.path) {
+            record.added = record.added.min(known.added);
+            record.sha256 = record.sha256.or(known.sha256);
+        }
+        imported.push(record);
+    }
+
+    for batch in imported.chunks(INDEX_BATCH) {
+        if let Err(e) = db.update_tracks(batch) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+
+    let mut record = TxRecord::new("import-beets", vec![file.display().to_string()], TxOutcome::Success);
+    record.files = imported.len() as u64;
+    log_transaction(record);
+
+    println!("Imported {} track(s) from {}", imported.len(), file.display());
+    if missing > 0 {
+        println!("Note: {} of them are not on disk; --reindex drops them", missing);
+    }
+}
+
+/// Write the library database out as a beets library
+pub fn export_beets(file: &Path) {
+    let tracks = library_db().tracks().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    match write_beets_library(file, &tracks) {
+        Ok(albums) => println!("Exported {} track(s) in {} album(s) to {}", tracks.len(), albums, file.display()),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Serve the indexed albums under the library root over HTTP until Ctrl-C
+pub fn publish_library(matches: &ArgMatches, targets: &[&String]) {
+    let [target] = targets else {
+        eprintln!("Error: --publish serves exactly one library directory; name one of the roots");
+        process::exit(1);
+    };
+    let root = std::path::absolute(target.as_str()).unwrap_or_else(|_| PathBuf::from(target.as_str()));
+
+    let tracks = library_db().tracks_under(&root).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+
+    // Tracks of an album may sit in disc subdirectories; the album is
+    // published as the directory holding all of them
+    let mut grouped: BTreeMap<(String, String), Vec<&TrackRecord>> = BTreeMap::new();
+    for track in &tracks {
+        grouped.entry((track.album_artist.clone(), track.album.clone())).or_default().push(track);
+    }
+
+    let mut albums = Vec::new();
+    for ((artist, title), tracks) in grouped {
+        let year = tracks.iter().find_map(|t| t.year);
+        let mut dir = tracks[0].path.parent().map(Path::to_path_buf).unwrap_or_default();
+        for track in &tracks {
+            while !track.path.starts_with(&dir) {
+                dir.pop();
+            }
+        }
+        let Ok(rel) = dir.strip_prefix(&root) else { continue };
+        if rel.as_os_str().is_empty() {
+            eprintln!("Warning: not publishing {} - {}: its tracks aren't in an album directory", artist, title);
+            continue;
+        }
+
+        let path: Vec<String> = rel.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+        albums.push(PublishedAlbum {
+            artist,
+            title,
+            year,
+            path: path.join("/"),
+            files: tracks.len(),
+            bytes: tracks.iter().map(|t| t.size).sum(),
+        });
+    }
+
+    if albums.is_empty() {
+        eprintln!("Error: Nothing indexed under {}; run flacman --reindex {} first", root.display(), target);
+        process::exit(1);
+    }
+
+    let listen = matches.get_one::<String>("listen").map_or("127.0.0.1:6601", String::as_str);
+    let listener = match std::net::TcpListener::bind(listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error: cannot listen on {}: {}", listen, e);
+            process::exit(1);
+        }
+    };
+
+    let publisher = Publisher::new(&root, albums, matches.get_one::<String>("sign-with").cloned());
+    println!(
+        "Publishing {} album(s) from {} on http://{}/{} (Ctrl-C to stop)",
+        publisher.albums().len(),
+        root.display(),
+        listen,
+        PUBLISH_INDEX
+    );
+
+    if let Err(e) = publisher.serve(listener, cancel_flag()) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+    println!("Stopped publishing");
+}
+
+/// Where `--daemon` listens unless `--socket` says otherwise
+fn daemon_socket() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("flacman.sock"),
+        None => data_dir().join("flacman.sock"),
+    }
+}
+
+/// State `--daemon` shares between the watcher and its clients
+struct Daemon {
+    root: PathBuf,
+    db: Mutex<LibraryDb>,
+    /// How requested imports are filed; the mode is set per request
+    import: AutoImport,
+    read_only: bool,
+    started: Instant,
+}
+
+impl Daemon {
+    fn db(&self) -> std::sync::MutexGuard<'_, LibraryDb> {
+        self.db.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn handle(&self, request: DaemonRequest) -> DaemonResponse {
+        debug!(?request, "daemon request");
+        let result = match request {
+            DaemonRequest::Status => {
+                let db = self.db();
+                db.tracks().and_then(|tracks| Ok((tracks.len(), db.albums()?.len()))).map(|(tracks, albums)| {
+                    DaemonResponse::success(&serde_json::json!({
+                        "library": self.root,
+                        "tracks": tracks,
+                        "albums": albums,
+                        "read_only": self.read_only,
+                        "uptime_secs": self.started.elapsed().as_secs(),
+                    }))
+                })
+            }
+            DaemonRequest::Query { term } => self.db().tracks().map(|mut tracks| {
+                sort_tracks(&mut tracks);
+                let index = SearchIndex::with_collation(tracks, collation().clone());
+                let hits: Vec<&TrackRecord> = index.search(&SearchQuery::parse(&term)).iter().map(|hit| hit.track).collect();
+                DaemonResponse::success(&hits)
+            }),
+            DaemonRequest::Albums { term } => self.db().albums().map(|mut albums| {
+                sort_albums(&mut albums);
+                let term = term.map(|t| t.to_lowercase());
+                let matching: Vec<_> = albums
+                    .iter()
+                    .filter(|album| {
+                        let name = format!("{} - {}", album.artist, album.title).to_lowercase();
+                        term.as_ref().is_none_or(|t| name.contains(t.as_str()))
+                    })
+                    .collect();
+                DaemonResponse::success(&matching)
+            }),
+            DaemonRequest::Import { path, mode } => return self.import(&path, mode.as_deref()),
+            DaemonRequest::Remove { path } => return self.remove(&path),
+        };
+        result.unwrap_or_else(|e| DaemonResponse::failure(e.to_string()))
+    }
+
+    /// File the albums under `path` into the library without asking
+    /// anything: conflicts keep the better copy
+    fn import(&self, path: &Path, mode: Option<&str>) -> DaemonResponse {
+        if self.read_only {
+            return DaemonResponse::failure("the library is read-only");
+        }
+        let mode = match mode.map(str::to_lowercase).as_deref() {
+            Some("copy") => TransferMode::Copy,
+            Some("move") => TransferMode::Move,
+            Some("symlink") => TransferMode::Symlink,
+            Some("reflink") => TransferMode::Reflink,
+            Some(other) => return DaemonResponse::failure(format!("unknown transfer mode {:?}", other)),
+            None => match config().transfer {
+                Some(DefaultTransfer::Move) => TransferMode::Move,
+                Some(DefaultTransfer::Symlink) => TransferMode::Symlink,
+                Some(DefaultTransfer::Reflink) => TransferMode::Reflink,
+                Some(DefaultTransfer::Copy) | None => TransferMode::Copy,
+            },
+        };
+        let Ok(path) = std::path::absolute(path) else {
+            return DaemonResponse::failure(format!("bad path {}", path.display()));
+        };
+        let albums = import_albums(&path.display().to_string());
+        if albums.is_empty() {
+            return DaemonResponse::failure(format!("no audio files in {}", path.display()));
+        }
+
+        let import = AutoImport { mode, ..self.import.clone() };
+        let _lock = match DbLock::wait(db_lock_path()) {
+            Ok(lock) => lock,
+            Err(e) => return DaemonResponse::failure(e.to_string()),
+        };
+        if !run_hooks(HookWhen::PreTransaction, HookOperation::Import, std::slice::from_ref(&path)) {
+            return DaemonResponse::failure("a pre-transaction hook failed");
+        }
+        let review_dir = if path.is_dir() { path.join(".review") } else { path.with_file_name(".review") };
+        let mut outcomes = Vec::new();
+        let mut imported = Vec::new();
+        for album in &albums {
+            let name = format!("{} - {}", album.artist, album.title);
+            let mut record = TxRecord::new("update", Vec::new(), TxOutcome::Success);
+            record.source = Some(path.display().to_string());
+
+            let outcome = match import.import(album, &review_dir, &mut |_| ConflictStrategy::KeepHigherQuality) {
+                Ok(ImportOutcome::Imported(paths)) | Ok(ImportOutcome::Resolved { paths, .. }) if !paths.is_empty() => {
+                    let records: Vec<TrackRecord> = paths.iter().filter_map(|p| track_record(p).ok()).collect();
+                    if let Err(e) = self.db().update_tracks(&records) {
+                        warn!(error = %e, "could not index imported tracks");
+                    }
+                    imported.extend(paths.iter().cloned());
+                    record.files = paths.len() as u64;
+                    record.targets = paths.iter().map(|p| p.display().to_string()).collect();
+                    record.changes = import_changes(mode, album, &paths);
+                    serde_json::json!({ "album": name, "imported": paths })
+                }
+                Ok(ImportOutcome::Held { dir, identification }) => {
+                    record.outcome = TxOutcome::Vetoed;
+                    record.messages = identification.problems.clone();
+                    serde_json::json!({ "album": name, "held": dir, "problems": identification.problems })
+                }
+                Ok(ImportOutcome::Resolved { existing, resolution, .. }) => {
+                    record.outcome = TxOutcome::Vetoed;
+                    record.messages = vec![format!("{}: {}", resolution.strategy, resolution.reason)];
+                    serde_json::json!({ "album": name, "skipped": existing, "reason": resolution.reason })
+                }
+                Ok(ImportOutcome::Imported(_)) => serde_json::json!({ "album": name, "imported": [] }),
+                Err(e) => {
+                    record.outcome = TxOutcome::Failed;
+                    record.messages = vec![e.to_string()];
+                    serde_json::json!({ "album": name, "error": e.to_string() })
+                }
+            };
+            log_transaction(record);
+            outcomes.push(outcome);
+        }
+        update_mpd(&imported);
+        run_hooks(HookWhen::PostTransaction, HookOperation::Import, &imported);
+        DaemonResponse::success(&outcomes)
+    }
+
+    /// Move `path`, inside the library, to the trash and forget its tracks
+    fn remove(&self, path: &Path) -> DaemonResponse {
+        if self.read_only {
+            return DaemonResponse::failure("the library is read-only");
+        }
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        if !path.starts_with(&self.root) || path == self.root {
+            return DaemonResponse::failure(format!("{} is not in the library", path.display()));
+        }
+
+        let _lock = match DbLock::wait(db_lock_path()) {
+            Ok(lock) => lock,
+            Err(e) => return DaemonResponse::failure(e.to_string()),
+        };
+        if !run_hooks(HookWhen::PreTransaction, HookOperation::Remove, std::slice::from_ref(&path)) {
+            return DaemonResponse::failure("a pre-transaction hook failed");
+        }
+        let entries = match trash().remove(&[&path]) {
+            Ok(entries) => entries,
+            Err(e) => return DaemonResponse::failure(e.to_string()),
+        };
+        let forgotten = self.db().remove_under(&path).unwrap_or_else(|e| {
+            warn!(error = %e, "could not update the library database");
+            0
+        });
+
+        let mut record = TxRecord::new("remove", vec![path.display().to_string()], TxOutcome::Success);
+        record.files = forgotten as u64;
+        let stored: Vec<PathBuf> = entries.iter().map(|entry| entry.stored.clone()).collect();
+        record.changes =
+            entries.into_iter().map(|entry| FileChange::Trashed { original: entry.original, stored: entry.stored }).collect();
+        log_transaction(record);
+        update_mpd(std::slice::from_ref(&path));
+        run_hooks(HookWhen::PostTransaction, HookOperation::Remove, std::slice::from_ref(&path));
+        DaemonResponse::success(&serde_json::json!({ "trashed": stored, "tracks": forgotten }))
+    }
+
+    /// Bring the database up to date with `changed` files and directories
+    fn reindex(&self, changed: &BTreeSet<PathBuf>, verbose: bool) {
+        let mut records = Vec::new();
+        let mut db = self.db();
+        for path in changed {
+            // A new directory arrives along with the files in it
+            if changed.iter().any(|dir| path != dir && path.starts_with(dir) && dir.is_dir()) {
+                continue;
+            }
+            let files = if path.is_dir() {
+                flacman_fs::find_audio_files(path).unwrap_or_default()
+            } else if path.is_file() {
+                vec![path.clone()]
+            } else {
+                match db.remove_under(path) {
+                    Ok(0) => {}
+                    Ok(n) if verbose => println!("Forgot {} ({} tracks)", path.display(), n),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Warning: {}: {}", path.display(), e),
+                }
+                continue;
+            };
+            for file in files {
+                match track_record(&file) {
+                    Ok(record) => records.push(record),
+                    Err(e) => eprintln!("Warning: skipped {}: {}", file.display(), e),
+                }
+            }
+        }
+
+        if let Err(e) = db.update_tracks(&records) {
+            eprintln!("Warning: could not index changes: {}", e);
+        } else if verbose && !records.is_empty() {
+            println!("Indexed {} changed file(s)", records.len());
+        }
+    }
+
+    /// Answer one client until it hangs up
+    #[cfg(unix)]
+    fn serve(&self, stream: std::os::unix::net::UnixStream) {
+        let reader = match stream.set_nonblocking(false).and_then(|()| stream.try_clone()) {
+            Ok(reader) => std::io::BufReader::new(reader),
+            Err(e) => return warn!(error = %e, "daemon client"),
+        };
+        if let Err(e) = serve_requests(reader, &stream, |request| self.handle(request)) {
+            warn!(error = %e, "daemon client");
+        }
+    }
+}
+
+/// Serve the library to other programs until Ctrl-C: keep the database
+/// open, index changes to the library as they happen, and answer the
+/// newline-delimited JSON requests of [`DaemonRequest`] on a Unix socket
+#[cfg(unix)]
+pub fn run_daemon(matches: &ArgMatches, targets: &[&String], verbose: bool) {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let [target] = targets else {
+        eprintln!("Error: --daemon serves exactly one library directory; name one of the roots");
+        process::exit(1);
+    };
+    let root = std::path::absolute(target.as_str()).unwrap_or_else(|_| PathBuf::from(target.as_str()));
+    let watcher = LibraryWatcher::new(&[&root]).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+
+    let socket = matches.get_one::<PathBuf>("socket").cloned().unwrap_or_else(daemon_socket);
+    // A socket nobody answers on was left behind by a daemon that died
+    if socket.exists() {
+        if UnixStream::connect(&socket).is_ok() {
+            eprintln!("Error: A daemon is already listening on {}", socket.display());
+            process::exit(1);
+        }
+        let _ = std::fs::remove_file(&socket);
+    }
+    if let Some(parent) = socket.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let listener = match UnixListener::bind(&socket).and_then(|l| l.set_nonblocking(true).map(|()| l)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error: cannot listen on {}: {}", socket.display(), e);
+            process::exit(1);
+        }
+    };
+
+    let daemon = Daemon {
+        root: root.clone(),
+        db: Mutex::new(library_db()),
+        import: AutoImport {
+            library: root.clone(),
+            template: naming_template(matches),
+            mode: TransferMode::Copy,
+            min_confidence: matches.get_one::<u8>("min-confidence").copied().unwrap_or(80),
+            on_conflict: ConflictStrategy::KeepHigherQuality,
+            trash: Some(trash()),
+            checksum: matches.get_flag("checksum"),
+            volumes: volume_set(),
+            sanitize: config().sanitize.clone(),
+            sidecars: config().sidecars.clone(),
+        },
+        read_only: matches.get_flag("read-only") || config().read_only,
+        started: Instant::now(),
+    };
+    println!("Serving {} on {} (Ctrl-C to stop)", root.display(), socket.display());
+
+    let cancel = cancel_flag();
+    std::thread::scope(|scope| {
+        let daemon = &daemon;
+        scope.spawn(move || {
+            while !cancel.load(Ordering::Relaxed) {
+                let mut changed = watcher.changes(Duration::from_millis(500));
+                if changed.is_empty() {
+                    continue;
+                }
+                // Let writes settle so half-copied files aren't indexed
+                loop {
+                    let more = watcher.changes(Duration::from_secs(1));
+                    if more.is_empty() {
+                        break;
+                    }
+                    changed.extend(more);
+                }
+                daemon.reindex(&changed, verbose);
+            }
+        });
+
+        while !cancel.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    scope.spawn(move || daemon.serve(stream));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    let _ = std::fs::remove_file(&socket);
+    println!("Stopped the daemon");
+}
+
+#[cfg(not(unix))]
+pub fn run_daemon(_matches: &ArgMatches, _targets: &[&String], _verbose: bool) {
+    eprintln!("Error: --daemon needs Unix domain sockets, which this platform doesn't have");
+    process::exit(1);
+}
+
+/// Albums in the library database, optionally only those whose artist or
+/// title contains `term`
+fn list_indexed_albums(term: Option<&str>, format: Option<&Template>, json: bool) {
+    let mut albums = library_db().albums().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    sort_albums(&mut albums);
+    if albums.is_empty() && !json {
+        println!("The library database is empty; fill it with: flacman --reindex <library>");
+        return;
+    }
+
+    let term = term.map(str::to_lowercase);
+    if json {
+        let matching = |album: &&flacman_core::AlbumRecord| {
+            let name = format!("{} - {}", album.artist, album.title).to_lowercase();
+            term.as_ref().is_none_or(|t| name.contains(t.as_str()))
+        };
+        print_json(&albums.iter().filter(matching).collect::<Vec<_>>());
+        return;
+    }
+    let mut found = 0;
+    for album in &albums {
+        let name = format!("{} - {}", album.artist, album.title);
+        if term.as_ref().is_some_and(|t| !name.to_lowercase().contains(t.as_str())) {
+            continue;
+        }
+        found += 1;
+        if let Some(format) = format {
+            println!("{}", format.render(album));
+            continue;
+        }
+        match album.year {
+            Some(year) => println!("{} ({}) [{} tracks, {}]", name, year, album.tracks, format_size(album.bytes)),
+            None => println!("{} [{} tracks, {}]", name, album.tracks, format_size(album.bytes)),
+        }
+    }
+
+    if let Some(term) = term
+        && found == 0
+    {
+        eprintln!("Error: No album matches: {}", term);
+        process::exit(1);
+    }
+}
+
+/// Sort `albums` by artist, then title
+fn sort_albums(albums: &mut [flacman_core::AlbumRecord]) {
+    let collation = collation();
+    albums.sort_by(|a, b| collation.compare(&a.artist, &b.artist).then_with(|| collation.compare(&a.title, &b.title)));
+}
+
+/// Sort `tracks` into album order: by album artist and album, then by
+/// disc and track number
+fn sort_tracks(tracks: &mut [TrackRecord]) {
+    let collation = collation();
+    tracks.sort_by(|a, b| {
+        collation
+            .compare(&a.album_artist, &b.album_artist)
+            .then_with(|| collation.compare(&a.album, &b.album))
+            .then_with(|| (a.disc, a.track, &a.path).cmp(&(b.disc, b.track, &b.path)))
+    });
+}
+
+/// Tracks of the library index matching `term`, best match first
+///
+/// `term` may limit words or quoted phrases to a field, as in
+/// `artist:radiohead album:"ok computer"`; misspellings still match.
+fn search_library(term: &str, format: Option<&Template>, verbose: bool, json: bool) {
+    let mut tracks = library_db().tracks().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    sort_tracks(&mut tracks);
+    let index = SearchIndex::with_collation(tracks, collation().clone());
+    let hits = index.search(&SearchQuery::parse(term));
+    if json {
+        print_json(&hits.iter().map(|hit| hit.track).collect::<Vec<_>>());
+        return;
+    }
+
+    for hit in &hits {
+        let track = hit.track;
+        if let Some(format) = format {
+            println!("{}", format.render(track));
+            continue;
+        }
+        println!("{} - {} ({})", track.artist, track.title, track.album);
+        if verbose {
+            println!("    {} (score {:.2})", track.path.display(), hit.score);
+        }
+    }
+    if hits.is_empty() {
+        process::exit(1);
+    }
+}
+
+/// The tracks of a `-Q` that makes or reads a playlist: the saved playlist
+/// of `--load-playlist`, the `-Qs` hits for the terms in `targets`, or the
+/// tracks of the albums matching them, in album order
+fn playlist_tracks(matches: &ArgMatches, db: &LibraryDb, targets: &[&String]) -> Vec<TrackRecord> {
+    let fail = |e: flacman_core::CoreError| -> ! {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    };
+    let term = targets.iter().map(|t| t.as_str()).collect::<Vec<_>>().join(" ");
+
+    if let Some(name) = matches.get_one::<String>("load-playlist") {
+        return db.playlist(name).unwrap_or_else(|e| fail(e)).unwrap_or_else(|| {
+            eprintln!("Error: No playlist named {} (see -Q --playlists)", name);
+            process::exit(1);
+        });
+    }
+    let mut tracks = db.tracks().unwrap_or_else(|e| fail(e));
+    sort_tracks(&mut tracks);
+    if matches.get_flag("search") {
+        let index = SearchIndex::with_collation(tracks.clone(), collation().clone());
+        return index.search(&SearchQuery::parse(&term)).into_iter().map(|hit| hit.track.clone()).collect();
+    }
+    let term = term.to_lowercase();
+    tracks
+        .into_iter()
+        .filter(|track| format!("{} - {}", track.album_artist, track.album).to_lowercase().contains(&term))
+        .collect()
+}
+
+/// Save the tracks of [`playlist_tracks`] with `--save-playlist`, write
+/// them to a file with `--playlist`, or else list them
+fn query_playlist(matches: &ArgMatches, targets: &[&String], format: Option<&Template>, verbose: bool, json: bool) {
+    let mut db = library_db();
+    let tracks = playlist_tracks(matches, &db, targets);
+    if tracks.is_empty() && !matches.contains_id("load-playlist") {
+        eprintln!("Error: No track matches: {}", targets.iter().map(|t| t.as_str()).collect::<Vec<_>>().join(" "));
+        process::exit(1);
+    }
+
+    let save = matches.get_one::<String>("save-playlist");
+    let file = matches.get_one::<PathBuf>("playlist");
+    if let Some(name) = save {
+        let paths: Vec<&Path> = tracks.iter().map(|track| track.path.as_path()).collect();
+        if let Err(e) = db.save_playlist(name, &paths) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        println!("Saved {} tracks as playlist {}", tracks.len(), name);
+    }
+    if let Some(file) = file {
+        let style = matches.get_one::<PathStyle>("playlist-paths").copied().unwrap_or_default();
+        if let Err(e) = write_playlist(file, &tracks, style, &export_options(matches)) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        println!("Wrote {} tracks to {}", tracks.len(), file.display());
+    }
+    if save.is_some() || file.is_some() {
+        return;
+    }
+
+    if json {
+        print_json(&tracks);
+        return;
+    }
+    for track in &tracks {
+        if let Some(format) = format {
+            println!("{}", format.render(track));
+            continue;
+        }
+        println!("{} - {} ({})", track.artist, track.title, track.album);
+        if verbose {
+            println!("    {}", track.path.display());
+        }
+    }
+}
+
+/// The playlists saved in the library database
+fn list_playlists(json: bool) {
+    let playlists = library_db().playlists().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    if json {
+        print_json(&playlists);
+        return;
+    }
+    if playlists.is_empty() {
+        println!("No saved playlists; save one with: flacman -Qs <term> --save-playlist <name>");
+    }
+    for playlist in &playlists {
+        println!("{} [{} tracks, saved {}]", playlist.name, playlist.entries, playlist.updated.format("%Y-%m-%d %H:%M"));
+    }
+}
+
+fn delete_playlist(name: &str) {
+    match library_db().delete_playlist(name) {
+        Ok(true) => println!("Deleted playlist {}", name),
+        Ok(false) => {
+            eprintln!("Error: No playlist named {}", name);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// What the library database knows about the files under `targets`
+fn show_track_info(targets: &[&String], json: bool) {
+    let db = library_db();
+    let mut found = Vec::new();
+
+    for target in targets {
+        let Ok(path) = std::path::absolute(target.as_str()) else { continue };
+        if !path.exists() {
+            continue;
+        }
+        let tracks = db.tracks_under(&path).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        });
+        if tracks.is_empty() && json {
+            eprintln!("{}: not in the library database (run --reindex)", path.display());
+        } else if tracks.is_empty() {
+            println!("{}: not in the library database (run --reindex)", path.display());
+        }
+        if json {
+            found.extend(tracks);
+            continue;
+        }
+
+        for track in &tracks {
+            print_track(track);
+            println!();
+        }
+    }
+
+    if json {
+        print_json(&found);
+    }
+}
+
+fn print_track(track: &TrackRecord) {
+    println!("Path        : {}", track.path.display());
+    println!("Title       : {}", track.title);
+    println!("Artist      : {}", track.artist);
+    match track.year {
+        Some(year) => println!("Album       : {} - {} ({})", track.album_artist, track.album, year),
+        None => println!("Album       : {} - {}", track.album_artist, track.album),
+    }
+    match (track.disc, track.track) {
+        (Some(disc), Some(number)) => println!("Track       : {}-{}", disc, number),
+        (None, Some(number)) => println!("Track       : {}", number),
+        _ => {}
+    }
+    for (key, value) in &track.tags {
+        println!("{:<12}: {}", key, value);
+    }
+    println!("Size        : {}", format_size(track.size));
+    if let Some(sha256) = &track.sha256 {
+        println!("SHA-256     : {}", sha256);
+    }
+    println!("Added       : {}", track.added.format("%Y-%m-%d %H:%M"));
+}
+
+/// Connect to MPD as `[mpd]` in flacman.conf says, else as `MPD_HOST`
+/// and `MPD_PORT` do for other clients
+fn mpd_client() -> Result<MpdClient, TagError> {
+    let settings = config().mpd.clone().unwrap_or_default();
+    let host = settings.host.or_else(|| std::env::var("MPD_HOST").ok()).unwrap_or_else(|| "localhost".to_owned());
+    let port = settings.port.or_else(|| std::env::var("MPD_PORT").ok().and_then(|p| p.parse().ok())).unwrap_or(6600);
+    MpdClient::connect(&host, port)
+}
+
+/// The directory MPD's paths are relative to
+fn mpd_music_dir() -> Option<PathBuf> {
+    let settings = config().mpd.as_ref();
+    settings.and_then(|mpd| mpd.music_directory.clone()).or_else(|| default_library().map(PathBuf::from))
+}
+
+/// Have the MPD in flacman.conf rescan the directories `paths` changed,
+/// if one is configured; failing to only warns
+fn update_mpd(paths: &[PathBuf]) {
+    let (Some(_), Some(music_dir)) = (&config().mpd, mpd_music_dir()) else { return };
+    let uris = update_uris(&music_dir, paths);
+    if uris.is_empty() {
+        return;
+    }
+
+    let updated = mpd_client().and_then(|mut client| uris.iter().try_for_each(|uri| client.update(uri)));
+    match updated {
+        Ok(()) if uris.len() == 1 => println!(":: Updating the MPD database for {}", music_dir.join(&uris[0]).display()),
+        Ok(()) => println!(":: Updating the MPD database for {} directories", uris.len()),
+        Err(e) => eprintln!("Warning: could not update MPD: {}", e),
+    }
+}
+
+/// Show what MPD is playing and the library's entry for it
+fn show_now_playing(json: bool) {
+    let playing = mpd_client().and_then(|mut client| client.now_playing()).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    let Some(playing) = playing else {
+        if json {
+            print_json(&serde_json::Value::Null);
+        } else {
+            println!("MPD is not playing anything");
+        }
+        return;
+    };
+
+    // Streams have a URL instead of a path
+    let path = mpd_music_dir().filter(|_| !playing.file.contains("://")).map(|dir| dir.join(&playing.file));
+    let track = path.as_deref().and_then(|path| {
+        library_db().track(path).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        })
+    });
+
+    if json {
+        print_json(&serde_json::json!({ "mpd": playing, "track": track }));
+        return;
+    }
+
+    println!("{:<12}: {}", if playing.state == "pause" { "Paused" } else { "Playing" }, now_playing_position(&playing));
+    match &track {
+        Some(track) => {
+            print_track(track);
+            if let Some(album) = track.path.parent() {
+                show_notes(&[&album.display().to_string()]);
+            }
+        }
+        None => {
+            let tag = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_owned());
+            println!("Title       : {}", tag(&playing.title));
+            println!("Artist      : {}", tag(&playing.artist));
+            println!("Album       : {}", tag(&playing.album));
+            match path {
+                Some(path) => println!("{}: not in the library database (run --reindex)", path.display()),
+                None => println!("{}: not in the library", playing.file),
+            }
+        }
+    }
+}
+
+/// `1:23 / 4:56`, or just the elapsed time for a stream
+fn now_playing_position(playing: &NowPlaying) -> String {
+    let time = |seconds: f64| format!("{}:{:02}", seconds as u64 / 60, seconds as u64 % 60);
+    match (playing.elapsed, playing.duration) {
+        (Some(elapsed), Some(duration)) => format!("{} / {}", time(elapsed), time(duration)),
+        (Some(elapsed), None) => time(elapsed),
+        _ => playing.file.clone(),
+    }
+}
+
+fn note_store() -> NoteStore {
+    NoteStore::new(data_dir().join("notes.json"))
+}
+
+/// An existing directory is an album, anything else an artist name
+fn note_subject(target: &str) -> NoteSubject {
+    let path = Path::new(target);
+    if path.is_dir() {
+        NoteSubject::Album(std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()))
+    } else {
+        NoteSubject::Artist(target.to_owned())
+    }
+}
+
+/// Print the notes on each target album or artist, if any
+fn show_notes(targets: &[&String]) {
+    let store = note_store();
+    for target in targets {
+        let subject = note_subject(target);
+        match store.get(&subject) {
+            Ok(Some(note)) => {
+                println!("Notes on {} (updated {}):", subject, note.updated.format("%Y-%m-%d"));
+                for line in note.text.lines() {
+                    println!("    {}", line);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Warning: could not read notes: {}", e),
+        }
+    }
+}
+
+/// Edit the notes on each target album directory or artist name
+///
+/// Opens `$VISUAL` or `$EDITOR` on the current note; when stdin isn't a
+/// terminal the new note is read from it instead, so notes can be set
+/// from scripts. An empty note removes it. With `mirror`, album notes are
+/// also written to `NOTES.md` next to the tracks.
+pub fn edit_notes(targets: &[&String], mirror: bool) {
+    if targets.is_empty() {
+        eprintln!("Error: No album directory or artist specified");
+        process::exit(1);
+    }
+
+    let store = note_store();
+    let piped = if std::io::stdin().is_terminal() {
+        None
+    } else {
+        let mut text = std::string::String::new();
+        if let Err(e) = std::io::Read::read_to_string(&mut std::io::stdin(), &mut text) {
+            eprintln!("Error: Could not read notes from stdin: {}", e);
+            process::exit(1);
+        }
+        Some(text)
+    };
+
+    for target in targets {
+        let subject = note_subject(target);
+        let current = match store.get(&subject) {
+            Ok(note) => note.map(|n| n.text).unwrap_or_default(),
+            Err(e) => {
+                eprintln!("Error: Could not read notes: {}", e);
+                process::exit(1);
+            }
+        };
+
+        let text = match &piped {
+            Some(text) => text.clone(),
+            None => match edit_text(&current, "notes.md") {
+                Ok(text) => text,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    process::exit(1);
+                }
+            },
+        };
+        if text.trim() == current.trim() {
+            println!("Notes on {} unchanged", subject);
+            continue;
+        }
+
+        let note = match store.set(&subject, &text) {
+            Ok(note) => note,
+            Err(e) => {
+                eprintln!("Error: Could not save notes: {}", e);
+                process::exit(1);
+            }
+        };
+        match &note {
+            Some(_) => println!("Saved notes on {}", subject),
+            None => println!("Removed notes on {}", subject),
+        }
+
+        if mirror && let NoteSubject::Album(dir) = &subject {
+            let dir_name = dir.display().to_string();
+            let title = read_albums(&[&dir_name])
+                .first()
+                .map(|album| format!("{} - {}", album.artist, album.title))
+                .unwrap_or_else(|| dir.file_name().unwrap_or_default().to_string_lossy().into_owned());
+            match mirror_note(dir, &title, note.as_ref()) {
+                Ok(path) if note.is_some() => println!("Wrote {}", path.display()),
+                Ok(_) => {}
+                Err(e) => eprintln!("Warning: could not update {}: {}", NOTES_FILE, e),
+            }
+        }
+    }
+}
+
+/// Show where each audio file under `targets` came from
+pub fn show_provenance(targets: &[&String]) {
+    if targets.is_empty() {
+        eprintln!("Error: No target specified");
+        process::exit(1);
+    }
+
+    let records = provenance_store().all().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+
+    for path in scan_audio_files(targets) {
+        let absolute = std::path::absolute(&path).unwrap_or_else(|_| path.clone());
+        println!("{}", path.display());
+
+        let Some(record) = records.get(&absolute) else {
+            println!("    No provenance recorded (imported before it was kept, or by hand)");
+            continue;
+        };
+
+        let field = |label: &str, value: &str| println!("    {:<11}: {}", label, value);
+        field("Source", &record.source.source);
+        if let Some(id) = &record.source.remote_id {
+            field("Remote ID", id);
+        }
+        if let Some(url) = &record.source.url {
+            field("URL", url);
+        }
+        field("Original", &record.original.display().to_string());
+        if let Some(downloaded) = record.source.downloaded {
+            field("Downloaded", &downloaded.format("%Y-%m-%d %H:%M").to_string());
+        }
+        field("Imported", &record.imported.format("%Y-%m-%d %H:%M").to_string());
+        field("SHA-256", &record.sha256);
+        if !record.source.transcodes.is_empty() {
+            field("Transcodes", &record.source.transcodes.join(" -> "));
+        }
+
+        if sha256_file(&path).is_ok_and(|sha| sha != record.sha256) {
+            println!("    Changed since import (retagged or modified)");
+        }
+        if AudioQuality::read(&path).is_ok_and(|q| !q.lossless) {
+            println!("    Lossy; the source may have a lossless original");
+        }
+    }
+}
+
+/// Ask on the terminal how to resolve an import conflict
+///
+/// Without a terminal to ask on, falls back to keeping the higher quality copy.
+/// [`ask_conflict`] with `progress` out of the way while it asks
+fn ask_conflict_over(progress: &dyn Progress) -> impl FnMut(&Conflict) -> ConflictStrategy + '_ {
+    move |conflict| {
+        let mut strategy = ConflictStrategy::default();
+        progress.suspend(&mut || strategy = ask_conflict(conflict));
+        strategy
+    }
+}
+
+fn ask_conflict(conflict: &Conflict) -> ConflictStrategy {
+    let describe =
+        |q: &Option<AudioQuality>| q.as_ref().map_or_else(|| "unknown quality".to_owned(), |q| q.to_string());
+
+    if !std::io::stdin().is_terminal() {
+        eprintln!(
+            "Warning: {} is already in the library and there is no terminal to ask; keeping the higher quality copy",
+            conflict.existing_dir.display()
+        );
+        return ConflictStrategy::KeepHigherQuality;
+    }
+
+    println!("{} is already in the library", conflict.existing_dir.display());
+    println!("    existing: {} files, {}", conflict.existing.len(), describe(&conflict.existing_quality));
+    println!("    incoming: {}", describe(&conflict.incoming_quality));
+
+    loop {
+        print!("[k]eep higher quality, keep [b]oth, [r]eplace? ");
+        let _ = std::io::stdout().flush();
+
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).unwrap_or(0) == 0 {
+            return ConflictStrategy::KeepHigherQuality;
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "" | "k" => return ConflictStrategy::KeepHigherQuality,
+            "b" => return ConflictStrategy::KeepBoth,
+            "r" => return ConflictStrategy::Replace,
+            _ => continue,
+        }
+    }
+}
+
+/// The post-download verification stage configured for this run
+fn verify_stage(matches: &ArgMatches) -> VerifyStage {
+    let mut stage = match VerifyStage::new().with_script_dir(&config_dir().join("verify.d")) {
+        Ok(stage) => stage,
+        Err(e) => {
+            eprintln!("Error: cannot load verification scripts: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Some(min_score) = matches.get_one::<i32>("min-log-score") {
+        stage = stage.with_check(LogScoreCheck { min_score: *min_score });
+    }
+
+    if let Some(dir) = matches.get_one::<String>("spectrograms") {
+        stage = stage.with_check(SpectrogramCheck { output_dir: PathBuf::from(dir) });
+    }
+
+    // Checksum manifests are always honoured; signatures only with a key
+    let trust = if let Some(key) = matches.get_one::<String>("minisign-key") {
+        Trust::Minisign { public_key: key.clone() }
+    } else if let Some(fingerprint) = matches.get_one::<String>("gpg-key") {
+        Trust::Gpg { fingerprint: fingerprint.clone() }
+    } else {
+        Trust::None
+    };
+    let require_manifest = matches.get_flag("require-manifest") || trust != Trust::None;
+
+    stage.with_check(ManifestCheck { trust: SourceTrust { trust, require_manifest } })
+}
+
+/// Run the verification stage on one item
+///
+/// A vetoed item is reported and, unless in a dry run, recorded in the
+/// transaction log.
+///
+/// # Returns
+/// Whether the item may be imported
+fn verify_item(stage: &VerifyStage, target: &str, dry_run: bool, verbose: bool) -> bool {
+    if stage.is_empty() {
+        return true;
+    }
+
+    let report = stage.run(Path::new(target));
+
+    for (check, verdict) in &report.verdicts {
+        match verdict {
+            Verdict::Accept if verbose => println!("{}: {} ok", target, check),
+            Verdict::Note(note) if verbose => println!("{}: {}: {}", target, check, note),
+            Verdict::Veto(reason) => eprintln!("Vetoed {}: {}: {}", target, check, reason),
+            _ => {}
+        }
+    }
+
+    if !report.is_vetoed() {
+        return true;
+    }
+    if dry_run {
+        return false;
+    }
+
+    let mut record = TxRecord::new("verify", vec![target.to_owned()], TxOutcome::Vetoed);
+    record.messages = report
+        .verdicts
+        .iter()
+        .filter_map(|(check, verdict)| match verdict {
+            Verdict::Veto(reason) => Some(format!("{}: {}", check, reason)),
+            _ => None,
+        })
+        .collect();
+
+    log_transaction(record);
+
+    false
 }
 
-pub fn handle_sync(matches: &ArgMatches, targets: &[&String], verbose: bool, noconfirm: bool) {
-    let artist = matches.get_flag("artist");
-    let album = matches.get_flag("album");
-    let track = matches.get_flag("track");
-    let search = matches.get_flag("search");
-    let info = matches.get_flag("info");
-    let refresh = matches.get_flag("refresh");
-    let format = matches.get_one::<String>("format");
-    let quality = matches.get_one::<String>("quality");
+/// Every directory holding audio files under `targets`; plain files are kept as is
+fn album_dirs(targets: &[&String]) -> Vec<String> {
+    let mut dirs = std::collections::BTreeSet::new();
 
-    if verbose {
-        println!("Operation: Sync (Download)");
+    for target in targets {
+        if !Path::new(target.as_str()).is_dir() {
+            dirs.insert(target.to_string());
+            continue;
+        }
+
+        match flacman_fs::find_audio_files(target.as_str()) {
+            Ok(files) => {
+                dirs.extend(files.iter().filter_map(|f| f.parent()).map(|d| d.display().to_string()));
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {}", target, e);
+                process::exit(1);
+            }
+        }
     }
 
-    if refresh {
-        println!("Refreshing remote source cache...");
+    dirs.into_iter().collect()
+}
+
+/// Checkpoint for a recursive import, keyed by operation and targets
+fn open_checkpoint(operation: &str, targets: &[&String], restart: bool) -> Checkpoint {
+    let mut parts = vec![operation.to_owned()];
+    parts.extend(targets.iter().map(|t| {
+        std::fs::canonicalize(t.as_str()).map_or_else(|_| t.to_string(), |p| p.display().to_string())
+    }));
+
+    let path = data_dir().join("checkpoints").join(Checkpoint::key(&parts));
+
+    if restart && path.exists() && let Err(e) = std::fs::remove_file(&path) {
+        eprintln!("Warning: could not discard checkpoint {}: {}", path.display(), e);
     }
 
-    if search {
-        if targets.is_empty() {
-            eprintln!("Error: No search term specified");
+    match Checkpoint::open(&path) {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => {
+            eprintln!("Error: cannot read checkpoint {}: {}", path.display(), e);
             process::exit(1);
         }
-        let target_type = if artist {
-            "artists"
-        } else if album {
-            "albums"
-        } else if track {
-            "tracks"
-        } else {
-            "all"
-        };
-        println!("Searching for {}: {:?}", target_type, targets);
-        return;
     }
+}
 
-    if info {
-        if targets.is_empty() {
-            eprintln!("Error: No target specified");
+/// Set from `--download-user`
+static DOWNLOAD_USER: OnceLock<Option<DownloadUser>> = OnceLock::new();
+
+/// The `--download-user` account that every network download runs as, if
+/// one was given
+fn download_user() -> Option<&'static DownloadUser> {
+    DOWNLOAD_USER.get().and_then(Option::as_ref)
+}
+
+/// Look up the `--download-user` account for [`download_user`]
+fn init_download_user(matches: &ArgMatches) {
+    let user = matches.get_one::<String>("download-user").map(|name| match DownloadUser::lookup(name) {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Error: {}", e);
             process::exit(1);
         }
-        let target_type = if artist {
-            "artist"
-        } else if album {
-            "album"
-        } else if track {
-            "track"
-        } else {
-            "item"
-        };
-        println!("Getting info for {}: {:?}", target_type, targets);
-        return;
-    }
+    });
+    let _ = DOWNLOAD_USER.set(user);
+}
 
+pub fn fetch_art(matches: &ArgMatches, targets: &[&String], verbose: bool) {
     if targets.is_empty() {
-        eprintln!("Error: No targets specified");
+        eprintln!("Error: No album directories specified");
         process::exit(1);
     }
 
-    // Determine download type
-    let download_type = if artist {
-        "artist discography"
-    } else if album {
-        "album"
-    } else if track {
-        "track"
-    } else {
-        eprintln!("Error: No target type specified (use -A for artist, -a for album, -t for track)");
-        process::exit(1);
+    let resolved = resolve_targets(targets);
+
+    let options = ArtFetchOptions {
+        fanart_api_key: std::env::var("FANART_API_KEY").ok(),
+        write_folder_image: true,
+        download_user: download_user().cloned(),
+        preview_writes: matches.get_flag("preview-writes"),
     };
 
-    println!("Downloading {} for: {:?}", download_type, targets);
+    for target in &resolved {
+        if verbose {
+            println!("Checking cover art in: {}", target);
+        }
+
+        match fetch_album_art(Path::new(target.as_str()), &options) {
+            Ok(report) if report.missing == 0 => {
+                if verbose {
+                    println!("{}: all tracks already have a front cover", target);
+                }
+            }
+            Ok(report) if options.preview_writes => {
+                for preview in &report.previews {
+                    print_preview(preview);
+                }
+                match report.source {
+                    Some(source) => println!("{}: would embed cover from {:?}; nothing was changed", target, source),
+                    None => println!("{}: no cover art found", target),
+                }
+            }
+            Ok(report) => match report.source {
+                Some(source) => {
+                    println!("{}: embedded cover from {:?} into {} file(s)", target, source, report.embedded.len());
+                    if let Some(folder_image) = report.folder_image {
+                        println!("Wrote {}", folder_image.display());
+                    }
+                }
+                None => println!("{}: no cover art found", target),
+            },
+            Err(e) => eprintln!("Error: {}: {}", target, e),
+        }
+    }
+}
+
+/// Measure the loudness of the albums under `targets`, `-j` tracks at a
+/// time, and write their ReplayGain tags
+///
+/// With `--skip-tagged`, albums whose tracks all have track and album gain
+/// are left alone. `confirm` is asked first, unless the caller already did.
+///
+/// # Returns
+/// Whether every album was measured and tagged
+pub fn replay_gain(matches: &ArgMatches, targets: &[&String], confirm: Option<Confirm>) -> bool {
+    if targets.is_empty() {
+        eprintln!("Error: No album directories specified");
+        process::exit(1);
+    }
+    if !content_type(matches).replaygain() {
+        println!("Note: spoken word carries no ReplayGain, skipping --replaygain");
+        return true;
+    }
 
-    if let Some(fmt) = format {
-        println!("Format: {}", fmt);
+    let skip_tagged = matches.get_flag("skip-tagged");
+    let albums: Vec<Album> = read_albums(targets)
+        .into_iter()
+        .filter(|album| !skip_tagged || !album.tracks().all(|t| has_replay_gain(&t.path)))
+        .collect();
+    if albums.is_empty() {
+        println!("No album needs ReplayGain tags");
+        return true;
     }
+    let preview = matches.get_flag("preview-writes");
+    if let Some(confirm) = confirm.filter(|_| !preview) {
+        confirm_or_exit(confirm, &format!("Measure {} album(s) and write their ReplayGain tags?", albums.len()));
+    }
+
+    let cancel = cancel_flag();
+    let jobs = cpu_jobs(matches);
+    let mut record = TxRecord::new("replaygain", targets.iter().map(|t| t.to_string()).collect(), TxOutcome::Success);
+    let mut written: u64 = 0;
+    let progress = progress(albums.len(), "Measuring");
+    for (i, album) in albums.iter().enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            drop(progress);
+            record.outcome = TxOutcome::Cancelled;
+            record.files = written;
+            log_transaction(record);
+            exit_cancelled(i, albums.len(), "run --replaygain --skip-tagged to finish");
+        }
+        let name = format!("{} - {}", album.artist, album.title);
+        progress.start(i, &name, None);
 
-    if let Some(qual) = quality {
-        println!("Quality: {}", qual);
+        let paths: Vec<PathBuf> = album.tracks().map(|t| t.path.clone()).collect();
+        let gains = match analyze_album(&paths, jobs) {
+            Ok(gains) => gains,
+            Err(e) => {
+                progress.message(&format!("Error: {}: {}", name, e));
+                record.messages.push(format!("{}: {}", name, e));
+                progress.finish(i);
+                continue;
+            }
+        };
+        for (path, gain) in paths.iter().zip(&gains) {
+            if preview {
+                match preview_write(path, |copy| write_replay_gain(copy, gain)) {
+                    Ok(preview) => progress.suspend(&mut || print_preview(&preview)),
+                    Err(e) => progress.message(&format!("Error: {}: {}", path.display(), e)),
+                }
+                continue;
+            }
+            match write_replay_gain(path, gain) {
+                Ok(()) => written += 1,
+                Err(e) => {
+                    progress.message(&format!("Error: {}: {}", path.display(), e));
+                    record.messages.push(format!("{}: {}", path.display(), e));
+                }
+            }
+        }
+        if let Some(album_gain) = gains.first().and_then(|g| g.album_gain) {
+            progress.println(&format!("{}: album gain {:+.2} dB", name, album_gain));
+        }
+        progress.finish(i);
     }
+    drop(progress);
 
-    if !noconfirm {
-        println!("Proceed with download? [Y/n]");
+    if preview {
+        println!("Nothing was changed");
+        return record.messages.is_empty();
     }
+    println!("Wrote ReplayGain tags to {} file(s)", written);
+    let failed = !record.messages.is_empty();
+    if failed {
+        record.outcome = if written == 0 { TxOutcome::Failed } else { TxOutcome::Partial };
+    }
+    record.files = written;
+    log_transaction(record);
+    !failed
 }
 
-pub fn handle_query(matches: &ArgMatches, targets: &[&String], verbose: bool) {
-    let list = matches.get_flag("list");
-    let search = matches.get_flag("search");
-    let info = matches.get_flag("info");
+/// Open the config file in `$VISUAL` or `$EDITOR`, writing a commented
+/// template first if there is none, then check that it still parses
+pub fn open_config() {
+    let path = config_path();
+    match Config::create_template(&path) {
+        Ok(true) => println!("Created {}", path.display()),
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("Error: Could not create {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
 
-    if verbose {
-        println!("Operation: Query (Local Library)");
+    println!("Opening {} in {}...", path.display(), editor_command());
+    if let Err(e) = edit_file(&path) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
     }
 
-    if list {
-        println!("Listing local music library...");
-    } else if search {
-        if targets.is_empty() {
-            eprintln!("Error: No search term specified");
+    if let Err(e) = Config::load_from(&path) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Copy ratings and play counts from MPD stickers or ListenBrainz stats
+/// into the tags of the tracks under `targets`
+///
+/// For MPD, each target should be the music directory MPD serves, since
+/// stickers are keyed by paths relative to it. `MPD_HOST` and `MPD_PORT`
+/// are honoured as by other MPD clients. Only values that differ from the
+/// tags are written. With `preview`, the writes are only shown.
+pub fn import_ratings(source: &str, targets: &[&String], verbose: bool, preview: bool) {
+    if targets.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+
+    let stats: Result<PlayStats, _> = match source.split_once(':') {
+        _ if source == "mpd" => mpd_client().and_then(|mut client| mpd_play_stats(&mut client)),
+        Some(("listenbrainz", user)) if !user.is_empty() => listenbrainz_play_stats(user, download_user()),
+        _ => {
+            eprintln!("Error: Unknown rating source: {} (use mpd or listenbrainz:USER)", source);
             process::exit(1);
         }
-        println!("Searching local library for: {:?}", targets);
-    } else if info {
-        if targets.is_empty() {
-            eprintln!("Error: No target specified");
+    };
+    let stats = match stats {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("Error: {}", e);
             process::exit(1);
         }
-        println!("Getting local info for: {:?}", targets);
-    } else if !targets.is_empty() {
-        println!("Querying local library for: {:?}", targets);
-    } else {
-        println!("Listing local music library...");
+    };
+    println!("Fetched stats for {} tracks from {}", stats.len(), source);
+
+    let mut record = TxRecord::new("import-ratings", Vec::new(), TxOutcome::Success);
+    record.source = Some(source.to_owned());
+    let mut failed = 0;
+
+    for target in targets {
+        let root = Path::new(target.as_str());
+        for album in read_albums(&[*target]) {
+            for track in album.tracks() {
+                let relative = track.path.strip_prefix(root).unwrap_or(&track.path);
+                let Some(found) = stats.lookup(relative, &track.metadata) else { continue };
+
+                let current = Popularity { rating: track.metadata.rating, play_count: track.metadata.play_count };
+                let update = Popularity {
+                    rating: found.rating.filter(|r| Some(*r) != current.rating),
+                    play_count: found.play_count.filter(|c| Some(*c) != current.play_count),
+                };
+                if update == Popularity::default() {
+                    continue;
+                }
+
+                if preview {
+                    match preview_write(&track.path, |copy| write_popularity(copy, update)) {
+                        Ok(preview) => {
+                            print_preview(&preview);
+                            record.files += 1;
+                        }
+                        Err(e) => {
+                            eprintln!("Error: {}: {}", track.path.display(), e);
+                            failed += 1;
+                        }
+                    }
+                    continue;
+                }
+
+                match write_popularity(&track.path, update) {
+                    Ok(()) => {
+                        if verbose {
+                            let rating = update.rating.map(|r| format!(" rating {}", r)).unwrap_or_default();
+                            let plays = update.play_count.map(|c| format!(" {} plays", c)).unwrap_or_default();
+                            println!("{}:{}{}", track.path.display(), rating, plays);
+                        }
+                        record.files += 1;
+                        record.targets.push(track.path.display().to_string());
+                    }
+                    Err(e) => {
+                        eprintln!("Error: {}: {}", track.path.display(), e);
+                        record.messages.push(format!("{}: {}", track.path.display(), e));
+                        failed += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    if preview {
+        println!("Previewed {} tracks, {} failed; nothing was changed", record.files, failed);
+        return;
+    }
+    println!("Updated {} tracks, {} failed", record.files, failed);
+
+    if record.files == 0 && failed == 0 {
+        return;
     }
+    if failed > 0 {
+        record.outcome = if record.files > 0 { TxOutcome::Partial } else { TxOutcome::Failed };
+    }
+    log_transaction(record);
 }
 
-pub fn handle_remove(matches: &ArgMatches, targets: &[&String], verbose: bool, noconfirm: bool) {
-    if verbose {
-        println!("Operation: Remove");
+/// What the filesystem under a library root supports, probed by trying it
+fn filesystem_capabilities(check: &str, root: &Path) -> Diagnosis {
+    let capabilities = match FsCapabilities::probe(root) {
+        Ok(capabilities) => capabilities,
+        Err(e) => return Diagnosis::warning(check, format!("cannot probe: {}", e), "check the path is writable"),
+    };
+
+    let supported: Vec<&str> = [
+        (capabilities.hardlink, "hardlinks"),
+        (capabilities.reflink, "reflinks"),
+        (capabilities.case_sensitive, "case-sensitive names"),
+    ]
+    .into_iter()
+    .filter_map(|(supported, what)| supported.then_some(what))
+    .collect();
+
+    if capabilities.hardlink {
+        Diagnosis::ok(check, supported.join(", "))
+    } else {
+        let others = if supported.is_empty() { "nothing else".to_owned() } else { supported.join(", ") };
+        Diagnosis::warning(
+            check,
+            format!("no hardlinks ({})", others),
+            "--hardlink and --dedup --hardlink won't work here; use --copy",
+        )
     }
+}
+
+/// Check everything flacman relies on and print a fix for each problem
+///
+/// `targets` are the library roots to check. Exits with status 1 if any
+/// check failed; warnings alone don't change the exit status.
+pub fn run_doctor(targets: &[&String]) {
+    let mut diagnoses = Vec::new();
+
+    let config = config_path();
+    diagnoses.push(match Config::load_from(&config) {
+        Ok(_) if config.exists() => Diagnosis::ok("config file", config.display().to_string()),
+        Ok(_) => Diagnosis::ok("config file", "none, built-in defaults in use"),
+        Err(ConfigError::Parse(_, e)) => Diagnosis::failed(
+            "config file",
+            format!("{} does not parse: {}", config.display(), e),
+            "fix it with flacman --config",
+        ),
+        Err(e) => Diagnosis::failed(
+            "config file",
+            format!("cannot read {}: {}", config.display(), e),
+            format!("fix the permissions of {} or move it aside", config.display()),
+        ),
+    });
+    diagnoses.push(check_writable_dir("data directory", &data_dir()));
+
+    let log = tx_log();
+    diagnoses.push(match log.read_all() {
+        Ok(records) => Diagnosis::ok("transaction log", format!("{} records", records.len())),
+        Err(e) if !log.path().exists() => Diagnosis::ok("transaction log", format!("not created yet ({})", e)),
+        Err(e) => Diagnosis::failed(
+            "transaction log",
+            format!("cannot read {}: {}", log.path().display(), e),
+            format!("move {} aside; --history starts over", log.path().display()),
+        ),
+    });
+    diagnoses.push(check_json_file("view registry", &data_dir().join("views.json"), "--build-view"));
+    diagnoses.push(check_json_file("wantlist", &wantlist_path(), "--mb-sync"));
+    diagnoses.push(check_json_file("subscriptions", &subscriptions_path(), "--subscribe"));
+    diagnoses.push(check_json_file("cache policy", &cache_policy_path(), "--set-cache-policy"));
+    diagnoses.push(check_json_file("download quotas", &quota_policy_path(), "--set-quota"));
+    diagnoses.push(check_json_file("volumes", &volumes_path(), "--add-volume"));
 
     if targets.is_empty() {
-        eprintln!("Error: No targets specified");
-        process::exit(1);
+        diagnoses.push(Diagnosis::warning(
+            "library roots",
+            "none given",
+            "pass the library directories as targets: flacman --doctor ~/Music",
+        ));
+    }
+    for target in targets {
+        let root = Path::new(target.as_str());
+        diagnoses.push(check_writable_dir(&format!("{} writable", target), root));
+        if root.is_dir() {
+            diagnoses.push(check_free_space(&format!("{} free space", target), root, DOCTOR_MIN_FREE_BYTES));
+            diagnoses.push(check_symlinks(&format!("{} symlinks", target), root));
+            diagnoses.push(filesystem_capabilities(&format!("{} filesystem", target), root));
+        }
+    }
+
+    for (program, needed_for, install) in [
+        ("ffmpeg", "format conversion", "install ffmpeg from your package manager"),
+        ("flac", "MD5 checks of FLAC audio (--validate-local)", "install flac"),
+        ("fpcalc", "fingerprinting (-Q --duplicates --fingerprint)", "install chromaprint (chromaprint-tools)"),
+        ("sox", "spectrograms (--spectrograms)", "install sox"),
+        ("yt-dlp", "downloading URLs (-S -t URL)", "pip install yt-dlp"),
+        ("slskd", "Soulseek sources", "see https://github.com/slskd/slskd"),
+        ("minisign", "--minisign-key verification", "install minisign"),
+        ("gpg", "--gpg-key verification", "install gnupg"),
+        ("sendmail", "email notifications", "install a sendmail-compatible MTA (msmtp, postfix)"),
+    ] {
+        diagnoses.push(check_program(program, needed_for, install));
     }
 
-    println!("Removing from library: {:?}", targets);
+    diagnoses.push(match std::env::var("FLACMAN_MB_TOKEN") {
+        Ok(token) if !token.is_empty() => Diagnosis::ok("MusicBrainz token", "set"),
+        _ => Diagnosis::warning(
+            "MusicBrainz token",
+            "FLACMAN_MB_TOKEN is not set, --mb-sync can't add to collections",
+            "create a token at https://musicbrainz.org/account/applications and export FLACMAN_MB_TOKEN",
+        ),
+    });
+    diagnoses.push(match std::env::var("FANART_API_KEY") {
+        Ok(key) if !key.is_empty() => Diagnosis::ok("fanart.tv key", "set"),
+        _ => Diagnosis::warning(
+            "fanart.tv key",
+            "FANART_API_KEY is not set, --fetch-art only asks the Cover Art Archive",
+            "get a key at https://fanart.tv/get-an-api-key/ and export FANART_API_KEY",
+        ),
+    });
 
-    if !noconfirm {
-        println!("Proceed with removal? [Y/n]");
+    for diagnosis in &diagnoses {
+        println!("[{:^4}] {}: {}", diagnosis.health, diagnosis.check, diagnosis.detail);
+        if let Some(fix) = &diagnosis.fix {
+            println!("       fix: {}", fix);
+        }
     }
-}
 
-pub fn handle_update(matches: &ArgMatches, targets: &[&String], verbose: bool, noconfirm: bool) {
-    let move_files = matches.get_flag("move");
-    let copy_files = matches.get_flag("copy");
-    let symlink_files = matches.get_flag("symlink");
-    let recursive = matches.get_flag("recursive");
+    let count = |health| diagnoses.iter().filter(|d| d.health == health).count();
+    let (warnings, failures) = (count(Health::Warning), count(Health::Failed));
+    println!("\n{} checks, {} warnings, {} failures", diagnoses.len(), warnings, failures);
 
-    if verbose {
-        println!("Operation: Update (Import to Repository)");
+    if failures > 0 {
+        process::exit(1);
     }
+}
 
+/// Validate every audio file under `targets` on `jobs` workers
+///
+/// Files whose size and modification time are what the library database
+/// indexed are only checked against the checksums stored there, and any
+/// difference is reported as bit rot. The rest, and every file with
+/// `full`, are validated in full: FLAC files are decoded and checked
+/// against their STREAMINFO MD5 when `flac` is installed. Indexed files
+/// that changed and pass are edits, and are indexed again so the stored
+/// checksums follow them. Progress is checkpointed per file. Ctrl-C stops
+/// the run with a partial report; running the same validation again
+/// continues where it stopped, so a large library can be checked over
+/// several sessions. With `report`, the failures are also written there.
+pub fn validate_local_repo(
+    targets: &[&String],
+    jobs: usize,
+    report_path: Option<&Path>,
+    restart: bool,
+    full: bool,
+    pager: bool,
+    verbose: bool,
+) {
     if targets.is_empty() {
-        eprintln!("Error: No source paths specified");
+        eprintln!("Error: No library directories specified");
         process::exit(1);
     }
 
-    let operation = if move_files {
-        "Moving"
-    } else if copy_files {
-        "Copying"
-    } else if symlink_files {
-        "Symlinking"
-    } else {
-        eprintln!("Error: No operation specified (use -m for move, -c for copy, -s for symlink)");
-        process::exit(1);
+    println!("Validating local music repository...");
+    let decode = find_program("flac").is_some();
+    if !decode {
+        eprintln!("Warning: flac is not installed; FLAC audio isn't checked against its MD5");
+    }
+
+    let files: Vec<PathBuf> =
+        targets.iter().flat_map(|target| scan_library_root(target, Path::new(target.as_str()))).collect();
+
+    let checkpoint = open_checkpoint("validate-local", targets, restart);
+    let failures_path = checkpoint.path().with_extension("failures");
+
+    if restart && failures_path.exists() && let Err(e) = std::fs::remove_file(&failures_path) {
+        eprintln!("Warning: could not discard {}: {}", failures_path.display(), e);
+    }
+
+    // Failures found by earlier, interrupted runs
+    let mut failures: Vec<ValidationFailure> = std::fs::read_to_string(&failures_path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+
+    let pending: Vec<PathBuf> = files.iter().filter(|f| !checkpoint.is_done(f)).cloned().collect();
+    if pending.len() < files.len() {
+        println!(
+            "Resuming: {} of {} files already checked (use --restart to start over)",
+            files.len() - pending.len(),
+            files.len()
+        );
+    }
+
+    if verbose {
+        println!("Checking {} files with {} workers...", pending.len(), jobs);
+    }
+
+    let cancel = cancel_flag();
+
+    let indexed: BTreeMap<PathBuf, TrackRecord> = match library_db().tracks() {
+        Ok(tracks) => tracks.into_iter().map(|t| (t.path.clone(), t)).collect(),
+        Err(e) => {
+            eprintln!("Warning: could not read the library database, validating every file in full: {}", e);
+            BTreeMap::new()
+        }
+    };
+    let verified = AtomicUsize::new(0);
+    let edited = Mutex::new(Vec::new());
+    let check = |path: &Path| {
+        let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        match indexed.get(&absolute) {
+            Some(record) if record.sha256.is_some() && unchanged_since_indexed(path, record) => {
+                verified.fetch_add(1, Ordering::Relaxed);
+                match verify_stored_checksums(path, record) {
+                    None if full => validate_file(path, decode),
+                    damage => damage,
+                }
+            }
+            record => {
+                let failure = validate_file(path, decode);
+                if failure.is_none() && record.is_some() {
+                    edited.lock().expect("edited files lock poisoned").push(absolute);
+                }
+                failure
+            }
+        }
     };
 
-    println!("{} files into repository from: {:?}", operation, targets);
+    let failures_file = std::fs::OpenOptions::new().create(true).append(true).open(&failures_path);
+    let failures_file = Mutex::new(failures_file.ok());
+    let checkpoint = Mutex::new(checkpoint);
+    let done = AtomicUsize::new(0);
+    let progress = progress(pending.len(), "Validating");
+
+    let report = check_files(&pending, jobs, cancel, check, |path, failure| {
+        progress.finish(done.fetch_add(1, Ordering::Relaxed));
+
+        if let Some(message) = failure {
+            let failure = ValidationFailure { path: path.to_path_buf(), message: message.to_owned() };
+            if let Some(file) = failures_file.lock().expect("failures lock poisoned").as_mut()
+                && let Ok(line) = serde_json::to_string(&failure)
+            {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+
+        if let Err(e) = checkpoint.lock().expect("checkpoint lock poisoned").mark_done(path) {
+            progress.message(&format!("Warning: could not update checkpoint: {}", e));
+        }
+    });
+    drop(progress);
+
+    failures.extend(report.failures);
+    failures.sort_by(|a, b| a.path.cmp(&b.path));
 
-    if recursive {
-        println!("Recursive mode enabled");
+    // Edited since they were indexed; store checksums of what they are now
+    let edited = edited.into_inner().expect("edited files lock poisoned");
+    let records: Vec<TrackRecord> = edited.iter().filter_map(|path| track_record(path).ok()).collect();
+    if !records.is_empty() && let Err(e) = library_db().update_tracks(&records) {
+        eprintln!("Warning: could not update the library database: {}", e);
     }
 
-    if !noconfirm {
-        println!("Proceed with {}? [Y/n]", operation.to_lowercase());
+    // Only the report is paged; the pager would fight with the progress line
+    page_output(pager);
+    for failure in &failures {
+        println!("{}: {}", failure.path.display(), failure.message);
     }
-}
 
-pub fn open_config() {
-    println!("Opening configuration file in default editor...");
-    // In real implementation, would open config file
-    println!("Config path: ~/.config/flacman/flacman.conf");
-}
+    let checked = files.len() - pending.len() + report.checked.len();
+    if let Some(path) = report_path
+        && let Err(e) = write_validation_report(path, &failures, checked, files.len())
+    {
+        eprintln!("Warning: could not write {}: {}", path.display(), e);
+    }
+
+    if report.interrupted {
+        println!(
+            "Interrupted: {} of {} files checked, {} failed so far; run --validate-local again to continue",
+            checked,
+            files.len(),
+            failures.len()
+        );
+        process::exit(130);
+    }
+
+    let checkpoint = checkpoint.into_inner().expect("checkpoint lock poisoned");
+    if let Err(e) = checkpoint.finish() {
+        eprintln!("Warning: could not remove checkpoint: {}", e);
+    }
+    let _ = std::fs::remove_file(&failures_path);
 
-pub fn validate_local_repo(verbose: bool) {
-    println!("Validating local music repository...");
     if verbose {
-        println!("Checking file integrity, metadata, and directory structure...");
+        println!(
+            "{} files checked against stored checksums, {} edited since they were indexed",
+            verified.load(Ordering::Relaxed),
+            edited.len()
+        );
     }
-    println!("Validation complete: OK");
+    if failures.is_empty() {
+        println!("Validation complete: OK ({} files)", files.len());
+    } else {
+        println!("Validation complete: {} of {} files failed", failures.len(), files.len());
+        process::exit(1);
+    }
+}
+
+/// Write the failures of a validation run to `path`, one per line, after a
+/// line saying how much was checked
+fn write_validation_report(
+    path: &Path,
+    failures: &[ValidationFailure],
+    checked: usize,
+    total: usize,
+) -> std::io::Result<()> {
+    let mut text = format!(
+        "# flacman --validate-local, {}: {} of {} files checked, {} failed\n",
+        Local::now().format("%Y-%m-%d %H:%M"),
+        checked,
+        total,
+        failures.len()
+    );
+    for failure in failures {
+        text.push_str(&format!("{}: {}\n", failure.path.display(), failure.message));
+    }
+    std::fs::write(path, text)
 }
 
 pub fn validate_remote_repo(verbose: bool) {
@@ -423,4 +6887,375 @@ pub fn validate_remote_repo(verbose: bool) {
         println!("Checking connectivity and API status...");
     }
     println!("Validation complete: OK");
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::{MutexGuard, PoisonError};
+    use flacman_core::MANIFEST_NAME;
+    use tempfile::tempdir;
+
+    /// Albums of the `shelf` source: `(path, artist, album, format)`
+    pub(crate) const SHELF: &[(&str, &str, &str, &str)] = &[
+        ("sync/lossless", "Shelf Band", "Lossless", "flac"),
+        ("dry/kept", "Dry Band", "Kept", "flac"),
+        ("upgrade/better", "Upgrade Band", "Better", "flac"),
+        ("upgrade/equal", "Upgrade Band", "Equal", "flac"),
+        ("upgrade/lower", "Upgrade Band", "Lower", "mp3"),
+    ];
+
+    /// Point flacman's config, data and cache directories at a scratch
+    /// directory, with a library and the `shelf` mirror source in the config
+    ///
+    /// The config is read once per process, so every test shares them; the
+    /// guard keeps the tests that use them from running at once.
+    pub(crate) fn scratch_env() -> MutexGuard<'static, ()> {
+        static DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+        static LOCK: Mutex<()> = Mutex::new(());
+        DIR.get_or_init(|| {
+            let dir = tempdir().unwrap();
+            let shelf = dir.path().join("shelf");
+            write_shelf(&shelf);
+            let library = dir.path().join("library");
+            std::fs::create_dir(&library).unwrap();
+            let inbox = dir.path().join("inbox");
+            std::fs::create_dir(&inbox).unwrap();
+            let config = dir.path().join("flacman.conf");
+            let sources = format!("[[sources]]\nname = \"shelf\"\nkind = \"mirror\"\nurl = \"{}\"\n", serve_dir(shelf));
+            std::fs::write(&config, format!("library = {:?}\ninboxes = [{:?}]\n\n{}", library, inbox, sources)).unwrap();
+            // SAFETY: the tests only read the environment through std::env,
+            // which serializes access to it
+            unsafe {
+                std::env::set_var("FLACMAN_CONFIG", &config);
+                for (var, name) in [("XDG_CONFIG_HOME", "config"), ("XDG_DATA_HOME", "data"), ("XDG_CACHE_HOME", "cache")] {
+                    std::env::set_var(var, dir.path().join(name));
+                }
+            }
+            dir
+        });
+        LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The library of [`scratch_env`]
+    pub(crate) fn scratch_library() -> PathBuf {
+        PathBuf::from(default_library().unwrap())
+    }
+
+    /// Publish [`SHELF`] under `root` as `--publish` would
+    fn write_shelf(root: &Path) {
+        let mut index = Vec::new();
+        for (path, artist, album, format) in SHELF {
+            let dir = root.join(path);
+            let files = write_album(&dir, artist, album, format);
+            let manifest: String = files
+                .iter()
+                .map(|f| format!("{}  {}\n", sha256_file(f).unwrap(), f.file_name().unwrap().to_string_lossy()))
+                .collect();
+            std::fs::write(dir.join(MANIFEST_NAME), manifest).unwrap();
+            index.push(serde_json::json!({"artist": artist, "title": album, "path": path}));
+        }
+        let index = serde_json::json!({"version": 1, "albums": index});
+        std::fs::write(root.join("index.json"), index.to_string()).unwrap();
+    }
+
+    /// Serve the files under `root` over HTTP; returns its URL
+    fn serve_dir(root: PathBuf) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().filter_map(|s| s.ok()) {
+                let mut reader = BufReader::new(&stream);
+                let mut request = String::new();
+                let mut line = String::new();
+                reader.read_line(&mut request).unwrap();
+                while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                    line.clear();
+                }
+                let path = request.split(' ').nth(1).unwrap_or("/").trim_start_matches('/');
+                let response = match std::fs::read(root.join(path)) {
+                    Ok(body) => [format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", body.len()).into_bytes(), body],
+                    Err(_) => [b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n".to_vec(), Vec::new()],
+                };
+                let _ = stream.write_all(&[&response[0][..], b"Connection: close\r\n\r\n", &response[1]].concat());
+            }
+        });
+        url
+    }
+
+    /// Write a two-track album tagged `artist` and `album` into `dir`, as
+    /// `flac` or 128 kbps `mp3` files with no audio in them
+    pub(crate) fn write_album(dir: &Path, artist: &str, album: &str, format: &str) -> Vec<PathBuf> {
+        std::fs::create_dir_all(dir).unwrap();
+        ["One", "Two"]
+            .iter()
+            .enumerate()
+            .map(|(i, title)| {
+                let number = (i + 1).to_string();
+                let tags = [("artist", artist), ("albumartist", artist), ("album", album), ("title", title), ("tracknumber", &number)];
+                let path = dir.join(format!("0{}.{}", number, format));
+                std::fs::write(&path, if format == "mp3" { mp3(&tags) } else { flac(&tags) }).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    /// A FLAC stream of a minute of 44.1 kHz, 16-bit stereo with `tags`
+    /// as its Vorbis comments, and no frames
+    fn flac(tags: &[(&str, &str)]) -> Vec<u8> {
+        let samples: u64 = 44_100 * 60;
+        let packed: u64 = (44_100u64 << 44) | (1 << 41) | (15 << 36) | samples;
+        let mut data = b"fLaC".to_vec();
+        data.extend_from_slice(&[0, 0, 0, 34]);
+        data.extend_from_slice(&4096u16.to_be_bytes());
+        data.extend_from_slice(&4096u16.to_be_bytes());
+        data.extend_from_slice(&[0; 6]);
+        data.extend_from_slice(&packed.to_be_bytes());
+        data.extend_from_slice(&[0; 16]);
+
+        let mut comments = 7u32.to_le_bytes().to_vec();
+        comments.extend_from_slice(b"flacman");
+        comments.extend_from_slice(&(tags.len() as u32).to_le_bytes());
+        for (key, value) in tags {
+            let text = format!("{}={}", key.to_uppercase(), value);
+            comments.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            comments.extend_from_slice(text.as_bytes());
+        }
+        data.push(0x84);
+        data.extend_from_slice(&(comments.len() as u32).to_be_bytes()[1..]);
+        data.extend_from_slice(&comments);
+        data
+    }
+
+    /// MPEG-1 Layer III frames at 128 kbps, 44.1 kHz behind an ID3v2.3 tag
+    /// of `tags`
+    fn mp3(tags: &[(&str, &str)]) -> Vec<u8> {
+        let mut frames = Vec::new();
+        for (key, value) in tags {
+            let id = match *key {
+                "artist" => "TPE1",
+                "albumartist" => "TPE2",
+                "album" => "TALB",
+                "title" => "TIT2",
+                _ => "TRCK",
+            };
+            frames.extend_from_slice(id.as_bytes());
+            frames.extend_from_slice(&(value.len() as u32 + 1).to_be_bytes());
+            frames.extend_from_slice(&[0, 0, 0]);
+            frames.extend_from_slice(value.as_bytes());
+        }
+        let size = frames.len() as u32;
+        let mut data = b"ID3\x03\x00\x00".to_vec();
+        data.extend([21, 14, 7, 0].map(|shift| (size >> shift) as u8 & 0x7f));
+        data.extend_from_slice(&frames);
+        for _ in 0..40 {
+            data.extend_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+            data.extend_from_slice(&[0; 413]);
+        }
+        data
+    }
+
+    pub(crate) fn parse(args: &[&str]) -> Result<ArgMatches, clap::Error> {
+        build_cli().try_get_matches_from(std::iter::once(&"flacman").chain(args))
+    }
+
+    /// Matches of the operation `args` run
+    fn operation(args: &[&str]) -> ArgMatches {
+        let matches = parse(args).unwrap();
+        matches.subcommand().map(|(_, operation)| operation.clone()).unwrap()
+    }
+
+    /// Run flacman with `args` on its command line
+    pub(crate) fn run(args: &[&str]) {
+        handle_matches(&parse(args).unwrap());
+    }
+
+    /// Files and their sizes
+    pub(crate) type Files = Vec<(PathBuf, u64)>;
+
+    /// Every file under `dir`, and its size
+    pub(crate) fn files_under(dir: &Path) -> Files {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir).into_iter().flatten().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => files.extend(files_under(&path)),
+                Ok(metadata) => files.push((path, metadata.len())),
+                Err(_) => {}
+            }
+        }
+        files.sort();
+        files
+    }
+
+    /// What a dry run must leave as it is: the library's files (its trash
+    /// among them), the database's tracks, the transaction log and the
+    /// downloads
+    fn written_state() -> (Files, Vec<PathBuf>, usize, Files) {
+        let tracks = library_db().tracks().unwrap().into_iter().map(|t| t.path).collect();
+        let transactions = tx_log().read_all().unwrap().len();
+        (files_under(&scratch_library()), tracks, transactions, files_under(&downloads_dir()))
+    }
+
+    #[test]
+    fn test_move_root_args() {
+        let matches = operation(&["move-root", "nas", "Low/Trust", "Low/Things We Lost"]);
+        assert_eq!(matches.get_one::<String>("root").unwrap(), "nas");
+        assert_eq!(matches.get_many::<String>("targets").unwrap().collect::<Vec<_>>(), ["Low/Trust", "Low/Things We Lost"]);
+
+        assert!(parse(&["move-root", "nas"]).is_err());
+        assert!(parse(&["move-root"]).is_err());
+    }
+
+    #[test]
+    fn test_trash_args() {
+        for name in ["list", "empty"] {
+            assert_eq!(operation(&["trash", name]).subcommand_name(), Some(name));
+        }
+        let restore = operation(&["trash", "restore", "Low/Trust"]);
+        let (_, restore) = restore.subcommand().unwrap();
+        assert_eq!(restore.get_one::<String>("pattern").unwrap(), "Low/Trust");
+
+        assert!(parse(&["trash"]).is_err());
+        assert!(parse(&["trash", "restore"]).is_err());
+    }
+
+    #[test]
+    fn test_remove_scopes() {
+        let matches = operation(&["-Rsc", "Low"]);
+        assert!(matches.get_flag("recursive") && matches.get_flag("cascade") && !matches.get_flag("nosave"));
+        let matches = operation(&["rm", "--recursive", "--cascade", "--nosave", "Low"]);
+        assert!(matches.get_flag("recursive") && matches.get_flag("cascade") && matches.get_flag("nosave"));
+
+        // -n is --nosave after -R, as in pacman, and --dry-run before it
+        let matches = operation(&["-Rn", "Low"]);
+        assert!(matches.get_flag("nosave") && !matches.get_flag("dry-run"));
+        let matches = operation(&["-n", "-R", "Low"]);
+        assert!(!matches.get_flag("nosave") && matches.get_flag("dry-run"));
+        assert!(operation(&["-R", "--dry-run", "Low"]).get_flag("dry-run"));
+
+        assert!(parse(&["-R", "--copy", "Low"]).is_err());
+        assert!(parse(&["-R", "--search", "Low"]).is_err());
+    }
+
+    #[test]
+    fn test_query_reconcile_args() {
+        let matches = operation(&["-Q", "--orphans", "--adopt"]);
+        assert!(matches.get_flag("orphans") && matches.get_flag("adopt"));
+        let matches = operation(&["query", "--missing", "--prune", "/srv/music"]);
+        assert!(matches.get_flag("missing") && matches.get_flag("prune"));
+
+        assert!(parse(&["-Q", "--adopt"]).is_err());
+        assert!(parse(&["-Q", "--prune"]).is_err());
+        assert!(parse(&["-Q", "--orphans", "--missing"]).is_err());
+    }
+
+    #[test]
+    fn test_format_string_args() {
+        let matches = operation(&["-Ql", "--format-string", "%artist% | %album% | %path%"]);
+        let album = flacman_tag::Album { artist: "Low".into(), title: "Trust".into(), discs: BTreeMap::new() };
+        assert_eq!(matches.get_one::<Template>("format-string").unwrap().render(&album), "Low | Trust | ");
+
+        assert!(parse(&["-Q", "--format-string", "%artist"]).is_err());
+        assert!(parse(&["-R", "--format-string", "%artist%", "Low"]).is_err());
+    }
+
+    #[test]
+    fn test_sysupgrade_conflicts() {
+        assert!(operation(&["-Su"]).get_flag("sysupgrade"));
+        assert!(operation(&["-Su", "--source", "nas", "Low"]).get_flag("sysupgrade"));
+
+        for args in [&["-Sus", "low"][..], &["-Sui", "low"], &["-Su", "--needed"], &["-Su", "--album", "Trust"]] {
+            assert!(parse(args).is_err(), "{:?} should be refused", args);
+        }
+    }
+
+    /// Run `flacman trash ARGS` against `trash`
+    fn run_trash(trash: &Trash, args: &[&str]) {
+        let matches = build_cli().get_matches_from(["flacman", "trash", "--noconfirm"].iter().chain(args));
+        manage_trash(matches.subcommand_matches("trash").unwrap(), trash, confirm_policy(&matches));
+    }
+
+    #[test]
+    fn test_manage_trash() {
+        let _env = scratch_env();
+        let dir = tempdir().unwrap();
+        let album = dir.path().join("library/Low/Trust");
+        std::fs::create_dir_all(&album).unwrap();
+        std::fs::write(album.join("01.flac"), b"").unwrap();
+        let trash = Trash::new(dir.path().join("trash")).with_transaction_ids(|| Some(7));
+
+        trash.remove(&[&album]).unwrap();
+        run_trash(&trash, &["list"]);
+        let entries = trash.list().unwrap();
+        assert_eq!((entries.len(), entries[0].transaction), (1, Some(7)));
+        assert!(!album.exists());
+
+        run_trash(&trash, &["restore", "Low/Trust"]);
+        assert!(album.join("01.flac").exists());
+        assert!(trash.list().unwrap().is_empty());
+
+        trash.remove(&[&album]).unwrap();
+        run_trash(&trash, &["empty"]);
+        assert!(trash.list().unwrap().is_empty());
+        assert!(!album.exists());
+        assert_eq!(std::fs::read_dir(trash.root()).unwrap().count(), 1, "only the trash's ignore file is left");
+    }
+
+    #[test]
+    fn test_dry_runs_write_nothing() {
+        let _env = scratch_env();
+        let library = scratch_library();
+        let album = library.join("Dry Band/Kept");
+        index_paths(&write_album(&album, "Dry Band", "Kept", "mp3"));
+        let gone = library.join("Dry Band/Gone");
+        index_paths(&write_album(&gone, "Dry Band", "Gone", "flac"));
+        run(&["-R", "--noconfirm", gone.to_str().unwrap()]);
+        let removal = tx_log().read_all().unwrap().last().unwrap().id.to_string();
+        let inbox = tempdir().unwrap();
+        let incoming = inbox.path().join("Dry Band - New");
+        write_album(&incoming, "Dry Band", "New", "flac");
+
+        let before = written_state();
+        let album = album.to_str().unwrap();
+        let runs: [&[&str]; 6] = [
+            &["-n", "-Sa", "--noconfirm", "Shelf Band - Lossless"],
+            &["-n", "-Su", "--noconfirm", album],
+            &["-n", "-U", "--copy", "--noconfirm", incoming.to_str().unwrap()],
+            &["-n", "-R", "--noconfirm", album],
+            &["-R", "--dry-run", "--no-trash", "--noconfirm", album],
+            &["-n", "--rollback", &removal, "--noconfirm"],
+        ];
+        for args in runs {
+            run(args);
+            assert_eq!(written_state(), before, "flacman {} wrote something", args.join(" "));
+        }
+        assert!(incoming.join("01.flac").exists());
+    }
+
+    #[test]
+    fn test_json_output() {
+        let _env = scratch_env();
+        assert!(parse(&["--json", "-Ql"]).unwrap().get_flag("json"));
+        assert!(parse(&["-Ss", "--json", "low"]).unwrap().get_flag("json"));
+
+        let dir = tempdir().unwrap();
+        let files = write_album(&dir.path().join("Low - Trust"), "Low", "Trust", "flac");
+        let album = read_albums(&[&dir.path().display().to_string()]).remove(0);
+        let json = serde_json::to_value(AlbumJson::new(&album, vec!["missing disc 2".to_owned()])).unwrap();
+        assert_eq!((json["artist"].as_str(), json["title"].as_str(), json["discs"].as_u64()), (Some("Low"), Some("Trust"), Some(1)));
+        assert_eq!(json["tracks"][1]["path"].as_str(), files[1].to_str());
+        assert_eq!((json["tracks"][1]["track"].as_u64(), json["tracks"][1]["title"].as_str()), (Some(2), Some("Two")));
+        assert_eq!(json["tracks"][0]["duration"].as_f64(), Some(60.0));
+        assert_eq!(json["warnings"][0].as_str(), Some("missing disc 2"));
+
+        // Remote results carry their source and whether the cache answered
+        let mut results = Vec::new();
+        source_search(&remote_source("shelf", 0).unwrap(), &[SearchKind::Album], "Shelf Band", 0, false, Some(&mut results));
+        assert_eq!(results.len(), 1);
+        assert_eq!((results[0]["source"].as_str(), results[0]["title"].as_str()), (Some("shelf"), Some("Lossless")));
+        assert!(results[0]["cached"].is_boolean());
+    }
+}
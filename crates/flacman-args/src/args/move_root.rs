@@ -0,0 +1,124 @@
+use super::*;
+
+
+/// Move every album that sits on the wrong volume to the one its rules pick
+pub fn rebalance(verbose: bool, confirm: Confirm) {
+    let volumes = volume_set();
+    if volumes.volumes.is_empty() {
+        eprintln!("Error: No volumes configured; add [[roots]] to flacman.conf or use --add-volume");
+        process::exit(1);
+    }
+
+    let roots: Vec<String> =
+        volumes.roots().iter().filter(|r| r.is_dir()).map(|r| r.display().to_string()).collect();
+    let albums = read_albums(&roots.iter().collect::<Vec<_>>());
+    if verbose {
+        println!("Checking placement of {} albums on {} volumes...", albums.len(), roots.len());
+    }
+
+    let plan = volumes.plan_rebalance(&albums);
+    if plan.is_empty() {
+        println!("Every album is on the right volume");
+        return;
+    }
+
+    relocate_albums("rebalance", &plan, &albums, confirm);
+}
+
+/// `flacman move-root`: move the albums under `targets` to the library
+/// root (volume) named `root`, keeping their paths below it
+pub fn move_to_root(root: &str, targets: &[&String], verbose: bool, confirm: Confirm) {
+    let volumes = volume_set();
+    if !volumes.volumes.iter().any(|v| v.name == root) {
+        let names: Vec<&str> = volumes.volumes.iter().map(|v| v.name.as_str()).collect();
+        eprintln!("Error: No root named {} (known: {:?})", root, names);
+        process::exit(1);
+    }
+
+    let albums = read_albums(targets);
+    let outside: Vec<&Album> = albums
+        .iter()
+        .filter(|a| flacman_tag::album_dir(a).is_none_or(|dir| volumes.volume_of(&dir).is_none()))
+        .collect();
+    for album in &outside {
+        eprintln!("Warning: {} - {} is on none of the roots; skipping", album.artist, album.title);
+    }
+
+    let plan = volumes.plan_move(&albums, root).unwrap_or_default();
+    if verbose {
+        println!("{} of {} albums to move to {}", plan.len(), albums.len(), root);
+    }
+    if plan.is_empty() {
+        println!("Nothing to move");
+        return;
+    }
+
+    relocate_albums("move-root", &plan, &albums, confirm);
+}
+
+/// Move each album of `plan` to its new volume and follow it in the
+/// database, provenance and notes, logging each as an `operation`
+/// transaction; exits unsuccessfully if any failed
+///
+/// Each album moves as a whole: it is renamed when both volumes share a
+/// filesystem, and otherwise copied, verified and only then removed from
+/// the old volume, so an interrupted move never leaves half an album.
+fn relocate_albums(operation: &str, plan: &[Relocation], albums: &[Album], confirm: Confirm) {
+    for relocation in plan {
+        println!("{} -> {} ({})", relocation.album, relocation.volume, relocation.to.display());
+    }
+    confirm_or_exit(confirm, &format!("Move {} album(s)?", plan.len()));
+
+    let resume = format!("run {} again to move the rest", if operation == "rebalance" { "--rebalance" } else { operation });
+    let cancel = cancel_flag();
+    let (mut moved, mut failed, mut changed) = (0, 0, Vec::new());
+    for relocation in plan {
+        if cancel.load(Ordering::Relaxed) {
+            exit_cancelled(moved, plan.len(), &resume);
+        }
+        let target = format!("{} -> {}", relocation.from.display(), relocation.to.display());
+        let files = albums
+            .iter()
+            .find(|a| flacman_tag::album_dir(a).as_deref() == Some(relocation.from.as_path()))
+            .map_or(0, |a| a.tracks().count() as u64);
+
+        match flacman_fs::move_dir(&relocation.from, &relocation.to, cancel) {
+            Ok(bytes) => {
+                if let Err(e) = provenance_store().relocate(&relocation.from, &relocation.to) {
+                    eprintln!("Warning: could not update provenance: {}", e);
+                }
+                if let Err(e) = note_store().relocate(&relocation.from, &relocation.to) {
+                    eprintln!("Warning: could not move notes: {}", e);
+                }
+                if let Err(e) = library_db().remove_all_under(std::slice::from_ref(&relocation.from)) {
+                    eprintln!("Warning: could not update the library database: {}", e);
+                }
+                index_paths(std::slice::from_ref(&relocation.to));
+                let mut record = TxRecord::new(operation, vec![target], TxOutcome::Success);
+                record.files = files;
+                record.bytes = bytes;
+                log_transaction(record);
+                changed.extend([relocation.from.clone(), relocation.to.clone()]);
+                moved += 1;
+            }
+            Err(flacman_fs::FsError::Cancelled) => {
+                // The half-copied album was removed and the original left in place
+                log_transaction(TxRecord::new(operation, vec![target], TxOutcome::Cancelled));
+                exit_cancelled(moved, plan.len(), &resume);
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {}", relocation.album, e);
+                let mut record = TxRecord::new(operation, vec![target], TxOutcome::Failed);
+                record.messages.push(e.to_string());
+                log_transaction(record);
+                failed += 1;
+            }
+        }
+    }
+
+    update_mpd(&changed);
+    println!("Moved {} album(s)", moved);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,180 @@
+use super::*;
+
+
+/// List the audio files under `roots` that the library database doesn't
+/// know, like `pacman -Qm` does foreign packages; with `adopt`, index them
+pub(super) fn list_orphans(roots: &[&String], adopt: bool, dry_run: bool, json: bool) {
+    if roots.is_empty() {
+        eprintln!("Error: No library directories specified");
+        process::exit(1);
+    }
+    let db = library_db();
+    let indexed = db.file_states().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+
+    let mut orphans = Vec::new();
+    for root in roots {
+        let root = std::path::absolute(root.as_str()).unwrap_or_else(|_| PathBuf::from(root.as_str()));
+        match flacman_fs::find_audio_files_par(&root) {
+            Ok(files) => orphans.extend(files.into_iter().filter(|f| !indexed.contains_key(f))),
+            Err(e) => {
+                eprintln!("Error: {}: {}", root.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+    orphans.sort();
+    orphans.dedup();
+    collation().sort_by(&mut orphans, |p| p.to_str().unwrap_or_default());
+
+    if json {
+        print_json(&orphans);
+    } else {
+        for path in &orphans {
+            println!("{}", path.display());
+        }
+        println!("{} file(s) not in the library database", orphans.len());
+    }
+    if !adopt || orphans.is_empty() {
+        return;
+    }
+    if dry_run {
+        println!("Would add {} file(s) to the library database", orphans.len());
+        return;
+    }
+    drop(db);
+    index_paths(&orphans);
+    if !json {
+        println!("Added {} file(s) to the library database", orphans.len());
+    }
+}
+
+/// List the tracks of the library database under `targets` (anywhere,
+/// with none) whose files are gone; with `prune`, forget them
+///
+/// Tracks under a root that is not there, such as an unmounted volume,
+/// are only warned about.
+pub(super) fn list_missing(targets: &[&String], prune: bool, dry_run: bool, json: bool) {
+    let mut db = library_db();
+    let tracks = if targets.is_empty() {
+        db.tracks()
+    } else {
+        let paths = targets.iter().map(|t| std::path::absolute(t.as_str()).unwrap_or_else(|_| PathBuf::from(t.as_str())));
+        paths.map(|path| db.tracks_under(&path)).collect::<Result<Vec<_>, _>>().map(|found| found.concat())
+    };
+    let mut missing: Vec<TrackRecord> = tracks
+        .unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        })
+        .into_iter()
+        .filter(|t| !t.path.exists())
+        .collect();
+    missing.sort_by(|a, b| a.path.cmp(&b.path));
+    missing.dedup_by(|a, b| a.path == b.path);
+
+    // A track on a volume that is not mounted is not gone, so neither
+    // list it nor let --prune drop it
+    let volumes = volume_set();
+    let library = default_library().map(Path::new);
+    let mut offline: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    missing.retain(|track| {
+        let root = volumes.volume_of(&track.path).map(|v| v.root.as_path());
+        let root = root.or_else(|| library.filter(|lib| track.path.starts_with(lib)));
+        match root {
+            Some(root) if !root.is_dir() => {
+                *offline.entry(root.to_path_buf()).or_default() += 1;
+                false
+            }
+            _ => true,
+        }
+    });
+    for (root, count) in &offline {
+        eprintln!("Warning: {} is not mounted; skipping {} track(s) on it", root.display(), count);
+    }
+    collation().sort_by(&mut missing, |t| t.path.to_str().unwrap_or_default());
+
+    if json {
+        print_json(&missing);
+    } else {
+        for track in &missing {
+            println!("{} ({} - {})", track.path.display(), track.album_artist, track.album);
+        }
+        println!("{} track(s) whose files are gone", missing.len());
+    }
+    if !prune || missing.is_empty() {
+        return;
+    }
+    if dry_run {
+        println!("Would remove {} track(s) from the library database", missing.len());
+        return;
+    }
+    let paths: Vec<&Path> = missing.iter().map(|t| t.path.as_path()).collect();
+    match db.remove_all_under(&paths) {
+        Ok(removed) if !json => println!("Removed {} track(s) from the library database", removed),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::tests::{scratch_env, write_album};
+    use tempfile::tempdir;
+
+    /// Paths of the database's tracks under `root`
+    fn indexed_under(root: &Path) -> Vec<PathBuf> {
+        library_db().tracks_under(root).unwrap().into_iter().map(|t| t.path).collect()
+    }
+
+    #[test]
+    fn test_adopt_orphans() {
+        let _env = scratch_env();
+        let root = tempdir().unwrap();
+        let files = write_album(&root.path().join("Orphan Band/Found"), "Orphan Band", "Found", "flac");
+        index_paths(&files[..1]);
+        let root_name = root.path().display().to_string();
+
+        list_orphans(&[&root_name], true, true, false);
+        assert_eq!(indexed_under(root.path()), files[..1]);
+        list_orphans(&[&root_name], true, false, false);
+        assert_eq!(indexed_under(root.path()), files);
+    }
+
+    #[test]
+    fn test_prune_missing() {
+        let _env = scratch_env();
+        let root = tempdir().unwrap();
+        let files = write_album(&root.path().join("Missing Band/Lost"), "Missing Band", "Lost", "flac");
+        index_paths(&files);
+        std::fs::remove_file(&files[1]).unwrap();
+        let root_name = root.path().display().to_string();
+
+        list_missing(&[&root_name], true, true, false);
+        assert_eq!(indexed_under(root.path()), files);
+        list_missing(&[&root_name], true, false, false);
+        assert_eq!(indexed_under(root.path()), files[..1]);
+    }
+
+    #[test]
+    fn test_prune_keeps_unmounted_root() {
+        let _env = scratch_env();
+        let root = tempdir().unwrap();
+        let volume = root.path().join("offline");
+        let files = write_album(&volume.join("Offline Band/Away"), "Offline Band", "Away", "flac");
+        index_paths(&files);
+        add_volume("offline", &volume.display().to_string(), "artist=Offline Band");
+        std::fs::remove_dir_all(&volume).unwrap();
+
+        list_missing(&[&volume.display().to_string()], true, false, false);
+        // Other tests share the data directory and its volumes
+        let _ = std::fs::remove_file(volumes_path());
+        assert_eq!(indexed_under(&volume), files);
+    }
+}
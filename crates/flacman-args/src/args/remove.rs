@@ -0,0 +1,359 @@
+use super::*;
+
+
+pub fn handle_remove(matches: &ArgMatches, targets: &[&String], verbose: bool, confirm: Confirm) {
+    if verbose {
+        println!("Operation: Remove");
+    }
+
+    if targets.is_empty() {
+        eprintln!("Error: No targets specified");
+        process::exit(1);
+    }
+
+    // Artist directories and wildcard patterns stand for several albums,
+    // which the user picks from before anything is removed
+    let (patterns, paths): (Vec<&String>, Vec<&String>) = targets.iter().partition(|t| t.contains(['*', '?']));
+    let mut resolved = Vec::new();
+    let mut albums = Vec::new();
+    // A target that isn't a path may name albums or tracks in the library
+    let (on_disk, named): (Vec<&String>, Vec<&String>) =
+        paths.into_iter().partition(|t| Path::new(t.as_str()).exists());
+    let mut unknown = Vec::new();
+    for target in named {
+        match library_matches(target).as_slice() {
+            [] => unknown.push(target),
+            [only] => {
+                eprintln!("Resolved '{}' to '{}'", target, only);
+                resolved.push(only.clone());
+            }
+            matches => albums.extend_from_slice(matches),
+        }
+    }
+    let scope = RemovalScope {
+        recursive: matches.get_flag("recursive"),
+        cascade: matches.get_flag("cascade"),
+        nosave: matches.get_flag("nosave"),
+    };
+    for target in resolve_targets(&on_disk).into_iter().chain(resolve_targets(&unknown)) {
+        match removal_candidates(&target) {
+            // -Rc takes the artist as a whole
+            Some(dirs) if !scope.cascade => albums.extend(dirs),
+            _ => resolved.push(target),
+        }
+    }
+    for pattern in patterns {
+        let dirs = removal_candidates(pattern).unwrap_or_default();
+        if dirs.is_empty() {
+            eprintln!("Error: No album matches: {}", pattern);
+            process::exit(1);
+        }
+        albums.extend(dirs);
+    }
+    albums.sort();
+    albums.dedup();
+    if !albums.is_empty() {
+        resolved.extend(choose_removals(albums, confirm));
+    }
+    let resolved: Vec<PathBuf> =
+        resolved.iter().map(|t| std::fs::canonicalize(t).unwrap_or_else(|_| PathBuf::from(t))).collect();
+    let plan = match removal_graph(&resolved).plan(&resolved, scope) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("Use -Rc to remove all of its albums");
+            process::exit(1);
+        }
+    };
+    let resolved: Vec<String> = plan.remove.iter().map(|p| p.display().to_string()).collect();
+    let targets: Vec<&String> = resolved.iter().collect();
+    let delete = matches.get_flag("no-trash");
+
+    println!("Removing from library: {:?}", targets);
+    for sidecar in &plan.saved {
+        println!("Keeping: {} (use -Rn to remove it too)", sidecar.display());
+    }
+
+    if matches.get_flag("dry-run") {
+        for target in &targets {
+            println!("{}: {}", if delete { "Would delete" } else { "Would move to trash" }, target);
+            let mut files: Vec<PathBuf> = flacman_fs::walkdir(target.as_str())
+                .map(|walk| walk.filter_map(|f| f.ok()).filter(|f| f.is_file()).collect())
+                .unwrap_or_default();
+            files.sort();
+            for file in files {
+                println!("    {}", file.display());
+            }
+        }
+        return;
+    }
+
+    confirm_or_exit(confirm, if delete { "Delete for good? This can't be undone" } else { "Proceed with removal?" });
+    if !run_hooks(HookWhen::PreTransaction, HookOperation::Remove, &plan.remove) {
+        process::exit(1);
+    }
+
+    let mut record = TxRecord::new("remove", Vec::new(), TxOutcome::Success);
+    for target in &targets {
+        let (files, bytes) = path_stats(Path::new(target.as_str()));
+        record.files += files;
+        record.bytes += bytes;
+    }
+
+    let trash = trash();
+    let removed: Vec<PathBuf> = if delete {
+        let mut removed = Vec::new();
+        for target in &targets {
+            let path = std::fs::canonicalize(target.as_str()).unwrap_or_else(|_| PathBuf::from(target.as_str()));
+            match flacman_fs::remove_tree(&path) {
+                Ok(_) => {
+                    println!("Deleted: {}", path.display());
+                    record.changes.push(FileChange::Deleted { path: path.clone() });
+                    removed.push(path);
+                }
+                Err(e) => {
+                    eprintln!("Error: {}: {}", target, e);
+                    record.messages.push(format!("{}: {}", target, e));
+                }
+            }
+        }
+        removed
+    } else {
+        match trash.remove(&targets) {
+            Ok(entries) => entries
+                .into_iter()
+                .map(|entry| {
+                    println!("Moved to trash: {}", entry.original.display());
+                    record.changes.push(FileChange::Trashed { original: entry.original.clone(), stored: entry.stored });
+                    entry.original
+                })
+                .collect(),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    };
+    record.targets = removed.iter().map(|p| p.display().to_string()).collect();
+
+    // The index forgets everything removed at once, or nothing
+    if let Err(e) = library_db().remove_all_under(&removed) {
+        eprintln!("Warning: could not update the library database: {}", e);
+    }
+    if let Some(library) = default_library().and_then(|l| std::fs::canonicalize(l).ok()) {
+        for path in &removed {
+            let Some(parent) = path.parent() else { continue };
+            match flacman_fs::prune_empty_dirs(parent, &library) {
+                Ok(dirs) if verbose => dirs.iter().for_each(|d| println!("Removed empty directory: {}", d.display())),
+                Ok(_) => {}
+                Err(e) => eprintln!("Warning: could not remove empty directory: {}", e),
+            }
+        }
+    }
+
+    let failed = record.messages.len();
+    if failed > 0 {
+        record.outcome = if removed.is_empty() { TxOutcome::Failed } else { TxOutcome::Partial };
+    }
+    let id = log_transaction(record);
+    update_mpd(&removed);
+    run_hooks(HookWhen::PostTransaction, HookOperation::Remove, &removed);
+    if !delete {
+        let days = config().trash.retention_days;
+        match id {
+            Some(id) => println!("Removed files are kept for {} days; undo with: flacman --rollback {}", days, id),
+            None => println!("Removed files are kept for {} days; restore with: flacman trash restore <album>", days),
+        }
+    }
+
+    purge_expired_trash(&trash, verbose);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// The artists, albums, tracks and sidecars around the `-R` targets
+///
+/// Targets (canonical paths) in the library bring in their whole artist
+/// directory, so `-Rs` and `-Rc` know everything they may take along.
+/// Targets elsewhere only bring in themselves, with the directory above
+/// as root.
+fn removal_graph(targets: &[PathBuf]) -> LibraryGraph {
+    let library = default_library().and_then(|l| std::fs::canonicalize(l).ok());
+    let audio_exts = flacman_fs::audio_exts();
+    let is_track = |path: &Path| {
+        path.extension().is_some_and(|ext| audio_exts.iter().any(|e| ext.eq_ignore_ascii_case(e.as_str())))
+    };
+
+    let mut graph = LibraryGraph::new();
+    let mut walked = HashSet::new();
+    for path in targets {
+        let (root, dir) = match library.as_ref().and_then(|l| Some((l, path.strip_prefix(l).ok()?))) {
+            Some((library, relative)) => {
+                let artist = relative.components().next().map(|c| library.join(c)).unwrap_or_default();
+                (library.clone(), artist)
+            }
+            None if path.is_dir() => (path.parent().map(Path::to_path_buf).unwrap_or_default(), path.to_path_buf()),
+            None => {
+                let album = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                (album.parent().map(Path::to_path_buf).unwrap_or_default(), album)
+            }
+        };
+        if !dir.is_dir() || !walked.insert(dir.clone()) {
+            continue;
+        }
+        let files = flacman_fs::walkdir(&dir).map(|walk| walk.filter_map(|f| f.ok()).filter(|f| f.is_file()));
+        let files = files.into_iter().flatten().map(|f| {
+            let track = is_track(&f);
+            (f, track)
+        });
+        graph.add_files(&root, files);
+    }
+    graph
+}
+
+/// What a `-R` target that isn't a path names in the library index: the
+/// directory of each album whose every track matches it, and the matching
+/// tracks of other albums
+///
+/// Only files still under the configured library count, so stale entries
+/// of a former library are never removed by name.
+fn library_matches(term: &str) -> Vec<String> {
+    let Some(library) = default_library().and_then(|l| std::fs::canonicalize(l).ok()) else {
+        return Vec::new();
+    };
+    let db = library_db();
+    let found = match db.search(term) {
+        Ok(found) => found,
+        Err(e) => {
+            eprintln!("Warning: could not search the library database: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut by_dir: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for track in found.into_iter().filter(|t| t.path.starts_with(&library) && t.path.exists()) {
+        let dir = track.path.parent().map(Path::to_path_buf).unwrap_or_default();
+        by_dir.entry(dir).or_default().push(track.path);
+    }
+
+    let mut matches = Vec::new();
+    for (dir, tracks) in by_dir {
+        let whole_album = db.tracks_under(&dir).is_ok_and(|all| all.len() == tracks.len());
+        if whole_album {
+            matches.push(dir.display().to_string());
+        } else {
+            matches.extend(tracks.iter().map(|t| t.display().to_string()));
+        }
+    }
+    matches
+}
+
+/// The albums an artist directory or wildcard pattern given to `-R`
+/// stands for; `None` for a target that is removed as it is
+///
+/// Patterns are matched against album directories below their last plain
+/// directory, or below the library root, by relative path or by name.
+fn removal_candidates(target: &str) -> Option<Vec<String>> {
+    let is_pattern = |part: &str| part.contains(['*', '?']);
+    if !is_pattern(target) {
+        let dirs = album_dirs(&[&target.to_owned()]);
+        return (Path::new(target).is_dir() && dirs.len() > 1).then_some(dirs);
+    }
+
+    let path = Path::new(target);
+    let base: PathBuf = path.components().take_while(|c| !is_pattern(&c.as_os_str().to_string_lossy())).collect();
+    let base = match default_library() {
+        Some(library) if base.as_os_str().is_empty() => PathBuf::from(library),
+        _ if base.as_os_str().is_empty() => PathBuf::from("."),
+        _ => base,
+    };
+    if !base.is_dir() {
+        return Some(Vec::new());
+    }
+    let pattern = path.strip_prefix(&base).unwrap_or(path).to_string_lossy().into_owned();
+
+    let dirs = album_dirs(&[&base.display().to_string()]);
+    let matching = dirs.into_iter().filter(|dir| {
+        let relative = Path::new(dir).strip_prefix(&base).unwrap_or(Path::new(dir));
+        wildcard_match(&pattern, &relative.to_string_lossy())
+            || relative.file_name().is_some_and(|name| wildcard_match(&pattern, &name.to_string_lossy()))
+    });
+    Some(matching.collect())
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any run of
+/// characters and `?` for any one; case-insensitive
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, start)) = backtrack {
+            backtrack = Some((star, start + 1));
+            p = star + 1;
+            t = start + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Let the user pick which of `albums` to remove, with the space each
+/// frees and the running total
+///
+/// Everything is kept selected when there is no one to ask.
+fn choose_removals(albums: Vec<String>, confirm: Confirm) -> Vec<String> {
+    if confirm.noconfirm || !std::io::stdin().is_terminal() {
+        return albums;
+    }
+
+    let mut list = Checklist::new(albums.into_iter().map(|album| {
+        let (_, bytes) = path_stats(Path::new(&album));
+        (album, bytes)
+    }));
+    loop {
+        for (i, item) in list.items.iter().enumerate() {
+            let mark = if item.selected { 'x' } else { ' ' };
+            println!("{:>3} [{}] {} ({})", i + 1, mark, item.label, format_size(item.size));
+        }
+        print!(
+            "{} of {} selected, {} to free; toggle numbers or ranges, [a]ll, [n]one, Enter to go on, [q]uit: ",
+            list.selected().count(),
+            list.items.len(),
+            format_size(list.total())
+        );
+        let _ = std::io::stdout().flush();
+
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).unwrap_or(0) == 0 {
+            answer = "q".to_owned();
+        }
+        match list.answer(&answer) {
+            Ok(ChecklistStep::Continue) => {}
+            Ok(ChecklistStep::Done) => break,
+            Ok(ChecklistStep::Quit) => {
+                println!("Aborted");
+                process::exit(1);
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    let selected: Vec<String> = list.selected().map(|item| item.label.clone()).collect();
+    if selected.is_empty() {
+        println!("Nothing selected");
+        process::exit(1);
+    }
+    selected
+}
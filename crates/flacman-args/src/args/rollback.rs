@@ -0,0 +1,196 @@
+use super::*;
+
+
+/// Undo the file changes of transaction `id`, last change first
+///
+/// Moved files are moved back and trashed ones restored. Files the
+/// transaction copied or created go to the trash rather than being
+/// deleted, so the rollback is itself a transaction that can be rolled
+/// back. Changes overtaken since (their file is gone, or something new is
+/// in the way) are skipped with a warning. A transaction is only rolled
+/// back once. A dry run prints what would be undone and stops.
+pub fn rollback(id: u64, dry_run: bool, confirm: Confirm) {
+    let log = tx_log();
+    let records = log.read_all().unwrap_or_else(|e| {
+        eprintln!("Error: cannot read transaction log: {}", e);
+        process::exit(1);
+    });
+    let Some(record) = records.iter().find(|r| r.id == id) else {
+        eprintln!("Error: No transaction #{} in {}", id, log.path().display());
+        process::exit(1);
+    };
+    if record.changes.is_empty() {
+        eprintln!("Error: Transaction #{} ({}) recorded no file changes to roll back", id, record.operation);
+        process::exit(1);
+    }
+    let marker = format!("#{}", id);
+    let earlier = records.iter().find(|r| {
+        r.operation == "rollback" && r.source.as_deref() == Some(marker.as_str()) && r.outcome != TxOutcome::Failed
+    });
+    if let Some(earlier) = earlier {
+        eprintln!("Error: Transaction #{} was already rolled back by #{}", id, earlier.id);
+        process::exit(1);
+    }
+
+    println!(
+        "Rolling back #{} {} from {} ({} change(s))",
+        id,
+        record.operation,
+        record.time.format("%Y-%m-%d %H:%M"),
+        record.changes.len()
+    );
+    if dry_run {
+        for change in record.changes.iter().rev() {
+            let (from, to) = match change {
+                FileChange::Deleted { path } => {
+                    println!("Would skip {}: deleted for good", path.display());
+                    continue;
+                }
+                FileChange::Copied { to: path, .. } | FileChange::Created { path } => (path, None),
+                FileChange::Moved { from, to } => (to, Some(from)),
+                FileChange::Trashed { original, stored } => (stored, Some(original)),
+            };
+            match to {
+                _ if from.symlink_metadata().is_err() => {
+                    println!("Would skip {}: no longer there", from.display())
+                }
+                Some(to) if to.symlink_metadata().is_ok() => {
+                    println!("Would skip {}: something new is in the way", to.display())
+                }
+                Some(to) => println!("Would move back: {}", to.display()),
+                None => println!("Would move to trash: {}", from.display()),
+            }
+        }
+        return;
+    }
+    confirm_or_exit(confirm, "Proceed with rollback?");
+
+    let mut undo = TxRecord::new("rollback", Vec::new(), TxOutcome::Success);
+    undo.source = Some(marker);
+    let mut discard = Vec::new();
+    let mut restored = Vec::new();
+    let mut vacated = Vec::new();
+    let mut skipped = 0;
+    let mut skip = |undo: &mut TxRecord, reason: String| {
+        eprintln!("Warning: skipped {}", reason);
+        undo.messages.push(reason);
+        skipped += 1;
+    };
+
+    for change in record.changes.iter().rev() {
+        let (from, to) = match change {
+            FileChange::Deleted { path } => {
+                skip(&mut undo, format!("{}: deleted for good", path.display()));
+                continue;
+            }
+            FileChange::Copied { to: path, .. } | FileChange::Created { path } => {
+                if path.symlink_metadata().is_ok() {
+                    discard.push(path.clone());
+                } else {
+                    skip(&mut undo, format!("{}: no longer there", path.display()));
+                }
+                continue;
+            }
+            FileChange::Moved { from, to } => (to, from),
+            FileChange::Trashed { original, stored } => (stored, original),
+        };
+
+        let levels = if from.is_dir() { 1 } else { 2 };
+        match move_back(from, to) {
+            Ok(()) => {
+                println!("Moved back: {}", to.display());
+                undo.targets.push(to.display().to_string());
+                undo.changes.push(FileChange::Moved { from: from.clone(), to: to.clone() });
+                vacated.push((from.clone(), levels));
+                if matches!(change, FileChange::Trashed { .. }) {
+                    restored.push(to.clone());
+                }
+            }
+            Err(reason) => skip(&mut undo, format!("{}: {}", to.display(), reason)),
+        }
+    }
+
+    if !discard.is_empty() {
+        match trash().remove(&discard) {
+            Ok(entries) => {
+                for entry in entries {
+                    println!("Moved to trash: {}", entry.original.display());
+                    undo.targets.push(entry.original.display().to_string());
+                    vacated.push((entry.original.clone(), 2));
+                    undo.changes.push(FileChange::Trashed { original: entry.original, stored: entry.stored });
+                }
+            }
+            Err(e) => {
+                for path in &discard {
+                    skip(&mut undo, format!("{}: {}", path.display(), e));
+                }
+            }
+        }
+    }
+
+    let mut db = library_db();
+    for (path, levels) in &vacated {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+        if let Err(e) = db.remove_under(&path) {
+            eprintln!("Warning: could not update the library database: {}", e);
+        }
+        remove_empty_parents(&path, *levels);
+    }
+    index_paths(&restored);
+
+    for target in &undo.targets {
+        let (files, bytes) = path_stats(Path::new(target));
+        undo.files += files;
+        undo.bytes += bytes;
+    }
+    undo.outcome = if skipped == 0 {
+        TxOutcome::Success
+    } else if undo.changes.is_empty() {
+        TxOutcome::Failed
+    } else {
+        TxOutcome::Partial
+    };
+    let failed = undo.outcome == TxOutcome::Failed;
+    log_transaction(undo);
+
+    if skipped > 0 {
+        eprintln!("Warning: {} of {} change(s) could not be rolled back", skipped, record.changes.len());
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Move `from` back to `to`, where a transaction found it
+///
+/// # Errors
+/// Why it was left alone, for the rollback's warning
+fn move_back(from: &Path, to: &Path) -> std::result::Result<(), String> {
+    if to.symlink_metadata().is_ok() {
+        return Err("something new is in the way".to_owned());
+    }
+    if from.symlink_metadata().is_err() {
+        return Err(format!("{} is gone", from.display()));
+    }
+
+    let moved = if from.is_dir() {
+        flacman_fs::move_dir(from, to, cancel_flag()).map(|_| ())
+    } else {
+        to.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(flacman_fs::FsError::from)
+            .and_then(|_| flacman_fs::move_file(from, to, false).map(|_| ()))
+    };
+    moved.map_err(|e| e.to_string())
+}
+
+/// Remove up to `levels` directories above `path` that it left empty: the
+/// album and artist directories above a track, the artist directory above
+/// an album
+fn remove_empty_parents(path: &Path, levels: usize) {
+    for dir in path.ancestors().skip(1).take(levels) {
+        if std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
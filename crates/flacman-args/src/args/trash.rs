@@ -0,0 +1,117 @@
+use super::*;
+
+
+/// `flacman trash list|restore|empty`
+pub(super) fn manage_trash(matches: &ArgMatches, trash: &Trash, confirm: Confirm) {
+    let verbose = matches.get_count("verbose") > 0;
+
+    match matches.subcommand() {
+        Some(("list", _)) => {
+            let entries = trash.list().unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            });
+            if matches.get_flag("json") {
+                let entries: Vec<_> = entries
+                    .iter()
+                    .map(|entry| {
+                        serde_json::json!({
+                            "batch": entry.batch,
+                            "transaction": entry.transaction,
+                            "removed_at": entry.removed_at.to_string(),
+                            "original": entry.original,
+                            "stored": entry.stored,
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&entries).unwrap_or_default());
+                return;
+            }
+            if entries.is_empty() {
+                println!("The trash in {} is empty", trash.root().display());
+                return;
+            }
+            for entry in &entries {
+                let (files, bytes) = path_stats(&entry.stored);
+                println!(
+                    "{:>5}  {}  {}  ({} file(s), {})",
+                    entry.transaction.map_or_else(|| "-".to_owned(), |id| format!("#{}", id)),
+                    entry.removed_at.format("%Y-%m-%d %H:%M"),
+                    entry.original.display(),
+                    files,
+                    format_size(bytes)
+                );
+            }
+            println!("Undo a removal with: flacman --rollback <#>, or put items back with: flacman trash restore <pattern>");
+        }
+        Some(("restore", restore_matches)) => {
+            let pattern = restore_matches.get_one::<String>("pattern").expect("required");
+            restore(trash, pattern, verbose);
+        }
+        Some(("empty", _)) => {
+            let entries = trash.list().unwrap_or_default();
+            if entries.is_empty() {
+                println!("The trash in {} is empty", trash.root().display());
+                return;
+            }
+            let bytes: u64 = entries.iter().map(|entry| path_stats(&entry.stored).1).sum();
+            confirm_or_exit(
+                confirm,
+                &format!("Delete {} removed item(s) ({}) for good?", entries.len(), format_size(bytes)),
+            );
+            match trash.purge_older_than(Duration::ZERO) {
+                Ok(purged) => println!("Emptied the trash ({} batch(es), {})", purged.len(), format_size(bytes)),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        _ => unreachable!("trash needs a subcommand"),
+    }
+}
+
+pub fn restore(trash: &Trash, pattern: &str, verbose: bool) {
+    match trash.restore(pattern) {
+        Ok(restored) if restored.is_empty() => {
+            eprintln!("Error: Nothing in the trash matches: {}", pattern);
+            process::exit(1);
+        }
+        Ok(restored) => {
+            let mut record = TxRecord::new("restore", Vec::new(), TxOutcome::Success);
+            record.source = Some(trash.root().display().to_string());
+
+            for path in &restored {
+                println!("Restored: {}", path.display());
+                let (files, bytes) = path_stats(path);
+                record.files += files;
+                record.bytes += bytes;
+                record.targets.push(path.display().to_string());
+            }
+
+            index_paths(&restored);
+            log_transaction(record);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+
+    purge_expired_trash(trash, verbose);
+}
+
+pub(super) fn purge_expired_trash(trash: &Trash, verbose: bool) {
+    let retention = Duration::from_secs(config().trash.retention_days * 24 * 60 * 60);
+
+    match trash.purge_older_than(retention) {
+        Ok(purged) => {
+            if verbose {
+                for batch in purged {
+                    println!("Purged expired trash batch: {}", batch);
+                }
+            }
+        }
+        Err(e) => eprintln!("Warning: could not purge expired trash: {}", e),
+    }
+}
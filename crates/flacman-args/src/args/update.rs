@@ -0,0 +1,805 @@
+use super::*;
+
+
+pub fn handle_update(matches: &ArgMatches, targets: &[&String], verbose: bool, confirm: Confirm) {
+    let started = Instant::now();
+    let transfer_flags = ["move", "copy", "symlink", "reflink"].iter().any(|flag| matches.get_flag(flag));
+    let transfer = if transfer_flags { None } else { config().transfer };
+    let move_files = matches.get_flag("move") || transfer == Some(DefaultTransfer::Move);
+    let copy_files = matches.get_flag("copy") || transfer == Some(DefaultTransfer::Copy);
+    let symlink_files = matches.get_flag("symlink") || transfer == Some(DefaultTransfer::Symlink);
+    let reflink_files = matches.get_flag("reflink") || transfer == Some(DefaultTransfer::Reflink);
+    let recursive = matches.get_flag("recursive");
+    let dry_run = matches.get_flag("dry-run");
+    let mb_lookup = matches.get_flag("mb-lookup");
+    let converter = matches.get_flag("convert").then(|| import_converter(matches, symlink_files || reflink_files));
+
+    if verbose {
+        println!("Operation: Update (Import to Repository)");
+    }
+
+    if targets.is_empty() {
+        eprintln!("Error: No source paths specified");
+        process::exit(1);
+    }
+
+    let operation = if move_files {
+        "Moving"
+    } else if copy_files {
+        "Copying"
+    } else if symlink_files {
+        "Symlinking"
+    } else if reflink_files {
+        "Reflinking"
+    } else {
+        eprintln!(
+            "Error: No operation specified (use -m for move, -c for copy, -s for symlink, or set transfer in the config)"
+        );
+        process::exit(1);
+    };
+
+    // A recursive import works directory by directory and checkpoints
+    // each one, so an interrupted run picks up where it stopped
+    let dirs;
+    let mut checkpoint = None;
+    let items: Vec<&String> = if recursive && dry_run {
+        dirs = album_dirs(targets);
+        dirs.iter().collect()
+    } else if recursive {
+        dirs = album_dirs(targets);
+        let cp = open_checkpoint(operation, targets, matches.get_flag("restart"));
+        let pending: Vec<&String> = dirs.iter().filter(|d| !cp.is_done(Path::new(d.as_str()))).collect();
+
+        if pending.len() < dirs.len() {
+            println!(
+                "Resuming: {} of {} directories already done (use --restart to start over)",
+                dirs.len() - pending.len(),
+                dirs.len()
+            );
+        }
+
+        checkpoint = Some(cp);
+        pending
+    } else {
+        targets.to_vec()
+    };
+
+    let stage = verify_stage(matches);
+    let mut accepted = Vec::new();
+    let mut vetoed = Vec::new();
+
+    let cancel = cancel_flag();
+    let total = items.len();
+    for (done, item) in items.into_iter().enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            // Finished directories are checkpointed, so the rerun skips them
+            exit_cancelled(done, total, "run the same command again to continue");
+        }
+        // Archives are verified once extracted
+        if is_archive(item) || verify_item(&stage, item, dry_run, verbose) {
+            accepted.push(item);
+        } else {
+            vetoed.push(item);
+        }
+
+        if let Some(cp) = &mut checkpoint
+            && let Err(e) = cp.mark_done(Path::new(item.as_str()))
+        {
+            eprintln!("Warning: could not update checkpoint: {}", e);
+        }
+    }
+
+    if accepted.is_empty() && vetoed.is_empty() {
+        println!("Nothing left to import");
+        if let Some(cp) = checkpoint {
+            let _ = cp.finish();
+        }
+        return;
+    }
+
+    if accepted.is_empty() {
+        eprintln!("Error: Every item was vetoed by verification, nothing to import");
+        process::exit(1);
+    }
+    let targets = accepted.as_slice();
+
+    let Some(library) = default_library() else {
+        eprintln!("Error: No repository configured (set library in {})", config_path().display());
+        process::exit(1);
+    };
+    println!("{} files into repository {} from: {:?}", operation, library_roots().join(", "), targets);
+    let mode = if move_files {
+        TransferMode::Move
+    } else if copy_files {
+        TransferMode::Copy
+    } else if reflink_files {
+        TransferMode::Reflink
+    } else {
+        TransferMode::Symlink
+    };
+    let import = AutoImport {
+        library: std::path::absolute(library).unwrap_or_else(|_| PathBuf::from(library)),
+        template: naming_template(matches),
+        mode,
+        min_confidence: 0,
+        on_conflict: matches.get_one::<ConflictStrategy>("on-conflict").copied().unwrap_or_default(),
+        trash: Some(trash()),
+        checksum: matches.get_flag("checksum"),
+        volumes: volume_set(),
+        sanitize: config().sanitize.clone(),
+        sidecars: config().sidecars.clone(),
+    };
+    if reflink_files && !dry_run {
+        ensure_reflinks(&import, targets);
+    }
+    if !dry_run {
+        confirm_or_exit(confirm, &format!("Proceed with {}?", operation.to_lowercase()));
+    }
+
+    if matches.get_flag("fetch-art") && !dry_run {
+        fetch_art(matches, targets, verbose);
+    }
+    if matches.get_flag("replaygain") && !dry_run {
+        let albums: Vec<&String> = targets.iter().copied().filter(|item| !is_archive(item)).collect();
+        // Albums that couldn't be measured are reported and imported all the same
+        replay_gain(matches, &albums, None);
+    }
+
+
+    if dry_run {
+        for item in targets {
+            if is_archive(item) {
+                println!("Would extract {} and import the albums in it", item);
+                continue;
+            }
+            let images = cue_images(item);
+            if !images.is_empty() {
+                for (cue, sheet, _) in &images {
+                    println!("Would split {} into {} tracks and import them", cue.display(), sheet.files[0].tracks.len());
+                }
+                continue;
+            }
+            if let Some(converter) = &converter {
+                println!("Would convert {} to {} and import the albums in it", item, converter.target());
+                continue;
+            }
+            for mut album in import_albums(item) {
+                if mb_lookup {
+                    musicbrainz_lookup(&mut album, verbose);
+                }
+                preview_import(&import, &album);
+            }
+        }
+        return;
+    }
+
+    let items: Vec<PathBuf> = targets.iter().map(|item| std::path::absolute(item.as_str()).unwrap_or_else(|_| PathBuf::from(item.as_str()))).collect();
+    if !run_hooks(HookWhen::PreTransaction, HookOperation::Import, &items) {
+        process::exit(1);
+    }
+
+    let mut summary = Summary::new("update");
+    summary.failed = vetoed.len();
+    summary.details = vetoed.iter().map(|t| format!("{}: vetoed by verification", t)).collect();
+
+    let progress = progress(targets.len(), "Importing");
+    for (done, item) in targets.iter().enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            drop(progress);
+            exit_cancelled(done, targets.len(), "run the same command again to import the rest");
+        }
+        progress.start(done, item, Some(path_stats(Path::new(item.as_str())).1));
+        if is_archive(item) {
+            import_archive(&import, &stage, item, matches, &mut summary, progress.as_ref(), verbose);
+        } else if !cue_images(item).is_empty() {
+            import_cue(&import, converter.as_ref(), item, matches, &mut summary, progress.as_ref(), verbose);
+        } else if let Some(converter) = &converter {
+            import_converted(&import, converter, item, matches, &mut summary, progress.as_ref(), verbose);
+        } else {
+            for mut album in import_albums(item) {
+                let canonical = if mb_lookup { musicbrainz_lookup(&mut album, verbose) } else { Vec::new() };
+                let bytes = import_album(&import, &album, &canonical, item, &mut summary, progress.as_ref(), verbose);
+                progress.advance(done, bytes);
+            }
+        }
+        progress.finish(done);
+    }
+    drop(progress);
+
+    if let Some(cp) = checkpoint
+        && let Err(e) = cp.finish()
+    {
+        eprintln!("Warning: could not remove checkpoint: {}", e);
+    }
+    let imported = take_imported();
+    update_mpd(&imported);
+    run_hooks(HookWhen::PostTransaction, HookOperation::Import, &imported);
+
+    summary.elapsed = started.elapsed();
+    notify_finished(matches, &summary);
+    if summary.failed > vetoed.len() {
+        process::exit(1);
+    }
+}
+
+/// Exit unless every root `targets` will be filed under can make reflinks
+///
+/// Archives and cue images are only read once extracted or split, so
+/// every root is checked for them.
+fn ensure_reflinks(import: &AutoImport, targets: &[&String]) {
+    let mut roots = BTreeSet::new();
+    for item in targets {
+        if is_archive(item) || !cue_images(item).is_empty() {
+            roots.insert(import.library.clone());
+            roots.extend(import.volumes.roots().into_iter().map(Path::to_path_buf));
+        } else {
+            roots.extend(import_albums(item).iter().map(|album| import.root(album).to_path_buf()));
+        }
+    }
+
+    for root in roots {
+        let capabilities = std::fs::create_dir_all(&root).and_then(|_| FsCapabilities::probe(&root));
+        if !capabilities.is_ok_and(|c| c.reflink) {
+            eprintln!("Error: The filesystem of {} can't make reflinks; use -c, which clones where it can", root.display());
+            process::exit(1);
+        }
+    }
+}
+
+/// Converter `-U --convert` uses: to the `-f` format, else `format` in
+/// flacman.conf, at `--quality`
+///
+/// Exits if there is no format, the tracks would be `linked` rather than
+/// copied or moved, or ffmpeg isn't installed.
+fn import_converter(matches: &ArgMatches, linked: bool) -> Converter {
+    let Some(format) = matches.get_one::<String>("format").or(config().format.as_ref()) else {
+        eprintln!("Error: --convert needs a format (use -f or set format in {})", config_path().display());
+        process::exit(1);
+    };
+    if linked {
+        eprintln!("Error: Converted tracks can't be linked; use -m or -c with --convert");
+        process::exit(1);
+    }
+    let target = convert_target(format, &quality_ladder(matches));
+    Converter::new(target).unwrap_or_else(|e| {
+        eprintln!("Error: {}; it is needed to convert to {}", e, target.format);
+        process::exit(1);
+    })
+}
+
+/// Library path template: `--template`, else `template` in flacman.conf,
+/// else the profile's default layout
+pub(super) fn naming_template(matches: &ArgMatches) -> Template {
+    if let Some(template) = matches.get_one::<Template>("template") {
+        return template.clone();
+    }
+    if let Some(template) = &config().template {
+        return template.parse().unwrap_or_else(|e| {
+            eprintln!("Error: template in {}: {}", config_path().display(), e);
+            process::exit(1);
+        });
+    }
+
+    content_type(matches).default_template(DiscLayout::default()).parse().expect("built-in templates parse")
+}
+
+/// Look `album` up on MusicBrainz and give it the canonical names of the
+/// release found, so it is filed under them
+///
+/// The release tagged on its tracks is used if there is one. Otherwise the
+/// release is searched for by the album's artist and title, or by the name
+/// of its directory (`Artist - Album`) when the tags have neither.
+///
+/// # Returns
+/// The canonical tags to write to each track once it is filed; empty when
+/// nothing matched, and the album is then left as it was
+fn musicbrainz_lookup(album: &mut Album, verbose: bool) -> Vec<(PathBuf, CanonicalTrack)> {
+    let name = format!("{} - {}", album.artist, album.title);
+    let Some(first) = album.tracks().next().map(|t| t.path.clone()) else {
+        return Vec::new();
+    };
+
+    let release = match release_ids(&first).ok().and_then(|(release, _)| release) {
+        Some(id) => musicbrainz().release(&id).map(Some),
+        None => {
+            let dir_name = first.parent().and_then(|d| d.file_name()).map(|n| n.to_string_lossy().into_owned());
+            let (artist, title) = if !album.artist.is_empty() && !album.title.is_empty() {
+                (album.artist.clone(), album.title.clone())
+            } else if let Some((artist, title)) = dir_name.as_deref().and_then(|n| n.split_once(" - ")) {
+                (artist.trim().to_owned(), title.trim().to_owned())
+            } else {
+                println!("{}: not enough tags to look up on MusicBrainz", name);
+                return Vec::new();
+            };
+            musicbrainz().find_release(&artist, &title, album.track_count())
+        }
+    };
+
+    let release = match release {
+        Ok(Some(release)) => release,
+        Ok(None) => {
+            println!("{}: no match on MusicBrainz, keeping the tags", name);
+            return Vec::new();
+        }
+        Err(e) => {
+            eprintln!("Warning: {}: MusicBrainz lookup failed: {}", name, e);
+            return Vec::new();
+        }
+    };
+    let tracks = canonical_tracks(album, &release);
+    if tracks.is_empty() {
+        println!("{}: tracks don't fit MusicBrainz release {}, keeping the tags", name, release.id);
+        return Vec::new();
+    }
+    if let Err(e) = apply_canonical(album, &tracks) {
+        eprintln!("Warning: {}: {}", name, e);
+        return Vec::new();
+    }
+
+    if verbose || name != format!("{} - {}", album.artist, album.title) {
+        println!("{}: matched {} - {} ({})", name, album.artist, album.title, release.id);
+    }
+    tracks
+}
+
+/// Write the canonical tags of `album`'s tracks to their imported copies,
+/// given the imported `paths` in track order
+fn write_canonical(album: &Album, canonical: &[(PathBuf, CanonicalTrack)], paths: &[PathBuf]) {
+    for (track, dest) in album.tracks().zip(paths) {
+        let Some((_, tags)) = canonical.iter().find(|(source, _)| *source == track.path) else {
+            continue;
+        };
+        if let Err(e) = write_canonical_tags(dest, tags) {
+            eprintln!("Warning: could not tag {}: {}", dest.display(), e);
+        }
+    }
+}
+
+/// The albums among the audio files at `item`, a file or a directory
+pub(super) fn import_albums(item: &String) -> Vec<Album> {
+    if Path::new(item.as_str()).is_dir() {
+        return read_albums(&[item]);
+    }
+
+    let path = PathBuf::from(item.as_str());
+    match MediaFile::new(&path).read() {
+        Ok(metadata) => group_albums(vec![AlbumTrack { path, metadata: metadata.clone() }]),
+        Err(e) => {
+            eprintln!("Warning: skipped {}: {}", item, e);
+            Vec::new()
+        }
+    }
+}
+
+fn is_archive(item: &str) -> bool {
+    ArchiveKind::of(Path::new(item)).is_some() && Path::new(item).is_file()
+}
+
+/// Log that importing `source` failed for `reason`
+fn fail_import(source: &str, reason: String, summary: &mut Summary, progress: &dyn Progress) {
+    progress.message(&format!("Error: {}: {}", source, reason));
+    let mut record = TxRecord::new("update", Vec::new(), TxOutcome::Failed);
+    record.source = Some(source.to_owned());
+    record.messages = vec![reason.clone()];
+    log_transaction(record);
+    summary.failed += 1;
+    summary.details.push(format!("{}: {}", source, reason));
+}
+
+/// A fresh scratch directory under `name` in the cache directory
+fn scratch_dir(name: &str) -> std::io::Result<tempfile::TempDir> {
+    let scratch = cache_dir().join(name);
+    std::fs::create_dir_all(&scratch).and_then(|_| tempfile::tempdir_in(&scratch))
+}
+
+/// Extract `archive` and file its albums into the library, all or nothing
+///
+/// The extracted tracks are verified and placed by template like any other
+/// source. If an album fails, the tracks already filed from the archive are
+/// removed again; the archive itself is only deleted, with
+/// `--delete-archive`, once everything in it was imported.
+fn import_archive(
+    import: &AutoImport,
+    stage: &VerifyStage,
+    archive: &str,
+    matches: &ArgMatches,
+    summary: &mut Summary,
+    progress: &dyn Progress,
+    verbose: bool,
+) {
+    let extracted = match scratch_dir("extract") {
+        Ok(dir) => dir,
+        Err(e) => return fail_import(archive, e.to_string(), summary, progress),
+    };
+    match flacman_fs::extract_archive(archive, extracted.path()) {
+        Ok(files) if verbose => progress.println(&format!("Extracted {} files from {}", files.len(), archive)),
+        Ok(_) => {}
+        Err(e) => return fail_import(archive, e.to_string(), summary, progress),
+    }
+
+    let dir = extracted.path().display().to_string();
+    if !verify_item(stage, &dir, false, verbose) {
+        return fail_import(archive, "vetoed by verification".to_owned(), summary, progress);
+    }
+    if !import_scratch(import, archive, extracted.path(), matches, summary, progress, verbose) {
+        return;
+    }
+
+    if matches.get_flag("delete-archive") {
+        match std::fs::remove_file(archive) {
+            Ok(()) => progress.println(&format!("Deleted {}", archive)),
+            Err(e) => progress.message(&format!("Warning: could not delete {}: {}", archive, e)),
+        }
+    }
+}
+
+/// Convert the tracks at `item` into scratch space and file the converted
+/// albums into the library, all or nothing
+///
+/// With `-m` the original tracks are deleted once everything was imported;
+/// with `-c` they are left as they are.
+fn import_converted(
+    import: &AutoImport,
+    converter: &Converter,
+    item: &str,
+    matches: &ArgMatches,
+    summary: &mut Summary,
+    progress: &dyn Progress,
+    verbose: bool,
+) {
+    let converted = match scratch_dir("convert") {
+        Ok(dir) => dir,
+        Err(e) => return fail_import(item, e.to_string(), summary, progress),
+    };
+    let path = Path::new(item);
+    let (files, from) = if path.is_dir() {
+        (flacman_fs::find_audio_files(path).unwrap_or_default(), path)
+    } else {
+        (vec![path.to_path_buf()], path.parent().unwrap_or(path))
+    };
+    let jobs = plan_conversion(&files, from, converted.path(), converter.target().format);
+
+    let cancel = cancel_flag();
+    let report = converter.convert_files(&jobs, cpu_jobs(matches), cancel, |_, job, failure| {
+        if failure.is_none() && verbose {
+            progress.println(&format!("Converted {} to {}", job.source.display(), converter.target()));
+        }
+    });
+    if let Some(failure) = report.failures.first() {
+        let reason = format!("could not convert {}: {}", failure.path.display(), failure.message);
+        return fail_import(item, reason, summary, progress);
+    }
+    if report.interrupted {
+        return;
+    }
+    if !import_scratch(import, item, converted.path(), matches, summary, progress, verbose) {
+        return;
+    }
+
+    if import.mode == TransferMode::Move {
+        let mut record = TxRecord::new("update", vec![item.to_owned()], TxOutcome::Success);
+        for job in &jobs {
+            match std::fs::remove_file(&job.source) {
+                Ok(()) => record.changes.push(FileChange::Deleted { path: job.source.clone() }),
+                Err(e) => progress.message(&format!("Warning: could not delete {}: {}", job.source.display(), e)),
+            }
+            if let Some(parent) = job.source.parent() {
+                let _ = flacman_fs::prune_empty_dirs(parent, path);
+            }
+        }
+        record.files = record.changes.len() as u64;
+        log_transaction(record);
+    }
+}
+
+/// The disc images described by cue sheets at `item`, a directory or a
+/// cue sheet
+fn cue_images(item: &str) -> Vec<(PathBuf, CueSheet, PathBuf)> {
+    let path = Path::new(item);
+    if path.is_dir() {
+        return find_cue_images(path);
+    }
+    if !path.extension().is_some_and(|e| e.eq_ignore_ascii_case("cue")) {
+        return Vec::new();
+    }
+    let images = find_cue_images(path.parent().unwrap_or(Path::new(".")));
+    images.into_iter().filter(|(cue, _, _)| cue.file_name() == path.file_name()).collect()
+}
+
+/// Split the disc images described by the cue sheets at `item` into tracks
+/// in scratch space and file them into the library, all or nothing
+///
+/// The tracks are FLAC unless `--convert` asks for another format. With
+/// `-m` the images and their cue sheets are deleted once everything was
+/// imported; with `-c` they are left as they are.
+fn import_cue(
+    import: &AutoImport,
+    converter: Option<&Converter>,
+    item: &str,
+    matches: &ArgMatches,
+    summary: &mut Summary,
+    progress: &dyn Progress,
+    verbose: bool,
+) {
+    let flac;
+    let converter = match converter {
+        Some(converter) => converter,
+        None => match Converter::new(ConvertTarget { format: AudioFormat::Flac, bitrate: None }) {
+            Ok(converter) => {
+                flac = converter;
+                &flac
+            }
+            Err(e) => return fail_import(item, format!("{}; it is needed to split cue sheets", e), summary, progress),
+        },
+    };
+    let split = match scratch_dir("cue") {
+        Ok(dir) => dir,
+        Err(e) => return fail_import(item, e.to_string(), summary, progress),
+    };
+
+    let images = cue_images(item);
+    for (i, (cue, sheet, image)) in images.iter().enumerate() {
+        // A directory each, as the images may be discs of different albums
+        match converter.split_image(sheet, image, &split.path().join(i.to_string())) {
+            Ok(tracks) if verbose => {
+                progress.println(&format!("Split {} into {} tracks", image.display(), tracks.len()));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                let reason = format!("could not split {}: {}", cue.display(), e);
+                return fail_import(item, reason, summary, progress);
+            }
+        }
+    }
+    if !import_scratch(import, item, split.path(), matches, summary, progress, verbose) {
+        return;
+    }
+
+    if import.mode == TransferMode::Move {
+        let mut record = TxRecord::new("update", vec![item.to_owned()], TxOutcome::Success);
+        for path in images.iter().flat_map(|(cue, _, image)| [cue, image]) {
+            match std::fs::remove_file(path) {
+                Ok(()) => record.changes.push(FileChange::Deleted { path: path.clone() }),
+                Err(e) => progress.message(&format!("Warning: could not delete {}: {}", path.display(), e)),
+            }
+        }
+        record.files = record.changes.len() as u64;
+        log_transaction(record);
+    }
+}
+
+/// File the albums in `scratch`, made from `source`, into the library, all
+/// or nothing: if an album fails, the tracks already filed are removed again
+///
+/// The scratch copies are moved into place whatever `import.mode` is.
+///
+/// # Returns
+/// Whether everything was imported
+fn import_scratch(
+    import: &AutoImport,
+    source: &str,
+    scratch: &Path,
+    matches: &ArgMatches,
+    summary: &mut Summary,
+    progress: &dyn Progress,
+    verbose: bool,
+) -> bool {
+    let mb_lookup = matches.get_flag("mb-lookup");
+    let mut albums = import_albums(&scratch.display().to_string());
+    if albums.is_empty() {
+        fail_import(source, "no audio files to import".to_owned(), summary, progress);
+        return false;
+    }
+
+    let import = AutoImport { mode: TransferMode::Move, ..import.clone() };
+    let mut imported = Vec::new();
+    for album in &mut albums {
+        let canonical = if mb_lookup { musicbrainz_lookup(album, verbose) } else { Vec::new() };
+        let name = format!("{} - {}", album.artist, album.title);
+        match import.import(album, scratch, &mut ask_conflict_over(progress)) {
+            Ok(ImportOutcome::Imported(paths)) => {
+                progress.println(&format!("Imported {} ({} tracks) from {}", name, paths.len(), source));
+                write_canonical(album, &canonical, &paths);
+                imported.extend(paths);
+            }
+            Ok(ImportOutcome::Resolved { paths, existing, resolution }) => {
+                let (strategy, reason) = (&resolution.strategy, &resolution.reason);
+                progress.println(&format!("{}: already in {} ({}: {})", name, existing.display(), strategy, reason));
+                write_canonical(album, &canonical, &paths);
+                imported.extend(paths);
+            }
+            Ok(ImportOutcome::Held { .. }) => unreachable!("nothing is held without a minimum confidence"),
+            Err(e) => {
+                for path in &imported {
+                    if let Err(e) = std::fs::remove_file(path) {
+                        progress.message(&format!("Warning: could not roll back {}: {}", path.display(), e));
+                    } else if let Some(parent) = path.parent() {
+                        let _ = std::fs::remove_dir(parent);
+                    }
+                }
+                let reason = format!("{}: {}; rolled back {} imported tracks", name, e, imported.len());
+                fail_import(source, reason, summary, progress);
+                return false;
+            }
+        }
+    }
+
+    if verbose {
+        for path in &imported {
+            progress.println(&format!("    {}", path.display()));
+        }
+    }
+    index_paths(&imported);
+    note_imported(&imported);
+    let mut record = TxRecord::new("update", Vec::new(), TxOutcome::Success);
+    record.source = Some(source.to_owned());
+    record.files = imported.len() as u64;
+    record.bytes = imported.iter().filter_map(|p| p.metadata().ok()).map(|m| m.len()).sum();
+    record.targets = imported.iter().map(|p| p.display().to_string()).collect();
+    record.changes = imported.iter().map(|p| FileChange::Created { path: p.clone() }).collect();
+    log_transaction(record);
+    summary.succeeded += 1;
+    true
+}
+
+/// File the tracks of `album`, from `item`, into the library by template
+/// and log the transaction
+///
+/// The `canonical` tags from a MusicBrainz lookup are written to the
+/// filed copies; symlinked and hardlinked tracks are left untouched, as
+/// tagging them would change the source files.
+///
+/// # Returns
+/// Bytes filed
+fn import_album(
+    import: &AutoImport,
+    album: &Album,
+    canonical: &[(PathBuf, CanonicalTrack)],
+    item: &str,
+    summary: &mut Summary,
+    progress: &dyn Progress,
+    verbose: bool,
+) -> u64 {
+    let name = format!("{} - {}", album.artist, album.title);
+    let mut record = TxRecord::new("update", Vec::new(), TxOutcome::Success);
+    record.source = Some(item.to_owned());
+
+    let paths = match import.import(album, Path::new(item), &mut ask_conflict_over(progress)) {
+        Ok(ImportOutcome::Imported(paths)) => {
+            progress.println(&format!("Imported {} ({} tracks)", name, paths.len()));
+            paths
+        }
+        Ok(ImportOutcome::Resolved { paths, existing, resolution }) => {
+            let (strategy, reason) = (&resolution.strategy, &resolution.reason);
+            progress.println(&format!("{}: already in {} ({}: {})", name, existing.display(), strategy, reason));
+            record.messages = vec![format!("conflict with {}", existing.display())];
+            if resolution.decision == ConflictDecision::Skip {
+                record.outcome = TxOutcome::Vetoed;
+                summary.details.push(format!("{}: already in the library, {}", name, resolution.reason));
+            }
+            paths
+        }
+        Ok(ImportOutcome::Held { .. }) => unreachable!("nothing is held without a minimum confidence"),
+        Err(e) => {
+            progress.message(&format!("Error: {}: {}", name, e));
+            record.outcome = TxOutcome::Failed;
+            record.messages = vec![e.to_string()];
+            summary.failed += 1;
+            summary.details.push(format!("{}: {}", name, e));
+            Vec::new()
+        }
+    };
+
+    if verbose {
+        for path in &paths {
+            progress.println(&format!("    {}", path.display()));
+        }
+    }
+    if !paths.is_empty() {
+        if !matches!(import.mode, TransferMode::Symlink | TransferMode::Hardlink) {
+            write_canonical(album, canonical, &paths);
+        }
+        index_paths(&paths);
+        note_imported(&paths);
+        summary.succeeded += 1;
+    }
+    record.files = paths.len() as u64;
+    record.bytes = paths.iter().filter_map(|p| p.metadata().ok()).map(|m| m.len()).sum();
+    record.targets = paths.iter().map(|p| p.display().to_string()).collect();
+    record.changes = import_changes(import.mode, album, &paths);
+    let bytes = record.bytes;
+    log_transaction(record);
+    bytes
+}
+
+/// Print where importing `album` would put each of its tracks, and what it
+/// would do about an album already in the library
+fn preview_import(import: &AutoImport, album: &Album) {
+    let name = format!("{} - {}", album.artist, album.title);
+    let conflict = match import.conflict(album) {
+        Ok(conflict) => conflict,
+        Err(e) => {
+            eprintln!("Error: {}: {}", name, e);
+            return;
+        }
+    };
+    let resolution = conflict.as_ref().map(|c| resolve_conflict(import.on_conflict, c, &mut ask_conflict));
+    let resolved = conflict.as_ref().zip(resolution.as_ref());
+    if let Some((existing, resolution)) = resolved {
+        let existing = existing.existing_dir.display();
+        println!("{}: already in {} ({}: {})", name, existing, resolution.strategy, resolution.reason);
+    }
+
+    let transfers = import.resolved_destinations(album, resolved);
+    if transfers.is_empty() {
+        println!("Would skip {}", name);
+        return;
+    }
+    let verb = match import.mode {
+        TransferMode::Copy => "copy",
+        TransferMode::Move => "move",
+        TransferMode::Symlink => "symlink",
+        TransferMode::Hardlink => "hardlink",
+        TransferMode::Reflink => "reflink",
+    };
+    let plan = import.plan(album, resolved);
+    println!("Would import {} ({}):", name, plan.summary());
+    for entry in plan.entries.iter().filter(|e| e.kind == ChangeKind::Prune) {
+        println!("    trash {}", plan.root.join(&entry.path).display());
+    }
+    for (source, dest) in &transfers {
+        println!("    {} {} -> {}", verb, source.display(), dest.display());
+    }
+    for (source, dest) in import.sidecar_destinations(album, &transfers).unwrap_or_default() {
+        println!("    {} {} -> {} (sidecar)", verb, source.display(), dest.display());
+    }
+}
+
+/// What importing `album` did to its files, given the imported `paths` in
+/// track order
+pub(super) fn import_changes(mode: TransferMode, album: &Album, paths: &[PathBuf]) -> Vec<FileChange> {
+    album
+        .tracks()
+        .zip(paths)
+        .map(|(track, to)| {
+            let from = std::path::absolute(&track.path).unwrap_or_else(|_| track.path.clone());
+            if mode == TransferMode::Move {
+                FileChange::Moved { from, to: to.clone() }
+            } else {
+                FileChange::Copied { from, to: to.clone() }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::tests::{run, scratch_env, scratch_library, write_album};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_update_files_by_template() {
+        let _env = scratch_env();
+        let library = scratch_library();
+        let inbox = tempdir().unwrap();
+        let copied = write_album(&inbox.path().join("rip"), "Template Band", "Copied", "flac");
+        let moved = write_album(&inbox.path().join("other rip"), "Template Band", "Moved", "flac");
+
+        let template = "%artist% Collection/%album%/%track% - %title%";
+        run(&["-U", "--copy", "--noconfirm", "--template", template, inbox.path().join("rip").to_str().unwrap()]);
+        let album = library.join("Template Band Collection/Copied");
+        let placed = vec![album.join("1 - One.flac"), album.join("2 - Two.flac")];
+        assert!(placed.iter().all(|p| p.exists()));
+        assert!(copied.iter().all(|p| p.exists()));
+        let indexed: Vec<PathBuf> = library_db().tracks_under(&album).unwrap().into_iter().map(|t| t.path).collect();
+        assert_eq!(indexed, placed);
+        let logged = tx_log().read_all().unwrap().pop().unwrap();
+        assert_eq!((logged.operation.as_str(), logged.files), ("update", 2));
+
+        // Without --template, the profile's layout
+        run(&["-U", "--move", "--noconfirm", inbox.path().join("other rip").to_str().unwrap()]);
+        assert!(library.join("Template Band/Moved/01 One.flac").exists());
+        assert!(moved.iter().all(|p| !p.exists()));
+    }
+}
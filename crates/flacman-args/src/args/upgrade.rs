@@ -0,0 +1,349 @@
+use super::*;
+
+
+/// A library album a source has a better release of
+struct Upgrade {
+    /// `Artist - Album`, as the library has it
+    name: String,
+    /// The album's tracks in the library
+    files: Vec<PathBuf>,
+    quality: AudioQuality,
+    release: Release,
+}
+
+/// `-Su`: replace the library albums under `targets` (or named by them,
+/// or all of them) that a source has in better quality
+///
+/// A release is better if it stands higher on the quality ladder than the
+/// worst track of the library's copy, e.g. FLAC over a 192 kbps MP3 rip;
+/// of several, the best by flacman.conf's `rank` is taken. Upgrades are
+/// downloaded like any `-S` album, then each replaces its old copy in one
+/// transaction: the old tracks go to the trash and the new ones are
+/// imported, or the old ones are put back if that fails.
+pub(super) fn upgrade_albums(matches: &ArgMatches, targets: &[&String], verbose: bool, confirm: Confirm) {
+    let started = Instant::now();
+    let refresh = matches.get_count("refresh");
+    let dry_run = matches.get_flag("dry-run");
+    let sources = source_names(matches);
+    if sources.is_empty() {
+        eprintln!("Error: No remote sources to upgrade from; add [[sources]] to flacman.conf");
+        process::exit(1);
+    }
+    let Some(library) = default_library() else {
+        eprintln!("Error: No library configured; set library in flacman.conf");
+        process::exit(1);
+    };
+    let Some(source) = pick_source(&sources, targets, false) else {
+        return;
+    };
+    let searched: Vec<String> = std::iter::once(source.clone()).chain(further_sources(&sources, &source, false)).collect();
+    let remotes: Vec<CachedSource> = searched.iter().filter_map(|s| remote_source(s, refresh)).collect();
+    if remotes.is_empty() {
+        eprintln!("Error: {}", RemoteError::UnknownSource(source));
+        process::exit(1);
+    }
+
+    let upgrades = find_upgrades(&remotes, &quality_ladder(matches), targets, verbose);
+    if upgrades.is_empty() {
+        println!("Nothing to upgrade");
+        return;
+    }
+    println!(":: {} upgrade(s):", upgrades.len());
+    for upgrade in &upgrades {
+        let release = &upgrade.release;
+        println!("    {}: {} -> {} [{}]", upgrade.name, upgrade.quality, release.format, release.source);
+    }
+    if dry_run {
+        let downloads: Vec<AlbumDownload> = upgrades.iter().map(Upgrade::download).collect();
+        preview_downloads(&remotes, &downloads);
+        for upgrade in &upgrades {
+            println!("Would move {} tracks of {} to the trash", upgrade.files.len(), upgrade.name);
+        }
+        return;
+    }
+    if !within_schedule(matches, "sync", "downloads", config().schedule.downloads) {
+        return;
+    }
+
+    confirm_or_exit(confirm, "Proceed with upgrade?");
+
+    let mut summary = Summary::new("upgrade");
+    let planned: Vec<PathBuf> = upgrades.iter().map(|u| downloads_dir().join(u.download().name().replace('/', "_"))).collect();
+    if !run_hooks(HookWhen::PreTransaction, HookOperation::Sync, &planned) {
+        process::exit(1);
+    }
+    evict_download_cache(verbose);
+
+    let jobs = matches.get_one::<usize>("jobs").copied().unwrap_or(DOWNLOAD_JOBS);
+    let mut dirs = Vec::new();
+    let mut outcomes = Vec::new();
+    for (i, remote) in remotes.iter().enumerate() {
+        let albums: Vec<AlbumDownload> = upgrades.iter().filter(|u| u.release.source_rank == i).map(Upgrade::download).collect();
+        if albums.is_empty() {
+            continue;
+        }
+        let failed = summary.failed;
+        dirs.extend(download_albums(remote, &albums, jobs, None, verbose, &mut summary));
+        outcomes.push((remote.name().to_owned(), summary.failed > failed));
+    }
+    let format = matches.get_one::<String>("format").or(config().format.as_ref());
+    if let Some(target) = format.map(|format| convert_target(format, &quality_ladder(matches))) {
+        convert_downloads(target, &dirs, cpu_jobs(matches), verbose, &mut summary);
+    }
+    run_hooks(HookWhen::PostTransaction, HookOperation::Sync, &dirs);
+
+    let health = source_health();
+    for (source, failed) in &outcomes {
+        let recorded = if *failed {
+            health.record_failure(source, Local::now())
+        } else {
+            health.record_success(source, Local::now())
+        };
+        if let Err(e) = recorded {
+            eprintln!("Warning: could not update source health: {}", e);
+        }
+    }
+
+    // What was downloaded counts once it has replaced the old copy
+    summary.succeeded = 0;
+    let downloaded: Vec<(&Upgrade, PathBuf)> = upgrades
+        .iter()
+        .filter_map(|u| {
+            let dir = downloads_dir().join(u.download().name().replace('/', "_"));
+            dirs.contains(&dir).then_some((u, dir))
+        })
+        .collect();
+    if !downloaded.is_empty() {
+        let replaced: Vec<PathBuf> = downloaded.iter().map(|(_, dir)| dir.clone()).collect();
+        if !run_hooks(HookWhen::PreTransaction, HookOperation::Import, &replaced) {
+            process::exit(1);
+        }
+        let import = AutoImport {
+            library: PathBuf::from(library),
+            template: naming_template(matches),
+            mode: TransferMode::Move,
+            min_confidence: 0,
+            on_conflict: ConflictStrategy::Replace,
+            trash: Some(trash()),
+            checksum: matches.get_flag("checksum"),
+            volumes: volume_set(),
+            sanitize: config().sanitize.clone(),
+            sidecars: config().sidecars.clone(),
+        };
+        for (upgrade, dir) in &downloaded {
+            replace_album(&import, upgrade, dir, verbose, &mut summary);
+        }
+        let imported = take_imported();
+        update_mpd(&imported);
+        run_hooks(HookWhen::PostTransaction, HookOperation::Import, &imported);
+    }
+
+    summary.elapsed = started.elapsed();
+    notify_finished(matches, &summary);
+    if summary.failed > 0 {
+        process::exit(1);
+    }
+}
+
+impl Upgrade {
+    fn download(&self) -> AlbumDownload {
+        let album = self.release.album.clone();
+        AlbumDownload { source: self.release.source_rank, tracks: album.tracks.clone(), album }
+    }
+}
+
+/// The albums of the library under or named by `targets` (all of them
+/// with none) that `sources` have a better release of, by `ladder`
+fn find_upgrades(sources: &[CachedSource], ladder: &QualityLadder, targets: &[&String], verbose: bool) -> Vec<Upgrade> {
+    let db = library_db();
+    let mut tracks = Vec::new();
+    let all = || {
+        db.tracks().unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        })
+    };
+    if targets.is_empty() {
+        tracks = all();
+    }
+    for target in targets {
+        let path = std::path::absolute(target.as_str()).unwrap_or_else(|_| PathBuf::from(target.as_str()));
+        let found = if path.exists() {
+            db.tracks_under(&path).unwrap_or_default()
+        } else {
+            let name = target.to_lowercase();
+            all().into_iter().filter(|t| format!("{} - {}", t.album_artist, t.album).to_lowercase() == name).collect()
+        };
+        if found.is_empty() {
+            eprintln!("Warning: {}: not in the library database", target);
+        }
+        tracks.extend(found);
+    }
+
+    let mut albums: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for track in tracks.into_iter().filter(|t| !t.album.is_empty()) {
+        let files = albums.entry(format!("{} - {}", track.album_artist, track.album)).or_default();
+        if !files.contains(&track.path) {
+            files.push(track.path);
+        }
+    }
+    println!("Checking {} album(s) for upgrades...", albums.len());
+
+    let ranking = if config().rank.is_empty() { &RankKey::DEFAULT[..] } else { &config().rank[..] };
+    let mut upgrades = Vec::new();
+    for (name, files) in albums {
+        let Some(quality) = album_quality(&files) else {
+            eprintln!("Warning: {}: could not read the quality of its tracks", name);
+            continue;
+        };
+        let local = Encoding::new(&quality.format, quality.lossless, quality.bitrate);
+        let (mut releases, errors) = find_releases(sources, &name);
+        for e in errors {
+            eprintln!("Warning: {}: {}", name, e);
+        }
+        releases.retain(|r| r.exact && r.upgrades(&local, ladder));
+        rank_releases(&mut releases, ranking, ladder);
+        match releases.into_iter().next() {
+            Some(release) => upgrades.push(Upgrade { name, files, quality, release }),
+            None if verbose => println!("{}: {} is the best there is", name, quality),
+            None => {}
+        }
+    }
+
+    upgrades
+}
+
+/// Replace the library copy of `upgrade` with the album downloaded to
+/// `dir`, in one logged transaction
+///
+/// The old tracks go to the trash first, so the new ones can take their
+/// place; if importing fails, they are put back.
+fn replace_album(import: &AutoImport, upgrade: &Upgrade, dir: &Path, verbose: bool, summary: &mut Summary) {
+    let name = &upgrade.name;
+    let mut fail = |reason: String| {
+        eprintln!("Error: {}: {}", name, reason);
+        summary.failed += 1;
+        summary.details.push(format!("{}: {}", name, reason));
+        reason
+    };
+    let mut record = TxRecord::new("upgrade", vec![name.clone()], TxOutcome::Failed);
+    record.source = Some(upgrade.release.source.clone());
+
+    let mut albums = read_albums(&[&dir.display().to_string()]);
+    let album = match albums.len() {
+        1 => albums.remove(0),
+        n => {
+            record.messages = vec![fail(format!("{} holds {} albums instead of one", dir.display(), n))];
+            log_transaction(record);
+            return;
+        }
+    };
+    let arrived: Vec<Option<DateTime<Local>>> = album
+        .tracks()
+        .map(|t| t.path.metadata().and_then(|m| m.modified()).ok().map(DateTime::<Local>::from))
+        .collect();
+    let source = SourceInfo::load(dir).ok().flatten();
+
+    let trash = trash();
+    let trashed = match trash.remove(&upgrade.files) {
+        Ok(entries) => entries,
+        Err(e) => {
+            record.messages = vec![fail(format!("could not move the old tracks to the trash: {}", e))];
+            log_transaction(record);
+            return;
+        }
+    };
+
+    let paths = match import.import(&album, dir, &mut ask_conflict) {
+        Ok(ImportOutcome::Imported(paths) | ImportOutcome::Resolved { paths, .. }) if !paths.is_empty() => paths,
+        outcome => {
+            let reason = match outcome {
+                Err(e) => e.to_string(),
+                _ => "nothing was imported".to_owned(),
+            };
+            if let Err(e) = trash.put_back(&trashed) {
+                eprintln!("Error: could not put the old tracks back: {}; restore them with: flacman trash restore", e);
+            }
+            record.messages = vec![fail(format!("{}; kept the old copy", reason))];
+            log_transaction(record);
+            return;
+        }
+    };
+
+    if let Err(e) = library_db().remove_all_under(&upgrade.files) {
+        eprintln!("Warning: could not update the library database: {}", e);
+    }
+    record_provenance(&album, &arrived, &paths, source.as_ref());
+    index_paths(&paths);
+    note_imported(&paths);
+    // Only the source sidecar is left of the download
+    let _ = std::fs::remove_file(dir.join(SOURCE_SIDECAR));
+    let _ = std::fs::remove_dir(dir);
+
+    let quality = album_quality(&paths).map_or_else(|| upgrade.release.format.clone(), |q| q.to_string());
+    println!("Upgraded {}: {} -> {}", name, upgrade.quality, quality);
+    if verbose {
+        for path in &paths {
+            println!("    {}", path.display());
+        }
+    }
+    record.outcome = TxOutcome::Success;
+    record.files = paths.len() as u64;
+    record.bytes = paths.iter().filter_map(|p| p.metadata().ok()).map(|m| m.len()).sum();
+    record.messages = vec![format!("{} -> {}", upgrade.quality, quality)];
+    record.changes = trashed.into_iter().map(|e| FileChange::Trashed { original: e.original, stored: e.stored }).collect();
+    record.changes.extend(import_changes(import.mode, &album, &paths));
+    log_transaction(record);
+    summary.succeeded += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::tests::{files_under, run, scratch_env, scratch_library, write_album};
+
+    #[test]
+    fn test_upgrade_replaces_album() {
+        let _env = scratch_env();
+        let album = scratch_library().join("Upgrade Band/Better");
+        let old = write_album(&album, "Upgrade Band", "Better", "mp3");
+        index_paths(&old);
+
+        run(&["-Su", "--noconfirm", album.to_str().unwrap()]);
+        let new = vec![album.join("01 One.flac"), album.join("02 Two.flac")];
+        assert!(new.iter().all(|p| p.exists()));
+        assert!(old.iter().all(|p| !p.exists()));
+        let indexed: Vec<PathBuf> = library_db().tracks_under(&album).unwrap().into_iter().map(|t| t.path).collect();
+        assert_eq!(indexed, new);
+
+        // The old copy waits in the trash, for the upgrade to be rolled back
+        let upgrade = tx_log().read_all().unwrap().pop().unwrap();
+        assert_eq!(upgrade.operation, "upgrade");
+        let trashed: Vec<PathBuf> = trash()
+            .list()
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.transaction == Some(upgrade.id))
+            .map(|e| e.original)
+            .collect();
+        assert_eq!(trashed, old);
+
+        run(&["--rollback", &upgrade.id.to_string(), "--noconfirm"]);
+        assert!(old.iter().all(|p| p.exists()));
+        assert!(new.iter().all(|p| !p.exists()));
+    }
+
+    #[test]
+    fn test_upgrade_keeps_equal_or_better_album() {
+        let _env = scratch_env();
+        for title in ["Equal", "Lower"] {
+            let album = scratch_library().join("Upgrade Band").join(title);
+            index_paths(&write_album(&album, "Upgrade Band", title, "flac"));
+            let before = (files_under(&scratch_library()), tx_log().read_all().unwrap().len());
+
+            run(&["-Su", "--noconfirm", album.to_str().unwrap()]);
+            let after = (files_under(&scratch_library()), tx_log().read_all().unwrap().len());
+            assert_eq!(after, before, "{} was replaced", title);
+        }
+    }
+}
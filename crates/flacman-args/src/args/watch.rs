@@ -0,0 +1,319 @@
+use super::*;
+
+
+/// How `--watch` brings inbox files into the library: the transfer flag
+/// given, else the configured transfer mode, else move
+fn watch_transfer(matches: &ArgMatches) -> TransferMode {
+    let flags = [
+        ("move", TransferMode::Move),
+        ("copy", TransferMode::Copy),
+        ("symlink", TransferMode::Symlink),
+        ("reflink", TransferMode::Reflink),
+    ];
+    if let Some(&(_, mode)) = flags.iter().find(|(flag, _)| matches.get_flag(flag)) {
+        return mode;
+    }
+    match config().transfer {
+        Some(DefaultTransfer::Copy) => TransferMode::Copy,
+        Some(DefaultTransfer::Symlink) => TransferMode::Symlink,
+        Some(DefaultTransfer::Reflink) => TransferMode::Reflink,
+        Some(DefaultTransfer::Move) | None => TransferMode::Move,
+    }
+}
+
+/// Auto-import service: watch the inbox directories in `targets` and file
+/// every album that finishes arriving into `library`
+///
+/// Complete files are grouped into albums per directory, run through the
+/// verification stage, identified from their tags and brought into place
+/// by the profile's naming template with [`watch_transfer`]'s mode. Albums
+/// identified with less than `--min-confidence` are held in
+/// `<inbox>/.review` instead. Each album is logged as a transaction, and
+/// every batch is notified.
+///
+/// The inboxes are rescanned when the operating system reports a change
+/// to them, and while files are still arriving; where it can't report
+/// changes, they are polled instead.
+pub fn watch_inboxes(matches: &ArgMatches, library: &str, targets: &[&String], verbose: bool) {
+    if targets.is_empty() {
+        eprintln!("Error: No inbox directories specified");
+        process::exit(1);
+    }
+
+    let debounce = Duration::from_secs(matches.get_one::<u64>("debounce").copied().unwrap_or(30));
+    let mut watcher = match InboxWatcher::new(targets, debounce) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let import = AutoImport {
+        library: std::path::absolute(library).unwrap_or_else(|_| PathBuf::from(library)),
+        template: naming_template(matches),
+        mode: watch_transfer(matches),
+        min_confidence: matches.get_one::<u8>("min-confidence").copied().unwrap_or(80),
+        on_conflict: matches.get_one::<ConflictStrategy>("on-conflict").copied().unwrap_or_default(),
+        trash: Some(trash()),
+        checksum: matches.get_flag("checksum"),
+        volumes: volume_set(),
+        sanitize: config().sanitize.clone(),
+        sidecars: config().sidecars.clone(),
+    };
+    let stage = verify_stage(matches);
+    let once = matches.get_flag("once");
+
+    let events = if once {
+        None
+    } else {
+        LibraryWatcher::new(watcher.dirs())
+            .map_err(|e| eprintln!("Warning: polling the inboxes instead: {}", e))
+            .ok()
+    };
+    if !once {
+        println!("Watching {} inbox(es), importing into {}", targets.len(), import.library.display());
+    }
+
+    let cancel = cancel_flag();
+    loop {
+        let ready = watcher.poll();
+        if !ready.is_empty() {
+            let started = Instant::now();
+            let total = ready.len();
+            let lock = lock_database(true);
+            let dirs: Vec<PathBuf> = ready.keys().cloned().collect();
+            if !run_hooks(HookWhen::PreTransaction, HookOperation::Import, &dirs) {
+                println!("Left {} directories in the inbox until flacman watches again", dirs.len());
+                continue;
+            }
+            let mut summary = import_ready(&import, &stage, watcher.dirs(), ready, cancel, verbose);
+            drop(lock);
+            let imported = take_imported();
+            update_mpd(&imported);
+            run_hooks(HookWhen::PostTransaction, HookOperation::Import, &imported);
+            summary.elapsed = started.elapsed();
+            notify_finished(matches, &summary);
+
+            if cancel.load(Ordering::Relaxed) && summary.succeeded + summary.failed < total {
+                exit_cancelled(summary.succeeded, total, "the rest stay in the inbox for the next run");
+            }
+        }
+
+        if cancel.load(Ordering::Relaxed) {
+            println!("Stopped watching");
+            break;
+        }
+        if once && watcher.pending() == 0 {
+            break;
+        }
+
+        let settle = debounce.clamp(Duration::from_millis(100), WATCH_POLL_INTERVAL);
+        match &events {
+            // Files still arriving are checked again once they may have settled
+            Some(events) if watcher.pending() == 0 => wait_for_changes(events, WATCH_RESCAN_INTERVAL, cancel),
+            _ => std::thread::sleep(settle),
+        }
+    }
+}
+
+/// Block until something in `events`' directories changes, `timeout`
+/// passes or `cancel` is set
+fn wait_for_changes(events: &LibraryWatcher, timeout: Duration, cancel: &AtomicBool) {
+    let deadline = Instant::now() + timeout;
+    while !cancel.load(Ordering::Relaxed) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || !events.changes(left.min(Duration::from_millis(500))).is_empty() {
+            break;
+        }
+    }
+}
+
+/// Import the albums in one batch of complete inbox files
+///
+/// # Arguments
+/// * `inboxes` - The watched directories, to find each group's review area
+/// * `ready` - Complete files by the directory they sit in
+/// * `cancel` - Checked between directories; the ones left stay in the inbox
+fn import_ready(
+    import: &AutoImport,
+    stage: &VerifyStage,
+    inboxes: &[PathBuf],
+    ready: std::collections::BTreeMap<PathBuf, Vec<PathBuf>>,
+    cancel: &AtomicBool,
+    verbose: bool,
+) -> Summary {
+    let mut summary = Summary::new("auto-import");
+
+    for (dir, files) in ready {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        let item = dir.display().to_string();
+        if !verify_item(stage, &item, false, verbose) {
+            summary.failed += 1;
+            summary.details.push(format!("{}: vetoed by verification", item));
+            continue;
+        }
+
+        let mut tracks = Vec::new();
+        for path in files {
+            match MediaFile::new(&path).read() {
+                Ok(metadata) => {
+                    let metadata = metadata.clone();
+                    tracks.push(AlbumTrack { path, metadata });
+                }
+                Err(e) => eprintln!("Warning: skipped {}: {}", path.display(), e),
+            }
+        }
+
+        let inbox = inboxes.iter().find(|inbox| dir.starts_with(inbox)).unwrap_or(&dir);
+        let review_dir = inbox.join(".review");
+        let source = SourceInfo::load(&dir).unwrap_or_else(|e| {
+            eprintln!("Warning: ignoring unreadable {} in {}: {}", SOURCE_SIDECAR, item, e);
+            None
+        });
+
+        for album in group_albums(tracks) {
+            // Taken before the files move, since a copy doesn't keep them
+            let arrived: Vec<Option<DateTime<Local>>> = album
+                .tracks()
+                .map(|t| t.path.metadata().and_then(|m| m.modified()).ok().map(DateTime::<Local>::from))
+                .collect();
+            let name = if album.artist.is_empty() && album.title.is_empty() {
+                item.clone()
+            } else {
+                format!("{} - {}", album.artist, album.title)
+            };
+            let mut record = TxRecord::new("auto-import", Vec::new(), TxOutcome::Success);
+            record.source = Some(item.clone());
+
+            match import.import(&album, &review_dir, &mut ask_conflict) {
+                Ok(ImportOutcome::Imported(paths)) => {
+                    record_provenance(&album, &arrived, &paths, source.as_ref());
+                    index_paths(&paths);
+                    note_imported(&paths);
+                    println!("Imported {} ({} tracks)", name, paths.len());
+                    if verbose {
+                        for path in &paths {
+                            println!("    {}", path.display());
+                        }
+                    }
+                    record.files = paths.len() as u64;
+                    record.bytes = paths.iter().filter_map(|p| p.metadata().ok()).map(|m| m.len()).sum();
+                    record.targets = paths.iter().map(|p| p.display().to_string()).collect();
+                    record.changes = import_changes(import.mode, &album, &paths);
+                    summary.succeeded += 1;
+                }
+                Ok(ImportOutcome::Held { dir: held, identification }) => {
+                    println!(
+                        "Held {} for review in {} (confidence {}%: {})",
+                        name,
+                        held.display(),
+                        identification.confidence,
+                        identification.problems.join(", ")
+                    );
+                    record.outcome = TxOutcome::Vetoed;
+                    record.targets = vec![held.display().to_string()];
+                    record.messages = identification.problems;
+                    summary.failed += 1;
+                    summary.details.push(format!("{}: held for review in {}", name, held.display()));
+                }
+                Ok(ImportOutcome::Resolved { paths, existing, resolution }) => {
+                    record_provenance(&album, &arrived, &paths, source.as_ref());
+                    index_paths(&paths);
+                    note_imported(&paths);
+                    let decision = match &resolution.decision {
+                        ConflictDecision::Skip => "Kept the library copy of",
+                        ConflictDecision::Replace => "Replaced",
+                        ConflictDecision::KeepBoth(_) => "Added another edition of",
+                    };
+                    println!("{} {} ({}: {})", decision, name, resolution.strategy, resolution.reason);
+
+                    record.files = paths.len() as u64;
+                    record.bytes = paths.iter().filter_map(|p| p.metadata().ok()).map(|m| m.len()).sum();
+                    record.targets = paths.iter().map(|p| p.display().to_string()).collect();
+                    record.changes = import_changes(import.mode, &album, &paths);
+                    record.messages = vec![
+                        format!("conflict with {}", existing.display()),
+                        format!("{}: {}", resolution.strategy, resolution.reason),
+                    ];
+                    if resolution.decision == ConflictDecision::Skip {
+                        record.outcome = TxOutcome::Vetoed;
+                        record.targets = vec![existing.display().to_string()];
+                        summary.details.push(format!("{}: already in the library, {}", name, resolution.reason));
+                    } else {
+                        summary.succeeded += 1;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error: {}: {}", name, e);
+                    record.outcome = TxOutcome::Failed;
+                    record.messages = vec![e.to_string()];
+                    summary.failed += 1;
+                    summary.details.push(format!("{}: {}", name, e));
+                }
+            }
+
+            log_transaction(record);
+        }
+    }
+
+    summary
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::tests::{parse, run, scratch_env, scratch_library, write_album};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_watch_transfer() {
+        let _env = scratch_env();
+        let transfer = |args: &[&str]| watch_transfer(&parse(args).unwrap());
+        assert_eq!(transfer(&["--watch", "/music", "--copy", "/inbox"]), TransferMode::Copy);
+        assert_eq!(transfer(&["--watch", "/music", "--reflink"]), TransferMode::Reflink);
+        // The scratch config sets no transfer mode
+        assert_eq!(transfer(&["--watch", "/music", "/inbox"]), TransferMode::Move);
+    }
+
+    #[test]
+    fn test_watch_once_imports_configured_inboxes() {
+        let _env = scratch_env();
+        let inbox = &config().inboxes[0];
+        let dropped = write_album(&inbox.join("drop"), "Watch Band", "Inbox", "flac");
+
+        let library = scratch_library();
+        run(&["--watch", library.to_str().unwrap(), "--once", "--debounce", "0", "--min-confidence", "0"]);
+        assert!(library.join("Watch Band/Inbox/01 One.flac").exists());
+        assert!(library.join("Watch Band/Inbox/02 Two.flac").exists());
+        assert!(dropped.iter().all(|p| !p.exists()));
+        let logged = tx_log().read_all().unwrap().pop().unwrap();
+        assert_eq!((logged.operation.as_str(), logged.files), ("auto-import", 2));
+    }
+
+    #[test]
+    fn test_wait_for_changes() {
+        let dir = tempdir().unwrap();
+        let events = LibraryWatcher::new(&[dir.path()]).unwrap();
+
+        let cancel = AtomicBool::new(true);
+        let started = Instant::now();
+        wait_for_changes(&events, Duration::from_secs(30), &cancel);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // Woken by the file arriving rather than the timeout
+        let cancel = AtomicBool::new(false);
+        let path = dir.path().join("01.flac");
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            std::fs::write(path, b"fLaC").unwrap();
+        });
+        let started = Instant::now();
+        wait_for_changes(&events, Duration::from_secs(30), &cancel);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        writer.join().unwrap();
+    }
+}
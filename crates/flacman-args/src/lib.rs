@@ -1,9 +1,29 @@
-use crate::args::{build_cli, handle_matches};
-
 mod args;
+mod logging;
+
+pub use args::{build_cli, handle_matches};
 
 #[test]
 fn test() {
+    let _env = args::tests::scratch_env();
     let argsz = build_cli().get_matches();
     handle_matches(&argsz);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_pacman_syntax() {
+    build_cli().debug_assert();
+    for args in [&["flacman", "-Ss", "low"][..], &["flacman", "sync", "--search", "low"], &["flacman", "download", "-s", "low"]] {
+        let matches = build_cli().get_matches_from(args);
+        let (operation, sync) = matches.subcommand().unwrap();
+        assert_eq!(operation, "sync");
+        assert!(sync.get_flag("search"));
+        assert_eq!(sync.get_many::<String>("targets").unwrap().collect::<Vec<_>>(), ["low"]);
+    }
+
+    // Shared options work on either side of the operation
+    let matches = build_cli().get_matches_from(["flacman", "-v", "-Qy", "--json"]);
+    assert_eq!(matches.subcommand_matches("query").unwrap().get_count("verbose"), 1);
+    assert!(build_cli().try_get_matches_from(["flacman", "-QS"]).is_err());
+    assert!(build_cli().try_get_matches_from(["flacman", "-Q", "--nosave"]).is_err());
+}
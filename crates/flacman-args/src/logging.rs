@@ -0,0 +1,102 @@
+use std::fs::OpenOptions;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::Mutex;
+
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::prelude::*;
+
+
+/// Environment variable with a filter that overrides `-v`, in the syntax
+/// of `RUST_LOG`, e.g. `FLACMAN_LOG=flacman_remote=trace`
+pub const LOG_ENV: &str = "FLACMAN_LOG";
+
+/// The crates whose events are shown at the level `-v` asks for; other
+/// crates only get to warn
+const CRATES: &[&str] = &[
+    "flacman",
+    "flacman_args",
+    "flacman_config",
+    "flacman_convert",
+    "flacman_core",
+    "flacman_fs",
+    "flacman_mb",
+    "flacman_registry",
+    "flacman_remote",
+    "flacman_tag",
+];
+
+/// What `-v` given `count` times shows on stderr: warnings without it,
+/// then info, debug and trace events
+pub fn stderr_level(count: u8) -> LevelFilter {
+    match count {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Filter directives showing the events of flacman's crates at `level`
+fn directives(level: LevelFilter) -> String {
+    let crates: Vec<String> = CRATES.iter().map(|name| format!("{}={}", name, level)).collect();
+    format!("warn,{}", crates.join(","))
+}
+
+fn filter(level: LevelFilter) -> EnvFilter {
+    EnvFilter::try_from_env(LOG_ENV).unwrap_or_else(|_| EnvFilter::new(directives(level)))
+}
+
+/// Send log events to stderr and, with `log_file`, append them there as
+/// JSON lines
+///
+/// The log file gets debug events at least, each with the spans it
+/// happened in, and a line with the duration of every span that closes.
+/// Only the first call in a process takes effect.
+///
+/// # Errors
+/// The log file can't be opened; stderr logging is set up all the same
+pub fn init_logging(verbosity: u8, log_file: Option<&Path>) -> std::io::Result<()> {
+    let level = stderr_level(verbosity);
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .without_time()
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_target(verbosity >= 2)
+        .with_filter(filter(level));
+
+    let file = log_file.map(|path| OpenOptions::new().create(true).append(true).open(path)).transpose();
+    let (file, error) = match file {
+        Ok(file) => (file, None),
+        Err(e) => (None, Some(e)),
+    };
+    let file = file.map(|file| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_span_list(true)
+            .with_current_span(false)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_writer(Mutex::new(file))
+            .with_filter(filter(level.max(LevelFilter::DEBUG)))
+    });
+
+    let _ = tracing_subscriber::registry().with(stderr).with(file).try_init();
+    error.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(stderr_level(0), LevelFilter::WARN);
+        assert_eq!(stderr_level(5), LevelFilter::TRACE);
+        assert_eq!(stderr_level(1).max(LevelFilter::DEBUG), LevelFilter::DEBUG);
+
+        let directives = directives(LevelFilter::DEBUG);
+        assert!(directives.starts_with("warn,flacman=debug,"));
+        assert!(directives.parse::<EnvFilter>().is_ok());
+    }
+}
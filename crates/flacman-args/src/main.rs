@@ -1,7 +1,15 @@
+use std::process::ExitCode;
+
 use crate::args::handle_matches;
 
 mod args;
-fn main() {
+mod logging;
+fn main() -> ExitCode {
+    // Downloads re-execute flacman as their own user to make a request
+    if let Some(code) = flacman_core::serve_download_child() {
+        return code;
+    }
+
     let matches = args::build_cli().get_matches();
-    handle_matches(&matches);
+    handle_matches(&matches)
 }
\ No newline at end of file
@@ -0,0 +1,399 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flacman_core::{ArtPolicy, Collation, Schedule, TrackFilter};
+use flacman_fs::{SanitizePolicy, SidecarPolicy};
+use flacman_remote::{RankKey, SourceConfig};
+use serde::{Deserialize, Serialize};
+
+use crate::configerror::{ConfigError, Result};
+
+
+/// Written by `--config` when there is no config file yet
+const TEMPLATE: &str = r#"# flacman configuration
+#
+# Every setting is optional; command-line flags win over what is set here.
+
+# Library (repository) root, used when a command is given no targets
+# library = "~/Music"
+
+# Further library roots, e.g. a fast disk for recent albums next to a NAS
+# for the archive. -U, --watch and -Su file each album on the first root
+# whose rule it matches (a --filter expression over the album's tags plus
+# `age`, the days since it was added), else on the first root without a
+# rule; queries, --validate-local and --reindex cover every root. Roots
+# added with --add-volume join these; flacman move-root moves albums
+# between them.
+# [[roots]]
+# name = "ssd"
+# path = "/mnt/ssd/music"
+# rule = "age<=90"
+# [[roots]]
+# name = "nas"
+# path = "/mnt/nas/music"
+
+# How -U and --watch bring files into the library when none of -m, -c,
+# --symlink or --reflink is given: "copy", "move", "symlink" or "reflink".
+# Defaults to copy for -U and move for --watch.
+# transfer = "copy"
+
+# Format -S downloads when -f isn't given (flac, mp3, opus, ...)
+# format = "flac"
+
+# Where -S places downloaded albums, one directory each; point --watch at
+# it to import them. Defaults to downloads/ in flacman's data directory.
+# downloads = "~/Music/Inbox"
+
+# Where -R puts what it removes until it is restored or purged, one
+# directory per transaction, which --rollback undoes. Defaults to
+# .flacman/trash in the library, where removing is an instant rename
+# rather than a copy; else trash/ in flacman's data directory. Batches
+# older than `retention_days` are purged by the next -R.
+# [trash]
+# path = "/srv/scratch/flacman-trash"
+# retention_days = 30
+
+# Inbox directories --watch imports from when given no targets
+# inboxes = ["~/Music/Inbox", "/srv/incoming"]
+
+# Where -U and --watch place tracks in the library, relative to its root;
+# the file's extension is added. Defaults to the profile's layout.
+# template = "%albumartist%/%album%%{year: (%year%)}/%track:02% %title%"
+
+# How names -U and --watch render from tags are made safe for the
+# filesystem: `replacement` stands in for characters a name can't have,
+# `ascii` transliterates (Sigur Rós to Sigur Ros), `max_length` caps each
+# name in bytes and `windows_safe` also keeps to what FAT and NTFS take
+# (on by default on Windows), for a library that is copied to such drives.
+# [sanitize]
+# replacement = "_"
+# ascii = false
+# max_length = 255
+# windows_safe = true
+
+# Non-audio files -U and --watch bring along from an album's directory
+# into its library directory, keeping their paths below it: globs matched
+# case-insensitively against those paths, e.g. "Scans/*". A file has to
+# match `include` and not `exclude`; an empty `include` leaves them all
+# behind. Files whose place in the library is taken stay where they are.
+# [sidecars]
+# include = ["*.jpg", "*.jpeg", "*.png", "*.cue", "*.log", "*.pdf", "*.lrc"]
+# exclude = ["*.log"]
+
+# How listings order artist, album and track names: `natural` compares
+# numbers by value (Track 2 before Track 10), `fold_diacritics` ignores
+# accents and case, and `fold_articles` sorts a name without a leading
+# word from `articles` (The Beatles under B)
+# [collation]
+# natural = true
+# fold_diacritics = true
+# fold_articles = true
+# articles = ["The", "A", "An"]
+
+# Never change the library: only queries, exports and playlists run, as
+# with --read-only. For a mounted backup or someone else's share.
+# read_only = true
+
+# Cover art storage per profile: "embedded" keeps the full cover in every
+# track; "shared" keeps it once as folder.jpg and embeds a thumbnail no
+# larger than `thumbnail` pixels (0 for none), applied by --dedup-art.
+# `max_size` caps the art --embed-art embeds and --resize-art leaves
+# embedded, in pixels on the longer side (0 for no cap)
+# [art]
+# max_size = 1200
+#
+# [art.default]
+# mode = "shared"
+# thumbnail = 300
+#
+# [art.profiles.portable]
+# mode = "embedded"
+
+# Hours heavy operations may run, so they don't compete with daytime use
+# of the same disks and connection; outside them, -S downloads and
+# --validate-local scrubs are deferred unless --now is given, and a
+# download stops starting tracks once its window closes
+# [schedule]
+# downloads = "01:00-07:00"
+# scrubs = "01:00-07:00"
+
+# Remote sources -S can download from, tried in this order unless
+# --source picks some; "mirror" is another flacman's --publish, "archive"
+# the Internet Archive (archive.org), optionally limited to a collection,
+# and "ytdlp" what -S -t downloads URLs with (yt-dlp, unless configured)
+# [[sources]]
+# name = "nas"
+# kind = "mirror"
+# url = "http://nas.local:8080"
+# [[sources]]
+# name = "archive"
+# kind = "archive"
+# options = { collection = "etree", formats = "Flac,VBR MP3" }
+
+# How -S ranks the releases an album target matches, across sources, when
+# asking which to download; --noconfirm takes the first. Each key breaks
+# the ties of the one before: "match" (artist and album before the title
+# alone), "source" (the order above), "quality" (the quality ladder),
+# "newest", "oldest", "largest" or "smallest".
+# rank = ["match", "source", "quality", "largest"]
+
+# MPD to keep current: after -U, --watch and -R change the library, its
+# database is updated for just the directories that changed. -Q
+# --now-playing asks it what is playing either way. `host` and `port`
+# default to MPD_HOST and MPD_PORT, then localhost:6600; `host` may be a
+# socket path and start with `password@`. `music_directory` is MPD's
+# music_directory if it isn't the library root, e.g. a different mount.
+# [mpd]
+# host = "localhost"
+# port = 6600
+# music_directory = "/srv/music"
+"#;
+
+/// How `-U` brings files in when no transfer flag is given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultTransfer {
+    Copy,
+    Move,
+    Symlink,
+    /// Copy-on-write clones; Btrfs and XFS only
+    Reflink,
+}
+
+/// The `[mpd]` section of flacman.conf
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MpdConfig {
+    /// `MPD_HOST` style host name or socket path
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// MPD's music directory, if not the library root
+    pub music_directory: Option<PathBuf>,
+}
+
+/// The `[trash]` section of flacman.conf
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrashConfig {
+    /// Where `-R` moves removed files; `None` keeps them in the library
+    pub path: Option<PathBuf>,
+    /// Days removed files stay restorable
+    pub retention_days: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        TrashConfig { path: None, retention_days: 30 }
+    }
+}
+
+/// One `[[roots]]` entry of flacman.conf: a storage root of a library
+/// split across disks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RootConfig {
+    pub name: String,
+    pub path: PathBuf,
+    /// Albums the root should hold; without one it takes everything no
+    /// other root claims
+    #[serde(default)]
+    pub rule: Option<TrackFilter>,
+}
+
+/// Settings from `flacman.conf`
+///
+/// Anything left out keeps its built-in default, so an empty or missing
+/// file is a valid configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Library root; a leading `~/` is the home directory
+    pub library: Option<PathBuf>,
+    /// Further roots the library is split across, in placement order
+    pub roots: Vec<RootConfig>,
+    pub transfer: Option<DefaultTransfer>,
+    /// Download format, e.g. `flac`
+    pub format: Option<String>,
+    /// Where `-S` places downloaded albums
+    pub downloads: Option<PathBuf>,
+    /// Inbox directories `--watch` imports from when given no targets
+    pub inboxes: Vec<PathBuf>,
+    /// Where `-R` moves removed files and for how long
+    pub trash: TrashConfig,
+    /// Library path template for imports, e.g. `%albumartist%/%album%/%track:02% %title%`
+    pub template: Option<String>,
+    /// How names rendered by `template` are made safe
+    pub sanitize: SanitizePolicy,
+    /// Which non-audio files go along with imported albums
+    pub sidecars: SidecarPolicy,
+    /// How listings order names
+    pub collation: Collation,
+    /// Refuse every operation that writes to the library
+    pub read_only: bool,
+    pub art: ArtPolicy,
+    /// When downloads and scrubs may run
+    pub schedule: Schedule,
+    /// Remote sources for `-S`, in order of preference
+    pub sources: Vec<SourceConfig>,
+    /// How `-S` ranks the releases a target matches; empty for [`RankKey::DEFAULT`]
+    pub rank: Vec<RankKey>,
+    /// MPD to update after library changes
+    pub mpd: Option<MpdConfig>,
+}
+
+/// Where the config file is: `$FLACMAN_CONFIG`, else `flacman.conf` in
+/// `$XDG_CONFIG_HOME/flacman` or `~/.config/flacman`
+pub fn config_path() -> PathBuf {
+    if let Some(path) = env::var_os("FLACMAN_CONFIG").filter(|p| !p.is_empty()) {
+        return PathBuf::from(path);
+    }
+
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    base.join("flacman").join("flacman.conf")
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+impl Config {
+    /// Load the config from [`config_path`]
+    pub fn load() -> Result<Self> {
+        Self::load_from(&config_path())
+    }
+
+    /// Load the config from `path`; a missing file gives the defaults
+    ///
+    /// # Errors
+    /// * `ConfigError::Parse` - The file isn't valid TOML or has unknown settings
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut config: Config =
+            toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e.message().to_owned()))?;
+        config.library = config.library.as_deref().map(expand_home);
+        config.downloads = config.downloads.as_deref().map(expand_home);
+        config.inboxes = config.inboxes.iter().map(|p| expand_home(p)).collect();
+        for root in &mut config.roots {
+            root.path = expand_home(&root.path);
+        }
+        config.trash.path = config.trash.path.as_deref().map(expand_home);
+        if let Some(mpd) = &mut config.mpd {
+            mpd.music_directory = mpd.music_directory.as_deref().map(expand_home);
+        }
+
+        Ok(config)
+    }
+
+    /// Write a commented template to `path` unless a file is already there
+    ///
+    /// # Returns
+    /// Whether the template was written
+    pub fn create_template(path: &Path) -> Result<bool> {
+        if path.exists() {
+            return Ok(false);
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, TEMPLATE)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("flacman.conf");
+        assert_eq!(Config::load_from(&path).unwrap(), Config::default());
+
+        assert!(Config::create_template(&path).unwrap());
+        assert!(!Config::create_template(&path).unwrap());
+        assert_eq!(Config::load_from(&path).unwrap(), Config::default());
+
+        let text = "library = \"/srv/music\"\ntransfer = \"move\"\nformat = \"opus\"\ntemplate = \"%album%/%title%\"\n";
+        fs::write(&path, format!("{text}read_only = true\ndownloads = \"/srv/inbox\"\n")).unwrap();
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.library.as_deref(), Some(Path::new("/srv/music")));
+        assert_eq!(config.transfer, Some(DefaultTransfer::Move));
+        assert_eq!(config.format.as_deref(), Some("opus"));
+        assert_eq!(config.template.as_deref(), Some("%album%/%title%"));
+        assert!(config.read_only);
+        assert_eq!(config.downloads.as_deref(), Some(Path::new("/srv/inbox")));
+        fs::write(&path, "inboxes = [\"/srv/inbox\", \"/srv/incoming\"]\n").unwrap();
+        assert_eq!(Config::load_from(&path).unwrap().inboxes, [Path::new("/srv/inbox"), Path::new("/srv/incoming")]);
+        fs::write(&path, "[trash]\npath = \"/srv/music/.flacman/trash\"\n").unwrap();
+        let trash = Config::load_from(&path).unwrap().trash;
+        assert_eq!((trash.path.as_deref(), trash.retention_days), (Some(Path::new("/srv/music/.flacman/trash")), 30));
+        fs::write(&path, "[trash]\nretention_days = 7\n").unwrap();
+        assert_eq!(Config::load_from(&path).unwrap().trash, TrashConfig { path: None, retention_days: 7 });
+
+        fs::write(&path, "[art]\nmax_size = 1200\n\n[art.default]\nmode = \"shared\"\nthumbnail = 300\n").unwrap();
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.art.storage(Some("portable")), flacman_core::ArtStorage::Shared { thumbnail: 300 });
+        assert_eq!(config.art.max_size, 1200);
+
+        fs::write(&path, "[schedule]\ndownloads = \"22:00-06:00\"\n").unwrap();
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.schedule.downloads.map(|w| w.to_string()).as_deref(), Some("22:00-06:00"));
+        assert_eq!(config.schedule.scrubs, None);
+        fs::write(&path, "[schedule]\nscrubs = \"1am-7am\"\n").unwrap();
+        assert!(matches!(Config::load_from(&path), Err(ConfigError::Parse(..))));
+
+        let source = "[[sources]]\nname = \"nas\"\nkind = \"mirror\"\nurl = \"http://nas.local:8080\"\n";
+        fs::write(&path, source).unwrap();
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.sources.len(), 1);
+        assert_eq!((config.sources[0].name.as_str(), config.sources[0].kind.as_str()), ("nas", "mirror"));
+        fs::write(&path, "rank = [\"quality\", \"newest\"]\n").unwrap();
+        assert_eq!(Config::load_from(&path).unwrap().rank, [RankKey::Quality, RankKey::Newest]);
+        fs::write(&path, "[mpd]\nhost = \"/run/mpd/socket\"\n").unwrap();
+        let mpd = Config::load_from(&path).unwrap().mpd.unwrap();
+        assert_eq!((mpd.host.as_deref(), mpd.port, mpd.music_directory), (Some("/run/mpd/socket"), None, None));
+
+        let roots = "[[roots]]\nname = \"ssd\"\npath = \"/mnt/ssd\"\nrule = \"age<=90, genre~jazz\"\n\
+                     [[roots]]\nname = \"nas\"\npath = \"/mnt/nas\"\n";
+        fs::write(&path, roots).unwrap();
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.roots.len(), 2);
+        assert_eq!(config.roots[0].rule.as_ref().map(|r| r.conditions.len()), Some(2));
+        assert_eq!((config.roots[1].path.as_path(), config.roots[1].rule.as_ref()), (Path::new("/mnt/nas"), None));
+        fs::write(&path, "[[roots]]\nname = \"ssd\"\npath = \"/mnt/ssd\"\nrule = \"age\"\n").unwrap();
+        assert!(matches!(Config::load_from(&path), Err(ConfigError::Parse(..))));
+
+        fs::write(&path, "[sanitize]\nreplacement = \"-\"\nascii = true\n").unwrap();
+        let sanitize = Config::load_from(&path).unwrap().sanitize;
+        assert_eq!((sanitize.replacement, sanitize.ascii, sanitize.max_length), ('-', true, 255));
+        fs::write(&path, "[sanitize]\nreplacement = \"--\"\n").unwrap();
+        assert!(matches!(Config::load_from(&path), Err(ConfigError::Parse(..))));
+        fs::write(&path, "[sidecars]\nexclude = [\"*.log\"]\n").unwrap();
+        let sidecars = Config::load_from(&path).unwrap().sidecars;
+        assert_eq!((sidecars.include, sidecars.exclude), (SidecarPolicy::default().include, vec!["*.log".to_owned()]));
+
+        fs::write(&path, "[collation]\narticles = [\"Die\"]\nnatural = false\n").unwrap();
+        let collation = Config::load_from(&path).unwrap().collation;
+        assert_eq!((collation.natural, collation.fold_articles, collation.articles), (false, true, vec!["Die".to_owned()]));
+
+        fs::write(&path, "libary = \"/srv/music\"\n").unwrap();
+        assert!(matches!(Config::load_from(&path), Err(ConfigError::Parse(..))));
+    }
+}
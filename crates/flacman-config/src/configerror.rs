@@ -0,0 +1,14 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid config file {0}: {1}")]
+    Parse(PathBuf, String),
+}
+
+pub type Result<T> = std::result::Result<T, ConfigError>;
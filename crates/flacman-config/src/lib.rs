@@ -0,0 +1,6 @@
+mod configerror;
+mod config;
+
+
+pub use configerror::{ConfigError, Result};
+pub use config::{Config, DefaultTransfer, MpdConfig, RootConfig, TrashConfig, config_path};
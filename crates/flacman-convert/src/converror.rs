@@ -0,0 +1,25 @@
+use thiserror::Error;
+
+
+#[derive(Error, Debug)]
+pub enum ConvertError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Tag error: {0}")]
+    Tag(#[from] lofty::error::LoftyError),
+
+    #[error("unsupported format {0:?}; expected flac, mp3, opus or ogg")]
+    Format(String),
+
+    #[error("ffmpeg is not installed")]
+    MissingFfmpeg,
+
+    #[error("ffmpeg failed: {0}")]
+    Ffmpeg(String),
+
+    #[error("Cue sheet: {0}")]
+    Cue(String),
+}
+
+pub type Result<T> = std::result::Result<T, ConvertError>;
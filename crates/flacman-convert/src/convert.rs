@@ -0,0 +1,400 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::picture::Picture;
+use lofty::probe::Probe;
+use lofty::tag::{Accessor, ItemKey, Tag};
+use tracing::{debug, instrument};
+
+use crate::converror::{ConvertError, Result};
+use crate::cue::{CueSheet, CueTrack, FRAMES_PER_SECOND};
+use crate::target::{AudioFormat, ConvertTarget};
+
+
+/// One file to convert
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConvertJob {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+}
+
+/// A file that failed to convert, and why
+#[derive(Debug, Clone)]
+pub struct ConvertFailure {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+/// Outcome of [`Converter::convert_files`]
+#[derive(Debug, Clone, Default)]
+pub struct ConvertReport {
+    /// Files written, in the order they were finished
+    pub converted: Vec<PathBuf>,
+    pub failures: Vec<ConvertFailure>,
+    /// Cancelled before every job was done
+    pub interrupted: bool,
+}
+
+/// Where each of `files` under `from` goes when converted to `format`
+/// under `to`, keeping the directories below `from`
+///
+/// Files that would be converted onto themselves, as converting in place
+/// to the format they are already in would, are left out.
+pub fn plan_conversion(files: &[PathBuf], from: &Path, to: &Path, format: AudioFormat) -> Vec<ConvertJob> {
+    files
+        .iter()
+        .filter_map(|source| {
+            let relative = source.strip_prefix(from).unwrap_or(source.file_name().map(Path::new)?);
+            let dest = to.join(relative).with_extension(format.extension());
+            (dest != *source).then(|| ConvertJob { source: source.clone(), dest })
+        })
+        .collect()
+}
+
+/// Converts audio files with ffmpeg
+///
+/// Tags are carried over by ffmpeg, embedded pictures by flacman, as
+/// ffmpeg can't put cover art in Ogg files. A file already in the target
+/// format is copied as it is rather than encoded again.
+#[derive(Debug, Clone)]
+pub struct Converter {
+    ffmpeg: PathBuf,
+    target: ConvertTarget,
+}
+
+impl Converter {
+    /// Convert to `target` with the ffmpeg on `PATH`
+    ///
+    /// # Errors
+    /// * `ConvertError::MissingFfmpeg` - ffmpeg isn't installed
+    pub fn new(target: ConvertTarget) -> Result<Self> {
+        let ffmpeg = flacman_core::find_program("ffmpeg").ok_or(ConvertError::MissingFfmpeg)?;
+        Ok(Converter { ffmpeg, target })
+    }
+
+    pub fn target(&self) -> ConvertTarget {
+        self.target
+    }
+
+    /// Convert `job.source` into `job.dest`, creating its directory
+    ///
+    /// The file is written under a temporary name first, so an interrupted
+    /// conversion never leaves a truncated `dest` behind.
+    ///
+    /// # Errors
+    /// * `ConvertError::Ffmpeg` - ffmpeg couldn't decode or encode the file
+    /// * `ConvertError::Tag` - The cover art couldn't be carried over
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(source = %job.source.display(), dest = %job.dest.display(), target = %self.target),
+        err(level = "debug")
+    )]
+    pub fn convert_file(&self, job: &ConvertJob) -> Result<()> {
+        if let Some(parent) = job.dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if AudioFormat::of(&job.source) == Some(self.target.format) {
+            debug!("already in the target format, copying");
+            fs::copy(&job.source, &job.dest)?;
+            return Ok(());
+        }
+
+        let stem = job.dest.file_stem().unwrap_or_default().to_string_lossy();
+        let partial = job.dest.with_file_name(format!(".{}.part.{}", stem, self.target.format.extension()));
+        let converted = self.encode(&job.source, &partial).and_then(|_| copy_pictures(&job.source, &partial));
+        match converted.and_then(|_| Ok(fs::rename(&partial, &job.dest)?)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                Err(e)
+            }
+        }
+    }
+
+    fn encode(&self, source: &Path, dest: &Path) -> Result<()> {
+        self.run_ffmpeg(ffmpeg_args(source, dest, self.target))
+    }
+
+    /// Split the disc image `image`, described by `sheet`, into a file per
+    /// track in `dest`, tagged from the sheet
+    ///
+    /// Each track runs from its `INDEX 01` to the next one's, so a pregap
+    /// ends the track before it as it does on the disc; the first track
+    /// starts with the image, so nothing before it is lost. Tags the sheet
+    /// doesn't have, and cover art, are taken from the image.
+    ///
+    /// # Returns
+    /// The track files, in order
+    ///
+    /// # Errors
+    /// * `ConvertError::Cue` - The sheet isn't of a single image
+    /// * `ConvertError::Ffmpeg` - ffmpeg couldn't cut a track out
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(image = %image.display(), dest = %dest.display()),
+        err(level = "debug")
+    )]
+    pub fn split_image(&self, sheet: &CueSheet, image: &Path, dest: &Path) -> Result<Vec<PathBuf>> {
+        let [file] = sheet.files.as_slice() else {
+            return Err(ConvertError::Cue(format!("{} names more than one file", image.display())));
+        };
+        fs::create_dir_all(dest)?;
+        let image_tag = Probe::open(image)
+            .and_then(|probe| probe.guess_file_type()?.read())
+            .ok()
+            .and_then(|tagged_file| tagged_file.primary_tag().or(tagged_file.first_tag()).cloned());
+
+        let extension = self.target.format.extension();
+        let mut tracks = Vec::new();
+        for (i, track) in file.tracks.iter().enumerate() {
+            let start = if i == 0 { 0 } else { track.start };
+            let end = file.tracks.get(i + 1).map(|next| next.start);
+            let title = track.title.clone().unwrap_or_else(|| format!("Track {}", track.number));
+            let name = format!("{:02} {}.{}", track.number, title.replace(['/', '\\', '\0'], "-"), extension);
+            let path = dest.join(name.trim_start_matches('.'));
+            let partial = dest.join(format!(".{:02}.part.{}", track.number, extension));
+
+            let split = self
+                .run_ffmpeg(split_args(image, start, end, &partial, self.target))
+                .and_then(|_| tag_track(&partial, sheet, track, file.tracks.len(), image_tag.as_ref()))
+                .and_then(|_| copy_pictures(image, &partial))
+                .and_then(|_| Ok(fs::rename(&partial, &path)?));
+            if let Err(e) = split {
+                let _ = fs::remove_file(&partial);
+                return Err(e);
+            }
+            tracks.push(path);
+        }
+        Ok(tracks)
+    }
+
+    fn run_ffmpeg(&self, args: Vec<OsString>) -> Result<()> {
+        debug!(ffmpeg = %self.ffmpeg.display(), ?args, "encoding");
+        let output = Command::new(&self.ffmpeg).args(args).output()?;
+        if output.status.success() {
+            return Ok(());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().map(str::trim).rfind(|l| !l.is_empty()).map(str::to_owned);
+        Err(ConvertError::Ffmpeg(reason.unwrap_or_else(|| format!("exited with {}", output.status))))
+    }
+
+    /// Convert `jobs` on `workers` threads
+    ///
+    /// `on_result` is called from the workers as each job is done, with its
+    /// index and failure, if any. Setting `cancel` lets the running jobs
+    /// finish and starts no more; jobs that fail because of the
+    /// cancellation, as ffmpeg does when it gets the same Ctrl-C, aren't
+    /// reported.
+    pub fn convert_files<F>(
+        &self,
+        jobs: &[ConvertJob],
+        workers: usize,
+        cancel: &AtomicBool,
+        on_result: F,
+    ) -> ConvertReport
+    where
+        F: Fn(usize, &ConvertJob, Option<&ConvertError>) + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let report = Mutex::new(ConvertReport::default());
+
+        thread::scope(|scope| {
+            for _ in 0..workers.max(1) {
+                scope.spawn(|| {
+                    while !cancel.load(Ordering::Relaxed) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(job) = jobs.get(i) else {
+                            break;
+                        };
+
+                        let result = self.convert_file(job);
+                        if result.is_err() && cancel.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        on_result(i, job, result.as_ref().err());
+
+                        let mut report = report.lock().expect("conversion report lock poisoned");
+                        match result {
+                            Ok(()) => report.converted.push(job.dest.clone()),
+                            Err(e) => {
+                                let failure = ConvertFailure { path: job.source.clone(), message: e.to_string() };
+                                report.failures.push(failure);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        let mut report = report.into_inner().expect("conversion report lock poisoned");
+        report.interrupted = report.converted.len() + report.failures.len() < jobs.len();
+        report
+    }
+}
+
+/// Arguments to ffmpeg converting the audio and tags of `source` to
+/// `target` in `dest`
+fn ffmpeg_args(source: &Path, dest: &Path, target: ConvertTarget) -> Vec<OsString> {
+    let mut args: Vec<OsString> = ["-nostdin", "-hide_banner", "-loglevel", "error", "-y", "-i"].map(Into::into).into();
+    args.push(source.into());
+    // Only the audio: cover art is carried over separately
+    for arg in ["-map", "0:a:0", "-map_metadata", "0", "-c:a", target.format.codec()] {
+        args.push(arg.into());
+    }
+    if let Some(kbps) = target.bitrate {
+        args.extend(["-b:a".into(), format!("{}k", kbps).into()]);
+    }
+    args.push(dest.into());
+    args
+}
+
+/// Arguments to ffmpeg cutting the audio from CD frame `start` to `end`,
+/// or the end, out of `image` into `dest` as `target`, without tags
+fn split_args(image: &Path, start: u64, end: Option<u64>, dest: &Path, target: ConvertTarget) -> Vec<OsString> {
+    let mut args: Vec<OsString> = ["-nostdin", "-hide_banner", "-loglevel", "error", "-y"].map(Into::into).into();
+    // Seeking before the input is sample accurate when decoding
+    args.extend(["-ss".into(), seconds(start).into(), "-i".into(), image.into()]);
+    if let Some(end) = end {
+        args.extend(["-t".into(), seconds(end - start).into()]);
+    }
+    for arg in ["-map", "0:a:0", "-map_metadata", "-1", "-c:a", target.format.codec()] {
+        args.push(arg.into());
+    }
+    if let Some(kbps) = target.bitrate {
+        args.extend(["-b:a".into(), format!("{}k", kbps).into()]);
+    }
+    args.push(dest.into());
+    args
+}
+
+/// CD frames as seconds, to the microsecond
+fn seconds(frames: u64) -> String {
+    let micros = frames * 1_000_000 / FRAMES_PER_SECOND;
+    format!("{}.{:06}", micros / 1_000_000, micros % 1_000_000)
+}
+
+/// Tag `path`, cut out of an image, as `track` of `sheet`, falling back
+/// on the `image` tags for what the sheet leaves out
+fn tag_track(path: &Path, sheet: &CueSheet, track: &CueTrack, total: usize, image: Option<&Tag>) -> Result<()> {
+    let mut tagged_file = Probe::open(path)?.guess_file_type()?.read()?;
+    let mut tag = Tag::new(tagged_file.primary_tag_type());
+    let from_image = |key: ItemKey| image.and_then(|tag| tag.get_string(&key)).map(str::to_owned);
+
+    let album_artist = sheet.performer.clone().or_else(|| from_image(ItemKey::AlbumArtist));
+    let album_artist = album_artist.or_else(|| from_image(ItemKey::TrackArtist));
+    let title = track.title.clone().unwrap_or_else(|| format!("Track {}", track.number));
+    let values = [
+        (ItemKey::TrackTitle, Some(title)),
+        (ItemKey::TrackArtist, track.performer.clone().or(album_artist.clone())),
+        (ItemKey::AlbumArtist, album_artist),
+        (ItemKey::AlbumTitle, sheet.title.clone().or_else(|| from_image(ItemKey::AlbumTitle))),
+        (ItemKey::RecordingDate, sheet.date.clone().or_else(|| from_image(ItemKey::RecordingDate))),
+        (ItemKey::Genre, sheet.genre.clone().or_else(|| from_image(ItemKey::Genre))),
+        (ItemKey::Isrc, track.isrc.clone()),
+        (ItemKey::Label, from_image(ItemKey::Label)),
+        (ItemKey::MusicBrainzReleaseId, from_image(ItemKey::MusicBrainzReleaseId)),
+    ];
+    for (key, value) in values {
+        if let Some(value) = value {
+            tag.insert_text(key, value);
+        }
+    }
+    tag.set_track(track.number);
+    tag.set_track_total(total as u32);
+    if let Some(disc) = image.and_then(|tag| tag.disk()) {
+        tag.set_disk(disc);
+    }
+    if let Some(discs) = image.and_then(|tag| tag.disk_total()) {
+        tag.set_disk_total(discs);
+    }
+
+    tagged_file.insert_tag(tag);
+    tagged_file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+/// Embed the pictures of `source` in `dest`, which has none
+fn copy_pictures(source: &Path, dest: &Path) -> Result<()> {
+    let source = Probe::open(source)?.guess_file_type()?.read()?;
+    let pictures: Vec<Picture> =
+        source.primary_tag().or(source.first_tag()).map(|tag| tag.pictures().to_vec()).unwrap_or_default();
+    if pictures.is_empty() {
+        return Ok(());
+    }
+
+    let mut tagged_file = Probe::open(dest)?.guess_file_type()?.read()?;
+    let tag_type = tagged_file.primary_tag_type();
+    if tagged_file.tag(tag_type).is_none() {
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = tagged_file.tag_mut(tag_type).expect("tag was just inserted");
+    for picture in pictures {
+        tag.push_picture(picture);
+    }
+    tagged_file.save_to_path(dest, WriteOptions::default())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_plan_and_args() {
+        let files = [PathBuf::from("/in/Low/Trust/01 Canada.flac"), PathBuf::from("/in/Low/Trust/02.opus")];
+        let jobs = plan_conversion(&files, Path::new("/in"), Path::new("/out"), AudioFormat::Opus);
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].dest, Path::new("/out/Low/Trust/01 Canada.opus"));
+        // In place, a file already in the format stays as it is
+        let jobs = plan_conversion(&files, Path::new("/in"), Path::new("/in"), AudioFormat::Opus);
+        let dest = PathBuf::from("/in/Low/Trust/01 Canada.opus");
+        assert_eq!(jobs, [ConvertJob { source: files[0].clone(), dest }]);
+
+        let target = ConvertTarget { format: AudioFormat::Opus, bitrate: Some(128) };
+        let args = ffmpeg_args(&files[0], &jobs[0].dest, target);
+        let args: Vec<&str> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(args[6], "/in/Low/Trust/01 Canada.flac");
+        assert_eq!(args[7..13], ["-map", "0:a:0", "-map_metadata", "0", "-c:a", "libopus"]);
+        assert_eq!(args[13..], ["-b:a", "128k", "/in/Low/Trust/01 Canada.opus"]);
+    }
+
+    #[test]
+    fn test_split_args() {
+        let target = ConvertTarget { format: AudioFormat::Flac, bitrate: None };
+        let args = split_args(Path::new("disc.flac"), 75 * 90 + 30, Some(75 * 200), Path::new(".02.part.flac"), target);
+        let args: Vec<&str> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(args[5..11], ["-ss", "90.400000", "-i", "disc.flac", "-t", "109.600000"]);
+        assert_eq!(args[15..], ["-c:a", "flac", ".02.part.flac"]);
+        assert_eq!(seconds(1), "0.013333");
+    }
+
+    #[test]
+    fn test_same_format_is_copied() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("01.opus");
+        fs::write(&source, b"not really opus").unwrap();
+        let converter = Converter {
+            ffmpeg: PathBuf::from("/nonexistent/ffmpeg"),
+            target: ConvertTarget { format: AudioFormat::Opus, bitrate: Some(160) },
+        };
+        let jobs = plan_conversion(&[source], dir.path(), &dir.path().join("out"), AudioFormat::Opus);
+
+        let cancel = AtomicBool::new(false);
+        let report = converter.convert_files(&jobs, 2, &cancel, |_, _, failure| assert!(failure.is_none()));
+        assert_eq!(report.converted, [dir.path().join("out/01.opus")]);
+        assert!(!report.interrupted);
+        assert_eq!(fs::read(dir.path().join("out/01.opus")).unwrap(), b"not really opus");
+    }
+}
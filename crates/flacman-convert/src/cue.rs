@@ -0,0 +1,257 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::converror::{ConvertError, Result};
+
+
+/// CD frames per second, the unit of cue sheet times
+pub const FRAMES_PER_SECOND: u64 = 75;
+
+/// Extensions disc images come with
+const IMAGE_EXTENSIONS: &[&str] = &["flac", "wav", "ape", "wv", "tta", "aiff", "aif"];
+
+/// A track of a cue sheet
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub isrc: Option<String>,
+    /// Start of the pregap (`INDEX 00`) in CD frames, if it has one
+    pub pregap: Option<u64>,
+    /// Start of the track proper (`INDEX 01`) in CD frames
+    pub start: u64,
+}
+
+/// A file of a cue sheet and the tracks in it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CueFile {
+    pub name: String,
+    pub tracks: Vec<CueTrack>,
+}
+
+/// A parsed cue sheet
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CueSheet {
+    pub performer: Option<String>,
+    pub title: Option<String>,
+    /// `REM DATE`
+    pub date: Option<String>,
+    /// `REM GENRE`
+    pub genre: Option<String>,
+    pub files: Vec<CueFile>,
+}
+
+impl CueSheet {
+    /// Read the cue sheet at `path`, in UTF-8 or, failing that, Latin-1 as
+    /// older rippers write them
+    ///
+    /// # Errors
+    /// * `ConvertError::Cue` - The sheet doesn't parse
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&data);
+        let text = match std::str::from_utf8(data) {
+            Ok(text) => text.to_owned(),
+            Err(_) => data.iter().map(|&b| char::from(b)).collect(),
+        };
+        text.parse().map_err(|e| match e {
+            ConvertError::Cue(reason) => ConvertError::Cue(format!("{}: {}", path.display(), reason)),
+            e => e,
+        })
+    }
+
+    /// Whether this sheet describes a whole disc in one file, which is
+    /// worth splitting, rather than files that already hold a track each
+    pub fn is_image(&self) -> bool {
+        matches!(self.files.as_slice(), [file] if file.tracks.len() > 1)
+    }
+
+    /// The audio file of an image sheet at `cue`
+    ///
+    /// Sheets often name the `.wav` the disc was ripped to when the image
+    /// was compressed afterwards, so an audio file with the same stem in
+    /// the same directory counts too.
+    pub fn image_path(&self, cue: &Path) -> Option<PathBuf> {
+        let [file] = self.files.as_slice() else {
+            return None;
+        };
+        let dir = cue.parent().unwrap_or(Path::new(""));
+        let named = dir.join(&file.name);
+        if named.is_file() {
+            return Some(named);
+        }
+        let stem = named.file_stem()?.to_owned();
+        let is_image = |path: &Path| {
+            let extension = path.extension().unwrap_or_default();
+            IMAGE_EXTENSIONS.iter().any(|e| extension.eq_ignore_ascii_case(e))
+        };
+        fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .find(|path| path.file_stem() == Some(stem.as_os_str()) && is_image(path))
+    }
+}
+
+impl std::str::FromStr for CueSheet {
+    type Err = ConvertError;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let mut sheet = CueSheet::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let invalid = |what: &str| ConvertError::Cue(format!("line {}: {}", i + 1, what));
+            let (command, rest) = line.trim().split_once(char::is_whitespace).unwrap_or((line.trim(), ""));
+            let rest = rest.trim();
+            let track = sheet.files.last_mut().and_then(|f| f.tracks.last_mut());
+
+            match command.to_ascii_uppercase().as_str() {
+                "REM" => {
+                    let (key, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    match key.to_ascii_uppercase().as_str() {
+                        "DATE" => sheet.date = Some(unquote(value)),
+                        "GENRE" => sheet.genre = Some(unquote(value)),
+                        _ => {}
+                    }
+                }
+                "FILE" => {
+                    // The name is quoted when it has spaces; the file type follows it
+                    let name = match rest.strip_prefix('"') {
+                        Some(quoted) => {
+                            quoted.split_once('"').map(|(name, _)| name).ok_or_else(|| invalid("unclosed quote"))?
+                        }
+                        None => rest.rsplit_once(char::is_whitespace).map_or(rest, |(name, _)| name),
+                    };
+                    sheet.files.push(CueFile { name: name.to_owned(), tracks: Vec::new() });
+                }
+                "TRACK" => {
+                    let file = sheet.files.last_mut().ok_or_else(|| invalid("TRACK before FILE"))?;
+                    let number = rest.split_whitespace().next().and_then(|n| n.parse().ok());
+                    let number = number.ok_or_else(|| invalid("bad track number"))?;
+                    file.tracks.push(CueTrack { number, ..Default::default() });
+                }
+                "INDEX" => {
+                    let track = track.ok_or_else(|| invalid("INDEX outside a track"))?;
+                    let (index, time) = rest.split_once(char::is_whitespace).ok_or_else(|| invalid("bad INDEX"))?;
+                    let frames = parse_time(time.trim()).ok_or_else(|| invalid("bad INDEX time"))?;
+                    match index.parse::<u32>() {
+                        Ok(0) => track.pregap = Some(frames),
+                        Ok(1) => track.start = frames,
+                        Ok(_) => {}
+                        Err(_) => return Err(invalid("bad INDEX number")),
+                    }
+                }
+                "TITLE" => match track {
+                    Some(track) => track.title = Some(unquote(rest)),
+                    None => sheet.title = Some(unquote(rest)),
+                },
+                "PERFORMER" => match track {
+                    Some(track) => track.performer = Some(unquote(rest)),
+                    None => sheet.performer = Some(unquote(rest)),
+                },
+                "ISRC" => {
+                    if let Some(track) = track {
+                        track.isrc = Some(unquote(rest));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if sheet.files.iter().all(|f| f.tracks.is_empty()) {
+            return Err(ConvertError::Cue("no tracks".to_owned()));
+        }
+        Ok(sheet)
+    }
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value).to_owned()
+}
+
+/// `mm:ss:ff` in CD frames
+fn parse_time(time: &str) -> Option<u64> {
+    let mut parts = time.split(':').map(|p| p.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    (parts.next().is_none() && seconds < 60 && frames < FRAMES_PER_SECOND)
+        .then_some((minutes * 60 + seconds) * FRAMES_PER_SECOND + frames)
+}
+
+/// Cue sheets directly in `dir` that describe an image there
+///
+/// # Returns
+/// The path of each sheet, the sheet and the path of its image, by name
+/// of the sheet
+pub fn find_cue_images(dir: &Path) -> Vec<(PathBuf, CueSheet, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut cues: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e.eq_ignore_ascii_case("cue")))
+        .collect();
+    cues.sort();
+
+    cues.into_iter()
+        .filter_map(|cue| {
+            let sheet = CueSheet::load(&cue).ok().filter(CueSheet::is_image)?;
+            let image = sheet.image_path(&cue)?;
+            Some((cue, sheet, image))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const SHEET: &str = r#"REM GENRE "Indie Rock"
+REM DATE 1993
+PERFORMER "Low"
+TITLE "I Could Live in Hope"
+FILE "Low - I Could Live in Hope.wav" WAVE
+  TRACK 01 AUDIO
+    TITLE "Words"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Fear"
+    PERFORMER "Low feat. Nobody"
+    INDEX 00 05:30:50
+    INDEX 01 05:32:00
+"#;
+
+    #[test]
+    fn test_parse_sheet() {
+        let sheet: CueSheet = SHEET.parse().unwrap();
+        assert_eq!(sheet.performer.as_deref(), Some("Low"));
+        assert_eq!(sheet.genre.as_deref(), Some("Indie Rock"));
+        assert_eq!(sheet.date.as_deref(), Some("1993"));
+        assert!(sheet.is_image());
+
+        let tracks = &sheet.files[0].tracks;
+        assert_eq!(sheet.files[0].name, "Low - I Could Live in Hope.wav");
+        assert_eq!(tracks[1].title.as_deref(), Some("Fear"));
+        assert_eq!(tracks[1].performer.as_deref(), Some("Low feat. Nobody"));
+        assert_eq!(tracks[1].pregap, Some((5 * 60 + 30) * 75 + 50));
+        assert_eq!(tracks[1].start, (5 * 60 + 32) * 75);
+
+        assert!(matches!("TRACK 01 AUDIO".parse::<CueSheet>(), Err(ConvertError::Cue(_))));
+        assert!("FILE \"a.wav\" WAVE\nTRACK 01 AUDIO\nINDEX 01 1:61:00".parse::<CueSheet>().is_err());
+    }
+
+    #[test]
+    fn test_find_image_by_stem() {
+        let dir = tempdir().unwrap();
+        // Latin-1, and naming the .wav the image was compressed from
+        let latin1: Vec<u8> = SHEET.replace("Fear", "Fe\u{e4}r").chars().map(|c| c as u8).collect();
+        fs::write(dir.path().join("disc.cue"), latin1).unwrap();
+        fs::write(dir.path().join("Low - I Could Live in Hope.flac"), b"").unwrap();
+
+        let images = find_cue_images(dir.path());
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].2, dir.path().join("Low - I Could Live in Hope.flac"));
+        assert_eq!(images[0].1.files[0].tracks[1].title.as_deref(), Some("Fe\u{e4}r"));
+    }
+}
@@ -0,0 +1,10 @@
+mod converror;
+mod target;
+mod convert;
+mod cue;
+
+
+pub use converror::{ConvertError, Result};
+pub use target::{AudioFormat, ConvertTarget};
+pub use cue::{CueFile, CueSheet, CueTrack, FRAMES_PER_SECOND, find_cue_images};
+pub use convert::{ConvertFailure, ConvertJob, ConvertReport, Converter, plan_conversion};
@@ -0,0 +1,134 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use flacman_core::{QualityLadder, QualityRung};
+
+use crate::converror::{ConvertError, Result};
+
+
+/// A format flacman can convert to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Flac,
+    Mp3,
+    Opus,
+    /// Ogg Vorbis
+    Vorbis,
+}
+
+impl AudioFormat {
+    /// File extension, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Flac => "flac",
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Opus => "opus",
+            AudioFormat::Vorbis => "ogg",
+        }
+    }
+
+    pub fn lossless(self) -> bool {
+        self == AudioFormat::Flac
+    }
+
+    /// The format of `path`, going by its extension
+    pub fn of(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    /// ffmpeg encoder
+    pub(crate) fn codec(self) -> &'static str {
+        match self {
+            AudioFormat::Flac => "flac",
+            AudioFormat::Mp3 => "libmp3lame",
+            AudioFormat::Opus => "libopus",
+            AudioFormat::Vorbis => "libvorbis",
+        }
+    }
+
+    /// Bitrate in kbps when the quality ladder asks for none
+    fn default_bitrate(self) -> Option<u32> {
+        match self {
+            AudioFormat::Flac => None,
+            AudioFormat::Mp3 => Some(320),
+            AudioFormat::Opus => Some(160),
+            AudioFormat::Vorbis => Some(256),
+        }
+    }
+
+    /// Whether `name`, as a quality rung writes it, means this format
+    fn is_named(self, name: &str) -> bool {
+        name == self.extension() || (self == AudioFormat::Vorbis && name == "vorbis")
+    }
+}
+
+impl FromStr for AudioFormat {
+    type Err = ConvertError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "flac" => Ok(AudioFormat::Flac),
+            "mp3" => Ok(AudioFormat::Mp3),
+            "opus" => Ok(AudioFormat::Opus),
+            "ogg" | "vorbis" => Ok(AudioFormat::Vorbis),
+            _ => Err(ConvertError::Format(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for AudioFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// What to convert to: a format and, for lossy ones, a bitrate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConvertTarget {
+    pub format: AudioFormat,
+    /// Bitrate in kbps; `None` for lossless formats
+    pub bitrate: Option<u32>,
+}
+
+impl ConvertTarget {
+    /// Convert to `format` at the bitrate `quality` asks of it
+    ///
+    /// The minimum bitrate of the first rung naming `format` is used, e.g.
+    /// 192 kbps for `-f opus -q "lossless, opus>=192"`. Without one, MP3 is
+    /// encoded at 320 kbps, Opus at 160 and Vorbis at 256.
+    pub fn new(format: AudioFormat, quality: &QualityLadder) -> Self {
+        let asked = quality.rungs.iter().find_map(|rung| match rung {
+            QualityRung::Format { format: name, min_bitrate } if format.is_named(name) => Some(*min_bitrate),
+            _ => None,
+        });
+        let bitrate = if format.lossless() { None } else { asked.flatten().or(format.default_bitrate()) };
+        ConvertTarget { format, bitrate }
+    }
+}
+
+impl fmt::Display for ConvertTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bitrate {
+            Some(kbps) => write!(f, "{} {}kbps", self.format, kbps),
+            None => write!(f, "{}", self.format),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_bitrate() {
+        let ladder: QualityLadder = "lossless, vorbis>=192, opus".parse().unwrap();
+        assert_eq!(ConvertTarget::new("ogg".parse().unwrap(), &ladder).bitrate, Some(192));
+        assert_eq!(ConvertTarget::new(AudioFormat::Opus, &ladder).bitrate, Some(160));
+        assert_eq!(ConvertTarget::new(AudioFormat::Mp3, &ladder).to_string(), "mp3 320kbps");
+        assert_eq!(ConvertTarget::new(AudioFormat::Flac, &ladder).bitrate, None);
+
+        assert_eq!(AudioFormat::of(Path::new("01 Canada.FLAC")), Some(AudioFormat::Flac));
+        assert!(matches!("m4a".parse::<AudioFormat>(), Err(ConvertError::Format(_))));
+    }
+}
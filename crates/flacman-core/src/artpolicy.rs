@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+
+/// How an album's cover art is stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum ArtStorage {
+    /// Every track embeds the full-size cover, as it came
+    #[default]
+    Embedded,
+    /// One `folder.<ext>` next to the tracks holds the full-size cover;
+    /// tracks embed a thumbnail at most `thumbnail` pixels on its longer
+    /// side, or nothing when it is 0
+    Shared { thumbnail: u32 },
+}
+
+/// Art storage of each library profile
+///
+/// Deserializable so it can live as an `[art]` table in flacman.conf:
+///
+/// ```toml
+/// [art]
+/// max_size = 1200
+///
+/// [art.default]
+/// mode = "shared"
+/// thumbnail = 300
+///
+/// [art.profiles.portable]
+/// mode = "embedded"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArtPolicy {
+    /// Longest side, in pixels, of art embedded by `--embed-art` or kept
+    /// embedded by `--resize-art`; 0 for no limit
+    pub max_size: u32,
+    pub default: ArtStorage,
+    pub profiles: HashMap<String, ArtStorage>,
+}
+
+impl ArtPolicy {
+    /// Art storage of `profile`, falling back to the default
+    pub fn storage(&self, profile: Option<&str>) -> ArtStorage {
+        profile.and_then(|p| self.profiles.get(p)).copied().unwrap_or(self.default)
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, TimeZone};
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags, Row, params};
+
+use crate::coreerror::{CoreError, Result};
+use crate::librarydb::TrackRecord;
+
+
+/// Fields beets and flacman both keep as plain tags, under the same names
+const SHARED_TAGS: &[&str] = &[
+    "genre",
+    "tracktotal",
+    "disctotal",
+    "label",
+    "samplerate",
+    "bitdepth",
+    "channels",
+    "mb_trackid",
+    "mb_albumid",
+    "mb_artistid",
+    "mb_albumartistid",
+    "mb_releasegroupid",
+];
+
+/// The subset of beets' schema flacman writes; beets adds the columns it
+/// is missing the first time it opens the library
+const BEETS_SCHEMA: &str = "
+    CREATE TABLE items (
+        id INTEGER PRIMARY KEY,
+        path BLOB,
+        album_id INTEGER,
+        title TEXT, artist TEXT, albumartist TEXT, album TEXT, genre TEXT, label TEXT,
+        year INTEGER, track INTEGER, tracktotal INTEGER, disc INTEGER, disctotal INTEGER, disctitle TEXT,
+        mb_trackid TEXT, mb_albumid TEXT, mb_artistid TEXT, mb_albumartistid TEXT, mb_releasegroupid TEXT,
+        length REAL, samplerate INTEGER, bitdepth INTEGER, channels INTEGER, format TEXT,
+        mtime REAL, added REAL
+    );
+    CREATE TABLE albums (
+        id INTEGER PRIMARY KEY,
+        artpath BLOB,
+        albumartist TEXT, album TEXT, genre TEXT, label TEXT, year INTEGER, disctotal INTEGER,
+        mb_albumid TEXT, mb_albumartistid TEXT, mb_releasegroupid TEXT,
+        added REAL
+    );
+";
+
+/// Read the items of the beets library at `path` as track records
+///
+/// Nothing is rescanned: tags, MusicBrainz IDs and the dates beets added
+/// the files come from its database. Only the size is read from the file,
+/// and is 0 for files that are gone.
+///
+/// # Errors
+/// * `CoreError::Beets` - Not a beets library
+pub fn read_beets_library(path: &Path) -> Result<Vec<TrackRecord>> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let has_items: bool = conn
+        .query_row("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'items'", [], |r| r.get(0))
+        .map_err(|_| CoreError::Beets(format!("{}: not an SQLite database", path.display())))?;
+    if !has_items {
+        return Err(CoreError::Beets(format!("{}: not a beets library", path.display())));
+    }
+
+    let mut stmt = conn.prepare("SELECT * FROM items ORDER BY id")?;
+    let items = stmt.query_map([], item_record)?;
+    Ok(items.collect::<rusqlite::Result<_>>()?)
+}
+
+fn item_record(row: &Row<'_>) -> rusqlite::Result<TrackRecord> {
+    let path = PathBuf::from(text(row, "path").unwrap_or_default());
+    let positive = |column: &str| number(row, column).filter(|n| *n > 0.0).map(|n| n as u32);
+
+    let mut tags = BTreeMap::new();
+    for &key in SHARED_TAGS {
+        let value = match number(row, key) {
+            // beets keeps 0 for numbers it doesn't know
+            Some(n) if n <= 0.0 => None,
+            _ => text(row, key),
+        };
+        if let Some(value) = value {
+            tags.insert(key.to_owned(), value);
+        }
+    }
+    if let Some(subtitle) = text(row, "disctitle") {
+        tags.insert("discsubtitle".to_owned(), subtitle);
+    }
+    if let Some(seconds) = number(row, "length").filter(|s| *s > 0.0) {
+        tags.insert("length".to_owned(), format_length(seconds as u64));
+    }
+
+    let artist = text(row, "artist").unwrap_or_default();
+    let added = number(row, "added").and_then(|t| Local.timestamp_opt(t as i64, 0).single());
+    Ok(TrackRecord {
+        size: path.metadata().map_or(0, |m| m.len()),
+        path,
+        album_artist: text(row, "albumartist").unwrap_or_else(|| artist.clone()),
+        album: text(row, "album").unwrap_or_default(),
+        artist,
+        title: text(row, "title").unwrap_or_default(),
+        disc: positive("disc"),
+        track: positive("track"),
+        year: positive("year"),
+        tags,
+        sha256: None,
+        modified: number(row, "mtime").map_or(0, |t| t as i64),
+        added: added.unwrap_or_else(Local::now),
+    })
+}
+
+/// Column `column` of `row` as text, whatever beets stored it as; `None`
+/// when it is missing, NULL or empty
+fn text(row: &Row<'_>, column: &str) -> Option<String> {
+    let value = match row.get_ref(column).ok()? {
+        // beets keeps paths as bytes
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        ValueRef::Integer(n) => n.to_string(),
+        ValueRef::Real(x) => x.to_string(),
+        ValueRef::Null => return None,
+    };
+    Some(value).filter(|v| !v.trim().is_empty())
+}
+
+fn number(row: &Row<'_>, column: &str) -> Option<f64> {
+    match row.get_ref(column).ok()? {
+        ValueRef::Integer(n) => Some(n as f64),
+        ValueRef::Real(x) => Some(x),
+        ValueRef::Text(text) => std::str::from_utf8(text).ok()?.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Write `tracks` to a new beets library at `path`, grouping them into
+/// albums by album artist and title
+///
+/// # Returns
+/// Number of albums written
+///
+/// # Errors
+/// * `CoreError::Beets` - `path` already exists; beets libraries aren't merged into
+pub fn write_beets_library(path: &Path, tracks: &[TrackRecord]) -> Result<usize> {
+    if path.exists() {
+        return Err(CoreError::Beets(format!("{} already exists", path.display())));
+    }
+    let mut conn = Connection::open(path)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(BEETS_SCHEMA)?;
+
+    let mut albums: HashMap<(&str, &str), i64> = HashMap::new();
+    for track in tracks {
+        let tag = |key: &str| track.tags.get(key).map_or("", String::as_str);
+        let count = |key: &str| tag(key).parse::<u32>().unwrap_or(0);
+        let added = track.added.timestamp() as f64;
+
+        let album_id = if track.album.is_empty() {
+            None
+        } else if let Some(&id) = albums.get(&(track.album_artist.as_str(), track.album.as_str())) {
+            tx.execute("UPDATE albums SET added = min(added, ?1) WHERE id = ?2", params![added, id])?;
+            Some(id)
+        } else {
+            tx.execute(
+                "INSERT INTO albums (artpath, albumartist, album, genre, label, year, disctotal,
+                                     mb_albumid, mb_albumartistid, mb_releasegroupid, added)
+                 VALUES (NULL, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    track.album_artist,
+                    track.album,
+                    tag("genre"),
+                    tag("label"),
+                    track.year.unwrap_or(0),
+                    count("disctotal"),
+                    tag("mb_albumid"),
+                    tag("mb_albumartistid"),
+                    tag("mb_releasegroupid"),
+                    added,
+                ],
+            )?;
+            let id = tx.last_insert_rowid();
+            albums.insert((track.album_artist.as_str(), track.album.as_str()), id);
+            Some(id)
+        };
+
+        let format = track.path.extension().map(|e| e.to_string_lossy().to_uppercase()).unwrap_or_default();
+        tx.execute(
+            "INSERT INTO items (path, album_id, title, artist, albumartist, album, genre, label,
+                                year, track, tracktotal, disc, disctotal, disctitle,
+                                mb_trackid, mb_albumid, mb_artistid, mb_albumartistid, mb_releasegroupid,
+                                length, samplerate, bitdepth, channels, format, mtime, added)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+                     ?20, ?21, ?22, ?23, ?24, ?25, ?26)",
+            params![
+                track.path.to_string_lossy().as_bytes(),
+                album_id,
+                track.title,
+                track.artist,
+                track.album_artist,
+                track.album,
+                tag("genre"),
+                tag("label"),
+                track.year.unwrap_or(0),
+                track.track.unwrap_or(0),
+                count("tracktotal"),
+                track.disc.unwrap_or(0),
+                count("disctotal"),
+                tag("discsubtitle"),
+                tag("mb_trackid"),
+                tag("mb_albumid"),
+                tag("mb_artistid"),
+                tag("mb_albumartistid"),
+                tag("mb_releasegroupid"),
+                parse_length(tag("length")).unwrap_or(0) as f64,
+                count("samplerate"),
+                count("bitdepth"),
+                count("channels"),
+                format,
+                track.modified as f64,
+                added,
+            ],
+        )?;
+    }
+
+    tx.commit()?;
+    Ok(albums.len())
+}
+
+/// Seconds as flacman's `length` tag, `m:ss` or `h:mm:ss`
+fn format_length(seconds: u64) -> String {
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+/// flacman's `length` tag back in seconds
+fn parse_length(length: &str) -> Option<u64> {
+    length.split(':').try_fold(0, |total, part| Some(total * 60 + part.parse::<u64>().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempdir().unwrap();
+        let added = Local.timestamp_opt(1_500_000_000, 0).unwrap();
+        let track = TrackRecord {
+            path: PathBuf::from("/music/Low/Trust/01 Canada.flac"),
+            album_artist: "Low".to_owned(),
+            album: "Trust".to_owned(),
+            artist: "Low".to_owned(),
+            title: "Canada".to_owned(),
+            disc: None,
+            track: Some(1),
+            year: Some(2002),
+            tags: BTreeMap::from([
+                ("genre".to_owned(), "Slowcore".to_owned()),
+                ("length".to_owned(), "1:02:03".to_owned()),
+                ("mb_albumid".to_owned(), "0d2f8b5c-4c9c-4b0e-9e6f-6b1f8a0d2e11".to_owned()),
+            ]),
+            sha256: None,
+            size: 0,
+            modified: 1_400_000_000,
+            added,
+        };
+        let second = TrackRecord { title: "Candy Girl".to_owned(), track: Some(2), ..track.clone() };
+
+        let library = dir.path().join("beets.db");
+        assert_eq!(write_beets_library(&library, &[track.clone(), second]).unwrap(), 1);
+        assert!(matches!(write_beets_library(&library, &[]), Err(CoreError::Beets(_))));
+
+        let read = read_beets_library(&library).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0], track);
+        assert_eq!(read[1].title, "Candy Girl");
+
+        let conn = Connection::open(&library).unwrap();
+        let path: Vec<u8> = conn.query_row("SELECT path FROM items WHERE id = 1", [], |r| r.get(0)).unwrap();
+        assert_eq!(path, b"/music/Low/Trust/01 Canada.flac");
+        assert_eq!(parse_length("4:05"), Some(245));
+    }
+
+    #[test]
+    fn test_not_a_beets_library() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("other.db");
+        Connection::open(&path).unwrap().execute_batch("CREATE TABLE things (id INTEGER)").unwrap();
+        assert!(matches!(read_beets_library(&path), Err(CoreError::Beets(_))));
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::coreerror::Result;
+
+
+/// Progress of a long bulk operation, persisted one finished directory per line
+///
+/// Every [`mark_done`](Checkpoint::mark_done) is flushed to disk before
+/// returning, so an interrupted run can be resumed by opening the same
+/// checkpoint and skipping everything already done.
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    done: HashSet<PathBuf>,
+    file: Option<File>,
+}
+
+impl Checkpoint {
+    /// Stable name for a run over `parts` (operation, targets, ...)
+    ///
+    /// Uses FNV-1a rather than `DefaultHasher` so the name survives
+    /// toolchain upgrades between the interrupted and the resumed run.
+    pub fn key<S: AsRef<str>>(parts: &[S]) -> String {
+        let mut hash: u64 = 0xcbf29ce484222325;
+
+        for part in parts {
+            for byte in part.as_ref().bytes().chain(std::iter::once(0)) {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+
+        format!("{hash:016x}")
+    }
+
+    /// Open a checkpoint, loading the progress of an earlier run if there is one
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut done = HashSet::new();
+
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                if !line.is_empty() {
+                    done.insert(PathBuf::from(line));
+                }
+            }
+        }
+
+        Ok(Checkpoint { path, done, file: None })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_done(&self, dir: &Path) -> bool {
+        self.done.contains(dir)
+    }
+
+    /// Number of directories finished so far
+    pub fn len(&self) -> usize {
+        self.done.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.done.is_empty()
+    }
+
+    /// Record `dir` as finished
+    pub fn mark_done(&mut self, dir: &Path) -> Result<()> {
+        if !self.done.insert(dir.to_path_buf()) {
+            return Ok(());
+        }
+
+        if self.file.is_none() {
+            if let Some(parent) = self.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            self.file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        }
+
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", dir.display())?;
+            file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    /// The run completed; forget its progress
+    pub fn finish(self) -> Result<()> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resume() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("checkpoints").join(Checkpoint::key(&["update", "/downloads"]));
+
+        let mut first = Checkpoint::open(&path).unwrap();
+        first.mark_done(Path::new("/downloads/a")).unwrap();
+        first.mark_done(Path::new("/downloads/b")).unwrap();
+        drop(first);
+
+        let resumed = Checkpoint::open(&path).unwrap();
+        assert_eq!(resumed.len(), 2);
+        assert!(resumed.is_done(Path::new("/downloads/a")));
+        assert!(!resumed.is_done(Path::new("/downloads/c")));
+
+        resumed.finish().unwrap();
+        assert!(!path.exists());
+        assert!(Checkpoint::open(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_key_depends_on_all_parts() {
+        assert_eq!(Checkpoint::key(&["update", "/a"]), Checkpoint::key(&["update", "/a"]));
+        assert_ne!(Checkpoint::key(&["update", "/a"]), Checkpoint::key(&["update", "/b"]));
+        assert_ne!(Checkpoint::key(&["ab", "c"]), Checkpoint::key(&["a", "bc"]));
+    }
+}
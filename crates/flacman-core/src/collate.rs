@@ -0,0 +1,190 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+
+/// How listings order artist, album and track names
+///
+/// Deserializable so it can live as a `[collation]` table in flacman.conf.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Collation {
+    /// Compare digit runs by value ("Track 2" before "Track 10")
+    pub natural: bool,
+    /// Ignore accents and case so "Émilie" sorts next to "Emil"
+    pub fold_diacritics: bool,
+    /// Sort "The Beatles" under B
+    pub fold_articles: bool,
+    /// Leading words dropped when `fold_articles` is on, matched case-insensitively
+    pub articles: Vec<String>,
+}
+
+impl Default for Collation {
+    fn default() -> Self {
+        Collation {
+            natural: true,
+            fold_diacritics: true,
+            fold_articles: true,
+            articles: vec!["The".into(), "A".into(), "An".into()],
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Chunk {
+    // Digits sort before text, like most file managers. Comparing the
+    // length of the zero-stripped run first makes the comparison numeric.
+    Number(usize, String),
+    Text(String),
+}
+
+impl Collation {
+    /// Strip a leading article, e.g. "The Beatles" -> "Beatles"
+    pub fn strip_article<'a>(&self, s: &'a str) -> &'a str {
+        if !self.fold_articles {
+            return s;
+        }
+
+        for article in &self.articles {
+            if let Some(head) = s.get(..article.len())
+                && head.eq_ignore_ascii_case(article)
+                && let Some(rest) = s[article.len()..].strip_prefix(' ')
+                && !rest.trim_start().is_empty()
+            {
+                return rest.trim_start();
+            }
+        }
+
+        s
+    }
+
+    /// Name as it should be displayed in a sorted index, e.g. "Beatles, The"
+    pub fn index_name(&self, s: &str) -> String {
+        let stripped = self.strip_article(s);
+        if stripped.len() == s.len() {
+            return s.to_owned();
+        }
+
+        let article = s[..s.len() - stripped.len()].trim_end();
+        format!("{stripped}, {article}")
+    }
+
+    pub(crate) fn fold(&self, s: &str) -> String {
+        if !self.fold_diacritics {
+            return s.to_owned();
+        }
+
+        let mut folded = String::with_capacity(s.len());
+        for c in s.nfd().filter(|c| !is_combining_mark(*c)) {
+            // Letters that don't decompose into base + accent
+            match c {
+                'ß' => folded.push_str("ss"),
+                'æ' | 'Æ' => folded.push_str("ae"),
+                'œ' | 'Œ' => folded.push_str("oe"),
+                'ø' | 'Ø' => folded.push('o'),
+                'đ' | 'Đ' => folded.push('d'),
+                'ł' | 'Ł' => folded.push('l'),
+                _ => folded.extend(c.to_lowercase()),
+            }
+        }
+
+        folded
+    }
+
+    fn chunks(&self, s: &str) -> Vec<Chunk> {
+        let folded = self.fold(self.strip_article(s));
+
+        if !self.natural {
+            return vec![Chunk::Text(folded)];
+        }
+
+        let mut chunks = Vec::new();
+        let mut current = String::new();
+        let mut in_digits = false;
+
+        for c in folded.chars() {
+            let is_digit = c.is_ascii_digit();
+            if !current.is_empty() && is_digit != in_digits {
+                chunks.push(Self::chunk(std::mem::take(&mut current), in_digits));
+            }
+            in_digits = is_digit;
+            current.push(c);
+        }
+
+        if !current.is_empty() {
+            chunks.push(Self::chunk(current, in_digits));
+        }
+
+        chunks
+    }
+
+    fn chunk(s: String, digits: bool) -> Chunk {
+        if !digits {
+            return Chunk::Text(s);
+        }
+
+        let trimmed = s.trim_start_matches('0');
+        Chunk::Number(trimmed.len(), trimmed.to_owned())
+    }
+
+    /// Compare two names according to this collation
+    ///
+    /// Names that collate equal (e.g. differing only in case) fall back to
+    /// plain string order so sorting stays deterministic.
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        self.chunks(a).cmp(&self.chunks(b)).then_with(|| a.cmp(b))
+    }
+
+    /// Sort `items` by the name returned from `key`
+    pub fn sort_by<T, F>(&self, items: &mut [T], key: F)
+    where
+        F: Fn(&T) -> &str,
+    {
+        items.sort_by(|a, b| self.compare(key(a), key(b)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str], collation: &Collation) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|s| s.to_string()).collect();
+        collation.sort_by(&mut names, |s| s.as_str());
+        names
+    }
+
+    #[test]
+    fn test_natural_numbers() {
+        let names = sorted(&["Track 10", "Track 2", "Track 1"], &Collation::default());
+        assert_eq!(names, ["Track 1", "Track 2", "Track 10"]);
+    }
+
+    #[test]
+    fn test_natural_disabled() {
+        let collation = Collation { natural: false, ..Default::default() };
+        let names = sorted(&["Track 10", "Track 2"], &collation);
+        assert_eq!(names, ["Track 10", "Track 2"]);
+    }
+
+    #[test]
+    fn test_articles() {
+        let names = sorted(&["The Beatles", "Blur", "ABBA", "A Tribe Called Quest"], &Collation::default());
+        assert_eq!(names, ["ABBA", "The Beatles", "Blur", "A Tribe Called Quest"]);
+    }
+
+    #[test]
+    fn test_article_only_name_kept() {
+        let collation = Collation::default();
+        assert_eq!(collation.strip_article("The The"), "The");
+        assert_eq!(collation.strip_article("The"), "The");
+        assert_eq!(collation.index_name("The Beatles"), "Beatles, The");
+    }
+
+    #[test]
+    fn test_diacritics() {
+        let names = sorted(&["Zola", "Émilie Simon", "Eels", "Ólafur Arnalds", "Oasis"], &Collation::default());
+        assert_eq!(names, ["Eels", "Émilie Simon", "Oasis", "Ólafur Arnalds", "Zola"]);
+    }
+}
@@ -0,0 +1,273 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::coreerror::{CoreError, Result};
+
+
+/// What to do when a confirmation is needed but stdin isn't a terminal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WithoutTerminal {
+    /// Fail, so scripts don't go ahead with something nobody agreed to
+    #[default]
+    Refuse,
+    /// Go ahead as if the user had said yes
+    Proceed,
+}
+
+/// How to ask "Proceed? [Y/n]" before changing anything
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Confirm {
+    /// `--noconfirm`: never ask, always go ahead
+    pub noconfirm: bool,
+    pub without_terminal: WithoutTerminal,
+}
+
+/// Yes for an empty answer and anything starting with `y`, no for `n`;
+/// `None` asks again
+fn parse_answer(line: &str) -> Option<bool> {
+    match line.trim().to_lowercase().as_str() {
+        "" | "y" | "yes" => Some(true),
+        "n" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+impl Confirm {
+    /// Ask `question` on the terminal, defaulting to yes
+    ///
+    /// # Returns
+    /// Whether to go ahead
+    ///
+    /// # Errors
+    /// * `CoreError::Prompt` - There is no terminal to ask on and
+    ///   `without_terminal` is `Refuse`
+    pub fn ask(&self, question: &str) -> Result<bool> {
+        let stdin = io::stdin();
+        self.ask_on(question, stdin.is_terminal(), &mut stdin.lock(), &mut io::stdout())
+    }
+
+    fn ask_on(&self, question: &str, terminal: bool, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<bool> {
+        if self.noconfirm {
+            return Ok(true);
+        }
+        if !terminal {
+            return self.unattended(question).map(|()| true);
+        }
+
+        loop {
+            write!(output, "{question} [Y/n] ")?;
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                // End of input is no answer, so nothing is changed
+                writeln!(output)?;
+                return Ok(false);
+            }
+            if let Some(answer) = parse_answer(&line) {
+                return Ok(answer);
+            }
+        }
+    }
+
+    /// Ask which of `options` to take, numbered from 1 and defaulting to
+    /// the first, as pacman asks which provider to install
+    ///
+    /// `--noconfirm`, and going ahead without a terminal, take the first.
+    ///
+    /// # Returns
+    /// The index of the option taken, or `None` if the answer was to skip
+    /// (`q` or end of input) or there was nothing to choose from
+    ///
+    /// # Errors
+    /// * `CoreError::Prompt` - There is no terminal to ask on and
+    ///   `without_terminal` is `Refuse`
+    pub fn choose(&self, question: &str, options: &[String]) -> Result<Option<usize>> {
+        let stdin = io::stdin();
+        self.choose_on(question, options, stdin.is_terminal(), &mut stdin.lock(), &mut io::stdout())
+    }
+
+    fn choose_on(
+        &self,
+        question: &str,
+        options: &[String],
+        terminal: bool,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<Option<usize>> {
+        if options.is_empty() {
+            return Ok(None);
+        }
+        if self.noconfirm || options.len() == 1 {
+            return Ok(Some(0));
+        }
+        if !terminal {
+            return self.unattended(question).map(|()| Some(0));
+        }
+
+        for (i, option) in options.iter().enumerate() {
+            writeln!(output, "{:>3}) {option}", i + 1)?;
+        }
+        loop {
+            write!(output, "{question} (default=1, q to skip): ")?;
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(output)?;
+                return Ok(None);
+            }
+            match line.trim().to_lowercase().as_str() {
+                "" => return Ok(Some(0)),
+                "q" => return Ok(None),
+                answer => match answer.parse::<usize>() {
+                    Ok(n) if (1..=options.len()).contains(&n) => return Ok(Some(n - 1)),
+                    _ => writeln!(output, "no such option: {answer}")?,
+                },
+            }
+        }
+    }
+
+    /// Whether to go ahead with `question` when there is no one to ask
+    fn unattended(&self, question: &str) -> Result<()> {
+        match self.without_terminal {
+            WithoutTerminal::Proceed => Ok(()),
+            WithoutTerminal::Refuse => Err(CoreError::Prompt(format!(
+                "\"{question}\" needs an answer but there is no terminal; pass --noconfirm to go ahead"
+            ))),
+        }
+    }
+}
+
+/// One entry of a [`Checklist`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecklistItem {
+    pub label: String,
+    pub size: u64,
+    pub selected: bool,
+}
+
+/// What to do after an answer to a [`Checklist`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecklistStep {
+    /// Show the list again and ask for more changes
+    Continue,
+    /// Go ahead with the selected items
+    Done,
+    /// Go ahead with nothing
+    Quit,
+}
+
+/// Items to pick from before acting on them, all selected to begin with
+///
+/// Answers are item numbers and ranges to toggle (`2`, `1-3 5`), `a` to
+/// select everything, `n` for nothing, an empty line when done, or `q` to
+/// give up.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checklist {
+    pub items: Vec<ChecklistItem>,
+}
+
+impl Checklist {
+    pub fn new(items: impl IntoIterator<Item = (String, u64)>) -> Self {
+        let items = items.into_iter().map(|(label, size)| ChecklistItem { label, size, selected: true }).collect();
+        Checklist { items }
+    }
+
+    /// Size of the selected items added up
+    pub fn total(&self) -> u64 {
+        self.items.iter().filter(|i| i.selected).map(|i| i.size).sum()
+    }
+
+    pub fn selected(&self) -> impl Iterator<Item = &ChecklistItem> {
+        self.items.iter().filter(|i| i.selected)
+    }
+
+    /// Apply one answer
+    ///
+    /// # Errors
+    /// * `CoreError::Prompt` - The answer isn't understood or names an item
+    ///   that isn't there; nothing is changed
+    pub fn answer(&mut self, line: &str) -> Result<ChecklistStep> {
+        match line.trim().to_lowercase().as_str() {
+            "" => return Ok(ChecklistStep::Done),
+            "q" => return Ok(ChecklistStep::Quit),
+            "a" => self.items.iter_mut().for_each(|i| i.selected = true),
+            "n" => self.items.iter_mut().for_each(|i| i.selected = false),
+            answer => {
+                let mut toggle = Vec::new();
+                for word in answer.split([' ', ',']).filter(|w| !w.is_empty()) {
+                    let (first, last) = word.split_once('-').unwrap_or((word, word));
+                    let range = first.parse::<usize>().ok().zip(last.parse::<usize>().ok());
+                    match range {
+                        Some((first, last)) if first >= 1 && first <= last && last <= self.items.len() => {
+                            toggle.extend(first - 1..last);
+                        }
+                        _ => return Err(CoreError::Prompt(format!("no such item: {word}"))),
+                    }
+                }
+                for i in toggle {
+                    self.items[i].selected = !self.items[i].selected;
+                }
+            }
+        }
+
+        Ok(ChecklistStep::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ask(confirm: Confirm, terminal: bool, input: &str) -> Result<bool> {
+        confirm.ask_on("Proceed?", terminal, &mut input.as_bytes(), &mut Vec::new())
+    }
+
+    #[test]
+    fn test_answers() {
+        let confirm = Confirm::default();
+        assert!(ask(confirm, true, "\n").unwrap());
+        assert!(ask(confirm, true, "maybe\nYes\n").unwrap());
+        assert!(!ask(confirm, true, "n\n").unwrap());
+        assert!(!ask(confirm, true, "").unwrap());
+
+        assert!(matches!(ask(confirm, false, "y\n"), Err(CoreError::Prompt(_))));
+        let proceed = Confirm { without_terminal: WithoutTerminal::Proceed, ..confirm };
+        assert!(ask(proceed, false, "").unwrap());
+        assert!(ask(Confirm { noconfirm: true, ..confirm }, true, "n\n").unwrap());
+    }
+
+    #[test]
+    fn test_choose() {
+        let options: Vec<String> = ["a", "b", "c"].map(str::to_owned).to_vec();
+        let choose = |confirm: Confirm, terminal: bool, input: &str| {
+            confirm.choose_on("Enter a number", &options, terminal, &mut input.as_bytes(), &mut Vec::new())
+        };
+        let confirm = Confirm::default();
+        assert_eq!(choose(confirm, true, "\n").unwrap(), Some(0));
+        assert_eq!(choose(confirm, true, "4\nb\n3\n").unwrap(), Some(2));
+        assert_eq!(choose(confirm, true, "q\n").unwrap(), None);
+        assert_eq!(choose(confirm, true, "").unwrap(), None);
+
+        assert!(matches!(choose(confirm, false, "2\n"), Err(CoreError::Prompt(_))));
+        assert_eq!(choose(Confirm { noconfirm: true, ..confirm }, false, "").unwrap(), Some(0));
+        assert_eq!(confirm.choose_on("?", &options[..1], true, &mut &b""[..], &mut Vec::new()).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn test_checklist() {
+        let mut list = Checklist::new([("a".to_owned(), 10), ("b".to_owned(), 20), ("c".to_owned(), 40)]);
+        assert_eq!(list.total(), 70);
+
+        assert_eq!(list.answer("1-2").unwrap(), ChecklistStep::Continue);
+        assert_eq!(list.total(), 40);
+        assert_eq!(list.answer("n").unwrap(), ChecklistStep::Continue);
+        assert_eq!(list.answer("2, 3").unwrap(), ChecklistStep::Continue);
+        assert_eq!(list.selected().map(|i| i.label.as_str()).collect::<Vec<_>>(), ["b", "c"]);
+
+        assert!(matches!(list.answer("1 4"), Err(CoreError::Prompt(_))));
+        assert_eq!(list.total(), 60);
+        assert_eq!(list.answer("\n").unwrap(), ChecklistStep::Done);
+        assert_eq!(list.answer("Q").unwrap(), ChecklistStep::Quit);
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::template::DiscLayout;
+
+
+/// What a library holds, which changes how it's named, listed and processed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    #[default]
+    Music,
+    /// Books split into parts or chapters; the artist tag is the author
+    Audiobook,
+    /// Episodes filed by show; the album tag is the show
+    Podcast,
+}
+
+impl ContentType {
+    /// Default library path template for new files
+    pub fn default_template(&self, layout: DiscLayout) -> &'static str {
+        match self {
+            ContentType::Music => layout.default_template(),
+            ContentType::Audiobook => {
+                "%author%/%album%%{narrator: (read by %narrator%)}/%{multidisc:Part %disc% - }%track:02% %title%"
+            }
+            ContentType::Podcast => "%album%/%{year:%year% - }%title%",
+        }
+    }
+
+    /// Whether the library counts towards genre/decade statistics and
+    /// duplicate-recording checks
+    pub fn in_music_stats(&self) -> bool {
+        *self == ContentType::Music
+    }
+
+    /// Whether ReplayGain is calculated and written; spoken word is
+    /// levelled by the publisher and gains nothing from album gain
+    pub fn replaygain(&self) -> bool {
+        *self == ContentType::Music
+    }
+
+    /// Whether chapter markers are read and kept
+    pub fn has_chapters(&self) -> bool {
+        *self != ContentType::Music
+    }
+}
+
+/// Content type of each library profile
+///
+/// Deserializable so it can live as a `[content]` table in flacman.conf:
+///
+/// ```toml
+/// [content.profiles]
+/// books = "audiobook"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ContentPolicy {
+    pub default: ContentType,
+    pub profiles: HashMap<String, ContentType>,
+}
+
+impl Default for ContentPolicy {
+    fn default() -> Self {
+        ContentPolicy {
+            default: ContentType::Music,
+            profiles: HashMap::from([
+                ("audiobooks".to_owned(), ContentType::Audiobook),
+                ("podcasts".to_owned(), ContentType::Podcast),
+            ]),
+        }
+    }
+}
+
+impl ContentPolicy {
+    /// Content type of `profile`, falling back to the default
+    pub fn content_type(&self, profile: Option<&str>) -> ContentType {
+        profile.and_then(|p| self.profiles.get(p)).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Template;
+    use std::collections::HashMap as Fields;
+
+    #[test]
+    fn test_audiobook_template() {
+        let policy = ContentPolicy::default();
+        let book = policy.content_type(Some("audiobooks"));
+        assert_eq!(policy.content_type(Some("portable")), ContentType::Music);
+        assert!(!book.replaygain());
+
+        let template: Template = book.default_template(DiscLayout::default()).parse().unwrap();
+        let fields: Fields<&str, &str> = Fields::from([
+            ("author", "Ursula K. Le Guin"),
+            ("album", "A Wizard of Earthsea"),
+            ("narrator", "Kobna Holdbrook-Smith"),
+            ("disc", "2"),
+            ("disctotal", "2"),
+            ("track", "3"),
+            ("title", "The Shadow"),
+        ]);
+
+        assert_eq!(
+            template.render(&fields),
+            "Ursula K. Le Guin/A Wizard of Earthsea (read by Kobna Holdbrook-Smith)/Part 2 - 03 The Shadow"
+        );
+    }
+}
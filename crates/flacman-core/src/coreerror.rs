@@ -10,8 +10,73 @@ pub enum CoreError {
     CapacityError(#[from] heapless::CapacityError),
 
     #[error("ParseError: {0}")]
-    ParseError(#[from] std::string::ParseError)
+    ParseError(#[from] std::string::ParseError),
 
+    #[error("Invalid template: {0}")]
+    Template(String),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    #[error("Library database was written by a newer flacman (schema version {0})")]
+    DatabaseVersion(i32),
+
+    #[error("Database is locked by {holder} ({}); if no flacman is running, remove the lock with --force-unlock", .path.display())]
+    Locked { path: std::path::PathBuf, holder: String },
+
+    #[error("beets library: {0}")]
+    Beets(String),
+
+    #[error("Notification failed: {0}")]
+    Notify(String),
+
+    #[error("Privilege separation: {0}")]
+    Privilege(String),
+
+    #[error("{0}")]
+    Fs(#[from] flacman_fs::FsError),
+
+    #[error("Invalid manifest: {0}")]
+    Manifest(String),
+
+    #[error("Pager: {0}")]
+    Pager(String),
+
+    #[error("Editor: {0}")]
+    Editor(String),
+
+    #[error("{0}")]
+    Prompt(String),
+
+    #[error("Invalid quality ladder: {0}")]
+    Quality(String),
+
+    #[error("{} has albums that would be removed with it", .0.display())]
+    HasDependents(std::path::PathBuf),
+
+    #[error("Invalid filter: {0}")]
+    Filter(String),
+
+    #[error("Invalid track list: {0}")]
+    TrackList(String),
+
+    #[error("Invalid size: {0}")]
+    Size(String),
+
+    #[error("Quota: {0}")]
+    Quota(String),
+
+    #[error("Download cache: {0}")]
+    Cache(String),
+
+    #[error("Invalid schedule: {0}")]
+    Schedule(String),
+
+    #[error("Hook {0}")]
+    Hook(String),
 }
 
 pub type Result<T> = std::result::Result<T, CoreError>;
\ No newline at end of file
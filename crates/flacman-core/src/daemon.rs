@@ -0,0 +1,108 @@
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+
+/// A request to `flacman --daemon`, one JSON object per line, e.g.
+/// `{"op": "query", "term": "artist:low canada"}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum DaemonRequest {
+    /// What the daemon serves and how much is indexed
+    Status,
+    /// Tracks matching a `-Qs` search, best match first
+    Query { term: String },
+    /// Albums whose artist or title contain `term`, or all of them
+    Albums {
+        #[serde(default)]
+        term: Option<String>,
+    },
+    /// Import the albums under `path` into the library
+    Import {
+        path: PathBuf,
+        /// `copy`, `move`, `symlink` or `reflink`; the configured transfer
+        /// mode, or copy, when left out
+        #[serde(default)]
+        mode: Option<String>,
+    },
+    /// Move `path`, a file or directory of the library, to the trash
+    Remove { path: PathBuf },
+}
+
+/// The answer to a [`DaemonRequest`], one JSON object per line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DaemonResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+}
+
+impl DaemonResponse {
+    pub fn success<T: Serialize>(result: &T) -> Self {
+        match serde_json::to_value(result) {
+            Ok(result) => DaemonResponse { ok: true, error: None, result: Some(result) },
+            Err(e) => DaemonResponse::failure(e.to_string()),
+        }
+    }
+
+    pub fn failure(error: impl Into<String>) -> Self {
+        DaemonResponse { ok: false, error: Some(error.into()), result: None }
+    }
+}
+
+/// Answer the requests read from `reader` on `writer` until the client
+/// hangs up
+///
+/// A line that isn't a request gets a failure response rather than ending
+/// the conversation; blank lines are skipped.
+pub fn serve_requests<R, W, F>(reader: R, mut writer: W, mut handle: F) -> std::io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(DaemonRequest) -> DaemonResponse,
+{
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<DaemonRequest>(&line) {
+            Ok(request) => handle(request),
+            Err(e) => DaemonResponse::failure(format!("bad request: {}", e)),
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serve_requests() {
+        let input = b"{\"op\": \"status\"}\n\n{\"op\": \"albums\"}\n{\"op\": \"remove\"}\n{\"op\": \"query\", \"term\": \"low\"}\n";
+        let mut output = Vec::new();
+        let mut seen = Vec::new();
+        serve_requests(&input[..], &mut output, |request| {
+            seen.push(request.clone());
+            match request {
+                DaemonRequest::Query { term } => DaemonResponse::success(&[term]),
+                _ => DaemonResponse::success(&()),
+            }
+        })
+        .unwrap();
+
+        assert_eq!(seen[1], DaemonRequest::Albums { term: None });
+        let lines: Vec<DaemonResponse> =
+            output.split(|&b| b == b'\n').filter(|l| !l.is_empty()).map(|l| serde_json::from_slice(l).unwrap()).collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[2].error.as_deref().unwrap().contains("missing field `path`"));
+        assert_eq!(lines[3].result, Some(serde_json::json!(["low"])));
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::coreerror::{CoreError, Result};
+
+
+/// Lock that one flacman holds on the library and its database while it
+/// changes them, like pacman's `db.lck`
+///
+/// The lock file holds the PID of the process that has it. It is locked
+/// with an advisory lock rather than created exclusively, so the system
+/// lets go of it whenever the holder exits, crashed or not, and a file
+/// left behind blocks no one. [`DbLock::force_unlock`] removes the file
+/// for when that isn't enough, e.g. a network filesystem that keeps locks
+/// of a client that went away.
+#[derive(Debug)]
+pub struct DbLock {
+    file: File,
+}
+
+impl DbLock {
+    /// Take the lock at `path`, failing at once if it is held
+    ///
+    /// # Errors
+    /// * `CoreError::Locked` - Another process holds the lock
+    pub fn acquire<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::lock(path.as_ref(), false)
+    }
+
+    /// Take the lock at `path`, waiting for its holder to let go
+    pub fn wait<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::lock(path.as_ref(), true)
+    }
+
+    fn lock(path: &Path, wait: bool) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        loop {
+            let mut file = OpenOptions::new().create(true).truncate(false).read(true).write(true).open(path)?;
+            if wait {
+                file.lock()?;
+            } else {
+                match file.try_lock() {
+                    Ok(()) => {}
+                    Err(TryLockError::WouldBlock) => return Err(locked(path, read_pid(&mut file))),
+                    Err(TryLockError::Error(e)) => return Err(e.into()),
+                }
+            }
+            // Removed by --force-unlock since it was opened: lock the new one
+            if !same_file(&file, path) {
+                continue;
+            }
+
+            file.set_len(0)?;
+            writeln!(file, "{}", std::process::id())?;
+            file.flush()?;
+            return Ok(DbLock { file });
+        }
+    }
+
+    /// The PID of the process holding the lock at `path`, if one does
+    ///
+    /// # Returns
+    /// `Some(None)` for a holder that didn't write its PID (yet)
+    pub fn holder<P: AsRef<Path>>(path: P) -> Result<Option<Option<u32>>> {
+        let mut file = match File::open(path.as_ref()) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match file.try_lock_shared() {
+            Ok(()) => Ok(None),
+            Err(TryLockError::WouldBlock) => Ok(Some(read_pid(&mut file))),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    /// Remove the lock file at `path`, whoever holds it
+    ///
+    /// # Returns
+    /// Whether there was one
+    pub fn force_unlock<P: AsRef<Path>>(path: P) -> Result<bool> {
+        match fs::remove_file(path.as_ref()) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for DbLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock; the PID would only mislead
+        let _ = self.file.set_len(0);
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut text = String::new();
+    file.read_to_string(&mut text).ok()?;
+    text.trim().parse().ok()
+}
+
+fn locked(path: &Path, pid: Option<u32>) -> CoreError {
+    let holder = pid.map_or_else(|| "another process".to_owned(), |pid| format!("PID {}", pid));
+    CoreError::Locked { path: path.to_path_buf(), holder }
+}
+
+#[cfg(unix)]
+fn same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lock() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db.lck");
+        assert_eq!(DbLock::holder(&path).unwrap(), None);
+
+        let lock = DbLock::acquire(&path).unwrap();
+        assert_eq!(DbLock::holder(&path).unwrap(), Some(Some(std::process::id())));
+        let Err(CoreError::Locked { holder, .. }) = DbLock::acquire(&path) else {
+            panic!("expected the lock to be held");
+        };
+        assert_eq!(holder, format!("PID {}", std::process::id()));
+
+        drop(lock);
+        assert_eq!(DbLock::holder(&path).unwrap(), None);
+        let lock = DbLock::acquire(&path).unwrap();
+
+        // A removed lock no longer keeps anyone out
+        assert!(DbLock::force_unlock(&path).unwrap());
+        assert!(!DbLock::force_unlock(&path).unwrap());
+        let _again = DbLock::wait(&path).unwrap();
+        drop(lock);
+        assert!(DbLock::holder(&path).unwrap().is_some());
+    }
+}
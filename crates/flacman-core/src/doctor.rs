@@ -0,0 +1,242 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::coreerror::Result;
+
+
+/// How one environment check came out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Health {
+    Ok,
+    /// Works, but something is missing or about to run out
+    Warning,
+    /// Some operation is going to fail
+    Failed,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Health::Ok => "ok",
+            Health::Warning => "warn",
+            Health::Failed => "FAIL",
+        })
+    }
+}
+
+/// Result of one `--doctor` check, with what to do about it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub check: String,
+    pub health: Health,
+    pub detail: String,
+    /// How to fix a warning or failure
+    pub fix: Option<String>,
+}
+
+impl Diagnosis {
+    pub fn ok(check: &str, detail: impl Into<String>) -> Self {
+        Diagnosis { check: check.to_owned(), health: Health::Ok, detail: detail.into(), fix: None }
+    }
+
+    pub fn warning(check: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Diagnosis { check: check.to_owned(), health: Health::Warning, detail: detail.into(), fix: Some(fix.into()) }
+    }
+
+    pub fn failed(check: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Diagnosis { check: check.to_owned(), health: Health::Failed, detail: detail.into(), fix: Some(fix.into()) }
+    }
+}
+
+/// Look `program` up on `PATH`, the way the shell would
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+
+    env::split_paths(&path).map(|dir| dir.join(program)).find(|candidate| is_executable(candidate))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.with_extension("exe").is_file() || path.is_file()
+}
+
+/// Check that an external program is installed
+///
+/// # Arguments
+/// * `program` - Executable name
+/// * `needed_for` - What stops working without it, e.g. "fingerprinting (-Q --fingerprint)"
+/// * `install` - How to get it
+pub fn check_program(program: &str, needed_for: &str, install: &str) -> Diagnosis {
+    let check = format!("{program} installed");
+    match find_program(program) {
+        Some(path) => Diagnosis::ok(&check, path.display().to_string()),
+        None => Diagnosis::warning(&check, format!("not on PATH, {needed_for} won't work"), install),
+    }
+}
+
+/// Check that `dir` exists (or can be created) and is writable
+///
+/// Writability is tested by creating and removing a probe file, which
+/// also catches read-only mounts that permission bits don't show.
+pub fn check_writable_dir(check: &str, dir: &Path) -> Diagnosis {
+    if !dir.exists() {
+        let parent = dir.ancestors().skip(1).find(|p| p.exists());
+        return match parent {
+            Some(parent) if probe_write(parent).is_ok() => {
+                Diagnosis::ok(check, format!("{} will be created on first use", dir.display()))
+            }
+            _ => Diagnosis::failed(
+                check,
+                format!("{} doesn't exist and can't be created", dir.display()),
+                format!("mkdir -p {}", dir.display()),
+            ),
+        };
+    }
+
+    if !dir.is_dir() {
+        return Diagnosis::failed(
+            check,
+            format!("{} is not a directory", dir.display()),
+            "move the file out of the way",
+        );
+    }
+
+    match probe_write(dir) {
+        Ok(()) => Diagnosis::ok(check, format!("{} is writable", dir.display())),
+        Err(e) => Diagnosis::failed(
+            check,
+            format!("cannot write to {}: {}", dir.display(), e),
+            format!("check the owner and mode of {} (and that it isn't mounted read-only)", dir.display()),
+        ),
+    }
+}
+
+fn probe_write(dir: &Path) -> Result<()> {
+    let probe = dir.join(format!(".flacman-doctor-{}", std::process::id()));
+    fs::write(&probe, b"")?;
+    fs::remove_file(&probe)?;
+    Ok(())
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Free space can't be queried here
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> Result<u64> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "free space is only checked on Unix").into())
+}
+
+/// Warn when the filesystem holding `path` has less than `min_bytes` left
+pub fn check_free_space(check: &str, path: &Path, min_bytes: u64) -> Diagnosis {
+    const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+    match free_space(path) {
+        Ok(free) if free < min_bytes => Diagnosis::warning(
+            check,
+            format!("only {:.1} GiB free on {}", free as f64 / GIB, path.display()),
+            "free up space, or run --dedup --hardlink to reclaim duplicate copies",
+        ),
+        Ok(free) => Diagnosis::ok(check, format!("{:.1} GiB free", free as f64 / GIB)),
+        Err(e) => Diagnosis::warning(check, format!("cannot query {}: {}", path.display(), e), "check the path"),
+    }
+}
+
+/// Check that symlinks can be created in `dir` (needed by --symlink and --build-view)
+pub fn check_symlinks(check: &str, dir: &Path) -> Diagnosis {
+    let link = dir.join(format!(".flacman-doctor-link-{}", std::process::id()));
+
+    #[cfg(unix)]
+    let created = std::os::unix::fs::symlink(".", &link);
+    #[cfg(windows)]
+    let created = std::os::windows::fs::symlink_dir(".", &link);
+
+    match created {
+        Ok(()) => {
+            let _ = fs::remove_file(&link).or_else(|_| fs::remove_dir(&link));
+            Diagnosis::ok(check, "supported")
+        }
+        Err(e) => Diagnosis::warning(
+            check,
+            format!("cannot create symlinks in {}: {}", dir.display(), e),
+            "use --copy instead of --symlink and keep views on a filesystem that supports links \
+             (on Windows, enable Developer Mode)",
+        ),
+    }
+}
+
+/// Check that an optional JSON state file, if present, still parses
+///
+/// # Arguments
+/// * `what` - Which command maintains the file, for the fix hint
+pub fn check_json_file(check: &str, path: &Path, what: &str) -> Diagnosis {
+    if !path.exists() {
+        return Diagnosis::ok(check, "not created yet");
+    }
+
+    match fs::read(path).map_err(|e| e.to_string()).and_then(|data| {
+        serde_json::from_slice::<serde_json::Value>(&data).map_err(|e| e.to_string())
+    }) {
+        Ok(_) => Diagnosis::ok(check, path.display().to_string()),
+        Err(e) => Diagnosis::failed(
+            check,
+            format!("{} is unreadable: {}", path.display(), e),
+            format!("move {} aside and rerun {}", path.display(), what),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_writable_dir() {
+        let dir = tempdir().unwrap();
+
+        assert_eq!(check_writable_dir("root", dir.path()).health, Health::Ok);
+        assert_eq!(check_writable_dir("root", &dir.path().join("new/sub")).health, Health::Ok);
+
+        let file = dir.path().join("file");
+        fs::write(&file, b"").unwrap();
+        let diagnosis = check_writable_dir("root", &file);
+        assert_eq!(diagnosis.health, Health::Failed);
+        assert!(diagnosis.fix.is_some());
+    }
+
+    #[test]
+    fn test_check_json_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("views.json");
+
+        assert_eq!(check_json_file("views", &path, "--build-view").health, Health::Ok);
+        fs::write(&path, b"[]").unwrap();
+        assert_eq!(check_json_file("views", &path, "--build-view").health, Health::Ok);
+        fs::write(&path, b"[{").unwrap();
+        assert_eq!(check_json_file("views", &path, "--build-view").health, Health::Failed);
+    }
+}
@@ -0,0 +1,507 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::{CoreError, Result};
+use flacman_fs::sha256_file;
+use crate::quota::parse_size;
+
+
+/// Where a cached blob was downloaded from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobOrigin {
+    pub source: String,
+    /// The source's own ID for the item
+    pub remote_id: String,
+    /// File name the source gave it
+    pub name: String,
+    /// `Artist - Album` it was downloaded as part of, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+}
+
+/// One downloaded file, stored once however many sources returned it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobEntry {
+    pub sha256: String,
+    pub size: u64,
+    pub added: DateTime<Local>,
+    pub origins: Vec<BlobOrigin>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    blobs: BTreeMap<String, BlobEntry>,
+    /// Download in progress per `source:remote_id`, by bytes fetched so far
+    #[serde(default)]
+    partial: BTreeMap<String, u64>,
+}
+
+/// Result of adding a file to the cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stored {
+    pub sha256: String,
+    pub path: PathBuf,
+    /// The content was already cached, possibly from another source
+    pub duplicate: bool,
+}
+
+/// A download that was started but not finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialDownload {
+    pub source: String,
+    pub remote_id: String,
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// Totals for `--cache-info`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub blobs: usize,
+    pub bytes: u64,
+    /// Blobs more than one source returned
+    pub shared: usize,
+    pub partial: usize,
+}
+
+/// Limits the download cache is held to after every sync
+///
+/// Stored as JSON in the data directory and edited with
+/// `--set-cache-policy`; every limit is off until set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EvictionPolicy {
+    /// Evict the oldest blobs until the cache is no larger than this
+    pub max_bytes: Option<u64>,
+    /// Evict blobs, and unfinished downloads, older than this
+    pub max_age_days: Option<u32>,
+    /// Keep only the most recent downloads of each album, one per source
+    pub keep_per_album: Option<usize>,
+}
+
+impl EvictionPolicy {
+    /// Load the policy from `path`; a missing file means no limits
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Apply a `max-size=SIZE`, `max-age=DAYS` or `keep-per-album=N`
+    /// setting; a value of 0 turns the limit off
+    ///
+    /// # Errors
+    /// * `CoreError::Cache` - Unknown setting or bad value
+    pub fn set(&mut self, setting: &str) -> Result<()> {
+        let invalid = || CoreError::Cache(format!("invalid setting: {setting}"));
+        let (name, value) = setting.split_once('=').ok_or_else(invalid)?;
+        let value = value.trim();
+
+        match name.trim() {
+            "max-size" => self.max_bytes = Some(parse_size(value)?).filter(|&b| b > 0),
+            "max-age" => {
+                let days: u32 = value.trim_end_matches('d').parse().map_err(|_| invalid())?;
+                self.max_age_days = Some(days).filter(|&d| d > 0);
+            }
+            "keep-per-album" => self.keep_per_album = Some(value.parse().map_err(|_| invalid())?).filter(|&n| n > 0),
+            _ => return Err(invalid()),
+        }
+
+        Ok(())
+    }
+}
+
+/// Why a blob was evicted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictReason {
+    /// Only belonged to downloads of an album superseded by newer ones
+    Superseded,
+    Age,
+    Size,
+}
+
+impl fmt::Display for EvictReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EvictReason::Superseded => "superseded by newer downloads of the album",
+            EvictReason::Age => "older than max-age",
+            EvictReason::Size => "over max-size",
+        })
+    }
+}
+
+/// What one eviction pass removed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvictionReport {
+    pub evicted: Vec<(BlobEntry, EvictReason)>,
+    pub bytes: u64,
+    /// Unfinished downloads dropped for their age
+    pub partial: usize,
+}
+
+/// Pick the blobs `policy` evicts, in the order the limits apply
+fn select_evictions(
+    blobs: &BTreeMap<String, BlobEntry>,
+    policy: &EvictionPolicy,
+    now: DateTime<Local>,
+) -> Vec<(String, EvictReason)> {
+    let mut evict: Vec<(String, EvictReason)> = Vec::new();
+    let chosen = |evict: &[(String, EvictReason)], sha256: &str| evict.iter().any(|(s, _)| s == sha256);
+
+    if let Some(keep) = policy.keep_per_album {
+        // One download of an album per source; the newest blob dates it
+        let mut downloads: BTreeMap<&str, BTreeMap<&str, DateTime<Local>>> = BTreeMap::new();
+        for entry in blobs.values() {
+            for origin in &entry.origins {
+                if let Some(album) = &origin.album {
+                    let added = downloads.entry(album).or_default().entry(&origin.source).or_insert(entry.added);
+                    *added = (*added).max(entry.added);
+                }
+            }
+        }
+
+        let mut kept: Vec<(&str, &str)> = Vec::new();
+        for (album, sources) in &downloads {
+            let mut sources: Vec<(&&str, &DateTime<Local>)> = sources.iter().collect();
+            sources.sort_by(|a, b| b.1.cmp(a.1));
+            kept.extend(sources.iter().take(keep).map(|(source, _)| (*album, **source)));
+        }
+
+        for entry in blobs.values() {
+            let albums: Vec<(&str, &str)> = entry
+                .origins
+                .iter()
+                .filter_map(|o| o.album.as_deref().map(|album| (album, o.source.as_str())))
+                .collect();
+            if !albums.is_empty() && !albums.iter().any(|download| kept.contains(download)) {
+                evict.push((entry.sha256.clone(), EvictReason::Superseded));
+            }
+        }
+    }
+
+    if let Some(days) = policy.max_age_days {
+        let cutoff = now - TimeDelta::days(days.into());
+        for entry in blobs.values().filter(|e| e.added < cutoff) {
+            if !chosen(&evict, &entry.sha256) {
+                evict.push((entry.sha256.clone(), EvictReason::Age));
+            }
+        }
+    }
+
+    if let Some(max_bytes) = policy.max_bytes {
+        let mut remaining: Vec<&BlobEntry> = blobs.values().filter(|e| !chosen(&evict, &e.sha256)).collect();
+        remaining.sort_by_key(|e| e.added);
+        let mut total: u64 = remaining.iter().map(|e| e.size).sum();
+        for entry in remaining {
+            if total <= max_bytes {
+                break;
+            }
+            total -= entry.size;
+            evict.push((entry.sha256.clone(), EvictReason::Size));
+        }
+    }
+
+    evict
+}
+
+/// Content-addressed download cache
+///
+/// Files live under `blobs/` named by their SHA-256, so the same audio
+/// fetched from two sources is stored and detected once. `index.json`
+/// maps blobs to the sources that returned them and tracks unfinished
+/// downloads in `partial/`. Every change to the index happens under an
+/// exclusive lock on `index.lock`, and files only appear in place by
+/// rename, so several flacman processes can share one cache.
+#[derive(Debug, Clone)]
+pub struct DownloadCache {
+    root: PathBuf,
+}
+
+impl DownloadCache {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        DownloadCache { root: root.as_ref().to_path_buf() }
+    }
+
+    fn partial_key(source: &str, remote_id: &str) -> String {
+        format!("{}:{}", source, remote_id)
+    }
+
+    /// Path of the blob with `sha256`, whether or not it is cached
+    pub fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.root.join("blobs").join(&sha256[..2.min(sha256.len())]).join(sha256)
+    }
+
+    /// Where a download from `source` is written while in progress
+    ///
+    /// The name is stable, so an interrupted download can be resumed from
+    /// the bytes already there.
+    pub fn partial_path(&self, source: &str, remote_id: &str) -> PathBuf {
+        let name: String = Self::partial_key(source, remote_id)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+            .collect();
+        self.root.join("partial").join(format!("{}.part", name))
+    }
+
+    /// Run `f` on the index while holding the cache lock, saving the index
+    /// afterwards when `write` is set
+    fn with_index<T>(&self, write: bool, f: impl FnOnce(&mut CacheIndex) -> Result<T>) -> Result<T> {
+        fs::create_dir_all(&self.root)?;
+        let lock = OpenOptions::new().create(true).truncate(false).write(true).open(self.root.join("index.lock"))?;
+        if write {
+            lock.lock()?;
+        } else {
+            lock.lock_shared()?;
+        }
+
+        let path = self.root.join("index.json");
+        let mut index = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => CacheIndex::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let result = f(&mut index)?;
+        if write {
+            let temp = path.with_extension("json.tmp");
+            fs::write(&temp, serde_json::to_vec(&index)?)?;
+            fs::rename(&temp, &path)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Note that a download from `source` has started or progressed
+    ///
+    /// # Returns
+    /// Where to write it
+    pub fn begin(&self, source: &str, remote_id: &str) -> Result<PathBuf> {
+        let path = self.partial_path(source, remote_id);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let bytes = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+
+        self.with_index(true, |index| {
+            index.partial.insert(Self::partial_key(source, remote_id), bytes);
+            Ok(())
+        })?;
+
+        Ok(path)
+    }
+
+    /// Move a finished download into the cache
+    ///
+    /// # Arguments
+    /// * `file` - Downloaded file; moved into the cache, or removed if its
+    ///   content is already there
+    /// * `origin` - Where it came from
+    pub fn store(&self, file: &Path, origin: BlobOrigin) -> Result<Stored> {
+        let sha256 = sha256_file(file)?;
+        let size = fs::metadata(file)?.len();
+        let blob = self.blob_path(&sha256);
+
+        self.with_index(true, |index| {
+            index.partial.remove(&Self::partial_key(&origin.source, &origin.remote_id));
+
+            let duplicate = blob.exists();
+            if duplicate {
+                fs::remove_file(file)?;
+            } else {
+                if let Some(parent) = blob.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                if fs::rename(file, &blob).is_err() {
+                    // Different filesystem: copy under a temporary name so
+                    // other processes never see a half-written blob
+                    let temp = blob.with_extension("tmp");
+                    fs::copy(file, &temp)?;
+                    fs::rename(&temp, &blob)?;
+                    fs::remove_file(file)?;
+                }
+            }
+
+            let entry = index.blobs.entry(sha256.clone()).or_insert_with(|| BlobEntry {
+                sha256: sha256.clone(),
+                size,
+                added: Local::now(),
+                origins: Vec::new(),
+            });
+            if !entry.origins.contains(&origin) {
+                entry.origins.push(origin);
+            }
+
+            Ok(Stored { sha256: sha256.clone(), path: blob.clone(), duplicate })
+        })
+    }
+
+    /// The cached blob `source` returned for `remote_id`, if any
+    pub fn lookup(&self, source: &str, remote_id: &str) -> Result<Option<BlobEntry>> {
+        self.with_index(false, |index| {
+            Ok(index
+                .blobs
+                .values()
+                .find(|e| e.origins.iter().any(|o| o.source == source && o.remote_id == remote_id))
+                .cloned())
+        })
+    }
+
+    /// The cached blob with `sha256`, if any
+    pub fn get(&self, sha256: &str) -> Result<Option<BlobEntry>> {
+        self.with_index(false, |index| Ok(index.blobs.get(sha256).cloned()))
+    }
+
+    /// Downloads that were started but never stored
+    pub fn partials(&self) -> Result<Vec<PartialDownload>> {
+        self.with_index(false, |index| {
+            Ok(index
+                .partial
+                .keys()
+                .filter_map(|key| {
+                    let (source, remote_id) = key.split_once(':')?;
+                    let path = self.partial_path(source, remote_id);
+                    let bytes = File::open(&path).and_then(|f| f.metadata()).map(|m| m.len()).unwrap_or(0);
+                    Some(PartialDownload { source: source.to_owned(), remote_id: remote_id.to_owned(), path, bytes })
+                })
+                .collect())
+        })
+    }
+
+    /// Remove what `policy` doesn't allow to stay
+    pub fn evict(&self, policy: &EvictionPolicy, now: DateTime<Local>) -> Result<EvictionReport> {
+        self.with_index(true, |index| {
+            let mut report = EvictionReport::default();
+
+            for (sha256, reason) in select_evictions(&index.blobs, policy, now) {
+                let entry = index.blobs.remove(&sha256).expect("evictions are picked from the index");
+                match fs::remove_file(self.blob_path(&sha256)) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+                report.bytes += entry.size;
+                report.evicted.push((entry, reason));
+            }
+
+            if let Some(days) = policy.max_age_days {
+                let cutoff = std::time::SystemTime::from(now - TimeDelta::days(days.into()));
+                let keys: Vec<String> = index.partial.keys().cloned().collect();
+                for key in keys {
+                    let (source, remote_id) = key.split_once(':').unwrap_or((&key, ""));
+                    let path = self.partial_path(source, remote_id);
+                    let stale = fs::metadata(&path).and_then(|m| m.modified()).map_or(true, |m| m < cutoff);
+                    if stale {
+                        let _ = fs::remove_file(&path);
+                        index.partial.remove(&key);
+                        report.partial += 1;
+                    }
+                }
+            }
+
+            Ok(report)
+        })
+    }
+
+    pub fn stats(&self) -> Result<CacheStats> {
+        self.with_index(false, |index| {
+            Ok(CacheStats {
+                blobs: index.blobs.len(),
+                bytes: index.blobs.values().map(|e| e.size).sum(),
+                shared: index.blobs.values().filter(|e| e.origins.len() > 1).count(),
+                partial: index.partial.len(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn origin(source: &str, remote_id: &str) -> BlobOrigin {
+        BlobOrigin {
+            source: source.to_owned(),
+            remote_id: remote_id.to_owned(),
+            name: "01 Canada.flac".to_owned(),
+            album: Some("Low - Trust".to_owned()),
+        }
+    }
+
+    fn blob(sha256: &str, size: u64, days_old: i64, origins: Vec<BlobOrigin>) -> (String, BlobEntry) {
+        let added = Local::now() - TimeDelta::days(days_old);
+        (sha256.to_owned(), BlobEntry { sha256: sha256.to_owned(), size, added, origins })
+    }
+
+    #[test]
+    fn test_select_evictions() {
+        let blobs: BTreeMap<String, BlobEntry> = [
+            blob("old-bandcamp", 100, 40, vec![origin("bandcamp", "1")]),
+            blob("new-soulseek", 100, 2, vec![origin("soulseek", "2")]),
+            blob("loose-old", 50, 10, vec![BlobOrigin { album: None, ..origin("qobuz", "3") }]),
+            blob("loose-new", 50, 1, vec![BlobOrigin { album: None, ..origin("qobuz", "4") }]),
+        ]
+        .into_iter()
+        .collect();
+
+        let policy = EvictionPolicy { keep_per_album: Some(1), ..Default::default() };
+        let evicted = select_evictions(&blobs, &policy, Local::now());
+        assert_eq!(evicted, [("old-bandcamp".to_owned(), EvictReason::Superseded)]);
+
+        let mut policy = EvictionPolicy::default();
+        policy.set("max-age=30d").unwrap();
+        policy.set("max-size=150").unwrap();
+        let evicted = select_evictions(&blobs, &policy, Local::now());
+        assert_eq!(
+            evicted,
+            [("old-bandcamp".to_owned(), EvictReason::Age), ("loose-old".to_owned(), EvictReason::Size)]
+        );
+
+        policy.set("max-age=0").unwrap();
+        assert_eq!(policy.max_age_days, None);
+        assert!(policy.set("max-files=3").is_err());
+    }
+
+    #[test]
+    fn test_duplicate_downloads_stored_once() {
+        let dir = tempdir().unwrap();
+        let cache = DownloadCache::new(dir.path().join("cache"));
+
+        let first = cache.begin("bandcamp", "trust-01").unwrap();
+        fs::write(&first, b"same audio").unwrap();
+        assert_eq!(cache.partials().unwrap()[0].bytes, 10);
+
+        let stored = cache.store(&first, origin("bandcamp", "trust-01")).unwrap();
+        assert!(!stored.duplicate);
+        assert!(stored.path.exists() && !first.exists());
+        assert!(cache.partials().unwrap().is_empty());
+
+        let second = dir.path().join("canada.flac");
+        fs::write(&second, b"same audio").unwrap();
+        let again = cache.store(&second, origin("soulseek", "user/canada.flac")).unwrap();
+        assert!(again.duplicate);
+        assert_eq!(again.sha256, stored.sha256);
+
+        let entry = cache.lookup("soulseek", "user/canada.flac").unwrap().unwrap();
+        assert_eq!(entry.origins.len(), 2);
+        assert_eq!(cache.stats().unwrap(), CacheStats { blobs: 1, bytes: 10, shared: 1, partial: 0 });
+    }
+}
@@ -0,0 +1,69 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::coreerror::{CoreError, Result};
+
+
+/// The editor to use: `VISUAL`, then `EDITOR`, then the platform default
+pub fn editor_command() -> String {
+    choose_editor(env::var("VISUAL").ok(), env::var("EDITOR").ok())
+}
+
+fn choose_editor(visual: Option<String>, editor: Option<String>) -> String {
+    let fallback = if cfg!(windows) { "notepad" } else { "vi" };
+    [visual, editor]
+        .into_iter()
+        .flatten()
+        .map(|command| command.trim().to_owned())
+        .find(|command| !command.is_empty())
+        .unwrap_or_else(|| fallback.to_owned())
+}
+
+/// Open `path` in the user's editor and wait for it to close
+///
+/// # Errors
+/// * `CoreError::Editor` - The editor couldn't be started or exited with an error
+pub fn edit_file(path: &Path) -> Result<()> {
+    let command = editor_command();
+    #[cfg(unix)]
+    let status = Command::new("sh").args(["-c", &format!("{command} \"$1\""), "sh"]).arg(path).status();
+    #[cfg(not(unix))]
+    let status = Command::new("cmd").args(["/C", &command]).arg(path).status();
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(CoreError::Editor(format!("{command} exited with {status}"))),
+        Err(e) => Err(CoreError::Editor(format!("cannot run {command}: {e}"))),
+    }
+}
+
+/// Let the user edit `text` in their editor
+///
+/// The text goes through a temporary file named after `name`, so editors
+/// pick the right syntax from its extension.
+///
+/// # Errors
+/// * `CoreError::Editor` - The editor couldn't be started or exited with an error
+pub fn edit_text(text: &str, name: &str) -> Result<String> {
+    let path = env::temp_dir().join(format!("flacman-{}-{}", std::process::id(), name));
+    fs::write(&path, text)?;
+
+    let edited = edit_file(&path).and_then(|()| Ok(fs::read_to_string(&path)?));
+    let _ = fs::remove_file(&path);
+
+    edited
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_editor() {
+        assert_eq!(choose_editor(Some("nvim".into()), Some("nano".into())), "nvim");
+        assert_eq!(choose_editor(Some(" ".into()), Some("nano".into())), "nano");
+        assert!(!choose_editor(None, None).is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+
+/// Which of a track's ReplayGain values an export applies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GainSource {
+    Track,
+    /// Keeps the loudness steps within an album; the usual choice
+    #[default]
+    Album,
+}
+
+/// How ReplayGain reaches an export target
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GainMode {
+    /// Leave it out entirely
+    None,
+    /// Keep the `REPLAYGAIN_*` tags and annotate playlists with the gain
+    #[default]
+    Tags,
+    /// Apply the gain to the audio while transcoding, for players that
+    /// ignore ReplayGain; playlists then carry no gain, or it would be
+    /// applied twice
+    Bake,
+}
+
+impl FromStr for GainMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "none" | "off" => Ok(GainMode::None),
+            "tags" => Ok(GainMode::Tags),
+            "bake" | "baked" => Ok(GainMode::Bake),
+            _ => Err(format!("unknown gain mode {s:?} (expected none, tags or bake)")),
+        }
+    }
+}
+
+/// A track's ReplayGain values, gains in dB and peaks as sample fractions
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+impl ReplayGain {
+    /// Parse a gain tag value such as `-6.20 dB`
+    pub fn parse_gain(value: &str) -> Option<f64> {
+        let value = value.trim();
+        let number = value.strip_suffix("dB").or_else(|| value.strip_suffix("db")).unwrap_or(value);
+        number.trim().parse().ok()
+    }
+
+    /// Parse a peak tag value such as `0.988525`
+    pub fn parse_peak(value: &str) -> Option<f64> {
+        value.trim().parse().ok().filter(|peak: &f64| *peak >= 0.0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.track_gain.is_none() && self.album_gain.is_none()
+    }
+}
+
+/// How tracks are exported to playlists and devices
+///
+/// Shared by every exporter so a profile's gain handling is the same
+/// whether its tracks end up in an M3U file or on a player.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub gain: GainMode,
+    /// Which gain to apply; the other is used when it is missing
+    pub source: GainSource,
+    /// Extra gain in dB on top of ReplayGain
+    pub preamp: f64,
+    /// Lower the gain where the peak would otherwise clip
+    pub prevent_clipping: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions { gain: GainMode::Tags, source: GainSource::Album, preamp: 0.0, prevent_clipping: true }
+    }
+}
+
+impl ExportOptions {
+    /// The gain in dB to apply to a track with `replay_gain`, if any
+    pub fn gain_db(&self, replay_gain: &ReplayGain) -> Option<f64> {
+        if self.gain == GainMode::None {
+            return None;
+        }
+
+        let track = replay_gain.track_gain.map(|gain| (gain, replay_gain.track_peak));
+        let album = replay_gain.album_gain.map(|gain| (gain, replay_gain.album_peak));
+        let (gain, peak) = match self.source {
+            GainSource::Track => track.or(album),
+            GainSource::Album => album.or(track),
+        }?;
+
+        let gain = gain + self.preamp;
+        Some(match peak {
+            // Keep peak * 10^(gain/20) at or below full scale
+            Some(peak) if self.prevent_clipping && peak > 0.0 => gain.min(-20.0 * peak.log10()),
+            _ => gain,
+        })
+    }
+
+    /// Extended M3U line carrying the gain, placed before the track's path
+    ///
+    /// Only in `Tags` mode; baked audio needs no further adjustment.
+    pub fn m3u_gain_line(&self, replay_gain: &ReplayGain) -> Option<String> {
+        if self.gain != GainMode::Tags {
+            return None;
+        }
+        self.gain_db(replay_gain).map(|gain| format!("#EXTGAIN:{:.2}", gain))
+    }
+
+    /// ffmpeg audio filter that bakes the gain into a transcode
+    ///
+    /// Only in `Bake` mode.
+    pub fn bake_filter(&self, replay_gain: &ReplayGain) -> Option<String> {
+        if self.gain != GainMode::Bake {
+            return None;
+        }
+        self.gain_db(replay_gain).map(|gain| format!("volume={:.2}dB", gain))
+    }
+}
+
+/// Export options of each library or device profile
+///
+/// Deserializable so it can live as an `[export]` table in flacman.conf:
+///
+/// ```toml
+/// [export.profiles.car]
+/// gain = "bake"
+/// source = "track"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ExportPolicy {
+    pub default: ExportOptions,
+    pub profiles: HashMap<String, ExportOptions>,
+}
+
+impl Default for ExportPolicy {
+    fn default() -> Self {
+        ExportPolicy {
+            default: ExportOptions::default(),
+            // Portable players mostly ignore ReplayGain
+            profiles: HashMap::from([(
+                "portable".to_owned(),
+                ExportOptions { gain: GainMode::Bake, ..Default::default() },
+            )]),
+        }
+    }
+}
+
+impl ExportPolicy {
+    /// The options of `profile`, falling back to the default
+    pub fn options(&self, profile: Option<&str>) -> ExportOptions {
+        profile.and_then(|p| self.profiles.get(p)).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gain_selection() {
+        let rg = ReplayGain {
+            track_gain: Some(-7.5),
+            track_peak: Some(0.5),
+            album_gain: Some(-6.2),
+            album_peak: Some(0.99),
+        };
+        let options = ExportOptions::default();
+        assert_eq!(options.m3u_gain_line(&rg).as_deref(), Some("#EXTGAIN:-6.20"));
+        assert_eq!(options.bake_filter(&rg), None);
+
+        // +9 dB on a 0.5 peak would clip; 20 * log10(2) is the headroom
+        let loud = ExportOptions { source: GainSource::Track, preamp: 16.5, ..options };
+        assert!((loud.gain_db(&rg).unwrap() - 6.0206).abs() < 0.001);
+
+        let baked = ExportPolicy::default().options(Some("portable"));
+        assert_eq!(baked.bake_filter(&rg).as_deref(), Some("volume=-6.20dB"));
+        assert_eq!(baked.m3u_gain_line(&rg), None);
+
+        let none = ExportOptions { gain: GainMode::None, ..options };
+        assert_eq!(none.gain_db(&rg), None);
+        assert_eq!(options.gain_db(&ReplayGain::default()), None);
+
+        assert_eq!(ReplayGain::parse_gain("-6.20 dB"), Some(-6.2));
+        assert_eq!(ReplayGain::parse_gain("+1.5"), Some(1.5));
+        assert_eq!(ReplayGain::parse_peak("-1"), None);
+    }
+}
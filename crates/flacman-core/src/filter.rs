@@ -0,0 +1,202 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::CoreError;
+use crate::template::TemplateFields;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Case-insensitive substring match
+    Contains,
+}
+
+impl FilterOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            FilterOp::Eq => "=",
+            FilterOp::Ne => "!=",
+            FilterOp::Lt => "<",
+            FilterOp::Le => "<=",
+            FilterOp::Gt => ">",
+            FilterOp::Ge => ">=",
+            FilterOp::Contains => "~",
+        }
+    }
+}
+
+/// One `field<op>value` comparison, e.g. `rating>=4`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub field: String,
+    pub op: FilterOp,
+    pub value: String,
+}
+
+impl Condition {
+    /// Whether `fields` satisfies the comparison
+    ///
+    /// Values that both parse as numbers compare numerically, anything
+    /// else compares case-insensitively as text. A missing field only
+    /// satisfies `!=`.
+    pub fn matches<F: TemplateFields + ?Sized>(&self, fields: &F) -> bool {
+        let Some(actual) = fields.field(&self.field) else {
+            return self.op == FilterOp::Ne;
+        };
+
+        if self.op == FilterOp::Contains {
+            return actual.to_lowercase().contains(&self.value.to_lowercase());
+        }
+
+        let ordering = match (actual.trim().parse::<f64>(), self.value.parse::<f64>()) {
+            (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            _ => actual.to_lowercase().cmp(&self.value.to_lowercase()),
+        };
+
+        match self.op {
+            FilterOp::Eq => ordering == Ordering::Equal,
+            FilterOp::Ne => ordering != Ordering::Equal,
+            FilterOp::Lt => ordering == Ordering::Less,
+            FilterOp::Le => ordering != Ordering::Greater,
+            FilterOp::Gt => ordering == Ordering::Greater,
+            FilterOp::Ge => ordering != Ordering::Less,
+            FilterOp::Contains => unreachable!("handled above"),
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Two-character operators first, so ">=" isn't read as ">"
+        const OPS: [(&str, FilterOp); 7] = [
+            (">=", FilterOp::Ge),
+            ("<=", FilterOp::Le),
+            ("!=", FilterOp::Ne),
+            (">", FilterOp::Gt),
+            ("<", FilterOp::Lt),
+            ("=", FilterOp::Eq),
+            ("~", FilterOp::Contains),
+        ];
+
+        let (at, symbol, op) = OPS
+            .iter()
+            .filter_map(|(symbol, op)| s.find(symbol).map(|at| (at, *symbol, *op)))
+            .min_by_key(|(at, symbol, _)| (*at, std::cmp::Reverse(symbol.len())))
+            .ok_or_else(|| CoreError::Filter(format!("no comparison in '{s}' (use =, !=, <, <=, >, >= or ~)")))?;
+
+        let field = s[..at].trim().to_lowercase();
+        let value = s[at + symbol.len()..].trim().to_owned();
+        if field.is_empty() {
+            return Err(CoreError::Filter(format!("no field in '{s}'")));
+        }
+
+        Ok(Condition { field, op, value })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.field, self.op.symbol(), self.value)
+    }
+}
+
+/// A smart-collection query: comma-separated conditions that must all hold
+///
+/// Fields are the template fields of a track (`artist`, `genre`, `year`,
+/// `rating`, `playcount`, ...), e.g. `rating>=4, genre~jazz`. Serialized
+/// in that same text form.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TrackFilter {
+    pub conditions: Vec<Condition>,
+}
+
+impl TrackFilter {
+    pub fn matches<F: TemplateFields + ?Sized>(&self, fields: &F) -> bool {
+        self.conditions.iter().all(|c| c.matches(fields))
+    }
+}
+
+impl FromStr for TrackFilter {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conditions = s
+            .split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if conditions.is_empty() {
+            return Err(CoreError::Filter("empty filter".to_owned()));
+        }
+
+        Ok(TrackFilter { conditions })
+    }
+}
+
+impl TryFrom<String> for TrackFilter {
+    type Error = CoreError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<TrackFilter> for String {
+    fn from(filter: TrackFilter) -> Self {
+        filter.to_string()
+    }
+}
+
+impl fmt::Display for TrackFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let conditions: Vec<String> = self.conditions.iter().map(ToString::to_string).collect();
+        write!(f, "{}", conditions.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_filter() {
+        let filter: TrackFilter = "rating>=4, genre ~ Jazz,year!=1999".parse().unwrap();
+        assert_eq!(filter.conditions.len(), 3);
+        assert_eq!(filter.conditions[0], Condition { field: "rating".into(), op: FilterOp::Ge, value: "4".into() });
+        assert_eq!(filter.conditions[1].op, FilterOp::Contains);
+        assert_eq!(filter.to_string(), "rating>=4, genre~Jazz, year!=1999");
+
+        assert!("rating".parse::<TrackFilter>().is_err());
+        assert!(">=4".parse::<TrackFilter>().is_err());
+        assert!("".parse::<TrackFilter>().is_err());
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let track = HashMap::from([("rating", "4"), ("genre", "Free Jazz"), ("playcount", "12")]);
+        let matches = |f: &str| f.parse::<TrackFilter>().unwrap().matches(&track);
+
+        assert!(matches("rating>=4"));
+        assert!(!matches("rating>4"));
+        // Numeric, not lexicographic
+        assert!(matches("playcount>9"));
+        assert!(matches("genre~jazz, rating=4"));
+        assert!(!matches("year>=2000"));
+        assert!(matches("year!=2000"));
+    }
+}
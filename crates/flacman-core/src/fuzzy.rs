@@ -0,0 +1,165 @@
+use crate::collate::Collation;
+
+
+/// Outcome of resolving a target against known names
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution {
+    /// Matches a candidate exactly (after case and accent folding)
+    Exact(usize),
+    /// Not exact, but one candidate is clearly the closest
+    Close(usize),
+    /// Several plausible candidates, best first
+    Suggestions(Vec<usize>),
+    NotFound,
+}
+
+/// Resolves misspelled or partial targets ("beatls", "things we lost")
+/// against names from the library, a source cache or the filesystem
+#[derive(Debug, Clone)]
+pub struct FuzzyMatcher {
+    pub collation: Collation,
+    /// Minimum score to pick a single match without asking
+    pub auto_threshold: f64,
+    /// How far the best match must be ahead of the runner-up to be picked
+    pub auto_margin: f64,
+    /// Minimum score to be suggested at all
+    pub suggest_threshold: f64,
+    pub max_suggestions: usize,
+}
+
+impl Default for FuzzyMatcher {
+    fn default() -> Self {
+        FuzzyMatcher {
+            collation: Collation::default(),
+            auto_threshold: 0.9,
+            auto_margin: 0.05,
+            suggest_threshold: 0.7,
+            max_suggestions: 5,
+        }
+    }
+}
+
+/// Jaro-Winkler similarity in `0.0..=1.0`
+fn jaro_winkler(a: &[char], b: &[char]) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    let window = (a.len().max(b.len()) / 2).saturating_sub(1);
+    let mut a_matched = vec![false; a.len()];
+    let mut b_matched = vec![false; b.len()];
+    let mut matches = 0;
+
+    for (i, ca) in a.iter().enumerate() {
+        let lo = i.saturating_sub(window);
+        let hi = (i + window + 1).min(b.len());
+        for j in lo..hi {
+            if !b_matched[j] && b[j] == *ca {
+                a_matched[i] = true;
+                b_matched[j] = true;
+                matches += 1;
+                break;
+            }
+        }
+    }
+
+    if matches == 0 {
+        return 0.0;
+    }
+
+    let a_seq = a.iter().zip(&a_matched).filter(|(_, m)| **m).map(|(c, _)| c);
+    let b_seq = b.iter().zip(&b_matched).filter(|(_, m)| **m).map(|(c, _)| c);
+    let transpositions = a_seq.zip(b_seq).filter(|(x, y)| x != y).count() / 2;
+
+    let m = matches as f64;
+    let jaro = (m / a.len() as f64 + m / b.len() as f64 + (m - transpositions as f64) / m) / 3.0;
+
+    let prefix = a.iter().zip(b).take(4).take_while(|(x, y)| x == y).count();
+    jaro + prefix as f64 * 0.1 * (1.0 - jaro)
+}
+
+impl FuzzyMatcher {
+    fn normalize(&self, s: &str) -> Vec<char> {
+        self.collation.fold(self.collation.strip_article(s.trim())).chars().collect()
+    }
+
+    /// Similarity of `query` to `candidate` in `0.0..=1.0`
+    ///
+    /// Folding ignores case, accents and leading articles; a query that is
+    /// a whole-word prefix of the candidate ("things we lost" for "Things
+    /// We Lost in the Fire") scores high even though the lengths differ.
+    pub fn score(&self, query: &str, candidate: &str) -> f64 {
+        let q = self.normalize(query);
+        let c = self.normalize(candidate);
+
+        if q == c {
+            return 1.0;
+        }
+
+        let jw = jaro_winkler(&q, &c);
+        let is_prefix = c.len() > q.len() && c.starts_with(&q) && !c[q.len()].is_alphanumeric();
+
+        if is_prefix && q.len() >= 3 { jw.max(0.9) } else { jw }
+    }
+
+    /// Resolve `query` against `candidates`
+    pub fn resolve<S: AsRef<str>>(&self, query: &str, candidates: &[S]) -> Resolution {
+        let mut scored: Vec<(usize, f64)> = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i, self.score(query, c.as_ref())))
+            .filter(|(_, score)| *score >= self.suggest_threshold)
+            .collect();
+
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let exact: Vec<usize> = scored.iter().take_while(|(_, s)| *s >= 1.0).map(|(i, _)| *i).collect();
+        if exact.len() == 1 {
+            return Resolution::Exact(exact[0]);
+        }
+
+        match scored.as_slice() {
+            [] => Resolution::NotFound,
+            [(i, best), rest @ ..]
+                if *best >= self.auto_threshold
+                    && exact.is_empty()
+                    && rest.first().is_none_or(|(_, next)| best - next >= self.auto_margin) =>
+            {
+                Resolution::Close(*i)
+            }
+            _ => Resolution::Suggestions(scored.iter().take(self.max_suggestions).map(|(i, _)| *i).collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTISTS: &[&str] = &["The Beatles", "Beach House", "Björk", "Bonobo", "Low", "Lowell"];
+
+    #[test]
+    fn test_exact_ignores_case_accents_articles() {
+        let matcher = FuzzyMatcher::default();
+        assert_eq!(matcher.resolve("bjork", ARTISTS), Resolution::Exact(2));
+        assert_eq!(matcher.resolve("beatles", ARTISTS), Resolution::Exact(0));
+        assert_eq!(matcher.resolve("LOW", ARTISTS), Resolution::Exact(4));
+    }
+
+    #[test]
+    fn test_typo_resolves() {
+        let matcher = FuzzyMatcher::default();
+        assert_eq!(matcher.resolve("beatls", ARTISTS), Resolution::Close(0));
+        assert_eq!(matcher.resolve("things we lost", &["Things We Lost in the Fire", "Trust"]), Resolution::Close(0));
+    }
+
+    #[test]
+    fn test_ambiguous_and_missing() {
+        let matcher = FuzzyMatcher::default();
+        assert!(matches!(matcher.resolve("bea", ARTISTS), Resolution::Suggestions(s) if s.len() >= 2));
+        assert_eq!(matcher.resolve("radiohead", ARTISTS), Resolution::NotFound);
+    }
+}
@@ -0,0 +1,340 @@
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use globset::{Glob, GlobMatcher};
+
+use crate::coreerror::{CoreError, Result};
+
+
+/// Transactions a [`Hook`] can be triggered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookOperation {
+    /// `-S` downloads
+    Sync,
+    /// `-U` and `--watch` imports
+    Import,
+    /// `-R` removals
+    Remove,
+}
+
+impl FromStr for HookOperation {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "sync" => Ok(HookOperation::Sync),
+            "import" => Ok(HookOperation::Import),
+            "remove" => Ok(HookOperation::Remove),
+            _ => Err(CoreError::Hook(format!("unknown operation {:?}; expected Sync, Import or Remove", s.trim()))),
+        }
+    }
+}
+
+impl fmt::Display for HookOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookOperation::Sync => write!(f, "sync"),
+            HookOperation::Import => write!(f, "import"),
+            HookOperation::Remove => write!(f, "remove"),
+        }
+    }
+}
+
+/// When a [`Hook`] runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookWhen {
+    /// Before anything is changed; with `AbortOnFail` a failure stops the transaction
+    PreTransaction,
+    /// After the changes are made
+    PostTransaction,
+}
+
+/// A pattern of a hook's `Target`; a leading `!` excludes what it matches
+#[derive(Debug, Clone)]
+struct TargetPattern {
+    negated: bool,
+    glob: GlobMatcher,
+}
+
+/// A command run before or after a transaction, read from a `.hook` file
+/// like pacman's:
+///
+/// ```text
+/// [Trigger]
+/// Operation = Import
+/// Operation = Remove
+/// Target = /srv/music/*
+///
+/// [Action]
+/// Description = Updating the MPD database
+/// When = PostTransaction
+/// Exec = /usr/bin/mpc --quiet update
+/// ```
+///
+/// A hook is triggered by the operations it lists, when at least one of
+/// the paths the transaction affects matches a `Target` (`*` matches
+/// across directories; with no `Target`, every path does). The paths that
+/// matched are written to its standard input, one per line. `Exec` is run
+/// without a shell; quote arguments with spaces. `AbortOnFail` makes a
+/// failing `PreTransaction` hook stop the transaction.
+#[derive(Debug, Clone)]
+pub struct Hook {
+    /// File name without `.hook`
+    pub name: String,
+    pub operations: Vec<HookOperation>,
+    targets: Vec<TargetPattern>,
+    pub description: Option<String>,
+    pub when: HookWhen,
+    pub exec: Vec<String>,
+    pub abort_on_fail: bool,
+}
+
+impl Hook {
+    /// Parse the hook file `name` (without `.hook`) holding `text`
+    ///
+    /// # Errors
+    /// * `CoreError::Hook` - A line isn't understood, or `Operation`,
+    ///   `When` or `Exec` is missing
+    pub fn parse(name: &str, text: &str) -> Result<Self> {
+        let fail = |line: usize, message: String| CoreError::Hook(format!("{}.hook, line {}: {}", name, line, message));
+        let mut section = None;
+        let mut operations = Vec::new();
+        let mut targets = Vec::new();
+        let mut description = None;
+        let mut when = None;
+        let mut exec = None;
+        let mut abort_on_fail = false;
+
+        for (n, line) in text.lines().enumerate().map(|(n, line)| (n + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                match name {
+                    "Trigger" | "Action" => section = Some(name.to_owned()),
+                    _ => return Err(fail(n, format!("unknown section [{}]", name))),
+                }
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), Some(value.trim())),
+                None => (line, None),
+            };
+            match (section.as_deref(), key, value) {
+                (Some("Trigger"), "Operation", Some(value)) => {
+                    operations.push(value.parse().map_err(|e: CoreError| fail(n, e.to_string()))?)
+                }
+                (Some("Trigger"), "Target", Some(value)) => {
+                    let (negated, pattern) = match value.strip_prefix('!') {
+                        Some(pattern) => (true, pattern),
+                        None => (false, value),
+                    };
+                    let glob = Glob::new(pattern).map_err(|e| fail(n, format!("bad target {:?}: {}", value, e.kind())))?;
+                    targets.push(TargetPattern { negated, glob: glob.compile_matcher() });
+                }
+                (Some("Action"), "Description", Some(value)) => description = Some(value.to_owned()),
+                (Some("Action"), "When", Some("PreTransaction")) => when = Some(HookWhen::PreTransaction),
+                (Some("Action"), "When", Some("PostTransaction")) => when = Some(HookWhen::PostTransaction),
+                (Some("Action"), "When", Some(value)) => {
+                    return Err(fail(n, format!("When is PreTransaction or PostTransaction, not {:?}", value)));
+                }
+                (Some("Action"), "Exec", Some(value)) => {
+                    let words = split_command(value).ok_or_else(|| fail(n, "unbalanced quotes in Exec".to_owned()))?;
+                    exec = Some(words).filter(|w| !w.is_empty());
+                }
+                (Some("Action"), "AbortOnFail", None) => abort_on_fail = true,
+                (None, _, _) => return Err(fail(n, "setting outside [Trigger] or [Action]".to_owned())),
+                _ => return Err(fail(n, format!("unknown setting {:?}", line))),
+            }
+        }
+
+        let missing = |setting: &str| CoreError::Hook(format!("{}.hook: no {}", name, setting));
+        if operations.is_empty() {
+            return Err(missing("Operation"));
+        }
+        Ok(Hook {
+            name: name.to_owned(),
+            operations,
+            targets,
+            description,
+            when: when.ok_or_else(|| missing("When"))?,
+            exec: exec.ok_or_else(|| missing("Exec"))?,
+            abort_on_fail,
+        })
+    }
+
+    /// The hooks in the `*.hook` files of `dir` (e.g. `~/.config/flacman/hooks`),
+    /// in file name order
+    ///
+    /// A missing directory has none.
+    pub fn load_dir(dir: &Path) -> Result<Vec<Hook>> {
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "hook"))
+            .collect();
+        files.sort();
+
+        files
+            .iter()
+            .map(|path| {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                Hook::parse(&name, &fs::read_to_string(path)?)
+            })
+            .collect()
+    }
+
+    /// The paths of an `operation` transaction that trigger this hook
+    ///
+    /// # Returns
+    /// None if the hook isn't triggered
+    pub fn triggered<'a>(&self, operation: HookOperation, paths: &'a [PathBuf]) -> Option<Vec<&'a PathBuf>> {
+        if !self.operations.contains(&operation) {
+            return None;
+        }
+        let matched: Vec<&PathBuf> = paths.iter().filter(|path| self.matches(path)).collect();
+        (!matched.is_empty()).then_some(matched)
+    }
+
+    /// Whether `path` matches the targets: the last pattern it matches
+    /// decides, as in pacman
+    fn matches(&self, path: &Path) -> bool {
+        if self.targets.is_empty() {
+            return true;
+        }
+        self.targets.iter().rev().find(|t| t.glob.is_match(path)).is_some_and(|t| !t.negated)
+    }
+
+    /// Run the command with `paths` on its standard input
+    ///
+    /// # Errors
+    /// * `CoreError::Hook` - The command couldn't be started or exited
+    ///   with a failure
+    pub fn run(&self, paths: &[&PathBuf]) -> Result<()> {
+        let mut child = Command::new(&self.exec[0])
+            .args(&self.exec[1..])
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| CoreError::Hook(format!("{}: could not run {}: {}", self.name, self.exec[0], e)))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // A command that doesn't read them has every right to exit first
+            for path in paths {
+                if writeln!(stdin, "{}", path.display()).is_err() {
+                    break;
+                }
+            }
+        }
+
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(CoreError::Hook(format!("{}: {} failed ({})", self.name, self.exec[0], status)));
+        }
+        Ok(())
+    }
+}
+
+/// Split `command` into words at spaces outside quotes; `\` escapes the
+/// next character outside single quotes
+///
+/// # Returns
+/// None if a quote isn't closed
+fn split_command(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.push(c),
+            (_, '\\') => word.extend(chars.next()),
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+                continue;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+                continue;
+            }
+            (None, c) => word.push(c),
+        }
+        in_word = true;
+    }
+
+    if quote.is_some() {
+        return None;
+    }
+    if in_word {
+        words.push(word);
+    }
+    Some(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse() {
+        let text = "# Keep MPD current\n[Trigger]\nOperation = Import\nOperation = remove\nTarget = /srv/music/*\nTarget = !*.cue\n\n\
+                    [Action]\nDescription = Updating MPD\nWhen = PostTransaction\nExec = /bin/sh -c 'cat > \"$0\"' out\\ file\n";
+        let hook = Hook::parse("mpd", text).unwrap();
+        assert_eq!(hook.operations, [HookOperation::Import, HookOperation::Remove]);
+        assert_eq!((hook.when, hook.abort_on_fail), (HookWhen::PostTransaction, false));
+        assert_eq!(hook.exec, ["/bin/sh", "-c", "cat > \"$0\"", "out file"]);
+
+        let paths = [PathBuf::from("/srv/music/Low/01.flac"), PathBuf::from("/srv/music/Low/Low.cue"), PathBuf::from("/tmp/x")];
+        assert_eq!(hook.triggered(HookOperation::Import, &paths).unwrap(), [&paths[0]]);
+        assert!(hook.triggered(HookOperation::Sync, &paths).is_none());
+        assert!(hook.triggered(HookOperation::Remove, &paths[1..]).is_none());
+
+        for bad in ["[Trigger]\nOperation = Install\n", "Exec = true\n", "[Action]\nWhen = PostTransaction\nExec = true\n"] {
+            assert!(matches!(Hook::parse("bad", bad), Err(CoreError::Hook(_))), "{}", bad);
+        }
+        assert!(split_command("a 'b").is_none());
+        assert_eq!(split_command(" a  \"\" b ").unwrap(), ["a", "", "b"]);
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("paths");
+        let text = format!(
+            "[Trigger]\nOperation = Sync\n[Action]\nWhen = PreTransaction\nExec = /bin/sh -c 'cat > \"$0\"' '{}'\nAbortOnFail\n",
+            out.display()
+        );
+        fs::write(dir.path().join("10-save.hook"), text).unwrap();
+        fs::write(dir.path().join("20-fail.hook"), "[Trigger]\nOperation = Sync\n[Action]\nWhen = PreTransaction\nExec = false\n")
+            .unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a hook").unwrap();
+
+        let hooks = Hook::load_dir(dir.path()).unwrap();
+        assert_eq!(hooks.iter().map(|h| h.name.as_str()).collect::<Vec<_>>(), ["10-save", "20-fail"]);
+        assert!(hooks[0].abort_on_fail);
+
+        let paths = [PathBuf::from("/inbox/Low - Trust"), PathBuf::from("/inbox/Low - Things We Lost")];
+        let matched = hooks[0].triggered(HookOperation::Sync, &paths).unwrap();
+        hooks[0].run(&matched).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), "/inbox/Low - Trust\n/inbox/Low - Things We Lost\n");
+        assert!(matches!(hooks[1].run(&matched), Err(CoreError::Hook(_))));
+        assert!(Hook::load_dir(&dir.path().join("missing")).unwrap().is_empty());
+    }
+}
@@ -1,6 +1,88 @@
 mod typing;
 mod coreerror;
+mod collate;
+mod template;
+mod txlog;
+mod verify;
+mod notify;
+mod metrics;
+mod checkpoint;
+mod privsep;
+mod manifest;
+mod fuzzy;
+mod pager;
+mod quality;
+mod content;
+mod artpolicy;
+mod doctor;
+mod filter;
+mod quota;
+mod export;
+mod searchcache;
+mod provenance;
+mod downloadcache;
+mod notes;
+mod editor;
+mod confirm;
+mod librarydb;
+mod beets;
+mod daemon;
+mod libgraph;
+mod search;
+mod progress;
+mod sourcehealth;
+mod schedule;
+mod dblock;
+mod hooks;
 
 
 pub use typing::String;
-pub use coreerror::CoreError;
\ No newline at end of file
+pub use coreerror::{CoreError, Result};
+pub use checkpoint::Checkpoint;
+pub use collate::Collation;
+pub use fuzzy::{FuzzyMatcher, Resolution};
+pub use template::{DiscLayout, Template, TemplateFields};
+pub use txlog::{FileChange, TxFilter, TxLog, TxOutcome, TxRecord};
+pub use verify::{
+    LogScoreCheck, ScriptCheck, SpectrogramCheck, Verdict, VerifyCheck, VerifyReport, VerifyStage, score_log,
+};
+pub use metrics::{Metric, MetricSample, MetricsStore, Trend};
+pub use privsep::{DownloadUser, HttpRequest, HttpResponse, serve_download_child};
+/// Shared with `--checksum`, so manifests and verified copies hash alike
+pub use flacman_fs::sha256_file;
+pub use manifest::{
+    MANIFEST_NAME, Manifest, ManifestCheck, ManifestMismatch, SourceTrust, Trust, verify_signature,
+};
+pub use doctor::{
+    Diagnosis, Health, check_free_space, check_json_file, check_program, check_symlinks, check_writable_dir,
+    find_program, free_space,
+};
+pub use quota::{
+    DownloadUsage, Quota, QuotaLedger, QuotaLevel, QuotaPolicy, QuotaUsage, QuotaWindow, parse_size,
+};
+pub use filter::{Condition, FilterOp, TrackFilter};
+pub use provenance::{Provenance, ProvenanceStore, SOURCE_SIDECAR, SourceInfo};
+pub use searchcache::{Cached, SearchCache};
+pub use downloadcache::{
+    BlobEntry, BlobOrigin, CacheStats, DownloadCache, EvictReason, EvictionPolicy, EvictionReport, PartialDownload,
+    Stored,
+};
+pub use export::{ExportOptions, ExportPolicy, GainMode, GainSource, ReplayGain};
+pub use artpolicy::{ArtPolicy, ArtStorage};
+pub use sourcehealth::{Failover, SourceHealth, SourceState};
+pub use schedule::{Schedule, TimeWindow};
+pub use content::{ContentPolicy, ContentType};
+pub use quality::{Encoding, QualityLadder, QualityPolicy, QualityRung};
+pub use pager::{pager_command, start_pager};
+pub use confirm::{Checklist, ChecklistItem, ChecklistStep, Confirm, WithoutTerminal};
+pub use editor::{edit_file, edit_text, editor_command};
+pub use dblock::DbLock;
+pub use hooks::{Hook, HookOperation, HookWhen};
+pub use librarydb::{AlbumRecord, LibraryDb, PlaylistRecord, TrackRecord, TrackSelection};
+pub use daemon::{DaemonRequest, DaemonResponse, serve_requests};
+pub use beets::{read_beets_library, write_beets_library};
+pub use libgraph::{LibraryGraph, LibraryNode, RemovalPlan, RemovalScope};
+pub use progress::{NoProgress, Progress, ProgressTotals};
+pub use search::{SearchField, SearchHit, SearchIndex, SearchQuery, SearchTerm};
+pub use notes::{NOTES_FILE, Note, NoteStore, NoteSubject, mirror_note};
+pub use notify::{NotifyConfig, NotifySettings, Summary, notify_desktop, notify_email, notify_webhook};
@@ -0,0 +1,294 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crate::coreerror::{CoreError, Result};
+
+
+/// What a path in the library is, as far as removing it goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibraryNode {
+    /// Directory of albums
+    Artist,
+    /// Directory holding tracks
+    Album,
+    Track,
+    /// Any other file of an album or artist: cover art, cue sheets, rip
+    /// logs, playlists
+    Sidecar,
+}
+
+/// How far a removal reaches beyond its targets, after pacman's `-Rs`,
+/// `-Rc` and `-Rn`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RemovalScope {
+    /// A track takes the rest of its album with it
+    pub recursive: bool,
+    /// An artist takes all of its albums with it
+    pub cascade: bool,
+    /// Sidecars go with the albums and artists they belong to instead of
+    /// being kept
+    pub nosave: bool,
+}
+
+/// What a removal deletes and what it leaves behind
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemovalPlan {
+    /// Files and directories to remove; a directory stands for everything
+    /// in it
+    pub remove: Vec<PathBuf>,
+    /// Sidecars kept although everything they belong to is removed
+    pub saved: Vec<PathBuf>,
+}
+
+/// The artists, albums, tracks and sidecars of a library, and what
+/// belongs to what
+///
+/// Tracks belong to the album directory they are in, and albums to the
+/// artist directory above them, unless that is the library root. A
+/// sidecar belongs to the nearest album above it, else to its artist.
+#[derive(Debug, Clone, Default)]
+pub struct LibraryGraph {
+    nodes: BTreeMap<PathBuf, LibraryNode>,
+    /// What each node belongs to
+    owners: BTreeMap<PathBuf, PathBuf>,
+}
+
+impl LibraryGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the files found below `root`
+    ///
+    /// Directories are removed as a whole once everything added from them
+    /// is, so every file in them should be added.
+    ///
+    /// # Arguments
+    /// * `root` - Library root the files are in, or for files outside any
+    ///   library the directory above their artist or album
+    /// * `files` - Each file and whether it is a track
+    pub fn add_files<I: IntoIterator<Item = (PathBuf, bool)>>(&mut self, root: &Path, files: I) {
+        let (tracks, sidecars): (Vec<_>, Vec<_>) = files.into_iter().partition(|(_, is_track)| *is_track);
+
+        for (track, _) in tracks {
+            let Some(album) = track.parent().filter(|dir| dir.starts_with(root) && *dir != root) else {
+                continue;
+            };
+            self.nodes.insert(album.to_path_buf(), LibraryNode::Album);
+            self.owners.insert(track.clone(), album.to_path_buf());
+            self.nodes.insert(track, LibraryNode::Track);
+        }
+
+        let albums: Vec<PathBuf> = self.nodes_of(LibraryNode::Album).map(Path::to_path_buf).collect();
+        for album in albums {
+            let Some(artist) = album.parent().filter(|dir| dir.starts_with(root) && *dir != root) else {
+                continue;
+            };
+            let artist = artist.to_path_buf();
+            // An album inside another one is a disc of it
+            if self.nodes.get(&artist) != Some(&LibraryNode::Album) {
+                self.nodes.insert(artist.clone(), LibraryNode::Artist);
+            }
+            self.owners.insert(album, artist);
+        }
+
+        for (sidecar, _) in sidecars {
+            let owner = sidecar
+                .ancestors()
+                .skip(1)
+                .take_while(|dir| dir.starts_with(root) && *dir != root)
+                .find(|dir| self.nodes.contains_key(*dir))
+                .map(Path::to_path_buf);
+            if let Some(owner) = owner {
+                self.owners.insert(sidecar.clone(), owner);
+                self.nodes.insert(sidecar, LibraryNode::Sidecar);
+            }
+        }
+    }
+
+    pub fn kind(&self, path: &Path) -> Option<LibraryNode> {
+        self.nodes.get(path).copied()
+    }
+
+    /// The album a track or sidecar belongs to, or the artist an album
+    /// belongs to
+    pub fn owner(&self, path: &Path) -> Option<&Path> {
+        self.owners.get(path).map(PathBuf::as_path)
+    }
+
+    /// What belongs to `path` directly
+    pub fn members(&self, path: &Path) -> Vec<&Path> {
+        self.owners.iter().filter(|(_, owner)| *owner == path).map(|(member, _)| member.as_path()).collect()
+    }
+
+    fn nodes_of(&self, kind: LibraryNode) -> impl Iterator<Item = &Path> {
+        self.nodes.iter().filter(move |(_, k)| **k == kind).map(|(path, _)| path.as_path())
+    }
+
+    /// What removing `targets` within `scope` takes away
+    ///
+    /// The tracks of the targets go, together with the sidecars of every
+    /// album and artist left without tracks when `scope.nosave` is set.
+    /// Albums and artists that end up with nothing left are removed as
+    /// whole directories. Paths not in the graph are removed as they are.
+    ///
+    /// # Errors
+    /// * `CoreError::HasDependents` - A target is an artist and
+    ///   `scope.cascade` isn't set
+    pub fn plan<P: AsRef<Path>>(&self, targets: &[P], scope: RemovalScope) -> Result<RemovalPlan> {
+        let mut removed: BTreeSet<&Path> = BTreeSet::new();
+        let mut plan = RemovalPlan::default();
+
+        for target in targets {
+            let target = target.as_ref();
+            let node = match self.kind(target) {
+                Some(LibraryNode::Track) if scope.recursive => self.owner(target).unwrap_or(target),
+                Some(LibraryNode::Artist) if !scope.cascade => {
+                    return Err(CoreError::HasDependents(target.to_path_buf()));
+                }
+                Some(_) => target,
+                None => {
+                    plan.remove.push(target.to_path_buf());
+                    continue;
+                }
+            };
+            // An explicitly named sidecar goes regardless of `nosave`
+            if self.kind(node) == Some(LibraryNode::Sidecar) {
+                removed.insert(node);
+            }
+            removed.extend(self.tracks_under(node));
+        }
+
+        // Whatever is left without tracks loses its sidecars too, or keeps
+        // them as saved
+        let emptied: Vec<&Path> = self
+            .nodes
+            .iter()
+            .filter(|(_, kind)| matches!(kind, LibraryNode::Album | LibraryNode::Artist))
+            .map(|(path, _)| path.as_path())
+            .filter(|dir| {
+                let tracks = self.tracks_under(dir);
+                !tracks.is_empty() && tracks.iter().all(|t| removed.contains(t))
+            })
+            .collect();
+        for dir in emptied {
+            for member in self.members(dir) {
+                if self.kind(member) != Some(LibraryNode::Sidecar) || removed.contains(member) {
+                    continue;
+                }
+                if scope.nosave {
+                    removed.insert(member);
+                } else {
+                    plan.saved.push(member.to_path_buf());
+                }
+            }
+        }
+
+        // A directory everything of which goes is removed as one
+        let mut covered: BTreeSet<&Path> = BTreeSet::new();
+        for (path, kind) in &self.nodes {
+            let is_dir = matches!(kind, LibraryNode::Album | LibraryNode::Artist);
+            if !is_dir || covered.iter().any(|d| path.starts_with(d)) {
+                continue;
+            }
+            if self.all_members(path).iter().all(|m| removed.contains(m)) {
+                covered.insert(path);
+            }
+        }
+        plan.remove.extend(covered.iter().map(|d| d.to_path_buf()));
+        plan.remove
+            .extend(removed.into_iter().filter(|p| !covered.iter().any(|d| p.starts_with(d))).map(Path::to_path_buf));
+
+        plan.remove.sort();
+        plan.remove.dedup();
+        plan.saved.sort();
+        Ok(plan)
+    }
+
+    /// The files belonging to `path`, directly or through its albums
+    fn all_members(&self, path: &Path) -> Vec<&Path> {
+        let mut files = Vec::new();
+        for member in self.members(path) {
+            match self.kind(member) {
+                Some(LibraryNode::Album) => files.extend(self.all_members(member)),
+                _ => files.push(member),
+            }
+        }
+        files
+    }
+
+    /// The tracks of `path`: itself if it is one, else those of the albums
+    /// it is or holds
+    fn tracks_under(&self, path: &Path) -> Vec<&Path> {
+        match self.kind(path) {
+            Some(LibraryNode::Track) => self.nodes.get_key_value(path).map(|(p, _)| p.as_path()).into_iter().collect(),
+            Some(LibraryNode::Sidecar) | None => Vec::new(),
+            Some(_) => self
+                .nodes
+                .iter()
+                .filter(|(p, kind)| **kind == LibraryNode::Track && p.starts_with(path))
+                .map(|(p, _)| p.as_path())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> LibraryGraph {
+        let files = [
+            ("/lib/Low/Trust/01 Canada.flac", true),
+            ("/lib/Low/Trust/02 Candy Girl.flac", true),
+            ("/lib/Low/Trust/cover.jpg", false),
+            ("/lib/Low/Trust/Scans/back.png", false),
+            ("/lib/Low/Secret Name/01 I Remember.flac", true),
+            ("/lib/Low/artist.jpg", false),
+            ("/lib/Loose/01.flac", true),
+        ];
+        let mut graph = LibraryGraph::new();
+        graph.add_files(Path::new("/lib"), files.map(|(path, is_track)| (PathBuf::from(path), is_track)));
+        graph
+    }
+
+    #[test]
+    fn test_graph_structure() {
+        let graph = graph();
+        assert_eq!(graph.kind(Path::new("/lib/Low")), Some(LibraryNode::Artist));
+        assert_eq!(graph.kind(Path::new("/lib/Loose")), Some(LibraryNode::Album));
+        assert_eq!(graph.owner(Path::new("/lib/Loose")), None);
+        assert_eq!(graph.owner(Path::new("/lib/Low/Trust/Scans/back.png")), Some(Path::new("/lib/Low/Trust")));
+        assert_eq!(graph.owner(Path::new("/lib/Low/artist.jpg")), Some(Path::new("/lib/Low")));
+        assert_eq!(graph.members(Path::new("/lib/Low")).len(), 3);
+    }
+
+    #[test]
+    fn test_plan_scopes() {
+        let graph = graph();
+        let p = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        // Plain removal of an album keeps its sidecars
+        let plan = graph.plan(&["/lib/Low/Trust"], RemovalScope::default()).unwrap();
+        assert_eq!(plan.remove, p(&["/lib/Low/Trust/01 Canada.flac", "/lib/Low/Trust/02 Candy Girl.flac"]));
+        assert_eq!(plan.saved, p(&["/lib/Low/Trust/Scans/back.png", "/lib/Low/Trust/cover.jpg"]));
+
+        let nosave = RemovalScope { nosave: true, ..Default::default() };
+        assert_eq!(graph.plan(&["/lib/Low/Trust"], nosave).unwrap().remove, p(&["/lib/Low/Trust"]));
+
+        // -s takes the rest of the album, -c the whole artist
+        let recursive = RemovalScope { recursive: true, nosave: true, ..Default::default() };
+        let plan = graph.plan(&["/lib/Low/Trust/01 Canada.flac"], recursive).unwrap();
+        assert_eq!(plan.remove, p(&["/lib/Low/Trust"]));
+        let plan = graph.plan(&["/lib/Low/Trust/01 Canada.flac"], RemovalScope::default()).unwrap();
+        assert_eq!((plan.remove, plan.saved), (p(&["/lib/Low/Trust/01 Canada.flac"]), Vec::new()));
+
+        assert!(matches!(graph.plan(&["/lib/Low"], nosave), Err(CoreError::HasDependents(_))));
+        let cascade = RemovalScope { cascade: true, ..Default::default() };
+        let plan = graph.plan(&["/lib/Low"], cascade).unwrap();
+        assert_eq!(plan.remove.len(), 3);
+        assert_eq!(plan.saved.len(), 3);
+        let plan = graph.plan(&["/lib/Low"], RemovalScope { nosave: true, ..cascade }).unwrap();
+        assert_eq!(plan.remove, p(&["/lib/Low"]));
+    }
+}
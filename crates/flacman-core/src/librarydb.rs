@@ -0,0 +1,638 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use serde::Serialize;
+
+use crate::coreerror::{CoreError, Result};
+use crate::template::TemplateFields;
+
+
+/// Schema version stored in `PRAGMA user_version`
+const SCHEMA_VERSION: i32 = 2;
+
+/// How long a statement waits for another connection's write to finish
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS artists (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS albums (
+        id INTEGER PRIMARY KEY,
+        artist_id INTEGER NOT NULL REFERENCES artists(id),
+        title TEXT NOT NULL,
+        year INTEGER,
+        UNIQUE (artist_id, title)
+    );
+    CREATE TABLE IF NOT EXISTS tracks (
+        id INTEGER PRIMARY KEY,
+        album_id INTEGER NOT NULL REFERENCES albums(id),
+        path TEXT NOT NULL UNIQUE,
+        artist TEXT NOT NULL,
+        title TEXT NOT NULL,
+        disc INTEGER,
+        track INTEGER,
+        tags TEXT NOT NULL,
+        sha256 TEXT,
+        size INTEGER NOT NULL,
+        modified INTEGER NOT NULL,
+        added TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS tracks_album ON tracks(album_id);
+    CREATE TABLE IF NOT EXISTS partial_albums (
+        artist TEXT NOT NULL,
+        title TEXT NOT NULL,
+        tracks TEXT NOT NULL,
+        PRIMARY KEY (artist, title)
+    );
+    CREATE TABLE IF NOT EXISTS playlists (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        updated TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS playlist_entries (
+        playlist_id INTEGER NOT NULL REFERENCES playlists(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        path TEXT NOT NULL,
+        PRIMARY KEY (playlist_id, position)
+    );
+";
+
+/// Columns of a track joined with its album and album artist, in the order
+/// `TrackRecord::from_row` reads them
+const TRACK_COLUMNS: &str = "
+    SELECT tracks.path, artists.name, albums.title, tracks.artist, tracks.title, tracks.disc, tracks.track,
+           albums.year, tracks.tags, tracks.sha256, tracks.size, tracks.modified, tracks.added
+    FROM tracks
+    JOIN albums ON albums.id = tracks.album_id
+    JOIN artists ON artists.id = albums.artist_id
+";
+
+/// One library file as the database knows it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TrackRecord {
+    pub path: PathBuf,
+    /// Album artist, or the track artist when there is none
+    pub album_artist: String,
+    pub album: String,
+    pub artist: String,
+    pub title: String,
+    pub disc: Option<u32>,
+    pub track: Option<u32>,
+    pub year: Option<u32>,
+    /// Other tags worth keeping, e.g. `genre`, `tracktotal`
+    pub tags: BTreeMap<String, String>,
+    pub sha256: Option<String>,
+    pub size: u64,
+    /// Modification time of the file when it was indexed, in Unix seconds
+    pub modified: i64,
+    /// When the file first entered the database
+    pub added: DateTime<Local>,
+}
+
+impl TrackRecord {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let path: String = row.get(0)?;
+        let tags: String = row.get(8)?;
+        let size: i64 = row.get(10)?;
+        Ok(TrackRecord {
+            path: PathBuf::from(path),
+            album_artist: row.get(1)?,
+            album: row.get(2)?,
+            artist: row.get(3)?,
+            title: row.get(4)?,
+            disc: row.get(5)?,
+            track: row.get(6)?,
+            year: row.get(7)?,
+            tags: serde_json::from_str(&tags).unwrap_or_default(),
+            sha256: row.get(9)?,
+            size: size as u64,
+            modified: row.get(11)?,
+            added: row.get(12)?,
+        })
+    }
+}
+
+/// The indexed tag fields, plus `path`, `size` (in bytes) and `added`
+impl TemplateFields for TrackRecord {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        let number = |n: Option<u32>| n.map(|n| Cow::Owned(n.to_string()));
+        match name {
+            "path" => Some(self.path.to_string_lossy()),
+            "albumartist" => Some(Cow::Borrowed(self.album_artist.as_str())),
+            "album" => Some(Cow::Borrowed(self.album.as_str())),
+            "artist" => Some(Cow::Borrowed(self.artist.as_str())),
+            "title" => Some(Cow::Borrowed(self.title.as_str())),
+            "disc" => number(self.disc),
+            "track" => number(self.track),
+            "year" => number(self.year),
+            "size" => Some(Cow::Owned(self.size.to_string())),
+            "added" => Some(Cow::Owned(self.added.format("%Y-%m-%d").to_string())),
+            _ => self.tags.get(name).map(|v| Cow::Borrowed(v.as_str())),
+        }
+        .filter(|v| !v.is_empty())
+    }
+}
+
+/// An album with the number and size of its indexed tracks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AlbumRecord {
+    pub artist: String,
+    pub title: String,
+    pub year: Option<u32>,
+    pub tracks: usize,
+    pub bytes: u64,
+}
+
+/// `artist`/`albumartist`, `album`, `year`, `tracks` and `size` (in bytes)
+impl TemplateFields for AlbumRecord {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        match name {
+            "artist" | "albumartist" => Some(Cow::Borrowed(self.artist.as_str())),
+            "album" => Some(Cow::Borrowed(self.title.as_str())),
+            "year" => self.year.map(|y| Cow::Owned(y.to_string())),
+            "tracks" => Some(Cow::Owned(self.tracks.to_string())),
+            "size" => Some(Cow::Owned(self.bytes.to_string())),
+            _ => None,
+        }
+        .filter(|v| !v.is_empty())
+    }
+}
+
+/// A named playlist kept in the database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlaylistRecord {
+    pub name: String,
+    /// Entries, including files that are no longer indexed
+    pub entries: usize,
+    /// When it was last saved
+    pub updated: DateTime<Local>,
+}
+
+/// Track numbers picked out of a release, e.g. `1,3,7-9`
+///
+/// Empty when only tracks named by title were picked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackSelection(pub BTreeSet<u32>);
+
+impl TrackSelection {
+    pub fn contains(&self, track: u32) -> bool {
+        self.0.contains(&track)
+    }
+}
+
+impl FromStr for TrackSelection {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut tracks = BTreeSet::new();
+        for word in s.split(',').map(str::trim).filter(|w| !w.is_empty()) {
+            let (first, last) = word.split_once('-').unwrap_or((word, word));
+            match (first.trim().parse::<u32>(), last.trim().parse::<u32>()) {
+                (Ok(first), Ok(last)) if first >= 1 && first <= last => tracks.extend(first..=last),
+                _ => return Err(CoreError::TrackList(format!("not a track or range of tracks: {word}"))),
+            }
+        }
+        Ok(TrackSelection(tracks))
+    }
+}
+
+/// Consecutive tracks are joined into ranges again
+impl fmt::Display for TrackSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for &track in &self.0 {
+            match ranges.last_mut() {
+                Some((_, last)) if *last + 1 == track => *last = track,
+                _ => ranges.push((track, track)),
+            }
+        }
+        let ranges: Vec<String> = ranges
+            .iter()
+            .map(|(first, last)| if first == last { first.to_string() } else { format!("{first}-{last}") })
+            .collect();
+        write!(f, "{}", ranges.join(","))
+    }
+}
+
+/// Index of the artists, albums and tracks in the library
+///
+/// Kept in an SQLite database in the data directory. Files are the truth:
+/// the index is updated as flacman adds and removes them and can be
+/// rebuilt from the library at any time.
+pub struct LibraryDb {
+    conn: Connection,
+}
+
+impl LibraryDb {
+    /// Open the database at `path`, creating it if needed
+    ///
+    /// # Errors
+    /// * `CoreError::Database` - Not an SQLite database
+    /// * `CoreError::DatabaseVersion` - Written by a newer flacman
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        // Readers wait out a writer in another process rather than fail
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+
+        let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(CoreError::DatabaseVersion(version));
+        }
+        conn.execute_batch(SCHEMA)?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
+        Ok(LibraryDb { conn })
+    }
+
+    /// Add or update the records of `tracks`, keyed by path
+    ///
+    /// A file keeps the date it was first added. Albums and artists left
+    /// without tracks by a retag are dropped.
+    ///
+    /// # Returns
+    /// Number of tracks written
+    pub fn update_tracks(&mut self, tracks: &[TrackRecord]) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+
+        for track in tracks {
+            tx.execute("INSERT OR IGNORE INTO artists (name) VALUES (?1)", params![track.album_artist])?;
+            let artist_id: i64 =
+                tx.query_row("SELECT id FROM artists WHERE name = ?1", params![track.album_artist], |r| r.get(0))?;
+
+            tx.execute(
+                "INSERT INTO albums (artist_id, title, year) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (artist_id, title) DO UPDATE SET year = coalesce(excluded.year, year)",
+                params![artist_id, track.album, track.year],
+            )?;
+            let album_id: i64 = tx.query_row(
+                "SELECT id FROM albums WHERE artist_id = ?1 AND title = ?2",
+                params![artist_id, track.album],
+                |r| r.get(0),
+            )?;
+
+            tx.execute(
+                "INSERT INTO tracks (album_id, path, artist, title, disc, track, tags, sha256, size, modified, added)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                 ON CONFLICT (path) DO UPDATE SET
+                     album_id = excluded.album_id, artist = excluded.artist, title = excluded.title,
+                     disc = excluded.disc, track = excluded.track, tags = excluded.tags,
+                     sha256 = excluded.sha256, size = excluded.size, modified = excluded.modified",
+                params![
+                    album_id,
+                    track.path.to_string_lossy(),
+                    track.artist,
+                    track.title,
+                    track.disc,
+                    track.track,
+                    serde_json::to_string(&track.tags)?,
+                    track.sha256,
+                    track.size as i64,
+                    track.modified,
+                    track.added,
+                ],
+            )?;
+        }
+
+        prune(&tx)?;
+        tx.commit()?;
+        Ok(tracks.len())
+    }
+
+    /// Forget `path` and, if it is a directory, every track below it
+    ///
+    /// # Returns
+    /// Number of tracks removed
+    pub fn remove_under(&mut self, path: &Path) -> Result<usize> {
+        self.remove_all_under(&[path])
+    }
+
+    /// Forget every path in `paths` and the tracks below them, all at once:
+    /// if any removal fails, the database is left as it was
+    ///
+    /// # Returns
+    /// Number of tracks removed
+    pub fn remove_all_under<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut removed = 0;
+        for path in paths {
+            let exact = path.as_ref().to_string_lossy();
+            let prefix = format!("{}/", exact.trim_end_matches('/'));
+            removed += tx.execute(
+                "DELETE FROM tracks WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+                params![exact, prefix],
+            )?;
+        }
+        prune(&tx)?;
+        tx.commit()?;
+
+        Ok(removed)
+    }
+
+    /// The record of the file at `path`, if it is indexed
+    pub fn track(&self, path: &Path) -> Result<Option<TrackRecord>> {
+        let sql = format!("{} WHERE tracks.path = ?1", TRACK_COLUMNS);
+        Ok(self.conn.query_row(&sql, params![path.to_string_lossy()], TrackRecord::from_row).optional()?)
+    }
+
+    /// Records of `path` and every track below it, in path order
+    pub fn tracks_under(&self, path: &Path) -> Result<Vec<TrackRecord>> {
+        let exact = path.to_string_lossy();
+        let prefix = format!("{}/", exact.trim_end_matches('/'));
+        let sql = format!(
+            "{} WHERE tracks.path = ?1 OR substr(tracks.path, 1, length(?2)) = ?2 ORDER BY tracks.path",
+            TRACK_COLUMNS
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let tracks = stmt.query_map(params![exact, prefix], TrackRecord::from_row)?;
+        Ok(tracks.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Every indexed track, by artist, album and position
+    pub fn tracks(&self) -> Result<Vec<TrackRecord>> {
+        let sql =
+            format!("{} ORDER BY artists.name, albums.title, tracks.disc, tracks.track, tracks.path", TRACK_COLUMNS);
+        let mut stmt = self.conn.prepare(&sql)?;
+        let tracks = stmt.query_map([], TrackRecord::from_row)?;
+        Ok(tracks.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Tracks whose artist, album artist, album or title contain every
+    /// word of `term`, ignoring ASCII case
+    pub fn search(&self, term: &str) -> Result<Vec<TrackRecord>> {
+        let words: Vec<String> = term.split_whitespace().map(str::to_lowercase).collect();
+        let haystack = "lower(artists.name || ' ' || albums.title || ' ' || tracks.artist || ' ' || tracks.title)";
+        let mut sql = format!("{} WHERE 1", TRACK_COLUMNS);
+        for i in 1..=words.len() {
+            sql.push_str(&format!(" AND instr({}, ?{}) > 0", haystack, i));
+        }
+        sql.push_str(" ORDER BY artists.name, albums.title, tracks.disc, tracks.track, tracks.path");
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let tracks = stmt.query_map(rusqlite::params_from_iter(&words), TrackRecord::from_row)?;
+        Ok(tracks.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Every album, by artist and title
+    pub fn albums(&self) -> Result<Vec<AlbumRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT artists.name, albums.title, albums.year, count(tracks.id), coalesce(sum(tracks.size), 0)
+             FROM albums
+             JOIN artists ON artists.id = albums.artist_id
+             JOIN tracks ON tracks.album_id = albums.id
+             GROUP BY albums.id
+             ORDER BY artists.name, albums.title",
+        )?;
+        let albums = stmt.query_map([], |row| {
+            let (tracks, bytes): (i64, i64) = (row.get(3)?, row.get(4)?);
+            Ok(AlbumRecord {
+                artist: row.get(0)?,
+                title: row.get(1)?,
+                year: row.get(2)?,
+                tracks: tracks as usize,
+                bytes: bytes as u64,
+            })
+        })?;
+
+        Ok(albums.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Size and modification time of every indexed file, to tell which
+    /// ones changed since
+    pub fn file_states(&self) -> Result<HashMap<PathBuf, (u64, i64)>> {
+        let mut stmt = self.conn.prepare("SELECT path, size, modified FROM tracks")?;
+        let states = stmt.query_map([], |row| {
+            let (path, size): (String, i64) = (row.get(0)?, row.get(1)?);
+            Ok((PathBuf::from(path), (size as u64, row.get(2)?)))
+        })?;
+
+        Ok(states.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Save `paths`, in order, as the playlist `name`, replacing what it
+    /// held before
+    ///
+    /// Entries are kept by path rather than by track so a playlist
+    /// survives the library being reindexed.
+    pub fn save_playlist<P: AsRef<Path>>(&mut self, name: &str, paths: &[P]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO playlists (name, updated) VALUES (?1, ?2)
+             ON CONFLICT (name) DO UPDATE SET updated = excluded.updated",
+            params![name, Local::now()],
+        )?;
+        let id: i64 = tx.query_row("SELECT id FROM playlists WHERE name = ?1", params![name], |r| r.get(0))?;
+
+        tx.execute("DELETE FROM playlist_entries WHERE playlist_id = ?1", params![id])?;
+        for (position, path) in paths.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO playlist_entries (playlist_id, position, path) VALUES (?1, ?2, ?3)",
+                params![id, position as i64, path.as_ref().to_string_lossy()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The indexed tracks of the playlist `name`, in order, or `None` if
+    /// there is no such playlist
+    ///
+    /// Entries whose file is no longer indexed are left out.
+    pub fn playlist(&self, name: &str) -> Result<Option<Vec<TrackRecord>>> {
+        let id: Option<i64> = self
+            .conn
+            .query_row("SELECT id FROM playlists WHERE name = ?1", params![name], |r| r.get(0))
+            .optional()?;
+        let Some(id) = id else {
+            return Ok(None);
+        };
+
+        let sql = format!(
+            "{} JOIN playlist_entries ON playlist_entries.path = tracks.path
+             WHERE playlist_entries.playlist_id = ?1 ORDER BY playlist_entries.position",
+            TRACK_COLUMNS
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let tracks = stmt.query_map(params![id], TrackRecord::from_row)?;
+        Ok(Some(tracks.collect::<rusqlite::Result<_>>()?))
+    }
+
+    /// Every saved playlist, by name
+    pub fn playlists(&self) -> Result<Vec<PlaylistRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT playlists.name, count(playlist_entries.path), playlists.updated
+             FROM playlists
+             LEFT JOIN playlist_entries ON playlist_entries.playlist_id = playlists.id
+             GROUP BY playlists.id
+             ORDER BY playlists.name",
+        )?;
+        let playlists = stmt.query_map([], |row| {
+            let entries: i64 = row.get(1)?;
+            Ok(PlaylistRecord { name: row.get(0)?, entries: entries as usize, updated: row.get(2)? })
+        })?;
+
+        Ok(playlists.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Forget the playlist `name`
+    ///
+    /// # Returns
+    /// Whether there was such a playlist
+    pub fn delete_playlist(&mut self, name: &str) -> Result<bool> {
+        Ok(self.conn.execute("DELETE FROM playlists WHERE name = ?1", params![name])? > 0)
+    }
+
+    /// Remember that only `tracks` of an album were wanted, so the others
+    /// aren't reported missing
+    ///
+    /// Kept apart from the indexed albums, since the album is marked before
+    /// any of its files arrive. Marking it again adds to the selection.
+    pub fn mark_partial(&mut self, artist: &str, title: &str, tracks: &TrackSelection) -> Result<()> {
+        let mut selection = self.partial_tracks(artist, title)?.unwrap_or_default();
+        selection.0.extend(&tracks.0);
+        self.conn.execute(
+            "INSERT INTO partial_albums (artist, title, tracks) VALUES (?1, ?2, ?3)
+             ON CONFLICT (artist, title) DO UPDATE SET tracks = excluded.tracks",
+            params![artist, title, selection.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// The tracks wanted of an album that was synced partially, `None`
+    /// for an album that should be complete
+    pub fn partial_tracks(&self, artist: &str, title: &str) -> Result<Option<TrackSelection>> {
+        let tracks: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT tracks FROM partial_albums WHERE artist = ?1 AND title = ?2",
+                params![artist, title],
+                |row| row.get(0),
+            )
+            .optional()?;
+        tracks.map(|t| t.parse()).transpose()
+    }
+}
+
+/// Drop albums and artists that no longer have any tracks
+fn prune(conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM albums WHERE id NOT IN (SELECT album_id FROM tracks)", [])?;
+    conn.execute("DELETE FROM artists WHERE id NOT IN (SELECT artist_id FROM albums)", [])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Template;
+    use tempfile::tempdir;
+
+    fn record(path: &str, album: &str, title: &str) -> TrackRecord {
+        TrackRecord {
+            path: PathBuf::from(path),
+            album_artist: "Low".to_owned(),
+            album: album.to_owned(),
+            artist: "Low".to_owned(),
+            title: title.to_owned(),
+            disc: None,
+            track: Some(1),
+            year: Some(2002),
+            tags: BTreeMap::from([("genre".to_owned(), "Slowcore".to_owned())]),
+            sha256: None,
+            size: 100,
+            modified: 0,
+            added: Local::now(),
+        }
+    }
+
+    #[test]
+    fn test_update_search_remove() {
+        let dir = tempdir().unwrap();
+        let mut db = LibraryDb::open(dir.path().join("library.db")).unwrap();
+
+        db.update_tracks(&[
+            record("/music/Low/Trust/01 Canada.flac", "Trust", "Canada"),
+            record("/music/Low/Trust/02 Candy Girl.flac", "Trust", "Candy Girl"),
+            record("/music/Low/Trust Me/01 Canada.flac", "Trust Me", "Canada"),
+        ])
+        .unwrap();
+
+        let found = db.search("low CANADA").unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(db.tracks().unwrap()[1].title, "Candy Girl");
+        assert_eq!(found[0].tags["genre"], "Slowcore");
+        assert_eq!(db.albums().unwrap().len(), 2);
+
+        // A retag moves the file to another album and drops the empty one
+        let first = db.track(Path::new("/music/Low/Trust Me/01 Canada.flac")).unwrap().unwrap();
+        db.update_tracks(&[TrackRecord { album: "Trust".to_owned(), ..first.clone() }]).unwrap();
+        let albums = db.albums().unwrap();
+        assert_eq!(albums.len(), 1);
+        assert_eq!((albums[0].tracks, albums[0].bytes), (3, 300));
+        assert_eq!(db.track(&first.path).unwrap().unwrap().added, first.added);
+
+        let template: Template = "%artist% | %album% | %{disc:%disc%-}%track:02% | %genre% | %path%".parse().unwrap();
+        assert_eq!(template.render(&first), "Low | Trust Me | 01 | Slowcore | /music/Low/Trust Me/01 Canada.flac");
+
+        // Only whole path components match a directory
+        assert_eq!(db.remove_under(Path::new("/music/Low/Trust")).unwrap(), 2);
+        assert_eq!(db.tracks_under(Path::new("/music/Low")).unwrap().len(), 1);
+        assert_eq!(db.file_states().unwrap().len(), 1);
+        assert_eq!(db.remove_all_under(&[Path::new("/music/Gone"), Path::new("/music/Low/Trust Me")]).unwrap(), 1);
+        assert!(db.albums().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_partial_albums() {
+        let dir = tempdir().unwrap();
+        let mut db = LibraryDb::open(dir.path().join("library.db")).unwrap();
+
+        let selection: TrackSelection = "1, 3,7-9".parse().unwrap();
+        assert_eq!(selection.to_string(), "1,3,7-9");
+        assert!("3-1".parse::<TrackSelection>().is_err());
+
+        assert_eq!(db.partial_tracks("Low", "Trust").unwrap(), None);
+        db.mark_partial("Low", "Trust", &selection).unwrap();
+        db.mark_partial("Low", "Trust", &"2".parse().unwrap()).unwrap();
+        assert_eq!(db.partial_tracks("Low", "Trust").unwrap().unwrap().to_string(), "1-3,7-9");
+    }
+
+    #[test]
+    fn test_playlists() {
+        let dir = tempdir().unwrap();
+        let mut db = LibraryDb::open(dir.path().join("library.db")).unwrap();
+        db.update_tracks(&[
+            record("/music/Low/Trust/01 Canada.flac", "Trust", "Canada"),
+            record("/music/Low/Trust/02 Candy Girl.flac", "Trust", "Candy Girl"),
+        ])
+        .unwrap();
+
+        let paths = ["/music/Low/Trust/02 Candy Girl.flac", "/music/Gone.flac", "/music/Low/Trust/01 Canada.flac"];
+        db.save_playlist("slow", &paths).unwrap();
+        let titles: Vec<String> = db.playlist("slow").unwrap().unwrap().into_iter().map(|t| t.title).collect();
+        assert_eq!(titles, ["Candy Girl", "Canada"]);
+        assert_eq!(db.playlists().unwrap()[0].entries, 3);
+        assert_eq!(db.playlist("fast").unwrap(), None);
+
+        // Saving again replaces the entries
+        db.save_playlist("slow", &paths[..1]).unwrap();
+        assert_eq!(db.playlist("slow").unwrap().unwrap().len(), 1);
+        assert!(db.delete_playlist("slow").unwrap());
+        assert!(!db.delete_playlist("slow").unwrap());
+        assert!(db.playlists().unwrap().is_empty());
+    }
+}
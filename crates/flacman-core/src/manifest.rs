@@ -0,0 +1,331 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use flacman_fs::sha256_file;
+use serde::Deserialize;
+
+use crate::coreerror::{CoreError, Result};
+use crate::verify::{Verdict, VerifyCheck};
+
+
+/// Name of the manifest a self-hosted source places in each album (or archive root)
+pub const MANIFEST_NAME: &str = "flacman.manifest";
+
+/// How a source's manifests are signed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum Trust {
+    /// Checksums are verified, signatures are not
+    #[default]
+    None,
+    /// `flacman.manifest.minisig`, checked with `minisign`; the key is a
+    /// public key file or the base64 key itself
+    Minisign { public_key: String },
+    /// `flacman.manifest.sig` or `.asc`, checked with `gpg` against the
+    /// signing key's full fingerprint
+    Gpg { fingerprint: String },
+}
+
+/// Trust configuration of one source
+///
+/// Deserializable so each source in flacman.conf can carry its own
+/// `[sources.<name>.trust]` table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SourceTrust {
+    #[serde(flatten)]
+    pub trust: Trust,
+    /// Veto items that come without a manifest
+    pub require_manifest: bool,
+}
+
+/// A problem found while checking files against a manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestMismatch {
+    Missing(PathBuf),
+    Changed(PathBuf),
+    /// A file the manifest doesn't list
+    Unlisted(PathBuf),
+}
+
+impl std::fmt::Display for ManifestMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ManifestMismatch::Missing(p) => write!(f, "{} is missing", p.display()),
+            ManifestMismatch::Changed(p) => write!(f, "{} does not match its checksum", p.display()),
+            ManifestMismatch::Unlisted(p) => write!(f, "{} is not in the manifest", p.display()),
+        }
+    }
+}
+
+/// Checksums of an album or archive, in `sha256sum` format
+/// (`<hex digest>  <relative path>` per line)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub entries: Vec<(String, PathBuf)>,
+}
+
+impl Manifest {
+    /// # Errors
+    /// * `CoreError::Manifest` - A line is not `<sha256>  <path>`
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parsed = line.split_once(char::is_whitespace).and_then(|(digest, path)| {
+                // `sha256sum -b` marks paths with a leading '*'
+                let path = path.trim_start().trim_start_matches('*');
+                let valid = digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit());
+                (valid && !path.is_empty()).then(|| (digest.to_ascii_lowercase(), PathBuf::from(path)))
+            });
+
+            match parsed {
+                Some(entry) => entries.push(entry),
+                None => return Err(CoreError::Manifest(format!("line {}: expected '<sha256>  <path>'", n + 1))),
+            }
+        }
+
+        Ok(Manifest { entries })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Check the files below `root` against the manifest
+    ///
+    /// Every file must be listed (except the manifest and its signatures)
+    /// and match its checksum.
+    pub fn verify_dir(&self, root: &Path) -> Result<Vec<ManifestMismatch>> {
+        let mut problems = Vec::new();
+
+        for (digest, rel) in &self.entries {
+            let path = root.join(rel);
+            if !path.is_file() {
+                problems.push(ManifestMismatch::Missing(rel.clone()));
+            } else if sha256_file(&path)? != *digest {
+                problems.push(ManifestMismatch::Changed(rel.clone()));
+            }
+        }
+
+        let mut stack = vec![root.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    stack.push(path);
+                    continue;
+                }
+
+                let rel = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+                let is_manifest = ["", ".minisig", ".sig", ".asc"]
+                    .iter()
+                    .any(|suffix| rel.as_os_str().to_str() == Some(&format!("{MANIFEST_NAME}{suffix}")));
+                if !is_manifest && !self.entries.iter().any(|(_, listed)| *listed == rel) {
+                    problems.push(ManifestMismatch::Unlisted(rel));
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+}
+
+/// Verify the detached signature of `manifest` according to `trust`
+///
+/// # Returns
+/// `Err(reason)` if the signature is missing or doesn't verify
+pub fn verify_signature(manifest: &Path, trust: &Trust) -> Result<std::result::Result<(), String>> {
+    let with_suffix = |suffix: &str| {
+        let mut name = manifest.as_os_str().to_owned();
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+
+    match trust {
+        Trust::None => Ok(Ok(())),
+        Trust::Minisign { public_key } => {
+            let sig = with_suffix(".minisig");
+            if !sig.is_file() {
+                return Ok(Err("manifest is not signed (no .minisig)".to_owned()));
+            }
+
+            let key_arg = if Path::new(public_key).is_file() { "-p" } else { "-P" };
+            let output = Command::new("minisign")
+                .args(["-V", "-q", key_arg, public_key.as_str(), "-m"])
+                .arg(manifest)
+                .arg("-x")
+                .arg(&sig)
+                .output()?;
+
+            if output.status.success() {
+                Ok(Ok(()))
+            } else {
+                Ok(Err(format!(
+                    "minisign rejected the signature: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )))
+            }
+        }
+        Trust::Gpg { fingerprint } => {
+            let Some(sig) = [".sig", ".asc"].iter().map(|s| with_suffix(s)).find(|p| p.is_file()) else {
+                return Ok(Err("manifest is not signed (no .sig or .asc)".to_owned()));
+            };
+
+            let output = Command::new("gpg")
+                .args(["--batch", "--status-fd", "1", "--verify"])
+                .arg(&sig)
+                .arg(manifest)
+                .output()?;
+
+            let wanted = fingerprint.replace(' ', "").to_ascii_uppercase();
+            let valid = signed_by(&String::from_utf8_lossy(&output.stdout), &wanted);
+
+            if output.status.success() && valid {
+                Ok(Ok(()))
+            } else {
+                Ok(Err(format!("no valid signature by {wanted}")))
+            }
+        }
+    }
+}
+
+/// Whether gpg's `--status-fd` output has a valid signature by the key
+/// `fingerprint`, either the signing (sub)key or its primary key
+///
+/// `VALIDSIG` lines start with the signing key's fingerprint and, from
+/// GnuPG 1.4.7 on, end with the primary key's after nine more fields.
+fn signed_by(status: &str, fingerprint: &str) -> bool {
+    status.lines().filter_map(|l| l.strip_prefix("[GNUPG:] VALIDSIG ")).any(|rest| {
+        let fields: Vec<&str> = rest.split_whitespace().collect();
+        fields.first() == Some(&fingerprint) || (fields.len() >= 10 && fields.last() == Some(&fingerprint))
+    })
+}
+
+/// Verification check that vetoes items whose manifest is unsigned,
+/// wrongly signed, or doesn't match the files
+pub struct ManifestCheck {
+    pub trust: SourceTrust,
+}
+
+impl VerifyCheck for ManifestCheck {
+    fn name(&self) -> &str {
+        "manifest"
+    }
+
+    fn check(&self, item: &Path) -> Result<Verdict> {
+        let manifest_path = item.join(MANIFEST_NAME);
+
+        if !item.is_dir() || !manifest_path.is_file() {
+            return Ok(if self.trust.require_manifest {
+                Verdict::Veto("no signed manifest".to_owned())
+            } else {
+                Verdict::Accept
+            });
+        }
+
+        if let Err(reason) = verify_signature(&manifest_path, &self.trust.trust)? {
+            return Ok(Verdict::Veto(reason));
+        }
+
+        let manifest = Manifest::load(&manifest_path)?;
+        let problems = manifest.verify_dir(item)?;
+
+        if problems.is_empty() {
+            return Ok(Verdict::Note(format!("{} files match the manifest", manifest.entries.len())));
+        }
+
+        let shown: Vec<String> = problems.iter().take(3).map(|p| p.to_string()).collect();
+        let more = problems.len().saturating_sub(shown.len());
+        let mut reason = shown.join("; ");
+        if more > 0 {
+            reason.push_str(&format!(" (and {more} more)"));
+        }
+
+        Ok(Verdict::Veto(reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn test_parse() {
+        let manifest = Manifest::parse(&format!("# album\n{EMPTY_SHA256}  01 Intro.flac\n{EMPTY_SHA256} *cover.jpg\n"))
+            .unwrap();
+        assert_eq!(manifest.entries.len(), 2);
+        assert_eq!(manifest.entries[1].1, PathBuf::from("cover.jpg"));
+
+        assert!(Manifest::parse("abc  01.flac").is_err());
+    }
+
+    #[test]
+    fn test_verify_dir() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("01.flac"), b"").unwrap();
+        fs::write(dir.path().join("02.flac"), b"tampered").unwrap();
+        fs::write(dir.path().join("extra.exe"), b"").unwrap();
+
+        let text = format!("{EMPTY_SHA256}  01.flac\n{EMPTY_SHA256}  02.flac\n{EMPTY_SHA256}  03.flac\n");
+        fs::write(dir.path().join(MANIFEST_NAME), &text).unwrap();
+
+        let mut problems = Manifest::parse(&text).unwrap().verify_dir(dir.path()).unwrap();
+        problems.sort_by_key(|p| p.to_string());
+
+        assert_eq!(
+            problems,
+            vec![
+                ManifestMismatch::Changed("02.flac".into()),
+                ManifestMismatch::Missing("03.flac".into()),
+                ManifestMismatch::Unlisted("extra.exe".into()),
+            ]
+        );
+
+        // Only the manifest and its signatures may go unlisted
+        fs::write(dir.path().join(format!("{MANIFEST_NAME}.minisig")), b"").unwrap();
+        fs::write(dir.path().join(format!("{MANIFEST_NAME}.x.flac")), b"").unwrap();
+        let problems = Manifest::parse(&text).unwrap().verify_dir(dir.path()).unwrap();
+        assert!(problems.contains(&ManifestMismatch::Unlisted(format!("{MANIFEST_NAME}.x.flac").into())));
+        assert!(!problems.iter().any(|p| p.to_string().contains(".minisig")));
+    }
+
+    #[test]
+    fn test_signed_by_subkey_or_primary() {
+        let status = "[GNUPG:] GOODSIG 0123456789ABCDEF Low\n\
+                      [GNUPG:] VALIDSIG SUBKEYFPR 2024-01-01 1704067200 0 4 0 1 10 00 PRIMARYFPR\n";
+        assert!(signed_by(status, "SUBKEYFPR"));
+        assert!(signed_by(status, "PRIMARYFPR"));
+        assert!(!signed_by(status, "0123456789ABCDEF"));
+        assert!(!signed_by("[GNUPG:] VALIDSIG SUBKEYFPR 2024-01-01\n", "2024-01-01"));
+    }
+
+    #[test]
+    fn test_check_requires_signature() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("01.flac"), b"").unwrap();
+        fs::write(dir.path().join(MANIFEST_NAME), format!("{EMPTY_SHA256}  01.flac\n")).unwrap();
+
+        let unsigned_ok = ManifestCheck { trust: SourceTrust::default() };
+        assert!(matches!(unsigned_ok.check(dir.path()).unwrap(), Verdict::Note(_)));
+
+        // A file named like the manifest isn't let through unlisted
+        fs::write(dir.path().join(format!("{MANIFEST_NAME}.x.flac")), b"").unwrap();
+        assert!(matches!(unsigned_ok.check(dir.path()).unwrap(), Verdict::Veto(reason) if reason.contains(".x.flac")));
+        fs::remove_file(dir.path().join(format!("{MANIFEST_NAME}.x.flac"))).unwrap();
+
+        let gpg = ManifestCheck {
+            trust: SourceTrust { trust: Trust::Gpg { fingerprint: "ABCD".into() }, require_manifest: true },
+        };
+        assert!(matches!(gpg.check(dir.path()).unwrap(), Verdict::Veto(_)));
+    }
+}
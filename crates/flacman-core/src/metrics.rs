@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::Result;
+
+
+/// One measurement
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Metric {
+    /// Wall time of a whole flacman operation
+    Operation { operation: String, secs: f64 },
+    /// A transfer from a remote source
+    Download { source: String, bytes: u64, secs: f64 },
+    /// A lookup in one of the caches
+    Cache { cache: String, hit: bool },
+    /// A library or download directory scan
+    Scan { root: String, files: u64, secs: f64 },
+}
+
+impl Metric {
+    /// `(group, label, numerator, denominator)`; a trend is sum(num) / sum(den)
+    fn parts(&self) -> (&'static str, &str, f64, f64) {
+        match self {
+            Metric::Operation { operation, secs } => ("duration", operation, *secs, 1.0),
+            Metric::Download { source, bytes, secs } => ("throughput", source, *bytes as f64, *secs),
+            Metric::Cache { cache, hit } => ("hit rate", cache, if *hit { 1.0 } else { 0.0 }, 1.0),
+            Metric::Scan { root, secs, .. } => ("scan time", root, *secs, 1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricSample {
+    pub time: DateTime<Local>,
+    #[serde(flatten)]
+    pub metric: Metric,
+}
+
+/// How one measured quantity has changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trend {
+    /// `duration`, `throughput` (bytes/s), `hit rate` (0..1) or `scan time`
+    pub group: &'static str,
+    /// Operation, source, cache or scanned root
+    pub label: String,
+    /// Value over the recent window
+    pub recent: Option<f64>,
+    /// Value before the recent window
+    pub previous: Option<f64>,
+    pub samples: usize,
+}
+
+impl Trend {
+    /// Relative change from `previous` to `recent`, e.g. `-0.4` for 40% lower
+    pub fn change(&self) -> Option<f64> {
+        match (self.recent, self.previous) {
+            (Some(recent), Some(previous)) if previous != 0.0 => Some(recent / previous - 1.0),
+            _ => None,
+        }
+    }
+}
+
+/// Local, append-only store of usage metrics, one JSON object per line
+///
+/// Nothing is ever sent anywhere; the store only exists to help tune
+/// concurrency and spot sources that have gotten slow.
+#[derive(Debug, Clone)]
+pub struct MetricsStore {
+    path: PathBuf,
+}
+
+impl MetricsStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        MetricsStore { path: path.as_ref().to_path_buf() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&self, metric: Metric) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let sample = MetricSample { time: Local::now(), metric };
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&sample)?)?;
+
+        Ok(())
+    }
+
+    /// All samples, oldest first; unparseable lines are skipped
+    pub fn read_all(&self) -> Result<Vec<MetricSample>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let reader = BufReader::new(fs::File::open(&self.path)?);
+        let mut samples = Vec::new();
+
+        for line in reader.lines() {
+            if let Ok(sample) = serde_json::from_str(&line?) {
+                samples.push(sample);
+            }
+        }
+
+        Ok(samples)
+    }
+
+    /// Compare the last `window` against everything before it
+    ///
+    /// # Returns
+    /// One trend per group and label, sorted by group then label
+    pub fn trends(&self, window: TimeDelta) -> Result<Vec<Trend>> {
+        Ok(trends(&self.read_all()?, Local::now() - window))
+    }
+}
+
+#[derive(Default)]
+struct Sums {
+    recent: (f64, f64),
+    previous: (f64, f64),
+    samples: usize,
+}
+
+fn trends(samples: &[MetricSample], cutoff: DateTime<Local>) -> Vec<Trend> {
+    let mut sums: BTreeMap<(&'static str, String), Sums> = BTreeMap::new();
+
+    for sample in samples {
+        let (group, label, num, den) = sample.metric.parts();
+        let entry = sums.entry((group, label.to_owned())).or_default();
+        let bucket = if sample.time >= cutoff { &mut entry.recent } else { &mut entry.previous };
+
+        bucket.0 += num;
+        bucket.1 += den;
+        entry.samples += 1;
+    }
+
+    let ratio = |(num, den): (f64, f64)| (den > 0.0).then(|| num / den);
+
+    sums.into_iter()
+        .map(|((group, label), sums)| Trend {
+            group,
+            label,
+            recent: ratio(sums.recent),
+            previous: ratio(sums.previous),
+            samples: sums.samples,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn sample(days_ago: i64, metric: Metric) -> MetricSample {
+        MetricSample { time: Local::now() - TimeDelta::days(days_ago), metric }
+    }
+
+    #[test]
+    fn test_record_and_read() {
+        let dir = tempdir().unwrap();
+        let store = MetricsStore::new(dir.path().join("metrics.log"));
+
+        store.record(Metric::Cache { cache: "search".into(), hit: true }).unwrap();
+        store.record(Metric::Operation { operation: "sync".into(), secs: 12.5 }).unwrap();
+
+        let samples = store.read_all().unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[1].metric, Metric::Operation { operation: "sync".into(), secs: 12.5 });
+    }
+
+    #[test]
+    fn test_slow_source_trend() {
+        let samples = vec![
+            sample(20, Metric::Download { source: "bandcamp".into(), bytes: 1000, secs: 1.0 }),
+            sample(10, Metric::Download { source: "bandcamp".into(), bytes: 3000, secs: 1.0 }),
+            sample(1, Metric::Download { source: "bandcamp".into(), bytes: 1000, secs: 1.0 }),
+            sample(1, Metric::Cache { cache: "search".into(), hit: true }),
+            sample(1, Metric::Cache { cache: "search".into(), hit: false }),
+        ];
+
+        let trends = trends(&samples, Local::now() - TimeDelta::days(7));
+        assert_eq!(trends.len(), 2);
+
+        let cache = &trends[0];
+        assert_eq!((cache.group, cache.recent, cache.previous), ("hit rate", Some(0.5), None));
+
+        let source = &trends[1];
+        assert_eq!(source.label, "bandcamp");
+        assert_eq!(source.previous, Some(2000.0));
+        assert_eq!(source.change(), Some(-0.5));
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::Result;
+
+
+/// File an album's notes are mirrored to, next to its tracks
+pub const NOTES_FILE: &str = "NOTES.md";
+
+/// What a note is attached to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoteSubject {
+    /// An album, by its directory
+    Album(PathBuf),
+    /// An artist, by name; case doesn't matter
+    Artist(String),
+}
+
+impl NoteSubject {
+    fn key(&self) -> String {
+        match self {
+            NoteSubject::Album(dir) => format!("album:{}", dir.display()),
+            NoteSubject::Artist(name) => format!("artist:{}", name.to_lowercase()),
+        }
+    }
+}
+
+impl fmt::Display for NoteSubject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteSubject::Album(dir) => write!(f, "{}", dir.display()),
+            NoteSubject::Artist(name) => write!(f, "artist {}", name),
+        }
+    }
+}
+
+/// Free-form remarks on an album or artist, e.g. "vinyl rip, pops at
+/// 3:12 on track 4"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Note {
+    pub text: String,
+    pub updated: DateTime<Local>,
+}
+
+/// Notes on albums and artists, kept in one JSON file in the data directory
+#[derive(Debug, Clone)]
+pub struct NoteStore {
+    path: PathBuf,
+}
+
+impl NoteStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        NoteStore { path: path.as_ref().to_path_buf() }
+    }
+
+    fn load(&self) -> Result<BTreeMap<String, Note>> {
+        match fs::read(&self.path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, notes: &BTreeMap<String, Note>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_vec_pretty(notes)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, subject: &NoteSubject) -> Result<Option<Note>> {
+        Ok(self.load()?.remove(&subject.key()))
+    }
+
+    /// Replace the note on `subject`; blank text removes it
+    ///
+    /// # Returns
+    /// The stored note, `None` if it was removed
+    pub fn set(&self, subject: &NoteSubject, text: &str) -> Result<Option<Note>> {
+        let mut notes = self.load()?;
+        let text = text.trim();
+
+        let note = if text.is_empty() {
+            notes.remove(&subject.key());
+            None
+        } else {
+            let note = Note { text: text.to_owned(), updated: Local::now() };
+            notes.insert(subject.key(), note.clone());
+            Some(note)
+        };
+
+        self.save(&notes)?;
+        Ok(note)
+    }
+
+    /// Carry the note on the album in `from` over to `to`
+    pub fn relocate(&self, from: &Path, to: &Path) -> Result<bool> {
+        let mut notes = self.load()?;
+        let Some(note) = notes.remove(&NoteSubject::Album(from.to_path_buf()).key()) else {
+            return Ok(false);
+        };
+
+        notes.insert(NoteSubject::Album(to.to_path_buf()).key(), note);
+        self.save(&notes)?;
+        Ok(true)
+    }
+}
+
+/// Write `note` to `NOTES.md` in `dir`, or remove the file when there is
+/// no note any more
+pub fn mirror_note(dir: &Path, title: &str, note: Option<&Note>) -> Result<PathBuf> {
+    let path = dir.join(NOTES_FILE);
+    match note {
+        Some(note) => fs::write(&path, format!("# {}\n\n{}\n", title, note.text))?,
+        None if path.exists() => fs::remove_file(&path)?,
+        None => {}
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_set_relocate_and_mirror() {
+        let dir = tempdir().unwrap();
+        let store = NoteStore::new(dir.path().join("notes.json"));
+        let album = NoteSubject::Album(PathBuf::from("/hdd/Low/Trust"));
+
+        store.set(&album, "  vinyl rip, pops at 3:12 on track 4\n").unwrap();
+        store.set(&NoteSubject::Artist("Low".to_owned()), "Duluth, MN").unwrap();
+        assert_eq!(store.get(&NoteSubject::Artist("LOW".to_owned())).unwrap().unwrap().text, "Duluth, MN");
+
+        assert!(store.relocate(Path::new("/hdd/Low/Trust"), Path::new("/ssd/Low/Trust")).unwrap());
+        assert_eq!(store.get(&album).unwrap(), None);
+        let moved = store.get(&NoteSubject::Album(PathBuf::from("/ssd/Low/Trust"))).unwrap().unwrap();
+        assert_eq!(moved.text, "vinyl rip, pops at 3:12 on track 4");
+
+        let mirrored = mirror_note(dir.path(), "Low - Trust", Some(&moved)).unwrap();
+        assert_eq!(fs::read_to_string(&mirrored).unwrap(), "# Low - Trust\n\nvinyl rip, pops at 3:12 on track 4\n");
+        assert!(store.set(&album, " ").unwrap().is_none());
+        mirror_note(dir.path(), "Low - Trust", None).unwrap();
+        assert!(!mirrored.exists());
+    }
+}
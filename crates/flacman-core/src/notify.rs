@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::{CoreError, Result};
+
+
+/// What a finished operation reports to the user
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    /// `sync`, `update`, `scrub`, `subscriptions`, ...
+    pub operation: String,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// One line per failure (or anything else worth mentioning)
+    pub details: Vec<String>,
+    #[serde(skip)]
+    pub elapsed: Duration,
+}
+
+impl Summary {
+    pub fn new(operation: &str) -> Self {
+        Summary { operation: operation.to_owned(), ..Default::default() }
+    }
+
+    /// One-line heading, e.g. "flacman sync: 12 done, 1 failed"
+    pub fn title(&self) -> String {
+        if self.failed == 0 {
+            format!("flacman {}: {} done", self.operation, self.succeeded)
+        } else {
+            format!("flacman {}: {} done, {} failed", self.operation, self.succeeded, self.failed)
+        }
+    }
+
+    pub fn body(&self) -> String {
+        let mut body = format!("Finished in {}s", self.elapsed.as_secs());
+        for line in &self.details {
+            body.push('\n');
+            body.push_str(line);
+        }
+        body
+    }
+}
+
+/// How to notify for one operation type
+///
+/// Deserializable so it can live as a `[notify]` table in flacman.conf,
+/// with per-operation overrides under `[notify.operations.<name>]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct NotifySettings {
+    /// Pop up a desktop notification (notify-send / osascript)
+    pub desktop: bool,
+    /// Mail the summary to this address through the local `sendmail`
+    pub email: Option<String>,
+    /// POST the summary as JSON to this URL
+    pub webhook: Option<String>,
+    /// Stay quiet for operations that finished faster than this
+    pub min_duration_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    #[serde(flatten)]
+    pub default: NotifySettings,
+    pub operations: HashMap<String, NotifySettings>,
+}
+
+impl NotifyConfig {
+    /// Settings for `operation`, falling back to the defaults
+    pub fn settings(&self, operation: &str) -> &NotifySettings {
+        self.operations.get(operation).unwrap_or(&self.default)
+    }
+
+    /// Send `summary` through every channel enabled for its operation
+    ///
+    /// Every channel is tried even if an earlier one fails.
+    ///
+    /// # Returns
+    /// The errors of the channels that failed
+    pub fn notify(&self, summary: &Summary) -> Vec<CoreError> {
+        let settings = self.settings(&summary.operation);
+        let mut errors = Vec::new();
+
+        if summary.elapsed < Duration::from_secs(settings.min_duration_secs) {
+            return errors;
+        }
+
+        if settings.desktop
+            && let Err(e) = notify_desktop(summary)
+        {
+            errors.push(e);
+        }
+
+        if let Some(to) = &settings.email
+            && let Err(e) = notify_email(to, summary)
+        {
+            errors.push(e);
+        }
+
+        if let Some(url) = &settings.webhook
+            && let Err(e) = notify_webhook(url, summary)
+        {
+            errors.push(e);
+        }
+
+        errors
+    }
+}
+
+fn run(command: &mut Command, what: &str) -> Result<()> {
+    let status = command.status().map_err(|e| CoreError::Notify(format!("cannot run {what}: {e}")))?;
+    if !status.success() {
+        return Err(CoreError::Notify(format!("{what} exited with {status}")));
+    }
+    Ok(())
+}
+
+/// Show a desktop notification
+pub fn notify_desktop(summary: &Summary) -> Result<()> {
+    if cfg!(target_os = "macos") {
+        let script = format!(
+            "display notification {:?} with title {:?}",
+            summary.body(),
+            summary.title()
+        );
+        run(Command::new("osascript").args(["-e", &script]), "osascript")
+    } else {
+        let urgency = if summary.failed > 0 { "critical" } else { "normal" };
+        run(
+            Command::new("notify-send")
+                .args(["--app-name=flacman", "--urgency", urgency])
+                .arg(summary.title())
+                .arg(summary.body()),
+            "notify-send",
+        )
+    }
+}
+
+/// Mail the summary via `sendmail -t`, which most MTAs (and msmtp) provide
+pub fn notify_email(to: &str, summary: &Summary) -> Result<()> {
+    let mut child = Command::new("sendmail")
+        .arg("-t")
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| CoreError::Notify(format!("cannot run sendmail: {e}")))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        write!(stdin, "To: {}\nSubject: {}\n\n{}\n", to, summary.title(), summary.body())?;
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(CoreError::Notify(format!("sendmail exited with {status}")));
+    }
+
+    Ok(())
+}
+
+/// POST the summary as JSON
+pub fn notify_webhook(url: &str, summary: &Summary) -> Result<()> {
+    let payload = serde_json::json!({
+        "title": summary.title(),
+        "body": summary.body(),
+        "summary": summary,
+    });
+
+    ureq::post(url)
+        .send_json(&payload)
+        .map_err(|e| CoreError::Notify(format!("webhook {url}: {e}")))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_text() {
+        let mut summary = Summary::new("sync");
+        summary.succeeded = 12;
+        assert_eq!(summary.title(), "flacman sync: 12 done");
+
+        summary.failed = 1;
+        summary.details.push("Low - Words: source timed out".into());
+        summary.elapsed = Duration::from_secs(90);
+        assert_eq!(summary.title(), "flacman sync: 12 done, 1 failed");
+        assert_eq!(summary.body(), "Finished in 90s\nLow - Words: source timed out");
+    }
+
+    #[test]
+    fn test_per_operation_settings() {
+        let config: NotifyConfig = serde_json::from_str(
+            r#"{"desktop": true, "operations": {"scrub": {"email": "me@example.com"}}}"#,
+        )
+        .unwrap();
+
+        assert!(config.settings("sync").desktop);
+        assert!(!config.settings("scrub").desktop);
+        assert_eq!(config.settings("scrub").email.as_deref(), Some("me@example.com"));
+    }
+
+    #[test]
+    fn test_quick_operations_stay_quiet() {
+        let config = NotifyConfig {
+            default: NotifySettings {
+                webhook: Some("http://127.0.0.1:9/unreachable".into()),
+                min_duration_secs: 60,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let summary = Summary { elapsed: Duration::from_secs(5), ..Summary::new("sync") };
+        assert!(config.notify(&summary).is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+use std::env;
+
+#[cfg(unix)]
+use crate::coreerror::CoreError;
+use crate::coreerror::Result;
+
+
+/// The pager to use, or `None` if paging is disabled
+///
+/// `FLACMAN_PAGER` wins over `PAGER`; `less` is the fallback. An empty
+/// value or `cat` disables paging, as with git.
+pub fn pager_command() -> Option<String> {
+    choose_pager(env::var("FLACMAN_PAGER").ok(), env::var("PAGER").ok())
+}
+
+fn choose_pager(flacman_pager: Option<String>, pager: Option<String>) -> Option<String> {
+    let command = flacman_pager.or(pager).unwrap_or_else(|| "less".to_owned());
+    let command = command.trim();
+
+    match command {
+        "" | "cat" => None,
+        _ => Some(command.to_owned()),
+    }
+}
+
+#[cfg(unix)]
+static PAGER: std::sync::Mutex<Option<std::process::Child>> = std::sync::Mutex::new(None);
+
+/// Send everything this process writes to stdout from now on through
+/// `command`
+///
+/// `less` gets `LESS=FRX` unless the user set `LESS`, so output that fits
+/// on one screen is printed as-is. On exit flacman waits for the pager to
+/// be closed, so the prompt doesn't come back underneath it.
+///
+/// # Errors
+/// * `CoreError::Pager` - The pager couldn't be started
+#[cfg(unix)]
+pub fn start_pager(command: &str) -> Result<()> {
+    use std::io::Write;
+    use std::os::fd::AsRawFd;
+    use std::process::{Command, Stdio};
+
+    let mut pager = Command::new("sh");
+    pager.args(["-c", command]).stdin(Stdio::piped());
+    if env::var_os("LESS").is_none() {
+        pager.env("LESS", "FRX");
+    }
+    if env::var_os("LV").is_none() {
+        pager.env("LV", "-c");
+    }
+
+    let mut child = pager.spawn().map_err(|e| CoreError::Pager(format!("cannot run {command}: {e}")))?;
+    let stdin = child.stdin.take().expect("pager stdin is piped");
+
+    std::io::stdout().flush()?;
+    if unsafe { libc::dup2(stdin.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+        let error = std::io::Error::last_os_error();
+        let _ = child.kill();
+        return Err(CoreError::Pager(format!("cannot redirect output to {command}: {error}")));
+    }
+    drop(stdin);
+
+    *PAGER.lock().expect("pager lock poisoned") = Some(child);
+
+    // Quitting the pager early should end flacman quietly rather than
+    // with "failed printing to stdout"
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+        libc::atexit(wait_for_pager);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+extern "C" fn wait_for_pager() {
+    use std::io::Write;
+
+    let _ = std::io::stdout().flush();
+    unsafe { libc::close(libc::STDOUT_FILENO) };
+
+    if let Ok(mut pager) = PAGER.lock()
+        && let Some(mut child) = pager.take()
+    {
+        let _ = child.wait();
+    }
+}
+
+/// Paging is not supported here; output goes straight to the console
+#[cfg(not(unix))]
+pub fn start_pager(_command: &str) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_pager() {
+        assert_eq!(choose_pager(None, None).as_deref(), Some("less"));
+        assert_eq!(choose_pager(None, Some("more".into())).as_deref(), Some("more"));
+        assert_eq!(choose_pager(Some("most".into()), Some("more".into())).as_deref(), Some("most"));
+        assert_eq!(choose_pager(Some("".into()), Some("more".into())), None);
+        assert_eq!(choose_pager(None, Some("cat".into())), None);
+    }
+}
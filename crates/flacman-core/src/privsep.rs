@@ -0,0 +1,390 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, ExitCode, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::{CoreError, Result};
+
+
+/// Set in the environment of the flacman a [`DownloadUser`] re-executes to
+/// make one request, so it serves that instead of parsing arguments
+const CHILD_ENV: &str = "FLACMAN_DOWNLOAD_CHILD";
+
+/// Unprivileged account that network downloads run as when flacman runs
+/// as root, like pacman's `DownloadUser`
+///
+/// Downloads run in a separate process started as this user: an external
+/// downloader such as yt-dlp, or flacman itself re-executed to make one
+/// HTTP request. The parent keeps its privileges to place the result into
+/// the library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadUser {
+    name: String,
+    uid: u32,
+    gid: u32,
+}
+
+impl DownloadUser {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Look up a user in the password database
+    ///
+    /// # Errors
+    /// * `CoreError::Privilege` - No such user, or not supported on this platform
+    #[cfg(unix)]
+    pub fn lookup(name: &str) -> Result<Self> {
+        let c_name = std::ffi::CString::new(name)
+            .map_err(|_| CoreError::Privilege(format!("invalid user name: {name:?}")))?;
+
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut buf = vec![0 as libc::c_char; 16 * 1024];
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+
+        let rc = unsafe { libc::getpwnam_r(c_name.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+
+        if rc != 0 || result.is_null() {
+            return Err(CoreError::Privilege(format!("no such user: {name}")));
+        }
+
+        Ok(DownloadUser { name: name.to_owned(), uid: pwd.pw_uid, gid: pwd.pw_gid })
+    }
+
+    #[cfg(not(unix))]
+    pub fn lookup(_name: &str) -> Result<Self> {
+        Err(CoreError::Privilege("dropping privileges is not supported on this platform".to_owned()))
+    }
+
+    /// A command running `program` as this user
+    ///
+    /// When not running as root there is nothing to drop, and it runs as
+    /// flacman does. Otherwise the child switches to the user's uid and
+    /// group, and loses its supplementary groups, before `program` starts.
+    pub fn command<S: AsRef<OsStr>>(&self, program: S) -> Command {
+        let mut command = Command::new(program);
+        #[cfg(unix)]
+        if is_root() {
+            use std::os::unix::process::CommandExt;
+            command.uid(self.uid).gid(self.gid);
+        }
+        command
+    }
+
+    /// A new directory in `parent` that a download running as this user
+    /// can write to, for files the parent moves into place afterwards
+    ///
+    /// # Errors
+    /// * `CoreError::Io` - The directory couldn't be created or handed over
+    pub fn scratch_dir(&self, parent: &Path) -> Result<PathBuf> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let dir = parent.join(format!(".flacman-download-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&dir)?;
+        #[cfg(unix)]
+        if is_root() {
+            std::os::unix::fs::chown(&dir, Some(self.uid), Some(self.gid))?;
+        }
+        Ok(dir)
+    }
+
+    /// Make `request` from flacman re-executed as this user
+    fn send(&self, request: &HttpRequest) -> std::result::Result<HttpResponse, ureq::Error> {
+        let failed = |what: &str| ureq::Error::Io(io::Error::other(format!("download process for user {}: {what}", self.name)));
+
+        let exe = std::env::current_exe()?;
+        let mut child = self
+            .command(exe)
+            .env(CHILD_ENV, "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+
+        let sent = serde_json::to_writer(child.stdin.take().expect("stdin is piped"), request);
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let mut head = String::new();
+        if sent.is_err() || stdout.read_line(&mut head)? == 0 {
+            let _ = child.wait();
+            return Err(failed("no response"));
+        }
+
+        match serde_json::from_str(&head) {
+            Ok(Head::Response { status, headers }) => {
+                Ok(HttpResponse { status, headers, body: Box::new(ChildBody { child, stdout, user: self.name.clone() }) })
+            }
+            Ok(Head::Error(message)) => {
+                let _ = child.wait();
+                Err(failed(&message))
+            }
+            Err(_) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(failed("garbled response"))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+/// An HTTP request a download makes
+///
+/// Sent with a [`DownloadUser`], it is made by a child process running as
+/// that user; the response streams back over a pipe.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpRequest {
+    method: String,
+    url: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+}
+
+impl HttpRequest {
+    pub fn get(url: &str) -> Self {
+        HttpRequest { method: "GET".to_owned(), url: url.to_owned(), query: Vec::new(), headers: Vec::new() }
+    }
+
+    /// A `PUT` with an empty body
+    pub fn put(url: &str) -> Self {
+        HttpRequest { method: "PUT".to_owned(), ..HttpRequest::get(url) }
+    }
+
+    /// Add the query parameter `name`, percent-encoded
+    pub fn query(mut self, name: &str, value: &str) -> Self {
+        self.query.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Send the request, from a process running as `user` if one is given
+    ///
+    /// # Errors
+    /// As ureq's: `ureq::Error::StatusCode` for a status of 400 or more,
+    /// `ureq::Error::Io` if the download process failed
+    pub fn send(&self, user: Option<&DownloadUser>) -> std::result::Result<HttpResponse, ureq::Error> {
+        let response = match user {
+            Some(user) if is_root() => user.send(self)?,
+            _ => self.send_here()?,
+        };
+        match response.status {
+            status if status >= 400 => Err(ureq::Error::StatusCode(status)),
+            _ => Ok(response),
+        }
+    }
+
+    /// Send the request from this process, whatever its status
+    fn send_here(&self) -> std::result::Result<HttpResponse, ureq::Error> {
+        fn prepare<B>(mut builder: ureq::RequestBuilder<B>, request: &HttpRequest) -> ureq::RequestBuilder<B> {
+            for (name, value) in &request.query {
+                builder = builder.query(name, value);
+            }
+            for (name, value) in &request.headers {
+                builder = builder.header(name, value);
+            }
+            builder.config().http_status_as_error(false).build()
+        }
+
+        let response = match self.method.as_str() {
+            "PUT" => prepare(ureq::put(&self.url), self).send_empty()?,
+            _ => prepare(ureq::get(&self.url), self).call()?,
+        };
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str().to_owned(), value.to_str().ok()?.to_owned())))
+            .collect();
+
+        Ok(HttpResponse {
+            status: response.status().as_u16(),
+            headers,
+            body: Box::new(response.into_body().into_reader()),
+        })
+    }
+}
+
+/// The answer to an [`HttpRequest`]; the body is read as it arrives
+pub struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Box<dyn Read + Send>,
+}
+
+impl HttpResponse {
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Value of the header `name`, matched case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.header("content-length")?.parse().ok()
+    }
+
+    pub fn into_reader(self) -> Box<dyn Read + Send> {
+        self.body
+    }
+
+    pub fn read_to_vec(mut self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.body.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// The body, failing if it is longer than `limit` bytes
+    pub fn read_to_vec_limited(self, limit: u64) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.body.take(limit + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > limit {
+            return Err(io::Error::other(format!("body is larger than {limit} bytes")));
+        }
+        Ok(data)
+    }
+
+    pub fn read_to_string(self) -> io::Result<String> {
+        String::from_utf8(self.read_to_vec()?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// First line a download process writes, before the body
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Head {
+    Response { status: u16, headers: Vec<(String, String)> },
+    Error(String),
+}
+
+/// Body of a response a download process streams back; it ends with an
+/// error if the process failed before sending all of it
+struct ChildBody {
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+    user: String,
+}
+
+impl Read for ChildBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() && !self.child.wait()?.success() {
+            return Err(io::Error::other(format!("download process for user {} failed", self.user)));
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for ChildBody {
+    fn drop(&mut self) {
+        // A body that isn't read to the end isn't wanted
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// Serve the request of a parent flacman, if this process is the download
+/// process a [`DownloadUser`] started
+///
+/// Call first thing in `main`; `None` means flacman should run as usual.
+/// The request is read from stdin, and the response written to stdout as
+/// a JSON line with its status and headers followed by the body.
+pub fn serve_download_child() -> Option<ExitCode> {
+    std::env::var_os(CHILD_ENV)?;
+
+    let mut stdout = io::stdout().lock();
+    let head = |stdout: &mut io::StdoutLock, head: &Head| {
+        serde_json::to_writer(&mut *stdout, head).is_ok() && stdout.write_all(b"\n").is_ok()
+    };
+    let fail = |stdout: &mut io::StdoutLock, message: String| {
+        head(stdout, &Head::Error(message));
+        Some(ExitCode::FAILURE)
+    };
+
+    // Never download with the privileges the parent was meant to keep
+    if is_root() {
+        return fail(&mut stdout, "still running as root".to_owned());
+    }
+    let request: HttpRequest = match serde_json::from_reader(io::stdin().lock()) {
+        Ok(request) => request,
+        Err(e) => return fail(&mut stdout, format!("bad request: {e}")),
+    };
+    let response = match request.send_here() {
+        Ok(response) => response,
+        Err(e) => return fail(&mut stdout, e.to_string()),
+    };
+
+    let sent = head(&mut stdout, &Head::Response { status: response.status, headers: response.headers.clone() })
+        && io::copy(&mut response.into_reader(), &mut stdout).is_ok()
+        && stdout.flush().is_ok();
+    Some(if sent { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let root = DownloadUser::lookup("root").unwrap();
+        assert_eq!((root.uid, root.gid), (0, 0));
+        assert!(DownloadUser::lookup("no-such-user-flacman").is_err());
+    }
+
+    #[test]
+    fn test_command_runs_as_user() {
+        let user = DownloadUser::lookup("nobody").unwrap();
+        let output = user.command("id").arg("-u").output().unwrap();
+        let uid = String::from_utf8(output.stdout).unwrap();
+
+        // As root the command switched to nobody; otherwise it ran as is
+        if is_root() {
+            assert_eq!(uid.trim(), user.uid.to_string());
+        } else {
+            assert_eq!(uid.trim(), unsafe { libc::getuid() }.to_string());
+        }
+    }
+
+    #[test]
+    fn test_child_body_reports_failure() {
+        let mut child = Command::new("sh").args(["-c", "printf partial; exit 3"]).stdout(Stdio::piped()).spawn().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let body = HttpResponse {
+            status: 200,
+            headers: vec![("Content-Length".to_owned(), "100".to_owned())],
+            body: Box::new(ChildBody { child, stdout, user: "nobody".to_owned() }),
+        };
+
+        assert_eq!(body.content_length(), Some(100));
+        assert!(body.read_to_vec().is_err());
+    }
+
+    #[test]
+    fn test_head_round_trip() {
+        let head = Head::Response { status: 304, headers: vec![("ETag".to_owned(), "\"v1\"".to_owned())] };
+        let line = serde_json::to_string(&head).unwrap();
+        assert!(matches!(serde_json::from_str(&line).unwrap(), Head::Response { status: 304, .. }));
+        assert!(matches!(serde_json::from_str(r#"{"error":"refused"}"#).unwrap(), Head::Error(m) if m == "refused"));
+    }
+}
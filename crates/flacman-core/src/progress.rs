@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+
+/// Receives the progress of a long operation made of items: files to
+/// validate, tracks to download, albums to import
+///
+/// Items are told apart by an id of the caller's choosing and may run at
+/// the same time. Implementations draw the progress or ignore it.
+pub trait Progress: Send + Sync {
+    /// Item `id` called `label` starts; `len` is its size in bytes, when
+    /// known
+    fn start(&self, id: usize, label: &str, len: Option<u64>);
+
+    /// `delta` more bytes of item `id` are done
+    fn advance(&self, id: usize, delta: u64);
+
+    /// Item `id` is over, done or failed; an item that never started
+    /// counts all the same
+    fn finish(&self, id: usize);
+
+    /// Run `f`, which writes to the terminal or asks something, with the
+    /// progress out of the way
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        f();
+    }
+
+    /// Print `line` on stdout without garbling what is drawn
+    fn println(&self, line: &str) {
+        self.suspend(&mut || println!("{}", line));
+    }
+
+    /// Print `message` on stderr without garbling what is drawn
+    fn message(&self, message: &str) {
+        self.suspend(&mut || eprintln!("{}", message));
+    }
+}
+
+/// Progress that isn't shown, for `--quiet` and output that isn't a
+/// terminal
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn start(&self, _id: usize, _label: &str, _len: Option<u64>) {}
+
+    fn advance(&self, _id: usize, _delta: u64) {}
+
+    fn finish(&self, _id: usize) {}
+}
+
+/// Turns running totals, as downloaders report them, into the deltas
+/// [`Progress::advance`] takes
+///
+/// The first total seen for an item starts it.
+#[derive(Debug, Default)]
+pub struct ProgressTotals {
+    done: Mutex<HashMap<usize, u64>>,
+}
+
+impl ProgressTotals {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Item `id` has `done` bytes of `len` so far
+    pub fn update(&self, progress: &dyn Progress, id: usize, label: &str, done: u64, len: Option<u64>) {
+        let mut totals = self.done.lock().expect("progress totals lock poisoned");
+        let last = match totals.get(&id) {
+            Some(last) => *last,
+            None => {
+                progress.start(id, label, len);
+                0
+            }
+        };
+        totals.insert(id, done);
+        if done > last {
+            progress.advance(id, done - last);
+        }
+    }
+
+    /// Item `id` is over
+    pub fn finish(&self, progress: &dyn Progress, id: usize) {
+        self.done.lock().expect("progress totals lock poisoned").remove(&id);
+        progress.finish(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl Progress for Recorder {
+        fn start(&self, id: usize, label: &str, len: Option<u64>) {
+            self.events.lock().unwrap().push(format!("start {id} {label} {len:?}"));
+        }
+
+        fn advance(&self, id: usize, delta: u64) {
+            self.events.lock().unwrap().push(format!("advance {id} {delta}"));
+        }
+
+        fn finish(&self, id: usize) {
+            self.events.lock().unwrap().push(format!("finish {id}"));
+        }
+    }
+
+    #[test]
+    fn test_progress_totals() {
+        let recorder = Recorder::default();
+        let totals = ProgressTotals::new();
+        totals.update(&recorder, 3, "01.flac", 100, Some(300));
+        totals.update(&recorder, 3, "01.flac", 100, Some(300));
+        totals.update(&recorder, 3, "01.flac", 300, Some(300));
+        totals.finish(&recorder, 3);
+        // A restarted item starts over
+        totals.update(&recorder, 3, "01.flac", 50, None);
+
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            [
+                "start 3 01.flac Some(300)",
+                "advance 3 100",
+                "advance 3 200",
+                "finish 3",
+                "start 3 01.flac None",
+                "advance 3 50",
+            ]
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::Result;
+
+
+/// Sidecar a downloader leaves next to the files it fetched, saying where
+/// they came from
+pub const SOURCE_SIDECAR: &str = "flacman-source.json";
+
+/// Where a batch of files was fetched from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceInfo {
+    /// Backend, e.g. `soulseek`, `bandcamp`, `yt-dlp`; `inbox` when unknown
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloaded: Option<DateTime<Local>>,
+    /// Encoders the audio passed through before it was published, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transcodes: Vec<String>,
+}
+
+impl SourceInfo {
+    /// The sidecar in `dir`, if a downloader left one
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        match fs::read(dir.join(SOURCE_SIDECAR)) {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        fs::write(dir.join(SOURCE_SIDECAR), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Where one library file came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Library path of the file
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub source: SourceInfo,
+    /// Where the file was before it was imported, under its original name
+    pub original: PathBuf,
+    pub imported: DateTime<Local>,
+    /// SHA-256 of the file as it was imported, before any retagging
+    pub sha256: String,
+}
+
+impl Provenance {
+    /// A record of `path` being imported now
+    pub fn new(path: PathBuf, source: SourceInfo, original: PathBuf, sha256: String) -> Self {
+        Provenance { path, source, original, imported: Local::now(), sha256 }
+    }
+}
+
+/// Provenance of every imported file
+///
+/// Append-only, one JSON object per line; the newest record for a path
+/// wins, so moving files only needs new records.
+#[derive(Debug, Clone)]
+pub struct ProvenanceStore {
+    path: PathBuf,
+}
+
+impl ProvenanceStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        ProvenanceStore { path: path.as_ref().to_path_buf() }
+    }
+
+    pub fn record(&self, provenance: &[Provenance]) -> Result<()> {
+        if provenance.is_empty() {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        for record in provenance {
+            writeln!(file, "{}", serde_json::to_string(record)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// The current record of every file, by library path
+    ///
+    /// Lines that don't parse are skipped.
+    pub fn all(&self) -> Result<HashMap<PathBuf, Provenance>> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+
+        let mut records = HashMap::new();
+        for line in BufReader::new(fs::File::open(&self.path)?).lines() {
+            if let Ok(record) = serde_json::from_str::<Provenance>(&line?) {
+                records.insert(record.path.clone(), record);
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Carry the records of files below `from` over to the same files below `to`
+    ///
+    /// # Returns
+    /// Number of records moved
+    pub fn relocate(&self, from: &Path, to: &Path) -> Result<usize> {
+        let moved: Vec<Provenance> = self
+            .all()?
+            .into_values()
+            .filter_map(|mut record| {
+                let relative = record.path.strip_prefix(from).ok()?.to_path_buf();
+                record.path = to.join(relative);
+                Some(record)
+            })
+            .collect();
+
+        self.record(&moved)?;
+        Ok(moved.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_record_and_relocate() {
+        let dir = tempdir().unwrap();
+        let store = ProvenanceStore::new(dir.path().join("provenance.log"));
+        let record = Provenance {
+            path: PathBuf::from("/hdd/Low/Trust/01 Canada.flac"),
+            source: SourceInfo {
+                source: "bandcamp".to_owned(),
+                url: Some("https://low.bandcamp.com/album/trust".to_owned()),
+                ..Default::default()
+            },
+            original: PathBuf::from("/inbox/low-trust/01 - canada.flac"),
+            imported: Local::now(),
+            sha256: "ab".repeat(32),
+        };
+        store.record(std::slice::from_ref(&record)).unwrap();
+
+        assert_eq!(store.relocate(Path::new("/hdd/Low/Trust"), Path::new("/ssd/Low/Trust")).unwrap(), 1);
+        let all = store.all().unwrap();
+        let moved = &all[Path::new("/ssd/Low/Trust/01 Canada.flac")];
+        assert_eq!(moved.source, record.source);
+        assert_eq!(moved.original, record.original);
+    }
+}
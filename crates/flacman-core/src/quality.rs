@@ -0,0 +1,234 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::coreerror::{CoreError, Result};
+
+
+/// How a release (or one of its tracks) is encoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encoding {
+    /// Lowercase format name, e.g. `flac`, `opus`
+    pub format: String,
+    pub lossless: bool,
+    /// Average bitrate in kbps, if known
+    pub bitrate: Option<u32>,
+}
+
+impl Encoding {
+    pub fn new(format: &str, lossless: bool, bitrate: Option<u32>) -> Self {
+        Encoding { format: format.to_lowercase(), lossless, bitrate }
+    }
+}
+
+/// One acceptable kind of encoding: `lossless`, `<format>` or `<format>>=<kbps>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QualityRung {
+    /// Any lossless format
+    Lossless,
+    /// A specific format, optionally with a minimum bitrate
+    Format { format: String, min_bitrate: Option<u32> },
+}
+
+impl QualityRung {
+    pub fn accepts(&self, encoding: &Encoding) -> bool {
+        match self {
+            QualityRung::Lossless => encoding.lossless,
+            QualityRung::Format { format, min_bitrate } => {
+                *format == encoding.format
+                    && min_bitrate.is_none_or(|min| encoding.lossless || encoding.bitrate.is_some_and(|b| b >= min))
+            }
+        }
+    }
+}
+
+impl FromStr for QualityRung {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+        if s == "lossless" {
+            return Ok(QualityRung::Lossless);
+        }
+
+        let (format, min_bitrate) = match s.split_once(">=") {
+            Some((format, kbps)) => {
+                let kbps = kbps.trim().trim_end_matches("kbps").trim_end_matches('k');
+                let kbps = kbps.parse().map_err(|_| CoreError::Quality(format!("bad bitrate in {s:?}")))?;
+                (format.trim().to_owned(), Some(kbps))
+            }
+            None => (s.clone(), None),
+        };
+
+        if format.is_empty() || !format.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(CoreError::Quality(format!("expected 'lossless', '<format>' or '<format>>=<kbps>', got {s:?}")));
+        }
+
+        Ok(QualityRung::Format { format, min_bitrate })
+    }
+}
+
+impl fmt::Display for QualityRung {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QualityRung::Lossless => write!(f, "lossless"),
+            QualityRung::Format { format, min_bitrate: None } => write!(f, "{format}"),
+            QualityRung::Format { format, min_bitrate: Some(kbps) } => write!(f, "{format}>={kbps}"),
+        }
+    }
+}
+
+/// Acceptable encodings, most preferred first
+///
+/// Written as a comma-separated list on the command line
+/// (`--quality "lossless, opus>=128"`) and as a list of strings in
+/// flacman.conf.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub struct QualityLadder {
+    pub rungs: Vec<QualityRung>,
+}
+
+impl QualityLadder {
+    pub fn accepts(&self, encoding: &Encoding) -> bool {
+        self.rungs.iter().any(|rung| rung.accepts(encoding))
+    }
+
+    /// Where `encoding` stands on the ladder, lower being better: the
+    /// first rung that accepts it (past the last for none), then the
+    /// highest bitrate, unknown ones last
+    pub fn standing(&self, encoding: &Encoding) -> (usize, Reverse<Option<u32>>) {
+        let rung = self.rungs.iter().position(|rung| rung.accepts(encoding)).unwrap_or(self.rungs.len());
+        (rung, Reverse(encoding.bitrate))
+    }
+
+    /// Pick the offer to download (or the target to convert to)
+    ///
+    /// The highest rung that any offer satisfies wins; among the offers
+    /// on that rung the one with the highest bitrate is taken.
+    ///
+    /// # Returns
+    /// The index of the chosen offer, or `None` if no offer is acceptable
+    pub fn choose(&self, offers: &[Encoding]) -> Option<usize> {
+        self.rungs.iter().find_map(|rung| {
+            offers
+                .iter()
+                .enumerate()
+                .filter(|(_, offer)| rung.accepts(offer))
+                .max_by_key(|(i, offer)| (offer.bitrate.unwrap_or(0), std::cmp::Reverse(*i)))
+                .map(|(i, _)| i)
+        })
+    }
+}
+
+impl FromStr for QualityLadder {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let rungs = s.split(',').map(str::parse).collect::<Result<Vec<_>>>()?;
+        if rungs.is_empty() {
+            return Err(CoreError::Quality("no acceptable encodings".to_owned()));
+        }
+        Ok(QualityLadder { rungs })
+    }
+}
+
+impl TryFrom<Vec<String>> for QualityLadder {
+    type Error = CoreError;
+
+    fn try_from(rungs: Vec<String>) -> Result<Self> {
+        rungs.join(",").parse()
+    }
+}
+
+impl fmt::Display for QualityLadder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rungs: Vec<String> = self.rungs.iter().map(|r| r.to_string()).collect();
+        write!(f, "{}", rungs.join(", "))
+    }
+}
+
+/// Which encodings each library or device profile accepts
+///
+/// Deserializable so it can live as a `[quality]` table in flacman.conf:
+///
+/// ```toml
+/// [quality]
+/// default = ["lossless"]
+///
+/// [quality.profiles]
+/// portable = ["opus>=128", "mp3>=256"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct QualityPolicy {
+    /// Used when no profile is active, or the profile has no ladder
+    pub default: QualityLadder,
+    pub profiles: HashMap<String, QualityLadder>,
+}
+
+impl Default for QualityPolicy {
+    fn default() -> Self {
+        let ladder = |s: &str| s.parse::<QualityLadder>().expect("built-in ladder");
+
+        QualityPolicy {
+            default: ladder("lossless"),
+            profiles: HashMap::from([
+                ("archive".to_owned(), ladder("lossless")),
+                ("portable".to_owned(), ladder("opus>=128, aac>=192, mp3>=256")),
+                ("audiobooks".to_owned(), ladder("aac>=64, opus>=32, mp3>=64, lossless")),
+                ("podcasts".to_owned(), ladder("opus, aac, mp3, lossless")),
+            ]),
+        }
+    }
+}
+
+impl QualityPolicy {
+    /// The ladder of `profile`, falling back to the default
+    pub fn ladder(&self, profile: Option<&str>) -> &QualityLadder {
+        profile.and_then(|p| self.profiles.get(p)).unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ladder() {
+        let ladder: QualityLadder = "Lossless, opus >= 128kbps, mp3".parse().unwrap();
+        assert_eq!(ladder.to_string(), "lossless, opus>=128, mp3");
+
+        assert!("opus>=fast".parse::<QualityLadder>().is_err());
+        assert!("".parse::<QualityLadder>().is_err());
+    }
+
+    #[test]
+    fn test_choose_follows_ladder() {
+        let offers = [
+            Encoding::new("mp3", false, Some(320)),
+            Encoding::new("opus", false, Some(96)),
+            Encoding::new("opus", false, Some(160)),
+            Encoding::new("flac", true, Some(900)),
+        ];
+
+        let policy = QualityPolicy::default();
+        assert_eq!(policy.ladder(None).choose(&offers), Some(3));
+        assert_eq!(policy.ladder(Some("portable")).choose(&offers), Some(2));
+        assert_eq!(policy.ladder(Some("archive")).choose(&offers[..3]), None);
+        assert!(!policy.ladder(Some("portable")).accepts(&offers[1]));
+    }
+
+    #[test]
+    fn test_policy_from_config() {
+        let policy: QualityPolicy =
+            serde_json::from_str(r#"{"profiles": {"car": ["mp3>=192"]}}"#).unwrap();
+
+        assert_eq!(policy.ladder(Some("car")).to_string(), "mp3>=192");
+        assert_eq!(policy.ladder(Some("unknown")).to_string(), "lossless");
+        assert!(serde_json::from_str::<QualityPolicy>(r#"{"default": ["ogg>>1"]}"#).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Local, Months, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::{CoreError, Result};
+
+
+/// Share of a quota at which to start warning, unless configured
+const DEFAULT_WARN_AT: f64 = 0.8;
+
+/// Parse a byte size such as `500MB`, `10GiB` or `1.5T`
+///
+/// Units are binary (`G`, `GB` and `GiB` all mean 1024³), since that is
+/// what the rest of flacman prints.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: f64 = number.parse().map_err(|_| CoreError::Size(s.to_owned()))?;
+    let shift = match unit.trim().to_ascii_lowercase().trim_end_matches("ib").trim_end_matches('b') {
+        "" => 0,
+        "k" => 10,
+        "m" => 20,
+        "g" => 30,
+        "t" => 40,
+        _ => return Err(CoreError::Size(s.to_owned())),
+    };
+
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+/// The period a quota is counted over; both follow the local calendar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaWindow {
+    Day,
+    Month,
+}
+
+impl QuotaWindow {
+    /// When the window containing `now` started
+    pub fn start(self, now: DateTime<Local>) -> DateTime<Local> {
+        let date = match self {
+            QuotaWindow::Day => now.date_naive(),
+            QuotaWindow::Month => now.date_naive().with_day(1).expect("every month has a first day"),
+        };
+        local_midnight(date)
+    }
+
+    /// When the window containing `now` ends and the quota resets
+    pub fn reset(self, now: DateTime<Local>) -> DateTime<Local> {
+        let start = self.start(now).date_naive();
+        let next = match self {
+            QuotaWindow::Day => start.succ_opt().expect("date in range"),
+            QuotaWindow::Month => start.checked_add_months(Months::new(1)).expect("date in range"),
+        };
+        local_midnight(next)
+    }
+}
+
+fn local_midnight(date: chrono::NaiveDate) -> DateTime<Local> {
+    let midnight = date.and_time(NaiveTime::MIN);
+    // A DST jump at midnight makes it ambiguous or skipped; take the earliest valid instant
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .unwrap_or_else(|| Local.from_utc_datetime(&midnight))
+}
+
+impl FromStr for QuotaWindow {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "day" | "daily" => Ok(QuotaWindow::Day),
+            "month" | "monthly" => Ok(QuotaWindow::Month),
+            _ => Err(CoreError::Quota(format!("unknown window '{s}' (use day or month)"))),
+        }
+    }
+}
+
+impl fmt::Display for QuotaWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            QuotaWindow::Day => "day",
+            QuotaWindow::Month => "month",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    pub window: QuotaWindow,
+    /// Bytes allowed per window
+    pub limit: u64,
+}
+
+/// Download limits per source, e.g. a seedbox's monthly bandwidth or an
+/// API's daily allowance
+///
+/// Stored as JSON in the data directory and edited with `--set-quota`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuotaPolicy {
+    /// Quotas by source name; a source can have a daily and a monthly one
+    pub quotas: BTreeMap<String, Vec<Quota>>,
+    /// Warn once this share of a quota is used (0..1)
+    pub warn_at: f64,
+}
+
+impl Default for QuotaPolicy {
+    fn default() -> Self {
+        QuotaPolicy { quotas: BTreeMap::new(), warn_at: DEFAULT_WARN_AT }
+    }
+}
+
+impl QuotaPolicy {
+    /// Load the policy from `path`; a missing file means no quotas
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Set (or with a `limit` of 0, remove) the quota of `source` for `window`
+    pub fn set(&mut self, source: &str, window: QuotaWindow, limit: u64) {
+        let quotas = self.quotas.entry(source.to_owned()).or_default();
+        quotas.retain(|q| q.window != window);
+        if limit > 0 {
+            quotas.push(Quota { window, limit });
+            quotas.sort_by_key(|q| q.window);
+        } else if quotas.is_empty() {
+            self.quotas.remove(source);
+        }
+    }
+
+    /// Where `source` stands against each of its quotas at `now`
+    pub fn usage(&self, source: &str, ledger: &[DownloadUsage], now: DateTime<Local>) -> Vec<QuotaUsage> {
+        let Some(quotas) = self.quotas.get(source) else {
+            return Vec::new();
+        };
+
+        quotas
+            .iter()
+            .map(|quota| {
+                let start = quota.window.start(now);
+                let used = ledger
+                    .iter()
+                    .filter(|u| u.source == source && u.time >= start && u.time <= now)
+                    .map(|u| u.bytes)
+                    .sum();
+
+                QuotaUsage { quota: *quota, used, resets: quota.window.reset(now), warn_at: self.warn_at }
+            })
+            .collect()
+    }
+}
+
+/// How far into one quota a source is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuotaUsage {
+    pub quota: Quota,
+    pub used: u64,
+    pub resets: DateTime<Local>,
+    warn_at: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QuotaLevel {
+    Within,
+    /// Past the warning threshold
+    Approaching,
+    Exhausted,
+}
+
+impl QuotaUsage {
+    pub fn fraction(&self) -> f64 {
+        self.used as f64 / self.quota.limit as f64
+    }
+
+    pub fn level(&self) -> QuotaLevel {
+        if self.used >= self.quota.limit {
+            QuotaLevel::Exhausted
+        } else if self.fraction() >= self.warn_at {
+            QuotaLevel::Approaching
+        } else {
+            QuotaLevel::Within
+        }
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.quota.limit.saturating_sub(self.used)
+    }
+}
+
+/// Bytes downloaded from one source in one go
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadUsage {
+    pub time: DateTime<Local>,
+    pub source: String,
+    pub bytes: u64,
+}
+
+/// Append-only record of bytes downloaded per source, one JSON object per line
+#[derive(Debug, Clone)]
+pub struct QuotaLedger {
+    path: PathBuf,
+}
+
+impl QuotaLedger {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        QuotaLedger { path: path.as_ref().to_path_buf() }
+    }
+
+    pub fn record(&self, source: &str, bytes: u64) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let usage = DownloadUsage { time: Local::now(), source: source.to_owned(), bytes };
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&usage)?)?;
+
+        Ok(())
+    }
+
+    /// All entries, oldest first; unparseable lines are skipped
+    pub fn read_all(&self) -> Result<Vec<DownloadUsage>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let reader = BufReader::new(fs::File::open(&self.path)?);
+        let mut entries = Vec::new();
+
+        for line in reader.lines() {
+            if let Ok(entry) = serde_json::from_str(&line?) {
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("10GiB").unwrap(), 10 << 30);
+        assert_eq!(parse_size("1.5 GB").unwrap(), 3 << 29);
+        assert_eq!(parse_size("200m").unwrap(), 200 << 20);
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("5 parsecs").is_err());
+    }
+
+    #[test]
+    fn test_windows() {
+        let now = Local.with_ymd_and_hms(2024, 1, 31, 15, 30, 0).unwrap();
+
+        assert_eq!(QuotaWindow::Day.start(now), Local.with_ymd_and_hms(2024, 1, 31, 0, 0, 0).unwrap());
+        assert_eq!(QuotaWindow::Day.reset(now), Local.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap());
+        assert_eq!(QuotaWindow::Month.start(now), Local.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(QuotaWindow::Month.reset(now), Local.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_usage_levels() {
+        let now = Local.with_ymd_and_hms(2024, 3, 15, 12, 0, 0).unwrap();
+        let usage = |days_ago: i64, bytes: u64| DownloadUsage {
+            time: now - TimeDelta::days(days_ago),
+            source: "seedbox".into(),
+            bytes,
+        };
+        let ledger = vec![usage(40, 900), usage(10, 500), usage(0, 350)];
+
+        let mut policy = QuotaPolicy::default();
+        policy.set("seedbox", QuotaWindow::Month, 1000);
+        policy.set("seedbox", QuotaWindow::Day, 300);
+
+        let usage = policy.usage("seedbox", &ledger, now);
+        assert_eq!(usage.len(), 2);
+        assert_eq!(
+            (usage[0].quota.window, usage[0].used, usage[0].level()),
+            (QuotaWindow::Day, 350, QuotaLevel::Exhausted)
+        );
+        assert_eq!((usage[1].used, usage[1].level()), (850, QuotaLevel::Approaching));
+        assert_eq!(usage[1].remaining(), 150);
+
+        assert!(policy.usage("bandcamp", &ledger, now).is_empty());
+
+        policy.set("seedbox", QuotaWindow::Day, 0);
+        policy.set("seedbox", QuotaWindow::Month, 0);
+        assert!(policy.quotas.is_empty());
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Local, NaiveTime, TimeDelta, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::{CoreError, Result};
+
+
+/// A daily stretch of local time, such as `01:00-07:00`
+///
+/// A window whose end is before its start runs past midnight
+/// (`22:00-06:00`); equal ends mean the whole day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// When the window next opens after `now`, or `now` if it is open
+    pub fn next_open(&self, now: DateTime<Local>) -> DateTime<Local> {
+        if self.contains(now.time()) {
+            return now;
+        }
+        let mut date = now.date_naive();
+        if now.time() >= self.start {
+            date = date.succ_opt().expect("date in range");
+        }
+        local_time(date.and_time(self.start))
+    }
+
+    /// When the window open at `now` closes; `None` if it isn't open or
+    /// never closes
+    pub fn closes(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        if self.start == self.end || !self.contains(now.time()) {
+            return None;
+        }
+        let mut date = now.date_naive();
+        if now.time() >= self.end {
+            date = date.succ_opt().expect("date in range");
+        }
+        Some(local_time(date.and_time(self.end)))
+    }
+}
+
+/// `time` in the local zone; a time skipped by a DST jump is taken an hour
+/// later
+fn local_time(time: chrono::NaiveDateTime) -> DateTime<Local> {
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .or_else(|| Local.from_local_datetime(&(time + TimeDelta::hours(1))).earliest())
+        .unwrap_or_else(|| Local.from_utc_datetime(&time))
+}
+
+impl FromStr for TimeWindow {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || CoreError::Schedule(format!("'{s}' is not a window like 01:00-07:00"));
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let time = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|_| invalid());
+
+        Ok(TimeWindow { start: time(start)?, end: time(end)? })
+    }
+}
+
+impl TryFrom<String> for TimeWindow {
+    type Error = CoreError;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<TimeWindow> for String {
+    fn from(window: TimeWindow) -> Self {
+        window.to_string()
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&format!("{}-{}", self.start.format("%H:%M"), self.end.format("%H:%M")))
+    }
+}
+
+/// When heavy operations may run; an operation without a window runs
+/// whenever it is started
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Schedule {
+    /// Downloads with `-S`
+    pub downloads: Option<TimeWindow>,
+    /// Full checks of the library's files (`--validate-local`)
+    pub scrubs: Option<TimeWindow>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_window() {
+        let at = |h: u32, m: u32| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        let night: TimeWindow = "01:00-07:00".parse().unwrap();
+        assert!(night.contains(at(1, 0)) && night.contains(at(6, 59)));
+        assert!(!night.contains(at(7, 0)) && !night.contains(at(12, 0)));
+        assert_eq!(night.to_string(), "01:00-07:00");
+
+        let late: TimeWindow = "22:00 - 06:00".parse().unwrap();
+        assert!(late.contains(at(23, 30)) && late.contains(at(5, 0)) && !late.contains(at(6, 0)));
+        assert!("01:00".parse::<TimeWindow>().is_err());
+        assert!("25:00-07:00".parse::<TimeWindow>().is_err());
+
+        let noon = Local.with_ymd_and_hms(2026, 3, 10, 12, 0, 0).unwrap();
+        assert_eq!(night.next_open(noon), Local.with_ymd_and_hms(2026, 3, 11, 1, 0, 0).unwrap());
+        assert_eq!(night.closes(noon), None);
+        let small_hours = Local.with_ymd_and_hms(2026, 3, 10, 0, 30, 0).unwrap();
+        assert_eq!(night.next_open(small_hours), Local.with_ymd_and_hms(2026, 3, 10, 1, 0, 0).unwrap());
+        let evening = Local.with_ymd_and_hms(2026, 3, 10, 23, 0, 0).unwrap();
+        assert_eq!(late.closes(evening), Some(Local.with_ymd_and_hms(2026, 3, 11, 6, 0, 0).unwrap()));
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+
+use crate::collate::Collation;
+use crate::librarydb::TrackRecord;
+
+
+/// Share of a term's trigrams a field must contain to match it at all
+const MIN_SIMILARITY: f64 = 0.5;
+
+/// Fuzzy matches rank below any exact one
+const FUZZY_WEIGHT: f64 = 0.8;
+
+/// Field a search term is limited to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchField {
+    /// Track or album artist
+    Artist,
+    Album,
+    Title,
+}
+
+impl SearchField {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "artist" => Some(SearchField::Artist),
+            "album" => Some(SearchField::Album),
+            "title" | "track" => Some(SearchField::Title),
+            _ => None,
+        }
+    }
+}
+
+/// One word or quoted phrase of a query, with the field it is limited to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchTerm {
+    pub field: Option<SearchField>,
+    pub text: String,
+}
+
+/// A parsed `-Qs` query, e.g. `artist:radiohead album:"ok computer" airbag`
+///
+/// Every term must match for a track to be found. A prefix that isn't a
+/// field name is part of the word (`re:member`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub terms: Vec<SearchTerm>,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Self {
+        let mut terms = Vec::new();
+        let mut chars = query.chars().peekable();
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let mut word = String::new();
+            let mut field = None;
+            let mut quoted = false;
+            for c in chars.by_ref() {
+                match c {
+                    '"' => quoted = !quoted,
+                    ':' if !quoted && field.is_none() && SearchField::from_name(&word).is_some() => {
+                        field = SearchField::from_name(&word);
+                        word.clear();
+                    }
+                    c if c.is_whitespace() && !quoted => break,
+                    c => word.push(c),
+                }
+            }
+            if !word.trim().is_empty() {
+                terms.push(SearchTerm { field, text: word.trim().to_owned() });
+            }
+        }
+
+        SearchQuery { terms }
+    }
+}
+
+/// Text folded for matching, with its trigrams
+#[derive(Debug, Clone)]
+struct Folded {
+    text: String,
+    trigrams: HashSet<[char; 3]>,
+}
+
+impl Folded {
+    fn new(collation: &Collation, text: &str) -> Self {
+        let text: String =
+            collation.fold(text).chars().map(|c| if c.is_alphanumeric() { c } else { ' ' }).collect();
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        // Each word padded the way pg_trgm does, so short words and word
+        // starts count
+        let mut trigrams = HashSet::new();
+        for word in text.split(' ').filter(|w| !w.is_empty()) {
+            let padded: Vec<char> = format!("  {} ", word).chars().collect();
+            trigrams.extend(padded.windows(3).map(|w| [w[0], w[1], w[2]]));
+        }
+        Folded { text, trigrams }
+    }
+
+    /// How well `term` matches this text, in `0.0..=1.0`
+    fn score(&self, term: &Folded) -> f64 {
+        if let Some(at) = self.text.find(&term.text) {
+            let word_start = at == 0 || self.text[..at].ends_with(' ');
+            return if word_start { 1.0 } else { 0.9 };
+        }
+        if term.trigrams.is_empty() {
+            return 0.0;
+        }
+
+        let shared = term.trigrams.intersection(&self.trigrams).count() as f64 / term.trigrams.len() as f64;
+        if shared >= MIN_SIMILARITY { shared * FUZZY_WEIGHT } else { 0.0 }
+    }
+}
+
+/// A track found by [`SearchIndex::search`]
+#[derive(Debug, Clone)]
+pub struct SearchHit<'a> {
+    pub track: &'a TrackRecord,
+    /// How well the track matches, in `0.0..=1.0`
+    pub score: f64,
+}
+
+/// Fuzzy search over the artists, albums and titles of library tracks
+///
+/// Matching ignores case, accents and punctuation. Terms found as they
+/// are rank first; misspelled ones still match when enough of their
+/// trigrams do.
+#[derive(Debug, Clone)]
+pub struct SearchIndex {
+    collation: Collation,
+    tracks: Vec<TrackRecord>,
+    /// Folded artist, album artist, album and title of each track
+    fields: Vec<[Folded; 4]>,
+}
+
+impl SearchIndex {
+    /// Index `tracks`; equally good matches are returned in this order
+    pub fn new(tracks: Vec<TrackRecord>) -> Self {
+        Self::with_collation(tracks, Collation::default())
+    }
+
+    /// Index `tracks`, folding names as `collation` does
+    pub fn with_collation(tracks: Vec<TrackRecord>, collation: Collation) -> Self {
+        let fields = tracks
+            .iter()
+            .map(|t| [&t.artist, &t.album_artist, &t.album, &t.title].map(|text| Folded::new(&collation, text)))
+            .collect();
+        SearchIndex { collation, tracks, fields }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    /// Tracks matching every term of `query`, best first
+    pub fn search(&self, query: &SearchQuery) -> Vec<SearchHit<'_>> {
+        let terms: Vec<(Option<SearchField>, Folded)> =
+            query.terms.iter().map(|t| (t.field, Folded::new(&self.collation, &t.text))).collect();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let mut hits: Vec<SearchHit<'_>> = self
+            .tracks
+            .iter()
+            .zip(&self.fields)
+            .filter_map(|(track, [artist, album_artist, album, title])| {
+                let mut total = 0.0;
+                for (field, term) in &terms {
+                    let fields: &[&Folded] = match field {
+                        Some(SearchField::Artist) => &[artist, album_artist],
+                        Some(SearchField::Album) => &[album],
+                        Some(SearchField::Title) => &[title],
+                        None => &[artist, album_artist, album, title],
+                    };
+                    let score = fields.iter().map(|f| f.score(term)).fold(0.0, f64::max);
+                    if score == 0.0 {
+                        return None;
+                    }
+                    total += score;
+                }
+                Some(SearchHit { track, score: total / terms.len() as f64 })
+            })
+            .collect();
+
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+    use std::path::PathBuf;
+
+    fn record(artist: &str, album: &str, title: &str) -> TrackRecord {
+        TrackRecord {
+            path: PathBuf::from(format!("/music/{artist}/{album}/{title}.flac")),
+            album_artist: artist.to_owned(),
+            album: album.to_owned(),
+            artist: artist.to_owned(),
+            title: title.to_owned(),
+            disc: None,
+            track: None,
+            year: None,
+            tags: Default::default(),
+            sha256: None,
+            size: 0,
+            modified: 0,
+            added: Local::now(),
+        }
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let query = SearchQuery::parse(r#"artist:radiohead  album:"ok computer" Title:airbag re:member"#);
+        let term = |field, text: &str| SearchTerm { field, text: text.to_owned() };
+        assert_eq!(
+            query.terms,
+            [
+                term(Some(SearchField::Artist), "radiohead"),
+                term(Some(SearchField::Album), "ok computer"),
+                term(Some(SearchField::Title), "airbag"),
+                term(None, "re:member"),
+            ]
+        );
+        assert!(SearchQuery::parse("  \"\" ").terms.is_empty());
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let index = SearchIndex::new(vec![
+            record("Radiohead", "OK Computer", "Airbag"),
+            record("Radiohead", "Kid A", "Everything in Its Right Place"),
+            record("Björk", "Homogenic", "Jóga"),
+            record("Sigur Rós", "Takk...", "Hoppípolla"),
+        ]);
+        let titles = |query: &str| -> Vec<String> {
+            index.search(&SearchQuery::parse(query)).iter().map(|hit| hit.track.title.clone()).collect()
+        };
+
+        assert_eq!(titles("bjork joga"), ["Jóga"]);
+        assert_eq!(titles("radiohed"), ["Airbag", "Everything in Its Right Place"]);
+        assert_eq!(titles(r#"artist:radiohead album:"ok computr""#), ["Airbag"]);
+        assert!(titles("album:radiohead").is_empty());
+        assert_eq!(titles("hoppipolla sigur"), ["Hoppípolla"]);
+
+        // An exact match outranks a fuzzy one
+        let hits = index.search(&SearchQuery::parse("kid"));
+        assert_eq!(hits[0].track.album, "Kid A");
+        assert!(hits.iter().skip(1).all(|hit| hit.score < hits[0].score));
+    }
+}
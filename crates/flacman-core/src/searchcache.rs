@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, TimeDelta};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::Result;
+
+
+/// Results older than this are still served, but marked stale
+const DEFAULT_TTL_DAYS: i64 = 7;
+
+/// Entries kept before the oldest are evicted
+const DEFAULT_CAPACITY: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    fetched: DateTime<Local>,
+    results: serde_json::Value,
+}
+
+/// Results served from the cache instead of the remote source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cached<T> {
+    pub results: T,
+    pub fetched: DateTime<Local>,
+    /// Older than the cache's time to live; the source may have changed since
+    pub stale: bool,
+}
+
+/// Recent remote search results and release metadata, kept so repeated
+/// queries can be answered offline or while a source is down
+///
+/// One JSON file in the data directory, keyed by source, kind and the
+/// normalized query.
+#[derive(Debug, Clone)]
+pub struct SearchCache {
+    path: PathBuf,
+    pub ttl: TimeDelta,
+    pub capacity: usize,
+}
+
+impl SearchCache {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        SearchCache {
+            path: path.as_ref().to_path_buf(),
+            ttl: TimeDelta::days(DEFAULT_TTL_DAYS),
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+
+    /// Cache key for a query; case and spacing don't make a new entry
+    pub fn key(source: &str, kind: &str, query: &str) -> String {
+        let query: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        format!("{}:{}:{}", source, kind, query.join(" "))
+    }
+
+    fn load(&self) -> Result<BTreeMap<String, CacheEntry>> {
+        match fs::read(&self.path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The cached results for `key`, however old
+    pub fn get<T: DeserializeOwned>(&self, key: &str, now: DateTime<Local>) -> Result<Option<Cached<T>>> {
+        let Some(entry) = self.load()?.remove(key) else {
+            return Ok(None);
+        };
+
+        Ok(Some(Cached {
+            results: serde_json::from_value(entry.results)?,
+            fetched: entry.fetched,
+            stale: now - entry.fetched > self.ttl,
+        }))
+    }
+
+    /// Store `results` for `key`, evicting the oldest entries over capacity
+    pub fn put<T: Serialize>(&self, key: &str, results: &T, now: DateTime<Local>) -> Result<()> {
+        let mut entries = self.load()?;
+        entries.insert(key.to_owned(), CacheEntry { fetched: now, results: serde_json::to_value(results)? });
+
+        while entries.len() > self.capacity {
+            let oldest = entries.iter().min_by_key(|(_, e)| e.fetched).map(|(k, _)| k.clone());
+            entries.remove(&oldest.expect("cache is not empty"));
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_vec(&entries)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cache_staleness_and_eviction() {
+        let dir = tempdir().unwrap();
+        let mut cache = SearchCache::new(dir.path().join("search-cache.json"));
+        cache.capacity = 2;
+        let now = Local::now();
+
+        let key = SearchCache::key("musicbrainz", "album", "  Things We  Lost ");
+        assert_eq!(key, "musicbrainz:album:things we lost");
+        assert_eq!(cache.get::<Vec<String>>(&key, now).unwrap(), None);
+
+        cache.put(&key, &vec!["Things We Lost in the Fire".to_owned()], now - TimeDelta::days(10)).unwrap();
+        let cached: Cached<Vec<String>> = cache.get(&key, now).unwrap().unwrap();
+        assert_eq!(cached.results, ["Things We Lost in the Fire"]);
+        assert!(cached.stale);
+
+        cache.put("b", &1, now).unwrap();
+        cache.put("c", &2, now).unwrap();
+        assert_eq!(cache.get::<Vec<String>>(&key, now).unwrap(), None);
+        assert!(!cache.get::<u32>("c", now).unwrap().unwrap().stale);
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::Result;
+
+
+/// Consecutive failures after which a source is passed over
+const DEFAULT_MAX_FAILURES: u32 = 3;
+
+/// How long an unhealthy source is passed over before it is tried again
+const DEFAULT_COOLDOWN_MINUTES: i64 = 60;
+
+/// Recent download results of one source
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceState {
+    /// Failures since the last success
+    pub failures: u32,
+    pub last_failure: Option<DateTime<Local>>,
+    pub last_success: Option<DateTime<Local>>,
+}
+
+/// Where the next download goes, given the configured sources in priority order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failover {
+    /// The source to use; `None` when every source is unhealthy
+    pub source: Option<String>,
+    /// Higher-priority sources passed over, with why
+    pub skipped: Vec<(String, String)>,
+}
+
+/// How well each download source has been doing, so a source that keeps
+/// failing is passed over for the next one until it has had time to recover
+///
+/// One JSON file in the data directory, keyed by source name.
+#[derive(Debug, Clone)]
+pub struct SourceHealth {
+    path: PathBuf,
+    pub max_failures: u32,
+    pub cooldown: TimeDelta,
+}
+
+impl SourceHealth {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        SourceHealth {
+            path: path.as_ref().to_path_buf(),
+            max_failures: DEFAULT_MAX_FAILURES,
+            cooldown: TimeDelta::minutes(DEFAULT_COOLDOWN_MINUTES),
+        }
+    }
+
+    /// The state of every source that has been used
+    pub fn load(&self) -> Result<BTreeMap<String, SourceState>> {
+        match fs::read(&self.path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn update(&self, source: &str, change: impl FnOnce(&mut SourceState)) -> Result<()> {
+        let mut states = self.load()?;
+        change(states.entry(source.to_owned()).or_default());
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_vec_pretty(&states)?)?;
+        Ok(())
+    }
+
+    pub fn record_success(&self, source: &str, now: DateTime<Local>) -> Result<()> {
+        self.update(source, |state| {
+            state.failures = 0;
+            state.last_success = Some(now);
+        })
+    }
+
+    pub fn record_failure(&self, source: &str, now: DateTime<Local>) -> Result<()> {
+        self.update(source, |state| {
+            state.failures += 1;
+            state.last_failure = Some(now);
+        })
+    }
+
+    /// Why `state` makes a source unfit to use at `now`, if it does
+    fn unhealthy(&self, state: &SourceState, now: DateTime<Local>) -> Option<String> {
+        let last_failure = state.last_failure?;
+        let retry_at = last_failure + self.cooldown;
+        (state.failures >= self.max_failures && now < retry_at).then(|| {
+            format!("failed {} times in a row, retried after {}", state.failures, retry_at.format("%H:%M"))
+        })
+    }
+
+    /// Pick the first of `sources` that is healthy and `usable`
+    ///
+    /// # Arguments
+    /// * `sources` - Configured sources, highest priority first
+    /// * `usable` - Other reasons to pass a source over, e.g. an exhausted
+    ///   quota or a quality policy it can't meet; `Err` holds the reason
+    pub fn failover<F>(&self, sources: &[String], now: DateTime<Local>, mut usable: F) -> Result<Failover>
+    where
+        F: FnMut(&str) -> std::result::Result<(), String>,
+    {
+        let states = self.load()?;
+        let mut skipped = Vec::new();
+
+        for source in sources {
+            let reason = states.get(source).and_then(|state| self.unhealthy(state, now));
+            match reason.map_or_else(|| usable(source), Err) {
+                Ok(()) => return Ok(Failover { source: Some(source.clone()), skipped }),
+                Err(reason) => skipped.push((source.clone(), reason)),
+            }
+        }
+
+        Ok(Failover { source: None, skipped })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_failover() {
+        let dir = tempdir().unwrap();
+        let health = SourceHealth::new(dir.path().join("source-health.json"));
+        let sources = vec!["seedbox".to_owned(), "mirror".to_owned(), "archive".to_owned()];
+        let now = Local::now();
+        let anything = |_: &str| Ok(());
+
+        assert_eq!(health.failover(&sources, now, anything).unwrap().source.as_deref(), Some("seedbox"));
+
+        for _ in 0..3 {
+            health.record_failure("seedbox", now).unwrap();
+        }
+        let failover = health.failover(&sources, now, |s| if s == "mirror" { Err("quota".into()) } else { Ok(()) });
+        let failover = failover.unwrap();
+        assert_eq!(failover.source.as_deref(), Some("archive"));
+        assert_eq!(failover.skipped.len(), 2);
+        assert_eq!(failover.skipped[1], ("mirror".to_owned(), "quota".to_owned()));
+
+        // Tried again once the cooldown is over, and healthy after a success
+        let later = now + TimeDelta::hours(2);
+        assert_eq!(health.failover(&sources, later, anything).unwrap().source.as_deref(), Some("seedbox"));
+        health.record_success("seedbox", now).unwrap();
+        assert_eq!(health.load().unwrap()["seedbox"].failures, 0);
+    }
+}
@@ -0,0 +1,424 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::coreerror::{CoreError, Result};
+
+
+/// Source of values for template fields
+///
+/// Field names are lowercase (`artist`, `albumartist`, `track`, ...).
+/// Returning `None` renders the field as an empty string and makes
+/// conditionals on it false.
+pub trait TemplateFields {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>>;
+}
+
+impl TemplateFields for HashMap<String, String> {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.get(name).map(|v| Cow::Borrowed(v.as_str()))
+    }
+}
+
+impl TemplateFields for HashMap<&str, &str> {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.get(name).map(|v| Cow::Borrowed(*v))
+    }
+}
+
+/// Where the tracks of a multi-disc release go
+///
+/// Deserializable so it can be set as `disc_layout` in flacman.conf.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscLayout {
+    /// `Album/Disc 2 - Subtitle/01 Title`
+    #[default]
+    Subdirectories,
+    /// `Album/2-01 Title`
+    Flat,
+}
+
+impl DiscLayout {
+    /// Default library path template for this layout
+    ///
+    /// Single-disc releases render the same with either layout.
+    pub fn default_template(&self) -> &'static str {
+        match self {
+            DiscLayout::Subdirectories => {
+                "%{compilation:Various Artists|%albumartist%}/%album%/\
+                 %{multidisc:Disc %disc%%{discsubtitle: - %discsubtitle%}/}%track:02% %title%"
+            }
+            DiscLayout::Flat => {
+                "%{compilation:Various Artists|%albumartist%}/%album%/%{multidisc:%disc%-}%track:02% %title%"
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Literal(String),
+    /// `%name%` or `%name:0N%` (zero-padded to N digits)
+    Field { name: String, pad: usize },
+    /// `%{name:then|else}`
+    Conditional { name: String, then: Vec<Node>, otherwise: Vec<Node> },
+}
+
+/// A parsed naming/output template
+///
+/// # Syntax
+/// * `%artist%` - value of a field
+/// * `%track:02%` - numeric field zero-padded to two digits
+/// * `%{compilation:Various Artists/%album%|%albumartist%/%album%}` -
+///   first branch if the field is set (non-empty and not `0`/`false`),
+///   otherwise the second; the `|else` part is optional
+/// * `%%`, `%|`, `%}` - literal `%`, `|`, `}`
+///
+/// Besides plain tag fields, two derived fields are understood:
+/// * `multidisc` - set when `disctotal` (or `disc`) is greater than 1
+/// * `compilation` - set when the `compilation` tag is set or the album
+///   artist is "Various Artists"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl FromStr for Template {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser { chars: s.chars().collect(), pos: 0 };
+        let nodes = parser.parse_nodes(false)?;
+
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("unexpected character"));
+        }
+
+        Ok(Template { nodes })
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> CoreError {
+        CoreError::Template(format!("{} at position {}", message, self.pos))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    /// Parse until end of input, or until an unescaped `|`/`}` inside a conditional
+    fn parse_nodes(&mut self, in_branch: bool) -> Result<Vec<Node>> {
+        let mut nodes = Vec::new();
+        let mut literal = String::new();
+
+        while let Some(c) = self.peek() {
+            if in_branch && (c == '|' || c == '}') {
+                break;
+            }
+
+            self.pos += 1;
+
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+
+            match self.peek() {
+                Some(escaped @ ('%' | '|' | '}')) => {
+                    self.pos += 1;
+                    literal.push(escaped);
+                }
+                Some('{') => {
+                    self.pos += 1;
+                    if !literal.is_empty() {
+                        nodes.push(Node::Literal(std::mem::take(&mut literal)));
+                    }
+                    nodes.push(self.parse_conditional()?);
+                }
+                Some(_) => {
+                    if !literal.is_empty() {
+                        nodes.push(Node::Literal(std::mem::take(&mut literal)));
+                    }
+                    nodes.push(self.parse_field()?);
+                }
+                None => return Err(self.error("dangling '%'")),
+            }
+        }
+
+        if !literal.is_empty() {
+            nodes.push(Node::Literal(literal));
+        }
+
+        Ok(nodes)
+    }
+
+    fn parse_name(&mut self) -> Result<String> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        if self.pos == start {
+            return Err(self.error("expected field name"));
+        }
+
+        Ok(self.chars[start..self.pos].iter().collect::<String>().to_lowercase())
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        if self.peek() != Some(expected) {
+            return Err(self.error(&format!("expected '{expected}'")));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn parse_field(&mut self) -> Result<Node> {
+        let name = self.parse_name()?;
+        let mut pad = 0;
+
+        if self.peek() == Some(':') {
+            self.pos += 1;
+            let start = self.pos;
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.pos += 1;
+            }
+            let spec: String = self.chars[start..self.pos].iter().collect();
+            pad = spec.parse().map_err(|_| self.error("expected padding width"))?;
+        }
+
+        self.expect('%')?;
+
+        Ok(Node::Field { name, pad })
+    }
+
+    fn parse_conditional(&mut self) -> Result<Node> {
+        let name = self.parse_name()?;
+        self.expect(':')?;
+
+        let then = self.parse_nodes(true)?;
+        let otherwise = if self.peek() == Some('|') {
+            self.pos += 1;
+            self.parse_nodes(true)?
+        } else {
+            Vec::new()
+        };
+
+        self.expect('}')?;
+
+        Ok(Node::Conditional { name, then, otherwise })
+    }
+}
+
+fn is_truthy(value: &str) -> bool {
+    let value = value.trim();
+    !(value.is_empty() || value == "0" || value.eq_ignore_ascii_case("false"))
+}
+
+/// Leading number of values like `2` or `2/3`
+fn leading_number(value: &str) -> Option<u32> {
+    value.split('/').next()?.trim().parse().ok()
+}
+
+impl Template {
+    fn lookup<'a, F: TemplateFields + ?Sized>(fields: &'a F, name: &str) -> Option<Cow<'a, str>> {
+        match name {
+            "multidisc" => {
+                let total = fields
+                    .field("disctotal")
+                    .and_then(|v| leading_number(&v))
+                    .or_else(|| fields.field("disc").and_then(|v| v.split('/').nth(1)?.trim().parse().ok()));
+                let disc = fields.field("disc").and_then(|v| leading_number(&v));
+
+                let multi = total.unwrap_or(0) > 1 || disc.unwrap_or(0) > 1;
+                multi.then_some(Cow::Borrowed("1"))
+            }
+            "compilation" => {
+                let flagged = fields.field("compilation").is_some_and(|v| is_truthy(&v));
+                let various = fields
+                    .field("albumartist")
+                    .is_some_and(|v| v.trim().eq_ignore_ascii_case("various artists"));
+
+                (flagged || various).then_some(Cow::Borrowed("1"))
+            }
+            _ => fields.field(name),
+        }
+    }
+
+    fn render_nodes<F, E>(nodes: &[Node], fields: &F, escape: &E, out: &mut String)
+    where
+        F: TemplateFields + ?Sized,
+        E: Fn(&str) -> String,
+    {
+        for node in nodes {
+            match node {
+                Node::Literal(text) => out.push_str(text),
+                Node::Field { name, pad } => {
+                    let value = Self::lookup(fields, name).unwrap_or_default();
+                    let value = match leading_number(&value) {
+                        Some(n) if *pad > 0 => format!("{:0width$}", n, width = *pad),
+                        _ => value.into_owned(),
+                    };
+                    out.push_str(&escape(&value));
+                }
+                Node::Conditional { name, then, otherwise } => {
+                    let set = Self::lookup(fields, name).is_some_and(|v| is_truthy(&v));
+                    let branch = if set { then } else { otherwise };
+                    Self::render_nodes(branch, fields, escape, out);
+                }
+            }
+        }
+    }
+
+    /// Render with field values inserted verbatim (for display output)
+    pub fn render<F: TemplateFields + ?Sized>(&self, fields: &F) -> String {
+        let mut out = String::new();
+        Self::render_nodes(&self.nodes, fields, &|v: &str| v.to_owned(), &mut out);
+        out
+    }
+
+    /// Render a relative path
+    ///
+    /// Path separators inside field values become `replacement`, as the
+    /// sanitize policy replaces other characters a name can't have, so
+    /// that only the template's own `/` create directories; empty
+    /// components (e.g. from a missing album) are dropped.
+    pub fn render_path<F: TemplateFields + ?Sized>(&self, fields: &F, replacement: char) -> std::path::PathBuf {
+        let mut out = String::new();
+        Self::render_nodes(&self.nodes, fields, &|v: &str| v.replace(['/', '\\'], &replacement.to_string()), &mut out);
+
+        out.split('/')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .collect()
+    }
+
+    /// Names of all fields the template refers to
+    pub fn fields(&self) -> Vec<&str> {
+        fn walk<'a>(nodes: &'a [Node], out: &mut Vec<&'a str>) {
+            for node in nodes {
+                match node {
+                    Node::Literal(_) => {}
+                    Node::Field { name, .. } => out.push(name),
+                    Node::Conditional { name, then, otherwise } => {
+                        out.push(name);
+                        walk(then, out);
+                        walk(otherwise, out);
+                    }
+                }
+            }
+        }
+
+        let mut out = Vec::new();
+        walk(&self.nodes, &mut out);
+        out.sort_unstable();
+        out.dedup();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const LAYOUT: &str = "%{compilation:Various Artists/%album%|%albumartist%/%album%}/\
+                          %{multidisc:Disc %disc%/}%track:02% %title%";
+
+    fn fields<'a>(pairs: &[(&'a str, &'a str)]) -> HashMap<&'a str, &'a str> {
+        pairs.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_plain_fields() {
+        let t: Template = "%artist% - %title%".parse().unwrap();
+        let f = fields(&[("artist", "Low"), ("title", "Words")]);
+        assert_eq!(t.render(&f), "Low - Words");
+    }
+
+    #[test]
+    fn test_padding_and_escapes() {
+        let t: Template = "%track:02%. 100%% %title%".parse().unwrap();
+        let f = fields(&[("track", "3/12"), ("title", "x")]);
+        assert_eq!(t.render(&f), "03. 100% x");
+    }
+
+    #[test]
+    fn test_compilation_path() {
+        let t: Template = LAYOUT.parse().unwrap();
+        let f = fields(&[
+            ("albumartist", "Various Artists"),
+            ("album", "Pure Moods"),
+            ("track", "1"),
+            ("title", "Orinoco Flow"),
+        ]);
+        assert_eq!(t.render_path(&f, '_'), PathBuf::from("Various Artists/Pure Moods/01 Orinoco Flow"));
+    }
+
+    #[test]
+    fn test_multidisc_path() {
+        let t: Template = LAYOUT.parse().unwrap();
+        let f = fields(&[
+            ("albumartist", "Pink Floyd"),
+            ("album", "The Wall"),
+            ("disc", "2"),
+            ("disctotal", "2"),
+            ("track", "4"),
+            ("title", "Vera"),
+        ]);
+        assert_eq!(t.render_path(&f, '_'), PathBuf::from("Pink Floyd/The Wall/Disc 2/04 Vera"));
+
+        let single = fields(&[("albumartist", "Low"), ("album", "Things We Lost"), ("disc", "1/1"), ("track", "1"), ("title", "Monkey")]);
+        assert_eq!(t.render_path(&single, '_'), PathBuf::from("Low/Things We Lost/01 Monkey"));
+    }
+
+    #[test]
+    fn test_disc_layouts() {
+        let f = fields(&[
+            ("albumartist", "The Cure"),
+            ("album", "Disintegration"),
+            ("disc", "2"),
+            ("disctotal", "3"),
+            ("discsubtitle", "Rarities"),
+            ("track", "5"),
+            ("title", "Babble"),
+        ]);
+
+        let subdirs: Template = DiscLayout::Subdirectories.default_template().parse().unwrap();
+        assert_eq!(
+            subdirs.render_path(&f, '_'),
+            PathBuf::from("The Cure/Disintegration/Disc 2 - Rarities/05 Babble")
+        );
+
+        let flat: Template = DiscLayout::Flat.default_template().parse().unwrap();
+        assert_eq!(flat.render_path(&f, '_'), PathBuf::from("The Cure/Disintegration/2-05 Babble"));
+    }
+
+    #[test]
+    fn test_values_cannot_add_directories() {
+        let t: Template = "%artist%/%title%".parse().unwrap();
+        let f = fields(&[("artist", "AC/DC"), ("title", "T.N.T.")]);
+        assert_eq!(t.render_path(&f, '_'), PathBuf::from("AC_DC/T.N.T."));
+        assert_eq!(t.render_path(&f, '-'), PathBuf::from("AC-DC/T.N.T."));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("%artist".parse::<Template>().is_err());
+        assert!("%{compilation:x".parse::<Template>().is_err());
+        assert!("%track:xx%".parse::<Template>().is_err());
+        assert!("abc}".parse::<Template>().is_ok());
+    }
+}
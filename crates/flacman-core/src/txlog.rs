@@ -0,0 +1,331 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::coreerror::Result;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxOutcome {
+    Success,
+    /// Some items were processed, others failed or were vetoed
+    Partial,
+    Failed,
+    /// Refused by a verification check before anything was changed
+    Vetoed,
+    /// Stopped by Ctrl-C at a safe point; `files` counts what was committed
+    Cancelled,
+}
+
+/// One change a transaction made to the filesystem, recorded so it can be
+/// rolled back
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum FileChange {
+    /// `to` was copied or linked from `from`, which was left in place
+    Copied { from: PathBuf, to: PathBuf },
+    Moved { from: PathBuf, to: PathBuf },
+    /// `original` was moved into the trash at `stored`
+    Trashed { original: PathBuf, stored: PathBuf },
+    /// `path` was written from nothing local, e.g. downloaded
+    Created { path: PathBuf },
+    /// `path` was deleted for good and can't be brought back
+    Deleted { path: PathBuf },
+}
+
+/// One line of the transaction log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TxRecord {
+    pub id: u64,
+    pub time: DateTime<Local>,
+    /// `sync`, `update`, `remove`, ...
+    pub operation: String,
+    pub targets: Vec<String>,
+    /// Number of files touched
+    #[serde(default)]
+    pub files: u64,
+    /// Total size of the touched files in bytes
+    #[serde(default)]
+    pub bytes: u64,
+    /// Where the files came from, e.g. a remote source or import path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub outcome: TxOutcome,
+    /// Free-form notes, e.g. which check vetoed an item and why
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<String>,
+    /// What was done to which files, in order, for `flacman --rollback`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<FileChange>,
+}
+
+impl TxRecord {
+    pub fn new(operation: &str, targets: Vec<String>, outcome: TxOutcome) -> Self {
+        TxRecord {
+            id: 0,
+            time: Local::now(),
+            operation: operation.to_owned(),
+            targets,
+            files: 0,
+            bytes: 0,
+            source: None,
+            outcome,
+            messages: Vec::new(),
+            changes: Vec::new(),
+        }
+    }
+}
+
+/// Selects records for `flacman --history`; unset fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct TxFilter {
+    /// Only records at or after this time
+    pub since: Option<DateTime<Local>>,
+    /// Only records with a target containing this text (case-insensitive)
+    pub target: Option<String>,
+}
+
+impl TxFilter {
+    pub fn matches(&self, record: &TxRecord) -> bool {
+        if self.since.is_some_and(|since| record.time < since) {
+            return false;
+        }
+
+        if let Some(target) = &self.target {
+            let needle = target.to_lowercase();
+            if !record.targets.iter().any(|t| t.to_lowercase().contains(&needle)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Append-only log of flacman operations, one JSON object per line
+///
+/// Like pacman.log, but machine readable so history can be queried.
+#[derive(Debug, Clone)]
+pub struct TxLog {
+    path: PathBuf,
+}
+
+impl TxLog {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        TxLog { path: path.as_ref().to_path_buf() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// All records in the log, oldest first
+    ///
+    /// Lines that don't parse (e.g. a write cut short by a crash) are skipped.
+    pub fn read_all(&self) -> Result<Vec<TxRecord>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let reader = BufReader::new(fs::File::open(&self.path)?);
+        let mut records = Vec::new();
+
+        for line in reader.lines() {
+            if let Ok(record) = serde_json::from_str(&line?) {
+                records.push(record);
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Records matching `filter`, oldest first
+    pub fn history(&self, filter: &TxFilter) -> Result<Vec<TxRecord>> {
+        Ok(self.read_all()?.into_iter().filter(|r| filter.matches(r)).collect())
+    }
+
+    /// The record of transaction `id`, if it is in the log
+    pub fn find(&self, id: u64) -> Result<Option<TxRecord>> {
+        Ok(self.read_all()?.into_iter().find(|r| r.id == id))
+    }
+
+    /// The id the next [`TxLog::append`] assigns, unless another process
+    /// appends first
+    pub fn next_id(&self) -> Result<u64> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(1),
+            Err(e) => return Err(e.into()),
+        };
+        file.lock_shared()?;
+        Ok(last_id(&mut file)? + 1)
+    }
+
+    /// Append a record, assigning it the next transaction id
+    ///
+    /// The log is locked while the id is picked and the record written, so
+    /// concurrent flacman processes never hand out the same id.
+    ///
+    /// # Returns
+    /// The assigned id
+    pub fn append(&self, mut record: TxRecord) -> Result<u64> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(&self.path)?;
+        file.lock()?;
+        record.id = last_id(&mut file)? + 1;
+        let mut line = serde_json::to_string(&record)?;
+        // Don't run on from a line a crash cut short
+        if ends_mid_line(&mut file)? {
+            line.insert(0, '\n');
+        }
+        writeln!(file, "{line}")?;
+
+        Ok(record.id)
+    }
+}
+
+/// Whether `file` ends without a newline
+fn ends_mid_line(file: &mut File) -> Result<bool> {
+    if file.metadata()?.len() == 0 {
+        return Ok(false);
+    }
+
+    let mut last = [0];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    Ok(last[0] != b'\n')
+}
+
+/// Id of the last record in the log `file`, or 0 when it has none
+///
+/// Reads backwards from the end a chunk at a time, so appending doesn't
+/// get slower as the log grows.
+fn last_id(file: &mut File) -> Result<u64> {
+    const CHUNK: u64 = 64 * 1024;
+
+    let mut end = file.metadata()?.len();
+    let mut tail = Vec::new();
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK);
+        let mut chunk = vec![0; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.append(&mut tail);
+        tail = chunk;
+        end = start;
+
+        // Before the first newline may be the end of a line that starts
+        // in the next chunk back
+        let lines = match tail.iter().position(|&b| b == b'\n') {
+            _ if start == 0 => &tail[..],
+            Some(first) => &tail[first + 1..],
+            None => continue,
+        };
+        if let Some(record) = lines.split(|&b| b == b'\n').rev().find_map(|l| serde_json::from_slice::<TxRecord>(l).ok()) {
+            return Ok(record.id);
+        }
+    }
+
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_append_assigns_ids() {
+        let dir = tempdir().unwrap();
+        let log = TxLog::new(dir.path().join("flacman.log"));
+
+        assert_eq!(log.next_id().unwrap(), 1);
+        let first = log.append(TxRecord::new("update", vec!["a".into()], TxOutcome::Success)).unwrap();
+        assert_eq!(log.next_id().unwrap(), 2);
+        let mut vetoed = TxRecord::new("update", vec!["b".into()], TxOutcome::Vetoed);
+        vetoed.messages.push("log-score: 60 < 100".into());
+        let second = log.append(vetoed).unwrap();
+
+        assert_eq!((first, second), (1, 2));
+
+        let records = log.read_all().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].outcome, TxOutcome::Vetoed);
+        assert_eq!(records[1].messages, ["log-score: 60 < 100"]);
+    }
+
+    #[test]
+    fn test_append_continues_after_a_torn_line() {
+        let dir = tempdir().unwrap();
+        let log = TxLog::new(dir.path().join("flacman.log"));
+
+        for _ in 0..3 {
+            log.append(TxRecord::new("update", vec!["x".repeat(40_000)], TxOutcome::Success)).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(log.path()).unwrap();
+        write!(file, "{{\"id\":9,\"operation\":").unwrap();
+        drop(file);
+
+        assert_eq!(log.append(TxRecord::new("remove", Vec::new(), TxOutcome::Success)).unwrap(), 4);
+        assert_eq!(log.find(4).unwrap().map(|r| r.operation), Some("remove".to_owned()));
+    }
+
+    #[test]
+    fn test_concurrent_appends_get_distinct_ids() {
+        let dir = tempdir().unwrap();
+        let log = TxLog::new(dir.path().join("flacman.log"));
+
+        let mut ids: Vec<u64> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| s.spawn(|| log.append(TxRecord::new("update", Vec::new(), TxOutcome::Success)).unwrap()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        ids.sort();
+        assert_eq!(ids, (1..=8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_changes_round_trip() {
+        let dir = tempdir().unwrap();
+        let log = TxLog::new(dir.path().join("flacman.log"));
+
+        let mut record = TxRecord::new("remove", vec!["/music/Low/Trust".into()], TxOutcome::Success);
+        record.changes = vec![
+            FileChange::Trashed { original: "/music/Low/Trust".into(), stored: "/trash/b/0-Trust".into() },
+            FileChange::Created { path: "/music/Low/Trust/01.flac".into() },
+        ];
+        let id = log.append(record.clone()).unwrap();
+        log.append(TxRecord::new("update", Vec::new(), TxOutcome::Failed)).unwrap();
+
+        let found = log.find(id).unwrap().unwrap();
+        assert_eq!(found.changes, record.changes);
+        assert!(fs::read_to_string(log.path()).unwrap().contains(r#""change":"trashed""#));
+        assert_eq!(log.find(3).unwrap(), None);
+    }
+
+    #[test]
+    fn test_history_filter() {
+        let dir = tempdir().unwrap();
+        let log = TxLog::new(dir.path().join("flacman.log"));
+
+        log.append(TxRecord::new("remove", vec!["/music/Low/Things We Lost".into()], TxOutcome::Success)).unwrap();
+        log.append(TxRecord::new("restore", vec!["/music/Blur/13".into()], TxOutcome::Success)).unwrap();
+
+        let by_target = TxFilter { target: Some("low".into()), ..Default::default() };
+        let records = log.history(&by_target).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].operation, "remove");
+
+        let future = TxFilter { since: Some(Local::now() + chrono::TimeDelta::hours(1)), ..Default::default() };
+        assert!(log.history(&future).unwrap().is_empty());
+        assert_eq!(log.history(&TxFilter::default()).unwrap().len(), 2);
+    }
+}
@@ -1,10 +1,20 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
 use std::str::FromStr;
 
 use heapless::String as HeaplessString;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::coreerror;
 
 
+/// A string kept on the stack when it is short, as most tag values are
+///
+/// It reads like a `str` through `Deref`, and strings compare, hash and
+/// serialize by their text whichever variant holds it.
 #[derive(Debug, Clone)]
 pub enum String {
     Tiny(HeaplessString<32>),
@@ -13,6 +23,17 @@ pub enum String {
     Large(std::string::String),
 }
 
+impl String {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Tiny(s) => s.as_str(),
+            Self::Small(s) => s.as_str(),
+            Self::Medium(s) => s.as_str(),
+            Self::Large(s) => s.as_str(),
+        }
+    }
+}
+
 impl FromStr for String {
 
     type Err = coreerror::CoreError;
@@ -28,7 +49,158 @@ impl FromStr for String {
         };
 
         Ok(res)
-        // TODO: Write tests!
     }
 
-}
\ No newline at end of file
+}
+
+impl From<&str> for String {
+    fn from(s: &str) -> Self {
+        s.parse().expect("the variant is picked to fit the length")
+    }
+}
+
+impl From<std::string::String> for String {
+    /// Keeps the allocation of strings too long to go on the stack
+    fn from(s: std::string::String) -> Self {
+        if s.len() > 128 { Self::Large(s) } else { Self::from(s.as_str()) }
+    }
+}
+
+impl From<String> for std::string::String {
+    fn from(s: String) -> Self {
+        match s {
+            String::Large(s) => s,
+            s => s.as_str().to_owned(),
+        }
+    }
+}
+
+impl Default for String {
+    fn default() -> Self {
+        Self::Tiny(HeaplessString::new())
+    }
+}
+
+impl Deref for String {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for String {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for String {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for String {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+// Strings compare by their text, whichever variant holds it
+
+impl PartialEq for String {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for String {}
+
+impl PartialEq<str> for String {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for String {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<std::string::String> for String {
+    fn eq(&self, other: &std::string::String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialOrd for String {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for String {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Hash for String {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl Serialize for String {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for String {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        std::string::String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_variants_and_conversions() {
+        let short = String::from("Low");
+        assert!(matches!(short, String::Tiny(_)));
+        assert!(matches!(String::from("a".repeat(33)), String::Small(_)));
+        assert!(matches!(String::from("a".repeat(128).as_str()), String::Medium(_)));
+        let long = "a".repeat(129);
+        assert!(matches!(String::from(long.as_str()), String::Large(_)));
+        assert_eq!(std::string::String::from(String::from(long.clone())), long);
+
+        assert_eq!(short, "Low");
+        assert_eq!(short.len(), 3);
+        assert_eq!(format!("[{:>5}]", short), "[  Low]");
+        assert_eq!(String::default(), "");
+    }
+
+    #[test]
+    fn test_compare_and_serde() {
+        // Equal text in different variants is the same string
+        let tiny = String::from("Trust");
+        let large = String::Large("Trust".to_owned());
+        assert_eq!(tiny, large);
+        let mut sorted = [large.clone(), String::from("Low")];
+        sorted.sort();
+        assert_eq!(sorted[0], "Low");
+        let set: HashSet<String> = [tiny, large].into_iter().collect();
+        assert_eq!(set.len(), 1);
+        assert!(set.contains("Trust"));
+
+        let json = serde_json::to_string(&String::from("Canada")).unwrap();
+        assert_eq!(json, "\"Canada\"");
+        assert_eq!(serde_json::from_str::<String>(&json).unwrap(), "Canada");
+    }
+}
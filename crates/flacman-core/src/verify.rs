@@ -0,0 +1,347 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::coreerror::Result;
+
+
+/// Result of a single check on a downloaded item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    /// Accept, but with something worth telling the user
+    Note(String),
+    /// Refuse to import the item
+    Veto(String),
+}
+
+/// A check run on every downloaded item before it is imported
+///
+/// The item is the album directory (or single file) as it sits in the
+/// download/staging area.
+pub trait VerifyCheck {
+    fn name(&self) -> &str;
+    fn check(&self, item: &Path) -> Result<Verdict>;
+}
+
+/// Outcome of running the whole stage on one item
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Every check's verdict, in the order they ran
+    pub verdicts: Vec<(String, Verdict)>,
+}
+
+impl VerifyReport {
+    /// First veto, as `(check name, reason)`
+    pub fn veto(&self) -> Option<(&str, &str)> {
+        self.verdicts.iter().find_map(|(name, verdict)| match verdict {
+            Verdict::Veto(reason) => Some((name.as_str(), reason.as_str())),
+            _ => None,
+        })
+    }
+
+    pub fn is_vetoed(&self) -> bool {
+        self.veto().is_some()
+    }
+}
+
+/// The hook point between download and import
+#[derive(Default)]
+pub struct VerifyStage {
+    checks: Vec<Box<dyn VerifyCheck>>,
+}
+
+impl VerifyStage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_check<C: VerifyCheck + 'static>(mut self, check: C) -> Self {
+        self.checks.push(Box::new(check));
+        self
+    }
+
+    /// Add a [`ScriptCheck`] for every executable in `dir` (e.g. `~/.config/flacman/verify.d`),
+    /// in file name order
+    ///
+    /// A missing directory adds nothing.
+    pub fn with_script_dir(mut self, dir: &Path) -> Result<Self> {
+        if !dir.is_dir() {
+            return Ok(self);
+        }
+
+        let mut scripts: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .collect();
+        scripts.sort();
+
+        for script in scripts {
+            self.checks.push(Box::new(ScriptCheck::new(script)));
+        }
+
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    /// Run all checks on `item`
+    ///
+    /// Every check runs even after a veto, so the report shows all
+    /// problems at once. A check that fails to run counts as a veto.
+    pub fn run(&self, item: &Path) -> VerifyReport {
+        let mut report = VerifyReport::default();
+
+        for check in &self.checks {
+            let verdict = check
+                .check(item)
+                .unwrap_or_else(|e| Verdict::Veto(format!("check failed to run: {e}")));
+            report.verdicts.push((check.name().to_owned(), verdict));
+        }
+
+        report
+    }
+}
+
+/// Runs an external program with the item path as its only argument
+///
+/// Exit status 0 accepts; anything else vetoes, with the first line of
+/// the program's output (stdout, then stderr) as the reason. Useful for
+/// AV scans (`clamscan`) or site-specific rules.
+pub struct ScriptCheck {
+    name: String,
+    program: PathBuf,
+}
+
+impl ScriptCheck {
+    pub fn new<P: AsRef<Path>>(program: P) -> Self {
+        let program = program.as_ref().to_path_buf();
+        let name = program
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| program.display().to_string());
+
+        ScriptCheck { name, program }
+    }
+}
+
+impl VerifyCheck for ScriptCheck {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check(&self, item: &Path) -> Result<Verdict> {
+        let output = Command::new(&self.program).arg(item).output()?;
+
+        if output.status.success() {
+            return Ok(Verdict::Accept);
+        }
+
+        let first_line = |bytes: &[u8]| {
+            String::from_utf8_lossy(bytes)
+                .lines()
+                .map(str::trim)
+                .find(|l| !l.is_empty())
+                .map(str::to_owned)
+        };
+
+        let reason = first_line(&output.stdout)
+            .or_else(|| first_line(&output.stderr))
+            .unwrap_or_else(|| format!("exited with {}", output.status));
+
+        Ok(Verdict::Veto(reason))
+    }
+}
+
+/// Scores EAC/XLD rip logs found in the item and vetoes low scores
+///
+/// This is a rough approximation of tracker log checkers: start at 100
+/// and deduct for insecure read modes, disabled cache defeat, missing
+/// test passes, and reported read errors. Items without any log are
+/// accepted (most sources don't come with one).
+pub struct LogScoreCheck {
+    pub min_score: i32,
+}
+
+/// (needle, deduction, description); needles are matched case-insensitively
+const LOG_DEDUCTIONS: &[(&str, i32, &str)] = &[
+    ("suspicious position", 20, "suspicious positions"),
+    ("timing problem", 20, "timing problems"),
+    ("missing samples", 20, "missing samples"),
+    ("copy crc mismatch", 20, "copy CRC mismatch"),
+    ("read mode               : burst", 20, "burst read mode"),
+    ("read mode               : fast", 20, "fast read mode"),
+    ("defeat audio cache      : no", 10, "audio cache not defeated"),
+    ("use c2 error pointers   : yes", 10, "C2 pointers used"),
+    ("null samples used in crc calculations       : no", 5, "null samples excluded from CRC"),
+];
+
+/// Decode a log file, which EAC writes as UTF-16LE with a BOM
+fn read_log(path: &Path) -> Result<String> {
+    let bytes = fs::read(path)?;
+
+    if let [0xFF, 0xFE, rest @ ..] = bytes.as_slice() {
+        let units: Vec<u16> = rest.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        return Ok(String::from_utf16_lossy(&units));
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Score the text of a single log
+///
+/// # Returns
+/// `(score, reasons for deductions)`
+pub fn score_log(text: &str) -> (i32, Vec<String>) {
+    let lower = text.to_lowercase();
+    let mut score = 100;
+    let mut reasons = Vec::new();
+
+    for (needle, deduction, description) in LOG_DEDUCTIONS {
+        if lower.contains(needle) {
+            score -= deduction;
+            reasons.push(format!("{description} (-{deduction})"));
+        }
+    }
+
+    // A copy CRC without a test CRC means the rip was never verified
+    if lower.contains("copy crc") && !lower.contains("test crc") {
+        score -= 10;
+        reasons.push("no test pass (-10)".to_owned());
+    }
+
+    (score, reasons)
+}
+
+impl VerifyCheck for LogScoreCheck {
+    fn name(&self) -> &str {
+        "log-score"
+    }
+
+    fn check(&self, item: &Path) -> Result<Verdict> {
+        let logs: Vec<PathBuf> = if item.is_dir() {
+            fs::read_dir(item)?
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("log")))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut worst: Option<(i32, Vec<String>, PathBuf)> = None;
+        for log in logs {
+            let (score, reasons) = score_log(&read_log(&log)?);
+            if worst.as_ref().is_none_or(|(w, _, _)| score < *w) {
+                worst = Some((score, reasons, log));
+            }
+        }
+
+        Ok(match worst {
+            None => Verdict::Accept,
+            Some((score, _, _)) if score >= self.min_score => Verdict::Note(format!("log score {score}")),
+            Some((score, reasons, log)) => Verdict::Veto(format!(
+                "{} scores {} < {}: {}",
+                log.file_name().unwrap_or_default().to_string_lossy(),
+                score,
+                self.min_score,
+                reasons.join(", ")
+            )),
+        })
+    }
+}
+
+/// Renders a spectrogram PNG per FLAC with `sox` for manual inspection
+///
+/// Never vetoes on its own; the images land in `output_dir` so a
+/// transcode posing as lossless can be spotted before import.
+pub struct SpectrogramCheck {
+    pub output_dir: PathBuf,
+}
+
+impl VerifyCheck for SpectrogramCheck {
+    fn name(&self) -> &str {
+        "spectrogram"
+    }
+
+    fn check(&self, item: &Path) -> Result<Verdict> {
+        let files: Vec<PathBuf> = if item.is_dir() {
+            fs::read_dir(item)?.filter_map(|e| e.ok()).map(|e| e.path()).collect()
+        } else {
+            vec![item.to_path_buf()]
+        };
+
+        fs::create_dir_all(&self.output_dir)?;
+        let mut written = 0;
+
+        for file in files.iter().filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("flac"))) {
+            let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+            let out = self.output_dir.join(format!("{stem}.png"));
+
+            let status = Command::new("sox")
+                .arg(file)
+                .args(["-n", "remix", "1", "spectrogram", "-t"])
+                .arg(&*stem)
+                .arg("-o")
+                .arg(&out)
+                .status()?;
+
+            if status.success() {
+                written += 1;
+            }
+        }
+
+        Ok(Verdict::Note(format!(
+            "{} spectrogram(s) in {}",
+            written,
+            self.output_dir.display()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(&'static str, Verdict);
+
+    impl VerifyCheck for Fixed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn check(&self, _item: &Path) -> Result<Verdict> {
+            Ok(self.1.clone())
+        }
+    }
+
+    #[test]
+    fn test_stage_reports_first_veto() {
+        let stage = VerifyStage::new()
+            .with_check(Fixed("ok", Verdict::Accept))
+            .with_check(Fixed("av", Verdict::Veto("infected".into())))
+            .with_check(Fixed("late", Verdict::Veto("also bad".into())));
+
+        let report = stage.run(Path::new("."));
+        assert_eq!(report.verdicts.len(), 3);
+        assert_eq!(report.veto(), Some(("av", "infected")));
+    }
+
+    #[test]
+    fn test_score_clean_log() {
+        let log = "Read mode               : Secure\nDefeat audio cache      : Yes\n\
+                   Test CRC 1234ABCD\nCopy CRC 1234ABCD\nAccurately ripped";
+        assert_eq!(score_log(log).0, 100);
+    }
+
+    #[test]
+    fn test_score_bad_log() {
+        let log = "Read mode               : Burst\nCopy CRC 1234ABCD\nSuspicious position 0:01:02";
+        let (score, reasons) = score_log(log);
+        assert_eq!(score, 50);
+        assert_eq!(reasons.len(), 3);
+    }
+}
@@ -0,0 +1,282 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use tracing::{debug, instrument};
+use walkdir::WalkDir;
+use zip::ZipArchive;
+
+use crate::fd::walkdir;
+use crate::fserror::{FsError, Result};
+
+
+/// Archive formats albums arrive in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    /// Bandcamp and most other stores; extracted without outside tools
+    Zip,
+    /// Extracted with `7z`, `7za` or `bsdtar`
+    SevenZip,
+    /// Extracted with `unrar`, `7z` or `bsdtar`
+    Rar,
+}
+
+impl ArchiveKind {
+    /// The kind of archive `path` is, by extension
+    pub fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "zip" => Some(ArchiveKind::Zip),
+            "7z" => Some(ArchiveKind::SevenZip),
+            "rar" => Some(ArchiveKind::Rar),
+            _ => None,
+        }
+    }
+
+    /// Programs that can extract this kind, in order of preference, each
+    /// with how to list `archive` and extract it into `dest`
+    fn extractors(&self, archive: &Path, dest: &Path) -> Vec<Extractor> {
+        let (archive, dest) = (archive.display().to_string(), dest.display().to_string());
+        let seven_zip = |program| Extractor {
+            program,
+            list: vec!["l".into(), "-slt".into(), archive.clone()],
+            entries: seven_zip_entries,
+            extract: vec!["x".into(), "-y".into(), format!("-o{dest}"), archive.clone()],
+        };
+        let bsdtar = Extractor {
+            program: "bsdtar",
+            list: vec!["-tf".into(), archive.clone()],
+            entries: line_entries,
+            extract: vec!["-xf".into(), archive.clone(), "-C".into(), dest.clone()],
+        };
+
+        match self {
+            ArchiveKind::Zip => Vec::new(),
+            ArchiveKind::SevenZip => vec![seven_zip("7z"), seven_zip("7za"), bsdtar],
+            ArchiveKind::Rar => {
+                let unrar = Extractor {
+                    program: "unrar",
+                    list: vec!["lb".into(), archive.clone()],
+                    entries: line_entries,
+                    extract: vec!["x".into(), "-o+".into(), archive.clone(), format!("{dest}/")],
+                };
+                vec![unrar, seven_zip("7z"), bsdtar]
+            }
+        }
+    }
+}
+
+/// An outside program that extracts archives
+struct Extractor {
+    program: &'static str,
+    /// Arguments that list the archive's entries
+    list: Vec<String>,
+    /// The entry names in what `list` prints
+    entries: fn(&str) -> Vec<String>,
+    /// Arguments that extract the archive
+    extract: Vec<String>,
+}
+
+/// Entry names in `7z l -slt` output: the `Path = ` lines after the
+/// `----------` that ends the archive's own properties
+fn seven_zip_entries(listing: &str) -> Vec<String> {
+    let entries = listing.split_once("\n----------").map_or("", |(_, entries)| entries);
+    entries.lines().filter_map(|line| line.strip_prefix("Path = ")).map(str::to_owned).collect()
+}
+
+/// Entry names listed one per line
+fn line_entries(listing: &str) -> Vec<String> {
+    listing.lines().filter(|line| !line.is_empty()).map(str::to_owned).collect()
+}
+
+/// Whether the entry `name` would be written outside the directory it is
+/// extracted into: absolute, with a drive letter, or climbing out with `..`
+fn escapes(name: &str) -> bool {
+    let drive = name.as_bytes().get(1) == Some(&b':');
+    name.starts_with(['/', '\\']) || drive || name.split(['/', '\\']).any(|part| part == "..")
+}
+
+/// Check the entries `extractor` lists in `archive`, then have it extract
+/// them
+///
+/// # Returns
+/// `false` if the program isn't installed
+fn run_extractor(extractor: &Extractor, archive: &Path) -> Result<bool> {
+    let failed = |reason: String| FsError::Archive(archive.to_path_buf(), reason);
+    let program = extractor.program;
+    let listing = match Command::new(program).args(&extractor.list).stderr(Stdio::null()).output() {
+        Ok(output) if output.status.success() => output.stdout,
+        Ok(output) => return Err(failed(format!("{program} could not list it ({})", output.status))),
+        Err(e) => {
+            debug!(program, error = %e, "could not run extractor");
+            return Ok(false);
+        }
+    };
+    if let Some(name) = (extractor.entries)(&String::from_utf8_lossy(&listing)).into_iter().find(|n| escapes(n)) {
+        return Err(failed(format!("entry outside the destination: {name}")));
+    }
+
+    let status = Command::new(program).args(&extractor.extract).stdout(Stdio::null()).stderr(Stdio::null()).status()?;
+    if !status.success() {
+        return Err(failed(format!("{program} failed ({status})")));
+    }
+    Ok(true)
+}
+
+/// Extract `archive` into `dest`, which is created if needed
+///
+/// Entries that would land outside `dest` are refused: names that are
+/// absolute or climb out with `..` before anything is extracted, and
+/// symlinks, which could point anywhere, once it is. Everything is
+/// extracted into a scratch directory first and only moved into `dest`
+/// when it passes.
+///
+/// # Returns
+/// Every file extracted, sorted
+///
+/// # Errors
+/// * `FsError::Archive` - The archive isn't a known kind, is damaged, has
+///   an entry outside `dest`, or no program that can extract it is
+///   installed
+/// * `FsError::Io` - Failed to write the extracted files
+#[instrument(
+    level = "debug",
+    skip_all,
+    fields(archive = %archive.as_ref().display(), dest = %dest.as_ref().display()),
+    err(level = "debug")
+)]
+pub fn extract_archive<P: AsRef<Path>, Q: AsRef<Path>>(archive: P, dest: Q) -> Result<Vec<PathBuf>> {
+    let (archive, dest) = (archive.as_ref(), dest.as_ref());
+    let kind = ArchiveKind::of(archive)
+        .ok_or_else(|| FsError::Archive(archive.to_path_buf(), "not a zip, 7z or rar archive".to_owned()))?;
+    fs::create_dir_all(dest)?;
+    let scratch = tempfile::Builder::new().prefix(".flacman-extract-").tempdir_in(dest)?;
+    extract_with(archive, kind, &kind.extractors(archive, scratch.path()), scratch.path())?;
+
+    for entry in fs::read_dir(scratch.path())? {
+        let entry = entry?;
+        fs::rename(entry.path(), dest.join(entry.file_name()))?;
+    }
+    drop(scratch);
+
+    let mut files = walkdir(dest)?.collect::<Result<Vec<PathBuf>>>()?;
+    files.sort();
+
+    Ok(files)
+}
+
+/// Extract `archive` into the empty directory `scratch` with the first of
+/// `extractors` that is installed, refusing entries that escape it
+fn extract_with(archive: &Path, kind: ArchiveKind, extractors: &[Extractor], scratch: &Path) -> Result<()> {
+    let failed = |reason: String| FsError::Archive(archive.to_path_buf(), reason);
+
+    if kind == ArchiveKind::Zip {
+        let mut zip = ZipArchive::new(File::open(archive)?).map_err(|e| failed(e.to_string()))?;
+        zip.extract(scratch).map_err(|e| failed(e.to_string()))?;
+    } else {
+        let mut tried = Vec::new();
+        let mut extracted = false;
+        for extractor in extractors {
+            if run_extractor(extractor, archive)? {
+                extracted = true;
+                break;
+            }
+            tried.push(extractor.program);
+        }
+        if !extracted {
+            return Err(failed(format!("none of {} is installed", tried.join(", "))));
+        }
+    }
+
+    for entry in WalkDir::new(scratch).min_depth(1) {
+        let entry = entry?;
+        if entry.path_is_symlink() {
+            let name = entry.path().strip_prefix(scratch).unwrap_or(entry.path());
+            return Err(failed(format!("entry outside the destination: symlink {}", name.display())));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+    use zip::write::SimpleFileOptions;
+
+    fn write_zip(path: &Path, names: &[&str]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for name in names {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(b"data").unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("Low - Trust.zip");
+        write_zip(&archive, &["Low - Trust - 01 Canada.flac", "cover.jpg"]);
+
+        let dest = dir.path().join("extracted");
+        let files = extract_archive(&archive, &dest).unwrap();
+        assert_eq!(files, [dest.join("Low - Trust - 01 Canada.flac"), dest.join("cover.jpg")]);
+
+        let evil = dir.path().join("evil.zip");
+        write_zip(&evil, &["../escape.flac"]);
+        assert!(matches!(extract_archive(&evil, dir.path().join("evil")), Err(FsError::Archive(..))));
+        assert!(!dir.path().join("escape.flac").exists());
+
+        assert!(matches!(extract_archive(dir.path().join("album.tar"), &dest), Err(FsError::Archive(..))));
+    }
+
+    /// A stand-in for 7z and friends: a shell script run to list, then to
+    /// extract into `scratch`
+    fn fake(listing: &str, extract: String) -> Extractor {
+        Extractor {
+            program: "sh",
+            list: vec!["-c".into(), format!("printf '{listing}'")],
+            entries: line_entries,
+            extract: vec!["-c".into(), extract],
+        }
+    }
+
+    #[test]
+    fn test_extractor_entries_checked() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("album.7z");
+        let scratch = dir.path().join("scratch");
+        fs::create_dir(&scratch).unwrap();
+        let extract = |extractor: Extractor| extract_with(&archive, ArchiveKind::SevenZip, &[extractor], &scratch);
+
+        let album = fake("01.flac\\n", format!("touch '{}/01.flac'", scratch.display()));
+        extract(album).unwrap();
+        assert!(scratch.join("01.flac").exists());
+
+        // Refused before the extractor writes anything
+        let climbing = fake("../escape.flac\\n", format!("touch '{}/../escape.flac'", scratch.display()));
+        assert!(matches!(extract(climbing), Err(FsError::Archive(..))));
+        assert!(!dir.path().join("escape.flac").exists());
+
+        let link = fake("link.flac\\n", format!("ln -s /etc/passwd '{}/link.flac'", scratch.display()));
+        assert!(matches!(extract(link), Err(FsError::Archive(..))));
+
+        let missing = Extractor { program: "flacman-no-such-extractor", ..fake("", String::new()) };
+        assert!(matches!(extract(missing), Err(FsError::Archive(_, reason)) if reason.contains("installed")));
+    }
+
+    #[test]
+    fn test_escaping_names() {
+        for name in ["../x.flac", "a/../../x.flac", "/etc/passwd", "C:\\x.flac", "a\\..\\..\\x.flac"] {
+            assert!(escapes(name), "{name}");
+        }
+        for name in ["Low/01 Canada.flac", "..hidden.flac", "a..b/c.flac"] {
+            assert!(!escapes(name), "{name}");
+        }
+
+        let listing = "Path = album.7z\nType = 7z\n\n----------\nPath = Low/01.flac\nSize = 4\n\nPath = ../x.flac\n";
+        assert_eq!(seven_zip_entries(listing), ["Low/01.flac", "../x.flac"]);
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs::{self, File};
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+
+use crate::fserror::Result;
+use crate::FsError;
+
+
+/// Check if two paths are the same file (hardlinks of each other)
+pub fn same_file<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> Result<bool> {
+    let (a, b) = (fs::metadata(a.as_ref())?, fs::metadata(b.as_ref())?);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(a.dev() == b.dev() && a.ino() == b.ino())
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        Ok(false)
+    }
+}
+
+/// Compare two files byte for byte
+pub fn identical_contents<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> Result<bool> {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+
+    let mut a = BufReader::new(File::open(a)?);
+    let mut b = BufReader::new(File::open(b)?);
+    let mut buf_a = vec![0u8; 64 * 1024];
+    let mut buf_b = vec![0u8; 64 * 1024];
+
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Replace `duplicate` with a hardlink to `keep`
+///
+/// The contents are compared byte for byte first. The link is created
+/// under a temporary name and renamed over `duplicate`, so `duplicate`
+/// never goes missing, even if flacman is interrupted.
+///
+/// # Arguments
+/// * `keep` - File to keep
+/// * `duplicate` - Identical file to replace with a link to `keep`
+///
+/// # Returns
+/// Bytes reclaimed; 0 if the two are already the same file
+///
+/// # Errors
+/// * `FsError::NotIdentical` - The files differ
+/// * `FsError::CrossDevice` - The files are on different filesystems
+pub fn replace_with_hardlink<P: AsRef<Path>, Q: AsRef<Path>>(keep: P, duplicate: Q) -> Result<u64> {
+    let (keep, duplicate) = (keep.as_ref(), duplicate.as_ref());
+
+    if same_file(keep, duplicate)? {
+        return Ok(0);
+    }
+
+    if !identical_contents(keep, duplicate)? {
+        return Err(FsError::NotIdentical(keep.to_path_buf(), duplicate.to_path_buf()));
+    }
+
+    let size = fs::metadata(duplicate)?.len();
+    let mut tmp_name = duplicate.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".flacman-link");
+    let tmp = duplicate.with_file_name(tmp_name);
+
+    match fs::hard_link(keep, &tmp) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            return Err(FsError::CrossDevice(duplicate.to_path_buf()));
+        }
+        Err(e) => return Err(FsError::Io(e)),
+    }
+
+    if let Err(e) = fs::rename(&tmp, duplicate) {
+        let _ = fs::remove_file(&tmp);
+        return Err(FsError::Io(e));
+    }
+
+    Ok(size)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_replace_with_hardlink() {
+        let dir = tempdir().unwrap();
+        let (a, b, c) = (dir.path().join("a.flac"), dir.path().join("b.flac"), dir.path().join("c.flac"));
+        fs::write(&a, b"same audio").unwrap();
+        fs::write(&b, b"same audio").unwrap();
+        fs::write(&c, b"other file").unwrap();
+
+        assert_eq!(replace_with_hardlink(&a, &b).unwrap(), 10);
+        assert!(same_file(&a, &b).unwrap());
+        assert_eq!(replace_with_hardlink(&a, &b).unwrap(), 0);
+
+        assert!(matches!(replace_with_hardlink(&a, &c), Err(FsError::NotIdentical(..))));
+        assert_eq!(fs::read(&c).unwrap(), b"other file");
+    }
+}
@@ -1,5 +1,12 @@
+use std::collections::HashMap;
+use std::fs;
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use std::time::SystemTime;
+use rayon::prelude::*;
+use globset::GlobBuilder;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use tracing::debug;
+use walkdir::{DirEntry, WalkDir};
 
 use crate::{fserror::Result, FsError};
 
@@ -51,13 +58,161 @@ pub fn walkdir<P: AsRef<Path>>(
     Ok(iter)
 }
 
+/// Name of the files, in gitignore syntax, that leave paths out of a walk
+pub const IGNORE_FILE: &str = ".flacmanignore";
+
+/// How [`walkdir_with`] walks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalkOptions {
+    /// Levels to descend below the root; `Some(1)` lists only the files
+    /// directly in it
+    pub max_depth: Option<usize>,
+    /// Follow symlinks to files and directories instead of leaving them
+    /// out; a link back to a directory it is in is reported as an error
+    /// and not followed
+    pub follow_symlinks: bool,
+    /// Leave out files and directories whose names start with a dot
+    pub skip_hidden: bool,
+    /// Leave out what the [`IGNORE_FILE`]s match, each applying to its
+    /// own directory and below, the nearest one winning
+    pub use_ignore_files: bool,
+}
+
+/// A file found by [`walkdir_with`], with the metadata the walk read for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    /// `None` where the platform doesn't record it
+    pub modified: Option<SystemTime>,
+}
+
+/// Walk directory as `options` say, yielding files as they are found
+///
+/// Directories are read one at a time as the iterator is advanced, so a
+/// walk can stop early without reading the rest of the tree.
+///
+/// # Errors
+/// * `FsError::NotFound` - Path doesn't exist
+/// * `FsError::NotADirectory` - Path is a file, not a directory
+/// * Iterator items may contain `FsError::WalkDir` for errors during
+///   traversal, including symlink loops
+pub fn walkdir_with<P: AsRef<Path>>(
+    path: P,
+    options: &WalkOptions,
+) -> Result<impl Iterator<Item = Result<WalkEntry>> + use<P>> {
+    let walk_path: &Path = path.as_ref();
+
+    if !walk_path.exists() {
+        return Err(FsError::NotFound(walk_path.to_path_buf()));
+    }
+
+    if walk_path.is_file() {
+        return Err(FsError::NotADirectory(walk_path.to_path_buf()));
+    }
+
+    let mut walker = WalkDir::new(walk_path).follow_links(options.follow_symlinks);
+    if let Some(depth) = options.max_depth {
+        walker = walker.max_depth(depth);
+    }
+
+    let options = *options;
+    let mut ignores: HashMap<PathBuf, Option<Gitignore>> = HashMap::new();
+    let iter = walker
+        .into_iter()
+        .filter_entry(move |entry| {
+            if entry.depth() == 0 {
+                return true;
+            }
+            if options.skip_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+                return false;
+            }
+            !(options.use_ignore_files && entry_ignored(&mut ignores, entry))
+        })
+        .filter_map(|entry_result| match entry_result {
+            Ok(entry) if entry.file_type().is_file() => Some(
+                entry
+                    .metadata()
+                    .map(|metadata| WalkEntry {
+                        path: entry.into_path(),
+                        size: metadata.len(),
+                        modified: metadata.modified().ok(),
+                    })
+                    .map_err(FsError::WalkDir),
+            ),
+            Ok(_) => None,
+            Err(e) => Some(Err(FsError::WalkDir(e))),
+        });
+
+    Ok(iter)
+}
+
+/// The [`IGNORE_FILE`] of `dir`, if it has one
+///
+/// Malformed lines are skipped, as git does.
+fn load_ignore_file(dir: &Path) -> Option<Gitignore> {
+    let path = dir.join(IGNORE_FILE);
+    if !path.is_file() {
+        return None;
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(error) = builder.add(&path) {
+        debug!(path = %path.display(), %error, "skipped part of an ignore file");
+    }
+    builder.build().map_err(|error| debug!(path = %path.display(), %error, "unreadable ignore file")).ok()
+}
+
+/// Whether the nearest of `ignores` (outermost first) with an opinion on
+/// `path` leaves it out
+fn is_ignored<'a>(ignores: impl DoubleEndedIterator<Item = &'a Gitignore>, path: &Path, is_dir: bool) -> bool {
+    ignores.rev().map(|ignore| ignore.matched(path, is_dir)).find(|m| !m.is_none()).is_some_and(|m| m.is_ignore())
+}
+
+/// Whether the ignore files of the directories `entry` is in leave it
+/// out, reading each directory's once
+fn entry_ignored(cache: &mut HashMap<PathBuf, Option<Gitignore>>, entry: &DirEntry) -> bool {
+    let dirs: Vec<&Path> = entry.path().ancestors().skip(1).take(entry.depth()).collect();
+    for dir in &dirs {
+        if !cache.contains_key(*dir) {
+            cache.insert(dir.to_path_buf(), load_ignore_file(dir));
+        }
+    }
+
+    let ignores = dirs.iter().rev().filter_map(|dir| cache[*dir].as_ref());
+    is_ignored(ignores, entry.path(), entry.file_type().is_dir())
+}
+
 /// Walk directory but silently skip errors (useful for user-facing operations)
 /// 
 /// Use this when you want to be permissive about filesystem errors
-/// (e.g., permission denied on some subdirectories)
+/// (e.g., permission denied on some subdirectories). Skipped entries are
+/// logged at debug level.
 pub fn walkdir_lenient<P: AsRef<Path>>(
     path: P,
-) -> Result<impl Iterator<Item = PathBuf>> {
+    options: &WalkOptions,
+) -> Result<impl Iterator<Item = WalkEntry>> {
+    let iter = walkdir_with(path, options)?
+        .filter_map(|e| e.map_err(|error| debug!(%error, "skipped unreadable entry")).ok());
+
+    Ok(iter)
+}
+
+/// Walk directory on rayon's thread pool, reading subdirectories in parallel
+///
+/// Worth it on network storage, where every directory read waits on the
+/// server. Entries of each directory are visited in name order, so the
+/// result is the same from run to run. What [`IGNORE_FILE`]s match is
+/// left out.
+///
+/// # Returns
+/// Every file under `path`, or the error for each entry that couldn't be read
+///
+/// # Errors
+/// * `FsError::NotFound` - Path doesn't exist
+/// * `FsError::NotADirectory` - Path is a file, not a directory
+/// * Items may contain `FsError::Io` for errors during traversal
+pub fn walkdir_par<P: AsRef<Path>>(path: P) -> Result<Vec<Result<PathBuf>>> {
     let walk_path: &Path = path.as_ref();
 
     if !walk_path.exists() {
@@ -68,13 +223,38 @@ pub fn walkdir_lenient<P: AsRef<Path>>(
         return Err(FsError::NotADirectory(walk_path.to_path_buf()));
     }
 
-    let iter = WalkDir::new(walk_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .map(|e| e.path().to_path_buf());
+    Ok(walk_par(walk_path, &[]))
+}
 
-    Ok(iter)
+/// Files under `dir`, leaving out what the ignore files of it and of the
+/// directories it is in, `ignores`, match
+fn walk_par(dir: &Path, ignores: &[Gitignore]) -> Vec<Result<PathBuf>> {
+    let mut entries = match fs::read_dir(dir) {
+        Ok(entries) => entries.collect::<Vec<_>>(),
+        Err(e) => return vec![Err(e.into())],
+    };
+    entries.sort_by_key(|entry| entry.as_ref().ok().map(|e| e.file_name()));
+
+    let mut ignores = ignores.to_vec();
+    ignores.extend(load_ignore_file(dir));
+
+    entries
+        .into_par_iter()
+        .flat_map_iter(|entry| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => return vec![Err(e.into())],
+            };
+            // Like walkdir, symlinks are neither followed nor listed
+            match entry.file_type() {
+                Ok(kind) if is_ignored(ignores.iter(), &entry.path(), kind.is_dir()) => Vec::new(),
+                Ok(kind) if kind.is_dir() => walk_par(&entry.path(), &ignores),
+                Ok(kind) if kind.is_file() => vec![Ok(entry.path())],
+                Ok(_) => Vec::new(),
+                Err(e) => vec![Err(e.into())],
+            }
+        })
+        .collect()
 }
 
 pub fn find_match_one<P: AsRef<Path>>(
@@ -122,53 +302,208 @@ pub fn find_ext<P: AsRef<Path>>(
     for result in walkdir(search_path)? {
         let path = result?;
 
-        if let Some(ext) = path.extension() {
-            if ext.eq_ignore_ascii_case(target_extension) {
-                matches.push(path);
-            }
+        if let Some(ext) = path.extension()
+            && ext.eq_ignore_ascii_case(target_extension)
+        {
+            matches.push(path);
         }
     }
 
     Ok(matches)
 }
 
+/// How [`find_glob`] matches a pattern
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GlobOptions {
+    /// Letters match regardless of case
+    pub case_insensitive: bool,
+    /// The pattern has to match the whole path below the search root;
+    /// otherwise it may match any trailing part of it, as if it started
+    /// with `**/`
+    pub anchored: bool,
+}
+
+/// Find files whose path contains `pattern`, or matches it as a glob
+///
+/// A pattern with any of `*?[{` is a glob, matched as by [`find_glob`]
+/// with the default options; anything else is looked for as plain text
+/// in the whole path.
+///
+/// # Errors
+/// * `FsError::Pattern` - The glob is malformed
 pub fn find_pattern<P: AsRef<Path>>(
     search_path: P,
     pattern: &str,
 ) -> Result<Vec<PathBuf>> {
+    if pattern.contains(['*', '?', '[', '{']) {
+        return find_glob(search_path, pattern, GlobOptions::default());
+    }
+
     let mut matches = Vec::new();
 
     for result in walkdir(search_path)? {
         let path = result?;
 
-        if let Some(path_str) = path.to_str() {
-            if path_str.contains(pattern) {
-                matches.push(path);
-            }
+        if let Some(path_str) = path.to_str()
+            && path_str.contains(pattern)
+        {
+            matches.push(path);
         }
     }
 
     Ok(matches)
 }
 
+/// Find files matching a glob such as `**/*.flac` or
+/// `Albums/*/{01,02}*.mp3`
+///
+/// The glob is matched against paths relative to `search_path`, with `/`
+/// separating components on every platform. `*` and `?` stay within one
+/// component, `**` spans any number of them, and `[...]` and `{a,b}` work
+/// as in a shell.
+///
+/// # Errors
+/// * `FsError::Pattern` - The glob is malformed
+pub fn find_glob<P: AsRef<Path>>(
+    search_path: P,
+    pattern: &str,
+    options: GlobOptions,
+) -> Result<Vec<PathBuf>> {
+    let root = search_path.as_ref();
+    let full = if options.anchored { pattern.to_owned() } else { format!("**/{}", pattern.trim_start_matches('/')) };
+    let glob = GlobBuilder::new(&full)
+        .case_insensitive(options.case_insensitive)
+        .literal_separator(true)
+        .backslash_escape(true)
+        .build()
+        .map_err(|e| FsError::Pattern(pattern.to_owned(), e.kind().to_string()))?
+        .compile_matcher();
+
+    let mut matches = Vec::new();
+
+    for result in walkdir(root)? {
+        let path = result?;
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+
+        if glob.is_match(relative) {
+            matches.push(path);
+        }
+    }
+
+    Ok(matches)
+}
+
+const AUDIO_EXTS: &[&str] = &["flac", "mp3", "m4a", "m4b", "ogg", "opus", "wav", "aac", "wma"];
+
 /// Find all audio files in a directory
 /// 
-/// Searches for common audio file extensions (flac, mp3, m4a, ogg, opus, wav, aac, wma)
+/// Searches for common audio file extensions (flac, mp3, m4a, m4b, ogg, opus, wav, aac, wma),
+/// leaving out what [`IGNORE_FILE`]s match
 pub fn find_audio_files<P: AsRef<Path>>(search_path: P) -> Result<Vec<PathBuf>> {
-    const AUDIO_EXTS: &[&str] = &["flac", "mp3", "m4a", "ogg", "opus", "wav", "aac", "wma"];
-    
+    let options = WalkOptions { use_ignore_files: true, ..Default::default() };
     let mut matches = Vec::new();
 
-    for result in walkdir_lenient(search_path)? {
-        let path = result;
+    for entry in walkdir_lenient(search_path, &options)? {
+        if is_audio_file(&entry.path) {
+            matches.push(entry.path);
+        }
+    }
+
+    Ok(matches)
+}
 
-        if let Some(ext) = path.extension() {
-            if let Some(ext_str) = ext.to_str() {
-                if AUDIO_EXTS.contains(&ext_str.to_lowercase().as_str()) {
-                    matches.push(path);
-                }
+/// Parallel [`find_audio_files`], walking with [`walkdir_par`]
+///
+/// Unreadable entries are skipped and ignore files honored, as with
+/// `find_audio_files`.
+pub fn find_audio_files_par<P: AsRef<Path>>(search_path: P) -> Result<Vec<PathBuf>> {
+    let files = walkdir_par(search_path)?;
+    Ok(files.into_iter().filter_map(|f| f.ok()).filter(|f| is_audio_file(f)).collect())
+}
+
+/// Predicates for [`find_filtered`]; unset fields don't filter
+///
+/// ```ignore
+/// // Audio files under 1MB, probably truncated downloads
+/// let spec = FilterSpec { max_size: Some(1024 * 1024), extensions: audio_exts(), ..Default::default() };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FilterSpec {
+    /// Minimum file size in bytes (inclusive)
+    pub min_size: Option<u64>,
+    /// Maximum file size in bytes (inclusive)
+    pub max_size: Option<u64>,
+    /// Only files modified at or after this time
+    pub newer_than: Option<SystemTime>,
+    /// Only files modified before this time
+    pub older_than: Option<SystemTime>,
+    /// Only these extensions (case-insensitive, without the dot); empty means any
+    pub extensions: Vec<String>,
+}
+
+impl FilterSpec {
+    fn matches(&self, entry: &WalkEntry) -> bool {
+        let (path, size) = (&entry.path, entry.size);
+
+        if self.min_size.is_some_and(|min| size < min) || self.max_size.is_some_and(|max| size > max) {
+            return false;
+        }
+
+        if self.newer_than.is_some() || self.older_than.is_some() {
+            let Some(modified) = entry.modified else {
+                return false;
+            };
+
+            if self.newer_than.is_some_and(|t| modified < t) || self.older_than.is_some_and(|t| modified >= t) {
+                return false;
             }
         }
+
+        if !self.extensions.is_empty() {
+            let Some(ext) = path.extension() else {
+                return false;
+            };
+
+            if !self.extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Whether `path` has one of the audio extensions [`find_audio_files`] looks for
+pub(crate) fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| AUDIO_EXTS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Extensions treated as audio by [`find_audio_files`], for use in a [`FilterSpec`]
+pub fn audio_exts() -> Vec<String> {
+    AUDIO_EXTS.iter().map(|e| e.to_string()).collect()
+}
+
+/// Find files matching size, modification time and extension predicates
+///
+/// # Arguments
+/// * `search_path` - Directory to search recursively
+/// * `spec` - Predicates every returned file satisfies
+///
+/// # Errors
+/// * `FsError::NotFound` - Path doesn't exist
+/// * `FsError::NotADirectory` - Path is a file, not a directory
+/// * `FsError::WalkDir` - Error during traversal
+pub fn find_filtered<P: AsRef<Path>>(search_path: P, spec: &FilterSpec) -> Result<Vec<PathBuf>> {
+    let mut matches = Vec::new();
+
+    for result in walkdir_with(search_path, &WalkOptions::default())? {
+        let entry = result?;
+
+        if spec.matches(&entry) {
+            matches.push(entry.path);
+        }
     }
 
     Ok(matches)
@@ -209,6 +544,75 @@ mod tests {
         assert_eq!(result.len(), 2);
     }
 
+    #[test]
+    fn test_find_audio_files_par() {
+        let dir = tempdir().unwrap();
+        for name in ["b/02.flac", "b/01.flac", "a/cover.jpg", "a/Disc 1/01.MP3", "00.opus"] {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+
+        let mut sequential = find_audio_files(dir.path()).unwrap();
+        sequential.sort();
+        assert_eq!(find_audio_files_par(dir.path()).unwrap(), sequential);
+        assert_eq!(walkdir_par(dir.path()).unwrap().len(), 5);
+        assert!(matches!(walkdir_par(dir.path().join("00.opus")), Err(FsError::NotADirectory(_))));
+    }
+
+    #[test]
+    fn test_walkdir_with_options() {
+        let dir = tempdir().unwrap();
+        for name in ["01.flac", "Low/Trust/01.flac", "Low/Trust/scans/front.png", "Low/.hidden/01.flac", ".sync/x.flac"] {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"audio").unwrap();
+        }
+        let found = |options: &WalkOptions| {
+            let mut found: Vec<String> = walkdir_with(dir.path(), options)
+                .unwrap()
+                .map(|e| e.unwrap().path.strip_prefix(dir.path()).unwrap().to_string_lossy().into_owned())
+                .collect();
+            found.sort();
+            found
+        };
+
+        assert_eq!(found(&WalkOptions::default()).len(), 5);
+        assert_eq!(found(&WalkOptions { max_depth: Some(1), ..Default::default() }), ["01.flac"]);
+        assert_eq!(found(&WalkOptions { skip_hidden: true, ..Default::default() }), ["01.flac", "Low/Trust/01.flac", "Low/Trust/scans/front.png"]);
+        let entry = walkdir_with(dir.path(), &WalkOptions { max_depth: Some(1), ..Default::default() }).unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.size, 5);
+        assert!(entry.modified.is_some());
+
+        // The nearest ignore file wins, and ignored directories aren't entered
+        fs::write(dir.path().join(IGNORE_FILE), "# not music\n*.png\n.sync/\n").unwrap();
+        fs::write(dir.path().join("Low").join(IGNORE_FILE), "!front.png\n/.hidden\n").unwrap();
+        let ignoring = WalkOptions { use_ignore_files: true, ..Default::default() };
+        assert_eq!(found(&ignoring), [IGNORE_FILE, "01.flac", "Low/.flacmanignore", "Low/Trust/01.flac", "Low/Trust/scans/front.png"]);
+        assert_eq!(find_audio_files_par(dir.path()).unwrap(), [dir.path().join("01.flac"), dir.path().join("Low/Trust/01.flac")]);
+        assert_eq!(find_audio_files(dir.path()).unwrap().len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walkdir_with_symlinks() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("Low/Trust")).unwrap();
+        fs::write(dir.path().join("Low/Trust/01.flac"), b"audio").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("Low/Trust"), dir.path().join("Favourites")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("Low/Trust/loop")).unwrap();
+
+        let walk = |follow_symlinks| {
+            walkdir_with(dir.path(), &WalkOptions { follow_symlinks, ..Default::default() }).unwrap().collect::<Vec<_>>()
+        };
+        assert_eq!(walk(false).len(), 1);
+
+        let followed = walk(true);
+        let files = followed.iter().filter_map(|e| e.as_ref().ok()).count();
+        let loops = followed.iter().filter(|e| matches!(e, Err(FsError::WalkDir(e)) if e.loop_ancestor().is_some())).count();
+        assert_eq!((files, loops), (2, 2));
+    }
+
     #[test]
     fn test_find_ext() {
         let dir = tempdir().unwrap();
@@ -220,4 +624,69 @@ mod tests {
         let result = find_ext(dir.path(), "flac").unwrap();
         assert_eq!(result.len(), 2); // Case-insensitive
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_find_glob() {
+        let dir = tempdir().unwrap();
+        for path in ["Albums/Low/01 Canada.flac", "Albums/Low/02 Candy Girl.MP3", "Albums/Low/03 Time.mp3", "01.flac"] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        let names = |found: Vec<PathBuf>| {
+            let mut names: Vec<String> =
+                found.iter().map(|p| p.file_name().unwrap().to_string_lossy().into_owned()).collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(names(find_pattern(dir.path(), "**/*.flac").unwrap()), ["01 Canada.flac", "01.flac"]);
+        assert_eq!(names(find_pattern(dir.path(), "Albums/*/{01,02}*.mp3").unwrap()), Vec::<String>::new());
+        let insensitive = GlobOptions { case_insensitive: true, ..Default::default() };
+        assert_eq!(
+            names(find_glob(dir.path(), "Albums/*/{01,02}*.mp3", insensitive).unwrap()),
+            ["02 Candy Girl.MP3"]
+        );
+
+        let anchored = GlobOptions { anchored: true, ..Default::default() };
+        assert_eq!(names(find_glob(dir.path(), "*.flac", anchored).unwrap()), ["01.flac"]);
+        assert_eq!(names(find_glob(dir.path(), "*.flac", GlobOptions::default()).unwrap()).len(), 2);
+        assert!(matches!(find_pattern(dir.path(), "Albums/{01"), Err(FsError::Pattern(..))));
+        assert_eq!(names(find_pattern(dir.path(), "Candy").unwrap()), ["02 Candy Girl.MP3"]);
+    }
+
+    #[test]
+    fn test_find_filtered_size() {
+        let dir = tempdir().unwrap();
+
+        std::fs::write(dir.path().join("tiny.flac"), b"x").unwrap();
+        std::fs::write(dir.path().join("big.flac"), vec![0u8; 4096]).unwrap();
+        std::fs::write(dir.path().join("tiny.txt"), b"x").unwrap();
+
+        let spec = FilterSpec { max_size: Some(1024), extensions: audio_exts(), ..Default::default() };
+        let result = find_filtered(dir.path(), &spec).unwrap();
+        assert_eq!(result, vec![dir.path().join("tiny.flac")]);
+
+        let spec = FilterSpec { min_size: Some(1024), ..Default::default() };
+        let result = find_filtered(dir.path(), &spec).unwrap();
+        assert_eq!(result, vec![dir.path().join("big.flac")]);
+    }
+
+    #[test]
+    fn test_find_filtered_mtime() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("song.flac")).unwrap();
+
+        let past = SystemTime::now() - std::time::Duration::from_secs(3600);
+        let future = SystemTime::now() + std::time::Duration::from_secs(3600);
+
+        let newer = FilterSpec { newer_than: Some(past), ..Default::default() };
+        assert_eq!(find_filtered(dir.path(), &newer).unwrap().len(), 1);
+
+        let too_new = FilterSpec { newer_than: Some(future), ..Default::default() };
+        assert!(find_filtered(dir.path(), &too_new).unwrap().is_empty());
+
+        let older = FilterSpec { older_than: Some(past), ..Default::default() };
+        assert!(find_filtered(dir.path(), &older).unwrap().is_empty());
+    }
+}
@@ -25,8 +25,48 @@ pub enum FsError {
     #[error("Path is not a directory: {0}")]
     NotADirectory(PathBuf),
 
+    #[error("Files are not identical: {0} and {1}")]
+    NotIdentical(PathBuf, PathBuf),
+
+    #[error("Cannot hardlink across filesystems: {0}")]
+    CrossDevice(PathBuf),
+
+    #[error("Name is reserved on Windows: {0}")]
+    ReservedName(PathBuf),
+
+    #[error("Cannot extract {0}: {1}")]
+    Archive(PathBuf, String),
+
+    #[error("Checksum of {1} doesn't match {0} after copying")]
+    ChecksumMismatch(PathBuf, PathBuf),
+
+    #[error("Bad pattern {0:?}: {1}")]
+    Pattern(String, String),
+
+    #[error("Cannot watch for changes: {0}")]
+    Watch(String),
+
+    #[error("{cause}; rolled back {} file(s){}", restored.len(), rollback_failures(stranded))]
+    RolledBack {
+        cause: Box<FsError>,
+        /// Sources of the transfers undone
+        restored: Vec<PathBuf>,
+        /// Destinations that could not be undone and are left in place
+        stranded: Vec<PathBuf>,
+    },
+
+    #[error("Cancelled")]
+    Cancelled,
+
     #[error("Error while walking directory")]
     WalkDir(#[from] walkdir::Error),
 }
 
+fn rollback_failures(stranded: &[PathBuf]) -> String {
+    match stranded.len() {
+        0 => String::new(),
+        n => format!(", {} could not be and are left in place", n),
+    }
+}
+
 pub type Result<T> = std::result::Result<T, FsError>;
\ No newline at end of file
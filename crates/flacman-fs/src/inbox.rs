@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use walkdir::WalkDir;
+
+use crate::fd::is_audio_file;
+use crate::fserror::Result;
+use crate::FsError;
+
+
+/// What a file looked like the last time it was polled
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    len: u64,
+    modified: Option<SystemTime>,
+    /// When `len`/`modified` were first seen with these values
+    since: Instant,
+}
+
+/// Polls "inbox" directories for audio files that have finished arriving
+///
+/// A file counts as complete once its size and modification time haven't
+/// changed for the debounce period, so half-copied downloads are left
+/// alone. Each complete file is reported once; a file that disappears and
+/// comes back is reported again. Hidden directories (such as the `.review`
+/// hold area) are not looked at.
+#[derive(Debug)]
+pub struct InboxWatcher {
+    dirs: Vec<PathBuf>,
+    debounce: Duration,
+    pending: HashMap<PathBuf, Snapshot>,
+    reported: HashSet<PathBuf>,
+}
+
+impl InboxWatcher {
+    /// # Errors
+    /// * `FsError::NotADirectory` - One of `dirs` isn't a directory
+    pub fn new<P: AsRef<Path>>(dirs: &[P], debounce: Duration) -> Result<Self> {
+        let dirs: Vec<PathBuf> = dirs.iter().map(|d| d.as_ref().to_path_buf()).collect();
+
+        if let Some(dir) = dirs.iter().find(|d| !d.is_dir()) {
+            return Err(FsError::NotADirectory(dir.clone()));
+        }
+
+        Ok(InboxWatcher { dirs, debounce, pending: HashMap::new(), reported: HashSet::new() })
+    }
+
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// Number of files seen but not complete yet
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Scan the inboxes once
+    ///
+    /// # Returns
+    /// Files that became complete since the last poll, grouped by the
+    /// directory they sit in (one group per album, as they are usually
+    /// dropped)
+    pub fn poll(&mut self) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+        let now = Instant::now();
+        let mut present = HashSet::new();
+        let mut ready: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+
+        for dir in &self.dirs {
+            let walker = WalkDir::new(dir)
+                .into_iter()
+                .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'));
+
+            for entry in walker.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                if !entry.file_type().is_file() || !is_audio_file(path) {
+                    continue;
+                }
+                let Ok(meta) = entry.metadata() else { continue };
+                present.insert(path.to_path_buf());
+
+                if self.reported.contains(path) {
+                    continue;
+                }
+
+                let (len, modified) = (meta.len(), meta.modified().ok());
+                let Some(snapshot) = self.pending.get_mut(path) else {
+                    self.pending.insert(path.to_path_buf(), Snapshot { len, modified, since: now });
+                    continue;
+                };
+                if snapshot.len != len || snapshot.modified != modified {
+                    *snapshot = Snapshot { len, modified, since: now };
+                    continue;
+                }
+
+                if now.duration_since(snapshot.since) >= self.debounce {
+                    self.pending.remove(path);
+                    self.reported.insert(path.to_path_buf());
+                    let parent = path.parent().unwrap_or(dir).to_path_buf();
+                    ready.entry(parent).or_default().push(path.to_path_buf());
+                }
+            }
+        }
+
+        self.pending.retain(|path, _| present.contains(path));
+        self.reported.retain(|path| present.contains(path));
+
+        for files in ready.values_mut() {
+            files.sort();
+        }
+
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_poll_debounces_and_reports_once() {
+        let dir = tempdir().unwrap();
+        let album = dir.path().join("Album");
+        fs::create_dir(&album).unwrap();
+        fs::create_dir(dir.path().join(".review")).unwrap();
+        fs::write(album.join("01.flac"), b"data").unwrap();
+        fs::write(album.join("cover.jpg"), b"jpeg").unwrap();
+        fs::write(dir.path().join(".review/02.flac"), b"held").unwrap();
+
+        let mut watcher = InboxWatcher::new(&[dir.path()], Duration::ZERO).unwrap();
+
+        // First sighting only starts the clock
+        assert!(watcher.poll().is_empty());
+
+        let ready = watcher.poll();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[&album], vec![album.join("01.flac")]);
+
+        assert!(watcher.poll().is_empty());
+
+        // A file that grows between polls isn't ready yet
+        fs::write(album.join("03.flac"), b"part").unwrap();
+        watcher.poll();
+        fs::write(album.join("03.flac"), b"partial download").unwrap();
+        assert!(watcher.poll().is_empty());
+        assert_eq!(watcher.poll()[&album], vec![album.join("03.flac")]);
+    }
+}
@@ -1,7 +1,35 @@
 mod fserror;
 mod fd;
 mod mv;
+mod rm;
+mod trash;
+mod plan;
+mod dedup;
+mod inbox;
+mod watch;
+mod transaction;
+mod platform;
+mod archive;
+mod sanitize;
+mod sidecar;
 
 pub use fserror::FsError;
-pub use fd::{walkdir, find_ext, find_match_all, find_match_one, find_pattern, find_audio_files};
-pub use mv::{copy_file, move_file, symlink_file, hardlink_file, transfer_file};
+pub use fd::{
+    walkdir, walkdir_par, walkdir_with, WalkEntry, WalkOptions, IGNORE_FILE, find_ext, find_match_all, find_match_one, find_pattern, find_glob, find_audio_files,
+    find_audio_files_par, find_filtered, audio_exts, FilterSpec, GlobOptions,
+};
+pub use mv::{
+    copy_file, copy_file_checked, move_file, move_file_checked, move_dir, symlink_file, hardlink_file, reflink_file,
+    sha256_file, transfer_file, transfer_file_checked, TransferMode,
+};
+pub use rm::{prune_empty_dirs, remove_file, remove_tree};
+pub use trash::{Trash, TrashEntry};
+pub use dedup::{identical_contents, replace_with_hardlink, same_file};
+pub use inbox::InboxWatcher;
+pub use watch::LibraryWatcher;
+pub use platform::{FsCapabilities, is_reserved_name, long_path, reflink, symlink, windows_safe_name};
+pub use plan::{ChangeKind, Plan, PlanEntry};
+pub use transaction::{FsTransaction, Transferred};
+pub use archive::{ArchiveKind, extract_archive};
+pub use sanitize::SanitizePolicy;
+pub use sidecar::SidecarPolicy;
@@ -1,7 +1,13 @@
-use std::fs;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use sha2::{Digest, Sha256};
+use tracing::{debug, instrument};
 
 use crate::fserror::Result;
+use crate::platform::{self, long_path};
 use crate::FsError;
 
 
@@ -16,7 +22,7 @@ fn validate_source(path: &Path) -> Result<()> {
     }
 
     // Check if readable
-    fs::metadata(path).map_err(|e| FsError::Io(e))?;
+    fs::metadata(path).map_err(FsError::Io)?;
 
     Ok(())
 }
@@ -24,22 +30,27 @@ fn validate_source(path: &Path) -> Result<()> {
 /// Check if destination is valid (parent exists, not same as source)
 fn validate_destination(source: &Path, dest: &Path, allow_overwrite: bool) -> Result<()> {
 
-    if let (Ok(src_canon), Ok(dst_canon)) = (fs::canonicalize(source), fs::canonicalize(dest)) {
-        if src_canon == dst_canon {
-            return Err(FsError::SameFile(dest.to_path_buf()));
-        }
+    if let (Ok(src_canon), Ok(dst_canon)) = (fs::canonicalize(source), fs::canonicalize(dest))
+        && src_canon == dst_canon
+    {
+        return Err(FsError::SameFile(dest.to_path_buf()));
     }
 
-    if let Some(parent) = dest.parent() {
-        if !parent.exists() {
-            return Err(FsError::NotFound(parent.to_path_buf()));
-        }
+    if let Some(parent) = dest.parent()
+        && !parent.exists()
+    {
+        return Err(FsError::NotFound(parent.to_path_buf()));
     }
 
     if dest.exists() && !allow_overwrite {
         return Err(FsError::AlreadyExists(dest.to_path_buf()));
     }
 
+    #[cfg(windows)]
+    if dest.file_name().is_some_and(|name| platform::is_reserved_name(&name.to_string_lossy())) {
+        return Err(FsError::ReservedName(dest.to_path_buf()));
+    }
+
     Ok(())
 }
 
@@ -54,6 +65,12 @@ fn validate_writable(path: &Path) -> Result<()> {
 }
 
 /// Copy file from source to destination
+///
+/// A new file is made a reflink of the source where the filesystem can
+/// share data blocks (Btrfs, XFS), so copying is nearly instant there.
+/// Otherwise `fs::copy` does the work, which already clones or copies in
+/// the kernel where it can (`copy_file_range` on Linux, `clonefile` on
+/// macOS).
 /// 
 /// # Arguments
 /// * `source` - Source file path
@@ -62,6 +79,12 @@ fn validate_writable(path: &Path) -> Result<()> {
 /// 
 /// # Returns
 /// The destination path on success
+#[instrument(
+    level = "debug",
+    skip_all,
+    fields(source = %source.as_ref().display(), dest = %dest.as_ref().display()),
+    err(level = "debug")
+)]
 pub fn copy_file<P: AsRef<Path>, Q: AsRef<Path>>(
     source: P,
     dest: Q,
@@ -75,9 +98,18 @@ pub fn copy_file<P: AsRef<Path>, Q: AsRef<Path>>(
 
     if overwrite && dst.exists() {
         validate_writable(dst)?;
+    } else {
+        match platform::reflink(&long_path(src), &long_path(dst)) {
+            Ok(()) => {
+                debug!("reflinked");
+                return Ok(dst.to_path_buf());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {}
+            Err(e) => return Err(FsError::Io(e)),
+        }
     }
 
-    fs::copy(src, dst)?;
+    fs::copy(long_path(src), long_path(dst))?;
 
     Ok(dst.to_path_buf())
 }
@@ -91,6 +123,12 @@ pub fn copy_file<P: AsRef<Path>, Q: AsRef<Path>>(
 /// 
 /// # Returns
 /// The destination path on success
+#[instrument(
+    level = "debug",
+    skip_all,
+    fields(source = %source.as_ref().display(), dest = %dest.as_ref().display()),
+    err(level = "debug")
+)]
 pub fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(
     source: P,
     dest: Q,
@@ -107,10 +145,11 @@ pub fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(
         fs::remove_file(dst)?;
     }
 
-    match fs::rename(src, dst) {
+    match fs::rename(long_path(src), long_path(dst)) {
         Ok(_) => Ok(dst.to_path_buf()),
         Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
-            fs::copy(src, dst)?;
+            debug!("rename crosses filesystems, copying instead");
+            fs::copy(long_path(src), long_path(dst))?;
             fs::remove_file(src)?;
             Ok(dst.to_path_buf())
         }
@@ -118,6 +157,105 @@ pub fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(
     }
 }
 
+/// SHA-256 of the contents of `path`, as lowercase hex
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut reader = BufReader::new(File::open(path.as_ref())?);
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher.finalize().iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Hash `src` and `dst` and fail if they differ, removing `dst`
+fn verify_copy(src: &Path, dst: &Path) -> Result<String> {
+    let expected = sha256_file(src)?;
+    if sha256_file(dst)? != expected {
+        let _ = fs::remove_file(dst);
+        return Err(FsError::ChecksumMismatch(src.to_path_buf(), dst.to_path_buf()));
+    }
+    Ok(expected)
+}
+
+/// Copy file from source to destination, then check that the copy reads
+/// back the same as the source
+///
+/// For libraries on network mounts, where a copy can come out damaged
+/// without any write failing.
+///
+/// # Arguments
+/// * `source` - Source file path
+/// * `dest` - Destination file path
+/// * `overwrite` - Whether to overwrite existing file
+///
+/// # Returns
+/// The destination path and the SHA-256 of its contents
+///
+/// # Errors
+/// * `FsError::ChecksumMismatch` - The copy differs from the source; it is
+///   removed again
+pub fn copy_file_checked<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    dest: Q,
+    overwrite: bool,
+) -> Result<(PathBuf, String)> {
+    let dst = copy_file(source.as_ref(), dest, overwrite)?;
+    let checksum = verify_copy(source.as_ref(), &dst)?;
+    Ok((dst, checksum))
+}
+
+/// Move file from source to destination, removing the source only once
+/// the destination reads back the same
+///
+/// A rename within one filesystem can't damage the contents, so only the
+/// destination is hashed then.
+///
+/// # Arguments
+/// * `source` - Source file path
+/// * `dest` - Destination file path
+/// * `overwrite` - Whether to overwrite existing file
+///
+/// # Returns
+/// The destination path and the SHA-256 of its contents
+///
+/// # Errors
+/// * `FsError::ChecksumMismatch` - The copy differs from the source; it is
+///   removed again and the source is left in place
+pub fn move_file_checked<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    dest: Q,
+    overwrite: bool,
+) -> Result<(PathBuf, String)> {
+    let src = source.as_ref();
+    let dst = dest.as_ref();
+
+    validate_source(src)?;
+    validate_destination(src, dst, overwrite)?;
+
+    if overwrite && dst.exists() {
+        validate_writable(dst)?;
+        fs::remove_file(dst)?;
+    }
+
+    match fs::rename(long_path(src), long_path(dst)) {
+        Ok(_) => Ok((dst.to_path_buf(), sha256_file(dst)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            fs::copy(long_path(src), long_path(dst))?;
+            let checksum = verify_copy(src, dst)?;
+            fs::remove_file(src)?;
+            Ok((dst.to_path_buf(), checksum))
+        }
+        Err(e) => Err(FsError::Io(e)),
+    }
+}
+
 /// Create a symbolic link
 /// 
 /// # Arguments
@@ -129,7 +267,7 @@ pub fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(
 /// The destination path on success
 /// 
 /// # Note
-/// Unix-only
+/// On Windows this needs Developer Mode or administrator rights
 /// 
 pub fn symlink_file<P: AsRef<Path>, Q: AsRef<Path>>(
     source: P,
@@ -141,10 +279,10 @@ pub fn symlink_file<P: AsRef<Path>, Q: AsRef<Path>>(
 
     validate_source(src)?;
 
-    if let Some(parent) = dst.parent() {
-        if !parent.exists() {
-            return Err(FsError::NotFound(parent.to_path_buf()));
-        }
+    if let Some(parent) = dst.parent()
+        && !parent.exists()
+    {
+        return Err(FsError::NotFound(parent.to_path_buf()));
     }
 
     if dst.exists() {
@@ -155,7 +293,7 @@ pub fn symlink_file<P: AsRef<Path>, Q: AsRef<Path>>(
         fs::remove_file(dst)?;
     }
 
-    std::os::unix::fs::symlink(src, dst)?;
+    platform::symlink(src, &long_path(dst))?;
 
     Ok(dst.to_path_buf())
 }
@@ -183,10 +321,10 @@ pub fn hardlink_file<P: AsRef<Path>, Q: AsRef<Path>>(
 
     validate_source(src)?;
 
-    if let Some(parent) = dst.parent() {
-        if !parent.exists() {
-            return Err(FsError::NotFound(parent.to_path_buf()));
-        }
+    if let Some(parent) = dst.parent()
+        && !parent.exists()
+    {
+        return Err(FsError::NotFound(parent.to_path_buf()));
     }
 
     if dst.exists() {
@@ -197,11 +335,155 @@ pub fn hardlink_file<P: AsRef<Path>, Q: AsRef<Path>>(
         fs::remove_file(dst)?;
     }
 
-    fs::hard_link(src, dst)?;
+    fs::hard_link(long_path(src), long_path(dst))?;
+
+    Ok(dst.to_path_buf())
+}
+
+/// Create a reflink: a copy sharing the source's data blocks until either
+/// is changed
+///
+/// # Arguments
+/// * `source` - Source file path
+/// * `dest` - Destination file path
+/// * `overwrite` - Whether to overwrite existing file
+///
+/// # Returns
+/// The destination path on success
+///
+/// # Errors
+/// * `FsError::Io` - With `ErrorKind::Unsupported` where the filesystem
+///   can't share blocks or the files are on different filesystems; use
+///   [`copy_file`] to fall back to a plain copy
+pub fn reflink_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    dest: Q,
+    overwrite: bool,
+) -> Result<PathBuf> {
+    let src = source.as_ref();
+    let dst = dest.as_ref();
+
+    validate_source(src)?;
+    validate_destination(src, dst, overwrite)?;
+
+    if overwrite && dst.exists() {
+        validate_writable(dst)?;
+        fs::remove_file(dst)?;
+    }
+
+    platform::reflink(&long_path(src), &long_path(dst))?;
 
     Ok(dst.to_path_buf())
 }
 
+/// Move a whole directory, all or nothing
+///
+/// A rename when `source` and `dest` are on the same filesystem.
+/// Otherwise the tree is copied into a hidden staging directory next to
+/// `dest`, every file's size is checked against the source, the staging
+/// directory is renamed into place and only then is `source` removed. If
+/// anything fails before that, the staging directory is removed and
+/// `source` is left as it was.
+///
+/// # Arguments
+/// * `source` - Directory to move
+/// * `dest` - New path of the directory; its parent is created if needed
+/// * `cancel` - Checked between files while copying across filesystems
+///
+/// # Returns
+/// Number of bytes moved
+///
+/// # Errors
+/// * `FsError::NotADirectory` - `source` is not a directory
+/// * `FsError::AlreadyExists` - `dest` already exists
+/// * `FsError::Cancelled` - `cancel` was set mid-copy; `source` is untouched
+#[instrument(
+    level = "debug",
+    skip_all,
+    fields(source = %source.as_ref().display(), dest = %dest.as_ref().display()),
+    err(level = "debug")
+)]
+pub fn move_dir<P: AsRef<Path>, Q: AsRef<Path>>(source: P, dest: Q, cancel: &AtomicBool) -> Result<u64> {
+    let src = source.as_ref();
+    let dst = dest.as_ref();
+
+    if !src.is_dir() {
+        return Err(FsError::NotADirectory(src.to_path_buf()));
+    }
+    if dst.exists() {
+        return Err(FsError::AlreadyExists(dst.to_path_buf()));
+    }
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let bytes = tree_size(src)?;
+
+    match fs::rename(src, dst) {
+        Ok(()) => return Ok(bytes),
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {}
+        Err(e) => return Err(FsError::Io(e)),
+    }
+
+    let name = dst.file_name().ok_or_else(|| FsError::NotFound(dst.to_path_buf()))?;
+    let staging = dst.with_file_name(format!(".{}.flacman-partial", name.to_string_lossy()));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+
+    let copied = copy_tree_verified(src, &staging, cancel).and_then(|()| fs::rename(&staging, dst).map_err(FsError::Io));
+    if let Err(e) = copied {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    fs::remove_dir_all(src)?;
+
+    Ok(bytes)
+}
+
+fn tree_size(dir: &Path) -> Result<u64> {
+    let mut bytes = 0;
+    for entry in walkdir::WalkDir::new(dir) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            bytes += entry.metadata()?.len();
+        }
+    }
+    Ok(bytes)
+}
+
+/// Copy the tree under `src` to `dst`, checking every copied file's size
+///
+/// Stops with `FsError::Cancelled` before the next file once `cancel` is set.
+fn copy_tree_verified(src: &Path, dst: &Path, cancel: &AtomicBool) -> Result<()> {
+    for entry in walkdir::WalkDir::new(src) {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(FsError::Cancelled);
+        }
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(src).expect("walk stays below its root");
+        let target = dst.join(relative);
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if entry.file_type().is_symlink() {
+            let link = fs::read_link(entry.path())?;
+            platform::symlink(&link, &target)?;
+        } else {
+            let copied = fs::copy(entry.path(), &target)?;
+            if copied != entry.metadata()?.len() {
+                return Err(FsError::Io(std::io::Error::other(format!(
+                    "short copy of {}",
+                    entry.path().display()
+                ))));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransferMode {
     /// Copy the file (leaves source intact)
@@ -212,9 +494,17 @@ pub enum TransferMode {
     Symlink,
     /// Create a hard link
     Hardlink,
+    /// Create a reflink (copy-on-write clone)
+    Reflink,
 }
 
 /// Generic transfer function that uses the specified mode
+#[instrument(
+    level = "debug",
+    skip_all,
+    fields(source = %source.as_ref().display(), dest = %dest.as_ref().display(), ?mode),
+    err(level = "debug")
+)]
 pub fn transfer_file<P: AsRef<Path>, Q: AsRef<Path>>(
     source: P,
     dest: Q,
@@ -226,6 +516,29 @@ pub fn transfer_file<P: AsRef<Path>, Q: AsRef<Path>>(
         TransferMode::Move => move_file(source, dest, overwrite),
         TransferMode::Symlink => symlink_file(source, dest, overwrite),
         TransferMode::Hardlink => hardlink_file(source, dest, overwrite),
+        TransferMode::Reflink => reflink_file(source, dest, overwrite),
+    }
+}
+
+/// Like [`transfer_file`], but copies and moves are checked with
+/// [`copy_file_checked`] and [`move_file_checked`]
+///
+/// A reflink shares the source's blocks, so like a link it isn't checked.
+///
+/// # Returns
+/// The destination path, and its SHA-256 unless it is a link or reflink
+pub fn transfer_file_checked<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    dest: Q,
+    mode: TransferMode,
+    overwrite: bool,
+) -> Result<(PathBuf, Option<String>)> {
+    match mode {
+        TransferMode::Copy => copy_file_checked(source, dest, overwrite).map(|(path, sum)| (path, Some(sum))),
+        TransferMode::Move => move_file_checked(source, dest, overwrite).map(|(path, sum)| (path, Some(sum))),
+        TransferMode::Symlink | TransferMode::Hardlink | TransferMode::Reflink => {
+            Ok((transfer_file(source, dest, mode, overwrite)?, None))
+        }
     }
 }
 
@@ -236,6 +549,61 @@ mod tests {
     use std::io::Write;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_move_dir() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("ssd/Artist/Album");
+        fs::create_dir_all(src.join("Disc 1")).unwrap();
+        fs::write(src.join("Disc 1/01.flac"), b"audio").unwrap();
+        fs::write(src.join("cover.jpg"), b"jpg").unwrap();
+
+        let dst = dir.path().join("hdd/Artist/Album");
+        assert_eq!(move_dir(&src, &dst, &AtomicBool::new(false)).unwrap(), 8);
+        assert!(!src.exists());
+        assert_eq!(fs::read(dst.join("Disc 1/01.flac")).unwrap(), b"audio");
+
+        // Never merges into an existing directory
+        fs::create_dir_all(&src).unwrap();
+        assert!(matches!(move_dir(&src, &dst, &AtomicBool::new(false)), Err(FsError::AlreadyExists(_))));
+    }
+
+    #[test]
+    fn test_copy_tree_verified() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("sub/a.flac"), b"abc").unwrap();
+
+        copy_tree_verified(&src, &dir.path().join("dst"), &AtomicBool::new(false)).unwrap();
+        assert_eq!(fs::read(dir.path().join("dst/sub/a.flac")).unwrap(), b"abc");
+        assert!(src.join("sub/a.flac").exists());
+
+        let cancelled = copy_tree_verified(&src, &dir.path().join("cancelled"), &AtomicBool::new(true));
+        assert!(matches!(cancelled, Err(FsError::Cancelled)));
+        assert!(!dir.path().join("cancelled/sub/a.flac").exists());
+    }
+
+    #[test]
+    fn test_checked_transfers() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("01 Canada.flac");
+        fs::write(&src, b"fLaC audio").unwrap();
+
+        let (copy, sum) = copy_file_checked(&src, dir.path().join("copy.flac"), false).unwrap();
+        assert_eq!(sum, sha256_file(&src).unwrap());
+        assert_eq!(sum.len(), 64);
+
+        let (moved, moved_sum) = move_file_checked(&src, dir.path().join("moved.flac"), false).unwrap();
+        assert_eq!(moved_sum, sum);
+        assert!(!src.exists());
+        assert_eq!(fs::read(moved).unwrap(), fs::read(&copy).unwrap());
+
+        // A copy that reads back differently is removed again
+        fs::write(&src, b"fLaC other").unwrap();
+        assert!(matches!(verify_copy(&src, &copy), Err(FsError::ChecksumMismatch(..))));
+        assert!(!copy.exists());
+    }
+
     #[test]
     fn test_copy_file() {
         let dir = tempdir().unwrap();
@@ -280,6 +648,29 @@ mod tests {
         assert!(dst.exists());
     }
 
+    #[test]
+    fn test_reflink_file() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("source.txt");
+        let dst = dir.path().join("clone.txt");
+        fs::write(&src, b"test content").unwrap();
+
+        // Only copy-on-write filesystems can clone; elsewhere nothing is left behind
+        match reflink_file(&src, &dst, false) {
+            Ok(path) => assert_eq!(fs::read(path).unwrap(), b"test content"),
+            Err(FsError::Io(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::Unsupported);
+                assert!(!dst.exists());
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+
+        // copy_file falls back to copying either way
+        let copy = dir.path().join("copy.txt");
+        copy_file(&src, &copy, false).unwrap();
+        assert_eq!(fs::read(&copy).unwrap(), b"test content");
+    }
+
     #[test]
     fn test_hardlink_file() {
         let dir = tempdir().unwrap();
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+
+/// What a planned operation does to one destination path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    /// A new file or directory
+    Add,
+    /// An existing file gets replaced
+    Overwrite,
+    /// A directory (or file) gets removed, e.g. an emptied album dir
+    Prune,
+}
+
+impl ChangeKind {
+    fn marker(self) -> char {
+        match self {
+            ChangeKind::Add => '+',
+            ChangeKind::Overwrite => '~',
+            ChangeKind::Prune => '-',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanEntry {
+    pub kind: ChangeKind,
+    /// Path relative to the plan's root
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// The changes an operation would make below a destination root
+///
+/// Rendered as a tree so a large import can be reviewed at a glance:
+///
+/// ```text
+/// Artist/Album (2024)/
+///   + 01 Title.flac
+///   ~ 02 Other.flac (overwritten)
+/// - Old Artist/ (pruned)
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    pub root: PathBuf,
+    pub entries: Vec<PlanEntry>,
+}
+
+#[derive(Default)]
+struct Node {
+    change: Option<(ChangeKind, bool)>,
+    children: BTreeMap<String, Node>,
+}
+
+impl Plan {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Plan { root: root.as_ref().to_path_buf(), entries: Vec::new() }
+    }
+
+    /// Plan writing a file to `dest` (absolute, or relative to the root)
+    ///
+    /// Counts as an overwrite if something already exists there.
+    pub fn write<P: AsRef<Path>>(&mut self, dest: P) {
+        let path = self.relative(dest.as_ref());
+        let kind = if self.root.join(&path).exists() { ChangeKind::Overwrite } else { ChangeKind::Add };
+        self.entries.push(PlanEntry { kind, path, is_dir: false });
+    }
+
+    /// Plan removing `dest` (absolute, or relative to the root)
+    pub fn prune<P: AsRef<Path>>(&mut self, dest: P) {
+        let path = self.relative(dest.as_ref());
+        let is_dir = self.root.join(&path).is_dir();
+        self.entries.push(PlanEntry { kind: ChangeKind::Prune, path, is_dir });
+    }
+
+    fn relative(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.root).unwrap_or(path).to_path_buf()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of entries of `kind`
+    pub fn count(&self, kind: ChangeKind) -> usize {
+        self.entries.iter().filter(|e| e.kind == kind).count()
+    }
+
+    /// e.g. "12 added, 1 overwritten, 2 pruned"
+    pub fn summary(&self) -> String {
+        format!(
+            "{} added, {} overwritten, {} pruned",
+            self.count(ChangeKind::Add),
+            self.count(ChangeKind::Overwrite),
+            self.count(ChangeKind::Prune)
+        )
+    }
+
+    /// Render the plan as a tree-style diff of the destination
+    ///
+    /// Unchanged parent directories are printed without a marker, and
+    /// chains of single directories are collapsed into one line.
+    pub fn render_tree(&self) -> String {
+        let mut tree = Node::default();
+
+        for entry in &self.entries {
+            let mut node = &mut tree;
+            for component in entry.path.components() {
+                let name = component.as_os_str().to_string_lossy().into_owned();
+                node = node.children.entry(name).or_default();
+            }
+            node.change = Some((entry.kind, entry.is_dir));
+        }
+
+        let mut out = String::new();
+        Self::render_children(&tree, 0, &mut out);
+        out
+    }
+
+    fn render_children(node: &Node, depth: usize, out: &mut String) {
+        for (name, child) in &node.children {
+            let indent = "  ".repeat(depth);
+
+            if let Some((kind, is_dir)) = child.change {
+                let slash = if is_dir || !child.children.is_empty() { "/" } else { "" };
+                let note = match kind {
+                    ChangeKind::Add => "",
+                    ChangeKind::Overwrite => " (overwritten)",
+                    ChangeKind::Prune => " (pruned)",
+                };
+                let _ = writeln!(out, "{}{} {}{}{}", indent, kind.marker(), name, slash, note);
+                Self::render_children(child, depth + 1, out);
+                continue;
+            }
+
+            // Collapse "Artist/" -> "Album/" into "Artist/Album/"
+            let mut label = name.clone();
+            let mut current = child;
+            while current.children.len() == 1 {
+                let (next_name, next) = current.children.iter().next().expect("one child");
+                if next.change.is_some() || next.children.is_empty() {
+                    break;
+                }
+                label.push('/');
+                label.push_str(next_name);
+                current = next;
+            }
+
+            let _ = writeln!(out, "{}{}/", indent, label);
+            Self::render_children(current, depth + 1, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_render_tree() {
+        let dir = tempdir().unwrap();
+        let album = dir.path().join("Low/Things We Lost (2001)");
+        fs::create_dir_all(&album).unwrap();
+        File::create(album.join("02 Whore.flac")).unwrap();
+        fs::create_dir_all(dir.path().join("Old/Empty")).unwrap();
+
+        let mut plan = Plan::new(dir.path());
+        plan.write(album.join("01 Monkey.flac"));
+        plan.write(album.join("02 Whore.flac"));
+        plan.write("Blur/13/01 Tender.flac");
+        plan.prune(dir.path().join("Old/Empty"));
+
+        assert_eq!(
+            plan.render_tree(),
+            "Blur/13/\n\
+             \x20 + 01 Tender.flac\n\
+             Low/Things We Lost (2001)/\n\
+             \x20 + 01 Monkey.flac\n\
+             \x20 ~ 02 Whore.flac (overwritten)\n\
+             Old/\n\
+             \x20 - Empty/ (pruned)\n"
+        );
+        assert_eq!(plan.summary(), "2 added, 1 overwritten, 1 pruned");
+    }
+}
@@ -0,0 +1,239 @@
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+
+/// Device names Windows reserves in every directory, with or without an
+/// extension
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1",
+    "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters Windows refuses anywhere in a file name
+pub(crate) const WINDOWS_INVALID: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Paths this long or longer need the `\\?\` prefix on Windows
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Windows error for a symlink made without the right to make one
+#[cfg(windows)]
+const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+
+/// Check if Windows refuses `name` as a file name: a reserved device name
+/// (`NUL`, `COM1.flac`, ...) or one ending in a dot or space
+///
+/// Checked on every platform, so a library built on Linux can be told in
+/// advance that it won't copy to an NTFS or exFAT drive as is.
+pub fn is_reserved_name(name: &str) -> bool {
+    if name.ends_with('.') || name.ends_with(' ') {
+        return name != "." && name != "..";
+    }
+
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    WINDOWS_RESERVED.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+/// `name` changed just enough for Windows to take it as a file name
+///
+/// Characters it refuses become `_`, trailing dots and spaces are dropped
+/// and a reserved device name gets a `_` after its stem (`CON.flac` becomes
+/// `CON_.flac`). Names Windows already accepts come back unchanged.
+pub fn windows_safe_name(name: &str) -> String {
+    let mut safe: String =
+        name.chars().map(|c| if c.is_control() || WINDOWS_INVALID.contains(&c) { '_' } else { c }).collect();
+    safe.truncate(safe.trim_end_matches(['.', ' ']).len());
+    if safe.is_empty() {
+        return "_".to_owned();
+    }
+
+    if is_reserved_name(&safe) {
+        let stem = safe.split('.').next().unwrap_or_default().trim_end().len();
+        safe.insert(stem, '_');
+    }
+    safe
+}
+
+/// `path` in the form Windows needs past `MAX_PATH` characters: absolute
+/// and prefixed with `\\?\` (`\\?\UNC\` for network shares)
+///
+/// Other platforms have no such limit and get `path` back as is.
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    #[cfg(windows)]
+    {
+        let text = path.as_os_str().to_string_lossy();
+        if text.len() < MAX_PATH || text.starts_with(r"\\?\") {
+            return Cow::Borrowed(path);
+        }
+        // Prefixed paths skip normalization, so they have to be complete
+        let Ok(absolute) = std::path::absolute(path) else {
+            return Cow::Borrowed(path);
+        };
+        let absolute = absolute.to_string_lossy();
+        let prefixed = match absolute.strip_prefix(r"\\") {
+            Some(share) => format!(r"\\?\UNC\{}", share),
+            None => format!(r"\\?\{}", absolute),
+        };
+        Cow::Owned(PathBuf::from(prefixed))
+    }
+
+    #[cfg(not(windows))]
+    {
+        Cow::Borrowed(path)
+    }
+}
+
+/// Create a symbolic link at `link` pointing to `target`
+///
+/// Windows has separate file and directory links; which one is made
+/// depends on what `target` is, resolved against the link's directory
+/// when relative. Without Developer Mode or administrator rights Windows
+/// refuses, which is reported as `ErrorKind::PermissionDenied`.
+pub fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(windows)]
+    {
+        let resolved = match link.parent() {
+            Some(parent) if target.is_relative() => parent.join(target),
+            _ => target.to_path_buf(),
+        };
+        let linked = if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, link)
+        } else {
+            std::os::windows::fs::symlink_file(target, link)
+        };
+        linked.map_err(|e| match e.raw_os_error() {
+            Some(ERROR_PRIVILEGE_NOT_HELD) => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "making symbolic links needs Developer Mode or administrator rights",
+            ),
+            _ => e,
+        })
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (target, link);
+        Err(io::Error::new(io::ErrorKind::Unsupported, "symbolic links are not supported on this platform"))
+    }
+}
+
+/// Clone `src` to `dst` sharing its data blocks (Btrfs, XFS, bcachefs)
+///
+/// Fails with `ErrorKind::Unsupported` where the platform or filesystem
+/// can't.
+pub fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::fs::File;
+        use std::os::fd::AsRawFd;
+
+        let source = File::open(src)?;
+        let dest = File::create_new(dst)?;
+        if unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } != 0 {
+            let error = io::Error::last_os_error();
+            drop(dest);
+            let _ = fs::remove_file(dst);
+            return Err(match error.raw_os_error() {
+                Some(libc::EOPNOTSUPP) | Some(libc::EXDEV) | Some(libc::EINVAL) | Some(libc::ENOTTY) => {
+                    io::Error::new(io::ErrorKind::Unsupported, error)
+                }
+                _ => error,
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (src, dst);
+        Err(io::Error::new(io::ErrorKind::Unsupported, "reflinks are not supported on this platform"))
+    }
+}
+
+/// What the filesystem holding a directory can do
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FsCapabilities {
+    pub symlink: bool,
+    pub hardlink: bool,
+    pub reflink: bool,
+    /// `a.flac` and `A.flac` are different files
+    pub case_sensitive: bool,
+}
+
+impl FsCapabilities {
+    /// Find out by trying each operation on scratch files in `dir`
+    ///
+    /// Platform and mount options both matter (FAT has no links, Windows
+    /// needs Developer Mode for symlinks, NTFS can be case-sensitive per
+    /// directory), so nothing is assumed from the OS alone.
+    ///
+    /// # Errors
+    /// Only if no scratch file can be written in `dir`
+    pub fn probe(dir: &Path) -> io::Result<Self> {
+        let scratch = |name: &str| -> PathBuf { dir.join(format!(".flacman-probe-{}-{}", std::process::id(), name)) };
+        let (source, lower) = (scratch("source"), scratch("case"));
+        let upper = scratch("CASE");
+        let (link, hard, clone) = (scratch("symlink"), scratch("hardlink"), scratch("reflink"));
+
+        fs::write(&source, b"flacman")?;
+        let capabilities = FsCapabilities {
+            symlink: symlink(&source, &link).is_ok(),
+            hardlink: fs::hard_link(&source, &hard).is_ok(),
+            reflink: reflink(&source, &clone).is_ok(),
+            case_sensitive: fs::write(&lower, b"").is_ok() && !upper.exists(),
+        };
+
+        for path in [&source, &lower, &link, &hard, &clone] {
+            let _ = fs::remove_file(path);
+        }
+
+        Ok(capabilities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_reserved_names() {
+        for name in ["CON", "nul.flac", "Com1.txt", "LPT9", "aux .mp3", "Trust.", "Trust "] {
+            assert!(is_reserved_name(name), "{name}");
+        }
+        for name in ["Conan.flac", "COM10", "01 Canada.flac", ".", "..", "NULL"] {
+            assert!(!is_reserved_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_windows_safe_name() {
+        assert_eq!(windows_safe_name("01 What? Why: \"Because\".flac"), "01 What_ Why_ _Because_.flac");
+        assert_eq!(windows_safe_name("CON.flac"), "CON_.flac");
+        assert_eq!(windows_safe_name("aux .mp3"), "aux_ .mp3");
+        assert_eq!(windows_safe_name("Trust. "), "Trust");
+        assert_eq!(windows_safe_name("..."), "_");
+        assert_eq!(windows_safe_name("01 Canada.flac"), "01 Canada.flac");
+        assert_eq!(long_path(Path::new("Low/Trust")), Path::new("Low/Trust"));
+    }
+
+    #[test]
+    fn test_probe_cleans_up() {
+        let dir = tempdir().unwrap();
+        let capabilities = FsCapabilities::probe(dir.path()).unwrap();
+
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        if capabilities.symlink {
+            let link = dir.path().join("link");
+            symlink(Path::new("target"), &link).unwrap();
+            assert_eq!(fs::read_link(&link).unwrap(), Path::new("target"));
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, instrument};
+
+use crate::fserror::Result;
+use crate::platform::long_path;
+use crate::FsError;
+
+
+/// Map the errors of a removal to the paths they are about
+fn removal_error(path: &Path, e: std::io::Error) -> FsError {
+    match e.kind() {
+        ErrorKind::NotFound => FsError::NotFound(path.to_path_buf()),
+        ErrorKind::PermissionDenied => FsError::PermissionError(path.to_path_buf()),
+        _ => FsError::Io(e),
+    }
+}
+
+/// Delete a file for good; a symlink is removed, not what it points to
+///
+/// # Returns
+/// Bytes freed
+///
+/// # Errors
+/// * `FsError::NotFound` - `path` doesn't exist
+/// * `FsError::NotAFile` - `path` is a directory
+/// * `FsError::PermissionError` - The directory holding it can't be written
+#[instrument(level = "debug", skip_all, fields(path = %path.as_ref().display()), err(level = "debug"))]
+pub fn remove_file<P: AsRef<Path>>(path: P) -> Result<u64> {
+    let path = path.as_ref();
+    let metadata = fs::symlink_metadata(long_path(path)).map_err(|e| removal_error(path, e))?;
+    if metadata.is_dir() {
+        return Err(FsError::NotAFile(path.to_path_buf()));
+    }
+
+    fs::remove_file(long_path(path)).map_err(|e| removal_error(path, e))?;
+    Ok(if metadata.is_file() { metadata.len() } else { 0 })
+}
+
+/// Delete a directory and everything in it for good, or a single file
+///
+/// Symlinks inside are removed without following them.
+///
+/// # Returns
+/// Number of files removed and the bytes freed
+///
+/// # Errors
+/// * `FsError::NotFound` - `path` doesn't exist
+/// * `FsError::PermissionError` - Something below can't be removed; what
+///   came before it is gone
+#[instrument(level = "debug", skip_all, fields(path = %path.as_ref().display()), err(level = "debug"))]
+pub fn remove_tree<P: AsRef<Path>>(path: P) -> Result<(u64, u64)> {
+    let path = path.as_ref();
+    let metadata = fs::symlink_metadata(long_path(path)).map_err(|e| removal_error(path, e))?;
+    if !metadata.is_dir() {
+        return Ok((1, remove_file(path)?));
+    }
+
+    let (mut files, mut bytes) = (0, 0);
+    for entry in walkdir::WalkDir::new(path) {
+        let entry = entry?;
+        if !entry.file_type().is_dir() {
+            files += 1;
+            bytes += if entry.file_type().is_file() { entry.metadata()?.len() } else { 0 };
+        }
+    }
+
+    fs::remove_dir_all(long_path(path)).map_err(|e| removal_error(path, e))?;
+    Ok((files, bytes))
+}
+
+/// Remove `dir` and then each directory above it as long as they are
+/// empty, stopping at `root`, which is never removed
+///
+/// Used after removals to drop the album and artist directories they
+/// emptied. Nothing happens if `dir` isn't below `root`.
+///
+/// # Returns
+/// The removed directories, deepest first
+pub fn prune_empty_dirs<P: AsRef<Path>, Q: AsRef<Path>>(dir: P, root: Q) -> Result<Vec<PathBuf>> {
+    let (dir, root) = (dir.as_ref(), root.as_ref());
+    let mut removed = Vec::new();
+
+    for dir in dir.ancestors().take_while(|d| *d != root && d.starts_with(root)) {
+        match fs::remove_dir(long_path(dir)) {
+            Ok(()) => {
+                debug!(dir = %dir.display(), "pruned empty directory");
+                removed.push(dir.to_path_buf());
+            }
+            // Already gone with whatever was removed below it
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) if e.kind() == ErrorKind::DirectoryNotEmpty => break,
+            Err(e) => return Err(removal_error(dir, e)),
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_remove_tree_and_prune() {
+        let root = tempdir().unwrap();
+        let artist = root.path().join("Low");
+        for album in ["Trust", "Secret Name"] {
+            fs::create_dir_all(artist.join(album)).unwrap();
+            fs::write(artist.join(album).join("01.flac"), b"12345").unwrap();
+        }
+        fs::write(artist.join("Trust/02.flac"), b"123").unwrap();
+
+        assert_eq!(remove_file(artist.join("Trust/02.flac")).unwrap(), 3);
+        assert!(matches!(remove_file(artist.join("Trust")), Err(FsError::NotAFile(_))));
+        assert_eq!(remove_tree(artist.join("Trust")).unwrap(), (1, 5));
+        assert!(matches!(remove_tree(artist.join("Trust")), Err(FsError::NotFound(_))));
+
+        // The artist still has an album
+        assert!(prune_empty_dirs(artist.join("Trust"), root.path()).unwrap().is_empty());
+        assert!(artist.exists());
+
+        remove_file(artist.join("Secret Name/01.flac")).unwrap();
+        let removed = prune_empty_dirs(artist.join("Secret Name"), root.path()).unwrap();
+        assert_eq!(removed, [artist.join("Secret Name"), artist.clone()]);
+        assert!(root.path().exists());
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+
+use deunicode::deunicode_with_tofu;
+use serde::{Deserialize, Serialize};
+
+use crate::platform::{WINDOWS_INVALID, is_reserved_name};
+
+
+/// How names made from tags become names the library's filesystems take
+///
+/// Separators, control characters and the `.` and `..` names are never
+/// let through; everything else is up to the policy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SanitizePolicy {
+    /// What stands in for each character a name can't have
+    pub replacement: char,
+    /// Transliterate to ASCII, e.g. `Sigur Rós` to `Sigur Ros`; emoji
+    /// become their names and what has no transliteration the replacement
+    pub ascii: bool,
+    /// Longest name in bytes, extension included; 255 for most filesystems
+    pub max_length: usize,
+    /// Also refuse what FAT and NTFS refuse: `<>:"\|?*`, names ending in
+    /// a dot or space and device names such as `CON`
+    pub windows_safe: bool,
+}
+
+impl Default for SanitizePolicy {
+    fn default() -> Self {
+        SanitizePolicy { replacement: '_', ascii: false, max_length: 255, windows_safe: cfg!(windows) }
+    }
+}
+
+impl SanitizePolicy {
+    /// `name` made safe as a directory name
+    pub fn component(&self, name: &str) -> String {
+        self.file_name(name, None)
+    }
+
+    /// `stem` made safe and given `extension`, which is kept whole when the
+    /// name has to be shortened
+    pub fn file_name(&self, stem: &str, extension: Option<&str>) -> String {
+        let replacement = self.replacement.to_string();
+        let stem = if self.ascii { deunicode_with_tofu(stem, &replacement) } else { stem.to_owned() };
+        let mut name: String = stem.trim().chars().map(|c| if self.refuses(c) { self.replacement } else { c }).collect();
+        if name.chars().all(|c| c == '.') {
+            name = name.replace('.', &replacement);
+        }
+
+        let suffix = extension.map(|ext| format!(".{}", ext)).unwrap_or_default();
+        let room = self.max_length.saturating_sub(suffix.len());
+        if name.len() > room {
+            name.truncate(name.floor_char_boundary(room));
+            name.truncate(name.trim_end().len());
+        }
+        if self.windows_safe {
+            name.truncate(name.trim_end_matches(['.', ' ']).len());
+        }
+        if name.is_empty() {
+            name = replacement;
+        }
+
+        let mut name = name + &suffix;
+        if self.windows_safe && is_reserved_name(&name) {
+            let stem = name.split('.').next().unwrap_or_default().trim_end().len();
+            name.insert(stem, self.replacement);
+        }
+        name
+    }
+
+    /// Each part of `path` made safe; the last is a file name that gets
+    /// `extension`
+    pub fn path(&self, path: &Path, extension: Option<&str>) -> PathBuf {
+        let parts: Vec<String> = path.iter().map(|part| part.to_string_lossy().into_owned()).collect();
+        let Some((last, dirs)) = parts.split_last() else {
+            return PathBuf::new();
+        };
+
+        let mut safe: PathBuf = dirs.iter().map(|dir| self.component(dir)).collect();
+        safe.push(self.file_name(last, extension));
+        safe
+    }
+
+    fn refuses(&self, c: char) -> bool {
+        c == '/' || c.is_control() || (self.windows_safe && WINDOWS_INVALID.contains(&c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        let posix = SanitizePolicy { windows_safe: false, ..Default::default() };
+        assert_eq!(posix.file_name("What? Why: \"Because\"", Some("flac")), "What? Why: \"Because\".flac");
+        assert_eq!(posix.component("AC/DC\t"), "AC_DC");
+        assert_eq!(posix.component(".."), "__");
+        assert_eq!(posix.component("T.N.T."), "T.N.T.");
+
+        let windows = SanitizePolicy { windows_safe: true, ..Default::default() };
+        assert_eq!(windows.file_name("What? Why: \"Because\"", Some("flac")), "What_ Why_ _Because_.flac");
+        assert_eq!(windows.component("T.N.T."), "T.N.T");
+        assert_eq!(windows.file_name("Con", Some("mp3")), "Con_.mp3");
+
+        let ascii = SanitizePolicy { ascii: true, replacement: '-', ..windows };
+        assert_eq!(ascii.component("Sigur Rós: ( )"), "Sigur Ros- ( )");
+        assert!(ascii.component("Love 🎵").is_ascii());
+
+        let short = SanitizePolicy { max_length: 12, ..posix };
+        assert_eq!(short.file_name("01 Ágætis byrjun", Some("flac")), "01 Ág.flac");
+        assert_eq!(short.file_name("01 Svefn-g-englar", None), "01 Svefn-g-e");
+        assert_eq!(
+            short.path(Path::new("Sigur Rós/Ágætis byrjun (1999)/01 Intro"), Some("flac")),
+            PathBuf::from("Sigur Rós/Ágætis byr/01 Intr.flac")
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::fd::{WalkOptions, is_audio_file, walkdir_lenient};
+use crate::fserror::{FsError, Result};
+
+
+/// Which non-audio files of an album directory go along when it is
+/// imported: cover art, cue sheets, rip logs, booklets, lyrics
+///
+/// Patterns are globs matched case-insensitively against the path below
+/// the album directory, or any trailing part of it, so `*.jpg` takes
+/// `Scans/back.jpg` too. A file has to match an `include` pattern and no
+/// `exclude` pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SidecarPolicy {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Default for SidecarPolicy {
+    fn default() -> Self {
+        let include = ["*.jpg", "*.jpeg", "*.png", "*.cue", "*.log", "*.pdf", "*.lrc"];
+        SidecarPolicy { include: include.map(str::to_owned).to_vec(), exclude: Vec::new() }
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(&format!("**/{}", pattern.trim_start_matches('/')))
+            .case_insensitive(true)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| FsError::Pattern(pattern.clone(), e.kind().to_string()))?;
+        set.add(glob);
+    }
+    set.build().map_err(|e| FsError::Pattern(patterns.join(", "), e.to_string()))
+}
+
+impl SidecarPolicy {
+    /// The sidecars in `album_dir` and below, in name order
+    ///
+    /// Hidden files and what `.flacmanignore` files leave out are skipped.
+    ///
+    /// # Errors
+    /// * `FsError::Pattern` - A pattern is malformed
+    /// * `FsError::NotFound` - `album_dir` doesn't exist
+    pub fn find(&self, album_dir: &Path) -> Result<Vec<PathBuf>> {
+        if self.include.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (include, exclude) = (glob_set(&self.include)?, glob_set(&self.exclude)?);
+
+        let options = WalkOptions { skip_hidden: true, use_ignore_files: true, ..Default::default() };
+        let mut sidecars: Vec<PathBuf> = walkdir_lenient(album_dir, &options)?
+            .map(|entry| entry.path)
+            .filter(|path| !is_audio_file(path))
+            .filter(|path| {
+                let relative = path.strip_prefix(album_dir).unwrap_or(path);
+                include.is_match(relative) && !exclude.is_match(relative)
+            })
+            .collect();
+        sidecars.sort();
+
+        Ok(sidecars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_find_sidecars() {
+        let dir = tempdir().unwrap();
+        for name in ["01.flac", "Cover.JPG", "Trust.cue", "rip.log", "Scans/booklet.pdf", "01.lrc", "notes.txt", ".cover.jpg"] {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"x").unwrap();
+        }
+        let names = |found: Vec<PathBuf>| -> Vec<String> {
+            found.iter().map(|p| p.strip_prefix(dir.path()).unwrap().to_string_lossy().into_owned()).collect()
+        };
+
+        let policy = SidecarPolicy::default();
+        assert_eq!(names(policy.find(dir.path()).unwrap()), ["01.lrc", "Cover.JPG", "Scans/booklet.pdf", "Trust.cue", "rip.log"]);
+
+        let policy = SidecarPolicy { exclude: vec!["*.log".into(), "Scans/*".into()], ..Default::default() };
+        assert_eq!(names(policy.find(dir.path()).unwrap()), ["01.lrc", "Cover.JPG", "Trust.cue"]);
+        let policy = SidecarPolicy { include: vec!["*.txt".into()], exclude: Vec::new() };
+        assert_eq!(names(policy.find(dir.path()).unwrap()), ["notes.txt"]);
+        let policy = SidecarPolicy { include: Vec::new(), exclude: Vec::new() };
+        assert!(policy.find(dir.path()).unwrap().is_empty());
+        let policy = SidecarPolicy { include: vec!["{*.jpg".into()], exclude: Vec::new() };
+        assert!(matches!(policy.find(dir.path()), Err(FsError::Pattern(..))));
+    }
+}
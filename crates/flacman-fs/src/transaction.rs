@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, warn};
+
+use crate::fserror::Result;
+use crate::mv::{TransferMode, move_file, transfer_file, transfer_file_checked};
+use crate::FsError;
+
+
+/// A transfer staged in a [`FsTransaction`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    source: PathBuf,
+    dest: PathBuf,
+    mode: TransferMode,
+}
+
+/// A transfer [`FsTransaction::commit`] carried out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transferred {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    /// SHA-256 of the destination, for checked copies and moves
+    pub sha256: Option<String>,
+}
+
+/// A batch of file transfers that happens completely or not at all
+///
+/// Transfers are staged with [`FsTransaction::stage`] and carried out in
+/// order by [`FsTransaction::commit`], creating the directories they need.
+/// If one fails, those already done are undone in reverse: moved files
+/// are moved back, copies and links are deleted, and so are the
+/// directories created for them. Destinations must not exist yet, so
+/// nothing is overwritten and undoing a transfer can't lose data.
+#[derive(Debug, Clone, Default)]
+pub struct FsTransaction {
+    steps: Vec<Step>,
+    checksum: bool,
+}
+
+impl FsTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check copies and moves against their source by SHA-256, as
+    /// [`transfer_file_checked`] does
+    pub fn checked(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Add a transfer of `source` to `dest` to the batch
+    pub fn stage<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, source: P, dest: Q, mode: TransferMode) -> &mut Self {
+        let (source, dest) = (source.as_ref().to_path_buf(), dest.as_ref().to_path_buf());
+        self.steps.push(Step { source, dest, mode });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Carry out the staged transfers, or none of them
+    ///
+    /// # Returns
+    /// The transfers, in the order they were staged
+    ///
+    /// # Errors
+    /// * `FsError::NotFound` - A source doesn't exist; nothing was transferred
+    /// * `FsError::AlreadyExists` - A destination exists or is staged twice; nothing was transferred
+    /// * `FsError::RolledBack` - A transfer failed and the ones before it were undone
+    pub fn commit(self) -> Result<Vec<Transferred>> {
+        let mut dests = HashSet::new();
+        for step in &self.steps {
+            if !step.source.is_file() && !step.source.is_symlink() {
+                return Err(FsError::NotFound(step.source.clone()));
+            }
+            if step.dest.exists() || step.dest.is_symlink() || !dests.insert(&step.dest) {
+                return Err(FsError::AlreadyExists(step.dest.clone()));
+            }
+        }
+
+        let mut done = Vec::new();
+        let mut created = Vec::new();
+        for step in &self.steps {
+            match self.transfer(step, &mut created) {
+                Ok(transferred) => done.push(transferred),
+                Err(e) => {
+                    debug!(source = %step.source.display(), error = %e, "rolling back");
+                    return Err(rollback(e, &self.steps[..done.len()], &created));
+                }
+            }
+        }
+
+        Ok(done)
+    }
+
+    fn transfer(&self, step: &Step, created: &mut Vec<PathBuf>) -> Result<Transferred> {
+        if let Some(parent) = step.dest.parent() {
+            let missing: Vec<&Path> = parent.ancestors().take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists()).collect();
+            fs::create_dir_all(parent)?;
+            created.extend(missing.into_iter().rev().map(Path::to_path_buf));
+        }
+
+        let (dest, sha256) = if self.checksum {
+            transfer_file_checked(&step.source, &step.dest, step.mode, false)?
+        } else {
+            (transfer_file(&step.source, &step.dest, step.mode, false)?, None)
+        };
+        Ok(Transferred { source: step.source.clone(), dest, sha256 })
+    }
+}
+
+/// Undo the transfers in `done`, latest first, then remove the `created`
+/// directories they leave empty
+fn rollback(cause: FsError, done: &[Step], created: &[PathBuf]) -> FsError {
+    let mut restored = Vec::new();
+    let mut stranded = Vec::new();
+
+    for step in done.iter().rev() {
+        let undone = match step.mode {
+            TransferMode::Move => move_file(&step.dest, &step.source, false).map(drop),
+            _ => fs::remove_file(&step.dest).map_err(FsError::from),
+        };
+        match undone {
+            Ok(()) => restored.push(step.source.clone()),
+            Err(e) => {
+                warn!(path = %step.dest.display(), error = %e, "could not roll back");
+                stranded.push(step.dest.clone());
+            }
+        }
+    }
+    for dir in created.iter().rev() {
+        let _ = fs::remove_dir(dir);
+    }
+
+    restored.reverse();
+    stranded.reverse();
+    FsError::RolledBack { cause: Box::new(cause), restored, stranded }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_commit() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("01.flac"), b"one").unwrap();
+        fs::write(dir.path().join("02.flac"), b"two").unwrap();
+        let album = dir.path().join("library/Low/Trust");
+
+        let mut transaction = FsTransaction::new().checked(true);
+        transaction
+            .stage(dir.path().join("01.flac"), album.join("01.flac"), TransferMode::Copy)
+            .stage(dir.path().join("02.flac"), album.join("02.flac"), TransferMode::Move);
+        let done = transaction.commit().unwrap();
+
+        assert_eq!(done.len(), 2);
+        assert_eq!(done[1].dest, album.join("02.flac"));
+        assert!(done.iter().all(|t| t.sha256.is_some()));
+        assert!(dir.path().join("01.flac").exists() && !dir.path().join("02.flac").exists());
+        assert_eq!(fs::read(album.join("02.flac")).unwrap(), b"two");
+
+        // Nothing is transferred onto an existing file
+        let mut transaction = FsTransaction::new();
+        transaction.stage(dir.path().join("01.flac"), album.join("01.flac"), TransferMode::Copy);
+        assert!(matches!(transaction.commit(), Err(FsError::AlreadyExists(_))));
+    }
+
+    #[test]
+    fn test_rollback() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("01.flac"), b"one").unwrap();
+        fs::write(dir.path().join("02.flac"), b"two").unwrap();
+        let library = dir.path().join("library");
+
+        // The second move of 02.flac finds it gone
+        let mut transaction = FsTransaction::new();
+        transaction
+            .stage(dir.path().join("01.flac"), library.join("Low/Trust/01.flac"), TransferMode::Copy)
+            .stage(dir.path().join("02.flac"), library.join("Low/Trust/02.flac"), TransferMode::Move)
+            .stage(dir.path().join("02.flac"), library.join("Low/Other/02.flac"), TransferMode::Move);
+
+        let Err(FsError::RolledBack { cause, restored, stranded }) = transaction.commit() else {
+            panic!("expected a rollback");
+        };
+        assert!(matches!(*cause, FsError::NotFound(_)));
+        assert_eq!(restored, [dir.path().join("01.flac"), dir.path().join("02.flac")]);
+        assert!(stranded.is_empty());
+        assert_eq!(fs::read(dir.path().join("02.flac")).unwrap(), b"two");
+        assert!(!library.exists());
+    }
+}
@@ -0,0 +1,441 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{Local, NaiveDateTime, TimeDelta};
+use tracing::{debug, instrument};
+
+use crate::fd::IGNORE_FILE;
+use crate::fserror::Result;
+use crate::FsError;
+
+
+/// Names of batches not made for a transaction, sortable and human
+/// readable; also how the manifest records when a batch was removed
+const BATCH_FORMAT: &str = "%Y-%m-%d_%H%M%S";
+const MANIFEST: &str = "manifest";
+/// Start of the manifest line saying when the batch was removed
+const REMOVED_AT: &str = "#removed\t";
+
+/// One file or directory held in the trash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashEntry {
+    /// Batch directory name, e.g. `42` or `2024-05-01_213000`
+    pub batch: String,
+    /// Transaction that removed the batch, which `--rollback` undoes
+    pub transaction: Option<u64>,
+    /// When the batch was removed
+    pub removed_at: NaiveDateTime,
+    /// Where the item currently lives inside the trash
+    pub stored: PathBuf,
+    /// Where the item was removed from
+    pub original: PathBuf,
+}
+
+/// Holding area for soft-deleted files
+///
+/// Every removal creates a batch directory holding the removed items
+/// plus a manifest of their original locations, so they can be restored
+/// until the batch is purged. Batches are named after the transaction
+/// that removed them when the trash knows its id, else after the time.
+/// The trash ignores itself, so walks of a library it is kept in leave
+/// it out.
+#[derive(Debug, Clone)]
+pub struct Trash {
+    root: PathBuf,
+    next_transaction: Option<fn() -> Option<u64>>,
+}
+
+/// Move a file or directory, copying across devices when needed
+fn move_path(src: &Path, dst: &Path) -> Result<()> {
+    match fs::rename(src, dst) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_tree(src, dst)?;
+            if src.is_dir() {
+                fs::remove_dir_all(src)?;
+            } else {
+                fs::remove_file(src)?;
+            }
+            Ok(())
+        }
+        Err(e) => Err(FsError::Io(e)),
+    }
+}
+
+fn copy_tree(src: &Path, dst: &Path) -> Result<()> {
+    if !src.is_dir() {
+        fs::copy(src, dst)?;
+        return Ok(());
+    }
+
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+    }
+
+    Ok(())
+}
+
+impl Trash {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Trash { root: root.as_ref().to_path_buf(), next_transaction: None }
+    }
+
+    /// Name each batch after the id `next_transaction` gives when it is
+    /// created: that of the transaction about to be logged
+    pub fn with_transaction_ids(mut self, next_transaction: fn() -> Option<u64>) -> Self {
+        self.next_transaction = Some(next_transaction);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Create a fresh batch directory named after the transaction, else
+    /// the current time
+    fn new_batch(&self) -> Result<(String, PathBuf)> {
+        fs::create_dir_all(&self.root)?;
+        let ignore = self.root.join(IGNORE_FILE);
+        if !ignore.exists() {
+            fs::write(ignore, "*\n")?;
+        }
+
+        let stamp = match self.next_transaction.and_then(|next| next()) {
+            Some(id) => id.to_string(),
+            None => Local::now().format(BATCH_FORMAT).to_string(),
+        };
+        let mut name = stamp.clone();
+        let mut n = 1;
+
+        while self.root.join(&name).exists() {
+            n += 1;
+            name = format!("{stamp}.{n}");
+        }
+
+        let dir = self.root.join(&name);
+        fs::create_dir(&dir)?;
+
+        Ok((name, dir))
+    }
+
+    /// Transaction a batch was named after
+    fn batch_transaction(name: &str) -> Option<u64> {
+        name.split('.').next()?.parse().ok()
+    }
+
+    /// When a batch was removed: as its manifest records, else as its
+    /// name says for batches from before manifests recorded it
+    fn batch_time(name: &str, manifest: &str) -> Option<NaiveDateTime> {
+        let stamp = match manifest.lines().next().and_then(|line| line.strip_prefix(REMOVED_AT)) {
+            Some(stamp) => stamp,
+            None => name.split('.').next()?,
+        };
+        NaiveDateTime::parse_from_str(stamp, BATCH_FORMAT).ok()
+    }
+
+    /// Move files or directories into a new trash batch
+    ///
+    /// # Returns
+    /// The created entries, in the order of `paths`
+    ///
+    /// # Errors
+    /// * `FsError::NotFound` - One of the paths doesn't exist (nothing is moved)
+    #[instrument(level = "debug", skip_all, fields(paths = paths.len()), err(level = "debug"))]
+    pub fn remove<P: AsRef<Path>>(&self, paths: &[P]) -> Result<Vec<TrashEntry>> {
+        let mut originals = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.as_ref();
+            if !path.exists() {
+                return Err(FsError::NotFound(path.to_path_buf()));
+            }
+            originals.push(fs::canonicalize(path)?);
+        }
+
+        let (batch, dir) = self.new_batch()?;
+        let transaction = Self::batch_transaction(&batch);
+        let removed_at = Local::now().naive_local();
+        let mut manifest = fs::File::create(dir.join(MANIFEST))?;
+        writeln!(manifest, "{}{}", REMOVED_AT, removed_at.format(BATCH_FORMAT))?;
+        let mut entries = Vec::with_capacity(originals.len());
+
+        for (i, original) in originals.into_iter().enumerate() {
+            let file_name = original.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            let stored_name = format!("{i}-{file_name}");
+            let stored = dir.join(&stored_name);
+
+            move_path(&original, &stored)?;
+            writeln!(manifest, "{}\t{}", stored_name, original.display())?;
+            debug!(original = %original.display(), stored = %stored.display(), "trashed");
+
+            entries.push(TrashEntry { batch: batch.clone(), transaction, removed_at, stored, original });
+        }
+
+        Ok(entries)
+    }
+
+    /// All entries currently in the trash, oldest batch first
+    pub fn list(&self) -> Result<Vec<TrashEntry>> {
+        let mut entries = Vec::new();
+
+        if !self.root.exists() {
+            return Ok(entries);
+        }
+
+        // Named after transaction ids or dates, batch names don't sort as
+        // text: order them by when they were removed, then by transaction
+        let mut batches = Vec::new();
+        for entry in fs::read_dir(&self.root)?.filter_map(|e| e.ok()).filter(|e| e.path().is_dir()) {
+            let (batch, dir) = (entry.file_name().to_string_lossy().into_owned(), entry.path());
+            let Ok(manifest) = fs::read_to_string(dir.join(MANIFEST)) else {
+                continue;
+            };
+            let Some(removed_at) = Self::batch_time(&batch, &manifest) else {
+                continue;
+            };
+            let suffix: Option<u32> = batch.split_once('.').and_then(|(_, n)| n.parse().ok());
+            batches.push((removed_at, Self::batch_transaction(&batch), suffix, batch, dir, manifest));
+        }
+        batches.sort();
+
+        for (removed_at, transaction, _, batch, dir, manifest) in batches {
+            for line in manifest.lines().filter(|line| !line.starts_with(REMOVED_AT)) {
+                if let Some((stored_name, original)) = line.split_once('\t') {
+                    let stored = dir.join(stored_name);
+                    if stored.exists() {
+                        entries.push(TrashEntry {
+                            batch: batch.clone(),
+                            transaction,
+                            removed_at,
+                            stored,
+                            original: PathBuf::from(original),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Put entries whose original path contains `pattern` back where they came from
+    ///
+    /// # Returns
+    /// The restored paths
+    ///
+    /// # Errors
+    /// * `FsError::AlreadyExists` - Something new already occupies an original location
+    #[instrument(level = "debug", skip(self), err(level = "debug"))]
+    pub fn restore(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        let entries: Vec<TrashEntry> =
+            self.list()?.into_iter().filter(|e| e.original.to_string_lossy().contains(pattern)).collect();
+        self.put_back(&entries)
+    }
+
+    /// Put `entries` back where they came from, e.g. those of a removal
+    /// that has to be undone
+    ///
+    /// # Returns
+    /// The restored paths
+    ///
+    /// # Errors
+    /// * `FsError::AlreadyExists` - Something new already occupies an original location
+    pub fn put_back(&self, entries: &[TrashEntry]) -> Result<Vec<PathBuf>> {
+        let mut restored = Vec::new();
+
+        for entry in entries {
+            if entry.original.exists() {
+                return Err(FsError::AlreadyExists(entry.original.clone()));
+            }
+
+            if let Some(parent) = entry.original.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            move_path(&entry.stored, &entry.original)?;
+            debug!(original = %entry.original.display(), "restored");
+            restored.push(entry.original.clone());
+        }
+
+        self.remove_empty_batches()?;
+
+        Ok(restored)
+    }
+
+    /// Permanently delete batches removed more than `retention` ago
+    ///
+    /// # Returns
+    /// Names of the purged batches
+    pub fn purge_older_than(&self, retention: Duration) -> Result<Vec<String>> {
+        let mut purged = Vec::new();
+
+        let cutoff = TimeDelta::from_std(retention)
+            .ok()
+            .and_then(|retention| Local::now().naive_local().checked_sub_signed(retention));
+
+        let Some(cutoff) = cutoff else {
+            return Ok(purged);
+        };
+
+        if !self.root.exists() {
+            return Ok(purged);
+        }
+
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Ok(manifest) = fs::read_to_string(entry.path().join(MANIFEST)) else {
+                continue;
+            };
+
+            if let Some(removed_at) = Self::batch_time(&name, &manifest)
+                && removed_at <= cutoff
+            {
+                fs::remove_dir_all(entry.path())?;
+                purged.push(name);
+            }
+        }
+
+        purged.sort();
+
+        Ok(purged)
+    }
+
+    /// Drop batch directories whose items have all been restored
+    fn remove_empty_batches(&self) -> Result<()> {
+        if !self.root.exists() {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(&self.root)? {
+            let dir = entry?.path();
+            if !dir.is_dir() {
+                continue;
+            }
+            let only_manifest = fs::read_dir(&dir)?
+                .filter_map(|e| e.ok())
+                .all(|e| e.file_name() == MANIFEST);
+
+            if only_manifest {
+                fs::remove_dir_all(&dir)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_remove_and_restore() {
+        let dir = tempdir().unwrap();
+        let album = dir.path().join("library/Artist/Album");
+        fs::create_dir_all(&album).unwrap();
+        File::create(album.join("01.flac")).unwrap();
+
+        let trash = Trash::new(dir.path().join("trash"));
+        let entries = trash.remove(&[&album]).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert!(!album.exists());
+        assert!(entries[0].stored.join("01.flac").exists());
+        assert_eq!(trash.list().unwrap().len(), 1);
+
+        let restored = trash.restore("Album").unwrap();
+        assert_eq!(restored.len(), 1);
+        assert!(album.join("01.flac").exists());
+        assert!(trash.list().unwrap().is_empty());
+
+        let entries = trash.remove(&[album.join("01.flac")]).unwrap();
+        assert_eq!(trash.put_back(&entries).unwrap(), [fs::canonicalize(album.join("01.flac")).unwrap()]);
+        assert!(trash.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remove_missing_moves_nothing() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("a.flac");
+        File::create(&file).unwrap();
+
+        let trash = Trash::new(dir.path().join("trash"));
+        let result = trash.remove(&[file.clone(), dir.path().join("missing.flac")]);
+
+        assert!(matches!(result, Err(FsError::NotFound(_))));
+        assert!(file.exists());
+    }
+
+    #[test]
+    fn test_batches_named_after_transactions() {
+        let dir = tempdir().unwrap();
+        let library = dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        for name in ["a.flac", "b.flac", "c.flac"] {
+            File::create(library.join(name)).unwrap();
+        }
+
+        let trash = Trash::new(library.join(".flacman/trash")).with_transaction_ids(|| Some(42));
+        trash.remove(&[library.join("a.flac")]).unwrap();
+        trash.remove(&[library.join("b.flac")]).unwrap();
+        let entries = trash.list().unwrap();
+        let batches: Vec<(&str, Option<u64>)> = entries.iter().map(|e| (e.batch.as_str(), e.transaction)).collect();
+        assert_eq!(batches, [("42", Some(42)), ("42.2", Some(42))]);
+
+        // Walks of the library leave the trash out
+        assert_eq!(crate::find_audio_files(&library).unwrap(), [library.join("c.flac")]);
+    }
+
+    #[test]
+    fn test_lists_oldest_batch_first() {
+        let dir = tempdir().unwrap();
+        let batch = |name: &str, removed_at: &str| {
+            let batch = dir.path().join("trash").join(name);
+            fs::create_dir_all(batch.join("0-Album")).unwrap();
+            let manifest = format!("{REMOVED_AT}{removed_at}\n0-Album\t/music/{name}\n");
+            fs::write(batch.join(MANIFEST), manifest).unwrap();
+        };
+        batch("10", "2024-05-02_090000");
+        batch("9", "2024-05-02_090000");
+        batch("42.2", "2024-06-01_120000");
+        batch("42", "2024-06-01_120000");
+        batch("2024-05-01_213000", "2024-05-01_213000");
+
+        let batches: Vec<String> = Trash::new(dir.path().join("trash")).list().unwrap().into_iter().map(|e| e.batch).collect();
+        assert_eq!(batches, ["2024-05-01_213000", "9", "10", "42", "42.2"]);
+    }
+
+    #[test]
+    fn test_lists_dated_batches() {
+        let dir = tempdir().unwrap();
+        let batch = dir.path().join("trash/2024-05-01_213000");
+        fs::create_dir_all(batch.join("0-Album")).unwrap();
+        fs::write(batch.join(MANIFEST), "0-Album\t/music/Artist/Album\n").unwrap();
+
+        let entries = Trash::new(dir.path().join("trash")).list().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].transaction, None);
+        assert_eq!(entries[0].removed_at.format(BATCH_FORMAT).to_string(), "2024-05-01_213000");
+        assert_eq!(entries[0].original, Path::new("/music/Artist/Album"));
+    }
+
+    #[test]
+    fn test_purge() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("a.flac");
+        File::create(&file).unwrap();
+
+        let trash = Trash::new(dir.path().join("trash"));
+        trash.remove(&[&file]).unwrap();
+
+        assert!(trash.purge_older_than(Duration::from_secs(30 * 86400)).unwrap().is_empty());
+        assert_eq!(trash.purge_older_than(Duration::ZERO).unwrap().len(), 1);
+        assert!(trash.list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, channel};
+use std::time::Duration;
+
+use notify::event::{EventKind, ModifyKind};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{debug, warn};
+
+use crate::fd::is_audio_file;
+use crate::fserror::Result;
+use crate::FsError;
+
+
+/// Watches directory trees for changes through the operating system
+/// (inotify on Linux, FSEvents on macOS, ReadDirectoryChangesW on Windows)
+/// instead of rescanning them
+///
+/// Only audio files and directories are reported, since a directory moved
+/// into the tree arrives as one event for the directory rather than one
+/// per file. Paths in hidden directories are left out.
+pub struct LibraryWatcher {
+    dirs: Vec<PathBuf>,
+    events: Receiver<notify::Result<Event>>,
+    // Dropping it stops the events
+    _watcher: RecommendedWatcher,
+}
+
+impl LibraryWatcher {
+    /// # Errors
+    /// * `FsError::NotADirectory` - One of `dirs` isn't a directory
+    /// * `FsError::Watch` - The operating system refused to watch one of them
+    pub fn new<P: AsRef<Path>>(dirs: &[P]) -> Result<Self> {
+        let dirs: Vec<PathBuf> = dirs.iter().map(|d| d.as_ref().to_path_buf()).collect();
+        if let Some(dir) = dirs.iter().find(|d| !d.is_dir()) {
+            return Err(FsError::NotADirectory(dir.clone()));
+        }
+
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(|e| FsError::Watch(e.to_string()))?;
+        for dir in &dirs {
+            watcher.watch(dir, RecursiveMode::Recursive).map_err(|e| FsError::Watch(format!("{}: {}", dir.display(), e)))?;
+        }
+
+        Ok(LibraryWatcher { dirs, events, _watcher: watcher })
+    }
+
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// Wait up to `timeout` for something to change, then gather whatever
+    /// else changed with it
+    ///
+    /// # Returns
+    /// The files and directories created, changed, renamed (under both
+    /// names) or removed; empty if nothing changed in time
+    pub fn changes(&self, timeout: Duration) -> BTreeSet<PathBuf> {
+        let mut changed = BTreeSet::new();
+        let first = match self.events.recv_timeout(timeout) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return changed,
+        };
+
+        for event in std::iter::once(first).chain(self.events.try_iter()) {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!(error = %e, "watch error");
+                    continue;
+                }
+            };
+            let relevant = matches!(
+                event.kind,
+                EventKind::Create(_)
+                    | EventKind::Remove(_)
+                    | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any)
+            );
+            if !relevant {
+                continue;
+            }
+            for path in event.paths {
+                if self.is_reported(&path) {
+                    debug!(path = %path.display(), kind = ?event.kind, "changed");
+                    changed.insert(path);
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// Whether a change to `path` is worth reporting: an audio file or a
+    /// directory (or what may have been one, if it is gone), outside
+    /// hidden directories
+    fn is_reported(&self, path: &Path) -> bool {
+        let Some(root) = self.dirs.iter().find(|dir| path.starts_with(dir)) else {
+            return false;
+        };
+        let hidden = path
+            .strip_prefix(root)
+            .map(|rel| rel.components().any(|c| c.as_os_str().to_string_lossy().starts_with('.')))
+            .unwrap_or(false);
+        !hidden && (is_audio_file(path) || path.is_dir() || !path.exists())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_reports_audio_changes() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join(".review")).unwrap();
+        let watcher = LibraryWatcher::new(&[dir.path()]).unwrap();
+        assert!(LibraryWatcher::new(&[dir.path().join("missing")]).is_err());
+
+        fs::write(dir.path().join("notes.txt"), b"").unwrap();
+        fs::write(dir.path().join(".review/held.flac"), b"").unwrap();
+        fs::write(dir.path().join("01 Canada.flac"), b"").unwrap();
+
+        // Events may trickle in; wait until the audio file shows up
+        let mut changed = BTreeSet::new();
+        for _ in 0..20 {
+            changed.extend(watcher.changes(Duration::from_millis(250)));
+            if !changed.is_empty() {
+                break;
+            }
+        }
+        let canonical = dir.path().canonicalize().unwrap();
+        let names: Vec<_> = changed.iter().filter_map(|p| p.strip_prefix(&canonical).or(p.strip_prefix(dir.path())).ok()).collect();
+        assert_eq!(names, [Path::new("01 Canada.flac")]);
+    }
+}
@@ -0,0 +1,176 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use flacman_core::{DownloadUser, HttpRequest, HttpResponse};
+use sha2::{Digest, Sha256};
+use tracing::{debug, instrument, trace};
+
+use crate::mberror::{MbError, Result};
+
+
+pub const MUSICBRAINZ_API: &str = "https://musicbrainz.org/ws/2";
+
+const USER_AGENT: &str = concat!("flacman/", env!("CARGO_PKG_VERSION"));
+
+/// MusicBrainz allows one request per second from each client
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a cached response is used before asking again
+const CACHE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Extra attempts at a request turned away because of the rate limit
+const RETRIES: u32 = 2;
+
+/// Client for the MusicBrainz web service
+///
+/// Requests are spaced to stay within the rate limit, however many
+/// threads share the client, and with a cache directory every response is
+/// kept on disk so the same lookup isn't made twice within a month.
+#[derive(Debug)]
+pub struct MbClient {
+    base: String,
+    cache: Option<PathBuf>,
+    /// When the last request was sent
+    last: Mutex<Option<Instant>>,
+    download_user: Option<DownloadUser>,
+}
+
+impl Default for MbClient {
+    fn default() -> Self {
+        MbClient::new(MUSICBRAINZ_API)
+    }
+}
+
+impl MbClient {
+    /// A client for the service at `base`, e.g. [`MUSICBRAINZ_API`] or a
+    /// local mirror
+    pub fn new(base: &str) -> Self {
+        MbClient { base: base.trim_end_matches('/').to_owned(), cache: None, last: Mutex::new(None), download_user: None }
+    }
+
+    /// Keep responses in `dir`
+    pub fn with_cache<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.cache = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Make requests as `user`
+    pub fn with_download_user(mut self, user: Option<DownloadUser>) -> Self {
+        self.download_user = user;
+        self
+    }
+
+    /// The JSON response for `path` below the API root with `query`
+    ///
+    /// A fresh cached copy is used if there is one.
+    #[instrument(level = "debug", skip(self), err(level = "debug"))]
+    pub fn get(&self, path: &str, query: &[(&str, &str)]) -> Result<Vec<u8>> {
+        let key = query.iter().fold(path.to_owned(), |key, (name, value)| format!("{key}&{name}={value}"));
+        let cached = self.cache.as_ref().map(|dir| dir.join(format!("{}.json", hex_sha256(&key))));
+        if let Some(cached) = &cached
+            && is_fresh(cached)
+        {
+            trace!(cached = %cached.display(), "answered from the cache");
+            return Ok(fs::read(cached)?);
+        }
+
+        let data = self.send(HttpRequest::get, path, query, None)?.read_to_vec()?;
+        if let Some(cached) = &cached {
+            fs::create_dir_all(cached.parent().expect("cache entries are in the cache directory"))?;
+            fs::write(cached, &data)?;
+        }
+        Ok(data)
+    }
+
+    /// Like [`get`](Self::get), but always asking the service; with an
+    /// OAuth `token`, private data such as a private collection can be read
+    #[instrument(level = "debug", skip(self, token), err(level = "debug"))]
+    pub fn get_uncached(&self, path: &str, query: &[(&str, &str)], token: Option<&str>) -> Result<Vec<u8>> {
+        Ok(self.send(HttpRequest::get, path, query, token)?.read_to_vec()?)
+    }
+
+    /// Make a change at `path`, authorized by the OAuth `token`
+    #[instrument(level = "debug", skip(self, token), err(level = "debug"))]
+    pub fn put(&self, path: &str, query: &[(&str, &str)], token: &str) -> Result<()> {
+        self.send(HttpRequest::put, path, query, Some(token))?;
+        Ok(())
+    }
+
+    /// Send a request made by `method` for `path` in turn
+    ///
+    /// An answer of 503, which is how MusicBrainz turns away clients that
+    /// are too fast, is retried after a pause.
+    fn send(
+        &self,
+        method: fn(&str) -> HttpRequest,
+        path: &str,
+        query: &[(&str, &str)],
+        token: Option<&str>,
+    ) -> Result<HttpResponse> {
+        let mut attempt = 0;
+        loop {
+            self.wait_turn();
+            let mut request = method(&format!("{}/{}", self.base, path)).header("User-Agent", USER_AGENT).query("fmt", "json");
+            for (name, value) in query {
+                request = request.query(name, value);
+            }
+            if let Some(token) = token {
+                request = request.header("Authorization", &format!("Bearer {token}"));
+            }
+            match request.send(self.download_user.as_ref()) {
+                Ok(response) => return Ok(response),
+                Err(ureq::Error::StatusCode(503)) if attempt < RETRIES => {
+                    attempt += 1;
+                    debug!(attempt, "rate limited, retrying");
+                    thread::sleep(REQUEST_INTERVAL * 2u32.pow(attempt));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Sleep until a request may be sent, and claim the slot
+    fn wait_turn(&self) {
+        let mut last = self.last.lock().expect("request clock lock poisoned");
+        if let Some(wait) = last.map(|at| REQUEST_INTERVAL.saturating_sub(at.elapsed())) {
+            thread::sleep(wait);
+        }
+        *last = Some(Instant::now());
+    }
+
+    pub(crate) fn parse<T: serde::de::DeserializeOwned>(data: &[u8]) -> Result<T> {
+        serde_json::from_slice(data).map_err(|e| MbError::Response(e.to_string()))
+    }
+}
+
+fn is_fresh(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .is_ok_and(|modified| SystemTime::now().duration_since(modified).is_ok_and(|age| age < CACHE_TTL))
+}
+
+fn hex_sha256(text: &str) -> String {
+    Sha256::digest(text.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cached_response() {
+        let dir = tempdir().unwrap();
+        // Nothing listens there, so only the cache can answer
+        let client = MbClient::new("http://127.0.0.1:9").with_cache(dir.path());
+        let key = "release/b1c6d1b4&inc=recordings";
+        fs::write(dir.path().join(format!("{}.json", hex_sha256(key))), br#"{"id": "b1c6d1b4"}"#).unwrap();
+
+        let data = client.get("release/b1c6d1b4", &[("inc", "recordings")]).unwrap();
+        assert_eq!(data, br#"{"id": "b1c6d1b4"}"#);
+        assert!(client.get("release/b1c6d1b4", &[("inc", "labels")]).is_err());
+    }
+}
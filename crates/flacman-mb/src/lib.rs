@@ -0,0 +1,8 @@
+mod mberror;
+mod client;
+mod release;
+
+
+pub use mberror::{MbError, Result};
+pub use client::{MUSICBRAINZ_API, MbClient};
+pub use release::{MbMedium, MbRelease, MbTrack, ReleaseHit};
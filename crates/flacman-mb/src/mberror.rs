@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+
+#[derive(Error, Debug)]
+pub enum MbError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("HTTP error: {0}")]
+    Http(#[from] ureq::Error),
+
+    #[error("MusicBrainz: unexpected response: {0}")]
+    Response(String),
+}
+
+pub type Result<T> = std::result::Result<T, MbError>;
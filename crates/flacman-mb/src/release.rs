@@ -0,0 +1,275 @@
+use serde::{Deserialize, Serialize};
+
+use crate::client::MbClient;
+use crate::mberror::Result;
+
+
+/// Search hits scoring below this are too loose a match to trust
+const MIN_SCORE: u32 = 90;
+
+/// A release (one edition of an album) as MusicBrainz has it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MbRelease {
+    /// Release MBID
+    pub id: String,
+    pub title: String,
+    /// Album artist as credited, joined the way MusicBrainz displays it
+    pub artist: String,
+    /// MBID of the first credited artist
+    pub artist_id: Option<String>,
+    /// Release date, `YYYY`, `YYYY-MM` or `YYYY-MM-DD`
+    pub date: Option<String>,
+    pub release_group_id: Option<String>,
+    /// Discs, in order
+    pub media: Vec<MbMedium>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MbMedium {
+    /// Disc number, from 1
+    pub position: u32,
+    pub title: Option<String>,
+    pub tracks: Vec<MbTrack>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MbTrack {
+    /// Recording MBID
+    pub recording_id: String,
+    /// Track MBID
+    pub id: String,
+    pub title: String,
+    /// Track artist as credited
+    pub artist: String,
+    /// Position on its disc, from 1
+    pub position: u32,
+    pub length_ms: Option<u64>,
+}
+
+/// One result of [`MbClient::search_releases`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReleaseHit {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    /// How well the release matches the query, out of 100
+    pub score: u32,
+    pub track_count: Option<u32>,
+    pub date: Option<String>,
+}
+
+impl MbRelease {
+    /// Year the release came out
+    pub fn year(&self) -> Option<u32> {
+        self.date.as_deref().and_then(|date| date.get(..4)).and_then(|year| year.parse().ok())
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.media.iter().map(|m| m.tracks.len()).sum()
+    }
+
+    /// Track `number` of disc `disc`
+    pub fn track(&self, disc: u32, number: u32) -> Option<&MbTrack> {
+        self.media.iter().find(|m| m.position == disc)?.tracks.iter().find(|t| t.position == number)
+    }
+
+    /// All tracks, disc by disc, with their disc numbers
+    pub fn tracks(&self) -> impl Iterator<Item = (u32, &MbTrack)> {
+        self.media.iter().flat_map(|m| m.tracks.iter().map(move |t| (m.position, t)))
+    }
+}
+
+#[derive(Deserialize)]
+struct ArtistCredit {
+    name: String,
+    #[serde(default)]
+    joinphrase: String,
+    artist: Option<CreditedArtist>,
+}
+
+#[derive(Deserialize)]
+struct CreditedArtist {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct ReleaseGroupJson {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct RecordingJson {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct TrackJson {
+    id: String,
+    title: String,
+    position: u32,
+    length: Option<u64>,
+    recording: RecordingJson,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<ArtistCredit>,
+}
+
+#[derive(Deserialize)]
+struct MediumJson {
+    position: u32,
+    title: Option<String>,
+    #[serde(default)]
+    tracks: Vec<TrackJson>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseJson {
+    id: String,
+    title: String,
+    date: Option<String>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<ArtistCredit>,
+    #[serde(rename = "release-group")]
+    release_group: Option<ReleaseGroupJson>,
+    #[serde(default)]
+    media: Vec<MediumJson>,
+    score: Option<u32>,
+    #[serde(rename = "track-count")]
+    track_count: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct SearchJson {
+    #[serde(default)]
+    releases: Vec<ReleaseJson>,
+}
+
+/// An artist credit as one name, e.g. `Simon & Garfunkel`
+fn credit_name(credit: &[ArtistCredit]) -> String {
+    credit.iter().map(|c| format!("{}{}", c.name, c.joinphrase)).collect()
+}
+
+/// Empty strings in MusicBrainz JSON mean the field isn't set
+fn non_empty(text: Option<String>) -> Option<String> {
+    text.filter(|t| !t.is_empty())
+}
+
+impl From<ReleaseJson> for MbRelease {
+    fn from(json: ReleaseJson) -> Self {
+        MbRelease {
+            artist: credit_name(&json.artist_credit),
+            artist_id: json.artist_credit.iter().find_map(|c| c.artist.as_ref()).map(|a| a.id.clone()),
+            id: json.id,
+            title: json.title,
+            date: non_empty(json.date),
+            release_group_id: json.release_group.map(|g| g.id),
+            media: json
+                .media
+                .into_iter()
+                .map(|medium| MbMedium {
+                    position: medium.position,
+                    title: non_empty(medium.title),
+                    tracks: medium
+                        .tracks
+                        .into_iter()
+                        .map(|track| MbTrack {
+                            artist: credit_name(&track.artist_credit),
+                            recording_id: track.recording.id,
+                            id: track.id,
+                            title: track.title,
+                            position: track.position,
+                            length_ms: track.length,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Escape the characters Lucene gives a meaning to, so `text` is searched
+/// for as it is
+fn lucene_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "+-&|!(){}[]^\"~*?:\\/".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl MbClient {
+    /// The release with MBID `id`, with its discs and tracks
+    ///
+    /// # Errors
+    /// * `MbError::Http` - The request failed, or there is no such release
+    /// * `MbError::Response` - The answer wasn't a release
+    pub fn release(&self, id: &str) -> Result<MbRelease> {
+        let data = self.get(&format!("release/{id}"), &[("inc", "artist-credits+recordings+release-groups")])?;
+        Ok(MbClient::parse::<ReleaseJson>(&data)?.into())
+    }
+
+    /// Releases called `album` by `artist`, best match first
+    pub fn search_releases(&self, artist: &str, album: &str) -> Result<Vec<ReleaseHit>> {
+        let query = format!("release:\"{}\" AND artist:\"{}\"", lucene_escape(album), lucene_escape(artist));
+        let data = self.get("release", &[("query", &query), ("limit", "10")])?;
+        let hits = MbClient::parse::<SearchJson>(&data)?.releases.into_iter().map(|release| ReleaseHit {
+            artist: credit_name(&release.artist_credit),
+            id: release.id,
+            title: release.title,
+            score: release.score.unwrap_or(0),
+            track_count: release.track_count,
+            date: non_empty(release.date),
+        });
+        Ok(hits.collect())
+    }
+
+    /// The release best matching an album of `tracks` tracks called `album`
+    /// by `artist`
+    ///
+    /// Of the hits close enough to be trusted, one with the same number of
+    /// tracks wins over a better scoring one without.
+    ///
+    /// # Returns
+    /// `None` if nothing matches closely enough
+    pub fn find_release(&self, artist: &str, album: &str, tracks: usize) -> Result<Option<MbRelease>> {
+        let hits: Vec<ReleaseHit> =
+            self.search_releases(artist, album)?.into_iter().filter(|hit| hit.score >= MIN_SCORE).collect();
+        let best = hits.iter().find(|hit| hit.track_count == Some(tracks as u32)).or(hits.first());
+        best.map(|hit| self.release(&hit.id)).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_release() {
+        let json = br#"{
+            "id": "7c3218d7", "title": "Trust", "date": "2002-09-17",
+            "artist-credit": [{"name": "Low", "joinphrase": "", "artist": {"id": "6b5b8b5c", "name": "Low"}}],
+            "release-group": {"id": "d1a3f0f3"},
+            "media": [{"position": 1, "title": "", "tracks": [
+                {"id": "t1", "title": "(That's How You Sing) Amazing Grace", "position": 1, "length": 247000,
+                 "recording": {"id": "r1"},
+                 "artist-credit": [{"name": "Low", "joinphrase": " & ", "artist": {"id": "6b5b8b5c"}},
+                                   {"name": "Friends", "artist": {"id": "00000000"}}]},
+                {"id": "t2", "title": "Canada", "position": 2, "recording": {"id": "r2"},
+                 "artist-credit": [{"name": "Low"}]}]}]
+        }"#;
+        let release: MbRelease = MbClient::parse::<ReleaseJson>(json).unwrap().into();
+
+        assert_eq!((release.artist.as_str(), release.artist_id.as_deref()), ("Low", Some("6b5b8b5c")));
+        assert_eq!((release.year(), release.track_count()), (Some(2002), 2));
+        assert_eq!(release.release_group_id.as_deref(), Some("d1a3f0f3"));
+        assert_eq!(release.media[0].title, None);
+        let first = release.track(1, 1).unwrap();
+        assert_eq!((first.recording_id.as_str(), first.artist.as_str()), ("r1", "Low & Friends"));
+        assert_eq!(release.track(1, 2).unwrap().length_ms, None);
+        assert!(release.track(2, 1).is_none());
+
+        assert_eq!(lucene_escape("AC/DC: Live!"), "AC\\/DC\\: Live\\!");
+    }
+}
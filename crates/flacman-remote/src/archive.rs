@@ -0,0 +1,393 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use flacman_core::{DownloadUser, HttpRequest};
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use tracing::debug;
+
+use crate::mirror::{download_resuming, percent_encode};
+use crate::registry::SourceConfig;
+use crate::remoteerror::{RemoteError, Result};
+use crate::source::{RemoteAlbum, RemoteArtist, RemoteItem, RemoteKind, RemoteSource, RemoteTrack};
+
+
+const ARCHIVE_URL: &str = "https://archive.org";
+
+/// Items a search returns
+const SEARCH_ROWS: usize = 50;
+
+/// Audio formats archive.org derives files in, best first; the first one
+/// an item has is downloaded unless the album id names another
+const DEFAULT_FORMATS: &[&str] =
+    &["Flac", "24bit Flac", "Apple Lossless Audio", "VBR MP3", "Ogg Vorbis", "128Kbps MP3", "64Kbps MP3"];
+
+/// The Internet Archive's audio collections: live recordings, netlabel
+/// releases, digitized 78s and the like
+///
+/// Items are albums, identified by their archive.org identifier; one
+/// followed by `/` and a format, e.g. `gd1977-05-08.sbd.hicks/VBR MP3`,
+/// downloads that format instead of the preferred one. Track lists and
+/// checksums come from the item's `_files.xml`, and every download is
+/// checked against its SHA-1 there. Artists are the items' creators.
+pub struct ArchiveOrg {
+    name: String,
+    /// Root URL, without a trailing `/`
+    base: String,
+    /// Searches are limited to this collection, e.g. `etree`
+    collection: Option<String>,
+    formats: Vec<String>,
+    download_user: Option<DownloadUser>,
+}
+
+impl ArchiveOrg {
+    pub fn new(name: &str) -> Self {
+        ArchiveOrg {
+            name: name.to_owned(),
+            base: ARCHIVE_URL.to_owned(),
+            collection: None,
+            formats: DEFAULT_FORMATS.iter().map(|f| f.to_string()).collect(),
+            download_user: None,
+        }
+    }
+
+    /// [`SourceFactory`](crate::SourceFactory) of the `archive` kind
+    ///
+    /// `url` defaults to archive.org itself. Options:
+    /// * `collection` - Only search this collection
+    /// * `formats` - Comma-separated formats to download, best first
+    ///
+    /// # Errors
+    /// * `RemoteError::Config` - `url` is not an HTTP URL
+    pub fn from_config(config: &SourceConfig, download_user: Option<&DownloadUser>) -> Result<Box<dyn RemoteSource>> {
+        let mut source = ArchiveOrg { download_user: download_user.cloned(), ..ArchiveOrg::new(&config.name) };
+        match config.url.as_deref() {
+            Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+                source.base = url.trim_end_matches('/').to_owned();
+            }
+            Some(url) => return Err(RemoteError::Config(config.name.clone(), format!("{} is not an HTTP URL", url))),
+            None => {}
+        }
+        source.collection = config.options.get("collection").cloned();
+        if let Some(formats) = config.options.get("formats") {
+            source.formats = formats.split(',').map(|f| f.trim().to_owned()).filter(|f| !f.is_empty()).collect();
+        }
+        Ok(Box::new(source))
+    }
+
+    fn get_text(&self, url: &str) -> Result<String> {
+        debug!(source = %self.name, url, "fetching");
+        match HttpRequest::get(url).send(self.download_user.as_ref()) {
+            Ok(response) => Ok(response.read_to_string()?),
+            Err(ureq::Error::StatusCode(404)) => Err(RemoteError::NotFound(self.name.clone(), url.to_owned())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Audio items matching the search `query`, in archive.org's query syntax
+    fn items(&self, query: &str) -> Result<Vec<RemoteItem>> {
+        let mut query = format!("({}) AND mediatype:(audio)", query);
+        if let Some(collection) = &self.collection {
+            query.push_str(&format!(" AND collection:({})", collection));
+        }
+        let fields: String =
+            ["identifier", "title", "creator", "year", "format"].iter().map(|f| format!("&fl%5B%5D={}", f)).collect();
+        let url = format!(
+            "{}/advancedsearch.php?q={}{}&rows={}&output=json",
+            self.base,
+            percent_encode(&query),
+            fields,
+            SEARCH_ROWS
+        );
+        parse_search(&self.name, &self.get_text(&url)?, &self.formats)
+    }
+
+    /// Download URL of `file` in the item `identifier`
+    fn file_url(&self, identifier: &str, file: &str) -> String {
+        let segments: Vec<String> = file.split('/').map(percent_encode).collect();
+        format!("{}/download/{}/{}", self.base, percent_encode(identifier), segments.join("/"))
+    }
+}
+
+/// The first of `values` as text: archive.org gives a field one value as
+/// a string or number, several as an array
+fn first_text(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Array(values) => first_text(values.first()),
+        _ => None,
+    }
+    .map(|s| s.trim().to_owned())
+    .filter(|s| !s.is_empty())
+}
+
+fn all_text(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::Array(values)) => values.iter().filter_map(|v| first_text(Some(v))).collect(),
+        value => first_text(value).into_iter().collect(),
+    }
+}
+
+/// The year at the start of a date like `1977-05-08`
+fn year_of(date: &str) -> Option<u32> {
+    date.get(..4)?.parse().ok()
+}
+
+/// Items of an `advancedsearch.php` response, each with the audio formats
+/// among `formats` it has, best first
+fn parse_search(source: &str, text: &str, formats: &[String]) -> Result<Vec<RemoteItem>> {
+    let response: Value = serde_json::from_str(text)
+        .map_err(|e| RemoteError::Response(source.to_owned(), format!("bad search response: {}", e)))?;
+    let Some(docs) = response.pointer("/response/docs").and_then(Value::as_array) else {
+        return Err(RemoteError::Response(source.to_owned(), "search response without docs".to_owned()));
+    };
+
+    Ok(docs
+        .iter()
+        .filter_map(|doc| {
+            let id = first_text(doc.get("identifier"))?;
+            let offered = all_text(doc.get("format"));
+            Some(RemoteItem {
+                title: first_text(doc.get("title")).unwrap_or_else(|| id.clone()),
+                id,
+                kind: RemoteKind::Album,
+                artist: first_text(doc.get("creator")),
+                year: first_text(doc.get("year")).as_deref().and_then(year_of),
+                formats: formats.iter().filter(|f| offered.contains(f)).cloned().collect(),
+            })
+        })
+        .collect())
+}
+
+/// One `<file>` of an item's `_files.xml`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ItemFile {
+    name: String,
+    format: String,
+    title: Option<String>,
+    track: Option<u32>,
+    size: Option<u64>,
+    sha1: Option<String>,
+}
+
+fn parse_files(source: &str, xml: &str) -> Result<Vec<ItemFile>> {
+    let doc = roxmltree::Document::parse(xml)
+        .map_err(|e| RemoteError::Response(source.to_owned(), format!("bad _files.xml: {}", e)))?;
+
+    let mut files = Vec::new();
+    for file in doc.root_element().children().filter(|n| n.has_tag_name("file")) {
+        let Some(name) = file.attribute("name") else {
+            continue;
+        };
+        let child = |tag: &str| {
+            file.children()
+                .find(|n| n.has_tag_name(tag))
+                .and_then(|n| n.text())
+                .map(|t| t.trim().to_owned())
+                .filter(|t| !t.is_empty())
+        };
+        files.push(ItemFile {
+            name: name.to_owned(),
+            format: child("format").unwrap_or_default(),
+            title: child("title"),
+            // "3" or "03" or "3/12"
+            track: child("track").and_then(|t| t.split('/').next()?.trim().parse().ok()),
+            size: child("size").and_then(|s| s.parse().ok()),
+            sha1: child("sha1").map(|s| s.to_lowercase()),
+        });
+    }
+    Ok(files)
+}
+
+/// The files of one format in track order, as tracks of the item `identifier`
+///
+/// Files without a track number are numbered after those with one, in
+/// name order.
+fn format_tracks(identifier: &str, files: &[ItemFile], format: &str) -> Vec<RemoteTrack> {
+    let mut files: Vec<&ItemFile> = files.iter().filter(|f| f.format == format).collect();
+    files.sort_by(|a, b| (a.track.is_none(), a.track, &a.name).cmp(&(b.track.is_none(), b.track, &b.name)));
+
+    files
+        .into_iter()
+        .enumerate()
+        .map(|(i, file)| {
+            let file_name = file.name.rsplit('/').next().unwrap_or(&file.name).to_owned();
+            let stem = Path::new(&file_name).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            RemoteTrack {
+                id: format!("{}/{}", identifier, file.name),
+                title: file.title.clone().unwrap_or(stem),
+                number: file.track.or(Some(i as u32 + 1)),
+                file_name,
+                size: file.size,
+                sha256: None,
+                sha1: file.sha1.clone(),
+            }
+        })
+        .collect()
+}
+
+fn sha1_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha1::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+impl RemoteSource for ArchiveOrg {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> &'static str {
+        "archive"
+    }
+
+    fn search(&self, kind: RemoteKind, query: &str) -> Result<Vec<RemoteItem>> {
+        match kind {
+            RemoteKind::Album => self.items(query),
+            RemoteKind::Artist => {
+                let lower = query.to_lowercase();
+                let mut artists: Vec<String> = self
+                    .items(&format!("creator:({})", query))?
+                    .into_iter()
+                    .filter_map(|item| item.artist)
+                    .filter(|artist| artist.to_lowercase().contains(&lower))
+                    .collect();
+                artists.sort_unstable();
+                artists.dedup();
+                Ok(artists
+                    .into_iter()
+                    .map(|artist| RemoteItem {
+                        id: artist.clone(),
+                        kind: RemoteKind::Artist,
+                        title: artist,
+                        artist: None,
+                        year: None,
+                        formats: Vec::new(),
+                    })
+                    .collect())
+            }
+            RemoteKind::Track => Err(RemoteError::Unsupported(self.name.clone(), "search for tracks")),
+        }
+    }
+
+    fn get_artist(&self, id: &str) -> Result<RemoteArtist> {
+        let quoted = format!("\"{}\"", id.replace('"', ""));
+        let albums: Vec<RemoteItem> =
+            self.items(&format!("creator:{}", quoted))?.into_iter().filter(|a| a.artist.as_deref() == Some(id)).collect();
+        if albums.is_empty() {
+            return Err(RemoteError::NotFound(self.name.clone(), id.to_owned()));
+        }
+        Ok(RemoteArtist { id: id.to_owned(), name: id.to_owned(), albums })
+    }
+
+    fn get_album(&self, id: &str) -> Result<RemoteAlbum> {
+        let (identifier, format) = match id.split_once('/') {
+            Some((identifier, format)) => (identifier, Some(format)),
+            None => (id, None),
+        };
+
+        let metadata: Value = serde_json::from_str(&self.get_text(&format!("{}/metadata/{}", self.base, identifier))?)
+            .map_err(|e| RemoteError::Response(self.name.clone(), format!("bad metadata: {}", e)))?;
+        // Unknown identifiers get an empty object rather than a 404
+        let Some(metadata) = metadata.get("metadata") else {
+            return Err(RemoteError::NotFound(self.name.clone(), identifier.to_owned()));
+        };
+        let files = parse_files(&self.name, &self.get_text(&self.file_url(identifier, &format!("{}_files.xml", identifier)))?)?;
+
+        let offered: Vec<&String> = self.formats.iter().filter(|f| files.iter().any(|file| file.format == **f)).collect();
+        let format = match format {
+            Some(format) if files.iter().any(|f| f.format == format) => format,
+            None if !offered.is_empty() => offered[0].as_str(),
+            _ => {
+                let offered: Vec<&str> = offered.iter().map(|f| f.as_str()).collect();
+                let wanted = format.map_or_else(|| self.formats.join(", "), str::to_owned);
+                let reason = format!("{} in {} (it has: {})", identifier, wanted, offered.join(", "));
+                return Err(RemoteError::NotFound(self.name.clone(), reason));
+            }
+        };
+
+        Ok(RemoteAlbum {
+            id: id.to_owned(),
+            title: first_text(metadata.get("title")).unwrap_or_else(|| identifier.to_owned()),
+            artist: first_text(metadata.get("creator")).unwrap_or_default(),
+            year: first_text(metadata.get("year")).or_else(|| first_text(metadata.get("date"))).as_deref().and_then(year_of),
+            tracks: format_tracks(identifier, &files, format),
+        })
+    }
+
+    fn download_track(&self, track: &RemoteTrack, dest: &Path, progress: &dyn Fn(u64, Option<u64>)) -> Result<u64> {
+        let Some((identifier, file)) = track.id.split_once('/') else {
+            return Err(RemoteError::NotFound(self.name.clone(), track.id.clone()));
+        };
+        let url = self.file_url(identifier, file);
+        download_resuming(&self.name, &url, track, dest, self.download_user.as_ref(), progress)?;
+
+        if let Some(expected) = &track.sha1
+            && sha1_file(dest)? != *expected
+        {
+            fs::remove_file(dest)?;
+            return Err(RemoteError::Checksum(dest.to_path_buf()));
+        }
+
+        Ok(fs::metadata(dest)?.len())
+    }
+
+    fn refresh(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const FILES_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<files>
+  <file name="gd77-05-08d1t02.flac" source="original">
+    <title>Loser</title><track>02</track><format>Flac</format>
+    <size>3</size><sha1>A9993E364706816ABA3E25717850C26C9CD0D89D</sha1>
+  </file>
+  <file name="gd77-05-08d1t01.flac" source="original">
+    <title>New Minglewood Blues</title><track>1/2</track><format>Flac</format>
+  </file>
+  <file name="gd77-05-08d1t01.mp3" source="derivative"><format>VBR MP3</format></file>
+  <file name="gd77-05-08_files.xml" source="original"><format>Metadata</format></file>
+</files>"#;
+
+    #[test]
+    fn test_search_and_files() {
+        let formats: Vec<String> = DEFAULT_FORMATS.iter().map(|f| f.to_string()).collect();
+        let search = r#"{"response": {"numFound": 2, "docs": [
+            {"identifier": "gd77-05-08", "title": "Grateful Dead Live at Barton Hall", "creator": ["Grateful Dead"],
+             "year": "1977", "format": ["VBR MP3", "Flac", "Metadata"]},
+            {"identifier": "untitled"}]}}"#;
+        let items = parse_search("archive", search, &formats).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].artist.as_deref(), Some("Grateful Dead"));
+        assert_eq!((items[0].year, items[0].formats.clone()), (Some(1977), vec!["Flac".to_owned(), "VBR MP3".to_owned()]));
+        assert_eq!(items[1].title, "untitled");
+        assert!(parse_search("archive", "{}", &formats).is_err());
+
+        let files = parse_files("archive", FILES_XML).unwrap();
+        assert_eq!(files.len(), 4);
+        let tracks = format_tracks("gd77-05-08", &files, "Flac");
+        assert_eq!(tracks.iter().map(|t| t.title.as_str()).collect::<Vec<_>>(), ["New Minglewood Blues", "Loser"]);
+        assert_eq!(tracks[1].id, "gd77-05-08/gd77-05-08d1t02.flac");
+        assert_eq!(tracks[1].sha1.as_deref(), Some("a9993e364706816aba3e25717850c26c9cd0d89d"));
+        assert_eq!(format_tracks("gd77-05-08", &files, "VBR MP3")[0].title, "gd77-05-08d1t01");
+
+        let archive = ArchiveOrg::new("archive");
+        assert_eq!(archive.file_url("gd77-05-08", "d1/t 01.flac"), "https://archive.org/download/gd77-05-08/d1/t%2001.flac");
+    }
+
+    #[test]
+    fn test_sha1_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("abc");
+        fs::write(&path, b"abc").unwrap();
+        assert_eq!(sha1_file(&path).unwrap(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::remoteerror::{RemoteError, Result};
+use crate::source::{RemoteAlbum, RemoteArtist, RemoteItem, RemoteKind, RemoteSource, RemoteTrack};
+
+
+/// Catalogs older than this are fetched again before they answer
+const DEFAULT_TTL_HOURS: i64 = 24;
+
+/// What [`RemoteSource::fetch_catalog`] found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CatalogFetch {
+    /// The catalog is still the one the validator was given for
+    Unchanged,
+    Changed {
+        /// Every album the source offers
+        albums: Vec<RemoteItem>,
+        /// Passed back on the next fetch, e.g. the HTTP ETag
+        validator: Option<String>,
+    },
+}
+
+/// What a source offers, as far as it was fetched: every album, and the
+/// track lists of those looked at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Catalog {
+    pub fetched: DateTime<Local>,
+    pub validator: Option<String>,
+    pub albums: Vec<RemoteItem>,
+    /// By album id
+    #[serde(default)]
+    pub tracks: BTreeMap<String, Vec<RemoteTrack>>,
+}
+
+impl Catalog {
+    /// Artists and albums whose name contains `query`, as a source's own
+    /// search finds them; tracks aren't listed in a catalog
+    pub fn search(&self, kind: RemoteKind, query: &str) -> Option<Vec<RemoteItem>> {
+        let query = query.to_lowercase();
+        match kind {
+            RemoteKind::Artist => {
+                let mut artists: Vec<&str> = self
+                    .albums
+                    .iter()
+                    .filter_map(|a| a.artist.as_deref())
+                    .filter(|artist| artist.to_lowercase().contains(&query))
+                    .collect();
+                artists.sort_unstable();
+                artists.dedup();
+                Some(
+                    artists
+                        .into_iter()
+                        .map(|artist| RemoteItem {
+                            id: artist.to_owned(),
+                            kind: RemoteKind::Artist,
+                            title: artist.to_owned(),
+                            artist: None,
+                            year: None,
+                            formats: Vec::new(),
+                        })
+                        .collect(),
+                )
+            }
+            RemoteKind::Album => Some(
+                self.albums
+                    .iter()
+                    .filter(|a| format!("{} - {}", a.artist.as_deref().unwrap_or(""), a.title).to_lowercase().contains(&query))
+                    .cloned()
+                    .collect(),
+            ),
+            RemoteKind::Track => None,
+        }
+    }
+
+    /// The artist `id` and their albums, if the catalog has any
+    pub fn artist(&self, id: &str) -> Option<RemoteArtist> {
+        let albums: Vec<RemoteItem> = self.albums.iter().filter(|a| a.artist.as_deref() == Some(id)).cloned().collect();
+        (!albums.is_empty()).then(|| RemoteArtist { id: id.to_owned(), name: id.to_owned(), albums })
+    }
+
+    /// The album `id` with its track list, if that was fetched
+    pub fn album(&self, id: &str) -> Option<RemoteAlbum> {
+        let album = self.albums.iter().find(|a| a.id == id)?;
+        Some(RemoteAlbum {
+            id: album.id.clone(),
+            title: album.title.clone(),
+            artist: album.artist.clone().unwrap_or_default(),
+            year: album.year,
+            tracks: self.tracks.get(id)?.clone(),
+        })
+    }
+}
+
+/// The catalogs of remote sources, one JSON file per source, so `-Ss` can
+/// be answered without asking the source every time, or at all when it
+/// can't be reached
+#[derive(Debug, Clone)]
+pub struct CatalogCache {
+    dir: PathBuf,
+    pub ttl: TimeDelta,
+}
+
+impl CatalogCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        CatalogCache { dir: dir.as_ref().to_path_buf(), ttl: TimeDelta::hours(DEFAULT_TTL_HOURS) }
+    }
+
+    fn path(&self, source: &str) -> PathBuf {
+        let name: String = source.chars().map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' }).collect();
+        self.dir.join(format!("{}.json", name))
+    }
+
+    /// The cached catalog of `source`, however old
+    pub fn load(&self, source: &str) -> Result<Option<Catalog>> {
+        match fs::read(self.path(source)) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map(Some)
+                .map_err(|e| RemoteError::Response(source.to_owned(), format!("bad cached catalog: {}", e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn store(&self, source: &str, catalog: &Catalog) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let data = serde_json::to_vec(catalog).map_err(|e| RemoteError::Response(source.to_owned(), e.to_string()))?;
+        fs::write(self.path(source), data)?;
+        Ok(())
+    }
+
+    pub fn remove(&self, source: &str) -> Result<()> {
+        match fs::remove_file(self.path(source)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_stale(&self, catalog: &Catalog, now: DateTime<Local>) -> bool {
+        now - catalog.fetched > self.ttl
+    }
+}
+
+/// Where the catalog a [`CachedSource`] answers from came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CatalogOrigin {
+    /// The cache, fresh enough not to ask the source
+    Cached,
+    /// The cache, after the source said nothing changed
+    Unchanged,
+    /// The source
+    Downloaded,
+    /// The cache, because the source couldn't be reached for the reason given
+    Offline(String),
+}
+
+/// The catalog a [`CachedSource`] answers from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogStatus {
+    pub origin: CatalogOrigin,
+    pub fetched: DateTime<Local>,
+    pub albums: usize,
+    /// Older than the cache's time to live; the source may have changed since
+    pub stale: bool,
+}
+
+/// A source with its catalog cached on disk
+///
+/// Artist and album searches, artists and the track lists already seen
+/// are answered from the catalog; the catalog is fetched again once it is
+/// older than the cache's time to live. Refreshing like `-y` always asks
+/// the source whether its catalog changed, and `-yy` downloads it in full
+/// and forgets the track lists too. When the source can't be reached, an
+/// old catalog answers rather than nothing. Sources that can't list their
+/// catalog are asked directly.
+pub struct CachedSource {
+    inner: Box<dyn RemoteSource>,
+    cache: CatalogCache,
+    refresh: u8,
+    /// Loaded on first use
+    catalog: Mutex<Option<Option<(Catalog, CatalogStatus)>>>,
+}
+
+impl CachedSource {
+    pub fn new(inner: Box<dyn RemoteSource>, cache: CatalogCache, refresh: u8) -> Self {
+        CachedSource { inner, cache, refresh, catalog: Mutex::new(None) }
+    }
+
+    /// Bring the catalog up to date as the refresh level asks
+    ///
+    /// # Returns
+    /// Where it came from; None if the source has no catalog to cache
+    ///
+    /// # Errors
+    /// Whatever kept the source from answering, if nothing is cached
+    pub fn update(&self) -> Result<Option<CatalogStatus>> {
+        let mut loaded = self.catalog.lock().expect("catalog lock poisoned");
+        if loaded.is_none() {
+            *loaded = Some(self.fetch(Local::now())?);
+        }
+        Ok(loaded.as_ref().and_then(|c| c.as_ref()).map(|(_, status)| status.clone()))
+    }
+
+    fn fetch(&self, now: DateTime<Local>) -> Result<Option<(Catalog, CatalogStatus)>> {
+        let name = self.inner.name();
+        // In full, but the old catalog still answers if the source can't
+        let full = self.refresh >= 2;
+        let cached = self.cache.load(name).unwrap_or_else(|e| {
+            warn!(source = name, error = %e, "ignoring unreadable catalog");
+            None
+        });
+        let status = |catalog: &Catalog, origin| CatalogStatus {
+            origin,
+            fetched: catalog.fetched,
+            albums: catalog.albums.len(),
+            stale: self.cache.is_stale(catalog, now),
+        };
+
+        if let Some(catalog) = cached.as_ref().filter(|c| self.refresh == 0 && !self.cache.is_stale(c, now)) {
+            let status = status(catalog, CatalogOrigin::Cached);
+            return Ok(Some((cached.expect("checked above"), status)));
+        }
+
+        debug!(source = name, refresh = self.refresh, "fetching catalog");
+        let validator = cached.as_ref().filter(|_| !full).and_then(|c| c.validator.as_deref());
+        let catalog = match (self.inner.fetch_catalog(validator), cached) {
+            (Err(RemoteError::Unsupported(..)), _) => return Ok(None),
+            (Ok(CatalogFetch::Unchanged), Some(catalog)) => {
+                let catalog = Catalog { fetched: now, ..catalog };
+                let status = status(&catalog, CatalogOrigin::Unchanged);
+                self.store(&catalog);
+                return Ok(Some((catalog, status)));
+            }
+            // Sources without a validator send the same catalog again
+            (Ok(CatalogFetch::Changed { albums, validator }), Some(catalog)) if !full && albums == catalog.albums => {
+                let catalog = Catalog { fetched: now, validator, ..catalog };
+                let status = status(&catalog, CatalogOrigin::Unchanged);
+                self.store(&catalog);
+                return Ok(Some((catalog, status)));
+            }
+            (Ok(CatalogFetch::Changed { albums, validator }), cached) => {
+                // Track lists of albums listed as before are kept
+                let mut tracks = BTreeMap::new();
+                if let Some(mut cached) = cached.filter(|_| !full) {
+                    for album in albums.iter().filter(|a| cached.albums.contains(a)) {
+                        tracks.extend(cached.tracks.remove_entry(&album.id));
+                    }
+                }
+                Catalog { fetched: now, validator, albums, tracks }
+            }
+            (Ok(CatalogFetch::Unchanged), None) => {
+                let reason = "claims an unchanged catalog without having been asked about one";
+                return Err(RemoteError::Response(name.to_owned(), reason.to_owned()));
+            }
+            (Err(e), Some(catalog)) => {
+                let status = status(&catalog, CatalogOrigin::Offline(e.to_string()));
+                return Ok(Some((catalog, status)));
+            }
+            (Err(e), None) => return Err(e),
+        };
+
+        let status = status(&catalog, CatalogOrigin::Downloaded);
+        self.store(&catalog);
+        Ok(Some((catalog, status)))
+    }
+
+    fn store(&self, catalog: &Catalog) {
+        if let Err(e) = self.cache.store(self.inner.name(), catalog) {
+            warn!(source = self.inner.name(), error = %e, "could not cache catalog");
+        }
+    }
+
+    /// Answer from the catalog with `answer`, or from the source with
+    /// `ask` if there is no catalog or it has no answer
+    fn answer<T>(&self, answer: impl FnOnce(&Catalog) -> Option<T>, ask: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.update()?;
+        let loaded = self.catalog.lock().expect("catalog lock poisoned");
+        match loaded.as_ref().and_then(|c| c.as_ref()).and_then(|(catalog, _)| answer(catalog)) {
+            Some(found) => Ok(found),
+            None => {
+                drop(loaded);
+                ask()
+            }
+        }
+    }
+}
+
+impl RemoteSource for CachedSource {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn kind(&self) -> &'static str {
+        self.inner.kind()
+    }
+
+    fn search(&self, kind: RemoteKind, query: &str) -> Result<Vec<RemoteItem>> {
+        self.answer(|catalog| catalog.search(kind, query), || self.inner.search(kind, query))
+    }
+
+    fn get_artist(&self, id: &str) -> Result<RemoteArtist> {
+        self.answer(|catalog| catalog.artist(id), || self.inner.get_artist(id))
+    }
+
+    fn get_album(&self, id: &str) -> Result<RemoteAlbum> {
+        let fetched = self.answer(|catalog| catalog.album(id).map(|album| (album, false)), || {
+            self.inner.get_album(id).map(|album| (album, true))
+        });
+        let (album, fetched) = fetched?;
+
+        if fetched
+            && let Some(Some((catalog, _))) = self.catalog.lock().expect("catalog lock poisoned").as_mut()
+            && catalog.albums.iter().any(|a| a.id == album.id)
+        {
+            catalog.tracks.insert(album.id.clone(), album.tracks.clone());
+            self.store(catalog);
+        }
+        Ok(album)
+    }
+
+    fn download_track(&self, track: &RemoteTrack, dest: &Path, progress: &dyn Fn(u64, Option<u64>)) -> Result<u64> {
+        self.inner.download_track(track, dest, progress)
+    }
+
+    fn fetch_catalog(&self, validator: Option<&str>) -> Result<CatalogFetch> {
+        self.inner.fetch_catalog(validator)
+    }
+
+    /// Forget the cached catalog, on disk too
+    fn refresh(&self) -> Result<()> {
+        *self.catalog.lock().expect("catalog lock poisoned") = None;
+        self.cache.remove(self.inner.name())?;
+        self.inner.refresh()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    /// A source with two albums, counting how often it is asked
+    struct Counting {
+        fetches: Arc<AtomicUsize>,
+        offline: bool,
+    }
+
+    fn album(title: &str) -> RemoteItem {
+        let id = format!("Low/{}", title);
+        RemoteItem { id, kind: RemoteKind::Album, title: title.to_owned(), artist: Some("Low".to_owned()), year: None, formats: Vec::new() }
+    }
+
+    impl RemoteSource for Counting {
+        fn name(&self) -> &str {
+            "nas"
+        }
+
+        fn kind(&self) -> &'static str {
+            "test"
+        }
+
+        fn search(&self, _: RemoteKind, _: &str) -> Result<Vec<RemoteItem>> {
+            Err(RemoteError::Unsupported("nas".to_owned(), "search"))
+        }
+
+        fn get_artist(&self, id: &str) -> Result<RemoteArtist> {
+            Err(RemoteError::NotFound("nas".to_owned(), id.to_owned()))
+        }
+
+        fn get_album(&self, id: &str) -> Result<RemoteAlbum> {
+            let track = RemoteTrack {
+                id: format!("{}/01 Canada.flac", id),
+                title: "Canada".to_owned(),
+                number: Some(1),
+                file_name: "01 Canada.flac".to_owned(),
+                size: None,
+                sha256: None,
+                sha1: None,
+            };
+            Ok(RemoteAlbum { id: id.to_owned(), title: "Trust".to_owned(), artist: "Low".to_owned(), year: None, tracks: vec![track] })
+        }
+
+        fn download_track(&self, _: &RemoteTrack, _: &Path, _: &dyn Fn(u64, Option<u64>)) -> Result<u64> {
+            Err(RemoteError::Unsupported("nas".to_owned(), "download"))
+        }
+
+        fn fetch_catalog(&self, validator: Option<&str>) -> Result<CatalogFetch> {
+            if self.offline {
+                return Err(RemoteError::Response("nas".to_owned(), "connection refused".to_owned()));
+            }
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            if validator == Some("v1") {
+                return Ok(CatalogFetch::Unchanged);
+            }
+            Ok(CatalogFetch::Changed { albums: vec![album("Trust"), album("Secret Name")], validator: Some("v1".to_owned()) })
+        }
+
+        fn refresh(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_refresh_levels() {
+        let dir = tempdir().unwrap();
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let source = |refresh: u8, offline: bool| {
+            let inner = Box::new(Counting { fetches: fetches.clone(), offline });
+            CachedSource::new(inner, CatalogCache::new(dir.path()), refresh)
+        };
+
+        // First use downloads the catalog; the track list is cached as it is fetched
+        let first = source(0, false);
+        assert_eq!(first.search(RemoteKind::Album, "low - trust").unwrap(), [album("Trust")]);
+        assert_eq!(first.get_album("Low/Trust").unwrap().tracks.len(), 1);
+        assert_eq!(first.update().unwrap().unwrap().origin, CatalogOrigin::Downloaded);
+        assert_eq!(first.get_artist("Low").unwrap().albums.len(), 2);
+
+        // Fresh catalogs answer without asking
+        assert_eq!(source(0, false).update().unwrap().unwrap().origin, CatalogOrigin::Cached);
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+
+        // -y asks, and keeps the track lists when nothing changed
+        let refreshed = source(1, false);
+        assert_eq!(refreshed.update().unwrap().unwrap().origin, CatalogOrigin::Unchanged);
+        assert!(CatalogCache::new(dir.path()).load("nas").unwrap().unwrap().tracks.contains_key("Low/Trust"));
+
+        // -yy downloads it all again
+        assert_eq!(source(2, false).update().unwrap().unwrap().origin, CatalogOrigin::Downloaded);
+        assert!(CatalogCache::new(dir.path()).load("nas").unwrap().unwrap().tracks.is_empty());
+        assert_eq!(fetches.load(Ordering::Relaxed), 3);
+
+        // Offline, the cache answers
+        let offline = source(1, true);
+        assert!(matches!(offline.update().unwrap().unwrap().origin, CatalogOrigin::Offline(_)));
+        assert_eq!(offline.search(RemoteKind::Artist, "lo").unwrap().len(), 1);
+
+        offline.refresh().unwrap();
+        assert!(offline.update().is_err());
+    }
+}
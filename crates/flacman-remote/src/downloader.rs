@@ -0,0 +1,285 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, instrument};
+
+use crate::remoteerror::RemoteError;
+use crate::source::{RemoteSource, RemoteTrack};
+
+
+/// How often a worker waiting out a backoff checks for cancellation
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+/// One track to download, and the file to download it into
+///
+/// An existing `dest` is taken as the start of an earlier attempt and
+/// resumed, so it should be a stable per-track partial file.
+#[derive(Debug, Clone)]
+pub struct DownloadJob {
+    pub track: RemoteTrack,
+    pub dest: PathBuf,
+}
+
+/// What happened to a job, reported as it happens
+#[derive(Debug)]
+pub enum DownloadEvent<'a> {
+    Started,
+    /// Bytes downloaded so far and the full size, if known
+    Progress(u64, Option<u64>),
+    /// The attempt failed and the job is tried again after `delay`
+    Retrying { attempt: u32, error: &'a RemoteError, delay: Duration },
+    /// Size of the finished file
+    Finished(u64),
+    Failed(&'a RemoteError),
+}
+
+/// Outcome of [`Downloader::run`]
+#[derive(Debug, Default)]
+pub struct DownloadReport {
+    /// Index of each finished job, with its size
+    pub finished: Vec<(usize, u64)>,
+    /// Index of each job that failed for good, with the last error
+    pub failed: Vec<(usize, RemoteError)>,
+    /// Jobs were left unstarted because of cancellation
+    pub interrupted: bool,
+}
+
+impl DownloadReport {
+    pub fn bytes(&self) -> u64 {
+        self.finished.iter().map(|(_, size)| size).sum()
+    }
+}
+
+/// Downloads tracks from a source on a pool of worker threads
+///
+/// A failure that may be temporary (see [`RemoteError::is_transient`]) is
+/// retried with exponential backoff; each retry resumes the partial file.
+#[derive(Debug, Clone)]
+pub struct Downloader {
+    concurrency: usize,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Downloader {
+    /// A downloader running up to `concurrency` downloads at once, retrying
+    /// each up to 3 times from 2 seconds apart
+    pub fn new(concurrency: usize) -> Self {
+        Downloader { concurrency: concurrency.max(1), retries: 3, backoff: Duration::from_secs(2) }
+    }
+
+    /// Retry a failed job up to `retries` times, waiting `backoff` before the
+    /// first retry and twice as long before each one after
+    pub fn with_retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Download `jobs` from `source`
+    ///
+    /// # Arguments
+    /// * `cancel` - Stops the workers from starting further jobs or retries;
+    ///   downloads under way run to the end of their current attempt
+    /// * `on_event` - Called from the workers with the index of the job
+    ///   each event is about
+    pub fn run<F>(
+        &self,
+        source: &dyn RemoteSource,
+        jobs: &[DownloadJob],
+        cancel: &AtomicBool,
+        on_event: F,
+    ) -> DownloadReport
+    where
+        F: Fn(usize, DownloadEvent) + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let report = Mutex::new(DownloadReport::default());
+
+        thread::scope(|scope| {
+            for _ in 0..self.concurrency.min(jobs.len()) {
+                scope.spawn(|| {
+                    while !cancel.load(Ordering::Relaxed) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(job) = jobs.get(i) else {
+                            break;
+                        };
+
+                        on_event(i, DownloadEvent::Started);
+                        match self.download(source, job, cancel, |event| on_event(i, event)) {
+                            Ok(size) => {
+                                on_event(i, DownloadEvent::Finished(size));
+                                report.lock().expect("download report lock poisoned").finished.push((i, size));
+                            }
+                            Err(e) => {
+                                on_event(i, DownloadEvent::Failed(&e));
+                                report.lock().expect("download report lock poisoned").failed.push((i, e));
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        let mut report = report.into_inner().expect("download report lock poisoned");
+        report.interrupted = report.finished.len() + report.failed.len() < jobs.len();
+        report.finished.sort_unstable();
+        report.failed.sort_unstable_by_key(|(i, _)| *i);
+        report
+    }
+
+    /// Download one job, retrying while the failures look temporary
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(source = source.name(), track = %job.track.id, dest = %job.dest.display()),
+        err(level = "debug")
+    )]
+    fn download(
+        &self,
+        source: &dyn RemoteSource,
+        job: &DownloadJob,
+        cancel: &AtomicBool,
+        on_event: impl Fn(DownloadEvent),
+    ) -> crate::Result<u64> {
+        let mut attempt = 0;
+        loop {
+            let error = match source.download_track(&job.track, &job.dest, &|done, total| {
+                on_event(DownloadEvent::Progress(done, total))
+            }) {
+                Ok(size) => return Ok(size),
+                Err(e) => e,
+            };
+            if attempt >= self.retries || !error.is_transient() || cancel.load(Ordering::Relaxed) {
+                return Err(error);
+            }
+
+            attempt += 1;
+            let delay = self.backoff * 2u32.saturating_pow(attempt - 1);
+            debug!(attempt, %error, ?delay, "retrying download");
+            on_event(DownloadEvent::Retrying { attempt, error: &error, delay });
+            let until = Instant::now() + delay;
+            while Instant::now() < until {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(error);
+                }
+                thread::sleep(CANCEL_POLL.min(until.saturating_duration_since(Instant::now())));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{RemoteAlbum, RemoteArtist, RemoteItem, RemoteKind};
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    /// Serves "0123456789" for every track, cutting the first two attempts
+    /// at each track short after four bytes
+    struct Flaky {
+        attempts: Mutex<Vec<String>>,
+    }
+
+    impl RemoteSource for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn kind(&self) -> &'static str {
+            "test"
+        }
+
+        fn search(&self, _: RemoteKind, _: &str) -> crate::Result<Vec<RemoteItem>> {
+            Ok(Vec::new())
+        }
+
+        fn get_artist(&self, id: &str) -> crate::Result<RemoteArtist> {
+            Err(RemoteError::NotFound("flaky".into(), id.into()))
+        }
+
+        fn get_album(&self, id: &str) -> crate::Result<RemoteAlbum> {
+            Err(RemoteError::NotFound("flaky".into(), id.into()))
+        }
+
+        fn download_track(
+            &self,
+            track: &RemoteTrack,
+            dest: &Path,
+            progress: &dyn Fn(u64, Option<u64>),
+        ) -> crate::Result<u64> {
+            if track.id == "gone" {
+                return Err(RemoteError::NotFound("flaky".into(), track.id.clone()));
+            }
+            let tries = {
+                let mut attempts = self.attempts.lock().unwrap();
+                attempts.push(track.id.clone());
+                attempts.iter().filter(|id| **id == track.id).count()
+            };
+
+            let data = b"0123456789";
+            let have = fs::metadata(dest).map(|m| m.len() as usize).unwrap_or(0);
+            let end = if tries <= 2 { (have + 4).min(data.len()) } else { data.len() };
+            OpenOptions::new().create(true).append(true).open(dest)?.write_all(&data[have..end])?;
+            progress(end as u64, Some(data.len() as u64));
+            if end < data.len() {
+                return Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset).into());
+            }
+            Ok(end as u64)
+        }
+
+        fn refresh(&self) -> crate::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_retry_and_resume() {
+        let dir = tempdir().unwrap();
+        let job = |id: &str| DownloadJob {
+            track: RemoteTrack {
+                id: id.to_owned(),
+                title: id.to_owned(),
+                number: None,
+                file_name: format!("{id}.flac"),
+                size: None,
+                sha256: None,
+                sha1: None,
+            },
+            dest: dir.path().join(format!("{id}.part")),
+        };
+        let jobs = [job("a"), job("b"), job("gone"), job("c")];
+        let source = Flaky { attempts: Mutex::new(Vec::new()) };
+        let retries = AtomicUsize::new(0);
+
+        let downloader = Downloader::new(2).with_retries(3, Duration::from_millis(1));
+        let report = downloader.run(&source, &jobs, &AtomicBool::new(false), |_, event| {
+            if let DownloadEvent::Retrying { .. } = event {
+                retries.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+
+        assert_eq!(report.finished, [(0, 10), (1, 10), (3, 10)]);
+        assert_eq!(report.bytes(), 30);
+        assert!(matches!(report.failed[..], [(2, RemoteError::NotFound(..))]));
+        assert!(!report.interrupted);
+        assert_eq!(retries.load(Ordering::Relaxed), 6);
+        assert_eq!(fs::read(dir.path().join("b.part")).unwrap(), b"0123456789");
+
+        // Without enough retries the partial file is kept for next time
+        let fresh = Flaky { attempts: Mutex::new(Vec::new()) };
+        let once = Downloader::new(1).with_retries(1, Duration::from_millis(1));
+        let report = once.run(&fresh, &[job("d")], &AtomicBool::new(false), |_, _| {});
+        assert!(matches!(report.failed[..], [(0, RemoteError::Io(_))]));
+        assert_eq!(fs::read(dir.path().join("d.part")).unwrap(), b"01234567");
+
+        let cancelled = once.run(&fresh, &[job("e")], &AtomicBool::new(true), |_, _| {});
+        assert!(cancelled.interrupted && cancelled.finished.is_empty());
+    }
+}
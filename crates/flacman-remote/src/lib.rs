@@ -0,0 +1,20 @@
+mod remoteerror;
+mod source;
+mod registry;
+mod mirror;
+mod archive;
+mod ytdlp;
+mod downloader;
+mod catalog;
+mod rank;
+
+
+pub use remoteerror::{RemoteError, Result};
+pub use source::{RemoteAlbum, RemoteArtist, RemoteItem, RemoteKind, RemoteSource, RemoteTrack};
+pub use registry::{SourceConfig, SourceFactory, SourceRegistry};
+pub use mirror::HttpMirror;
+pub use archive::ArchiveOrg;
+pub use ytdlp::{YtDlp, is_url};
+pub use downloader::{DownloadEvent, DownloadJob, DownloadReport, Downloader};
+pub use catalog::{CachedSource, Catalog, CatalogCache, CatalogFetch, CatalogOrigin, CatalogStatus};
+pub use rank::{RankKey, Release, rank_releases};
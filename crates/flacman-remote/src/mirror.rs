@@ -0,0 +1,360 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use flacman_core::{DownloadUser, HttpRequest, MANIFEST_NAME, Manifest, sha256_file};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::catalog::CatalogFetch;
+use crate::registry::SourceConfig;
+use crate::remoteerror::{RemoteError, Result};
+use crate::source::{RemoteAlbum, RemoteArtist, RemoteItem, RemoteKind, RemoteSource, RemoteTrack};
+
+
+/// Album list at the root of a published library
+const INDEX: &str = "index.json";
+
+/// Index format the mirror understands
+const INDEX_VERSION: u32 = 1;
+
+/// Files of an album offered as tracks; covers, logs and the like are not
+const AUDIO_EXTS: &[&str] = &["flac", "mp3", "m4a", "m4b", "ogg", "opus", "wav", "aac", "wma"];
+
+#[derive(Debug, Clone, Deserialize)]
+struct IndexAlbum {
+    artist: String,
+    title: String,
+    year: Option<u32>,
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct IndexJson {
+    version: u32,
+    albums: Vec<IndexAlbum>,
+}
+
+/// Another flacman's library, as served by its `--publish`
+///
+/// Artists are identified by name, albums by their path below the
+/// published root and tracks by their path below that. Track lists come
+/// from each album's manifest, so every download is checked against it,
+/// and downloads resume with HTTP range requests.
+pub struct HttpMirror {
+    name: String,
+    /// Root URL, without a trailing `/`
+    base: String,
+    /// The album list, fetched on first use
+    albums: Mutex<Option<Vec<IndexAlbum>>>,
+    download_user: Option<DownloadUser>,
+}
+
+impl HttpMirror {
+    pub fn new(name: &str, url: &str) -> Self {
+        HttpMirror {
+            name: name.to_owned(),
+            base: url.trim_end_matches('/').to_owned(),
+            albums: Mutex::new(None),
+            download_user: None,
+        }
+    }
+
+    /// [`SourceFactory`](crate::SourceFactory) of the `mirror` kind
+    ///
+    /// # Errors
+    /// * `RemoteError::Config` - `url` is missing or not an HTTP URL
+    pub fn from_config(config: &SourceConfig, download_user: Option<&DownloadUser>) -> Result<Box<dyn RemoteSource>> {
+        match config.url.as_deref() {
+            Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+                Ok(Box::new(HttpMirror { download_user: download_user.cloned(), ..HttpMirror::new(&config.name, url) }))
+            }
+            Some(url) => Err(RemoteError::Config(config.name.clone(), format!("{} is not an HTTP URL", url))),
+            None => Err(RemoteError::Config(config.name.clone(), "a mirror needs a url".to_owned())),
+        }
+    }
+
+    /// URL of `path` below the published root
+    fn url(&self, path: &str) -> String {
+        let segments: Vec<String> = path.split('/').map(percent_encode).collect();
+        format!("{}/{}", self.base, segments.join("/"))
+    }
+
+    fn get_text(&self, path: &str) -> Result<String> {
+        debug!(source = %self.name, url = %self.url(path), "fetching");
+        match HttpRequest::get(&self.url(path)).send(self.download_user.as_ref()) {
+            Ok(response) => Ok(response.read_to_string()?),
+            Err(ureq::Error::StatusCode(404)) => Err(RemoteError::NotFound(self.name.clone(), path.to_owned())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The published albums, fetched unless already cached
+    fn albums(&self) -> Result<Vec<IndexAlbum>> {
+        let mut albums = self.albums.lock().expect("mirror index lock poisoned");
+        if albums.is_none() {
+            *albums = Some(parse_index(&self.name, &self.get_text(INDEX)?)?);
+        }
+        Ok(albums.clone().unwrap_or_default())
+    }
+}
+
+fn parse_index(source: &str, text: &str) -> Result<Vec<IndexAlbum>> {
+    let index: IndexJson = serde_json::from_str(text)
+        .map_err(|e| RemoteError::Response(source.to_owned(), format!("bad {}: {}", INDEX, e)))?;
+    if index.version != INDEX_VERSION {
+        return Err(RemoteError::Response(source.to_owned(), format!("unknown {} version {}", INDEX, index.version)));
+    }
+    Ok(index.albums)
+}
+
+fn album_item(album: &IndexAlbum) -> RemoteItem {
+    RemoteItem {
+        id: album.path.clone(),
+        kind: RemoteKind::Album,
+        title: album.title.clone(),
+        artist: Some(album.artist.clone()),
+        year: album.year,
+        formats: Vec::new(),
+    }
+}
+
+/// The audio files listed in an album's manifest, in manifest order
+///
+/// A file named like `03 Title.flac` is track 3, titled `Title`.
+fn album_tracks(album: &IndexAlbum, manifest: &Manifest) -> Vec<RemoteTrack> {
+    let is_audio = |path: &PathBuf| {
+        path.extension().is_some_and(|ext| AUDIO_EXTS.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+    };
+
+    manifest
+        .entries
+        .iter()
+        .filter(|(_, path)| is_audio(path))
+        .map(|(sha256, path)| {
+            let rel = path.to_string_lossy().into_owned();
+            let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| rel.clone());
+            let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            let digits = stem.len() - stem.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let title = stem[digits..].trim_start_matches([' ', '.', '-', '_']);
+
+            RemoteTrack {
+                id: format!("{}/{}", album.path, rel),
+                title: if title.is_empty() { stem.clone() } else { title.to_owned() },
+                number: stem[..digits].parse().ok(),
+                file_name,
+                size: None,
+                sha256: Some(sha256.clone()),
+                sha1: None,
+            }
+        })
+        .collect()
+}
+
+/// Writes to a file, reporting the bytes written so far
+struct ProgressWriter<'a> {
+    file: File,
+    written: u64,
+    total: Option<u64>,
+    progress: &'a dyn Fn(u64, Option<u64>),
+}
+
+impl Write for ProgressWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        (self.progress)(self.written, self.total);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Download `url` into `dest` with HTTP range requests, continuing after
+/// whatever an earlier attempt left there; the request is made as `user`
+/// if given, and the file written by flacman itself
+pub(crate) fn download_resuming(
+    source: &str,
+    url: &str,
+    track: &RemoteTrack,
+    dest: &Path,
+    user: Option<&DownloadUser>,
+    progress: &dyn Fn(u64, Option<u64>),
+) -> Result<()> {
+    let offset = fs::metadata(dest).map(|m| m.len()).unwrap_or(0);
+    let mut request = HttpRequest::get(url);
+    if offset > 0 {
+        request = request.header("Range", &format!("bytes={}-", offset));
+    }
+    debug!(url, offset, "downloading track");
+
+    match request.send(user) {
+        Ok(response) => {
+            // A server that ignores the range sends the whole file again
+            let resumed = response.status() == 206;
+            let start = if resumed { offset } else { 0 };
+            let total = response.content_length().map(|len| start + len).or(track.size);
+            let file = OpenOptions::new().create(true).write(true).append(resumed).truncate(!resumed).open(dest)?;
+            let mut writer = ProgressWriter { file, written: start, total, progress };
+            progress(start, total);
+            io::copy(&mut response.into_reader(), &mut writer)?;
+        }
+        // Nothing is left past the offset: the earlier attempt finished
+        Err(ureq::Error::StatusCode(416)) if offset > 0 => {}
+        Err(ureq::Error::StatusCode(404)) => return Err(RemoteError::NotFound(source.to_owned(), track.id.clone())),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+/// Escape everything but unreserved characters in one URL path segment
+pub(crate) fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+impl RemoteSource for HttpMirror {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> &'static str {
+        "mirror"
+    }
+
+    fn search(&self, kind: RemoteKind, query: &str) -> Result<Vec<RemoteItem>> {
+        let query = query.to_lowercase();
+        let albums = self.albums()?;
+
+        match kind {
+            RemoteKind::Artist => {
+                let mut artists: Vec<&str> = albums
+                    .iter()
+                    .map(|a| a.artist.as_str())
+                    .filter(|artist| artist.to_lowercase().contains(&query))
+                    .collect();
+                artists.sort_unstable();
+                artists.dedup();
+                Ok(artists
+                    .into_iter()
+                    .map(|artist| RemoteItem {
+                        id: artist.to_owned(),
+                        kind: RemoteKind::Artist,
+                        title: artist.to_owned(),
+                        artist: None,
+                        year: None,
+                        formats: Vec::new(),
+                    })
+                    .collect())
+            }
+            RemoteKind::Album => Ok(albums
+                .iter()
+                .filter(|a| format!("{} - {}", a.artist, a.title).to_lowercase().contains(&query))
+                .map(album_item)
+                .collect()),
+            RemoteKind::Track => Err(RemoteError::Unsupported(self.name.clone(), "search for tracks")),
+        }
+    }
+
+    fn get_artist(&self, id: &str) -> Result<RemoteArtist> {
+        let albums: Vec<RemoteItem> = self.albums()?.iter().filter(|a| a.artist == id).map(album_item).collect();
+        if albums.is_empty() {
+            return Err(RemoteError::NotFound(self.name.clone(), id.to_owned()));
+        }
+        Ok(RemoteArtist { id: id.to_owned(), name: id.to_owned(), albums })
+    }
+
+    fn get_album(&self, id: &str) -> Result<RemoteAlbum> {
+        let albums = self.albums()?;
+        let Some(album) = albums.iter().find(|a| a.path == id) else {
+            return Err(RemoteError::NotFound(self.name.clone(), id.to_owned()));
+        };
+        let manifest = Manifest::parse(&self.get_text(&format!("{}/{}", album.path, MANIFEST_NAME))?)?;
+
+        Ok(RemoteAlbum {
+            id: album.path.clone(),
+            title: album.title.clone(),
+            artist: album.artist.clone(),
+            year: album.year,
+            tracks: album_tracks(album, &manifest),
+        })
+    }
+
+    fn download_track(&self, track: &RemoteTrack, dest: &Path, progress: &dyn Fn(u64, Option<u64>)) -> Result<u64> {
+        download_resuming(&self.name, &self.url(&track.id), track, dest, self.download_user.as_ref(), progress)?;
+
+        if let Some(expected) = &track.sha256
+            && sha256_file(dest).map_err(flacman_core::CoreError::from)? != *expected
+        {
+            fs::remove_file(dest)?;
+            return Err(RemoteError::Checksum(dest.to_path_buf()));
+        }
+
+        Ok(fs::metadata(dest)?.len())
+    }
+
+    /// The index, unless its ETag is still `validator`
+    fn fetch_catalog(&self, validator: Option<&str>) -> Result<CatalogFetch> {
+        let mut request = HttpRequest::get(&self.url(INDEX));
+        if let Some(etag) = validator {
+            request = request.header("If-None-Match", etag);
+        }
+        debug!(source = %self.name, url = %self.url(INDEX), ?validator, "fetching catalog");
+
+        let response = match request.send(self.download_user.as_ref()) {
+            Ok(response) if response.status() == 304 => return Ok(CatalogFetch::Unchanged),
+            Ok(response) => response,
+            Err(ureq::Error::StatusCode(404)) => return Err(RemoteError::NotFound(self.name.clone(), INDEX.to_owned())),
+            Err(e) => return Err(e.into()),
+        };
+        let etag = response.header("etag").map(str::to_owned);
+        let albums = parse_index(&self.name, &response.read_to_string()?)?;
+        let items = albums.iter().map(album_item).collect();
+        *self.albums.lock().expect("mirror index lock poisoned") = Some(albums);
+
+        Ok(CatalogFetch::Changed { albums: items, validator: etag })
+    }
+
+    fn refresh(&self) -> Result<()> {
+        *self.albums.lock().expect("mirror index lock poisoned") = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_and_tracks() {
+        let index = r#"{"version": 1, "albums": [
+            {"artist": "Low", "title": "Trust", "year": 2002, "path": "Low/Trust", "files": 3, "bytes": 30},
+            {"artist": "Low", "title": "Secret Name", "path": "Low/Secret Name", "files": 1, "bytes": 10}]}"#;
+        let albums = parse_index("nas", index).unwrap();
+        assert_eq!(albums.len(), 2);
+        assert_eq!(album_item(&albums[0]).year, Some(2002));
+        assert!(matches!(parse_index("nas", r#"{"version": 2, "albums": []}"#), Err(RemoteError::Response(..))));
+
+        let digest = "a".repeat(64);
+        let text = format!("{digest}  01 Canada.flac\n{digest}  02. Candy Girl.flac\n{digest}  cover.jpg\n");
+        let tracks = album_tracks(&albums[0], &Manifest::parse(&text).unwrap());
+        assert_eq!(tracks.len(), 2);
+        assert_eq!((tracks[0].number, tracks[0].title.as_str()), (Some(1), "Canada"));
+        assert_eq!((tracks[1].number, tracks[1].title.as_str()), (Some(2), "Candy Girl"));
+        assert_eq!(tracks[1].id, "Low/Trust/02. Candy Girl.flac");
+        assert_eq!(tracks[0].sha256.as_deref(), Some(digest.as_str()));
+
+        let mirror = HttpMirror::new("nas", "http://nas.local:8080/");
+        assert_eq!(mirror.url(&tracks[1].id), "http://nas.local:8080/Low/Trust/02.%20Candy%20Girl.flac");
+    }
+}
@@ -0,0 +1,155 @@
+use std::cmp::Ordering;
+use std::path::Path;
+
+use flacman_core::{Encoding, QualityLadder};
+use serde::{Deserialize, Serialize};
+
+use crate::source::RemoteAlbum;
+
+
+/// Formats that lose nothing, by file extension or last word of an
+/// archive.org format name (`24bit Flac`)
+const LOSSLESS: &[&str] = &["flac", "alac", "wav", "aiff", "aif", "ape", "wv", "tta"];
+
+/// What `-S` ranks the releases a target matches by, most important first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RankKey {
+    /// `Artist - Album` matches before ones of the album title alone
+    Match,
+    /// Sources in the order they are configured or given to `--source`
+    Source,
+    /// The highest rung of the quality ladder, then the highest bitrate
+    Quality,
+    Newest,
+    Oldest,
+    Largest,
+    Smallest,
+}
+
+impl RankKey {
+    /// Ranking used unless flacman.conf sets `rank`
+    pub const DEFAULT: [RankKey; 4] = [RankKey::Match, RankKey::Source, RankKey::Quality, RankKey::Largest];
+
+    fn compare(self, a: &Release, b: &Release, ladder: &QualityLadder) -> Ordering {
+        // Unknown years and sizes go last either way
+        match self {
+            RankKey::Match => b.exact.cmp(&a.exact),
+            RankKey::Source => a.source_rank.cmp(&b.source_rank),
+            RankKey::Quality => ladder.standing(&a.encoding()).cmp(&ladder.standing(&b.encoding())),
+            RankKey::Newest => b.album.year.cmp(&a.album.year),
+            RankKey::Oldest => a.album.year.unwrap_or(u32::MAX).cmp(&b.album.year.unwrap_or(u32::MAX)),
+            RankKey::Largest => b.size().cmp(&a.size()),
+            RankKey::Smallest => a.size().unwrap_or(u64::MAX).cmp(&b.size().unwrap_or(u64::MAX)),
+        }
+    }
+}
+
+/// A release one of the sources has for a `-S` target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    pub source: String,
+    /// Position of `source` among those searched, 0 for the preferred one
+    pub source_rank: usize,
+    /// Whether the target named artist and album, not just the album title
+    pub exact: bool,
+    pub album: RemoteAlbum,
+    /// What the tracks are in, e.g. `flac` or archive.org's `VBR MP3`
+    pub format: String,
+}
+
+impl Release {
+    /// A release of `album`; `format` defaults to the extension of its tracks
+    pub fn new(source: &str, source_rank: usize, exact: bool, album: RemoteAlbum, format: Option<String>) -> Self {
+        let format = format.unwrap_or_else(|| {
+            let extension = album.tracks.first().and_then(|t| Path::new(&t.file_name).extension().map(|e| e.to_string_lossy()));
+            extension.map(|e| e.to_lowercase()).unwrap_or_default()
+        });
+        Release { source: source.to_owned(), source_rank, exact, album, format }
+    }
+
+    /// Size of the tracks the source gives one for, if any
+    pub fn size(&self) -> Option<u64> {
+        self.album.tracks.iter().filter_map(|t| t.size).reduce(|a, b| a + b)
+    }
+
+    /// Whether the release is on `ladder` and stands higher on it than
+    /// `local`, a copy the library has
+    pub fn upgrades(&self, local: &Encoding, ladder: &QualityLadder) -> bool {
+        let encoding = self.encoding();
+        ladder.accepts(&encoding) && ladder.standing(&encoding) < ladder.standing(local)
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        let format = self.format.to_lowercase();
+        let bitrate = format.split_whitespace().find_map(|w| w.strip_suffix("kbps")?.parse().ok());
+        let name = match format.split_whitespace().last().unwrap_or("") {
+            _ if format.starts_with("apple lossless") => "alac",
+            "vorbis" => "ogg",
+            name => name,
+        };
+        Encoding::new(name, LOSSLESS.contains(&name), bitrate)
+    }
+}
+
+/// Sort `releases` best first by `ranking`, each key breaking the ties of
+/// the one before; releases still tied keep their order
+pub fn rank_releases(releases: &mut [Release], ranking: &[RankKey], ladder: &QualityLadder) {
+    releases.sort_by(|a, b| {
+        ranking.iter().map(|key| key.compare(a, b, ladder)).find(|o| o.is_ne()).unwrap_or(Ordering::Equal)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::RemoteTrack;
+
+    fn release(source_rank: usize, exact: bool, year: Option<u32>, format: &str, size: u64) -> Release {
+        let track = RemoteTrack {
+            id: "1".to_owned(),
+            title: "Dreams".to_owned(),
+            number: Some(2),
+            file_name: "02 Dreams.flac".to_owned(),
+            size: Some(size),
+            sha256: None,
+            sha1: None,
+        };
+        let album = RemoteAlbum {
+            id: format!("{}-{}", source_rank, format),
+            title: "Rumours".to_owned(),
+            artist: "Fleetwood Mac".to_owned(),
+            year,
+            tracks: vec![track],
+        };
+        let format = Some(format).filter(|f| !f.is_empty()).map(str::to_owned);
+        Release::new(&format!("source{}", source_rank), source_rank, exact, album, format)
+    }
+
+    #[test]
+    fn test_rank_releases() {
+        let lossless: QualityLadder = "lossless, mp3>=256".parse().unwrap();
+        let ids = |releases: &[Release]| releases.iter().map(|r| r.album.id.clone()).collect::<Vec<_>>();
+        let mut releases = vec![
+            release(1, false, Some(2004), "VBR MP3", 80),
+            release(1, true, Some(1977), "320Kbps MP3", 90),
+            release(0, true, None, "", 300),
+            release(1, true, Some(1977), "24bit Flac", 600),
+        ];
+        assert_eq!(releases[2].format, "flac");
+        assert!(releases[3].encoding().lossless && releases[1].encoding().bitrate == Some(320));
+        assert_eq!(Release { format: "Apple Lossless Audio".to_owned(), ..releases[0].clone() }.encoding().format, "alac");
+
+        let mp3 = Encoding::new("mp3", false, Some(192));
+        assert!(releases[3].upgrades(&mp3, &lossless) && releases[1].upgrades(&mp3, &lossless));
+        assert!(!releases[0].upgrades(&mp3, &lossless) && !releases[3].upgrades(&Encoding::new("flac", true, Some(900)), &lossless));
+
+        rank_releases(&mut releases, &RankKey::DEFAULT, &lossless);
+        assert_eq!(ids(&releases), ["0-", "1-24bit Flac", "1-320Kbps MP3", "1-VBR MP3"]);
+
+        rank_releases(&mut releases, &[RankKey::Oldest, RankKey::Smallest], &lossless);
+        assert_eq!(ids(&releases), ["1-320Kbps MP3", "1-24bit Flac", "1-VBR MP3", "0-"]);
+        rank_releases(&mut releases, &[RankKey::Quality, RankKey::Newest], &"mp3".parse().unwrap());
+        assert_eq!(ids(&releases), ["1-320Kbps MP3", "1-VBR MP3", "1-24bit Flac", "0-"]);
+    }
+}
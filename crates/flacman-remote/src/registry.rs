@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use flacman_core::DownloadUser;
+use serde::{Deserialize, Serialize};
+
+use crate::archive::ArchiveOrg;
+use crate::mirror::HttpMirror;
+use crate::ytdlp::YtDlp;
+use crate::remoteerror::{RemoteError, Result};
+use crate::source::RemoteSource;
+
+
+/// One `[[sources]]` entry of flacman.conf
+///
+/// ```toml
+/// [[sources]]
+/// name = "nas"
+/// kind = "mirror"
+/// url = "http://nas.local:8080"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    /// What `--source` selects it by
+    pub name: String,
+    /// Which implementation serves it, e.g. `mirror` or `archive`
+    pub kind: String,
+    pub url: Option<String>,
+    /// Settings particular to the kind, e.g. credentials
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+}
+
+/// Creates a source of one kind from its configuration; its downloads run
+/// as the user, if one is given
+pub type SourceFactory = fn(&SourceConfig, Option<&DownloadUser>) -> Result<Box<dyn RemoteSource>>;
+
+/// The kinds of source flacman knows, by the name used in flacman.conf
+#[derive(Default)]
+pub struct SourceRegistry {
+    factories: BTreeMap<&'static str, SourceFactory>,
+    download_user: Option<DownloadUser>,
+}
+
+impl SourceRegistry {
+    /// A registry with every kind of source built into flacman
+    pub fn with_builtins() -> Self {
+        let mut registry = SourceRegistry::default();
+        registry.register("mirror", HttpMirror::from_config);
+        registry.register("archive", ArchiveOrg::from_config);
+        registry.register("ytdlp", YtDlp::from_config);
+        registry
+    }
+
+    /// Have the sources created download as `user`
+    pub fn download_as(mut self, user: Option<DownloadUser>) -> Self {
+        self.download_user = user;
+        self
+    }
+
+    /// Make `kind` available, replacing any factory already registered for it
+    pub fn register(&mut self, kind: &'static str, factory: SourceFactory) {
+        self.factories.insert(kind, factory);
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.factories.keys().copied()
+    }
+
+    /// # Errors
+    /// * `RemoteError::Config` - The kind isn't registered, or the factory
+    ///   rejected the configuration
+    pub fn create(&self, config: &SourceConfig) -> Result<Box<dyn RemoteSource>> {
+        match self.factories.get(config.kind.as_str()) {
+            Some(factory) => factory(config, self.download_user.as_ref()),
+            None => {
+                let known: Vec<&str> = self.kinds().collect();
+                let reason = format!("unknown kind {:?} (known: {})", config.kind, known.join(", "));
+                Err(RemoteError::Config(config.name.clone(), reason))
+            }
+        }
+    }
+
+    /// Create every configured source, in the order given
+    ///
+    /// # Errors
+    /// * `RemoteError::Config` - As for [`create`](Self::create), or two
+    ///   sources share a name
+    pub fn create_all(&self, configs: &[SourceConfig]) -> Result<Vec<Box<dyn RemoteSource>>> {
+        let mut sources: Vec<Box<dyn RemoteSource>> = Vec::new();
+        for config in configs {
+            if sources.iter().any(|s| s.name() == config.name) {
+                return Err(RemoteError::Config(config.name.clone(), "configured more than once".to_owned()));
+            }
+            sources.push(self.create(config)?);
+        }
+        Ok(sources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_all() {
+        let registry = SourceRegistry::with_builtins();
+        let mirror = |name: &str| SourceConfig {
+            name: name.to_owned(),
+            kind: "mirror".to_owned(),
+            url: Some("http://nas.local:8080/".to_owned()),
+            ..Default::default()
+        };
+
+        let sources = registry.create_all(&[mirror("nas"), mirror("backup")]).unwrap();
+        assert_eq!(sources.iter().map(|s| s.name()).collect::<Vec<_>>(), ["nas", "backup"]);
+        assert_eq!(sources[0].kind(), "mirror");
+
+        assert!(matches!(registry.create_all(&[mirror("nas"), mirror("nas")]), Err(RemoteError::Config(..))));
+        let qobuz = SourceConfig { kind: "qobuz".to_owned(), ..mirror("qobuz") };
+        match registry.create(&qobuz) {
+            Err(RemoteError::Config(_, reason)) => assert!(reason.contains("known: archive, mirror, ytdlp")),
+            _ => panic!("expected an unknown kind to be refused"),
+        }
+        let no_url = SourceConfig { url: None, ..mirror("nas") };
+        assert!(matches!(registry.create(&no_url), Err(RemoteError::Config(..))));
+    }
+}
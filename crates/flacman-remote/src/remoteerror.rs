@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+
+#[derive(Error, Debug)]
+pub enum RemoteError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("HTTP error: {0}")]
+    Http(#[from] ureq::Error),
+
+    #[error("{0}: unexpected response: {1}")]
+    Response(String, String),
+
+    #[error("{0}: not found: {1}")]
+    NotFound(String, String),
+
+    #[error("{0} can't {1}")]
+    Unsupported(String, &'static str),
+
+    #[error("No source is configured as {0}")]
+    UnknownSource(String),
+
+    #[error("Source {0}: {1}")]
+    Config(String, String),
+
+    #[error("Checksum of {0} doesn't match the source")]
+    Checksum(PathBuf),
+
+    #[error("Core error: {0}")]
+    Core(#[from] flacman_core::CoreError),
+}
+
+impl RemoteError {
+    /// Whether trying again later might succeed: the connection or the
+    /// transfer failed, or the server is busy or having trouble
+    pub fn is_transient(&self) -> bool {
+        match self {
+            RemoteError::Io(_) | RemoteError::Checksum(_) => true,
+            RemoteError::Http(ureq::Error::StatusCode(status)) => *status == 408 || *status == 429 || *status >= 500,
+            RemoteError::Http(_) => true,
+            _ => false,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, RemoteError>;
@@ -0,0 +1,127 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::CatalogFetch;
+use crate::remoteerror::{RemoteError, Result};
+
+
+/// What a search looks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteKind {
+    Artist,
+    Album,
+    Track,
+}
+
+impl fmt::Display for RemoteKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            RemoteKind::Artist => "artist",
+            RemoteKind::Album => "album",
+            RemoteKind::Track => "track",
+        })
+    }
+}
+
+/// One search result; `id` is only meaningful to the source it came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteItem {
+    pub id: String,
+    pub kind: RemoteKind,
+    /// Artist name, or album or track title
+    pub title: String,
+    /// Artist of an album or track
+    pub artist: Option<String>,
+    pub year: Option<u32>,
+    /// Formats the source offers it in, for sources that offer several;
+    /// the album in one of them is `<id>/<format>`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub formats: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteArtist {
+    pub id: String,
+    pub name: String,
+    pub albums: Vec<RemoteItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteAlbum {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    pub year: Option<u32>,
+    /// In track order
+    pub tracks: Vec<RemoteTrack>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteTrack {
+    pub id: String,
+    pub title: String,
+    pub number: Option<u32>,
+    /// File name the source offers it under, extension included
+    pub file_name: String,
+    pub size: Option<u64>,
+    /// Hex SHA-256 of the file, when the source publishes one
+    pub sha256: Option<String>,
+    /// Hex SHA-1 of the file, for sources that publish that instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<String>,
+}
+
+/// Somewhere `-S` can find and download music
+///
+/// Implemented once per kind of source (a store, an archive, another
+/// flacman's published library) and created from the `[[sources]]` of
+/// flacman.conf by a [`SourceRegistry`](crate::SourceRegistry). Operations
+/// a kind of source can't do return `RemoteError::Unsupported`.
+pub trait RemoteSource: Send + Sync {
+    /// The name the source is configured and selected (`--source`) by
+    fn name(&self) -> &str;
+
+    /// Kind of source, as written in flacman.conf
+    fn kind(&self) -> &'static str;
+
+    fn search(&self, kind: RemoteKind, query: &str) -> Result<Vec<RemoteItem>>;
+
+    /// An artist and the albums the source has by them
+    fn get_artist(&self, id: &str) -> Result<RemoteArtist>;
+
+    /// An album and its track list
+    fn get_album(&self, id: &str) -> Result<RemoteAlbum>;
+
+    /// Download `track` into `dest`, resuming after whatever an earlier
+    /// attempt left there
+    ///
+    /// # Arguments
+    /// * `progress` - Called as data arrives, with the bytes in `dest` so far
+    ///   and the full size if known
+    ///
+    /// # Returns
+    /// Size of the downloaded file in bytes
+    ///
+    /// # Errors
+    /// * `RemoteError::Checksum` - The file doesn't match the checksum the
+    ///   source published; `dest` is removed so the next attempt starts over
+    fn download_track(&self, track: &RemoteTrack, dest: &Path, progress: &dyn Fn(u64, Option<u64>)) -> Result<u64>;
+
+    /// Every album the source offers, for the
+    /// [`CatalogCache`](crate::CatalogCache)
+    ///
+    /// # Arguments
+    /// * `validator` - What the last fetch returned; a source that can tell
+    ///   answers `CatalogFetch::Unchanged` if its catalog is still the same
+    fn fetch_catalog(&self, validator: Option<&str>) -> Result<CatalogFetch> {
+        let _ = validator;
+        Err(RemoteError::Unsupported(self.name().to_owned(), "list its catalog"))
+    }
+
+    /// Drop whatever the source has cached about the remote side, so the
+    /// next call sees it as it is now
+    fn refresh(&self) -> Result<()>;
+}
@@ -0,0 +1,294 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use flacman_core::DownloadUser;
+use serde_json::Value;
+use tracing::debug;
+
+use crate::registry::SourceConfig;
+use crate::remoteerror::{RemoteError, Result};
+use crate::source::{RemoteAlbum, RemoteArtist, RemoteItem, RemoteKind, RemoteSource, RemoteTrack};
+
+
+/// Program run unless the `program` option names another, e.g. `youtube-dl`
+const DEFAULT_PROGRAM: &str = "yt-dlp";
+
+/// Prefix of the progress lines asked of yt-dlp, told apart from its other output
+const PROGRESS_PREFIX: &str = "flacman-progress";
+
+/// Video and audio sites, through yt-dlp
+///
+/// Albums are the URLs of a video, track or playlist; there is no
+/// catalog to search. The best audio stream of each is downloaded and
+/// extracted without re-encoding, with the site's metadata and thumbnail
+/// embedded; converting to the format asked for is left to `-S` as for
+/// any download. Needs yt-dlp (and ffmpeg, which it uses to extract).
+pub struct YtDlp {
+    name: String,
+    program: String,
+    download_user: Option<DownloadUser>,
+}
+
+impl YtDlp {
+    pub fn new(name: &str) -> Self {
+        YtDlp { name: name.to_owned(), program: DEFAULT_PROGRAM.to_owned(), download_user: None }
+    }
+
+    /// [`SourceFactory`](crate::SourceFactory) of the `ytdlp` kind; the
+    /// `program` option names the program if it isn't `yt-dlp`
+    pub fn from_config(config: &SourceConfig, download_user: Option<&DownloadUser>) -> Result<Box<dyn RemoteSource>> {
+        let mut source = YtDlp { download_user: download_user.cloned(), ..YtDlp::new(&config.name) };
+        if let Some(program) = config.options.get("program") {
+            source.program = program.clone();
+        }
+        Ok(Box::new(source))
+    }
+
+    /// The program, run as the download user if there is one
+    fn command(&self) -> Command {
+        let mut command = match &self.download_user {
+            Some(user) => user.command(&self.program),
+            None => Command::new(&self.program),
+        };
+        command.args(["--no-warnings", "--format", "bestaudio/best"]);
+        command
+    }
+
+    /// Run `command`, returning what it printed
+    fn run(&self, mut command: Command) -> Result<String> {
+        debug!(source = %self.name, ?command, "running");
+        let output = command.output().map_err(|e| self.spawn_error(e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = stderr.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("failed").trim();
+            return Err(RemoteError::Response(self.name.clone(), message.to_owned()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn spawn_error(&self, e: std::io::Error) -> RemoteError {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => RemoteError::Config(self.name.clone(), format!("{} is not installed", self.program)),
+            _ => e.into(),
+        }
+    }
+
+    /// Download `track` into `scratch`, else next to `dest`, and move it to `dest`
+    fn download_into(
+        &self,
+        track: &RemoteTrack,
+        dest: &Path,
+        scratch: Option<&Path>,
+        progress: &dyn Fn(u64, Option<u64>),
+    ) -> Result<u64> {
+        // yt-dlp names the file by what it extracted; it is renamed to `dest` after
+        let output = match (scratch, dest.file_name()) {
+            (Some(scratch), Some(name)) => scratch.join(name),
+            _ => dest.to_path_buf(),
+        };
+        let stem = output.to_string_lossy().replace('%', "%%");
+        let mut command = self.command();
+        command
+            .args(["--no-playlist", "--extract-audio", "--audio-format", "best"])
+            .args(["--embed-metadata", "--embed-thumbnail", "--newline", "--progress"])
+            .args(["--progress-template", &format!("{} %(progress.downloaded_bytes)s %(progress.total_bytes)s", PROGRESS_PREFIX)])
+            .args(["--print", "after_move:filepath", "--output", &format!("{}.%(ext)s", stem), "--", &track.id])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        debug!(source = %self.name, ?command, "downloading track");
+
+        let mut child = command.spawn().map_err(|e| self.spawn_error(e))?;
+        let mut written: Option<PathBuf> = None;
+        for line in BufReader::new(child.stdout.take().expect("stdout is piped")).lines() {
+            let line = line?;
+            match line.strip_prefix(PROGRESS_PREFIX) {
+                Some(numbers) => {
+                    let mut numbers = numbers.split_whitespace().map(|n| n.parse::<f64>().ok().map(|n| n as u64));
+                    if let Some(Some(done)) = numbers.next() {
+                        progress(done, numbers.next().flatten().or(track.size));
+                    }
+                }
+                None if !line.trim().is_empty() => written = Some(PathBuf::from(line.trim())),
+                None => {}
+            }
+        }
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = stderr.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("failed").trim().to_owned();
+            return Err(RemoteError::Response(self.name.clone(), message));
+        }
+        let Some(written) = written.filter(|w| w.is_file()) else {
+            return Err(RemoteError::Response(self.name.clone(), format!("{} wrote no audio", self.program)));
+        };
+        fs::rename(&written, dest)?;
+
+        Ok(fs::metadata(dest)?.len())
+    }
+}
+
+/// Whether `target` is a URL for yt-dlp rather than a name
+pub fn is_url(target: &str) -> bool {
+    target.starts_with("https://") || target.starts_with("http://")
+}
+
+fn text(info: &Value, keys: &[&str]) -> Option<String> {
+    keys.iter().filter_map(|key| info.get(*key)?.as_str()).map(str::trim).find(|s| !s.is_empty()).map(str::to_owned)
+}
+
+fn number(info: &Value, keys: &[&str]) -> Option<u32> {
+    keys.iter().find_map(|key| info.get(*key)?.as_u64()).map(|n| n as u32)
+}
+
+/// The performer yt-dlp found, without the ` - Topic` YouTube adds to
+/// channels it generates for artists
+fn artist(info: &Value) -> Option<String> {
+    text(info, &["artist", "creator", "album_artist", "uploader", "channel"])
+        .map(|a| a.strip_suffix(" - Topic").unwrap_or(&a).to_owned())
+}
+
+fn year(info: &Value) -> Option<u32> {
+    number(info, &["release_year"]).or_else(|| text(info, &["release_date", "upload_date"])?.get(..4)?.parse().ok())
+}
+
+/// Extension the audio extracted from an entry ends up with
+fn audio_extension(info: &Value) -> &'static str {
+    let codec = info.get("acodec").and_then(Value::as_str).unwrap_or("");
+    match codec.split('.').next().unwrap_or("") {
+        "opus" => "opus",
+        "vorbis" => "ogg",
+        "mp3" => "mp3",
+        "flac" => "flac",
+        "alac" | "mp4a" | "aac" => "m4a",
+        _ => match info.get("ext").and_then(Value::as_str) {
+            Some("mp3") => "mp3",
+            Some("ogg") => "ogg",
+            Some("webm") => "opus",
+            _ => "m4a",
+        },
+    }
+}
+
+/// An album of the `yt-dlp --dump-single-json` output for `url`: a
+/// playlist's entries, or a single video as an album of one track
+fn album_from_info(url: &str, info: &Value) -> RemoteAlbum {
+    let entries: Vec<&Value> = match info.get("entries").and_then(Value::as_array) {
+        Some(entries) => entries.iter().filter(|e| e.is_object()).collect(),
+        None => vec![info],
+    };
+    let single = info.get("entries").is_none();
+
+    let tracks = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let title = text(entry, &["track", "title"]).unwrap_or_else(|| format!("Track {}", i + 1));
+            let number = if single { number(entry, &["track_number"]) } else { number(entry, &["playlist_index"]) };
+            let number = number.unwrap_or(i as u32 + 1);
+            let id = if single { url.to_owned() } else { text(entry, &["webpage_url", "url"]).unwrap_or_default() };
+            RemoteTrack {
+                id,
+                file_name: format!("{:02} {}.{}", number, title.replace(['/', '\\'], "_"), audio_extension(entry)),
+                title,
+                number: Some(number),
+                size: entry.get("filesize").or_else(|| entry.get("filesize_approx")).and_then(Value::as_u64),
+                sha256: None,
+                sha1: None,
+            }
+        })
+        .collect();
+
+    let first = entries.first().copied().unwrap_or(info);
+    let title = if single { text(info, &["album", "title"]) } else { text(info, &["title"]) };
+    RemoteAlbum {
+        id: url.to_owned(),
+        title: title.unwrap_or_else(|| url.to_owned()),
+        artist: artist(first).or_else(|| artist(info)).unwrap_or_default(),
+        year: year(first).or_else(|| year(info)),
+        tracks,
+    }
+}
+
+impl RemoteSource for YtDlp {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> &'static str {
+        "ytdlp"
+    }
+
+    fn search(&self, _kind: RemoteKind, _query: &str) -> Result<Vec<RemoteItem>> {
+        Err(RemoteError::Unsupported(self.name.clone(), "search; give URLs instead"))
+    }
+
+    fn get_artist(&self, _id: &str) -> Result<RemoteArtist> {
+        Err(RemoteError::Unsupported(self.name.clone(), "list artists"))
+    }
+
+    fn get_album(&self, id: &str) -> Result<RemoteAlbum> {
+        if !is_url(id) {
+            return Err(RemoteError::NotFound(self.name.clone(), format!("{} (not a URL)", id)));
+        }
+        let mut command = self.command();
+        command.args(["--no-progress", "--dump-single-json", "--", id]);
+        let info: Value = serde_json::from_str(&self.run(command)?)
+            .map_err(|e| RemoteError::Response(self.name.clone(), format!("unexpected output: {}", e)))?;
+        Ok(album_from_info(id, &info))
+    }
+
+    fn download_track(&self, track: &RemoteTrack, dest: &Path, progress: &dyn Fn(u64, Option<u64>)) -> Result<u64> {
+        // A download user writes to a directory of its own, which the file
+        // is taken from
+        let scratch = match (&self.download_user, dest.parent()) {
+            (Some(user), Some(parent)) => Some(user.scratch_dir(parent)?),
+            _ => None,
+        };
+        let result = self.download_into(track, dest, scratch.as_deref(), progress);
+        if let Some(scratch) = scratch {
+            let _ = fs::remove_dir_all(scratch);
+        }
+        result
+    }
+
+    fn refresh(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_album_from_info() {
+        let video = serde_json::json!({
+            "title": "Low - Canada (Official Video)", "track": "Canada", "artist": "Low", "album": "Trust",
+            "release_year": 2002, "acodec": "opus", "ext": "webm", "filesize": 4_000_000
+        });
+        let album = album_from_info("https://youtu.be/x", &video);
+        assert_eq!((album.title.as_str(), album.artist.as_str(), album.year), ("Trust", "Low", Some(2002)));
+        assert_eq!(album.tracks[0].file_name, "01 Canada.opus");
+        assert_eq!(album.tracks[0].id, "https://youtu.be/x");
+
+        let playlist = serde_json::json!({
+            "_type": "playlist", "title": "Trust", "uploader": "Low - Topic",
+            "entries": [
+                {"title": "Candy Girl", "uploader": "Low - Topic", "playlist_index": 2, "upload_date": "20020924",
+                 "webpage_url": "https://youtu.be/b", "acodec": "mp4a.40.2"},
+                null,
+                {"title": "AC/DC", "playlist_index": 3, "webpage_url": "https://youtu.be/c"}
+            ]
+        });
+        let album = album_from_info("https://youtube.com/playlist?list=y", &playlist);
+        assert_eq!((album.artist.as_str(), album.year), ("Low", Some(2002)));
+        assert_eq!(album.tracks.len(), 2);
+        assert_eq!((album.tracks[0].number, album.tracks[0].file_name.as_str()), (Some(2), "02 Candy Girl.m4a"));
+        assert_eq!(album.tracks[1].file_name, "03 AC_DC.m4a");
+        assert!(is_url(&album.tracks[1].id) && !is_url("Low - Trust"));
+    }
+}
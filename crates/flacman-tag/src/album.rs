@@ -0,0 +1,332 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use flacman_core::TemplateFields;
+
+use crate::mediafile::Metadata;
+use crate::view::album_dir;
+
+
+/// One track of an [`Album`]
+#[derive(Debug, Clone)]
+pub struct AlbumTrack {
+    pub path: PathBuf,
+    pub metadata: Metadata,
+}
+
+/// A release, with the tracks of every disc grouped together
+#[derive(Debug, Clone)]
+pub struct Album {
+    /// Album artist, or the track artist when no album artist is tagged
+    pub artist: String,
+    /// Album title without any "(Disc 2)" style suffix
+    pub title: String,
+    /// Tracks by disc number; untagged discs count as disc 1
+    pub discs: BTreeMap<u32, Vec<AlbumTrack>>,
+}
+
+/// A gap or clash in disc/track numbering
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NumberingIssue {
+    MissingDisc(u32),
+    MissingTrack { disc: u32, track: u32 },
+    DuplicateTrack { disc: u32, track: u32 },
+    Unnumbered(PathBuf),
+}
+
+impl fmt::Display for NumberingIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NumberingIssue::MissingDisc(disc) => write!(f, "disc {disc} is missing"),
+            NumberingIssue::MissingTrack { disc, track } => write!(f, "disc {disc}: track {track} is missing"),
+            NumberingIssue::DuplicateTrack { disc, track } => {
+                write!(f, "disc {disc}: track {track} appears more than once")
+            }
+            NumberingIssue::Unnumbered(path) => write!(f, "{} has no track number", path.display()),
+        }
+    }
+}
+
+/// Split a disc suffix off an album title
+///
+/// Understands the usual ways of naming discs as separate albums:
+/// "Title (Disc 2)", "Title [CD2]", "Title - Disk 2", "Title CD 2".
+///
+/// # Returns
+/// `(title without suffix, disc number)`, or the unchanged title and `None`
+pub fn split_disc_suffix(album: &str) -> (&str, Option<u32>) {
+    let trimmed = album.trim_end();
+
+    let (body, opener) = match trimmed.chars().last() {
+        Some(')') => (&trimmed[..trimmed.len() - 1], Some('(')),
+        Some(']') => (&trimmed[..trimmed.len() - 1], Some('[')),
+        _ => (trimmed, None),
+    };
+
+    let digits_start = body.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let Ok(disc) = body[digits_start..].parse::<u32>() else {
+        return (album, None);
+    };
+
+    let before = body[..digits_start].trim_end();
+    let lower = before.to_ascii_lowercase();
+    let Some(word) = ["disc", "disk", "cd"].iter().find(|w| lower.ends_with(*w)) else {
+        return (album, None);
+    };
+
+    let mut base = &before[..before.len() - word.len()];
+    if base.chars().last().is_some_and(|c| c.is_alphanumeric()) {
+        return (album, None);
+    }
+    base = base.trim_end();
+
+    if let Some(opener) = opener {
+        match base.strip_suffix(opener) {
+            Some(stripped) => base = stripped.trim_end(),
+            None => return (album, None),
+        }
+    }
+
+    let base = base.trim_end_matches(['-', ':', ',']).trim_end();
+    if base.is_empty() {
+        return (album, None);
+    }
+
+    (base, Some(disc))
+}
+
+/// Group tracks into albums, merging the discs of multi-disc releases
+///
+/// Discs are recognised from the disc number tag, or failing that from a
+/// disc suffix on the album title. Albums come back in input order of
+/// their first track.
+pub fn group_albums(tracks: Vec<AlbumTrack>) -> Vec<Album> {
+    let mut albums: Vec<Album> = Vec::new();
+    let mut index: BTreeMap<(String, String), usize> = BTreeMap::new();
+
+    for track in tracks {
+        let meta = &track.metadata;
+        let artist = meta
+            .album_artist
+            .as_ref()
+            .map(|a| a.as_str())
+            .filter(|a| !a.is_empty())
+            .unwrap_or(meta.author.as_str())
+            .to_owned();
+
+        let (title, suffix_disc) = split_disc_suffix(meta.album.as_str());
+        let title = title.to_owned();
+        let disc = meta.disc_number.or(suffix_disc).unwrap_or(1);
+
+        let key = (artist.to_lowercase(), title.to_lowercase());
+        let i = *index.entry(key).or_insert_with(|| {
+            albums.push(Album { artist, title, discs: BTreeMap::new() });
+            albums.len() - 1
+        });
+
+        albums[i].discs.entry(disc).or_default().push(track);
+    }
+
+    for album in &mut albums {
+        for tracks in album.discs.values_mut() {
+            tracks.sort_by_key(|t| (t.metadata.track_number.unwrap_or(u32::MAX), t.path.clone()));
+        }
+    }
+
+    albums
+}
+
+/// The track's tag fields, plus `path`
+impl TemplateFields for AlbumTrack {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        match name {
+            "path" => Some(self.path.to_string_lossy()),
+            _ => self.metadata.field(name),
+        }
+    }
+}
+
+/// The first track's tag fields, plus `path` (the directory holding every
+/// track), `tracks` and `discs`
+impl TemplateFields for Album {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        match name {
+            "artist" | "albumartist" => Some(Cow::Borrowed(self.artist.as_str())),
+            "album" => Some(Cow::Borrowed(self.title.as_str())),
+            "path" => album_dir(self).map(|dir| Cow::Owned(dir.display().to_string())),
+            "tracks" => Some(Cow::Owned(self.track_count().to_string())),
+            "discs" => Some(Cow::Owned(self.disc_total().to_string())),
+            _ => self.tracks().next().and_then(|t| t.metadata.field(name)),
+        }
+    }
+}
+
+impl Album {
+    pub fn track_count(&self) -> usize {
+        self.discs.values().map(Vec::len).sum()
+    }
+
+    /// Number of discs the release should have, from tags or the discs present
+    pub fn disc_total(&self) -> u32 {
+        let tagged = self
+            .tracks()
+            .filter_map(|t| t.metadata.disc_total)
+            .max()
+            .unwrap_or(0);
+
+        tagged.max(self.discs.keys().copied().max().unwrap_or(0))
+    }
+
+    pub fn is_multidisc(&self) -> bool {
+        self.disc_total() > 1
+    }
+
+    pub fn tracks(&self) -> impl Iterator<Item = &AlbumTrack> {
+        self.discs.values().flatten()
+    }
+
+    /// Subtitle of `disc`, if any of its tracks carries one
+    pub fn disc_subtitle(&self, disc: u32) -> Option<&str> {
+        self.discs
+            .get(&disc)?
+            .iter()
+            .find_map(|t| t.metadata.disc_subtitle.as_ref())
+            .map(|s| s.as_str())
+            .filter(|s| !s.is_empty())
+    }
+
+    /// Check that discs run 1..=total and tracks on each disc run 1..=total
+    /// without gaps or repeats
+    pub fn check_numbering(&self) -> Vec<NumberingIssue> {
+        let mut issues = Vec::new();
+
+        for disc in 1..=self.disc_total() {
+            let Some(tracks) = self.discs.get(&disc) else {
+                issues.push(NumberingIssue::MissingDisc(disc));
+                continue;
+            };
+
+            let mut counts: BTreeMap<u32, usize> = BTreeMap::new();
+            for track in tracks {
+                match track.metadata.track_number {
+                    Some(n) => *counts.entry(n).or_default() += 1,
+                    None => issues.push(NumberingIssue::Unnumbered(track.path.clone())),
+                }
+            }
+
+            let tagged_total = tracks.iter().filter_map(|t| t.metadata.track_total).max().unwrap_or(0);
+            let total = tagged_total.max(counts.keys().copied().max().unwrap_or(0));
+
+            for track in 1..=total {
+                match counts.get(&track) {
+                    None => issues.push(NumberingIssue::MissingTrack { disc, track }),
+                    Some(n) if *n > 1 => issues.push(NumberingIssue::DuplicateTrack { disc, track }),
+                    _ => {}
+                }
+            }
+        }
+
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn track(album: &str, disc: Option<u32>, number: Option<u32>) -> AlbumTrack {
+        let s = |v: &str| flacman_core::String::from_str(v).unwrap();
+
+        AlbumTrack {
+            path: PathBuf::from(format!("{}-{:?}-{:?}.flac", album, disc, number)),
+            metadata: Metadata {
+                track_name: s("x"),
+                album: s(album),
+                author: s("The Who"),
+                album_artist: None,
+                track_number: number,
+                track_total: None,
+                disc_number: disc,
+                disc_total: None,
+                disc_subtitle: None,
+                genre: None,
+                year: None,
+                narrator: None,
+                label: None,
+                rating: None,
+                play_count: None,
+                properties: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_split_disc_suffix() {
+        assert_eq!(split_disc_suffix("Tommy (Disc 2)"), ("Tommy", Some(2)));
+        assert_eq!(split_disc_suffix("Tommy [CD1]"), ("Tommy", Some(1)));
+        assert_eq!(split_disc_suffix("Tommy - Disk 3"), ("Tommy", Some(3)));
+        assert_eq!(split_disc_suffix("Tommy CD 2"), ("Tommy", Some(2)));
+        assert_eq!(split_disc_suffix("Blink-182"), ("Blink-182", None));
+        assert_eq!(split_disc_suffix("Abacd 2"), ("Abacd 2", None));
+        assert_eq!(split_disc_suffix("CD 2"), ("CD 2", None));
+    }
+
+    #[test]
+    fn test_group_multidisc() {
+        let albums = group_albums(vec![
+            track("Tommy (Disc 1)", None, Some(1)),
+            track("Tommy (Disc 2)", None, Some(1)),
+            track("Tommy", Some(2), Some(2)),
+            track("Quadrophenia", None, Some(1)),
+        ]);
+
+        assert_eq!(albums.len(), 2);
+        assert_eq!(albums[0].title, "Tommy");
+        assert_eq!(albums[0].discs.len(), 2);
+        assert_eq!(albums[0].discs[&2].len(), 2);
+        assert!(albums[0].is_multidisc());
+        assert!(!albums[1].is_multidisc());
+    }
+
+    #[test]
+    fn test_check_numbering() {
+        let mut with_total = track("Tommy", Some(1), Some(1));
+        with_total.metadata.disc_total = Some(3);
+
+        let album = group_albums(vec![
+            with_total,
+            track("Tommy", Some(1), Some(3)),
+            track("Tommy", Some(2), Some(1)),
+            track("Tommy", Some(2), Some(1)),
+            track("Tommy", Some(2), None),
+        ])
+        .remove(0);
+
+        assert_eq!(
+            album.check_numbering(),
+            vec![
+                NumberingIssue::MissingTrack { disc: 1, track: 2 },
+                NumberingIssue::Unnumbered(PathBuf::from("Tommy-Some(2)-None.flac")),
+                NumberingIssue::DuplicateTrack { disc: 2, track: 1 },
+                NumberingIssue::MissingDisc(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_template_fields() {
+        let mut tracks = vec![track("Tommy", Some(1), Some(1)), track("Tommy", Some(2), Some(1))];
+        for (track, dir) in tracks.iter_mut().zip(["Tommy/CD1", "Tommy/CD2"]) {
+            track.path = PathBuf::from("/music/The Who").join(dir).join("01.flac");
+        }
+        let album = group_albums(tracks).remove(0);
+
+        let template: flacman_core::Template = "%albumartist% - %album% [%tracks% tracks, %discs% discs] %path%".parse().unwrap();
+        assert_eq!(template.render(&album), "The Who - Tommy [2 tracks, 2 discs] /music/The Who/Tommy");
+        let template: flacman_core::Template = "%disc%-%track:02% %title% %path%".parse().unwrap();
+        assert_eq!(template.render(&album.discs[&2][0]), "2-01 x /music/The Who/Tommy/CD2/01.flac");
+    }
+}
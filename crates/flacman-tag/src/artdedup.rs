@@ -0,0 +1,225 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::tag::{Tag, TagType};
+
+use crate::preview::{WritePreview, preview_write};
+use crate::tagerror::{Result, TagError};
+
+
+/// JPEG quality of embedded thumbnails
+const THUMBNAIL_QUALITY: u8 = 85;
+
+/// Front covers embedded in the tracks of one album
+#[derive(Debug, Clone, Default)]
+pub struct AlbumArt {
+    pub album_dir: PathBuf,
+    pub tracks: Vec<PathBuf>,
+    /// Tracks that embed a front cover
+    pub with_cover: usize,
+    /// How many different front covers they embed
+    pub distinct: usize,
+    /// Size of every embedded front cover added up
+    pub embedded_bytes: u64,
+    /// The cover, when every track embeds the same one
+    pub shared: Option<Picture>,
+}
+
+impl AlbumArt {
+    /// Bytes saved by keeping the shared cover once instead of in every
+    /// track, before thumbnails are added back
+    pub fn reclaimable(&self) -> u64 {
+        self.shared.as_ref().map_or(0, |p| self.embedded_bytes.saturating_sub(p.data().len() as u64))
+    }
+}
+
+/// What [`share_album_art`] did, or would do
+#[derive(Debug, Clone, Default)]
+pub struct ArtShareReport {
+    /// `folder.<ext>` holding the full-size cover
+    pub folder_image: Option<PathBuf>,
+    /// Tracks whose embedded cover was replaced
+    pub rewritten: Vec<PathBuf>,
+    /// Total size of the tracks before and after
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    /// What rewriting would change, when only previewing writes
+    pub previews: Vec<WritePreview>,
+}
+
+fn front_cover(path: &Path) -> Result<Option<Picture>> {
+    let tagged_file = lofty::read_from_path(path)?;
+    Ok(tagged_file.tags().iter().find_map(|tag| tag.get_picture_type(PictureType::CoverFront)).cloned())
+}
+
+/// Compare the front covers embedded in every track under `album_dir`
+pub fn scan_album_art(album_dir: &Path) -> Result<AlbumArt> {
+    let mut art = AlbumArt { album_dir: album_dir.to_path_buf(), ..Default::default() };
+    let mut covers: Vec<Picture> = Vec::new();
+    let mut every_track = true;
+
+    for track in flacman_fs::find_audio_files(album_dir)? {
+        match front_cover(&track)? {
+            Some(cover) => {
+                art.with_cover += 1;
+                art.embedded_bytes += cover.data().len() as u64;
+                if !covers.iter().any(|c| c.data() == cover.data()) {
+                    covers.push(cover);
+                }
+            }
+            None => every_track = false,
+        }
+        art.tracks.push(track);
+    }
+
+    art.distinct = covers.len();
+    if every_track && covers.len() == 1 && art.tracks.len() > 1 {
+        art.shared = covers.pop();
+    }
+
+    Ok(art)
+}
+
+/// `picture` scaled down to at most `max` pixels on its longer side, as a
+/// JPEG; `None` if it is already that small
+pub fn thumbnail(picture: &Picture, max: u32) -> Result<Option<Picture>> {
+    let image = image::load_from_memory(picture.data()).map_err(|e| TagError::Artwork(e.to_string()))?;
+    if image.width().max(image.height()) <= max {
+        return Ok(None);
+    }
+
+    let mut data = Vec::new();
+    let encoder = JpegEncoder::new_with_quality(Cursor::new(&mut data), THUMBNAIL_QUALITY);
+    image.thumbnail(max, max).to_rgb8().write_with_encoder(encoder).map_err(|e| TagError::Artwork(e.to_string()))?;
+
+    Ok(Some(Picture::new_unchecked(PictureType::CoverFront, Some(MimeType::Jpeg), None, data)))
+}
+
+/// Replace the embedded front cover of `path` with `cover`, or remove it
+fn replace_front_cover(path: &Path, cover: Option<&Picture>) -> Result<()> {
+    let mut tagged_file = lofty::read_from_path(path)?;
+
+    let tag_types: Vec<TagType> = tagged_file.tags().iter().map(|t| t.tag_type()).collect();
+    for tag_type in tag_types {
+        if let Some(tag) = tagged_file.tag_mut(tag_type) {
+            tag.remove_picture_type(PictureType::CoverFront);
+        }
+    }
+    if let Some(cover) = cover {
+        if tagged_file.primary_tag().is_none() {
+            tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+        }
+        tagged_file.primary_tag_mut().expect("primary tag was just inserted").push_picture(cover.clone());
+    }
+
+    tagged_file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+/// Keep the cover every track of an album embeds once, as `folder.<ext>`,
+/// and embed only a thumbnail of it
+///
+/// Albums whose cover is already no larger than the thumbnail are left
+/// alone.
+///
+/// # Arguments
+/// * `art` - The album, as scanned; its tracks must share one cover
+/// * `max` - Longest side of the embedded thumbnail; 0 embeds nothing
+/// * `preview` - Rewrite temporary copies and report the differences
+///   instead; no folder image is written
+///
+/// # Errors
+/// * `TagError::Artwork` - The tracks don't share a cover, the cover can't
+///   be decoded, or a different `folder.<ext>` is already there
+pub fn share_album_art(art: &AlbumArt, max: u32, preview: bool) -> Result<ArtShareReport> {
+    let mut report = ArtShareReport::default();
+    let Some(cover) = &art.shared else {
+        return Err(TagError::Artwork(format!("tracks of {} don't share one cover", art.album_dir.display())));
+    };
+
+    let embedded = if max == 0 {
+        None
+    } else {
+        match thumbnail(cover, max)? {
+            Some(thumbnail) => Some(thumbnail),
+            None => return Ok(report),
+        }
+    };
+
+    let extension = match cover.mime_type() {
+        Some(MimeType::Png) => "png",
+        _ => "jpg",
+    };
+    let folder_image = art.album_dir.join(format!("folder.{}", extension));
+    match fs::read(&folder_image) {
+        Ok(existing) if existing != cover.data() => {
+            return Err(TagError::Artwork(format!("{} already holds a different image", folder_image.display())));
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if !preview {
+                fs::write(&folder_image, cover.data())?;
+            }
+        }
+        Err(e) => return Err(e.into()),
+    }
+    report.folder_image = Some(folder_image);
+
+    for track in &art.tracks {
+        if preview {
+            let result = preview_write(track, |copy| replace_front_cover(copy, embedded.as_ref()))?;
+            report.bytes_before += result.size_before;
+            report.bytes_after += result.size_after;
+            report.previews.push(result);
+            continue;
+        }
+
+        report.bytes_before += fs::metadata(track)?.len();
+        replace_front_cover(track, embedded.as_ref())?;
+        report.bytes_after += fs::metadata(track)?.len();
+        report.rewritten.push(track.clone());
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage};
+
+    fn png(width: u32, height: u32) -> Picture {
+        let mut data = Vec::new();
+        RgbImage::new(width, height).write_to(&mut Cursor::new(&mut data), ImageFormat::Png).unwrap();
+        Picture::new_unchecked(PictureType::CoverFront, Some(MimeType::Png), None, data)
+    }
+
+    #[test]
+    fn test_thumbnail_keeps_aspect_ratio() {
+        let thumbnail = thumbnail(&png(1200, 600), 300).unwrap().unwrap();
+        assert_eq!(thumbnail.mime_type(), Some(&MimeType::Jpeg));
+
+        let decoded = image::load_from_memory(thumbnail.data()).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (300, 150));
+        assert!(super::thumbnail(&png(200, 200), 300).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_reclaimable() {
+        let cover = png(10, 10);
+        let art = AlbumArt {
+            with_cover: 3,
+            distinct: 1,
+            embedded_bytes: 3 * cover.data().len() as u64,
+            shared: Some(cover.clone()),
+            ..Default::default()
+        };
+        assert_eq!(art.reclaimable(), 2 * cover.data().len() as u64);
+        assert_eq!(AlbumArt { shared: None, ..art }.reclaimable(), 0);
+    }
+}
@@ -0,0 +1,381 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flacman_core::{DownloadUser, HttpRequest};
+use lofty::config::WriteOptions;
+use lofty::file::TaggedFileExt;
+use lofty::picture::{MimeType, Picture, PictureInformation, PictureType};
+use lofty::tag::{ItemKey, Tag, TagExt};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::preview::{WritePreview, preview_write};
+use crate::tagerror::Result;
+use crate::TagError;
+
+
+pub(crate) const USER_AGENT: &str = concat!("flacman/", env!("CARGO_PKG_VERSION"));
+const COVER_ART_ARCHIVE: &str = "https://coverartarchive.org";
+const FANART_TV: &str = "https://webservice.fanart.tv/v3/music/albums";
+
+/// Upper bound for a single downloaded image (scans of vinyl sleeves get big)
+const MAX_IMAGE_BYTES: u64 = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtSource {
+    /// coverartarchive.org, looked up by release or release group MBID
+    CoverArtArchive,
+    /// fanart.tv, looked up by release group MBID (requires an API key)
+    FanartTv,
+}
+
+/// A downloaded front cover candidate
+#[derive(Debug, Clone)]
+pub struct CoverArt {
+    pub source: ArtSource,
+    pub picture: Picture,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CoverArt {
+    fn from_bytes(source: ArtSource, data: Vec<u8>) -> Result<Self> {
+        let mut picture = Picture::from_reader(&mut data.as_slice())?;
+        picture.set_pic_type(PictureType::CoverFront);
+
+        let info = PictureInformation::from_picture(&picture)?;
+
+        Ok(CoverArt { source, picture, width: info.width, height: info.height })
+    }
+
+    /// File extension matching the image format (`jpg` if unknown)
+    pub fn extension(&self) -> &str {
+        match self.picture.mime_type() {
+            Some(MimeType::Png) => "png",
+            Some(MimeType::Gif) => "gif",
+            Some(MimeType::Bmp) => "bmp",
+            Some(MimeType::Tiff) => "tiff",
+            _ => "jpg",
+        }
+    }
+
+    fn area(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ArtFetchOptions {
+    /// fanart.tv personal/project API key; fanart.tv is skipped without one
+    pub fanart_api_key: Option<String>,
+    /// Write the chosen image next to the tracks as `folder.<ext>`
+    pub write_folder_image: bool,
+    /// Run downloads as this user when running as root
+    pub download_user: Option<DownloadUser>,
+    /// Embed into temporary copies and report the differences instead of
+    /// touching the tracks; no folder image is written
+    pub preview_writes: bool,
+}
+
+/// What happened to a single album directory
+#[derive(Debug, Default)]
+pub struct ArtFetchReport {
+    pub album_dir: PathBuf,
+    /// Number of tracks that had no front cover before fetching
+    pub missing: usize,
+    /// Source of the embedded image, `None` if nothing had to be fetched
+    pub source: Option<ArtSource>,
+    /// Tracks that received the front cover
+    pub embedded: Vec<PathBuf>,
+    /// Written `folder.jpg` (or `.png`), if any
+    pub folder_image: Option<PathBuf>,
+    /// What embedding would change, when only previewing writes
+    pub previews: Vec<WritePreview>,
+}
+
+/// Check whether a file already carries an embedded front cover
+pub fn has_front_cover(path: &Path) -> Result<bool> {
+    let tagged_file = lofty::read_from_path(path)?;
+
+    Ok(tagged_file
+        .tags()
+        .iter()
+        .any(|tag| tag.get_picture_type(PictureType::CoverFront).is_some()))
+}
+
+/// Read the MusicBrainz release and release group IDs from a file's tags
+///
+/// # Returns
+/// `(release_id, release_group_id)`, either of which may be missing
+pub fn release_ids(path: &Path) -> Result<(Option<String>, Option<String>)> {
+    let tagged_file = lofty::read_from_path(path)?;
+
+    let lookup = |key: ItemKey| {
+        tagged_file
+            .tags()
+            .iter()
+            .find_map(|tag| tag.get_string(&key))
+            .map(|s| s.trim().to_owned())
+            .filter(|s| !s.is_empty())
+    };
+
+    Ok((
+        lookup(ItemKey::MusicBrainzReleaseId),
+        lookup(ItemKey::MusicBrainzReleaseGroupId),
+    ))
+}
+
+/// GET `url`, as `user` if given
+///
+/// Only the host and path are traced: fanart.tv takes its API key in the query.
+#[instrument(
+    level = "debug",
+    skip(url, user),
+    fields(url = url.split_once('?').map_or(url, |(endpoint, _)| endpoint)),
+    err(level = "debug")
+)]
+fn http_get_bytes(url: &str, user: Option<&DownloadUser>) -> Result<Vec<u8>> {
+    let response = HttpRequest::get(url).header("User-Agent", USER_AGENT).send(user)?;
+
+    Ok(response.read_to_vec_limited(MAX_IMAGE_BYTES)?)
+}
+
+/// Fetch the front cover from the Cover Art Archive
+///
+/// Tries the exact release first and falls back to the release group,
+/// which covers releases that have no art of their own uploaded.
+///
+/// # Returns
+/// `None` if the archive has no front image for either ID
+pub fn fetch_cover_art_archive(
+    release_id: Option<&str>,
+    release_group_id: Option<&str>,
+    download_user: Option<&DownloadUser>,
+) -> Result<Option<CoverArt>> {
+    let urls = release_id
+        .map(|id| format!("{COVER_ART_ARCHIVE}/release/{id}/front"))
+        .into_iter()
+        .chain(release_group_id.map(|id| format!("{COVER_ART_ARCHIVE}/release-group/{id}/front")));
+
+    for url in urls {
+        match http_get_bytes(&url, download_user) {
+            Ok(data) => return CoverArt::from_bytes(ArtSource::CoverArtArchive, data).map(Some),
+            Err(TagError::Http(ureq::Error::StatusCode(404))) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(None)
+}
+
+#[derive(Deserialize)]
+struct FanartResponse {
+    #[serde(default)]
+    albums: std::collections::HashMap<String, FanartAlbum>,
+}
+
+#[derive(Deserialize)]
+struct FanartAlbum {
+    #[serde(default)]
+    albumcover: Vec<FanartImage>,
+}
+
+#[derive(Deserialize)]
+struct FanartImage {
+    url: String,
+    #[serde(default)]
+    likes: String,
+}
+
+/// Fetch the most liked album cover for a release group from fanart.tv
+///
+/// # Returns
+/// `None` if fanart.tv doesn't know the release group or has no covers for it
+pub fn fetch_fanart_tv(
+    release_group_id: &str,
+    api_key: &str,
+    download_user: Option<&DownloadUser>,
+) -> Result<Option<CoverArt>> {
+    let url = format!("{FANART_TV}/{release_group_id}?api_key={api_key}");
+
+    let body = match http_get_bytes(&url, download_user) {
+        Ok(body) => body,
+        Err(TagError::Http(ureq::Error::StatusCode(404))) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let body: FanartResponse =
+        serde_json::from_slice(&body).map_err(|e| TagError::Download(format!("fanart.tv: {e}")))?;
+
+    let best = body
+        .albums
+        .into_values()
+        .flat_map(|album| album.albumcover)
+        .max_by_key(|image| image.likes.parse::<u32>().unwrap_or(0));
+
+    match best {
+        Some(image) => {
+            let data = http_get_bytes(&image.url, download_user)?;
+            CoverArt::from_bytes(ArtSource::FanartTv, data).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Pick the highest resolution candidate
+///
+/// Ties go to the earlier candidate, so callers should pass sources in
+/// order of preference.
+pub fn pick_best(candidates: Vec<CoverArt>) -> Option<CoverArt> {
+    candidates
+        .into_iter()
+        .rev()
+        .max_by_key(CoverArt::area)
+}
+
+/// Embed `art` as the front cover of a file, replacing any existing front cover
+pub fn embed_front_cover(path: &Path, art: &CoverArt) -> Result<()> {
+    let mut tagged_file = lofty::read_from_path(path)?;
+
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+
+    let tag = tagged_file
+        .primary_tag_mut()
+        .expect("primary tag was just inserted");
+
+    tag.remove_picture_type(PictureType::CoverFront);
+    tag.push_picture(art.picture.clone());
+    tag.save_to_path(path, WriteOptions::default())?;
+
+    Ok(())
+}
+
+/// Write `art` into `dir` as `folder.<ext>`
+///
+/// # Returns
+/// Path of the written image
+pub fn write_folder_image(dir: &Path, art: &CoverArt) -> Result<PathBuf> {
+    let dest = dir.join(format!("folder.{}", art.extension()));
+    fs::write(&dest, art.picture.data())?;
+
+    Ok(dest)
+}
+
+/// Fetch and embed front cover art for every track in an album directory
+/// that doesn't have one yet
+///
+/// # Arguments
+/// * `album_dir` - Directory holding the album's tracks
+/// * `options` - Which sources to query and what to write
+///
+/// # Errors
+/// * `TagError::MissingReleaseId` - No track is tagged with a MusicBrainz release
+/// * `TagError::Http` - A source failed for reasons other than "not found"
+pub fn fetch_album_art(album_dir: &Path, options: &ArtFetchOptions) -> Result<ArtFetchReport> {
+    let mut report = ArtFetchReport { album_dir: album_dir.to_path_buf(), ..Default::default() };
+
+    let mut missing = Vec::new();
+    for track in flacman_fs::find_audio_files(album_dir)? {
+        if !has_front_cover(&track)? {
+            missing.push(track);
+        }
+    }
+
+    report.missing = missing.len();
+    if missing.is_empty() {
+        return Ok(report);
+    }
+
+    let mut release_id = None;
+    let mut release_group_id = None;
+    for track in &missing {
+        let (rid, rgid) = release_ids(track)?;
+        release_id = release_id.or(rid);
+        release_group_id = release_group_id.or(rgid);
+
+        if release_id.is_some() && release_group_id.is_some() {
+            break;
+        }
+    }
+
+    if release_id.is_none() && release_group_id.is_none() {
+        return Err(TagError::MissingReleaseId(album_dir.to_path_buf()));
+    }
+
+    let mut candidates = Vec::new();
+    let user = options.download_user.as_ref();
+    if let Some(art) = fetch_cover_art_archive(release_id.as_deref(), release_group_id.as_deref(), user)? {
+        candidates.push(art);
+    }
+
+    if let (Some(key), Some(rgid)) = (&options.fanart_api_key, &release_group_id)
+        && let Some(art) = fetch_fanart_tv(rgid, key, user)?
+    {
+        candidates.push(art);
+    }
+
+    let Some(art) = pick_best(candidates) else {
+        return Ok(report);
+    };
+
+    if options.preview_writes {
+        for track in missing {
+            report.previews.push(preview_write(&track, |copy| embed_front_cover(copy, &art))?);
+        }
+        report.source = Some(art.source);
+        return Ok(report);
+    }
+
+    for track in missing {
+        embed_front_cover(&track, &art)?;
+        report.embedded.push(track);
+    }
+
+    if options.write_folder_image {
+        report.folder_image = Some(write_folder_image(album_dir, &art)?);
+    }
+
+    report.source = Some(art.source);
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(source: ArtSource, width: u32, height: u32) -> CoverArt {
+        CoverArt {
+            source,
+            picture: Picture::new_unchecked(PictureType::CoverFront, Some(MimeType::Jpeg), None, Vec::new()),
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_pick_best_largest() {
+        let best = pick_best(vec![
+            candidate(ArtSource::CoverArtArchive, 500, 500),
+            candidate(ArtSource::FanartTv, 1000, 1000),
+        ]);
+        assert_eq!(best.unwrap().source, ArtSource::FanartTv);
+    }
+
+    #[test]
+    fn test_pick_best_tie_prefers_first() {
+        let best = pick_best(vec![
+            candidate(ArtSource::CoverArtArchive, 1000, 1000),
+            candidate(ArtSource::FanartTv, 1000, 1000),
+        ]);
+        assert_eq!(best.unwrap().source, ArtSource::CoverArtArchive);
+    }
+
+    #[test]
+    fn test_pick_best_empty() {
+        assert!(pick_best(Vec::new()).is_none());
+    }
+}
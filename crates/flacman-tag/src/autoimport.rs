@@ -0,0 +1,583 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flacman_core::Template;
+use flacman_fs::{FsTransaction, Plan, SanitizePolicy, SidecarPolicy, TransferMode, Trash, find_audio_files};
+use tracing::{debug, instrument};
+
+use crate::album::Album;
+use crate::artwork::release_ids;
+use crate::conflict::{
+    Conflict, ConflictDecision, ConflictResolution, ConflictStrategy, album_quality, resolve_conflict,
+};
+use crate::tagerror::Result;
+use crate::volumes::VolumeSet;
+
+
+/// How sure we are that an album's tags describe it well enough to file it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identification {
+    /// 0 (nothing to go on) to 100 (fully tagged and MusicBrainz-identified)
+    pub confidence: u8,
+    /// What lowered the confidence, one line each
+    pub problems: Vec<String>,
+}
+
+/// Judge how well `album` is identified by its own tags
+///
+/// Starts from full confidence and deducts for a missing artist or title,
+/// untitled tracks, numbering gaps and a missing MusicBrainz release ID.
+pub fn identify(album: &Album) -> Identification {
+    let mut confidence: i32 = 100;
+    let mut problems = Vec::new();
+
+    if album.artist.is_empty() {
+        confidence -= 40;
+        problems.push("no artist".to_owned());
+    }
+    if album.title.is_empty() {
+        confidence -= 40;
+        problems.push("no album title".to_owned());
+    }
+
+    let untitled = album.tracks().filter(|t| t.metadata.track_name.as_str().is_empty()).count();
+    if untitled > 0 {
+        confidence -= 20;
+        problems.push(format!("{} untitled track(s)", untitled));
+    }
+
+    for issue in album.check_numbering() {
+        confidence -= 10;
+        problems.push(issue.to_string());
+    }
+
+    let identified = album
+        .tracks()
+        .any(|t| release_ids(&t.path).is_ok_and(|(release, _)| release.is_some()));
+    if !identified {
+        confidence -= 15;
+        problems.push("no MusicBrainz release ID".to_owned());
+    }
+
+    Identification { confidence: confidence.clamp(0, 100) as u8, problems }
+}
+
+/// What became of one album picked up from an inbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    /// Filed into the library at these paths
+    Imported(Vec<PathBuf>),
+    /// Moved aside for review because it wasn't identified well enough
+    Held { dir: PathBuf, identification: Identification },
+    /// Already in the library at `existing`; `paths` holds what was
+    /// imported, nothing if the strategy kept the library copy
+    Resolved { paths: Vec<PathBuf>, existing: PathBuf, resolution: ConflictResolution },
+}
+
+/// Files albums from an inbox into the library by path template
+#[derive(Debug, Clone)]
+pub struct AutoImport {
+    pub library: PathBuf,
+    pub template: Template,
+    pub mode: TransferMode,
+    /// Albums identified with less confidence than this are held for review
+    pub min_confidence: u8,
+    /// What to do about albums that are already in the library
+    pub on_conflict: ConflictStrategy,
+    /// Where replaced albums go; without one they are deleted
+    pub trash: Option<Trash>,
+    /// Check every copied or moved track against its source by SHA-256
+    pub checksum: bool,
+    /// Roots of a library split across disks; albums go under the root of
+    /// the volume they are placed on, and under `library` when there are none
+    pub volumes: VolumeSet,
+    /// How names rendered from tags are made safe for the filesystem
+    pub sanitize: SanitizePolicy,
+    /// Non-audio files that go along with an album into its directory
+    pub sidecars: SidecarPolicy,
+}
+
+impl AutoImport {
+    /// The root `album` is filed under
+    pub fn root(&self, album: &Album) -> &Path {
+        self.volumes.place(album).map_or(self.library.as_path(), |volume| volume.root.as_path())
+    }
+
+    /// Where each track of `album` goes in the library
+    ///
+    /// # Returns
+    /// `(source, destination)` pairs, in disc and track order
+    pub fn destinations(&self, album: &Album) -> Vec<(PathBuf, PathBuf)> {
+        let root = self.root(album);
+        album
+            .tracks()
+            .map(|track| {
+                let rendered = self.template.render_path(&track.metadata, self.sanitize.replacement);
+                let extension = track.path.extension().map(|ext| ext.to_string_lossy());
+                let dest = root.join(self.sanitize.path(&rendered, extension.as_deref()));
+                (track.path.clone(), dest)
+            })
+            .collect()
+    }
+
+    /// The album already in the library where `album` would go, if any
+    ///
+    /// That is an existing directory, below the common parent of the
+    /// destinations, that holds audio files.
+    pub fn conflict(&self, album: &Album) -> Result<Option<Conflict>> {
+        let destinations = self.destinations(album);
+        let Some(dir) = common_dir(destinations.iter().map(|(_, dest)| dest.as_path())) else {
+            return Ok(None);
+        };
+        if dir == self.root(album) || !dir.is_dir() {
+            return Ok(None);
+        }
+
+        let existing = find_audio_files(&dir)?;
+        if existing.is_empty() {
+            return Ok(None);
+        }
+
+        let incoming: Vec<&Path> = destinations.iter().map(|(source, _)| source.as_path()).collect();
+        Ok(Some(Conflict {
+            existing_dir: dir,
+            existing_quality: album_quality(&existing),
+            existing,
+            incoming_quality: album_quality(&incoming),
+        }))
+    }
+
+    /// The changes importing `album` makes to the library, given how a
+    /// conflict with an existing album was resolved
+    pub fn plan(&self, album: &Album, conflict: Option<(&Conflict, &ConflictResolution)>) -> Plan {
+        let mut plan = Plan::new(self.root(album));
+        let destinations = self.resolved_destinations(album, conflict);
+
+        if let Some((existing, resolution)) = conflict
+            && resolution.decision == ConflictDecision::Replace
+        {
+            for path in &existing.existing {
+                if !destinations.iter().any(|(_, dest)| dest == path) {
+                    plan.prune(path);
+                }
+            }
+        }
+        for (_, dest) in &destinations {
+            plan.write(dest);
+        }
+        for (_, dest) in self.sidecar_destinations(album, &destinations).unwrap_or_default() {
+            plan.write(dest);
+        }
+
+        plan
+    }
+
+    /// The sidecars of `album` and the directory they were found in
+    ///
+    /// Only a directory that holds this album's tracks and no others has
+    /// sidecars; one shared with other albums, such as an inbox with loose
+    /// files, keeps them.
+    pub fn sidecars(&self, album: &Album) -> Result<Option<(PathBuf, Vec<PathBuf>)>> {
+        let Some(dir) = common_dir(album.tracks().map(|t| t.path.as_path())) else {
+            return Ok(None);
+        };
+        let shared = find_audio_files(&dir)?.iter().any(|f| !album.tracks().any(|t| &t.path == f));
+        if shared {
+            debug!(dir = %dir.display(), "not carrying sidecars out of a directory shared with other albums");
+            return Ok(None);
+        }
+
+        let sidecars = self.sidecars.find(&dir)?;
+        Ok(Some((dir, sidecars)))
+    }
+
+    /// Where the sidecars of `album` go, given where its tracks go; those
+    /// whose place is taken stay behind
+    pub fn sidecar_destinations(&self, album: &Album, destinations: &[(PathBuf, PathBuf)]) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let Some(dest_dir) = common_dir(destinations.iter().map(|(_, dest)| dest.as_path())) else {
+            return Ok(Vec::new());
+        };
+        // A template without directories puts the album straight in the root
+        if dest_dir == self.root(album) {
+            return Ok(Vec::new());
+        }
+        let Some((dir, sidecars)) = self.sidecars(album)? else {
+            return Ok(Vec::new());
+        };
+
+        Ok(sidecars
+            .into_iter()
+            .filter_map(|sidecar| {
+                let dest = dest_dir.join(sidecar.strip_prefix(&dir).ok()?);
+                (!dest.exists()).then_some((sidecar, dest))
+            })
+            .collect())
+    }
+
+    /// [`destinations`](Self::destinations), moved into the edition
+    /// directory when keeping both copies and dropped when keeping only the
+    /// library's
+    pub fn resolved_destinations(
+        &self,
+        album: &Album,
+        conflict: Option<(&Conflict, &ConflictResolution)>,
+    ) -> Vec<(PathBuf, PathBuf)> {
+        let destinations = self.destinations(album);
+
+        match conflict.map(|(existing, resolution)| (existing, &resolution.decision)) {
+            Some((_, ConflictDecision::Skip)) => Vec::new(),
+            Some((existing, ConflictDecision::KeepBoth(edition))) => destinations
+                .into_iter()
+                .map(|(source, dest)| {
+                    let relative = dest.strip_prefix(&existing.existing_dir).map(Path::to_path_buf);
+                    (source, relative.map_or(dest.clone(), |relative| edition.join(relative)))
+                })
+                .collect(),
+            _ => destinations,
+        }
+    }
+
+    /// Import `album`, or hold it under `review_dir` if it isn't identified well enough
+    ///
+    /// Held albums are moved (whatever the transfer mode) into a directory
+    /// named after the album, so they drop out of the inbox until someone
+    /// looks at them. An album that is already in the library is handled
+    /// by the conflict strategy; skipped albums stay where they are.
+    ///
+    /// # Arguments
+    /// * `choose` - Asked for a strategy when `on_conflict` is `Interactive`
+    ///
+    /// # Errors
+    /// Filesystem errors while transferring; the album's tracks are
+    /// transferred all together or not at all, though an album it was to
+    /// replace stays in the trash
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(artist = %album.artist, album = %album.title, mode = ?self.mode),
+        err(level = "debug")
+    )]
+    pub fn import(
+        &self,
+        album: &Album,
+        review_dir: &Path,
+        choose: &mut dyn FnMut(&Conflict) -> ConflictStrategy,
+    ) -> Result<ImportOutcome> {
+        let identification = identify(album);
+
+        if identification.confidence < self.min_confidence {
+            let name = match (album.artist.is_empty(), album.title.is_empty()) {
+                (false, false) => format!("{} - {}", album.artist, album.title),
+                _ => album
+                    .tracks()
+                    .next()
+                    .and_then(|t| t.path.parent()?.file_name())
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "Unknown".to_owned()),
+            };
+            let dir = review_dir.join(name.replace(['/', '\\'], "-"));
+            fs::create_dir_all(&dir)?;
+
+            let mut transaction = FsTransaction::new();
+            for track in album.tracks() {
+                transaction.stage(&track.path, dir.join(track.path.file_name().unwrap_or_default()), TransferMode::Move);
+            }
+            if let Some((album_dir, sidecars)) = self.sidecars(album)? {
+                for sidecar in sidecars {
+                    let relative = sidecar.strip_prefix(&album_dir).unwrap_or(&sidecar).to_path_buf();
+                    transaction.stage(sidecar, dir.join(relative), TransferMode::Move);
+                }
+            }
+            transaction.commit()?;
+
+            return Ok(ImportOutcome::Held { dir, identification });
+        }
+
+        let conflict = self.conflict(album)?;
+        let resolution = conflict.as_ref().map(|c| resolve_conflict(self.on_conflict, c, choose));
+        let resolved = conflict.as_ref().zip(resolution.as_ref());
+        if let Some((existing, resolution)) = resolved {
+            let existing = existing.existing_dir.display();
+            debug!(%existing, strategy = %resolution.strategy, "album already in library");
+        }
+
+        if let Some((existing, resolution)) = resolved
+            && resolution.decision == ConflictDecision::Replace
+        {
+            match &self.trash {
+                Some(trash) => {
+                    trash.remove(&existing.existing)?;
+                }
+                None => {
+                    for path in &existing.existing {
+                        fs::remove_file(path)?;
+                    }
+                }
+            }
+        }
+
+        let mut transaction = FsTransaction::new().checked(self.checksum);
+        let destinations = self.resolved_destinations(album, resolved);
+        let sidecars = self.sidecar_destinations(album, &destinations)?;
+        for (source, dest) in destinations {
+            transaction.stage(source, dest, self.mode);
+        }
+        for (source, dest) in &sidecars {
+            transaction.stage(source, dest, self.mode);
+        }
+        // Only the tracks are reported; the sidecars just went along
+        let imported = transaction
+            .commit()?
+            .into_iter()
+            .map(|transferred| transferred.dest)
+            .filter(|dest| !sidecars.iter().any(|(_, sidecar)| sidecar == dest))
+            .collect();
+
+        Ok(match (conflict, resolution) {
+            (Some(existing), Some(resolution)) => {
+                ImportOutcome::Resolved { paths: imported, existing: existing.existing_dir, resolution }
+            }
+            _ => ImportOutcome::Imported(imported),
+        })
+    }
+}
+
+/// Deepest directory holding all of `paths`
+fn common_dir<'a>(paths: impl Iterator<Item = &'a Path>) -> Option<PathBuf> {
+    let mut common: Option<PathBuf> = None;
+
+    for parent in paths.filter_map(Path::parent) {
+        common = Some(match common {
+            None => parent.to_path_buf(),
+            Some(dir) => dir.ancestors().find(|a| parent.starts_with(a))?.to_path_buf(),
+        });
+    }
+
+    common
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::album::AlbumTrack;
+    use crate::mediafile::Metadata;
+    use crate::volumes::Volume;
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+    use tempfile::tempdir;
+
+    fn album(dir: &Path, artist: &str, title: &str, tracks: &[(u32, &str)]) -> Album {
+        let s = |v: &str| flacman_core::String::from_str(v).unwrap();
+        let tracks = tracks
+            .iter()
+            .map(|(number, name)| {
+                let path = dir.join(format!("{:02}.flac", number));
+                fs::write(&path, b"audio").unwrap();
+                let metadata = Metadata {
+                    track_name: s(name),
+                    album: s(title),
+                    author: s(artist),
+                    album_artist: None,
+                    track_number: Some(*number),
+                    track_total: None,
+                    disc_number: None,
+                    disc_total: None,
+                    disc_subtitle: None,
+                    genre: None,
+                    year: None,
+                    narrator: None,
+                    label: None,
+                    rating: None,
+                    play_count: None,
+                    properties: Default::default(),
+                };
+                AlbumTrack { path, metadata }
+            })
+            .collect();
+
+        Album { artist: artist.to_owned(), title: title.to_owned(), discs: BTreeMap::from([(1, tracks)]) }
+    }
+
+    #[test]
+    fn test_import_files_by_template() {
+        let dir = tempdir().unwrap();
+        let inbox = dir.path().join("inbox");
+        fs::create_dir(&inbox).unwrap();
+        let album = album(&inbox, "Artist", "Album", &[(1, "One"), (2, "Two")]);
+
+        let import = AutoImport {
+            library: dir.path().join("library"),
+            template: "%albumartist%/%album%/%track:02% %title%".parse().unwrap(),
+            mode: TransferMode::Move,
+            min_confidence: 80,
+            on_conflict: ConflictStrategy::KeepHigherQuality,
+            trash: None,
+            checksum: true,
+            volumes: VolumeSet::default(),
+            sanitize: SanitizePolicy::default(),
+            sidecars: SidecarPolicy::default(),
+        };
+
+        let outcome = import.import(&album, &inbox.join(".review"), &mut |_| ConflictStrategy::Replace).unwrap();
+        let expected = dir.path().join("library/Artist/Album/02 Two.flac");
+        assert!(matches!(outcome, ImportOutcome::Imported(ref paths) if paths[1] == expected));
+        assert!(expected.exists());
+        assert!(!inbox.join("02.flac").exists());
+    }
+
+    #[test]
+    fn test_import_routes_to_volume() {
+        let dir = tempdir().unwrap();
+        let inbox = dir.path().join("inbox");
+        fs::create_dir(&inbox).unwrap();
+        let trust = album(&inbox, "Low", "Trust", &[(1, "Candy Girl")]);
+        let rumours = album(&inbox, "Fleetwood Mac", "Rumours", &[(1, "Dreams")]);
+
+        let mut volumes = VolumeSet::default();
+        volumes.add(Volume { name: "ssd".into(), root: dir.path().join("ssd"), rule: Some("album=trust".parse().unwrap()) });
+        volumes.add(Volume { name: "nas".into(), root: dir.path().join("nas"), rule: None });
+        let import = AutoImport {
+            library: dir.path().join("library"),
+            template: "%albumartist%/%album%/%title%".parse().unwrap(),
+            mode: TransferMode::Copy,
+            min_confidence: 0,
+            on_conflict: ConflictStrategy::KeepHigherQuality,
+            trash: None,
+            checksum: false,
+            volumes,
+            sanitize: SanitizePolicy::default(),
+            sidecars: SidecarPolicy::default(),
+        };
+
+        assert_eq!(import.destinations(&trust)[0].1, dir.path().join("ssd/Low/Trust/Candy Girl.flac"));
+        assert_eq!(import.root(&rumours), dir.path().join("nas"));
+        let unsplit = AutoImport { volumes: VolumeSet::default(), ..import.clone() };
+        assert_eq!(unsplit.root(&trust), dir.path().join("library"));
+    }
+
+    #[test]
+    fn test_sidecars_go_along() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("inbox/Trust");
+        fs::create_dir_all(source.join("Scans")).unwrap();
+        for name in ["cover.jpg", "Scans/booklet.pdf", "Trust.log", "notes.txt"] {
+            fs::write(source.join(name), b"x").unwrap();
+        }
+        let album = album(&source, "Low", "Trust", &[(1, "Canada"), (2, "Candy Girl")]);
+
+        let library = dir.path().join("library");
+        fs::create_dir_all(library.join("Low/Trust")).unwrap();
+        fs::write(library.join("Low/Trust/Trust.log"), b"kept").unwrap();
+        let import = AutoImport {
+            library: library.clone(),
+            template: "%albumartist%/%album%/%track:02% %title%".parse().unwrap(),
+            mode: TransferMode::Copy,
+            min_confidence: 0,
+            on_conflict: ConflictStrategy::KeepHigherQuality,
+            trash: None,
+            checksum: false,
+            volumes: VolumeSet::default(),
+            sanitize: SanitizePolicy::default(),
+            sidecars: SidecarPolicy::default(),
+        };
+        assert_eq!(import.plan(&album, None).summary(), "4 added, 0 overwritten, 0 pruned");
+
+        let ImportOutcome::Imported(paths) = import.import(&album, &dir.path().join(".review"), &mut |_| unreachable!()).unwrap() else {
+            panic!("expected the album to be imported");
+        };
+        assert_eq!(paths.len(), 2);
+        assert!(library.join("Low/Trust/cover.jpg").exists() && library.join("Low/Trust/Scans/booklet.pdf").exists());
+        assert_eq!(fs::read(library.join("Low/Trust/Trust.log")).unwrap(), b"kept");
+        assert!(!library.join("Low/Trust/notes.txt").exists());
+
+        // Another album's tracks in the same directory keep its sidecars there
+        fs::write(source.join("bonus.mp3"), b"audio").unwrap();
+        assert_eq!(import.sidecars(&album).unwrap(), None);
+    }
+
+    #[test]
+    fn test_poorly_tagged_album_is_held() {
+        let dir = tempdir().unwrap();
+        let album = album(dir.path(), "", "", &[(1, ""), (3, "")]);
+
+        let import = AutoImport {
+            library: dir.path().join("library"),
+            template: "%albumartist%/%album%/%title%".parse().unwrap(),
+            mode: TransferMode::Copy,
+            min_confidence: 50,
+            on_conflict: ConflictStrategy::KeepHigherQuality,
+            trash: None,
+            checksum: false,
+            volumes: VolumeSet::default(),
+            sanitize: SanitizePolicy::default(),
+            sidecars: SidecarPolicy::default(),
+        };
+
+        let review = dir.path().join(".review");
+        match import.import(&album, &review, &mut |_| ConflictStrategy::Replace).unwrap() {
+            ImportOutcome::Held { dir: held, identification } => {
+                assert!(identification.confidence < 50);
+                assert!(identification.problems.iter().any(|p| p == "no artist"));
+                assert!(held.starts_with(&review));
+                assert!(held.join("03.flac").exists());
+            }
+            outcome => panic!("expected the album to be held, got {:?}", outcome),
+        }
+        assert!(!dir.path().join("library").exists());
+    }
+
+    #[test]
+    fn test_conflict_strategies() {
+        let dir = tempdir().unwrap();
+        let inbox = dir.path().join("inbox");
+        fs::create_dir(&inbox).unwrap();
+        let library = dir.path().join("library");
+        fs::create_dir_all(library.join("Artist/Album")).unwrap();
+        fs::write(library.join("Artist/Album/01 One.flac"), b"old").unwrap();
+        fs::write(library.join("Artist/Album/03 Bonus.flac"), b"old").unwrap();
+
+        let mut import = AutoImport {
+            library: library.clone(),
+            template: "%albumartist%/%album%/%track:02% %title%".parse().unwrap(),
+            mode: TransferMode::Copy,
+            min_confidence: 0,
+            on_conflict: ConflictStrategy::Interactive,
+            trash: None,
+            checksum: false,
+            volumes: VolumeSet::default(),
+            sanitize: SanitizePolicy::default(),
+            sidecars: SidecarPolicy::default(),
+        };
+        let album = album(&inbox, "Artist", "Album", &[(1, "One"), (2, "Two")]);
+
+        let conflict = import.conflict(&album).unwrap().unwrap();
+        assert_eq!(conflict.existing_dir, library.join("Artist/Album"));
+        let replace = resolve_conflict(ConflictStrategy::Replace, &conflict, &mut |_| unreachable!());
+        let plan = import.plan(&album, Some((&conflict, &replace)));
+        assert_eq!(plan.summary(), "1 added, 1 overwritten, 1 pruned");
+
+        // Unreadable files can't be compared, so the library copy stays
+        let outcome = import.import(&album, &inbox.join(".review"), &mut |_| ConflictStrategy::KeepHigherQuality);
+        match outcome.unwrap() {
+            ImportOutcome::Resolved { paths, resolution, .. } => {
+                assert!(paths.is_empty());
+                assert_eq!(resolution.decision, ConflictDecision::Skip);
+            }
+            outcome => panic!("expected a resolved conflict, got {:?}", outcome),
+        }
+
+        import.on_conflict = ConflictStrategy::KeepBoth;
+        match import.import(&album, &inbox.join(".review"), &mut |_| unreachable!()).unwrap() {
+            ImportOutcome::Resolved { paths, existing, .. } => {
+                assert_eq!(existing, library.join("Artist/Album"));
+                assert_eq!(paths[1], library.join("Artist/Album (edition 2)/02 Two.flac"));
+            }
+            outcome => panic!("expected a resolved conflict, got {:?}", outcome),
+        }
+
+        import.on_conflict = ConflictStrategy::Replace;
+        import.import(&album, &inbox.join(".review"), &mut |_| unreachable!()).unwrap();
+        assert!(!library.join("Artist/Album/03 Bonus.flac").exists());
+        assert_eq!(fs::read(library.join("Artist/Album/01 One.flac")).unwrap(), b"audio");
+    }
+}
@@ -0,0 +1,219 @@
+use std::path::Path;
+
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::tag::{Accessor, ItemKey, Tag};
+use flacman_mb::MbClient;
+use serde::Deserialize;
+
+use crate::tagerror::{Result, TagError};
+
+
+/// Release facts that legacy imports are often missing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReleaseFacts {
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub label: Option<String>,
+}
+
+impl ReleaseFacts {
+    pub fn is_empty(&self) -> bool {
+        self.genre.is_none() && self.year.is_none() && self.label.is_none()
+    }
+
+    /// The facts of `canonical` that `self` lacks; what is already tagged
+    /// is never replaced
+    pub fn missing_from(&self, canonical: &ReleaseFacts) -> ReleaseFacts {
+        ReleaseFacts {
+            genre: canonical.genre.clone().filter(|_| self.genre.is_none()),
+            year: canonical.year.filter(|_| self.year.is_none()),
+            label: canonical.label.clone().filter(|_| self.label.is_none()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GenreJson {
+    name: String,
+    #[serde(default)]
+    count: u32,
+}
+
+#[derive(Deserialize)]
+struct LabelJson {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct LabelInfoJson {
+    label: Option<LabelJson>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseGroupJson {
+    #[serde(rename = "first-release-date", default)]
+    first_release_date: Option<String>,
+    #[serde(default)]
+    genres: Vec<GenreJson>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseJson {
+    date: Option<String>,
+    #[serde(default)]
+    genres: Vec<GenreJson>,
+    #[serde(rename = "label-info", default)]
+    label_info: Vec<LabelInfoJson>,
+    #[serde(rename = "release-group")]
+    release_group: Option<ReleaseGroupJson>,
+}
+
+impl ReleaseJson {
+    /// The most voted genre of the release, else of its release group; the
+    /// year the release group first came out, else the release's own; the
+    /// first credited label
+    fn facts(self) -> ReleaseFacts {
+        let year = |date: &Option<String>| date.as_deref().and_then(|d| d.get(..4)?.parse::<u32>().ok());
+        let top_genre = |genres: Vec<GenreJson>| genres.into_iter().max_by_key(|g| g.count).map(|g| g.name);
+        let (group_year, group_genre) = match self.release_group {
+            Some(group) => (year(&group.first_release_date), top_genre(group.genres)),
+            None => (None, None),
+        };
+
+        ReleaseFacts {
+            genre: top_genre(self.genres).or(group_genre),
+            year: group_year.or_else(|| year(&self.date)),
+            label: self.label_info.into_iter().find_map(|info| info.label).map(|label| label.name),
+        }
+    }
+}
+
+/// Fetch the canonical genre, year and label of a release from MusicBrainz
+pub fn fetch_release_facts(client: &MbClient, release_id: &str) -> Result<ReleaseFacts> {
+    let data = client.get(&format!("release/{release_id}"), &[("inc", "genres+labels+release-groups")])?;
+    let release: ReleaseJson = serde_json::from_slice(&data)
+        .map_err(|e| TagError::MusicBrainz(format!("unexpected response: {e}")))?;
+
+    Ok(release.facts())
+}
+
+/// The genre, year and label tagged on `path`
+pub fn read_release_facts(path: &Path) -> Result<ReleaseFacts> {
+    let tagged_file = lofty::read_from_path(path)?;
+    let Some(tag) = tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) else {
+        return Ok(ReleaseFacts::default());
+    };
+
+    let text = |value: Option<String>| value.map(|v| v.trim().to_owned()).filter(|v| !v.is_empty());
+    Ok(ReleaseFacts {
+        genre: text(tag.genre().map(|g| g.into_owned())),
+        year: tag.year(),
+        label: text(tag.get_string(&ItemKey::Label).map(str::to_owned)),
+    })
+}
+
+/// Tag `path` with every fact `facts` holds, leaving other tags alone
+pub fn write_release_facts(path: &Path, facts: &ReleaseFacts) -> Result<()> {
+    let mut tagged_file = lofty::read_from_path(path)?;
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    let tag = tagged_file.primary_tag_mut().expect("primary tag was just inserted");
+
+    if let Some(genre) = &facts.genre {
+        tag.set_genre(genre.clone());
+    }
+    // As the date most players read (DATE, TDRC, ©day) rather than the
+    // YEAR field some formats also have
+    if let Some(year) = facts.year {
+        tag.insert_text(ItemKey::RecordingDate, year.to_string());
+    }
+    if let Some(label) = &facts.label {
+        tag.insert_text(ItemKey::Label, label.clone());
+    }
+
+    tagged_file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+/// What the source of a download says one of its tracks is
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceTags {
+    pub artist: String,
+    pub album: String,
+    pub title: String,
+    pub year: Option<u32>,
+    pub track: Option<u32>,
+}
+
+/// Tag `path` with whatever of `tags` it lacks, so downloads from sources
+/// that don't tag their files (archive.org recordings, often) can be
+/// imported; tags the file has are left alone
+///
+/// # Returns
+/// Whether anything was written
+pub fn write_missing_tags(path: &Path, tags: &SourceTags) -> Result<bool> {
+    let mut tagged_file = lofty::read_from_path(path)?;
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    let tag = tagged_file.primary_tag_mut().expect("primary tag was just inserted");
+
+    let missing = |value: Option<std::borrow::Cow<'_, str>>| value.is_none_or(|v| v.trim().is_empty());
+    let mut changed = false;
+    if missing(tag.artist()) && !tags.artist.is_empty() {
+        tag.set_artist(tags.artist.clone());
+        changed = true;
+    }
+    if missing(tag.album()) && !tags.album.is_empty() {
+        tag.set_album(tags.album.clone());
+        changed = true;
+    }
+    if missing(tag.title()) && !tags.title.is_empty() {
+        tag.set_title(tags.title.clone());
+        changed = true;
+    }
+    if tag.track().is_none()
+        && let Some(track) = tags.track
+    {
+        tag.set_track(track);
+        changed = true;
+    }
+    if tag.year().is_none()
+        && let Some(year) = tags.year
+    {
+        tag.insert_text(ItemKey::RecordingDate, year.to_string());
+        changed = true;
+    }
+
+    if changed {
+        tagged_file.save_to_path(path, WriteOptions::default())?;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_release_facts() {
+        let json = r#"{"id": "b1c6d1b4", "title": "Trust", "date": "2004-03-01",
+            "genres": [{"name": "slowcore", "count": 3}, {"name": "indie rock", "count": 1}],
+            "label-info": [{"catalog-number": "KRANK 070", "label": {"name": "Kranky"}}],
+            "release-group": {"first-release-date": "2002-09-24", "genres": [{"name": "rock", "count": 9}]}}"#;
+        let release: ReleaseJson = serde_json::from_str(json).unwrap();
+        let canonical = release.facts();
+        assert_eq!(
+            canonical,
+            ReleaseFacts { genre: Some("slowcore".into()), year: Some(2002), label: Some("Kranky".into()) }
+        );
+
+        let tagged = ReleaseFacts { year: Some(2004), ..Default::default() };
+        let missing = tagged.missing_from(&canonical);
+        assert_eq!(missing.year, None);
+        assert_eq!(missing.label.as_deref(), Some("Kranky"));
+        assert!(canonical.missing_from(&canonical).is_empty());
+    }
+}
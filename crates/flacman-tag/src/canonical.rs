@@ -0,0 +1,211 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use flacman_core::String;
+use flacman_mb::MbRelease;
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::tag::{Accessor, ItemKey, Tag};
+
+use crate::album::Album;
+use crate::tagerror::Result;
+
+
+/// What MusicBrainz says one track should be tagged with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanonicalTrack {
+    pub title: std::string::String,
+    pub artist: std::string::String,
+    pub album: std::string::String,
+    pub album_artist: std::string::String,
+    pub year: Option<u32>,
+    pub disc: u32,
+    pub track: u32,
+    pub release_id: std::string::String,
+    pub release_group_id: Option<std::string::String>,
+    pub album_artist_id: Option<std::string::String>,
+    pub recording_id: std::string::String,
+    pub track_id: std::string::String,
+}
+
+/// Match the tracks of `album` to those of `release`
+///
+/// Tracks are matched by disc and track number. When numbers are missing
+/// or don't fit the release but the album has exactly as many tracks, they
+/// are matched in order instead.
+///
+/// # Returns
+/// The canonical tags of each matched track, by its path; tracks that
+/// couldn't be matched are left out
+pub fn canonical_tracks(album: &Album, release: &MbRelease) -> Vec<(PathBuf, CanonicalTrack)> {
+    let canonical = |disc: u32, track: &flacman_mb::MbTrack| CanonicalTrack {
+        title: track.title.clone(),
+        artist: track.artist.clone(),
+        album: release.title.clone(),
+        album_artist: release.artist.clone(),
+        year: release.year(),
+        disc,
+        track: track.position,
+        release_id: release.id.clone(),
+        release_group_id: release.release_group_id.clone(),
+        album_artist_id: release.artist_id.clone(),
+        recording_id: track.recording_id.clone(),
+        track_id: track.id.clone(),
+    };
+
+    let by_number: Vec<_> = album
+        .discs
+        .iter()
+        .flat_map(|(disc, tracks)| tracks.iter().map(move |t| (*disc, t)))
+        .filter_map(|(disc, t)| {
+            let mb = release.track(disc, t.metadata.track_number?)?;
+            Some((t.path.clone(), canonical(disc, mb)))
+        })
+        .collect();
+    if by_number.len() == album.track_count() || album.track_count() != release.track_count() {
+        return by_number;
+    }
+
+    album.tracks().zip(release.tracks()).map(|(t, (disc, mb))| (t.path.clone(), canonical(disc, mb))).collect()
+}
+
+/// Give the in-memory tags of `album` the canonical values of `tracks`,
+/// so names worked out from them (import destinations, say) are the
+/// canonical ones
+///
+/// A year already tagged is kept, as it may be that of the first release
+/// rather than this edition.
+pub fn apply_canonical(album: &mut Album, tracks: &[(PathBuf, CanonicalTrack)]) -> Result<()> {
+    let Some((_, first)) = tracks.first() else {
+        return Ok(());
+    };
+    album.artist = first.album_artist.clone();
+    album.title = first.album.clone();
+
+    for track in album.discs.values_mut().flatten() {
+        let Some((_, canonical)) = tracks.iter().find(|(path, _)| *path == track.path) else {
+            continue;
+        };
+        let metadata = &mut track.metadata;
+        metadata.track_name = String::from_str(&canonical.title)?;
+        metadata.author = String::from_str(&canonical.artist)?;
+        metadata.album = String::from_str(&canonical.album)?;
+        metadata.album_artist = Some(String::from_str(&canonical.album_artist)?);
+        metadata.track_number = Some(canonical.track);
+        metadata.disc_number = Some(canonical.disc);
+        metadata.year = metadata.year.or(canonical.year);
+    }
+    Ok(())
+}
+
+/// Tag `path` with the names, numbers and MusicBrainz IDs of `track`,
+/// leaving other tags alone
+pub fn write_canonical_tags(path: &Path, track: &CanonicalTrack) -> Result<()> {
+    let mut tagged_file = lofty::read_from_path(path)?;
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    let tag = tagged_file.primary_tag_mut().expect("primary tag was just inserted");
+
+    tag.set_title(track.title.clone());
+    tag.set_artist(track.artist.clone());
+    tag.set_album(track.album.clone());
+    tag.insert_text(ItemKey::AlbumArtist, track.album_artist.clone());
+    tag.set_track(track.track);
+    tag.set_disk(track.disc);
+    if tag.year().is_none()
+        && let Some(year) = track.year
+    {
+        tag.insert_text(ItemKey::RecordingDate, year.to_string());
+    }
+
+    tag.insert_text(ItemKey::MusicBrainzReleaseId, track.release_id.clone());
+    tag.insert_text(ItemKey::MusicBrainzRecordingId, track.recording_id.clone());
+    tag.insert_text(ItemKey::MusicBrainzTrackId, track.track_id.clone());
+    if let Some(id) = &track.release_group_id {
+        tag.insert_text(ItemKey::MusicBrainzReleaseGroupId, id.clone());
+    }
+    if let Some(id) = &track.album_artist_id {
+        tag.insert_text(ItemKey::MusicBrainzReleaseArtistId, id.clone());
+    }
+
+    tagged_file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::album::AlbumTrack;
+    use crate::mediafile::Metadata;
+    use flacman_mb::{MbMedium, MbTrack};
+    use std::collections::BTreeMap;
+
+    fn track(path: &str, number: Option<u32>) -> AlbumTrack {
+        let s = |v: &str| String::from_str(v).unwrap();
+        AlbumTrack {
+            path: PathBuf::from(path),
+            metadata: Metadata {
+                track_name: s(path),
+                album: s("trust"),
+                author: s("low"),
+                album_artist: None,
+                track_number: number,
+                track_total: None,
+                disc_number: None,
+                disc_total: None,
+                disc_subtitle: None,
+                genre: None,
+                year: None,
+                narrator: None,
+                label: None,
+                rating: None,
+                play_count: None,
+                properties: Default::default(),
+            },
+        }
+    }
+
+    fn release() -> MbRelease {
+        let mb = |n: u32, title: &str| MbTrack {
+            recording_id: format!("r{n}"),
+            id: format!("t{n}"),
+            title: title.to_owned(),
+            artist: "Low".to_owned(),
+            position: n,
+            length_ms: None,
+        };
+        MbRelease {
+            id: "7c3218d7".to_owned(),
+            title: "Trust".to_owned(),
+            artist: "Low".to_owned(),
+            artist_id: Some("6b5b8b5c".to_owned()),
+            date: Some("2002-09-17".to_owned()),
+            release_group_id: None,
+            media: vec![MbMedium { position: 1, title: None, tracks: vec![mb(1, "Canada"), mb(2, "Candy Girl")] }],
+        }
+    }
+
+    #[test]
+    fn test_canonical_tracks() {
+        let numbered = vec![track("b.flac", Some(2)), track("a.flac", Some(1))];
+        let mut album = Album { artist: "low".into(), title: "trust".into(), discs: BTreeMap::from([(1, numbered)]) };
+        let tracks = canonical_tracks(&album, &release());
+        assert_eq!(tracks.len(), 2);
+        assert_eq!((tracks[0].0.to_str(), tracks[0].1.title.as_str()), (Some("b.flac"), "Candy Girl"));
+
+        apply_canonical(&mut album, &tracks).unwrap();
+        assert_eq!((album.artist.as_str(), album.title.as_str()), ("Low", "Trust"));
+        let first = &album.discs[&1][1].metadata;
+        assert_eq!((first.track_name.as_str(), first.year), ("Canada", Some(2002)));
+
+        // Unnumbered tracks are matched in order when the counts agree
+        let unnumbered = vec![track("x.flac", None), track("y.flac", None)];
+        let album = Album { artist: "low".into(), title: "trust".into(), discs: BTreeMap::from([(1, unnumbered)]) };
+        let tracks = canonical_tracks(&album, &release());
+        assert_eq!((tracks[1].0.to_str(), tracks[1].1.track), (Some("y.flac"), 2));
+
+        let short = Album { discs: BTreeMap::from([(1, vec![track("x.flac", None)])]), ..album };
+        assert!(canonical_tracks(&short, &release()).is_empty());
+    }
+}
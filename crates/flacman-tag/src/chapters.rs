@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use lofty::file::TaggedFileExt;
+use lofty::tag::ItemKey;
+
+use crate::tagerror::Result;
+
+
+/// A chapter marker inside an audiobook or podcast file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    pub start: Duration,
+    pub title: String,
+}
+
+/// Parse a chapter start like `01:02:03.500` or `2:03`
+fn parse_timestamp(value: &str) -> Option<Duration> {
+    let mut secs = 0.0;
+    for part in value.trim().split(':') {
+        secs = secs * 60.0 + part.parse::<f64>().ok().filter(|n| *n >= 0.0)?;
+    }
+    Some(Duration::from_secs_f64(secs))
+}
+
+/// Read the chapter markers of a file
+///
+/// Chapters are read from the `CHAPTERnnn` / `CHAPTERnnnNAME` Vorbis
+/// comment convention used by FLAC, Ogg and Opus audiobooks. M4B and ID3v2
+/// chapters live outside the tag and aren't read yet.
+///
+/// # Returns
+/// Chapters sorted by start time; empty if the file has none
+pub fn read_chapters(path: &Path) -> Result<Vec<Chapter>> {
+    let tagged_file = lofty::read_from_path(path)?;
+    let mut starts: BTreeMap<u32, Duration> = BTreeMap::new();
+    let mut names: BTreeMap<u32, String> = BTreeMap::new();
+
+    for tag in tagged_file.tags() {
+        for item in tag.items() {
+            let ItemKey::Unknown(key) = item.key() else {
+                continue;
+            };
+            let Some(value) = item.value().text() else {
+                continue;
+            };
+            let Some(rest) = key.to_ascii_uppercase().strip_prefix("CHAPTER").map(str::to_owned) else {
+                continue;
+            };
+
+            let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+            let Ok(index) = rest[..digits].parse::<u32>() else {
+                continue;
+            };
+
+            match &rest[digits..] {
+                "" => {
+                    if let Some(start) = parse_timestamp(value) {
+                        starts.insert(index, start);
+                    }
+                }
+                "NAME" => {
+                    names.insert(index, value.to_owned());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut chapters: Vec<Chapter> = starts
+        .into_iter()
+        .map(|(index, start)| Chapter {
+            start,
+            title: names.remove(&index).unwrap_or_else(|| format!("Chapter {index}")),
+        })
+        .collect();
+    chapters.sort_by_key(|c| c.start);
+
+    Ok(chapters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("00:00:00.000"), Some(Duration::ZERO));
+        assert_eq!(parse_timestamp("01:02:03.5"), Some(Duration::from_millis(3_723_500)));
+        assert_eq!(parse_timestamp("2:03"), Some(Duration::from_secs(123)));
+        assert_eq!(parse_timestamp("soon"), None);
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use flacman_mb::MbClient;
+use serde::{Deserialize, Serialize};
+
+use crate::artwork::{USER_AGENT, release_ids};
+use crate::tagerror::{Result, TagError};
+
+
+/// Page size for browsing, and MBIDs per add request
+const BATCH_SIZE: usize = 100;
+
+/// A release in a MusicBrainz collection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionRelease {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+}
+
+#[derive(Deserialize)]
+struct ArtistCredit {
+    name: String,
+    #[serde(default)]
+    joinphrase: String,
+}
+
+#[derive(Deserialize)]
+struct ReleaseJson {
+    id: String,
+    title: String,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<ArtistCredit>,
+}
+
+#[derive(Deserialize)]
+struct BrowseJson {
+    releases: Vec<ReleaseJson>,
+    #[serde(rename = "release-count")]
+    release_count: usize,
+}
+
+/// A MusicBrainz release collection, read and changed through an
+/// [`MbClient`]
+///
+/// Reading a public collection needs no credentials. Adding releases, or
+/// reading a private collection, needs an OAuth token with the
+/// `collection` scope.
+#[derive(Debug, Clone)]
+pub struct MbCollection {
+    pub id: String,
+    pub token: Option<String>,
+}
+
+impl MbCollection {
+    /// Every release in the collection
+    pub fn releases(&self, client: &MbClient) -> Result<Vec<CollectionRelease>> {
+        let mut releases = Vec::new();
+        let limit = BATCH_SIZE.to_string();
+
+        loop {
+            let offset = releases.len().to_string();
+            let query =
+                [("collection", self.id.as_str()), ("inc", "artist-credits"), ("limit", &limit), ("offset", &offset)];
+            let data = client.get_uncached("release", &query, self.token.as_deref())?;
+            let page: BrowseJson = serde_json::from_slice(&data)
+                .map_err(|e| TagError::MusicBrainz(format!("unexpected response: {e}")))?;
+
+            let fetched = page.releases.len();
+            releases.extend(page.releases.into_iter().map(|r| CollectionRelease {
+                id: r.id,
+                title: r.title,
+                artist: r.artist_credit.iter().map(|c| format!("{}{}", c.name, c.joinphrase)).collect(),
+            }));
+
+            if fetched == 0 || releases.len() >= page.release_count {
+                return Ok(releases);
+            }
+        }
+    }
+
+    /// Add releases to the collection
+    ///
+    /// # Errors
+    /// * `TagError::MusicBrainz` - No token is configured
+    pub fn add(&self, client: &MbClient, release_ids: &[String]) -> Result<()> {
+        let Some(token) = &self.token else {
+            return Err(TagError::MusicBrainz("adding to a collection needs an OAuth token".to_owned()));
+        };
+
+        let client_id = USER_AGENT.replace('/', "-");
+        for batch in release_ids.chunks(BATCH_SIZE) {
+            let path = format!("collection/{}/releases/{}", self.id, batch.join(";"));
+            client.put(&path, &[("client", &client_id)], token)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// MusicBrainz release IDs tagged on any of `files`
+///
+/// Only one file per directory is read, since an album's tracks share
+/// a release.
+pub fn local_release_ids(files: &[PathBuf]) -> BTreeSet<String> {
+    let mut seen_dirs = BTreeSet::new();
+    let mut ids = BTreeSet::new();
+
+    for file in files {
+        if !seen_dirs.insert(file.parent().map(|p| p.to_path_buf())) {
+            continue;
+        }
+        if let Ok((Some(id), _)) = release_ids(file) {
+            ids.insert(id.to_lowercase());
+        }
+    }
+
+    ids
+}
+
+/// What a two-way sync has to do
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CollectionSync {
+    /// Owned locally but missing from the collection
+    pub to_add: Vec<String>,
+    /// In the collection but missing locally
+    pub wanted: Vec<CollectionRelease>,
+}
+
+impl CollectionSync {
+    pub fn new(local: &BTreeSet<String>, collection: &[CollectionRelease]) -> Self {
+        let remote: BTreeSet<String> = collection.iter().map(|r| r.id.to_lowercase()).collect();
+
+        CollectionSync {
+            to_add: local.difference(&remote).cloned().collect(),
+            wanted: collection.iter().filter(|r| !local.contains(&r.id.to_lowercase())).cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(id: &str) -> CollectionRelease {
+        CollectionRelease { id: id.into(), title: "Things We Lost in the Fire".into(), artist: "Low".into() }
+    }
+
+    #[test]
+    fn test_sync_plan() {
+        let local: BTreeSet<String> = ["aaa".to_owned(), "bbb".to_owned()].into();
+        let sync = CollectionSync::new(&local, &[release("BBB"), release("ccc")]);
+
+        assert_eq!(sync.to_add, vec!["aaa".to_owned()]);
+        assert_eq!(sync.wanted, vec![release("ccc")]);
+    }
+
+    #[test]
+    fn test_browse_response() {
+        let page: BrowseJson = serde_json::from_str(
+            r#"{"release-count": 1, "release-offset": 0, "releases": [{"id": "x", "title": "Zen Arcade",
+                "artist-credit": [{"name": "Hüsker Dü", "joinphrase": ""}]}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(page.release_count, 1);
+        assert_eq!(page.releases[0].artist_credit[0].name, "Hüsker Dü");
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::duplicates::AudioQuality;
+
+
+/// What to do when an imported album is already in the library
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictStrategy {
+    /// Keep whichever copy sounds better; a tie keeps the library's copy
+    #[default]
+    KeepHigherQuality,
+    /// Import next to the existing album as a separate edition
+    KeepBoth,
+    /// Replace the existing album with the incoming one
+    Replace,
+    /// Ask which of the other strategies to apply
+    Interactive,
+}
+
+impl FromStr for ConflictStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "keep-higher-quality" | "higher-quality" => Ok(ConflictStrategy::KeepHigherQuality),
+            "keep-both" | "keep-both-as-editions" | "editions" => Ok(ConflictStrategy::KeepBoth),
+            "replace" => Ok(ConflictStrategy::Replace),
+            "interactive" | "ask" => Ok(ConflictStrategy::Interactive),
+            _ => Err(format!(
+                "unknown conflict strategy {s:?} (expected keep-higher-quality, keep-both, replace or interactive)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ConflictStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            ConflictStrategy::KeepHigherQuality => "keep-higher-quality",
+            ConflictStrategy::KeepBoth => "keep-both",
+            ConflictStrategy::Replace => "replace",
+            ConflictStrategy::Interactive => "interactive",
+        })
+    }
+}
+
+/// An incoming album that would land on an album already in the library
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Library directory of the existing album
+    pub existing_dir: PathBuf,
+    /// Audio files of the existing album
+    pub existing: Vec<PathBuf>,
+    pub existing_quality: Option<AudioQuality>,
+    pub incoming_quality: Option<AudioQuality>,
+}
+
+/// What a conflict strategy decided
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictDecision {
+    /// Leave the library alone and don't import
+    Skip,
+    /// Trash the existing album, then import
+    Replace,
+    /// Import into this directory, next to the existing album
+    KeepBoth(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictResolution {
+    /// The strategy that decided, after asking for `Interactive`
+    pub strategy: ConflictStrategy,
+    pub decision: ConflictDecision,
+    /// Why, in a form fit for the transaction log
+    pub reason: String,
+}
+
+/// Quality of an album as a whole: that of its worst readable track
+pub fn album_quality<P: AsRef<Path>>(files: &[P]) -> Option<AudioQuality> {
+    files
+        .iter()
+        .filter_map(|f| AudioQuality::read(f.as_ref()).ok())
+        .min_by_key(AudioQuality::rank_key)
+}
+
+/// Decide what to do about `conflict`
+///
+/// # Arguments
+/// * `choose` - Asked for a strategy when `strategy` is `Interactive`;
+///   answering `Interactive` again falls back to `KeepHigherQuality`
+pub fn resolve_conflict(
+    strategy: ConflictStrategy,
+    conflict: &Conflict,
+    choose: &mut dyn FnMut(&Conflict) -> ConflictStrategy,
+) -> ConflictResolution {
+    let (decision, reason) = match strategy {
+        ConflictStrategy::Interactive => {
+            let chosen = match choose(conflict) {
+                ConflictStrategy::Interactive => ConflictStrategy::KeepHigherQuality,
+                chosen => chosen,
+            };
+            let mut resolution = resolve_conflict(chosen, conflict, choose);
+            resolution.reason.push_str(" (chosen interactively)");
+            return resolution;
+        }
+        ConflictStrategy::KeepHigherQuality => match (&conflict.incoming_quality, &conflict.existing_quality) {
+            (Some(incoming), Some(existing)) if incoming.rank_key() > existing.rank_key() => {
+                (ConflictDecision::Replace, format!("incoming {} is better than existing {}", incoming, existing))
+            }
+            (Some(incoming), Some(existing)) => (
+                ConflictDecision::Skip,
+                format!("existing {} is at least as good as incoming {}", existing, incoming),
+            ),
+            _ => (ConflictDecision::Skip, "quality could not be compared, keeping the library copy".to_owned()),
+        },
+        ConflictStrategy::KeepBoth => {
+            let dir = edition_dir(&conflict.existing_dir);
+            let name = dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            (ConflictDecision::KeepBoth(dir), format!("kept both, importing as {}", name))
+        }
+        ConflictStrategy::Replace => (ConflictDecision::Replace, "replacing the existing album".to_owned()),
+    };
+
+    ConflictResolution { strategy, decision, reason }
+}
+
+/// The first free `<album> (edition N)` directory next to `dir`
+fn edition_dir(dir: &Path) -> PathBuf {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+
+    (2..)
+        .map(|n| dir.with_file_name(format!("{} (edition {})", name, n)))
+        .find(|candidate| !candidate.exists())
+        .expect("some edition number is free")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn quality(lossless: bool, bitrate: u32) -> Option<AudioQuality> {
+        Some(AudioQuality {
+            format: if lossless { "flac" } else { "mp3" }.to_owned(),
+            lossless,
+            bit_depth: lossless.then_some(16),
+            sample_rate: Some(44_100),
+            bitrate: Some(bitrate),
+            size: 0,
+        })
+    }
+
+    #[test]
+    fn test_resolve_conflict() {
+        let dir = tempdir().unwrap();
+        let existing_dir = dir.path().join("Low/Trust");
+        fs::create_dir_all(&existing_dir).unwrap();
+        fs::create_dir_all(dir.path().join("Low/Trust (edition 2)")).unwrap();
+
+        let conflict = Conflict {
+            existing_dir: existing_dir.clone(),
+            existing: Vec::new(),
+            existing_quality: quality(false, 320),
+            incoming_quality: quality(true, 900),
+        };
+        let mut never = |_: &Conflict| -> ConflictStrategy { panic!("not interactive") };
+
+        let better = resolve_conflict(ConflictStrategy::KeepHigherQuality, &conflict, &mut never);
+        assert_eq!(better.decision, ConflictDecision::Replace);
+        assert_eq!(better.reason, "incoming FLAC 16-bit/44.1 kHz is better than existing MP3 320 kbps");
+
+        let worse = Conflict { existing_quality: quality(true, 900), incoming_quality: quality(false, 320), ..conflict };
+        let kept = resolve_conflict(ConflictStrategy::KeepHigherQuality, &worse, &mut never);
+        assert_eq!(kept.decision, ConflictDecision::Skip);
+
+        let both = resolve_conflict(ConflictStrategy::KeepBoth, &worse, &mut never);
+        assert_eq!(both.decision, ConflictDecision::KeepBoth(dir.path().join("Low/Trust (edition 3)")));
+
+        let asked = resolve_conflict(ConflictStrategy::Interactive, &worse, &mut |_| ConflictStrategy::Replace);
+        assert_eq!((asked.strategy, asked.decision), (ConflictStrategy::Replace, ConflictDecision::Replace));
+        assert!(asked.reason.ends_with("(chosen interactively)"));
+
+        assert_eq!("keep-both-as-editions".parse(), Ok(ConflictStrategy::KeepBoth));
+        assert!("merge".parse::<ConflictStrategy>().is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::tag::{Tag, TagType};
+
+use crate::artwork::has_front_cover;
+use crate::preview::{WritePreview, preview_write};
+use crate::tagerror::{Result, TagError};
+
+
+/// JPEG quality of resized or converted art
+const ART_QUALITY: u8 = 90;
+
+/// Stems a folder image goes by, in order of preference
+const FOLDER_IMAGE_STEMS: &[&str] = &["cover", "folder", "front"];
+
+const FOLDER_IMAGE_EXTS: &[&str] = &["jpg", "jpeg", "png"];
+
+/// What embedding or resizing art in an album did, or would do
+#[derive(Debug, Clone, Default)]
+pub struct CoverArtReport {
+    /// Tracks that were rewritten
+    pub rewritten: Vec<PathBuf>,
+    /// What rewriting would change, when only previewing writes
+    pub previews: Vec<WritePreview>,
+}
+
+/// The folder image of `album_dir`: `cover`, `folder` or `front` as a JPEG
+/// or PNG, in any case
+pub fn folder_image(album_dir: &Path) -> Option<PathBuf> {
+    let mut images: Vec<(usize, PathBuf)> = fs::read_dir(album_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+            let ext = path.extension()?.to_string_lossy().to_lowercase();
+            let rank = FOLDER_IMAGE_STEMS.iter().position(|s| *s == stem)?;
+            FOLDER_IMAGE_EXTS.contains(&ext.as_str()).then_some((rank, path))
+        })
+        .collect();
+    images.sort();
+    images.into_iter().next().map(|(_, path)| path)
+}
+
+/// `picture` as a JPEG or PNG no larger than `max` pixels on its longer
+/// side, keeping its type and description
+///
+/// Oversized art is scaled down and re-encoded as JPEG, as is art in any
+/// other format. `max` 0 means no limit.
+///
+/// # Returns
+/// `None` if the picture already fits
+///
+/// # Errors
+/// * `TagError::Artwork` - The picture can't be decoded
+pub fn fit_picture(picture: &Picture, max: u32) -> Result<Option<Picture>> {
+    let image = image::load_from_memory(picture.data()).map_err(|e| TagError::Artwork(e.to_string()))?;
+    let oversized = max > 0 && image.width().max(image.height()) > max;
+    if !oversized && matches!(picture.mime_type(), Some(MimeType::Jpeg | MimeType::Png)) {
+        return Ok(None);
+    }
+
+    let image = if oversized { image.thumbnail(max, max) } else { image };
+    let mut data = Vec::new();
+    let encoder = JpegEncoder::new_with_quality(Cursor::new(&mut data), ART_QUALITY);
+    image.to_rgb8().write_with_encoder(encoder).map_err(|e| TagError::Artwork(e.to_string()))?;
+
+    let description = picture.description().map(str::to_owned);
+    Ok(Some(Picture::new_unchecked(picture.pic_type(), Some(MimeType::Jpeg), description, data)))
+}
+
+/// The front cover embedded in `path`, else its first picture
+fn embedded_cover(path: &Path) -> Result<Option<Picture>> {
+    let tagged_file = lofty::read_from_path(path)?;
+    let tags = tagged_file.tags();
+    let front = tags.iter().find_map(|tag| tag.get_picture_type(PictureType::CoverFront));
+    Ok(front.or_else(|| tags.iter().find_map(|tag| tag.pictures().first())).cloned())
+}
+
+/// Write the cover embedded in the tracks under `album_dir` next to them
+///
+/// A JPEG or PNG is written as it is, to `cover.jpg` or `cover.png`; any
+/// other format is converted to `cover.jpg`.
+///
+/// # Arguments
+/// * `preview` - Only work out where the cover would go
+///
+/// # Returns
+/// The written image, or `None` when no track embeds a picture
+///
+/// # Errors
+/// * `TagError::Artwork` - A different image is already there, or the
+///   cover needs converting and can't be decoded
+pub fn extract_cover(album_dir: &Path, preview: bool) -> Result<Option<PathBuf>> {
+    let mut cover = None;
+    for track in flacman_fs::find_audio_files(album_dir)? {
+        cover = embedded_cover(&track)?;
+        if cover.is_some() {
+            break;
+        }
+    }
+    let Some(cover) = cover else {
+        return Ok(None);
+    };
+
+    let cover = fit_picture(&cover, 0)?.unwrap_or(cover);
+    let extension = if cover.mime_type() == Some(&MimeType::Png) { "png" } else { "jpg" };
+    let dest = album_dir.join(format!("cover.{}", extension));
+    match fs::read(&dest) {
+        Ok(existing) if existing != cover.data() => {
+            Err(TagError::Artwork(format!("{} already holds a different image", dest.display())))
+        }
+        Ok(_) => Ok(Some(dest)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if !preview {
+                fs::write(&dest, cover.data())?;
+            }
+            Ok(Some(dest))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Embed the folder image of `album_dir` as the front cover of every track
+/// there that has none
+///
+/// # Arguments
+/// * `max` - Longest side of the embedded cover; larger images are scaled
+///   down first, 0 embeds them as they are
+/// * `preview` - Rewrite temporary copies and report the differences
+///   instead
+///
+/// # Errors
+/// * `TagError::Artwork` - There is no folder image, or it can't be decoded
+pub fn embed_folder_art(album_dir: &Path, max: u32, preview: bool) -> Result<CoverArtReport> {
+    let mut report = CoverArtReport::default();
+    let Some(image) = folder_image(album_dir) else {
+        return Err(TagError::Artwork(format!("no folder image in {}", album_dir.display())));
+    };
+
+    let data = fs::read(&image)?;
+    let mut picture = Picture::from_reader(&mut data.as_slice())?;
+    picture.set_pic_type(PictureType::CoverFront);
+    let picture = fit_picture(&picture, max)?.unwrap_or(picture);
+
+    for track in flacman_fs::find_audio_files(album_dir)? {
+        if has_front_cover(&track)? {
+            continue;
+        }
+        if preview {
+            report.previews.push(preview_write(&track, |copy| push_picture(copy, &picture))?);
+            continue;
+        }
+        push_picture(&track, &picture)?;
+        report.rewritten.push(track);
+    }
+
+    Ok(report)
+}
+
+fn push_picture(path: &Path, picture: &Picture) -> Result<()> {
+    let mut tagged_file = lofty::read_from_path(path)?;
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    tagged_file.primary_tag_mut().expect("primary tag was just inserted").push_picture(picture.clone());
+
+    tagged_file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+/// Fit every picture embedded in `path` with [`fit_picture`]
+///
+/// # Returns
+/// Whether any picture had to change; the file is only written if so
+fn fit_embedded_art(path: &Path, max: u32) -> Result<bool> {
+    let mut tagged_file = lofty::read_from_path(path)?;
+    let mut changed = false;
+
+    let tag_types: Vec<TagType> = tagged_file.tags().iter().map(|t| t.tag_type()).collect();
+    for tag_type in tag_types {
+        let Some(tag) = tagged_file.tag_mut(tag_type) else { continue };
+        for i in 0..tag.pictures().len() {
+            if let Some(fitted) = fit_picture(&tag.pictures()[i], max)? {
+                tag.set_picture(i, fitted);
+                changed = true;
+            }
+        }
+    }
+
+    if changed {
+        tagged_file.save_to_path(path, WriteOptions::default())?;
+    }
+    Ok(changed)
+}
+
+/// Scale down or convert the art embedded in the tracks under `album_dir`
+/// that is larger than `max` pixels or neither JPEG nor PNG
+///
+/// # Arguments
+/// * `preview` - Rewrite temporary copies and report the differences
+///   instead
+pub fn resize_album_art(album_dir: &Path, max: u32, preview: bool) -> Result<CoverArtReport> {
+    let mut report = CoverArtReport::default();
+
+    for track in flacman_fs::find_audio_files(album_dir)? {
+        if preview {
+            let result = preview_write(&track, |copy| fit_embedded_art(copy, max).map(|_| ()))?;
+            if !result.is_unchanged() {
+                report.previews.push(result);
+            }
+        } else if fit_embedded_art(&track, max)? {
+            report.rewritten.push(track);
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage};
+    use tempfile::tempdir;
+
+    fn encoded(width: u32, height: u32, format: ImageFormat, mime: MimeType) -> Picture {
+        let mut data = Vec::new();
+        RgbImage::new(width, height).write_to(&mut Cursor::new(&mut data), format).unwrap();
+        Picture::new_unchecked(PictureType::CoverBack, Some(mime), Some("back".to_owned()), data)
+    }
+
+    #[test]
+    fn test_fit_picture() {
+        let fitted = fit_picture(&encoded(1600, 800, ImageFormat::Png, MimeType::Png), 1000).unwrap().unwrap();
+        assert_eq!((fitted.mime_type(), fitted.pic_type()), (Some(&MimeType::Jpeg), PictureType::CoverBack));
+        assert_eq!(fitted.description(), Some("back"));
+        let decoded = image::load_from_memory(fitted.data()).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (1000, 500));
+
+        assert!(fit_picture(&encoded(800, 800, ImageFormat::Png, MimeType::Png), 1000).unwrap().is_none());
+        assert!(fit_picture(&encoded(1600, 800, ImageFormat::Jpeg, MimeType::Jpeg), 0).unwrap().is_none());
+        let bmp = fit_picture(&encoded(20, 20, ImageFormat::Bmp, MimeType::Bmp), 0).unwrap().unwrap();
+        assert_eq!(bmp.mime_type(), Some(&MimeType::Jpeg));
+    }
+
+    #[test]
+    fn test_folder_image() {
+        let dir = tempdir().unwrap();
+        assert_eq!(folder_image(dir.path()), None);
+        for name in ["Folder.JPG", "back.jpg", "front.png", "cover.gif"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        assert_eq!(folder_image(dir.path()), Some(dir.path().join("Folder.JPG")));
+        fs::write(dir.path().join("cover.jpeg"), b"").unwrap();
+        assert_eq!(folder_image(dir.path()), Some(dir.path().join("cover.jpeg")));
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hasher};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use lofty::file::{AudioFile, FileType, TaggedFileExt};
+
+use crate::fingerprint::{self, Fingerprint};
+use crate::mediafile::MediaFile;
+use crate::tagerror::Result;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKind {
+    /// Byte-for-byte identical files
+    Identical,
+    /// Same recording by acoustic fingerprint, possibly in different formats/encodes
+    SameRecording,
+    /// Same artist and title tags and about the same length
+    SameTags,
+}
+
+/// Technical properties used to decide which copy of a recording to keep
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioQuality {
+    /// Lowercase file extension, e.g. `flac`
+    pub format: String,
+    pub lossless: bool,
+    pub bit_depth: Option<u8>,
+    pub sample_rate: Option<u32>,
+    /// Audio bitrate in kbps
+    pub bitrate: Option<u32>,
+    /// File size in bytes
+    pub size: u64,
+}
+
+impl AudioQuality {
+    pub fn read(path: &Path) -> Result<Self> {
+        let tagged_file = lofty::read_from_path(path)?;
+        let properties = tagged_file.properties();
+
+        let lossless = match tagged_file.file_type() {
+            FileType::Flac | FileType::Wav | FileType::Aiff | FileType::Ape | FileType::WavPack => true,
+            // ALAC reports a bit depth, AAC doesn't
+            FileType::Mp4 => properties.bit_depth().is_some(),
+            _ => false,
+        };
+
+        Ok(AudioQuality {
+            format: path
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default()
+                .to_lowercase(),
+            lossless,
+            bit_depth: properties.bit_depth(),
+            sample_rate: properties.sample_rate(),
+            bitrate: properties.audio_bitrate(),
+            size: fs::metadata(path)?.len(),
+        })
+    }
+
+    /// Sort key, higher is better: lossless first, then resolution, then bitrate
+    pub(crate) fn rank_key(&self) -> (bool, u8, u32, u32) {
+        (
+            self.lossless,
+            self.bit_depth.unwrap_or(0),
+            self.sample_rate.unwrap_or(0),
+            self.bitrate.unwrap_or(0),
+        )
+    }
+}
+
+/// e.g. `FLAC 24-bit/96 kHz` or `MP3 320 kbps`
+impl fmt::Display for AudioQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format.to_uppercase())?;
+
+        match (self.lossless, self.bit_depth, self.sample_rate, self.bitrate) {
+            (true, Some(bits), Some(rate), _) => write!(f, " {}-bit/{} kHz", bits, f64::from(rate) / 1000.0),
+            (_, _, _, Some(bitrate)) => write!(f, " {} kbps", bitrate),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A set of files that hold the same audio, best quality first
+#[derive(Debug, Clone)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    pub files: Vec<(PathBuf, AudioQuality)>,
+}
+
+impl DuplicateGroup {
+    fn new(kind: DuplicateKind, mut files: Vec<(PathBuf, AudioQuality)>) -> Self {
+        files.sort_by_key(|(_, q)| std::cmp::Reverse(q.rank_key()));
+        DuplicateGroup { kind, files }
+    }
+
+    /// Bytes freed by keeping only the best ranked file
+    pub fn reclaimable(&self) -> u64 {
+        self.files.iter().skip(1).map(|(_, q)| q.size).sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DuplicateOptions {
+    /// Also group different encodes by Chromaprint fingerprint (needs `fpcalc`)
+    pub fingerprint: bool,
+    /// Minimum fingerprint similarity to call two files the same recording
+    pub threshold: f64,
+    /// Maximum duration difference in seconds for fingerprint and tag comparison
+    pub max_duration_delta: f64,
+    /// Also group files tagged with the same artist and title
+    pub by_tags: bool,
+}
+
+impl Default for DuplicateOptions {
+    fn default() -> Self {
+        DuplicateOptions { fingerprint: false, threshold: 0.85, max_duration_delta: 3.0, by_tags: false }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DuplicateReport {
+    pub groups: Vec<DuplicateGroup>,
+    /// Files that couldn't be read or fingerprinted, with the reason
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// Hash full file contents
+fn content_hash(path: &Path) -> Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = DefaultHasher::new();
+    let mut buf = vec![0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buf[..n]);
+    }
+
+    Ok(hasher.finish())
+}
+
+fn find_root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// Group fingerprints whose durations are close and whose audio matches
+///
+/// # Returns
+/// Groups of indices into `prints`, only groups with more than one member
+fn group_fingerprints(prints: &[Fingerprint], options: &DuplicateOptions) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..prints.len()).collect();
+    order.sort_by(|&a, &b| prints[a].duration.total_cmp(&prints[b].duration));
+
+    let mut parent: Vec<usize> = (0..prints.len()).collect();
+
+    for (pos, &i) in order.iter().enumerate() {
+        for &j in &order[pos + 1..] {
+            if prints[j].duration - prints[i].duration > options.max_duration_delta {
+                break;
+            }
+
+            if fingerprint::similarity(&prints[i].raw, &prints[j].raw) >= options.threshold {
+                let (ri, rj) = (find_root(&mut parent, i), find_root(&mut parent, j));
+                parent[ri] = rj;
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..prints.len() {
+        let root = find_root(&mut parent, i);
+        groups.entry(root).or_default().push(i);
+    }
+
+    groups.into_values().filter(|g| g.len() > 1).collect()
+}
+
+/// Group files whose artist and title tags match, ignoring case, and whose
+/// durations are within `max_duration_delta` of the next shorter one
+///
+/// # Returns
+/// Groups of indices into `tags`, only groups with more than one member
+fn group_tags(tags: &[(String, String, f64)], max_duration_delta: f64) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..tags.len()).collect();
+    order.sort_by(|&a, &b| {
+        let ((artist_a, title_a, duration_a), (artist_b, title_b, duration_b)) = (&tags[a], &tags[b]);
+        (artist_a, title_a).cmp(&(artist_b, title_b)).then(duration_a.total_cmp(duration_b))
+    });
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (pos, &i) in order.iter().enumerate() {
+        let previous = pos.checked_sub(1).map(|p| &tags[order[p]]);
+        let (artist, title, duration) = &tags[i];
+        match previous {
+            Some((a, t, d)) if a == artist && t == title && duration - d <= max_duration_delta => {
+                groups.last_mut().expect("previous file started a group").push(i);
+            }
+            _ => groups.push(vec![i]),
+        }
+    }
+
+    groups.into_iter().filter(|g| g.len() > 1).collect()
+}
+
+/// Lowercased artist and title of `path` and its length in seconds;
+/// `None` for files without a title
+fn track_tags(path: &Path) -> Result<Option<(String, String, f64)>> {
+    let mut file = MediaFile::new(path);
+    let metadata = file.read()?;
+    let title = metadata.track_name.as_str().trim().to_lowercase();
+    if title.is_empty() {
+        return Ok(None);
+    }
+
+    let artist = metadata.author.as_str().trim().to_lowercase();
+    Ok(Some((artist, title, metadata.properties.duration.as_secs_f64())))
+}
+
+/// Find duplicate audio files
+///
+/// Byte-identical files are always reported. With `options.fingerprint`,
+/// one representative of every distinct file is fingerprinted and files
+/// holding the same recording are grouped as well; with `options.by_tags`,
+/// so are files tagged with the same artist and title at about the same
+/// length. Groups are ranked so the highest quality (lossless, then bit
+/// depth, sample rate, bitrate) copy comes first.
+pub fn find_duplicates(files: &[PathBuf], options: &DuplicateOptions) -> Result<DuplicateReport> {
+    let mut report = DuplicateReport::default();
+
+    let mut by_content: HashMap<(u64, u64), Vec<(PathBuf, AudioQuality)>> = HashMap::new();
+    for path in files {
+        let entry = AudioQuality::read(path).and_then(|q| Ok(((q.size, content_hash(path)?), q)));
+
+        match entry {
+            Ok((key, quality)) => by_content.entry(key).or_default().push((path.clone(), quality)),
+            Err(e) => report.skipped.push((path.clone(), e.to_string())),
+        }
+    }
+
+    let mut representatives = Vec::new();
+    for (_, group) in by_content {
+        representatives.push(group[0].clone());
+
+        if group.len() > 1 {
+            report.groups.push(DuplicateGroup::new(DuplicateKind::Identical, group));
+        }
+    }
+
+    if options.fingerprint {
+        let mut prints = Vec::new();
+        let mut fingerprinted = Vec::new();
+
+        for (path, quality) in representatives.clone() {
+            match fingerprint::fingerprint(&path) {
+                Ok(fp) => {
+                    prints.push(fp);
+                    fingerprinted.push((path, quality));
+                }
+                Err(e) => report.skipped.push((path, e.to_string())),
+            }
+        }
+
+        for members in group_fingerprints(&prints, options) {
+            let files = members.into_iter().map(|i| fingerprinted[i].clone()).collect();
+            report.groups.push(DuplicateGroup::new(DuplicateKind::SameRecording, files));
+        }
+    }
+
+    if options.by_tags {
+        let mut tags = Vec::new();
+        let mut tagged = Vec::new();
+
+        for (path, quality) in representatives {
+            match track_tags(&path) {
+                Ok(Some(tag)) => {
+                    tags.push(tag);
+                    tagged.push((path, quality));
+                }
+                Ok(None) => {}
+                Err(e) => report.skipped.push((path, e.to_string())),
+            }
+        }
+
+        for members in group_tags(&tags, options.max_duration_delta) {
+            let files: Vec<(PathBuf, AudioQuality)> = members.into_iter().map(|i| tagged[i].clone()).collect();
+            // Already reported if the fingerprints matched too
+            let known = report.groups.iter().any(|g| {
+                g.kind == DuplicateKind::SameRecording
+                    && g.files.len() == files.len()
+                    && files.iter().all(|(path, _)| g.files.iter().any(|(p, _)| p == path))
+            });
+            if !known {
+                report.groups.push(DuplicateGroup::new(DuplicateKind::SameTags, files));
+            }
+        }
+    }
+
+    report.groups.sort_by(|a, b| a.files[0].0.cmp(&b.files[0].0));
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quality(format: &str, lossless: bool, bitrate: u32) -> AudioQuality {
+        AudioQuality {
+            format: format.to_owned(),
+            lossless,
+            bit_depth: lossless.then_some(16),
+            sample_rate: Some(44100),
+            bitrate: Some(bitrate),
+            size: u64::from(bitrate) * 1000,
+        }
+    }
+
+    #[test]
+    fn test_group_ranks_lossless_first() {
+        let group = DuplicateGroup::new(
+            DuplicateKind::SameRecording,
+            vec![
+                (PathBuf::from("a.mp3"), quality("mp3", false, 320)),
+                (PathBuf::from("a.flac"), quality("flac", true, 900)),
+                (PathBuf::from("a.opus"), quality("opus", false, 128)),
+            ],
+        );
+
+        let order: Vec<&str> = group.files.iter().map(|(_, q)| q.format.as_str()).collect();
+        assert_eq!(order, ["flac", "mp3", "opus"]);
+        assert_eq!(group.reclaimable(), 320_000 + 128_000);
+    }
+
+    #[test]
+    fn test_group_fingerprints_respects_duration() {
+        let raw: Vec<u32> = (0..100u32).map(|i| i.wrapping_mul(2_654_435_761)).collect();
+        let print = |path: &str, duration: f64| Fingerprint {
+            path: PathBuf::from(path),
+            duration,
+            raw: raw.clone(),
+        };
+
+        let prints = vec![print("a", 200.0), print("b", 201.0), print("c", 260.0)];
+        let groups = group_fingerprints(&prints, &DuplicateOptions::default());
+
+        assert_eq!(groups.len(), 1);
+        let mut members = groups[0].clone();
+        members.sort();
+        assert_eq!(members, [0, 1]);
+    }
+
+    #[test]
+    fn test_group_tags() {
+        let tag = |artist: &str, title: &str, duration: f64| (artist.to_owned(), title.to_owned(), duration);
+        let tags = vec![
+            tag("low", "canada", 210.0),
+            tag("low", "canada", 261.0),
+            tag("low", "canada", 212.5),
+            tag("low", "candy girl", 211.0),
+            tag("low", "canada", 263.0),
+        ];
+
+        let mut groups = group_tags(&tags, 3.0);
+        groups.sort();
+        assert_eq!(groups, [vec![0, 2], vec![1, 4]]);
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flacman_core::{ExportOptions, ReplayGain};
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::tag::{ItemKey, Tag};
+
+use crate::album::AlbumTrack;
+use crate::replaygain::{R128_REFERENCE, REPLAYGAIN_REFERENCE};
+use crate::tagerror::Result;
+
+
+/// ReplayGain values from the tags of `tag`
+///
+/// The `R128_*` gains of Opus files count when there are no `REPLAYGAIN_*`
+/// ones, brought to the ReplayGain reference.
+pub(crate) fn tag_replay_gain(tag: &Tag) -> ReplayGain {
+    let gain = |key: &ItemKey| tag.get_string(key).and_then(ReplayGain::parse_gain);
+    let peak = |key: &ItemKey| tag.get_string(key).and_then(ReplayGain::parse_peak);
+    let r128 = |key: &str| {
+        let q78: i16 = tag.get_string(&ItemKey::Unknown(key.to_owned()))?.trim().parse().ok()?;
+        Some(f64::from(q78) / 256.0 + REPLAYGAIN_REFERENCE - R128_REFERENCE)
+    };
+
+    ReplayGain {
+        track_gain: gain(&ItemKey::ReplayGainTrackGain).or_else(|| r128("R128_TRACK_GAIN")),
+        track_peak: peak(&ItemKey::ReplayGainTrackPeak),
+        album_gain: gain(&ItemKey::ReplayGainAlbumGain).or_else(|| r128("R128_ALBUM_GAIN")),
+        album_peak: peak(&ItemKey::ReplayGainAlbumPeak),
+    }
+}
+
+/// ReplayGain values of the file at `path`; empty when it has none
+pub fn read_replay_gain(path: &Path) -> Result<ReplayGain> {
+    let tagged_file = lofty::read_from_path(path)?;
+    let tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag());
+
+    Ok(tag.map(tag_replay_gain).unwrap_or_default())
+}
+
+/// Write an extended M3U playlist of `tracks` to `playlist`
+///
+/// Paths below the playlist's directory are written relative to it, so
+/// the playlist keeps working when the library is mounted elsewhere.
+/// Each entry gets an `#EXTINF` line and, when `options` keep ReplayGain
+/// as tags, an `#EXTGAIN` line with the gain to apply.
+///
+/// # Returns
+/// Number of entries that carry a gain
+pub fn write_m3u(playlist: &Path, tracks: &[&AlbumTrack], options: &ExportOptions) -> Result<usize> {
+    let base = playlist.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut out = String::from("#EXTM3U\n");
+    let mut with_gain = 0;
+
+    for track in tracks {
+        let tagged_file = lofty::read_from_path(&track.path)?;
+        let seconds = tagged_file.properties().duration().as_secs();
+        let replay_gain = tagged_file
+            .primary_tag()
+            .or_else(|| tagged_file.first_tag())
+            .map(tag_replay_gain)
+            .unwrap_or_default();
+
+        let metadata = &track.metadata;
+        let _ = writeln!(out, "#EXTINF:{},{} - {}", seconds, metadata.author.as_str(), metadata.track_name.as_str());
+        if let Some(line) = options.m3u_gain_line(&replay_gain) {
+            out.push_str(&line);
+            out.push('\n');
+            with_gain += 1;
+        }
+
+        let path: PathBuf = track.path.strip_prefix(&base).map_or_else(|_| track.path.clone(), Path::to_path_buf);
+        let _ = writeln!(out, "{}", path.display());
+    }
+
+    if let Some(parent) = playlist.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(playlist, out)?;
+
+    Ok(with_gain)
+}
@@ -0,0 +1,138 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::tagerror::Result;
+use crate::TagError;
+
+
+/// Chromaprint's `fpcalc` binary, looked up on `PATH`
+const FPCALC: &str = "fpcalc";
+
+/// How far two fingerprints may be shifted against each other when
+/// comparing (one raw item is ~0.124s, so this is about 10 seconds of
+/// leading silence or pregap difference)
+const MAX_OFFSET: usize = 80;
+
+/// Minimum number of overlapping items for a comparison to count
+const MIN_OVERLAP: usize = 40;
+
+/// Acoustic fingerprint of a single recording
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    pub path: PathBuf,
+    /// Duration reported by fpcalc, in seconds
+    pub duration: f64,
+    /// Raw (uncompressed) Chromaprint fingerprint
+    pub raw: Vec<u32>,
+}
+
+#[derive(Deserialize)]
+struct FpcalcOutput {
+    duration: f64,
+    fingerprint: Vec<u32>,
+}
+
+/// Compute the Chromaprint fingerprint of an audio file with `fpcalc`
+///
+/// # Errors
+/// * `TagError::Fingerprint` - fpcalc is missing, failed, or printed garbage
+pub fn fingerprint(path: &Path) -> Result<Fingerprint> {
+    let output = Command::new(FPCALC)
+        .arg("-raw")
+        .arg("-json")
+        .arg(path)
+        .output()
+        .map_err(|e| TagError::Fingerprint(path.to_path_buf(), format!("cannot run {FPCALC}: {e}")))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(TagError::Fingerprint(path.to_path_buf(), stderr.trim().to_owned()));
+    }
+
+    let parsed: FpcalcOutput = serde_json::from_slice(&output.stdout)
+        .map_err(|e| TagError::Fingerprint(path.to_path_buf(), e.to_string()))?;
+
+    Ok(Fingerprint {
+        path: path.to_path_buf(),
+        duration: parsed.duration,
+        raw: parsed.fingerprint,
+    })
+}
+
+/// Compare two raw fingerprints
+///
+/// # Returns
+/// Fraction of matching bits at the best alignment, in `0.0..=1.0`.
+/// Unrelated recordings hover around 0.5; different encodes of the same
+/// recording usually score above 0.85.
+pub fn similarity(a: &[u32], b: &[u32]) -> f64 {
+    let mut best = 0.0;
+
+    for offset in 0..=MAX_OFFSET {
+        for (x, y) in [(a, b), (b, a)] {
+            if offset >= x.len() {
+                continue;
+            }
+
+            let x = &x[offset..];
+            let overlap = x.len().min(y.len());
+            if overlap < MIN_OVERLAP {
+                continue;
+            }
+
+            let errors: u32 = x.iter().zip(y).map(|(p, q)| (p ^ q).count_ones()).sum();
+            let score = 1.0 - f64::from(errors) / (overlap as f64 * 32.0);
+
+            if score > best {
+                best = score;
+            }
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random items so tests don't need real audio
+    fn noise(seed: u32, len: usize) -> Vec<u32> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_similarity_identical() {
+        let fp = noise(1, 200);
+        assert_eq!(similarity(&fp, &fp), 1.0);
+    }
+
+    #[test]
+    fn test_similarity_shifted() {
+        let fp = noise(1, 200);
+        assert_eq!(similarity(&fp[10..], &fp), 1.0);
+        assert_eq!(similarity(&fp, &fp[10..]), 1.0);
+    }
+
+    #[test]
+    fn test_similarity_unrelated() {
+        let score = similarity(&noise(1, 200), &noise(2, 200));
+        assert!(score < 0.7, "unrelated score {score}");
+    }
+
+    #[test]
+    fn test_similarity_too_short() {
+        let fp = noise(1, 10);
+        assert_eq!(similarity(&fp, &fp), 0.0);
+    }
+}
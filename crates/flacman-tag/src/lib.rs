@@ -1,6 +1,78 @@
 mod tagerror;
 mod mediafile;
+mod artwork;
+mod artdedup;
+mod coverart;
+mod fingerprint;
+mod duplicates;
+mod album;
+mod validate;
+mod numbering;
+mod view;
+mod collection;
+mod chapters;
+mod autoimport;
+mod conflict;
+mod rating;
+mod playstats;
+mod mpd;
+mod volumes;
+mod export;
+mod search;
+mod provenance;
+mod preview;
+mod watchlist;
+mod publish;
+mod backfill;
+mod canonical;
+mod scanner;
+mod replaygain;
+mod playlist;
 
 
 pub use tagerror::TagError;
-pub use mediafile::*;
\ No newline at end of file
+pub use mediafile::*;
+pub use album::{Album, AlbumTrack, NumberingIssue, group_albums, split_disc_suffix};
+pub use artdedup::{AlbumArt, ArtShareReport, scan_album_art, share_album_art, thumbnail};
+pub use artwork::{
+    ArtFetchOptions, ArtFetchReport, ArtSource, CoverArt, embed_front_cover, fetch_album_art,
+    fetch_cover_art_archive, fetch_fanart_tv, has_front_cover, pick_best, release_ids,
+    write_folder_image,
+};
+pub use coverart::{CoverArtReport, embed_folder_art, extract_cover, fit_picture, folder_image, resize_album_art};
+pub use fingerprint::{Fingerprint, fingerprint, similarity};
+pub use duplicates::{
+    AudioQuality, DuplicateGroup, DuplicateKind, DuplicateOptions, DuplicateReport, find_duplicates,
+};
+pub use numbering::{NumberingFix, TrackNumbers, plan_numbering};
+pub use view::{ViewFacet, ViewRegistry, ViewReport, ViewSpec, album_dir, build_view};
+pub use collection::{CollectionRelease, CollectionSync, MbCollection, local_release_ids};
+pub use conflict::{
+    Conflict, ConflictDecision, ConflictResolution, ConflictStrategy, album_quality, resolve_conflict,
+};
+pub use autoimport::{AutoImport, Identification, ImportOutcome, identify};
+pub use rating::{
+    Popularity, parse_rating, popm_to_stars, read_popularity, stars_to_popm, write_popularity,
+};
+pub use playstats::{PlayStats, listenbrainz_play_stats, mpd_play_stats};
+pub use mpd::{MpdClient, NowPlaying, update_uris};
+pub use preview::{WritePreview, preview_write, tag_snapshot};
+pub use publish::{PUBLISH_INDEX, PublishedAlbum, Publisher};
+pub use provenance::{encoder_chain, track_provenance};
+pub use search::{ReleaseInfo, SearchHit, SearchKind, lookup_release, search_musicbrainz};
+pub use export::{read_replay_gain, write_m3u};
+pub use playlist::{PathStyle, PlaylistFormat, write_playlist};
+pub use watchlist::{Subscription, Watchlist, WatchlistMerge};
+pub use volumes::{Relocation, Volume, VolumeSet};
+pub use chapters::{Chapter, read_chapters};
+pub use backfill::{ReleaseFacts, SourceTags, fetch_release_facts, read_release_facts, write_missing_tags, write_release_facts};
+pub use canonical::{CanonicalTrack, apply_canonical, canonical_tracks, write_canonical_tags};
+pub use replaygain::{
+    Loudness, REPLAYGAIN_REFERENCE, album_replay_gain, analyze_album, has_replay_gain, measure_loudness,
+    write_replay_gain,
+};
+pub use scanner::{Scan, ScanSummary, Scanner};
+pub use validate::{
+    ValidationFailure, ValidationReport, check_files, flac_md5_tag, streaminfo_md5, unchanged_since_indexed, validate_file,
+    validate_files, verify_stored_checksums,
+};
@@ -1,8 +1,10 @@
-use std::{fs::File, path::{Path, PathBuf}};
+use std::{borrow::Cow, fs::File, io::ErrorKind, path::{Path, PathBuf}, str::FromStr, time::Duration};
 
-use flacman_core::String;
-use lofty::file::TaggedFileExt;
-use crate::tagerror::Result;
+use flacman_core::{String, TemplateFields};
+use lofty::{file::{AudioFile, FileType, TaggedFileExt}, tag::{Accessor, ItemKey}};
+use tracing::instrument;
+use crate::rating::{read_popularity, tag_popularity};
+use crate::tagerror::{Result, TagError};
 
 
 pub struct MediaFile {
@@ -11,30 +13,209 @@ pub struct MediaFile {
 }
 
 impl MediaFile {
-    
+
     pub fn new(path: &Path) -> Self {
-        MediaFile { path: path.to_path_buf() }
+        MediaFile { path: path.to_path_buf(), metadata: None }
     }
 
-    fn 
-
+    /// Read the tags and audio properties of the file, once
+    ///
+    /// # Errors
+    /// * `TagError::NotFound` - The file doesn't exist
+    /// * `TagError::PermissionError` - The file can't be opened for reading
+    /// * `TagError::NotAFile` - The path is a directory
+    /// * `TagError::LoftyReadError` - The format is unsupported or the file is damaged
+    #[instrument(level = "trace", skip(self), fields(path = %self.path.display()))]
     pub fn read(&mut self) -> Result<&Metadata> {
 
-        if let Some(metadata) = &self.metadata {
-            return Ok(metadata);
-        }
+        if self.metadata.is_none() {
+            if self.path.is_dir() {
+                return Err(TagError::NotAFile(self.path.clone()));
+            }
+            let mut file = File::open(&self.path).map_err(|e| match e.kind() {
+                ErrorKind::NotFound => TagError::NotFound(self.path.clone()),
+                ErrorKind::PermissionDenied => TagError::PermissionError(self.path.clone()),
+                _ => e.into(),
+            })?;
+            let tagged_file = lofty::read_from(&mut file)?;
+            let p_tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag());
+            let popularity = match tagged_file.file_type() {
+                FileType::Mpeg => read_popularity(&self.path)?,
+                _ => p_tag.map(tag_popularity).unwrap_or_default(),
+            };
 
-        let mut file = File::open(&self.path)?;
-        let tagged_file = lofty::read_from(&mut file)?;
-        let p_tag = tagged_file.primary_tag();
+            let field = |value: Option<Cow<'_, str>>| {
+                String::from_str(value.as_deref().unwrap_or_default())
+            };
 
+            let item = |key: ItemKey| {
+                p_tag.and_then(|t| t.get_string(&key)).map(String::from_str).transpose()
+            };
+
+            self.metadata = Some(Metadata {
+                track_name: field(p_tag.and_then(|t| t.title()))?,
+                album: field(p_tag.and_then(|t| t.album()))?,
+                author: field(p_tag.and_then(|t| t.artist()))?,
+                album_artist: item(ItemKey::AlbumArtist)?,
+                track_number: p_tag.and_then(|t| t.track()),
+                track_total: p_tag.and_then(|t| t.track_total()),
+                disc_number: p_tag.and_then(|t| t.disk()),
+                disc_total: p_tag.and_then(|t| t.disk_total()),
+                disc_subtitle: item(ItemKey::SetSubtitle)?,
+                genre: p_tag.and_then(|t| t.genre()).map(|g| String::from_str(&g)).transpose()?,
+                year: p_tag.and_then(|t| t.year()),
+                narrator: p_tag.and_then(narrator).map(String::from_str).transpose()?,
+                label: item(ItemKey::Label)?,
+                rating: popularity.rating,
+                play_count: popularity.play_count,
+                properties: AudioProperties::of(tagged_file.properties()),
+            });
+        }
 
-        return ;
-    } 
+        Ok(self.metadata.as_ref().expect("metadata was just populated"))
+    }
 }
 
+#[derive(Debug, Clone)]
 pub struct Metadata {
     pub track_name: String,
     pub album: String,
     pub author: String,
-}
\ No newline at end of file
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    /// Title of this disc within the release, e.g. "Live at Leeds"
+    pub disc_subtitle: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    /// Reader of an audiobook
+    pub narrator: Option<String>,
+    /// Record label of the release
+    pub label: Option<String>,
+    /// Star rating, 0 to 5
+    pub rating: Option<u8>,
+    pub play_count: Option<u32>,
+    pub properties: AudioProperties,
+}
+
+/// Properties of the audio stream itself, as opposed to its tags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AudioProperties {
+    pub duration: Duration,
+    pub sample_rate: Option<u32>,
+    /// Bits per sample; `None` for lossy formats
+    pub bit_depth: Option<u8>,
+    pub channels: Option<u8>,
+    /// Average bitrate in kbps
+    pub bitrate: Option<u32>,
+}
+
+impl AudioProperties {
+    fn of(properties: &lofty::properties::FileProperties) -> Self {
+        AudioProperties {
+            duration: properties.duration(),
+            sample_rate: properties.sample_rate(),
+            bit_depth: properties.bit_depth(),
+            channels: properties.channels(),
+            bitrate: properties.audio_bitrate(),
+        }
+    }
+}
+
+/// Narrator of an audiobook: `NARRATOR` in Vorbis comments and APE, a
+/// `NARRATOR` TXXX frame in ID3v2, or the `©nrt` atom in MP4/M4B
+fn narrator(tag: &lofty::tag::Tag) -> Option<&str> {
+    tag.items().find_map(|item| match item.key() {
+        ItemKey::Unknown(key) if key.eq_ignore_ascii_case("NARRATOR") || key == "©nrt" => item.value().text(),
+        _ => None,
+    })
+}
+
+impl TemplateFields for Metadata {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        fn text(s: &String) -> Option<Cow<'_, str>> {
+            Some(s.as_str()).filter(|s| !s.is_empty()).map(Cow::Borrowed)
+        }
+        let number = |n: Option<u32>| n.map(|n| Cow::Owned(n.to_string()));
+
+        match name {
+            "title" => text(&self.track_name),
+            "album" => text(&self.album),
+            "artist" | "author" => text(&self.author),
+            "narrator" => self.narrator.as_ref().and_then(text),
+            "albumartist" => self.album_artist.as_ref().and_then(text).or_else(|| text(&self.author)),
+            "track" => number(self.track_number),
+            "tracktotal" => number(self.track_total),
+            "disc" => number(self.disc_number),
+            "disctotal" => number(self.disc_total),
+            "discsubtitle" => self.disc_subtitle.as_ref().and_then(text),
+            "genre" => self.genre.as_ref().and_then(text),
+            "year" => number(self.year),
+            "label" => self.label.as_ref().and_then(text),
+            "rating" => number(self.rating.map(u32::from)),
+            "playcount" => number(self.play_count),
+            "length" => Some(Cow::Owned(format_duration(self.properties.duration))),
+            "samplerate" => number(self.properties.sample_rate),
+            "bitdepth" => number(self.properties.bit_depth.map(u32::from)),
+            "channels" => number(self.properties.channels.map(u32::from)),
+            "bitrate" => number(self.properties.bitrate),
+            _ => None,
+        }
+    }
+}
+
+/// `duration` as `m:ss`, or `h:mm:ss` from an hour on
+fn format_duration(duration: Duration) -> std::string::String {
+    let seconds = duration.as_secs();
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A FLAC stream with only a STREAMINFO block: 44.1 kHz, 16-bit
+    /// stereo, 95 seconds long
+    fn streaminfo_only() -> Vec<u8> {
+        let samples: u64 = 44_100 * 95;
+        let packed: u64 = (44_100u64 << 44) | (1 << 41) | (15 << 36) | samples;
+
+        let mut data = b"fLaC".to_vec();
+        data.extend_from_slice(&[0x80, 0, 0, 34]);
+        data.extend_from_slice(&4096u16.to_be_bytes());
+        data.extend_from_slice(&4096u16.to_be_bytes());
+        data.extend_from_slice(&[0; 6]);
+        data.extend_from_slice(&packed.to_be_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data
+    }
+
+    #[test]
+    fn test_read_audio_properties() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.flac");
+        fs::write(&path, streaminfo_only()).unwrap();
+
+        let mut file = MediaFile::new(&path);
+        let metadata = file.read().unwrap();
+        assert_eq!(metadata.properties.sample_rate, Some(44_100));
+        assert_eq!(metadata.properties.bit_depth, Some(16));
+        assert_eq!(metadata.properties.channels, Some(2));
+        assert_eq!(metadata.properties.duration.as_secs(), 95);
+        assert_eq!(metadata.field("length").as_deref(), Some("1:35"));
+        assert!(metadata.track_name.as_str().is_empty());
+    }
+
+    #[test]
+    fn test_read_maps_open_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(MediaFile::new(dir.path()).read(), Err(TagError::NotAFile(_))));
+        assert!(matches!(MediaFile::new(&dir.path().join("gone.flac")).read(), Err(TagError::NotFound(_))));
+    }
+}
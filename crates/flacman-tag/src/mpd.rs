@@ -0,0 +1,229 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::tagerror::{Result, TagError};
+
+
+/// How long to wait for MPD to answer a command
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A connection to an MPD server
+pub struct MpdClient {
+    reader: BufReader<Box<dyn Read + Send>>,
+    writer: Box<dyn Write + Send>,
+}
+
+/// The song MPD is playing or has paused
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NowPlaying {
+    /// Path relative to MPD's music directory, or a stream URL
+    pub file: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// `play` or `pause`
+    pub state: String,
+    /// Seconds into the song
+    pub elapsed: Option<f64>,
+    pub duration: Option<f64>,
+}
+
+impl MpdClient {
+    /// Connect to the MPD server at `host` and `port`
+    ///
+    /// # Arguments
+    /// * `host` - `MPD_HOST` style: a host name or the path of MPD's
+    ///   socket, optionally preceded by `password@`
+    /// * `port` - Usually 6600; unused for a socket
+    pub fn connect(host: &str, port: u16) -> Result<Self> {
+        let (password, host) = match host.rsplit_once('@') {
+            Some((password, host)) => (Some(password), host),
+            None => (None, host),
+        };
+        let unreachable = |e: std::io::Error| TagError::Mpd(format!("{host}:{port}: {e}"));
+
+        let (reader, writer): (Box<dyn Read + Send>, Box<dyn Write + Send>) = if host.starts_with('/') {
+            #[cfg(unix)]
+            {
+                let stream = UnixStream::connect(host).map_err(|e| TagError::Mpd(format!("{host}: {e}")))?;
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                (Box::new(stream.try_clone()?), Box::new(stream))
+            }
+            #[cfg(not(unix))]
+            return Err(TagError::Mpd(format!("{host}: sockets are not supported on this platform")));
+        } else {
+            let stream = TcpStream::connect((host, port)).map_err(unreachable)?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            (Box::new(stream.try_clone()?), Box::new(stream))
+        };
+        let mut client = MpdClient { reader: BufReader::new(reader), writer };
+
+        let mut greeting = String::new();
+        client.reader.read_line(&mut greeting)?;
+        if !greeting.starts_with("OK MPD") {
+            return Err(TagError::Mpd(format!("not an MPD server: {}", greeting.trim())));
+        }
+        if let Some(password) = password {
+            client.command(&format!("password {}", quote(password)))?;
+        }
+
+        Ok(client)
+    }
+
+    /// Send one command and collect its response lines up to `OK`
+    pub fn command(&mut self, command: &str) -> Result<Vec<String>> {
+        mpd_command(&mut self.reader, &mut self.writer, command)
+    }
+
+    /// Have MPD rescan `uri`, a directory or file relative to its music
+    /// directory; the empty string rescans everything
+    ///
+    /// MPD scans in the background; this returns once the scan is queued.
+    pub fn update(&mut self, uri: &str) -> Result<()> {
+        self.command(&format!("update {}", quote(uri)))?;
+        Ok(())
+    }
+
+    /// The current song, unless MPD is stopped
+    pub fn now_playing(&mut self) -> Result<Option<NowPlaying>> {
+        let status = pairs(&self.command("status")?);
+        let state = status.iter().find(|(k, _)| k == "state").map(|(_, v)| v.clone()).unwrap_or_default();
+        if state != "play" && state != "pause" {
+            return Ok(None);
+        }
+        let song = pairs(&self.command("currentsong")?);
+        let value = |pairs: &[(String, String)], key: &str| pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+        let Some(file) = value(&song, "file") else {
+            return Ok(None);
+        };
+
+        Ok(Some(NowPlaying {
+            file,
+            title: value(&song, "Title"),
+            artist: value(&song, "Artist"),
+            album: value(&song, "Album"),
+            state,
+            elapsed: value(&status, "elapsed").and_then(|v| v.parse().ok()),
+            duration: value(&status, "duration").or_else(|| value(&song, "duration")).and_then(|v| v.parse().ok()),
+        }))
+    }
+}
+
+impl Drop for MpdClient {
+    fn drop(&mut self) {
+        let _ = self.writer.write_all(b"close\n");
+    }
+}
+
+/// `arg` as one argument of an MPD command
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// `key: value` lines of a response
+fn pairs(lines: &[String]) -> Vec<(String, String)> {
+    lines.iter().filter_map(|l| l.split_once(": ")).map(|(k, v)| (k.to_owned(), v.to_owned())).collect()
+}
+
+/// Send one command and collect its response lines up to `OK`
+pub(crate) fn mpd_command<R: BufRead, W: Write>(reader: &mut R, writer: &mut W, command: &str) -> Result<Vec<String>> {
+    writer.write_all(command.as_bytes())?;
+    writer.write_all(b"\n")?;
+
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(TagError::Mpd("connection closed".to_owned()));
+        }
+        let line = line.trim_end();
+
+        if line == "OK" {
+            return Ok(lines);
+        }
+        // "no such sticker" just means nothing is rated yet
+        if line.starts_with("ACK") && line.contains("no such sticker") {
+            return Ok(Vec::new());
+        }
+        if let Some(error) = line.strip_prefix("ACK ") {
+            return Err(TagError::Mpd(format!("{command}: {error}")));
+        }
+        lines.push(line.to_owned());
+    }
+}
+
+/// The URIs to have MPD rescan after `paths` under `music_dir` changed:
+/// the directories of changed files, and changed or removed directories
+/// themselves, leaving out those inside another
+///
+/// Paths outside `music_dir` are ignored; the empty URI is all of it.
+pub fn update_uris(music_dir: &Path, paths: &[PathBuf]) -> Vec<String> {
+    let mut dirs: Vec<&Path> = paths
+        .iter()
+        .filter_map(|path| {
+            let dir = if path.is_file() { path.parent()? } else { path.as_path() };
+            dir.strip_prefix(music_dir).ok()
+        })
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+
+    let mut uris: Vec<&Path> = Vec::new();
+    for dir in dirs {
+        if !uris.iter().any(|kept| dir.starts_with(kept)) {
+            uris.push(dir);
+        }
+    }
+    uris.iter().map(|uri| uri.to_string_lossy().replace('\\', "/")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_now_playing() {
+        let response = "volume: 80\nstate: play\nelapsed: 12.5\nduration: 201.3\nOK\n\
+                        file: Low/Trust/02 Candy Girl.flac\nArtist: Low\nAlbum: Trust\nTitle: Candy Girl\nOK\n";
+        let mut client = MpdClient {
+            reader: BufReader::new(Box::new(Cursor::new(response.as_bytes().to_vec()))),
+            writer: Box::new(Vec::new()),
+        };
+        let playing = client.now_playing().unwrap().unwrap();
+        assert_eq!(playing.file, "Low/Trust/02 Candy Girl.flac");
+        assert_eq!((playing.title.as_deref(), playing.state.as_str()), (Some("Candy Girl"), "play"));
+        assert_eq!((playing.elapsed, playing.duration), (Some(12.5), Some(201.3)));
+
+        client.reader = BufReader::new(Box::new(Cursor::new(b"state: stop\nOK\n".to_vec())));
+        assert_eq!(client.now_playing().unwrap(), None);
+        assert_eq!(quote("say \"hi\"\\"), "\"say \\\"hi\\\"\\\\\"");
+    }
+
+    #[test]
+    fn test_update_uris() {
+        let dir = tempdir().unwrap();
+        let album = dir.path().join("Low/Trust");
+        fs::create_dir_all(&album).unwrap();
+        fs::write(album.join("01.flac"), b"").unwrap();
+
+        let paths = [
+            album.join("01.flac"),
+            dir.path().join("Low/Trust/02.flac"),
+            dir.path().join("Low/Secret Name"),
+            dir.path().join("Low"),
+            PathBuf::from("/elsewhere/Low"),
+        ];
+        assert_eq!(update_uris(dir.path(), &paths[..3]), ["Low/Secret Name", "Low/Trust"]);
+        assert_eq!(update_uris(dir.path(), &paths), ["Low"]);
+        assert_eq!(update_uris(dir.path(), &[dir.path().join("Low/Trust/01.flac"), dir.path().to_path_buf()]), [""]);
+    }
+}
@@ -0,0 +1,275 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use lofty::config::{ParseOptions, WriteOptions};
+use lofty::file::{AudioFile, FileType, TaggedFileExt};
+use lofty::id3::v2::FrameId;
+use lofty::mpeg::MpegFile;
+use lofty::probe::Probe;
+use lofty::tag::{Accessor, Tag, TagExt};
+
+use crate::album::Album;
+use crate::preview::{WritePreview, preview_write};
+use crate::tagerror::Result;
+
+
+/// Numbers a track should carry after normalization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackNumbers {
+    pub track: u32,
+    pub track_total: u32,
+    /// Only set for tracks that are tagged with a disc
+    pub disc: Option<u32>,
+    pub disc_total: Option<u32>,
+}
+
+/// What normalizing one track changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumberingFix {
+    pub path: PathBuf,
+    /// Numbers to write, if the tags need rewriting
+    pub retag: Option<TrackNumbers>,
+    /// New path, if the file name's track number needs padding
+    pub rename: Option<PathBuf>,
+}
+
+impl NumberingFix {
+    /// Rewrite the tags, then rename the file
+    ///
+    /// # Returns
+    /// Where the file is now
+    pub fn apply(&self) -> Result<PathBuf> {
+        if let Some(numbers) = &self.retag {
+            write_numbers(&self.path, numbers)?;
+        }
+
+        match &self.rename {
+            Some(dest) => Ok(flacman_fs::move_file(&self.path, dest, false)?),
+            None => Ok(self.path.clone()),
+        }
+    }
+
+    /// What the tag rewrite would change, worked out on a temporary copy
+    ///
+    /// # Returns
+    /// `None` when only the file name changes
+    pub fn preview(&self) -> Result<Option<WritePreview>> {
+        self.retag.as_ref().map(|numbers| preview_write(&self.path, |copy| write_numbers(copy, numbers))).transpose()
+    }
+}
+
+/// Whether a stored number is in canonical form: no leading zeros, and a
+/// "n/total" pair only where the format has no separate total field
+fn is_canonical(raw: &str, slash_allowed: bool) -> bool {
+    let canonical_number = |s: &str| !s.is_empty() && !s.starts_with('0') && s.bytes().all(|b| b.is_ascii_digit());
+
+    match raw.trim().split_once('/') {
+        Some((number, total)) => slash_allowed && canonical_number(number) && canonical_number(total),
+        None => canonical_number(raw.trim()),
+    }
+}
+
+/// Raw `(key, value)` pairs of a FLAC file's Vorbis comment block
+///
+/// lofty splits "1/12" into number and total while reading, so the stored
+/// form can only be seen by reading the block directly.
+fn flac_vorbis_comments(path: &Path) -> Result<Vec<(String, String)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != b"fLaC" {
+        return Ok(Vec::new());
+    }
+
+    loop {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header)?;
+        let is_last = header[0] & 0x80 != 0;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+
+        if header[0] & 0x7f != 4 {
+            if is_last {
+                return Ok(Vec::new());
+            }
+            std::io::copy(&mut (&mut reader).take(len.into()), &mut std::io::sink())?;
+            continue;
+        }
+
+        let mut block = Vec::new();
+        (&mut reader).take(len.into()).read_to_end(&mut block)?;
+        let mut block = block.as_slice();
+
+        let vendor_len = read_u32_le(&mut block)? as usize;
+        block = block.get(vendor_len..).unwrap_or_default();
+        let count = read_u32_le(&mut block)?;
+
+        let mut comments = Vec::new();
+        for _ in 0..count {
+            let len = read_u32_le(&mut block)? as usize;
+            let Some(entry) = block.get(..len) else {
+                break;
+            };
+            if let Some((key, value)) = String::from_utf8_lossy(entry).split_once('=') {
+                comments.push((key.to_ascii_uppercase(), value.to_owned()));
+            }
+            block = &block[len..];
+        }
+
+        return Ok(comments);
+    }
+}
+
+fn read_u32_le(block: &mut &[u8]) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    block.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Whether the track or disc number is stored in a non-canonical form,
+/// e.g. "01" or "1/12" in a Vorbis comment or "01/12" in ID3v2
+fn has_odd_number_forms(path: &Path) -> Result<bool> {
+    let file_type = Probe::open(path)?.guess_file_type()?.file_type();
+
+    Ok(match file_type {
+        Some(FileType::Flac) => flac_vorbis_comments(path)?
+            .iter()
+            .filter(|(key, _)| key == "TRACKNUMBER" || key == "DISCNUMBER")
+            .any(|(_, raw)| !is_canonical(raw, false)),
+        Some(FileType::Mpeg) => {
+            let options = ParseOptions::new().implicit_conversions(false);
+            MpegFile::read_from(&mut File::open(path)?, options)?.id3v2().is_some_and(|id3| {
+                ["TRCK", "TPOS"]
+                    .iter()
+                    .filter_map(|id| id3.get_text(&FrameId::Valid((*id).into())))
+                    .any(|raw| !is_canonical(raw, true))
+            })
+        }
+        // MP4 stores numbers as integers; other formats are rewritten
+        // only when totals are missing
+        _ => false,
+    })
+}
+
+fn write_numbers(path: &Path, numbers: &TrackNumbers) -> Result<()> {
+    let mut tagged_file = lofty::read_from_path(path)?;
+
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+
+    let tag = tagged_file.primary_tag_mut().expect("primary tag was just inserted");
+    tag.set_track(numbers.track);
+    tag.set_track_total(numbers.track_total);
+    if let Some(disc) = numbers.disc {
+        tag.set_disk(disc);
+    }
+    if let Some(disc_total) = numbers.disc_total {
+        tag.set_disk_total(disc_total);
+    }
+    tag.save_to_path(path, WriteOptions::default())?;
+
+    Ok(())
+}
+
+/// Zero-pad the track number a file name starts with
+///
+/// Understands "1 Title.flac", "1. Title.flac" and "2-1 Title.flac"
+/// (disc-track). The name is only touched if its number is `track`.
+///
+/// # Returns
+/// The padded file name, if it differs
+fn padded_file_name(name: &str, track: u32, width: usize) -> Option<String> {
+    let digits = |s: &str| s.bytes().take_while(u8::is_ascii_digit).count();
+
+    let lead = digits(name);
+    let (disc_prefix, rest) = match name[lead..].strip_prefix('-') {
+        Some(after) if lead > 0 && digits(after) > 0 => name.split_at(lead + 1),
+        _ => ("", name),
+    };
+
+    let len = digits(rest);
+    let (number, tail) = rest.split_at(len);
+    let separated = tail.starts_with([' ', '.', '-', '_']);
+
+    if len == 0 || !separated || number.parse::<u32>().ok() != Some(track) {
+        return None;
+    }
+
+    let padded = format!("{disc_prefix}{track:0width$}{tail}");
+    (padded != name).then_some(padded)
+}
+
+/// Work out how to normalize the numbering of `album`
+///
+/// Missing track totals are taken from the disc (the highest track number,
+/// or the number of tracks if that's higher), and missing disc totals from
+/// the album. Totals that are already tagged are kept. File names get
+/// their track number padded to two digits, or more for discs with 100
+/// or more tracks. Unnumbered tracks are left alone.
+pub fn plan_numbering(album: &Album) -> Result<Vec<NumberingFix>> {
+    let mut fixes = Vec::new();
+    let disc_total = album.disc_total();
+
+    for tracks in album.discs.values() {
+        let highest = tracks.iter().filter_map(|t| t.metadata.track_number).max().unwrap_or(0);
+        let derived_total = highest.max(tracks.len() as u32);
+
+        for track in tracks {
+            let meta = &track.metadata;
+            let Some(number) = meta.track_number else {
+                continue;
+            };
+
+            let numbers = TrackNumbers {
+                track: number,
+                track_total: meta.track_total.unwrap_or(derived_total),
+                disc: meta.disc_number,
+                disc_total: meta.disc_number.map(|_| meta.disc_total.unwrap_or(disc_total)),
+            };
+
+            let missing_totals = meta.track_total.is_none() || (meta.disc_number.is_some() && meta.disc_total.is_none());
+            let retag = (missing_totals || has_odd_number_forms(&track.path)?).then_some(numbers.clone());
+
+            let width = numbers.track_total.to_string().len().max(2);
+            let rename = track
+                .path
+                .file_name()
+                .and_then(|name| padded_file_name(&name.to_string_lossy(), number, width))
+                .map(|name| track.path.with_file_name(name))
+                .filter(|dest| !dest.exists());
+
+            if retag.is_some() || rename.is_some() {
+                fixes.push(NumberingFix { path: track.path.clone(), retag, rename });
+            }
+        }
+    }
+
+    Ok(fixes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_forms() {
+        assert!(is_canonical("1", false));
+        assert!(!is_canonical("01", false));
+        assert!(!is_canonical("1/12", false));
+        assert!(is_canonical("1/12", true));
+        assert!(!is_canonical("01/12", true));
+    }
+
+    #[test]
+    fn test_padded_file_name() {
+        assert_eq!(padded_file_name("1 Intro.flac", 1, 2).as_deref(), Some("01 Intro.flac"));
+        assert_eq!(padded_file_name("2-3. Song.flac", 3, 2).as_deref(), Some("2-03. Song.flac"));
+        assert_eq!(padded_file_name("07 Song.flac", 7, 3).as_deref(), Some("007 Song.flac"));
+        assert_eq!(padded_file_name("01 Song.flac", 1, 2), None);
+        assert_eq!(padded_file_name("1979.flac", 1, 2), None);
+        assert_eq!(padded_file_name("3 Song.flac", 4, 2), None);
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use flacman_core::{ExportOptions, TrackRecord};
+use lofty::file::{AudioFile, TaggedFileExt};
+
+use crate::export::tag_replay_gain;
+use crate::tagerror::{Result, TagError};
+
+
+/// File format of a playlist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    /// Extended M3U in UTF-8
+    M3u8,
+    /// XML Shareable Playlist Format
+    Xspf,
+}
+
+impl PlaylistFormat {
+    /// The format a playlist at `path` is written in, by extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "m3u" | "m3u8" => Some(PlaylistFormat::M3u8),
+            "xspf" => Some(PlaylistFormat::Xspf),
+            _ => None,
+        }
+    }
+}
+
+/// How a playlist refers to its tracks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathStyle {
+    /// Relative to the playlist's directory, so the playlist keeps working
+    /// when it is moved along with the library
+    #[default]
+    Relative,
+    /// Absolute, so the playlist can be moved anywhere on this machine
+    Absolute,
+}
+
+impl FromStr for PathStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "relative" => Ok(PathStyle::Relative),
+            "absolute" => Ok(PathStyle::Absolute),
+            _ => Err(format!("unknown path style {s:?} (expected relative or absolute)")),
+        }
+    }
+}
+
+/// Write `tracks`, in order, to a playlist at `playlist` in the format its
+/// extension names
+///
+/// Track lengths are read from the files; M3U entries also get an
+/// `#EXTGAIN` line when `options` keep ReplayGain as tags.
+///
+/// # Errors
+/// * `TagError::Playlist` - The extension is neither `.m3u`, `.m3u8` nor `.xspf`
+pub fn write_playlist(playlist: &Path, tracks: &[TrackRecord], style: PathStyle, options: &ExportOptions) -> Result<()> {
+    let format = PlaylistFormat::from_path(playlist)
+        .ok_or_else(|| TagError::Playlist(format!("{}: not an .m3u8 or .xspf file", playlist.display())))?;
+    let base = std::path::absolute(playlist)?.parent().map(Path::to_path_buf).unwrap_or_default();
+    let location = |track: &TrackRecord| match style {
+        PathStyle::Absolute => track.path.clone(),
+        PathStyle::Relative => relative_path(&track.path, &base),
+    };
+
+    let out = match format {
+        PlaylistFormat::M3u8 => {
+            let mut out = String::from("#EXTM3U\n");
+            for track in tracks {
+                let (seconds, gain) = match lofty::read_from_path(&track.path) {
+                    Ok(tagged_file) => {
+                        let tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag());
+                        let replay_gain = tag.map(tag_replay_gain).unwrap_or_default();
+                        (tagged_file.properties().duration().as_secs() as i64, options.m3u_gain_line(&replay_gain))
+                    }
+                    Err(_) => (-1, None),
+                };
+                let _ = writeln!(out, "#EXTINF:{},{} - {}", seconds, track.artist, track.title);
+                if let Some(line) = gain {
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+                let _ = writeln!(out, "{}", location(track).display());
+            }
+            out
+        }
+        PlaylistFormat::Xspf => {
+            let mut out = String::from(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n",
+            );
+            for track in tracks {
+                let path = location(track);
+                let uri = match style {
+                    PathStyle::Absolute => format!("file://{}", uri_escape(&path)),
+                    PathStyle::Relative => uri_escape(&path),
+                };
+                let _ = writeln!(out, "    <track>");
+                let _ = writeln!(out, "      <location>{}</location>", xml_escape(&uri));
+                let _ = writeln!(out, "      <title>{}</title>", xml_escape(&track.title));
+                let _ = writeln!(out, "      <creator>{}</creator>", xml_escape(&track.artist));
+                let _ = writeln!(out, "      <album>{}</album>", xml_escape(&track.album));
+                if let Some(number) = track.track {
+                    let _ = writeln!(out, "      <trackNum>{}</trackNum>", number);
+                }
+                if let Ok(duration) = lofty::read_from_path(&track.path).map(|f| f.properties().duration()) {
+                    let _ = writeln!(out, "      <duration>{}</duration>", duration.as_millis());
+                }
+                let _ = writeln!(out, "    </track>");
+            }
+            out.push_str("  </trackList>\n</playlist>\n");
+            out
+        }
+    };
+
+    if let Some(parent) = playlist.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(playlist, out)?;
+    Ok(())
+}
+
+/// `path` relative to the directory `base`, stepping up with `..` where
+/// they part; `path` itself if they share no root
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let (path_parts, base_parts): (Vec<Component>, Vec<Component>) =
+        (path.components().collect(), base.components().collect());
+    let common = path_parts.iter().zip(&base_parts).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return path.to_path_buf();
+    }
+
+    let mut relative: PathBuf = base_parts[common..].iter().map(|_| Component::ParentDir).collect();
+    relative.extend(&path_parts[common..]);
+    relative
+}
+
+/// Percent-encode `path` for a URI, keeping `/` as the separator
+fn uri_escape(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut out = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => out.push(byte as char),
+            _ => {
+                let _ = write!(out, "%{:02X}", byte);
+            }
+        }
+    }
+    out
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use chrono::Local;
+    use tempfile::tempdir;
+
+    fn record(path: &Path, title: &str) -> TrackRecord {
+        TrackRecord {
+            path: path.to_path_buf(),
+            album_artist: "Boards of Canada".to_owned(),
+            album: "Music Has the Right to Children".to_owned(),
+            artist: "Boards of Canada".to_owned(),
+            title: title.to_owned(),
+            disc: None,
+            track: Some(2),
+            year: Some(1998),
+            tags: BTreeMap::new(),
+            sha256: None,
+            size: 0,
+            modified: 0,
+            added: Local::now(),
+        }
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let track = Path::new("/music/Low/Trust/01 Canada.flac");
+        assert_eq!(relative_path(track, Path::new("/music")), Path::new("Low/Trust/01 Canada.flac"));
+        assert_eq!(relative_path(track, Path::new("/music/Playlists")), Path::new("../Low/Trust/01 Canada.flac"));
+        assert_eq!(uri_escape(Path::new("../Low/01 Canada.flac")), "../Low/01%20Canada.flac");
+    }
+
+    #[test]
+    fn test_write_playlists() {
+        let dir = tempdir().unwrap();
+        let tracks = [record(&dir.path().join("BoC/02 An Eagle & a Hawk.flac"), "An Eagle & a Hawk")];
+        let options = ExportOptions::default();
+
+        let m3u = dir.path().join("lists/out.m3u8");
+        write_playlist(&m3u, &tracks, PathStyle::Relative, &options).unwrap();
+        let text = fs::read_to_string(&m3u).unwrap();
+        assert_eq!(text, "#EXTM3U\n#EXTINF:-1,Boards of Canada - An Eagle & a Hawk\n../BoC/02 An Eagle & a Hawk.flac\n");
+
+        let xspf = dir.path().join("out.xspf");
+        write_playlist(&xspf, &tracks, PathStyle::Absolute, &options).unwrap();
+        let text = fs::read_to_string(&xspf).unwrap();
+        assert!(text.contains("<title>An Eagle &amp; a Hawk</title>"));
+        assert!(text.contains("/BoC/02%20An%20Eagle%20%26%20a%20Hawk.flac</location>"));
+        assert!(text.contains("<location>file:///"));
+
+        let err = write_playlist(&dir.path().join("out.pls"), &tracks, PathStyle::Relative, &options);
+        assert!(matches!(err, Err(TagError::Playlist(_))));
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use flacman_core::{DownloadUser, HttpRequest};
+use serde::Deserialize;
+
+use crate::artwork::USER_AGENT;
+use crate::mediafile::Metadata;
+use crate::mpd::MpdClient;
+use crate::rating::Popularity;
+use crate::tagerror::{Result, TagError};
+
+
+const LISTENBRAINZ_API: &str = "https://api.listenbrainz.org/1";
+
+/// Recordings per ListenBrainz stats page (the API maximum)
+const LISTENBRAINZ_PAGE: usize = 1000;
+
+/// Ratings and play counts pulled from a player or scrobbling service
+///
+/// Tracks are looked up by file path relative to the music directory
+/// (MPD) or by artist and title (ListenBrainz).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlayStats {
+    by_path: HashMap<String, Popularity>,
+    by_title: HashMap<(String, String), Popularity>,
+}
+
+impl PlayStats {
+    /// Stats for the track at `path` (relative to the music directory) with `metadata`
+    pub fn lookup(&self, relative: &Path, metadata: &Metadata) -> Option<Popularity> {
+        let by_path = relative.to_str().and_then(|p| self.by_path.get(p));
+        let key = title_key(metadata.author.as_str(), metadata.track_name.as_str());
+
+        by_path.or_else(|| self.by_title.get(&key)).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_path.len() + self.by_title.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn title_key(artist: &str, title: &str) -> (String, String) {
+    (artist.trim().to_lowercase(), title.trim().to_lowercase())
+}
+
+/// Read song stickers from an MPD server
+///
+/// `rating` stickers are taken to be 0-10 (the scale ncmpcpp, myMPD and
+/// most other clients write) and halved into stars; `playCount` stickers
+/// are what myMPD keeps.
+pub fn mpd_play_stats(client: &mut MpdClient) -> Result<PlayStats> {
+    let mut stats = PlayStats::default();
+
+    for (file, value) in sticker_pairs(&client.command("sticker find song \"\" rating")?) {
+        if let Ok(rating) = value.trim().parse::<f64>() {
+            let entry = stats.by_path.entry(file).or_default();
+            entry.rating = Some((rating / 2.0).round().clamp(0.0, 5.0) as u8);
+        }
+    }
+    for (file, value) in sticker_pairs(&client.command("sticker find song \"\" playCount")?) {
+        if let Ok(count) = value.trim().parse::<u32>() {
+            stats.by_path.entry(file).or_default().play_count = Some(count);
+        }
+    }
+
+    Ok(stats)
+}
+
+/// `(file, value)` pairs from a `sticker find` response
+fn sticker_pairs(lines: &[String]) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut file = None;
+
+    for line in lines {
+        if let Some(path) = line.strip_prefix("file: ") {
+            file = Some(path.to_owned());
+        } else if let Some(sticker) = line.strip_prefix("sticker: ")
+            && let Some((_, value)) = sticker.split_once('=')
+            && let Some(file) = file.take()
+        {
+            pairs.push((file, value.to_owned()));
+        }
+    }
+
+    pairs
+}
+
+#[derive(Deserialize)]
+struct ListenBrainzRecording {
+    artist_name: String,
+    track_name: String,
+    listen_count: u32,
+}
+
+#[derive(Deserialize)]
+struct ListenBrainzPayload {
+    recordings: Vec<ListenBrainzRecording>,
+    total_recording_count: usize,
+}
+
+#[derive(Deserialize)]
+struct ListenBrainzStats {
+    payload: ListenBrainzPayload,
+}
+
+/// All-time play counts of a ListenBrainz user, fetched as `download_user`
+/// if given
+///
+/// ListenBrainz has no ratings, only play counts. Stats are computed
+/// periodically on their side, so very recent listens may be missing.
+pub fn listenbrainz_play_stats(user: &str, download_user: Option<&DownloadUser>) -> Result<PlayStats> {
+    let mut stats = PlayStats::default();
+    let mut offset = 0;
+
+    loop {
+        let url = format!(
+            "{LISTENBRAINZ_API}/stats/user/{user}/recordings?range=all_time&count={LISTENBRAINZ_PAGE}&offset={offset}"
+        );
+        let response = HttpRequest::get(&url).header("User-Agent", USER_AGENT).send(download_user)?;
+
+        // Stats not calculated yet for this user
+        if response.status() == 204 {
+            return Ok(stats);
+        }
+
+        let page: ListenBrainzStats = serde_json::from_slice(&response.read_to_vec()?)
+            .map_err(|e| TagError::ListenBrainz(format!("unexpected response: {e}")))?;
+
+        let fetched = page.payload.recordings.len();
+        for recording in page.payload.recordings {
+            let key = title_key(&recording.artist_name, &recording.track_name);
+            let entry = stats.by_title.entry(key).or_default();
+            entry.play_count = Some(entry.play_count.unwrap_or(0) + recording.listen_count);
+        }
+
+        offset += fetched;
+        if fetched == 0 || offset >= page.payload.total_recording_count {
+            return Ok(stats);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpd::mpd_command;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_mpd_sticker_response() {
+        let response = "file: Low/Things/01.flac\nsticker: rating=8\nfile: Low/Things/02.flac\nsticker: rating=3\nOK\n";
+        let mut reader = Cursor::new(response.as_bytes());
+        let mut sent = Vec::new();
+
+        let lines = mpd_command(&mut reader, &mut sent, "sticker find song \"\" rating").unwrap();
+        assert_eq!(sent, b"sticker find song \"\" rating\n");
+        assert_eq!(
+            sticker_pairs(&lines),
+            vec![("Low/Things/01.flac".to_owned(), "8".to_owned()), ("Low/Things/02.flac".to_owned(), "3".to_owned())]
+        );
+
+        let mut reader = Cursor::new(&b"ACK [50@0] {sticker} no such sticker\n"[..]);
+        assert!(mpd_command(&mut reader, &mut Vec::new(), "sticker find song \"\" playCount").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lofty::file::TaggedFileExt;
+use lofty::tag::ItemValue;
+
+use crate::tagerror::Result;
+
+
+/// Binary tag values up to this size are shown in hex, longer ones by size
+const MAX_HEX_BYTES: usize = 32;
+
+/// What a tag write would do to one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WritePreview {
+    pub path: PathBuf,
+    /// Unified diff of the tag state, empty when the write changes nothing
+    pub diff: String,
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+impl WritePreview {
+    pub fn is_unchanged(&self) -> bool {
+        self.diff.is_empty() && self.size_before == self.size_after
+    }
+}
+
+/// Tag state of the file at `path` as sorted `TAG:KEY=value` lines
+///
+/// Keys are the format's own names where there is one; pictures are
+/// summarized by type, MIME type and size.
+pub fn tag_snapshot(path: &Path) -> Result<Vec<String>> {
+    let tagged_file = lofty::read_from_path(path)?;
+    let mut lines = Vec::new();
+
+    for tag in tagged_file.tags() {
+        let tag_type = tag.tag_type();
+        for item in tag.items() {
+            let key = item.key().map_key(tag_type, true).map_or_else(|| format!("{:?}", item.key()), str::to_owned);
+            let value = match item.value() {
+                ItemValue::Text(text) | ItemValue::Locator(text) => text.clone(),
+                ItemValue::Binary(data) if data.len() <= MAX_HEX_BYTES => {
+                    data.iter().map(|b| format!("{:02x}", b)).collect()
+                }
+                ItemValue::Binary(data) => format!("<{} bytes>", data.len()),
+            };
+            lines.push(format!("{:?}:{}={}", tag_type, key, value));
+        }
+        for picture in tag.pictures() {
+            let mime = picture.mime_type().map_or("unknown", |m| m.as_str());
+            lines.push(format!("{:?}:PICTURE={:?} {} <{} bytes>", tag_type, picture.pic_type(), mime, picture.data().len()));
+        }
+    }
+
+    lines.sort();
+    Ok(lines)
+}
+
+/// Unified diff of two sorted snapshots, without context lines
+fn snapshot_diff(label: &str, before: &[String], after: &[String]) -> String {
+    let mut body = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < before.len() || j < after.len() {
+        match (before.get(i), after.get(j)) {
+            (Some(old), Some(new)) if old == new => {
+                i += 1;
+                j += 1;
+            }
+            (Some(old), Some(new)) if old < new => {
+                body.push(format!("-{}", old));
+                i += 1;
+            }
+            (Some(old), None) => {
+                body.push(format!("-{}", old));
+                i += 1;
+            }
+            (_, Some(new)) => {
+                body.push(format!("+{}", new));
+                j += 1;
+            }
+            (None, None) => unreachable!("loop ends when both snapshots are exhausted"),
+        }
+    }
+
+    if body.is_empty() {
+        return String::new();
+    }
+
+    let mut diff = format!("--- {label}\n+++ {label} (after write)\n");
+    diff.push_str(&format!("@@ -1,{} +1,{} @@\n", before.len(), after.len()));
+    for line in body {
+        diff.push_str(&line);
+        diff.push('\n');
+    }
+    diff
+}
+
+/// Temporary copy of `path`, keeping its extension so the format is still
+/// recognized
+fn temp_copy(path: &Path) -> Result<PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let name = format!(
+        ".flacman-preview-{}-{}{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        extension
+    );
+    let copy = std::env::temp_dir().join(name);
+    fs::copy(path, &copy)?;
+
+    Ok(copy)
+}
+
+/// Apply `write` to a temporary copy of `path` and describe the result
+///
+/// The original is never opened for writing; the copy is removed before
+/// returning, whether or not the write succeeded.
+///
+/// # Errors
+/// Whatever `write` returns, or an I/O or tag error reading the copy
+pub fn preview_write<F>(path: &Path, write: F) -> Result<WritePreview>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    let before = tag_snapshot(path)?;
+    let size_before = fs::metadata(path)?.len();
+
+    let copy = temp_copy(path)?;
+    let result = write(&copy).and_then(|()| Ok((tag_snapshot(&copy)?, fs::metadata(&copy)?.len())));
+    let _ = fs::remove_file(&copy);
+    let (after, size_after) = result?;
+
+    Ok(WritePreview {
+        path: path.to_path_buf(),
+        diff: snapshot_diff(&path.display().to_string(), &before, &after),
+        size_before,
+        size_after,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let before = lines(&["VorbisComments:ARTIST=Low", "VorbisComments:TRACKNUMBER=01"]);
+        let after = lines(&["VorbisComments:ARTIST=Low", "VorbisComments:TRACKNUMBER=1", "VorbisComments:TRACKTOTAL=12"]);
+
+        let diff = snapshot_diff("01 Canada.flac", &before, &after);
+        assert_eq!(
+            diff,
+            "--- 01 Canada.flac\n+++ 01 Canada.flac (after write)\n@@ -1,2 +1,3 @@\n\
+             -VorbisComments:TRACKNUMBER=01\n+VorbisComments:TRACKNUMBER=1\n+VorbisComments:TRACKTOTAL=12\n"
+        );
+        assert_eq!(snapshot_diff("x", &before, &before), "");
+    }
+}
@@ -0,0 +1,44 @@
+use std::path::Path;
+
+use flacman_core::{Provenance, SourceInfo, sha256_file};
+use lofty::file::TaggedFileExt;
+use lofty::tag::ItemKey;
+
+use crate::tagerror::Result;
+
+
+/// Encoders named in the tags of `path`, e.g. `LAME 3.100 (-V 0)`
+///
+/// Tags only remember the last encoder, so this is at most one step of
+/// the chain; earlier steps have to come from the source.
+pub fn encoder_chain(path: &Path) -> Result<Vec<String>> {
+    let tagged_file = lofty::read_from_path(path)?;
+    let Some(tag) = tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) else {
+        return Ok(Vec::new());
+    };
+
+    let software = tag.get_string(&ItemKey::EncoderSoftware).or_else(|| tag.get_string(&ItemKey::EncodedBy));
+    let settings = tag.get_string(&ItemKey::EncoderSettings).filter(|s| Some(*s) != software);
+
+    Ok(match (software, settings) {
+        (Some(software), Some(settings)) => vec![format!("{} ({})", software, settings)],
+        (Some(encoder), None) | (None, Some(encoder)) => vec![encoder.to_owned()],
+        (None, None) => Vec::new(),
+    })
+}
+
+/// Provenance of a file just imported to `path` from `original`
+///
+/// Checksums the file as it is now, before any retagging, and appends
+/// its encoder to the transcode chain the source reported.
+pub fn track_provenance(path: &Path, original: &Path, mut source: SourceInfo) -> Result<Provenance> {
+    let sha256 = sha256_file(path)?;
+
+    for encoder in encoder_chain(path).unwrap_or_default() {
+        if source.transcodes.last() != Some(&encoder) {
+            source.transcodes.push(encoder);
+        }
+    }
+
+    Ok(Provenance::new(path.to_path_buf(), source, original.to_path_buf(), sha256))
+}
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use flacman_core::{MANIFEST_NAME, sha256_file};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::tagerror::{Result, TagError};
+
+
+/// Name of the album list at the root of a published library
+pub const PUBLISH_INDEX: &str = "index.json";
+
+/// How long a connection may sit idle before it is dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// One album offered by a published library
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PublishedAlbum {
+    pub artist: String,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<u32>,
+    /// Directory of the album relative to the library root, `/`-separated
+    pub path: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Serialize)]
+struct PublishIndex<'a> {
+    version: u32,
+    albums: &'a [PublishedAlbum],
+}
+
+/// A manifest generated for an album directory, with the newest file
+/// modification time it covers
+struct CachedManifest {
+    modified: SystemTime,
+    text: String,
+    signature: Option<String>,
+}
+
+/// Serves a library over HTTP the way a self-hosted source is laid out
+///
+/// * `GET /index.json` lists the published albums
+/// * `GET /<album>/flacman.manifest` is the album's checksum manifest,
+///   generated on first request and again whenever a file changes; with a
+///   GPG key, `flacman.manifest.asc` is its detached signature. A manifest
+///   already in the album directory is served as is, with its signatures.
+/// * `GET /<album>/<file>` downloads a file, honoring `Range` so a broken
+///   download can be resumed
+///
+/// Only files inside published album directories are reachable, and
+/// nothing hidden. Requests are logged at info level, failed ones as
+/// warnings.
+pub struct Publisher {
+    root: PathBuf,
+    albums: Vec<PublishedAlbum>,
+    gpg_key: Option<String>,
+    manifests: Mutex<HashMap<PathBuf, CachedManifest>>,
+}
+
+impl Publisher {
+    /// # Arguments
+    /// * `root` - Library root the album paths are relative to
+    /// * `albums` - What to publish
+    /// * `gpg_key` - Key to sign generated manifests with, if any
+    pub fn new(root: &Path, albums: Vec<PublishedAlbum>, gpg_key: Option<String>) -> Self {
+        Publisher { root: root.to_path_buf(), albums, gpg_key, manifests: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn albums(&self) -> &[PublishedAlbum] {
+        &self.albums
+    }
+
+    /// Answer requests on `listener` until `cancel` is set
+    ///
+    /// Each connection is handled on its own thread, one request per
+    /// connection.
+    pub fn serve(&self, listener: TcpListener, cancel: &AtomicBool) -> Result<()> {
+        listener.set_nonblocking(true)?;
+
+        std::thread::scope(|scope| {
+            while !cancel.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        scope.spawn(move || {
+                            if let Err(e) = self.handle(stream) {
+                                warn!(error = %e, "request failed");
+                            }
+                        });
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut range = None;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':')
+                && name.trim().eq_ignore_ascii_case("range")
+            {
+                range = Some(value.trim().to_owned());
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        if method != "GET" && method != "HEAD" {
+            return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"method not allowed\n", true);
+        }
+        let head_only = method == "HEAD";
+        info!(method, target, "request");
+
+        let Some(path) = percent_decode(target.split('?').next().unwrap_or("")) else {
+            return respond(&mut stream, "400 Bad Request", "text/plain", b"bad path\n", head_only);
+        };
+        let path = path.trim_start_matches('/');
+
+        if path.is_empty() || path == PUBLISH_INDEX {
+            let index = PublishIndex { version: 1, albums: &self.albums };
+            let body = serde_json::to_vec_pretty(&index).map_err(|e| TagError::Download(e.to_string()))?;
+            return respond(&mut stream, "200 OK", "application/json", &body, head_only);
+        }
+
+        let Some(album) = self.albums.iter().find(|a| path.starts_with(&format!("{}/", a.path))) else {
+            return respond(&mut stream, "404 Not Found", "text/plain", b"not found\n", head_only);
+        };
+        let dir = self.root.join(&album.path);
+        let rel = &path[album.path.len() + 1..];
+
+        let is_manifest = rel == MANIFEST_NAME || rel == format!("{}.asc", MANIFEST_NAME);
+        if is_manifest && !dir.join(MANIFEST_NAME).is_file() {
+            let (text, signature) = self.manifest(&dir)?;
+            let body = if rel == MANIFEST_NAME { Some(text) } else { signature };
+            return match body {
+                Some(body) => respond(&mut stream, "200 OK", "text/plain", body.as_bytes(), head_only),
+                None => respond(&mut stream, "404 Not Found", "text/plain", b"not signed\n", head_only),
+            };
+        }
+
+        match safe_join(&dir, rel) {
+            Some(file) if file.is_file() => send_file(&mut stream, &file, range.as_deref(), head_only),
+            _ => respond(&mut stream, "404 Not Found", "text/plain", b"not found\n", head_only),
+        }
+    }
+
+    /// The manifest of the files in `dir` and its signature, generated
+    /// again if any file changed since last time
+    fn manifest(&self, dir: &Path) -> Result<(String, Option<String>)> {
+        let mut files = Vec::new();
+        let mut modified = SystemTime::UNIX_EPOCH;
+        let visible = |e: &walkdir::DirEntry| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.');
+        for entry in walkdir::WalkDir::new(dir).sort_by_file_name().into_iter().filter_entry(visible) {
+            let entry = entry.map_err(|e| TagError::Io(e.into()))?;
+            if entry.file_type().is_file() {
+                modified = modified.max(entry.metadata().map_err(|e| TagError::Io(e.into()))?.modified()?);
+                files.push(entry.into_path());
+            }
+        }
+
+        let mut manifests = self.manifests.lock().expect("manifest cache lock poisoned");
+        if let Some(cached) = manifests.get(dir)
+            && cached.modified == modified
+        {
+            return Ok((cached.text.clone(), cached.signature.clone()));
+        }
+
+        let mut text = String::new();
+        for file in &files {
+            let rel = file.strip_prefix(dir).unwrap_or(file);
+            let rel: Vec<String> = rel.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+            text.push_str(&format!("{}  {}\n", sha256_file(file)?, rel.join("/")));
+        }
+
+        let signature = match &self.gpg_key {
+            Some(key) => Some(gpg_sign(key, &text)?),
+            None => None,
+        };
+        manifests.insert(dir.to_path_buf(), CachedManifest { modified, text: text.clone(), signature: signature.clone() });
+
+        Ok((text, signature))
+    }
+}
+
+/// Detached ASCII-armored signature of `text` made by `gpg` with `key`
+fn gpg_sign(key: &str, text: &str) -> Result<String> {
+    let mut child = Command::new("gpg")
+        .args(["--batch", "--yes", "--armor", "--detach-sign", "--local-user", key])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().expect("stdin is piped").write_all(text.as_bytes())?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(TagError::Download(format!(
+            "gpg could not sign the manifest: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8], head_only: bool) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    if !head_only {
+        stream.write_all(body)?;
+    }
+    Ok(())
+}
+
+/// Send `path`, or the part of it a `Range: bytes=...` header asks for
+fn send_file(stream: &mut TcpStream, path: &Path, range: Option<&str>, head_only: bool) -> Result<()> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let (status, start, end) = match range.map(|r| parse_range(r, len)) {
+        None => ("200 OK", 0, len),
+        Some(Some((start, end))) => ("206 Partial Content", start, end),
+        Some(None) => {
+            write!(
+                stream,
+                "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0\r\n\
+                 Connection: close\r\n\r\n",
+                len
+            )?;
+            return Ok(());
+        }
+    };
+
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/octet-stream\r\nAccept-Ranges: bytes\r\n", status)?;
+    if start > 0 || end < len {
+        write!(stream, "Content-Range: bytes {}-{}/{}\r\n", start, end.saturating_sub(1), len)?;
+    }
+    write!(stream, "Content-Length: {}\r\nConnection: close\r\n\r\n", end - start)?;
+
+    if !head_only {
+        file.seek(SeekFrom::Start(start))?;
+        std::io::copy(&mut file.take(end - start), stream)?;
+    }
+    Ok(())
+}
+
+/// The byte range `bytes=START-END`, `bytes=START-` or `bytes=-SUFFIX`
+/// asks for in a file of `len` bytes, as a half-open `(start, end)`
+///
+/// Only single ranges are supported.
+fn parse_range(header: &str, len: u64) -> Option<(u64, u64)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (len.saturating_sub(suffix), len)
+        }
+        (start, "") => (start.parse().ok()?, len),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.saturating_add(1).min(len)),
+    };
+
+    (start < end && start < len).then_some((start, end))
+}
+
+/// Decode `%XX` escapes in a URL path
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// `dir` joined with the relative URL path `rel`, refusing anything that
+/// could leave `dir` or name a hidden file
+fn safe_join(dir: &Path, rel: &str) -> Option<PathBuf> {
+    let rel = Path::new(rel);
+    let safe = rel.components().all(|c| match c {
+        Component::Normal(name) => !name.to_string_lossy().starts_with('.'),
+        _ => false,
+    });
+    if !safe || rel.as_os_str().is_empty() {
+        return None;
+    }
+
+    let path = dir.join(rel);
+    // A symlink inside the album must still point inside it
+    let resolved = fs::canonicalize(&path).ok()?;
+    resolved.starts_with(fs::canonicalize(dir).ok()?).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 100)));
+        assert_eq!(parse_range("bytes=500-", 1000), Some((500, 1000)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 1000)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some((900, 1000)));
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+    }
+
+    #[test]
+    fn test_serve_range_and_manifest() {
+        let dir = tempdir().unwrap();
+        let album = dir.path().join("Low/Trust");
+        fs::create_dir_all(&album).unwrap();
+        fs::write(album.join("01 Canada.flac"), b"0123456789").unwrap();
+        fs::write(dir.path().join("Low/secret.txt"), b"not published").unwrap();
+
+        let published = PublishedAlbum {
+            artist: "Low".to_owned(),
+            title: "Trust".to_owned(),
+            year: None,
+            path: "Low/Trust".to_owned(),
+            files: 1,
+            bytes: 10,
+        };
+        let publisher = Publisher::new(dir.path(), vec![published], None);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cancel = AtomicBool::new(false);
+
+        let get = |target: &str, range: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: x\r\n{}\r\n", target, range).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| publisher.serve(listener, &cancel).unwrap());
+
+            let partial = get("/Low/Trust/01%20Canada.flac", "Range: bytes=4-\r\n");
+            assert!(partial.starts_with("HTTP/1.1 206"));
+            assert!(partial.contains("Content-Range: bytes 4-9/10"));
+            assert!(partial.ends_with("\r\n\r\n456789"));
+
+            let manifest = get("/Low/Trust/flacman.manifest", "");
+            assert!(manifest.ends_with("  01 Canada.flac\n"));
+            assert!(get("/Low/secret.txt", "").starts_with("HTTP/1.1 404"));
+            assert!(get("/Low/Trust/../secret.txt", "").starts_with("HTTP/1.1 404"));
+
+            cancel.store(true, Ordering::Relaxed);
+        });
+    }
+}
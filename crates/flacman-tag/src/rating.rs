@@ -0,0 +1,198 @@
+use std::fs::File;
+use std::path::Path;
+
+use lofty::config::{ParseOptions, WriteOptions};
+use lofty::file::{AudioFile, FileType, TaggedFileExt};
+use lofty::id3::v2::{Frame, FrameId, Id3v2Tag, PopularimeterFrame};
+use lofty::mpeg::MpegFile;
+use lofty::probe::Probe;
+use lofty::tag::{ItemKey, ItemValue, Tag, TagExt, TagItem, TagType};
+
+use crate::tagerror::Result;
+
+
+/// Email written into POPM frames; players match frames by it
+const POPM_EMAIL: &str = "flacman";
+
+/// A track's star rating (0 to 5) and play count
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Popularity {
+    pub rating: Option<u8>,
+    pub play_count: Option<u32>,
+}
+
+/// Stars for a POPM rating byte, using the ranges Windows Media Player,
+/// foobar2000 and MusicBee agree on (1, 64, 128, 196, 255 for 1-5 stars)
+pub fn popm_to_stars(rating: u8) -> Option<u8> {
+    match rating {
+        0 => None,
+        1..=31 => Some(1),
+        32..=95 => Some(2),
+        96..=159 => Some(3),
+        160..=223 => Some(4),
+        224..=255 => Some(5),
+    }
+}
+
+pub fn stars_to_popm(stars: u8) -> u8 {
+    match stars {
+        0 => 0,
+        1 => 1,
+        2 => 64,
+        3 => 128,
+        4 => 196,
+        _ => 255,
+    }
+}
+
+/// Stars for a textual rating
+///
+/// Taggers disagree on the scale: FMPS and Quod Libet store a fraction
+/// (`0.8`), foobar2000 stores stars (`4`) and most Vorbis taggers store
+/// a percentage (`80`). The scale is guessed from the value.
+pub fn parse_rating(text: &str) -> Option<u8> {
+    let value: f64 = text.trim().parse().ok()?;
+
+    let stars = if text.contains('.') && value <= 1.0 {
+        value * 5.0
+    } else if value <= 5.0 {
+        value
+    } else if value <= 100.0 {
+        value / 20.0
+    } else {
+        return None;
+    };
+
+    Some(stars.round().clamp(0.0, 5.0) as u8)
+}
+
+/// Rating and play count from the tags of `tag`
+///
+/// Looks at `FMPS_RATING`/`FMPS_PLAYCOUNT` first (they are unambiguous),
+/// then the format's own rating field (Vorbis `RATING`, MP4 `rate`) and
+/// `PLAY_COUNT`/`PLAYCOUNT`.
+pub(crate) fn tag_popularity(tag: &Tag) -> Popularity {
+    let unknown = |names: &[&str]| {
+        tag.items().find_map(|item| match item.key() {
+            ItemKey::Unknown(key) if names.iter().any(|n| key.eq_ignore_ascii_case(n)) => item.value().text(),
+            _ => None,
+        })
+    };
+
+    let rating = unknown(&["FMPS_RATING"])
+        .and_then(parse_rating)
+        .or_else(|| tag.get_string(&ItemKey::Popularimeter).and_then(parse_rating));
+
+    let play_count = unknown(&["FMPS_PLAYCOUNT", "PLAY_COUNT", "PLAYCOUNT"])
+        .and_then(|count| count.trim().parse::<f64>().ok())
+        .map(|count| count as u32);
+
+    Popularity { rating, play_count }
+}
+
+/// Rating and play count of `path`, including ID3v2 `POPM` frames
+pub fn read_popularity(path: &Path) -> Result<Popularity> {
+    let tagged_file = lofty::read_from_path(path)?;
+    let tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag());
+    let mut popularity = tag.map(tag_popularity).unwrap_or_default();
+
+    // POPM isn't mapped into the generic tag, so it has to be read from the frame
+    if tagged_file.file_type() == FileType::Mpeg
+        && let Some(popm) = read_popm(path)?
+    {
+        popularity.rating = popularity.rating.or(popm_to_stars(popm.rating));
+        if popm.counter > 0 {
+            popularity.play_count = popularity.play_count.or(Some(popm.counter.min(u32::MAX as u64) as u32));
+        }
+    }
+
+    Ok(popularity)
+}
+
+fn read_popm(path: &Path) -> Result<Option<PopularimeterFrame<'static>>> {
+    let file = MpegFile::read_from(&mut File::open(path)?, ParseOptions::new())?;
+
+    Ok(file.id3v2().and_then(|id3| match id3.get(&FrameId::Valid("POPM".into())) {
+        Some(Frame::Popularimeter(popm)) => Some(popm.clone()),
+        _ => None,
+    }))
+}
+
+/// Write rating and play count into the tags of `path`
+///
+/// MP3 files get a `POPM` frame; everything else gets `FMPS_RATING` and
+/// `FMPS_PLAYCOUNT` plus the format's own rating field where there is
+/// one (Vorbis `RATING` and MP4 `rate`, as a percentage). `None` leaves
+/// the current value alone.
+pub fn write_popularity(path: &Path, popularity: Popularity) -> Result<()> {
+    let file_type = Probe::open(path)?.guess_file_type()?.file_type();
+
+    if file_type == Some(FileType::Mpeg) {
+        return write_popm(path, popularity);
+    }
+
+    let mut tagged_file = lofty::read_from_path(path)?;
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = tagged_file.primary_tag_mut().expect("primary tag was just inserted");
+
+    if let Some(stars) = popularity.rating {
+        let fraction = format!("{:.1}", f64::from(stars) / 5.0);
+        tag.insert_unchecked(TagItem::new(ItemKey::Unknown("FMPS_RATING".to_owned()), ItemValue::Text(fraction)));
+        if matches!(tag.tag_type(), TagType::VorbisComments | TagType::Mp4Ilst) {
+            tag.insert_text(ItemKey::Popularimeter, (u32::from(stars) * 20).to_string());
+        }
+    }
+    if let Some(count) = popularity.play_count {
+        let key = ItemKey::Unknown("FMPS_PLAYCOUNT".to_owned());
+        tag.insert_unchecked(TagItem::new(key, ItemValue::Text(count.to_string())));
+    }
+
+    tag.save_to_path(path, WriteOptions::default())?;
+
+    Ok(())
+}
+
+fn write_popm(path: &Path, popularity: Popularity) -> Result<()> {
+    let mut file = MpegFile::read_from(&mut File::open(path)?, ParseOptions::new())?;
+    let current = read_popm(path)?;
+
+    let rating = match popularity.rating {
+        Some(stars) => stars_to_popm(stars),
+        None => current.as_ref().map_or(0, |popm| popm.rating),
+    };
+    let counter = match popularity.play_count {
+        Some(count) => u64::from(count),
+        None => current.as_ref().map_or(0, |popm| popm.counter),
+    };
+
+    let mut id3 = file.id3v2().cloned().unwrap_or_else(Id3v2Tag::new);
+    id3.insert(Frame::Popularimeter(PopularimeterFrame::new(POPM_EMAIL.to_owned(), rating, counter)));
+    file.set_id3v2(id3);
+    file.save_to_path(path, WriteOptions::default())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rating_scales() {
+        assert_eq!(parse_rating("0.8"), Some(4));
+        assert_eq!(parse_rating("1.0"), Some(5));
+        assert_eq!(parse_rating("3"), Some(3));
+        assert_eq!(parse_rating("60"), Some(3));
+        assert_eq!(parse_rating("100"), Some(5));
+        assert_eq!(parse_rating("255"), None);
+        assert_eq!(parse_rating("good"), None);
+
+        for stars in 1..=5 {
+            assert_eq!(popm_to_stars(stars_to_popm(stars)), Some(stars));
+        }
+        assert_eq!(popm_to_stars(0), None);
+    }
+}
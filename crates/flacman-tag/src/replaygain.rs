@@ -0,0 +1,368 @@
+use std::f64::consts::PI;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use flacman_core::ReplayGain;
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, FileType, TaggedFileExt};
+use lofty::tag::{ItemKey, Tag};
+use tracing::{debug, instrument};
+
+use crate::tagerror::{Result, TagError};
+
+
+/// Loudness ReplayGain 2.0 brings tracks to, in LUFS
+pub const REPLAYGAIN_REFERENCE: f64 = -18.0;
+
+/// Loudness the `R128_*` gains of Opus files bring tracks to, in LUFS
+pub(crate) const R128_REFERENCE: f64 = -23.0;
+
+/// Sample rate ffmpeg decodes to for measuring
+const SAMPLE_RATE: u32 = 48000;
+
+/// Blocks quieter than this never count towards the loudness, in LUFS
+const ABSOLUTE_GATE: f64 = -70.0;
+
+/// Blocks this much quieter than the ungated loudness don't count, in LU
+const RELATIVE_GATE: f64 = -10.0;
+
+/// Loudness of a track as EBU R128 measures it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS; `None` for silence
+    pub integrated: Option<f64>,
+    /// Highest sample, as a fraction of full scale
+    pub peak: f64,
+    /// Mean square of each 400ms gating block, kept to measure albums
+    blocks: Vec<f64>,
+}
+
+/// Second-order IIR filter, one per channel and stage
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The two K-weighting stages of ITU-R BS.1770 at `rate`: a high shelf
+/// for the head, then a high pass
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = f64::from(rate);
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass =
+        Biquad { b: [1.0, -2.0, 1.0], a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0], z: [0.0; 2] };
+
+    [shelf, high_pass]
+}
+
+/// Measures interleaved samples as they are decoded
+struct Meter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    /// Samples per channel in a 100ms step; gating blocks are four steps
+    step_len: usize,
+    /// Weighted sum of squares of the step being filled
+    sum: f64,
+    filled: usize,
+    steps: Vec<f64>,
+    peak: f64,
+}
+
+impl Meter {
+    fn new(channels: usize, rate: u32) -> Self {
+        // The surround channels of 5.1 count 1.5 dB more, the LFE not at all
+        let weights = match channels {
+            6 => vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41],
+            _ => vec![1.0; channels],
+        };
+        Meter {
+            channels,
+            filters: vec![k_weighting(rate); channels],
+            weights,
+            step_len: rate as usize / 10,
+            sum: 0.0,
+            filled: 0,
+            steps: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let sample = f64::from(sample);
+                self.peak = self.peak.max(sample.abs());
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let weighted = high_pass.process(shelf.process(sample));
+                self.sum += self.weights[channel] * weighted * weighted;
+            }
+
+            self.filled += 1;
+            if self.filled == self.step_len {
+                self.steps.push(self.sum / self.step_len as f64);
+                (self.sum, self.filled) = (0.0, 0);
+            }
+        }
+    }
+
+    fn finish(self) -> Loudness {
+        let blocks: Vec<f64> = self.steps.windows(4).map(|steps| steps.iter().sum::<f64>() / 4.0).collect();
+        Loudness { integrated: gated_loudness(&blocks), peak: self.peak, blocks }
+    }
+}
+
+fn lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// Integrated loudness of `blocks`, gated as EBU R128 says
+fn gated_loudness(blocks: &[f64]) -> Option<f64> {
+    let mean = |blocks: &[f64]| (!blocks.is_empty()).then(|| blocks.iter().sum::<f64>() / blocks.len() as f64);
+
+    let audible: Vec<f64> = blocks.iter().copied().filter(|&z| lufs(z) > ABSOLUTE_GATE).collect();
+    let gate = lufs(mean(&audible)?) + RELATIVE_GATE;
+    let counted: Vec<f64> = audible.into_iter().filter(|&z| lufs(z) > gate).collect();
+    mean(&counted).map(lufs)
+}
+
+/// Measure the loudness of the file at `path`, which ffmpeg decodes
+///
+/// # Errors
+/// * `TagError::Loudness` - ffmpeg is missing or can't decode the file
+/// * `TagError::LoftyReadError` - The file isn't audio lofty knows
+#[instrument(level = "debug", skip_all, fields(path = %path.display()), err(level = "debug"))]
+pub fn measure_loudness(path: &Path) -> Result<Loudness> {
+    let failed = |reason: String| TagError::Loudness(path.to_path_buf(), reason);
+    let ffmpeg = flacman_core::find_program("ffmpeg").ok_or_else(|| failed("ffmpeg is not installed".to_owned()))?;
+    let channels = lofty::read_from_path(path)?.properties().channels().unwrap_or(2).max(1);
+
+    let mut child = Command::new(ffmpeg)
+        .args(["-nostdin", "-hide_banner", "-loglevel", "error", "-i"])
+        .arg(path)
+        .args(["-map", "0:a:0", "-f", "f32le", "-acodec", "pcm_f32le"])
+        .args(["-ac", &channels.to_string(), "-ar", &SAMPLE_RATE.to_string(), "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut meter = Meter::new(usize::from(channels), SAMPLE_RATE);
+    let frame_len = 4 * usize::from(channels);
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut buffer = vec![0u8; 4096 * frame_len];
+    let mut pending = 0;
+    let mut samples = Vec::new();
+    loop {
+        let read = stdout.read(&mut buffer[pending..])?;
+        if read == 0 {
+            break;
+        }
+        // A read may end inside a frame; the rest comes with the next one
+        let filled = pending + read;
+        let whole = filled - filled % frame_len;
+        samples.clear();
+        samples.extend(buffer[..whole].chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])));
+        meter.push(&samples);
+        buffer.copy_within(whole..filled, 0);
+        pending = filled - whole;
+    }
+
+    let mut stderr = String::new();
+    child.stderr.take().expect("stderr is piped").read_to_string(&mut stderr)?;
+    let status = child.wait()?;
+    if !status.success() {
+        let reason = stderr.lines().map(str::trim).rfind(|l| !l.is_empty()).map(str::to_owned);
+        return Err(failed(reason.unwrap_or_else(|| format!("ffmpeg exited with {}", status))));
+    }
+
+    let loudness = meter.finish();
+    debug!(integrated = ?loudness.integrated, peak = loudness.peak, "measured");
+    Ok(loudness)
+}
+
+/// ReplayGain values of each of `tracks`, measured as one album
+///
+/// The album gain comes from the loudness of all the tracks together, not
+/// from the average of theirs. Silent tracks get no track gain.
+pub fn album_replay_gain(tracks: &[Loudness]) -> Vec<ReplayGain> {
+    let blocks: Vec<f64> = tracks.iter().flat_map(|t| t.blocks.iter().copied()).collect();
+    let album_gain = gated_loudness(&blocks).map(|lufs| REPLAYGAIN_REFERENCE - lufs);
+    let album_peak = tracks.iter().map(|t| t.peak).fold(None, |peak: Option<f64>, p| Some(peak.unwrap_or(p).max(p)));
+
+    tracks
+        .iter()
+        .map(|track| ReplayGain {
+            track_gain: track.integrated.map(|lufs| REPLAYGAIN_REFERENCE - lufs),
+            track_peak: Some(track.peak),
+            album_gain,
+            album_peak,
+        })
+        .collect()
+}
+
+/// Measure the tracks of an album on `workers` threads
+///
+/// # Returns
+/// The ReplayGain values of each track, in the order of `paths`
+///
+/// # Errors
+/// The first track that couldn't be measured, as without it there is no
+/// album gain
+pub fn analyze_album(paths: &[PathBuf], workers: usize) -> Result<Vec<ReplayGain>> {
+    let next = AtomicUsize::new(0);
+    let measured = Mutex::new(vec![None; paths.len()]);
+    let failure = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, paths.len().max(1)) {
+            scope.spawn(|| {
+                while failure.lock().expect("loudness lock poisoned").is_none() {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(i) else {
+                        break;
+                    };
+                    match measure_loudness(path) {
+                        Ok(loudness) => measured.lock().expect("loudness lock poisoned")[i] = Some(loudness),
+                        Err(e) => *failure.lock().expect("loudness lock poisoned") = Some(e),
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(e) = failure.into_inner().expect("loudness lock poisoned") {
+        return Err(e);
+    }
+    let measured: Vec<Loudness> =
+        measured.into_inner().expect("loudness lock poisoned").into_iter().map(Option::unwrap_or_default).collect();
+    Ok(album_replay_gain(&measured))
+}
+
+/// Whether the file at `path` has both track and album gain tagged
+pub fn has_replay_gain(path: &Path) -> bool {
+    crate::export::read_replay_gain(path).is_ok_and(|gain| gain.track_gain.is_some() && gain.album_gain.is_some())
+}
+
+/// Tag the file at `path` with `gain`
+///
+/// Opus files get the `R128_TRACK_GAIN` and `R128_ALBUM_GAIN` tags their
+/// players read instead, without peaks; other files get `REPLAYGAIN_*`.
+/// Values `gain` doesn't have are left as they are.
+///
+/// # Errors
+/// * `TagError::LoftyReadError` - The file can't be read or written
+pub fn write_replay_gain(path: &Path, gain: &ReplayGain) -> Result<()> {
+    let mut tagged_file = lofty::read_from_path(path)?;
+    let opus = tagged_file.file_type() == FileType::Opus;
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = tagged_file.primary_tag_mut().expect("primary tag was just inserted");
+
+    if opus {
+        // Q7.8 fixed point, relative to the R128 reference
+        let q78 = |db: f64| ((db + R128_REFERENCE - REPLAYGAIN_REFERENCE) * 256.0).round().clamp(-32768.0, 32767.0);
+        for (key, db) in [("R128_TRACK_GAIN", gain.track_gain), ("R128_ALBUM_GAIN", gain.album_gain)] {
+            if let Some(db) = db {
+                tag.insert_text(ItemKey::Unknown(key.to_owned()), format!("{}", q78(db) as i32));
+            }
+        }
+    } else {
+        let gains = [(ItemKey::ReplayGainTrackGain, gain.track_gain), (ItemKey::ReplayGainAlbumGain, gain.album_gain)];
+        for (key, db) in gains {
+            if let Some(db) = db {
+                tag.insert_text(key, format!("{:.2} dB", db));
+            }
+        }
+        let peaks = [(ItemKey::ReplayGainTrackPeak, gain.track_peak), (ItemKey::ReplayGainAlbumPeak, gain.album_peak)];
+        for (key, peak) in peaks {
+            if let Some(peak) = peak {
+                tag.insert_text(key, format!("{:.6}", peak));
+            }
+        }
+    }
+
+    tagged_file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `seconds` of a 997 Hz sine, the frequency BS.1770 is specified at,
+    /// peaking at `amplitude`, on `channels` channels
+    fn sine(amplitude: f32, seconds: f32, channels: usize) -> Vec<f32> {
+        let frames = (SAMPLE_RATE as f32 * seconds) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * 997.0 * i as f32 / SAMPLE_RATE as f32;
+                std::iter::repeat_n(amplitude * phase.sin(), channels)
+            })
+            .collect()
+    }
+
+    fn measure(samples: &[f32], channels: usize) -> Loudness {
+        let mut meter = Meter::new(channels, SAMPLE_RATE);
+        meter.push(samples);
+        meter.finish()
+    }
+
+    #[test]
+    fn test_sine_loudness() {
+        // A full scale 997 Hz sine on one channel is -3.01 LUFS, and 6 dB
+        // quieter at half the amplitude
+        let loudness = measure(&sine(1.0, 3.0, 1), 1);
+        assert!((loudness.integrated.unwrap() + 3.01).abs() < 0.05, "{:?}", loudness.integrated);
+        let loudness = measure(&sine(0.5, 3.0, 2), 2);
+        assert!((loudness.integrated.unwrap() + 6.02).abs() < 0.05, "{:?}", loudness.integrated);
+        assert!((loudness.peak - 0.5).abs() < 1e-3);
+
+        assert_eq!(measure(&[0.0; 48000], 1).integrated, None);
+    }
+
+    #[test]
+    fn test_album_gain() {
+        let loud = measure(&sine(0.5, 2.0, 1), 1);
+        let quiet = measure(&sine(0.05, 2.0, 1), 1);
+        let silent = measure(&[0.0; 48000], 1);
+        let gains = album_replay_gain(&[loud.clone(), quiet.clone(), silent]);
+
+        let track_gain = REPLAYGAIN_REFERENCE - loud.integrated.unwrap();
+        assert!((gains[0].track_gain.unwrap() - track_gain).abs() < 1e-9);
+        assert_eq!(gains[2].track_gain, None);
+        // The quiet track is gated out of the album loudness, 20 dB down
+        assert!((gains[1].album_gain.unwrap() - track_gain).abs() < 0.01);
+        assert!(gains.iter().all(|g| g.album_peak == Some(loud.peak)));
+    }
+}
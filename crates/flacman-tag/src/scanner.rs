@@ -0,0 +1,333 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use flacman_fs::{FsError, WalkEntry, WalkOptions};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::mediafile::MediaFile;
+use crate::tagerror::{Result, TagError};
+
+
+/// What a file looked like when it was last scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    modified: SystemTime,
+}
+
+type ScanCache = BTreeMap<PathBuf, CacheEntry>;
+
+/// Finds the media files under a directory
+///
+/// Configured with the `with_*` methods, then run with [`Scanner::scan`];
+/// an incremental scan needs [`Scan::finish`] to save what it saw.
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    root: PathBuf,
+    extensions: Vec<String>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    exclude: Vec<String>,
+    walk: WalkOptions,
+    cache: Option<PathBuf>,
+}
+
+impl Scanner {
+    /// Scan `root` for audio files, honoring ignore files and following
+    /// no symlinks, as [`flacman_fs::find_audio_files`] does
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Scanner {
+            root: root.as_ref().to_path_buf(),
+            extensions: flacman_fs::audio_exts(),
+            min_size: None,
+            max_size: None,
+            exclude: Vec::new(),
+            walk: WalkOptions { use_ignore_files: true, ..Default::default() },
+            cache: None,
+        }
+    }
+
+    /// Only find files with one of `extensions`, ignoring case; none at
+    /// all finds every file
+    pub fn with_extensions<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extensions = extensions.into_iter().map(|e| e.into().trim_start_matches('.').to_owned()).collect();
+        self
+    }
+
+    /// Skip files smaller than `bytes`
+    pub fn with_min_size(mut self, bytes: u64) -> Self {
+        self.min_size = Some(bytes);
+        self
+    }
+
+    /// Skip files larger than `bytes`
+    pub fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Skip the files and directories matching the glob `pattern`
+    ///
+    /// Patterns match paths relative to the root at any depth, so `Scans`
+    /// skips every directory called that and `*.part.flac` every such
+    /// file; a leading `/` matches from the root only.
+    ///
+    /// # Errors
+    /// * `FsError::Pattern` - `pattern` isn't a valid glob
+    pub fn with_exclude(mut self, pattern: &str) -> Result<Self> {
+        glob(pattern)?;
+        self.exclude.push(pattern.to_owned());
+        Ok(self)
+    }
+
+    /// Descend into symlinked directories and find symlinked files;
+    /// symlink loops are skipped
+    pub fn with_follow_symlinks(mut self, follow: bool) -> Self {
+        self.walk.follow_symlinks = follow;
+        self
+    }
+
+    /// Scan incrementally: only find files whose size or modification time
+    /// changed since the scan that last saved `cache`
+    pub fn with_cache<P: AsRef<Path>>(mut self, cache: P) -> Self {
+        self.cache = Some(cache.as_ref().to_path_buf());
+        self
+    }
+
+    /// Start walking the root
+    ///
+    /// # Returns
+    /// An iterator over the files found, in directory order; unreadable
+    /// entries are skipped and logged at debug level
+    ///
+    /// # Errors
+    /// * `TagError::NotFound` - The root doesn't exist
+    /// * `TagError::NotADirectory` - The root is a file
+    /// * `TagError::ScanCache` - The cache file is damaged
+    pub fn scan(&self) -> Result<Scan> {
+        if !self.root.exists() {
+            return Err(TagError::NotFound(self.root.clone()));
+        }
+        if !self.root.is_dir() {
+            return Err(TagError::NotADirectory(self.root.clone()));
+        }
+
+        let mut exclude = GlobSetBuilder::new();
+        for pattern in &self.exclude {
+            exclude.add(glob(pattern)?);
+        }
+        let exclude = exclude.build().map_err(|e| FsError::Pattern(self.exclude.join(", "), e.to_string()))?;
+        let previous = match &self.cache {
+            Some(path) => load_cache(path)?,
+            None => ScanCache::new(),
+        };
+
+        let root = self.root.clone();
+        let walk = flacman_fs::walkdir_with(self.root.clone(), &self.walk)?
+            .filter(move |entry| entry.as_ref().map_or(true, |entry| !excluded(&exclude, &root, &entry.path)));
+
+        Ok(Scan {
+            scanner: self.clone(),
+            walk: Box::new(walk),
+            previous,
+            seen: ScanCache::new(),
+            unchanged: 0,
+            done: false,
+        })
+    }
+
+    fn wanted(&self, path: &Path, size: u64) -> bool {
+        let extension = path.extension().unwrap_or_default();
+        (self.extensions.is_empty() || self.extensions.iter().any(|e| extension.eq_ignore_ascii_case(e)))
+            && self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
+    }
+}
+
+/// Whether `path`, or a directory it is in below `root`, matches `exclude`
+fn excluded(exclude: &GlobSet, root: &Path, path: &Path) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative.ancestors().filter(|p| !p.as_os_str().is_empty()).any(|p| exclude.is_match(p))
+}
+
+/// Compile an exclude pattern, unanchored unless it starts with `/`
+fn glob(pattern: &str) -> Result<globset::Glob> {
+    let full = match pattern.strip_prefix('/') {
+        Some(anchored) => anchored.to_owned(),
+        None => format!("**/{}", pattern),
+    };
+    let glob = GlobBuilder::new(&full)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| FsError::Pattern(pattern.to_owned(), e.kind().to_string()))?;
+    Ok(glob)
+}
+
+fn load_cache(path: &Path) -> Result<ScanCache> {
+    match fs::read(path) {
+        Ok(data) => {
+            serde_json::from_slice(&data).map_err(|e| TagError::ScanCache(format!("{}: {}", path.display(), e)))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ScanCache::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A scan in progress, yielding each media file found
+///
+/// Call [`Scan::finish`] when done to save the cache of an incremental
+/// scan.
+pub struct Scan {
+    scanner: Scanner,
+    walk: Box<dyn Iterator<Item = std::result::Result<WalkEntry, FsError>>>,
+    previous: ScanCache,
+    seen: ScanCache,
+    unchanged: usize,
+    done: bool,
+}
+
+/// What a finished [`Scan`] found
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanSummary {
+    /// Files yielded, new or changed
+    pub found: usize,
+    /// Files left out of an incremental scan because they hadn't changed
+    pub unchanged: usize,
+    /// Files in the cache that are gone; empty unless the scan ran to the
+    /// end
+    pub removed: Vec<PathBuf>,
+}
+
+impl Scan {
+    /// Stop scanning, saving the cache if there is one
+    ///
+    /// Files of a scan stopped early that weren't reached yet keep what
+    /// the cache had for them.
+    ///
+    /// # Errors
+    /// * `TagError::Io` - The cache couldn't be written
+    pub fn finish(mut self) -> Result<ScanSummary> {
+        let found = self.seen.len() - self.unchanged;
+        let mut removed = Vec::new();
+        for (path, entry) in self.previous {
+            if self.seen.contains_key(&path) {
+                continue;
+            }
+            if self.done {
+                removed.push(path);
+            } else {
+                self.seen.insert(path, entry);
+            }
+        }
+
+        if let Some(path) = &self.scanner.cache {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let data = serde_json::to_vec(&self.seen).map_err(|e| TagError::ScanCache(e.to_string()))?;
+            fs::write(path, data)?;
+        }
+        Ok(ScanSummary { found, unchanged: self.unchanged, removed })
+    }
+}
+
+impl Iterator for Scan {
+    type Item = MediaFile;
+
+    fn next(&mut self) -> Option<MediaFile> {
+        loop {
+            let entry = match self.walk.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(error)) => {
+                    debug!(%error, "skipped unreadable entry");
+                    continue;
+                }
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            };
+            if !self.scanner.wanted(&entry.path, entry.size) {
+                continue;
+            }
+
+            let path = entry.path;
+            let modified = entry.modified.unwrap_or(SystemTime::UNIX_EPOCH);
+            let current = CacheEntry { size: entry.size, modified };
+            let unchanged = self.scanner.cache.is_some() && self.previous.get(&path) == Some(&current);
+            self.seen.insert(path.clone(), current);
+            if unchanged {
+                self.unchanged += 1;
+                continue;
+            }
+            return Some(MediaFile::new(&path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn names(scan: &mut Scan, root: &Path) -> Vec<String> {
+        let mut names: Vec<String> =
+            scan.map(|f| f.path.strip_prefix(root).unwrap().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_filters() {
+        let dir = tempdir().unwrap();
+        for (name, size) in [("Low/01.flac", 10), ("Low/02.FLAC", 1), ("Low/cover.jpg", 10), ("Low/Scans/03.flac", 10)] {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, vec![0; size]).unwrap();
+        }
+
+        let scanner = Scanner::new(dir.path());
+        assert_eq!(names(&mut scanner.scan().unwrap(), dir.path()), ["Low/01.flac", "Low/02.FLAC", "Low/Scans/03.flac"]);
+        let scanner = scanner.with_min_size(5).with_exclude("Scans").unwrap();
+        assert_eq!(names(&mut scanner.scan().unwrap(), dir.path()), ["Low/01.flac"]);
+        let scanner = Scanner::new(dir.path()).with_extensions(["jpg"]).with_max_size(5);
+        assert!(names(&mut scanner.scan().unwrap(), dir.path()).is_empty());
+
+        assert!(matches!(Scanner::new(dir.path()).with_exclude("[Scans"), Err(TagError::Fs(FsError::Pattern(..)))));
+        assert!(matches!(Scanner::new(dir.path().join("Low/01.flac")).scan(), Err(TagError::NotADirectory(_))));
+    }
+
+    #[test]
+    fn test_incremental_scan() {
+        let dir = tempdir().unwrap();
+        let library = dir.path().join("library");
+        fs::create_dir(&library).unwrap();
+        fs::write(library.join("01.flac"), b"one").unwrap();
+        fs::write(library.join("02.flac"), b"two").unwrap();
+        let scanner = Scanner::new(&library).with_cache(dir.path().join("cache/scan.json"));
+
+        let mut scan = scanner.scan().unwrap();
+        assert_eq!(names(&mut scan, &library), ["01.flac", "02.flac"]);
+        assert_eq!(scan.finish().unwrap().found, 2);
+
+        // Only the changed file is found again, and the deleted one reported
+        fs::write(library.join("01.flac"), b"one, retagged").unwrap();
+        fs::remove_file(library.join("02.flac")).unwrap();
+        let mut scan = scanner.scan().unwrap();
+        assert_eq!(names(&mut scan, &library), ["01.flac"]);
+        let summary = scan.finish().unwrap();
+        assert_eq!(summary.removed, [library.join("02.flac")]);
+
+        let mut scan = scanner.scan().unwrap();
+        assert!(names(&mut scan, &library).is_empty());
+        assert_eq!(scan.finish().unwrap(), ScanSummary { found: 0, unchanged: 1, removed: Vec::new() });
+    }
+}
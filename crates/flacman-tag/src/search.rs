@@ -0,0 +1,188 @@
+use std::fmt;
+
+use flacman_mb::MbClient;
+use serde::{Deserialize, Serialize};
+
+use crate::tagerror::{Result, TagError};
+
+
+/// Results asked for per search
+const SEARCH_LIMIT: usize = 25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchKind {
+    Artist,
+    Album,
+    Track,
+}
+
+impl SearchKind {
+    /// MusicBrainz entity searched for
+    fn entity(self) -> &'static str {
+        match self {
+            SearchKind::Artist => "artist",
+            SearchKind::Album => "release",
+            SearchKind::Track => "recording",
+        }
+    }
+}
+
+impl fmt::Display for SearchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            SearchKind::Artist => "artist",
+            SearchKind::Album => "album",
+            SearchKind::Track => "track",
+        })
+    }
+}
+
+/// One search result
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// MusicBrainz ID
+    pub id: String,
+    pub kind: SearchKind,
+    /// Artist name, or release or recording title
+    pub title: String,
+    /// Credited artist of a release or recording; disambiguation of an artist
+    pub artist: Option<String>,
+    pub date: Option<String>,
+    /// Relevance, 0-100
+    pub score: u8,
+}
+
+/// A release with its track list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReleaseInfo {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    pub date: Option<String>,
+    pub country: Option<String>,
+    /// Track titles by medium, in order
+    pub media: Vec<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct ArtistCredit {
+    name: String,
+    #[serde(default)]
+    joinphrase: String,
+}
+
+fn credit(credits: &[ArtistCredit]) -> String {
+    credits.iter().map(|c| format!("{}{}", c.name, c.joinphrase)).collect()
+}
+
+#[derive(Deserialize)]
+struct EntityJson {
+    id: String,
+    #[serde(default)]
+    score: u8,
+    #[serde(alias = "name", default)]
+    title: String,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<ArtistCredit>,
+    #[serde(alias = "first-release-date", default)]
+    date: Option<String>,
+    #[serde(default)]
+    disambiguation: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SearchJson {
+    #[serde(alias = "artists", alias = "releases", alias = "recordings", default)]
+    entities: Vec<EntityJson>,
+}
+
+/// Search MusicBrainz for artists, albums (releases) or tracks (recordings)
+///
+/// Searches always ask the service; their results are cached by the caller.
+///
+/// # Returns
+/// Hits by descending relevance
+pub fn search_musicbrainz(client: &MbClient, kind: SearchKind, query: &str) -> Result<Vec<SearchHit>> {
+    let limit = SEARCH_LIMIT.to_string();
+    let data = client.get_uncached(kind.entity(), &[("query", query), ("limit", &limit)], None)?;
+    let found: SearchJson = serde_json::from_slice(&data)
+        .map_err(|e| TagError::MusicBrainz(format!("unexpected response: {e}")))?;
+
+    Ok(found
+        .entities
+        .into_iter()
+        .map(|e| SearchHit {
+            artist: match kind {
+                SearchKind::Artist => e.disambiguation.filter(|d| !d.is_empty()),
+                _ => Some(credit(&e.artist_credit)).filter(|a| !a.is_empty()),
+            },
+            id: e.id,
+            kind,
+            title: e.title,
+            date: e.date.filter(|d| !d.is_empty()),
+            score: e.score,
+        })
+        .collect())
+}
+
+#[derive(Deserialize)]
+struct TrackJson {
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct MediumJson {
+    #[serde(default)]
+    tracks: Vec<TrackJson>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseJson {
+    id: String,
+    title: String,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<ArtistCredit>,
+    date: Option<String>,
+    country: Option<String>,
+    #[serde(default)]
+    media: Vec<MediumJson>,
+}
+
+/// Look up a release and its track list by MusicBrainz ID
+pub fn lookup_release(client: &MbClient, id: &str) -> Result<ReleaseInfo> {
+    let data = client.get_uncached(&format!("release/{id}"), &[("inc", "artist-credits+recordings")], None)?;
+    let release: ReleaseJson = serde_json::from_slice(&data)
+        .map_err(|e| TagError::MusicBrainz(format!("unexpected response: {e}")))?;
+
+    Ok(ReleaseInfo {
+        artist: credit(&release.artist_credit),
+        id: release.id,
+        title: release.title,
+        date: release.date.filter(|d| !d.is_empty()),
+        country: release.country,
+        media: release.media.into_iter().map(|m| m.tracks.into_iter().map(|t| t.title).collect()).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_search_response() {
+        let json = r#"{"created": "2024-01-01", "count": 1, "releases": [{
+            "id": "b1c6d1b4", "score": 100, "title": "Trust", "date": "2002-09-24",
+            "artist-credit": [{"name": "Low", "joinphrase": ""}]
+        }]}"#;
+        let found: SearchJson = serde_json::from_str(json).unwrap();
+        assert_eq!(found.entities.len(), 1);
+        assert_eq!(found.entities[0].title, "Trust");
+        assert_eq!(credit(&found.entities[0].artist_credit), "Low");
+
+        let json = r#"{"artists": [{"id": "a1", "score": 90, "name": "Low", "disambiguation": "US slowcore"}]}"#;
+        let found: SearchJson = serde_json::from_str(json).unwrap();
+        assert_eq!(found.entities[0].title, "Low");
+        assert_eq!(found.entities[0].disambiguation.as_deref(), Some("US slowcore"));
+    }
+}
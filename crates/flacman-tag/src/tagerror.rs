@@ -20,8 +20,52 @@ pub enum TagError {
     NotADirectory(PathBuf),
 
     #[error("Error reading file metadata: {0}")]
-    LoftyReadError(#[from] lofty::error::LoftyError)
-    
+    LoftyReadError(#[from] lofty::error::LoftyError),
+
+    #[error("Invalid tag value: {0}")]
+    Core(#[from] flacman_core::CoreError),
+
+    #[error("Filesystem error: {0}")]
+    Fs(#[from] flacman_fs::FsError),
+
+    #[error("HTTP error: {0}")]
+    Http(#[from] ureq::Error),
+
+    #[error("Download failed: {0}")]
+    Download(String),
+
+    #[error("MusicBrainz: {0}")]
+    MusicBrainz(String),
+
+    #[error("{0}")]
+    Mb(#[from] flacman_mb::MbError),
+
+    #[error("MPD: {0}")]
+    Mpd(String),
+
+    #[error("ListenBrainz: {0}")]
+    ListenBrainz(String),
+
+    #[error("No MusicBrainz release ID tagged in: {0}")]
+    MissingReleaseId(PathBuf),
+
+    #[error("Artwork: {0}")]
+    Artwork(String),
+
+    #[error("Watchlist file: {0}")]
+    Watchlist(String),
+
+    #[error("Cannot measure loudness of {0}: {1}")]
+    Loudness(PathBuf, String),
+
+    #[error("Scan cache: {0}")]
+    ScanCache(String),
+
+    #[error("Playlist: {0}")]
+    Playlist(String),
+
+    #[error("Cannot fingerprint {0}: {1}")]
+    Fingerprint(PathBuf, String),
 }
 
-pub type Result<T> = std::result::Result<T, TagError>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, TagError>;
@@ -0,0 +1,367 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use chrono::{DateTime, Local};
+use flacman_core::TrackRecord;
+use flacman_fs::sha256_file;
+use lofty::config::{ParseOptions, ParsingMode};
+use lofty::file::{AudioFile, FileType, TaggedFileExt};
+use lofty::probe::Probe;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::tagerror::Result;
+
+/// The reference FLAC tool, looked up on `PATH`; `flac -t` decodes a file
+/// and checks the audio against the MD5 in its STREAMINFO block
+const FLAC: &str = "flac";
+
+/// A file that failed validation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationFailure {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+/// Outcome of [`validate_files`]
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    /// Files that were checked, in completion order
+    pub checked: Vec<PathBuf>,
+    pub failures: Vec<ValidationFailure>,
+    /// The run was cancelled before every file was checked
+    pub interrupted: bool,
+}
+
+/// The MD5 of the decoded audio that the encoder stored in the STREAMINFO
+/// block of a FLAC file
+///
+/// # Returns
+/// `None` when the file isn't FLAC, or the encoder left the MD5 unset
+pub fn streaminfo_md5(path: &Path) -> Result<Option<[u8; 16]>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0u8; 10];
+    reader.read_exact(&mut header[..4])?;
+
+    // Some taggers put an ID3v2 tag in front of the stream
+    if &header[..3] == b"ID3" {
+        reader.read_exact(&mut header[4..])?;
+        let size = header[6..10].iter().fold(0u64, |size, b| (size << 7) | u64::from(b & 0x7f));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        std::io::copy(&mut reader.by_ref().take(size + footer), &mut std::io::sink())?;
+        reader.read_exact(&mut header[..4])?;
+    }
+    if &header[..4] != b"fLaC" {
+        return Ok(None);
+    }
+
+    // STREAMINFO is always the first block: a 4-byte block header, then
+    // 18 bytes of stream parameters before the MD5
+    let mut streaminfo = [0u8; 38];
+    reader.read_exact(&mut streaminfo)?;
+    if streaminfo[0] & 0x7f != 0 {
+        return Ok(None);
+    }
+    let md5: [u8; 16] = streaminfo[22..].try_into().expect("STREAMINFO MD5 is 16 bytes");
+
+    Ok((md5 != [0; 16]).then_some(md5))
+}
+
+/// The STREAMINFO MD5 of a FLAC file in hex, as the library database keeps
+/// it in the `flac_md5` tag
+pub fn flac_md5_tag(path: &Path) -> Option<String> {
+    let md5 = streaminfo_md5(path).ok()??;
+    Some(md5.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Whether `path` still has the size and modification time `record` was
+/// indexed with, so any change to its contents since wasn't an edit
+pub fn unchanged_since_indexed(path: &Path, record: &TrackRecord) -> bool {
+    let Ok(stat) = path.metadata() else {
+        return false;
+    };
+    let modified = stat.modified().ok().map_or(0, |m| DateTime::<Local>::from(m).timestamp());
+    stat.len() == record.size && modified == record.modified
+}
+
+/// Compare a file that [`unchanged_since_indexed`] with the checksums
+/// `record` holds, which is far cheaper than decoding it
+///
+/// # Returns
+/// A description of the damage if the contents changed anyway, which
+/// only bit rot (or a tool that restores modification times) explains
+pub fn verify_stored_checksums(path: &Path, record: &TrackRecord) -> Option<String> {
+    if let Some(stored) = record.tags.get("flac_md5")
+        && flac_md5_tag(path).as_ref() != Some(stored)
+    {
+        return Some("STREAMINFO MD5 changed since the file was indexed, but not its size or time (bit rot?)".to_owned());
+    }
+
+    match (&record.sha256, sha256_file(path)) {
+        (Some(stored), Ok(sum)) if *stored != sum => {
+            Some("contents changed since the file was indexed, but not its size or time (bit rot?)".to_owned())
+        }
+        (Some(_), Err(e)) => Some(e.to_string()),
+        _ => None,
+    }
+}
+
+/// Decode a FLAC file with `decoder -t` ([`FLAC`]) and compare it with its
+/// STREAMINFO MD5
+///
+/// Files without an MD5 have nothing to compare against and pass.
+///
+/// # Returns
+/// A description of the problem, if any
+fn check_flac_md5(decoder: &str, path: &Path) -> Option<String> {
+    match streaminfo_md5(path) {
+        Ok(Some(_)) => {}
+        Ok(None) => return None,
+        Err(e) => return Some(e.to_string()),
+    }
+
+    let output = match Command::new(decoder).args(["-t", "-s"]).arg(path).output() {
+        Ok(output) => output,
+        Err(e) => return Some(format!("cannot run {decoder}: {e}")),
+    };
+    if output.status.success() {
+        return None;
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = stderr.lines().map(str::trim).rfind(|l| !l.is_empty()).unwrap_or("decoding failed");
+    Some(format!("audio doesn't match its MD5 ({})", reason))
+}
+
+/// Check that a single audio file parses cleanly
+///
+/// Tags and stream properties are read in strict mode, so malformed
+/// metadata that a lenient read would skip over is reported. With
+/// `decode`, FLAC audio is also decoded and checked against the MD5 the
+/// encoder stored, which catches damage inside the audio frames.
+///
+/// # Returns
+/// A description of the problem, if any
+pub fn validate_file(path: &Path, decode: bool) -> Option<String> {
+    let failure = check_file(path, decode);
+    if let Some(failure) = &failure {
+        debug!(path = %path.display(), failure, "file failed validation");
+    }
+    failure
+}
+
+fn check_file(path: &Path, decode: bool) -> Option<String> {
+    let options = ParseOptions::new().parsing_mode(ParsingMode::Strict);
+
+    let probe = match Probe::open(path) {
+        Ok(probe) => probe.options(options),
+        Err(e) => return Some(e.to_string()),
+    };
+
+    let probe = match probe.guess_file_type() {
+        Ok(probe) => probe,
+        Err(e) => return Some(e.to_string()),
+    };
+
+    match probe.read() {
+        Ok(file) if file.properties().duration().is_zero() => Some("no audio (zero duration)".to_owned()),
+        Ok(file) if decode && file.file_type() == FileType::Flac => check_flac_md5(FLAC, path),
+        Ok(_) => None,
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+/// Validate `files` on a pool of `jobs` worker threads, decoding FLAC
+/// audio with `decode` (see [`validate_file`])
+///
+/// `on_result` is called from the workers after each file, with the
+/// failure message if the file is bad. Setting `cancel` stops the workers
+/// after the files they are currently checking; the report then covers
+/// only what was checked.
+pub fn validate_files<F>(
+    files: &[PathBuf],
+    jobs: usize,
+    decode: bool,
+    cancel: &AtomicBool,
+    on_result: F,
+) -> ValidationReport
+where
+    F: Fn(&Path, Option<&str>) + Sync,
+{
+    check_files(files, jobs, cancel, |path| validate_file(path, decode), on_result)
+}
+
+/// Like [`validate_files`], but with `check` deciding what is wrong with
+/// each file, e.g. to verify some against stored checksums instead
+pub fn check_files<C, F>(files: &[PathBuf], jobs: usize, cancel: &AtomicBool, check: C, on_result: F) -> ValidationReport
+where
+    C: Fn(&Path) -> Option<String> + Sync,
+    F: Fn(&Path, Option<&str>) + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let report = Mutex::new(ValidationReport::default());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| {
+                while !cancel.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = files.get(i) else {
+                        break;
+                    };
+
+                    let failure = check(path);
+                    on_result(path, failure.as_deref());
+
+                    let mut report = report.lock().expect("validation report lock poisoned");
+                    report.checked.push(path.clone());
+                    if let Some(message) = failure {
+                        report.failures.push(ValidationFailure { path: path.clone(), message });
+                    }
+                }
+            });
+        }
+    });
+
+    let mut report = report.into_inner().expect("validation report lock poisoned");
+    report.interrupted = report.checked.len() < files.len();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_bad_files_fail() {
+        let dir = tempdir().unwrap();
+        let files: Vec<PathBuf> = (0..5)
+            .map(|i| {
+                let path = dir.path().join(format!("{i}.flac"));
+                std::fs::write(&path, b"not a flac").unwrap();
+                path
+            })
+            .collect();
+
+        let seen = AtomicUsize::new(0);
+        let report = validate_files(&files, 3, true, &AtomicBool::new(false), |_, failure| {
+            assert!(failure.is_some());
+            seen.fetch_add(1, Ordering::Relaxed);
+        });
+
+        assert_eq!(seen.load(Ordering::Relaxed), 5);
+        assert_eq!(report.checked.len(), 5);
+        assert_eq!(report.failures.len(), 5);
+        assert!(!report.interrupted);
+    }
+
+    #[test]
+    fn test_streaminfo_md5() {
+        let dir = tempdir().unwrap();
+        let mut flac = b"fLaC".to_vec();
+        flac.extend_from_slice(&[0x80, 0, 0, 34]);
+        flac.extend_from_slice(&[0; 18]);
+        flac.extend_from_slice(&[0xab; 16]);
+
+        let path = dir.path().join("tagged.flac");
+        let mut id3 = b"ID3\x04\x00\x00\x00\x00\x00\x05".to_vec();
+        id3.extend_from_slice(&[0; 5]);
+        std::fs::write(&path, [id3, flac.clone()].concat()).unwrap();
+        assert_eq!(streaminfo_md5(&path).unwrap(), Some([0xab; 16]));
+
+        // An unset MD5 leaves nothing to check the audio against
+        flac.truncate(26);
+        flac.extend_from_slice(&[0; 16]);
+        std::fs::write(&path, &flac).unwrap();
+        assert_eq!(streaminfo_md5(&path).unwrap(), None);
+        assert_eq!(check_flac_md5(FLAC, &path), None);
+    }
+
+    #[test]
+    fn test_cancelled_run_is_partial() {
+        let files = vec![PathBuf::from("a.flac"), PathBuf::from("b.flac")];
+        let report = validate_files(&files, 2, false, &AtomicBool::new(true), |_, _| {});
+
+        assert!(report.checked.is_empty());
+        assert!(report.interrupted);
+    }
+
+    #[test]
+    fn test_stored_checksums() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("01 Canada.flac");
+        std::fs::write(&path, b"audio").unwrap();
+        let stat = path.metadata().unwrap();
+        let mut record = TrackRecord {
+            path: path.clone(),
+            album_artist: "Low".to_owned(),
+            album: "Trust".to_owned(),
+            artist: "Low".to_owned(),
+            title: "Canada".to_owned(),
+            disc: None,
+            track: Some(1),
+            year: None,
+            tags: Default::default(),
+            sha256: Some(sha256_file(&path).unwrap()),
+            size: stat.len(),
+            modified: DateTime::<Local>::from(stat.modified().unwrap()).timestamp(),
+            added: Local::now(),
+        };
+        assert!(unchanged_since_indexed(&path, &record));
+        assert_eq!(verify_stored_checksums(&path, &record), None);
+
+        // Same size, same time, different bytes
+        std::fs::write(&path, b"audiO").unwrap();
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(stat.modified().unwrap()).unwrap();
+        assert!(unchanged_since_indexed(&path, &record));
+        assert!(verify_stored_checksums(&path, &record).unwrap().contains("bit rot"));
+
+        record.size += 1;
+        assert!(!unchanged_since_indexed(&path, &record));
+    }
+
+    #[test]
+    fn test_flac_md5_checks() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("01 Canada.flac");
+        let mut flac = b"fLaC".to_vec();
+        flac.extend_from_slice(&[0x80, 0, 0, 34]);
+        flac.extend_from_slice(&[0; 18]);
+        flac.extend_from_slice(&[0xab; 16]);
+        std::fs::write(&path, &flac).unwrap();
+
+        // The decoder's verdict stands, `true` and `false` standing in for flac
+        assert_eq!(check_flac_md5("true", &path), None);
+        let failure = check_flac_md5("false", &path).unwrap();
+        assert_eq!(failure, "audio doesn't match its MD5 (decoding failed)");
+        assert!(check_flac_md5("flacman-no-such-decoder", &path).unwrap().starts_with("cannot run"));
+
+        // A STREAMINFO MD5 changed under an unchanged size and time
+        let stat = path.metadata().unwrap();
+        let mut record = TrackRecord {
+            path: path.clone(),
+            album_artist: "Low".to_owned(),
+            album: "Trust".to_owned(),
+            artist: "Low".to_owned(),
+            title: "Canada".to_owned(),
+            disc: None,
+            track: Some(1),
+            year: None,
+            tags: [("flac_md5".to_owned(), flac_md5_tag(&path).unwrap())].into(),
+            sha256: None,
+            size: stat.len(),
+            modified: DateTime::<Local>::from(stat.modified().unwrap()).timestamp(),
+            added: Local::now(),
+        };
+        assert_eq!(record.tags["flac_md5"], "ab".repeat(16));
+        assert_eq!(verify_stored_checksums(&path, &record), None);
+        record.tags.insert("flac_md5".to_owned(), "cd".repeat(16));
+        assert!(verify_stored_checksums(&path, &record).unwrap().starts_with("STREAMINFO MD5 changed"));
+    }
+}
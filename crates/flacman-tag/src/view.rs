@@ -0,0 +1,277 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::album::Album;
+use crate::tagerror::{Result, TagError};
+
+
+/// Facet an alternate view of the library is organized by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ViewFacet {
+    Genre,
+    Year,
+    Decade,
+}
+
+impl FromStr for ViewFacet {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "genre" => Ok(ViewFacet::Genre),
+            "year" => Ok(ViewFacet::Year),
+            "decade" => Ok(ViewFacet::Decade),
+            _ => Err(format!("unknown view {s:?} (expected genre, year or decade)")),
+        }
+    }
+}
+
+impl fmt::Display for ViewFacet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ViewFacet::Genre => write!(f, "genre"),
+            ViewFacet::Year => write!(f, "year"),
+            ViewFacet::Decade => write!(f, "decade"),
+        }
+    }
+}
+
+impl ViewFacet {
+    /// Directory an album is filed under, e.g. "Rock", "2001" or "2000s"
+    ///
+    /// The album's most common genre and earliest year are used; albums
+    /// without one go under "Unknown".
+    pub fn value(&self, album: &Album) -> String {
+        let year = album.tracks().filter_map(|t| t.metadata.year).filter(|y| *y > 0).min();
+
+        let value = match self {
+            ViewFacet::Genre => {
+                let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+                for genre in album.tracks().filter_map(|t| t.metadata.genre.as_ref()) {
+                    *counts.entry(genre.as_str().trim()).or_default() += 1;
+                }
+                counts.remove("");
+                counts.into_iter().max_by_key(|(_, n)| *n).map(|(genre, _)| genre.to_owned())
+            }
+            ViewFacet::Year => year.map(|y| y.to_string()),
+            ViewFacet::Decade => year.map(|y| format!("{}s", y - y % 10)),
+        };
+
+        value.map_or_else(|| "Unknown".to_owned(), |v| file_name_safe(&v))
+    }
+}
+
+fn file_name_safe(s: &str) -> String {
+    let safe: String = s.chars().map(|c| if c == '/' || c == '\0' { '-' } else { c }).collect();
+    safe.trim_start_matches('.').to_owned()
+}
+
+/// Directory holding all of an album's tracks (the parent of its disc
+/// subdirectories, for multi-disc albums)
+pub fn album_dir(album: &Album) -> Option<PathBuf> {
+    let mut parents = album.tracks().filter_map(|t| t.path.parent());
+    let mut common = parents.next()?.to_path_buf();
+
+    for parent in parents {
+        while !parent.starts_with(&common) {
+            if !common.pop() {
+                return None;
+            }
+        }
+    }
+
+    Some(common)
+}
+
+/// What [`build_view`] changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ViewReport {
+    pub added: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+/// Create or update a symlink tree presenting `albums` by `facet`
+///
+/// Each album appears as `<root>/<facet value>/<artist> - <title>`, a
+/// symlink to its directory. Links that are already right are left
+/// alone, stale links are removed along with facet directories that end
+/// up empty, and anything in `root` that isn't a symlink is never touched.
+///
+/// # Errors
+/// * `TagError::NotADirectory` - `root` exists but is not a directory
+pub fn build_view(albums: &[Album], facet: ViewFacet, root: &Path) -> Result<ViewReport> {
+    if root.exists() && !root.is_dir() {
+        return Err(TagError::NotADirectory(root.to_path_buf()));
+    }
+    fs::create_dir_all(root)?;
+
+    let mut wanted: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for album in albums {
+        let Some(dir) = album_dir(album) else {
+            continue;
+        };
+        let target = fs::canonicalize(&dir)?;
+        let name = file_name_safe(&format!("{} - {}", album.artist, album.title));
+        wanted.insert(root.join(facet.value(album)).join(name), target);
+    }
+
+    let mut report = ViewReport::default();
+
+    for facet_dir in fs::read_dir(root)? {
+        let facet_dir = facet_dir?.path();
+        if facet_dir.is_symlink() || !facet_dir.is_dir() {
+            continue;
+        }
+
+        for entry in fs::read_dir(&facet_dir)? {
+            let link = entry?.path();
+            if !link.is_symlink() {
+                continue;
+            }
+
+            if wanted.get(&link).is_some_and(|target| fs::read_link(&link).ok().as_ref() == Some(target)) {
+                wanted.remove(&link);
+                report.unchanged += 1;
+            } else {
+                fs::remove_file(&link)?;
+                report.removed += 1;
+            }
+        }
+
+        if fs::read_dir(&facet_dir)?.next().is_none() {
+            fs::remove_dir(&facet_dir)?;
+        }
+    }
+
+    for (link, target) in wanted {
+        if let Some(parent) = link.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if link.exists() || link.is_symlink() {
+            // Something that isn't ours is in the way
+            continue;
+        }
+        flacman_fs::symlink(&target, &link)?;
+        report.added += 1;
+    }
+
+    Ok(report)
+}
+
+/// A view to keep up to date, and the library it presents
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewSpec {
+    pub facet: ViewFacet,
+    pub dir: PathBuf,
+    pub library: Vec<PathBuf>,
+}
+
+/// The views flacman regenerates after each transaction
+#[derive(Debug, Clone)]
+pub struct ViewRegistry {
+    path: PathBuf,
+}
+
+impl ViewRegistry {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        ViewRegistry { path: path.as_ref().to_path_buf() }
+    }
+
+    pub fn load(&self) -> Result<Vec<ViewSpec>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        serde_json::from_str(&fs::read_to_string(&self.path)?)
+            .map_err(|e| TagError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))
+    }
+
+    /// Add `spec`, replacing any view registered for the same directory
+    pub fn register(&self, spec: ViewSpec) -> Result<()> {
+        let mut views = self.load()?;
+        views.retain(|v| v.dir != spec.dir);
+        views.push(spec);
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(&views)
+            .map_err(|e| TagError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        fs::write(&self.path, json)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::album::AlbumTrack;
+    use crate::mediafile::Metadata;
+    use tempfile::tempdir;
+
+    fn album(dir: &Path, title: &str, genre: &str, year: u32) -> Album {
+        let s = |v: &str| flacman_core::String::from_str(v).unwrap();
+        let path = dir.join(title).join("01.flac");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::File::create(&path).unwrap();
+
+        let metadata = Metadata {
+            track_name: s("x"),
+            album: s(title),
+            author: s("Low"),
+            album_artist: None,
+            track_number: Some(1),
+            track_total: None,
+            disc_number: None,
+            disc_total: None,
+            disc_subtitle: None,
+            genre: Some(s(genre)),
+            year: Some(year),
+            narrator: None,
+            label: None,
+            rating: None,
+            play_count: None,
+            properties: Default::default(),
+        };
+
+        Album {
+            artist: "Low".into(),
+            title: title.into(),
+            discs: BTreeMap::from([(1, vec![AlbumTrack { path, metadata }])]),
+        }
+    }
+
+    #[test]
+    fn test_facet_values() {
+        let dir = tempdir().unwrap();
+        let a = album(dir.path(), "Things We Lost", "Slowcore/Indie", 2001);
+
+        assert_eq!(ViewFacet::Genre.value(&a), "Slowcore-Indie");
+        assert_eq!(ViewFacet::Year.value(&a), "2001");
+        assert_eq!(ViewFacet::Decade.value(&a), "2000s");
+    }
+
+    #[test]
+    fn test_build_view_incrementally() {
+        let lib = tempdir().unwrap();
+        let view = tempdir().unwrap();
+        let a = album(lib.path(), "Things We Lost", "Slowcore", 2001);
+        let b = album(lib.path(), "Double Negative", "Experimental", 2018);
+
+        let report = build_view(&[a.clone(), b], ViewFacet::Decade, view.path()).unwrap();
+        assert_eq!(report, ViewReport { added: 2, removed: 0, unchanged: 0 });
+        assert!(view.path().join("2010s/Low - Double Negative").join("01.flac").exists());
+
+        let report = build_view(&[a], ViewFacet::Decade, view.path()).unwrap();
+        assert_eq!(report, ViewReport { added: 0, removed: 1, unchanged: 1 });
+        assert!(!view.path().join("2010s").exists());
+    }
+}
@@ -0,0 +1,245 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use flacman_core::{TemplateFields, TrackFilter};
+use serde::{Deserialize, Serialize};
+
+use crate::album::Album;
+use crate::mediafile::Metadata;
+use crate::tagerror::{Result, TagError};
+use crate::view::album_dir;
+
+
+/// One storage root of a split library
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Volume {
+    pub name: String,
+    pub root: PathBuf,
+    /// Albums this volume should hold; `None` takes everything no other
+    /// volume claims
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<TrackFilter>,
+}
+
+/// The volumes a library is spread across, in placement order
+///
+/// An album belongs on the first volume whose rule it matches, else on
+/// the first volume without a rule. Stored as JSON in the data directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeSet {
+    pub volumes: Vec<Volume>,
+}
+
+/// Album-level fields a placement rule can test: the first track's tag
+/// fields, plus `age` (days since the album's newest file was modified,
+/// i.e. roughly since it was added)
+struct AlbumFields<'a> {
+    metadata: &'a Metadata,
+    age_days: Option<u64>,
+}
+
+impl TemplateFields for AlbumFields<'_> {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        match name {
+            "age" => self.age_days.map(|days| Cow::Owned(days.to_string())),
+            _ => self.metadata.field(name),
+        }
+    }
+}
+
+fn age_days(album: &Album, now: SystemTime) -> Option<u64> {
+    let newest = album.tracks().filter_map(|t| fs::metadata(&t.path).and_then(|m| m.modified()).ok()).max()?;
+    Some(now.duration_since(newest).map_or(0, |age| age.as_secs() / 86_400))
+}
+
+impl VolumeSet {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| TagError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| TagError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        fs::write(path, json)?;
+
+        Ok(())
+    }
+
+    /// Add `volume`, replacing any volume of the same name in place
+    pub fn add(&mut self, volume: Volume) {
+        match self.volumes.iter_mut().find(|v| v.name == volume.name) {
+            Some(existing) => *existing = volume,
+            None => self.volumes.push(volume),
+        }
+    }
+
+    pub fn roots(&self) -> Vec<&Path> {
+        self.volumes.iter().map(|v| v.root.as_path()).collect()
+    }
+
+    /// The volume whose root holds `path`
+    pub fn volume_of(&self, path: &Path) -> Option<&Volume> {
+        self.volumes.iter().filter(|v| path.starts_with(&v.root)).max_by_key(|v| v.root.components().count())
+    }
+
+    /// The volume `album` belongs on
+    pub fn place(&self, album: &Album) -> Option<&Volume> {
+        self.place_at(album, SystemTime::now())
+    }
+
+    fn place_at(&self, album: &Album, now: SystemTime) -> Option<&Volume> {
+        let metadata = &album.tracks().next()?.metadata;
+        let fields = AlbumFields { metadata, age_days: age_days(album, now) };
+
+        self.volumes
+            .iter()
+            .find(|v| v.rule.as_ref().is_some_and(|rule| rule.matches(&fields)))
+            .or_else(|| self.volumes.iter().find(|v| v.rule.is_none()))
+    }
+
+    /// Albums that sit on a different volume than their placement rules say
+    ///
+    /// Albums outside every volume, or whose tracks aren't below one
+    /// common directory, are left alone.
+    pub fn plan_rebalance(&self, albums: &[Album]) -> Vec<Relocation> {
+        albums.iter().filter_map(|album| self.relocation(album, self.place(album)?)).collect()
+    }
+
+    /// Albums to move onto the volume named `name`, whatever their rules
+    /// say; those already on it are left out
+    ///
+    /// # Returns
+    /// `None` if there is no volume of that name
+    pub fn plan_move(&self, albums: &[Album], name: &str) -> Option<Vec<Relocation>> {
+        let target = self.volumes.iter().find(|v| v.name == name)?;
+        Some(albums.iter().filter_map(|album| self.relocation(album, target)).collect())
+    }
+
+    /// Where `album` goes on `target`: the same path relative to the root
+    /// of the volume it is on now
+    fn relocation(&self, album: &Album, target: &Volume) -> Option<Relocation> {
+        let dir = album_dir(album)?;
+        let current = self.volume_of(&dir)?;
+        if target.name == current.name {
+            return None;
+        }
+
+        let relative = dir.strip_prefix(&current.root).ok()?;
+        if relative.as_os_str().is_empty() {
+            return None;
+        }
+
+        Some(Relocation {
+            album: format!("{} - {}", album.artist, album.title),
+            to: target.root.join(relative),
+            from: dir,
+            volume: target.name.clone(),
+        })
+    }
+}
+
+/// An album directory that should move to another volume
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub album: String,
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Name of the volume it moves to
+    pub volume: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::album::AlbumTrack;
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+    use tempfile::tempdir;
+
+    fn album(dir: &Path, title: &str, year: u32) -> Album {
+        let s = |v: &str| flacman_core::String::from_str(v).unwrap();
+        let path = dir.join("Low").join(title).join("01.flac");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::File::create(&path).unwrap();
+
+        let metadata = Metadata {
+            track_name: s("x"),
+            album: s(title),
+            author: s("Low"),
+            album_artist: None,
+            track_number: Some(1),
+            track_total: None,
+            disc_number: None,
+            disc_total: None,
+            disc_subtitle: None,
+            genre: None,
+            year: Some(year),
+            narrator: None,
+            label: None,
+            rating: None,
+            play_count: None,
+            properties: Default::default(),
+        };
+
+        let discs = BTreeMap::from([(1, vec![AlbumTrack { path, metadata }])]);
+        Album { artist: "Low".into(), title: title.into(), discs }
+    }
+
+    fn volumes(dir: &Path) -> VolumeSet {
+        let mut set = VolumeSet::default();
+        set.add(Volume { name: "ssd".into(), root: dir.join("ssd"), rule: Some("year>=2020".parse().unwrap()) });
+        set.add(Volume { name: "hdd".into(), root: dir.join("hdd"), rule: None });
+        set
+    }
+
+    #[test]
+    fn test_place_and_plan() {
+        let dir = tempdir().unwrap();
+        let set = volumes(dir.path());
+        let recent_on_hdd = album(&dir.path().join("hdd"), "HEY WHAT", 2021);
+        let old_on_hdd = album(&dir.path().join("hdd"), "Secret Name", 1999);
+        let old_on_ssd = album(&dir.path().join("ssd"), "Trust", 2002);
+
+        assert_eq!(set.place(&recent_on_hdd).unwrap().name, "ssd");
+        assert_eq!(set.place(&old_on_hdd).unwrap().name, "hdd");
+
+        let plan = set.plan_rebalance(&[recent_on_hdd, old_on_hdd, old_on_ssd]);
+        assert_eq!(plan.len(), 2);
+        assert_eq!(plan[0].to, dir.path().join("ssd/Low/HEY WHAT"));
+        assert_eq!(plan[1].from, dir.path().join("ssd/Low/Trust"));
+        assert_eq!(plan[1].volume, "hdd");
+
+        let albums = [album(&dir.path().join("hdd"), "Secret Name", 1999), album(&dir.path().join("ssd"), "Trust", 2002)];
+        let moves = set.plan_move(&albums, "ssd").unwrap();
+        assert_eq!(moves.len(), 1);
+        assert_eq!((moves[0].from.clone(), moves[0].to.clone()), (dir.path().join("hdd/Low/Secret Name"), dir.path().join("ssd/Low/Secret Name")));
+        assert!(set.plan_move(&albums, "tape").is_none());
+    }
+
+    #[test]
+    fn test_age_rule_and_round_trip() {
+        let dir = tempdir().unwrap();
+        let mut set = volumes(dir.path());
+        set.add(Volume { name: "ssd".into(), root: dir.path().join("ssd"), rule: Some("age<=30".parse().unwrap()) });
+        assert_eq!(set.volumes.len(), 2);
+
+        let fresh = album(&dir.path().join("hdd"), "Double Negative", 2018);
+        assert_eq!(set.place(&fresh).unwrap().name, "ssd");
+        let later = SystemTime::now() + std::time::Duration::from_secs(60 * 86_400);
+        assert_eq!(set.place_at(&fresh, later).unwrap().name, "hdd");
+
+        let path = dir.path().join("volumes.json");
+        set.save(&path).unwrap();
+        assert_eq!(VolumeSet::load(&path).unwrap(), set);
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::collection::CollectionRelease;
+use crate::tagerror::{Result, TagError};
+
+
+/// Version written to exported files; newer files are refused
+const WATCHLIST_VERSION: u32 = 1;
+
+/// An artist whose new releases are watched for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subscription {
+    pub artist: String,
+    /// MusicBrainz artist ID, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mbid: Option<String>,
+}
+
+impl Subscription {
+    /// Whether both name the same artist: by MBID when both have one,
+    /// otherwise by name, ignoring case
+    fn same_artist(&self, other: &Subscription) -> bool {
+        match (&self.mbid, &other.mbid) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.artist.to_lowercase() == other.artist.to_lowercase(),
+        }
+    }
+}
+
+/// Everything flacman is watching for, in a form that can move between
+/// machines
+///
+/// Written as TOML or JSON depending on the file extension.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watchlist {
+    pub version: u32,
+    #[serde(default)]
+    pub subscriptions: Vec<Subscription>,
+    #[serde(default)]
+    pub wantlist: Vec<CollectionRelease>,
+}
+
+/// What merging one watchlist into another added
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WatchlistMerge {
+    pub subscriptions: usize,
+    pub wanted: usize,
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("toml"))
+}
+
+impl Watchlist {
+    pub fn new(subscriptions: Vec<Subscription>, wantlist: Vec<CollectionRelease>) -> Self {
+        Watchlist { version: WATCHLIST_VERSION, subscriptions, wantlist }
+    }
+
+    /// Read an exported watchlist
+    ///
+    /// # Errors
+    /// * `TagError::Watchlist` - The file doesn't parse, or was written by
+    ///   a newer flacman
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let watchlist: Watchlist = if is_toml(path) {
+            toml::from_str(&text).map_err(|e| TagError::Watchlist(format!("{}: {}", path.display(), e)))?
+        } else {
+            serde_json::from_str(&text).map_err(|e| TagError::Watchlist(format!("{}: {}", path.display(), e)))?
+        };
+
+        if watchlist.version > WATCHLIST_VERSION {
+            return Err(TagError::Watchlist(format!(
+                "{} is version {}, this flacman reads up to {}",
+                path.display(),
+                watchlist.version,
+                WATCHLIST_VERSION
+            )));
+        }
+
+        Ok(watchlist)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<PathBuf> {
+        let text = if is_toml(path) {
+            toml::to_string_pretty(self).map_err(|e| TagError::Watchlist(e.to_string()))?
+        } else {
+            serde_json::to_string_pretty(self).map_err(|e| TagError::Watchlist(e.to_string()))?
+        };
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, text)?;
+
+        Ok(path.to_path_buf())
+    }
+
+    /// Add the entries of `other` that aren't here yet
+    ///
+    /// A subscription that only gains an MBID is updated in place rather
+    /// than added twice.
+    pub fn merge(&mut self, other: Watchlist) -> WatchlistMerge {
+        let mut added = WatchlistMerge::default();
+
+        for subscription in other.subscriptions {
+            match self.subscriptions.iter_mut().find(|s| s.same_artist(&subscription)) {
+                Some(existing) => {
+                    if existing.mbid.is_none() {
+                        existing.mbid = subscription.mbid;
+                    }
+                }
+                None => {
+                    self.subscriptions.push(subscription);
+                    added.subscriptions += 1;
+                }
+            }
+        }
+
+        for release in other.wantlist {
+            if !self.wantlist.iter().any(|r| r.id == release.id) {
+                self.wantlist.push(release);
+                added.wanted += 1;
+            }
+        }
+
+        added
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn subscription(artist: &str, mbid: Option<&str>) -> Subscription {
+        Subscription { artist: artist.to_owned(), mbid: mbid.map(str::to_owned) }
+    }
+
+    fn release(id: &str) -> CollectionRelease {
+        CollectionRelease { id: id.to_owned(), title: "Trust".to_owned(), artist: "Low".to_owned() }
+    }
+
+    #[test]
+    fn test_round_trip_and_merge() {
+        let dir = tempdir().unwrap();
+        let exported = Watchlist::new(
+            vec![subscription("Low", Some("a1")), subscription("Duster", None)],
+            vec![release("r1"), release("r2")],
+        );
+        for name in ["watch.toml", "watch.json"] {
+            let path = exported.save(&dir.path().join(name)).unwrap();
+            assert_eq!(Watchlist::load(&path).unwrap(), exported);
+        }
+
+        let mut local = Watchlist::new(vec![subscription("low", None)], vec![release("r1")]);
+        let added = local.merge(exported);
+        assert_eq!(added, WatchlistMerge { subscriptions: 1, wanted: 1 });
+        assert_eq!(local.subscriptions[0].mbid.as_deref(), Some("a1"));
+        assert_eq!(local.wantlist.len(), 2);
+    }
+}
@@ -1,3 +1,12 @@
-fn main() {
-    println!("Hello, world!");
+use std::process::ExitCode;
+
+
+fn main() -> ExitCode {
+    // Downloads re-execute flacman as their own user to make a request
+    if let Some(code) = flacman_core::serve_download_child() {
+        return code;
+    }
+
+    let matches = flacman_args::build_cli().get_matches();
+    flacman_args::handle_matches(&matches)
 }